@@ -0,0 +1,127 @@
+//! Benchmarks of the work done for every segment, run with `cargo bench`
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::thread;
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use tcp_stack::checksum::Checksum;
+use tcp_stack::config::StackConfig;
+use tcp_stack::data_link::loopback::Loopback;
+use tcp_stack::reader_writer::{RawWriter, Segment};
+use tcp_stack::socket::{TcpListener, TcpStream};
+use tcp_stack::socket_addr::{Addr, Quad};
+use tcp_stack::stack::NetStack;
+use tcp_stack::table::{rss_hash, SocketTable};
+use tcp_stack::tcp::packet::TcpIpHeader;
+use tcp_stack::tcp::vars::{seq_lt, ReceiveSequenceSpace};
+
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const MSS: usize = 1460;
+
+fn quad(port: u16) -> Quad {
+    Quad::new(Addr::new(SERVER, 80), Addr::new(CLIENT, port))
+}
+
+/// an ipv4 segment of `payload` with valid checksums
+fn build(quad: &Quad, payload: &[u8]) -> Vec<u8> {
+    let mut header = TcpIpHeader::from_quad(quad, 1, u16::MAX, 64).unwrap();
+    header.set_ack_number(1);
+    header.finalize(payload).unwrap();
+    let mut writer = RawWriter::new(0);
+    writer.write_header(&header).unwrap();
+    writer.write_payload(payload).unwrap();
+    writer.finalize().unwrap().to_vec()
+}
+
+fn segments(c: &mut Criterion) {
+    let quad = quad(40000);
+    let payload = vec![0x5a; MSS];
+    let frame = build(&quad, &payload);
+    let mut group = c.benchmark_group("segment");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("build", |b| b.iter(|| build(black_box(&quad), black_box(&payload))));
+    group.bench_function("parse", |b| b.iter(|| Segment::parse(black_box(&frame), 0).unwrap().quad()));
+    group.bench_function("verify", |b| {
+        let segment = Segment::parse(&frame, 0).unwrap();
+        b.iter(|| black_box(&segment).checksum_valid().unwrap())
+    });
+    group.finish();
+}
+
+fn checksums(c: &mut Criterion) {
+    let mut group = c.benchmark_group("checksum");
+    for len in [64, MSS, u16::MAX as usize] {
+        let data = vec![0xa5; len];
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_function(len.to_string(), |b| b.iter(|| {
+            let mut checksum = Checksum::ipv4_pseudo_header(CLIENT.octets(), SERVER.octets(), 6, len);
+            checksum.add(black_box(&data));
+            checksum.finish()
+        }));
+    }
+    group.finish();
+}
+
+fn sequence_windows(c: &mut Criterion) {
+    let rcv = ReceiveSequenceSpace::from_seq_number(u32::MAX - 1000, u16::MAX);
+    // around rcv.nxt, across the wrap of the sequence space
+    let seqs: Vec<u32> = (0..1024u32).map(|i| rcv.nxt.wrapping_add(i * 97).wrapping_sub(8192)).collect();
+    let mut group = c.benchmark_group("sequence");
+    group.throughput(Throughput::Elements(seqs.len() as u64));
+    group.bench_function("acceptable", |b| b.iter(|| {
+        seqs.iter().filter(|&&seq| black_box(&rcv).acceptable(seq, MSS as u32)).count()
+    }));
+    group.bench_function("seq_lt", |b| b.iter(|| {
+        seqs.iter().filter(|&&seq| seq_lt(black_box(seq), rcv.nxt)).count()
+    }));
+    group.finish();
+}
+
+fn connection_table(c: &mut Criterion) {
+    let mut group = c.benchmark_group("table");
+    for connections in [16u16, 1024, 16384] {
+        let mut table: SocketTable<u16, ()> = SocketTable::new();
+        for port in 0..connections {
+            table.insert(quad(1024 + port), port);
+        }
+        let quads: Vec<Quad> = (0..connections).map(|port| quad(1024 + port)).collect();
+        group.throughput(Throughput::Elements(quads.len() as u64));
+        group.bench_function(format!("get/{}", connections), |b| b.iter(|| {
+            quads.iter().filter_map(|quad| table.get(black_box(quad))).count()
+        }));
+        group.bench_function(format!("rss_hash/{}", connections), |b| b.iter(|| {
+            quads.iter().map(|quad| rss_hash(black_box(quad))).fold(0, u32::wrapping_add)
+        }));
+    }
+    group.finish();
+}
+
+/// bytes written on one stack and read on the other over a loopback link
+fn loopback_transfer(c: &mut Criterion) {
+    const LEN: usize = 1 << 20;
+    let (a, b) = Loopback::pair();
+    let client = NetStack::with_device(a, StackConfig::builder().addr(CLIENT).build().unwrap()).unwrap();
+    let server = NetStack::with_device(b, StackConfig::builder().addr(SERVER).build().unwrap()).unwrap();
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let mut stream = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    stream.wait_established(Some(Duration::from_secs(5))).unwrap();
+    let mut accepted = listener.accept().unwrap();
+
+    let mut group = c.benchmark_group("loopback");
+    group.throughput(Throughput::Bytes(LEN as u64));
+    group.sample_size(10);
+    let data = vec![0x5a; LEN];
+    let mut received = vec![0; LEN];
+    group.bench_function("transfer", |b| b.iter(|| thread::scope(|scope| {
+        scope.spawn(|| stream.write_all(&data).unwrap());
+        accepted.read_exact(&mut received).unwrap();
+    })));
+    group.finish();
+}
+
+criterion_group!(benches, segments, checksums, sequence_windows, connection_table, loopback_transfer);
+criterion_main!(benches);
@@ -0,0 +1,180 @@
+#ifndef TCP_STACK_H
+#define TCP_STACK_H
+
+/* regenerate with `cbindgen --output include/tcp_stack.h` after changing src/ffi.rs */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/* Result codes, `TCP_STACK_ERROR_OK` or a negative error */
+typedef enum {
+  TCP_STACK_ERROR_OK = 0,
+  /* a null handle, a malformed string or an out of range value */
+  TCP_STACK_ERROR_INVALID_ARGUMENT = -1,
+  /* a `try_` function would have to wait */
+  TCP_STACK_ERROR_WOULD_BLOCK = -2,
+  TCP_STACK_ERROR_CONNECTION_REFUSED = -3,
+  TCP_STACK_ERROR_CONNECTION_RESET = -4,
+  TCP_STACK_ERROR_TIMED_OUT = -5,
+  TCP_STACK_ERROR_ADDR_IN_USE = -6,
+  TCP_STACK_ERROR_ADDR_NOT_AVAILABLE = -7,
+  /* the connection isn't established or was shut down for writing */
+  TCP_STACK_ERROR_NOT_CONNECTED = -8,
+  /* the device needs privileges the process lacks, e.g. CAP_NET_ADMIN for a TUN interface */
+  TCP_STACK_ERROR_PERMISSION_DENIED = -9,
+  /* any other failure, the log has the details */
+  TCP_STACK_ERROR_IO = -10,
+  /* a bug in the stack, the panic was caught */
+  TCP_STACK_ERROR_PANIC = -11,
+} TcpStackError;
+
+/* States of a connection, as passed to a `TcpStackStateCallback` */
+typedef enum {
+  TCP_STACK_STATE_CLOSED,
+  TCP_STACK_STATE_LISTEN,
+  TCP_STACK_STATE_SYN_RECEIVED,
+  TCP_STACK_STATE_SYN_SENT,
+  TCP_STACK_STATE_ESTABLISHED,
+  TCP_STACK_STATE_FIN_WAIT1,
+  TCP_STACK_STATE_FIN_WAIT2,
+  TCP_STACK_STATE_CLOSE_WAIT,
+  TCP_STACK_STATE_CLOSING,
+  TCP_STACK_STATE_LAST_ACK,
+  TCP_STACK_STATE_TIME_WAIT,
+} TcpStackState;
+
+/* A listening socket, from `tcp_stack_listen` */
+typedef struct TcpStackListener TcpStackListener;
+
+/* A stack, from `tcp_stack_open` */
+typedef struct TcpStackNet TcpStackNet;
+
+/* A connection, from `tcp_stack_accept` or `tcp_stack_connect` */
+typedef struct TcpStackStream TcpStackStream;
+
+/* Configuration of `tcp_stack_open`, zeroed fields get the defaults */
+typedef struct {
+  /* name of the TUN interface, NULL for "tcp0" */
+  const char *interface;
+  /* address of the stack, e.g. "192.168.3.2", required */
+  const char *addr;
+  /* address/prefix the interface gets on the host, e.g. "192.168.3.1/24", NULL for none */
+  const char *host;
+  /* 0 for the mtu of the interface, 1500 when it is configured with `host` */
+  size_t mtu;
+  /* queues of the interface, each one served by its own thread, 0 for 1 */
+  size_t queues;
+} TcpStackConfig;
+
+/* (local, remote) endpoints of a connection, addresses in host byte order */
+typedef struct {
+  uint32_t local_addr;
+  uint16_t local_port;
+  uint32_t remote_addr;
+  uint16_t remote_port;
+} TcpStackQuad;
+
+/*
+ * called with the `user` pointer given to `tcp_stack_on_state_change` when a connection
+ * changes state. it runs on a thread of the stack while the stack is locked: it must return
+ * quickly and must not call any `tcp_stack_*` function
+ */
+typedef void (*TcpStackStateCallback)(void *user,
+                                      const TcpStackQuad *quad,
+                                      TcpStackState from,
+                                      TcpStackState to);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/* open the TUN interface of `config` and start the stack on it */
+int tcp_stack_open(const TcpStackConfig *config, TcpStackNet **out);
+
+/*
+ * stop the stack and release the handle. its connections are reset and its listeners
+ * closed: reads on their handles return end of file, writes and accepts fail with
+ * `TCP_STACK_ERROR_NOT_CONNECTED`, the blocked ones too, and each handle is still released
+ * by its own `*_close`
+ */
+void tcp_stack_close(TcpStackNet *stack);
+
+/*
+ * a descriptor turning readable when a connection or listener of the stack becomes ready,
+ * for epoll or poll together with the `tcp_stack_try_*` functions. it stays readable
+ * until `tcp_stack_clear_readiness`
+ */
+int tcp_stack_readiness_fd(const TcpStackNet *stack);
+
+int tcp_stack_clear_readiness(const TcpStackNet *stack);
+
+/*
+ * call `callback` with `user` on every state change of a connection, return an id for
+ * `tcp_stack_remove_callback` or a negative error
+ */
+int64_t tcp_stack_on_state_change(const TcpStackNet *stack,
+                                  TcpStackStateCallback callback,
+                                  void *user);
+
+/* stop calling the callback `id`, once this returns it won't be called again */
+int tcp_stack_remove_callback(const TcpStackNet *stack, int64_t id);
+
+/* listen on `port` of every address of the stack */
+int tcp_stack_listen(const TcpStackNet *stack, uint16_t port, TcpStackListener **out);
+
+/* wait for the next established connection */
+int tcp_stack_accept(const TcpStackListener *listener, TcpStackStream **out);
+
+/* `TCP_STACK_ERROR_WOULD_BLOCK` if no connection is waiting */
+int tcp_stack_try_accept(const TcpStackListener *listener, TcpStackStream **out);
+
+/* stop listening, connections not accepted yet are reset */
+void tcp_stack_listener_close(TcpStackListener *listener);
+
+/*
+ * connect to `addr`, e.g. "192.168.3.1:80", and wait up to `timeout_ms` milliseconds for
+ * the handshake, 0 waits as long as the retransmissions go on
+ */
+int tcp_stack_connect(const TcpStackNet *stack,
+                      const char *addr,
+                      uint32_t timeout_ms,
+                      TcpStackStream **out);
+
+/*
+ * read up to `len` bytes into `buf`, waiting for data. return the bytes read,
+ * 0 at end of file, or a negative error
+ */
+intptr_t tcp_stack_read(const TcpStackStream *stream, uint8_t *buf, size_t len);
+
+/* like `tcp_stack_read` but `TCP_STACK_ERROR_WOULD_BLOCK` if nothing arrived */
+intptr_t tcp_stack_try_read(const TcpStackStream *stream, uint8_t *buf, size_t len);
+
+/*
+ * queue `len` bytes of `buf`, waiting while the send buffer is full. return the bytes
+ * queued or a negative error
+ */
+intptr_t tcp_stack_write(const TcpStackStream *stream, const uint8_t *buf, size_t len);
+
+/* like `tcp_stack_write` but `TCP_STACK_ERROR_WOULD_BLOCK` if the send buffer is full */
+intptr_t tcp_stack_try_write(const TcpStackStream *stream, const uint8_t *buf, size_t len);
+
+/* send a FIN after the queued data, reading goes on */
+int tcp_stack_shutdown(const TcpStackStream *stream);
+
+/* reset the connection, queued data is discarded */
+int tcp_stack_abort(const TcpStackStream *stream);
+
+/* endpoints of the connection */
+int tcp_stack_stream_quad(const TcpStackStream *stream, TcpStackQuad *out);
+
+/* close the connection and release the handle, queued data is still sent */
+void tcp_stack_stream_close(TcpStackStream *stream);
+
+/* static description of a result code */
+const char *tcp_stack_strerror(int code);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* TCP_STACK_H */
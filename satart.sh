@@ -1,15 +1,10 @@
 #!/bin/zsh
 
 exe_path=$(pwd)/target/release/tcp-stack
-ipaddress=192.168.3.1/24
 
 cargo build --release
 
 sudo setcap cap_net_admin=eip "$exe_path"
 
-$exe_path&
-pid=$!
-sudo ip addr add $ipaddress dev tcp0
-sudo ip link set up dev tcp0
-trap "kill $pid" INT TERM
-wait $pid
+# the stack assigns 192.168.3.1/24 to tcp0 and brings it up itself
+$exe_path --host 192.168.3.1/24 --addr 192.168.3.2 listen --port 8080
@@ -0,0 +1,42 @@
+//! Connections of the stack as file descriptors
+//!
+//! a stream is relayed to one end of a unix socketpair and the other end is handed out,
+//! so programs reading and writing a fd serve connections of the stack unmodified, e.g. as
+//! the stdin and stdout of a child process like inetd does. the relay runs two threads per
+//! connection, see `proxy::relay`: a FIN shuts the socket down for writing and the socket
+//! closed by the program sends one, a reset shuts the socket down and a program closing it
+//! before reading everything resets the connection
+
+use std::io;
+use std::os::unix::io::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+use crate::proxy::relay;
+use crate::socket::TcpStream;
+
+/// relay `stream` to a unix socket on its own threads and return the socket
+pub fn socketpair(stream: TcpStream) -> io::Result<UnixStream> {
+    let (ours, theirs) = UnixStream::pair()?;
+    thread::spawn(move || match relay(&stream, &ours) {
+        Ok((sent, received)) => {
+            debug!(peer = %stream.peer_addr(), sent, received, "bridged connection closed");
+        }
+        Err(e) => {
+            debug!(peer = %stream.peer_addr(), error = %e, "bridged connection failed");
+        }
+    });
+    Ok(theirs)
+}
+
+/// run `command` with `stream` as its stdin and stdout, inetd style
+pub fn spawn(stream: TcpStream, command: &mut Command) -> io::Result<Child> {
+    let socket = socketpair(stream)?;
+    let stdin = OwnedFd::from(socket.try_clone()?);
+    let child = command.stdin(Stdio::from(stdin)).stdout(Stdio::from(OwnedFd::from(socket))).spawn();
+    // the command holds on to the socket until it's told otherwise, the program
+    // has to be the only one with it for its close to end the connection
+    command.stdin(Stdio::null()).stdout(Stdio::null());
+    child
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::meta::ETHERNET_MTU;
+
+/// buffers kept by the pool of `BufferPool::global`
+const GLOBAL_POOL_BUFFERS: usize = 256;
+
+struct PoolInner {
+    buffer_size: usize,
+    /// released buffers beyond this are freed
+    max_free: usize,
+    free: Mutex<Vec<Vec<u8>>>,
+    /// buffers handed out and not returned yet
+    outstanding: AtomicUsize,
+}
+
+impl PoolInner {
+    fn release(&self, mut buf: Vec<u8>) {
+        self.outstanding.fetch_sub(1, Ordering::Relaxed);
+        // a buffer which grew is as good as a new one, a shrunk one is dropped
+        if buf.capacity() < self.buffer_size {
+            return;
+        }
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < self.max_free {
+            buf.clear();
+            free.push(buf);
+        }
+    }
+}
+
+/// Pool of fixed size byte buffers, so sending and receiving frames doesn't
+/// allocate once the pool is warm. clones share the buffers
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+impl BufferPool {
+    /// buffers of `buffer_size` bytes, at most `max_free` are kept for reuse
+    pub fn new(buffer_size: usize, max_free: usize) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                buffer_size,
+                max_free,
+                free: Mutex::new(Vec::new()),
+                outstanding: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// buffers large enough for any frame of an ethernet sized link
+    pub fn with_mtu(header_len: usize, max_free: usize) -> Self {
+        Self::new(ETHERNET_MTU + header_len, max_free)
+    }
+
+    /// the pool used by `RawWriter::new`
+    pub fn global() -> &'static BufferPool {
+        static POOL: OnceLock<BufferPool> = OnceLock::new();
+        POOL.get_or_init(|| BufferPool::with_mtu(crate::meta::TUN_SIZE, GLOBAL_POOL_BUFFERS))
+    }
+
+    /// an empty buffer with a capacity of at least `buffer_size`
+    pub fn get(&self) -> PooledBuf {
+        let free = self.inner.free.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let buf = free.unwrap_or_else(|| Vec::with_capacity(self.inner.buffer_size));
+        self.inner.outstanding.fetch_add(1, Ordering::Relaxed);
+        PooledBuf {
+            buf,
+            pool: Some(self.inner.clone()),
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.inner.buffer_size
+    }
+
+    /// buffers ready for reuse
+    pub fn free_count(&self) -> usize {
+        self.inner.free.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// buffers in use
+    pub fn outstanding(&self) -> usize {
+        self.inner.outstanding.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer_size", &self.buffer_size())
+            .field("free", &self.free_count())
+            .field("outstanding", &self.outstanding())
+            .finish()
+    }
+}
+
+/// Growable byte buffer which returns to its pool when dropped
+pub struct PooledBuf {
+    buf: Vec<u8>,
+    pool: Option<Arc<PoolInner>>,
+}
+
+impl PooledBuf {
+    /// buffer which belongs to no pool
+    pub fn unpooled(capacity: usize) -> Self {
+        Self {
+            buf: Vec::with_capacity(capacity),
+            pool: None,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// zero filled up to `len` bytes, e.g. to receive a frame into it
+    pub fn resize(&mut self, len: usize) {
+        self.buf.resize(len, 0);
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.buf.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// read only handle which can be cloned without copying, e.g. to keep a
+    /// sent segment for retransmission
+    pub fn freeze(self) -> SharedBuf {
+        SharedBuf(Arc::new(self))
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl Write for PooledBuf {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// a copy from the same pool
+impl Clone for PooledBuf {
+    fn clone(&self) -> Self {
+        let mut copy = match &self.pool {
+            Some(inner) => BufferPool { inner: inner.clone() }.get(),
+            None => PooledBuf::unpooled(self.capacity()),
+        };
+        copy.extend_from_slice(self);
+        copy
+    }
+}
+
+impl fmt::Debug for PooledBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PooledBuf")
+            .field("len", &self.buf.len())
+            .field("capacity", &self.buf.capacity())
+            .finish()
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.release(std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+/// Reference counted, immutable `PooledBuf`, the buffer returns to the pool with the last clone
+#[derive(Clone, Debug)]
+pub struct SharedBuf(Arc<PooledBuf>);
+
+impl Deref for SharedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, IoSlice, Result};
+use std::net::Ipv4Addr;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::ops::Not;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use crate::data_link::{gather, Capabilities, DataLayer};
+use crate::ip_options::IpOptionSet;
+use crate::reader_writer::Segment;
+use crate::result;
+use crate::socket_addr::Quad;
+use crate::tcp::vars::TcpControl;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Direction {
+    /// received from the device
+    In,
+    /// sent by the stack
+    Out,
+}
+
+/// Header fields of a captured tcp segment
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SegmentInfo {
+    /// sender and receiver of the segment
+    pub quad: Quad,
+    pub seq: u32,
+    pub ack: u32,
+    /// the flags byte of the tcp header
+    pub flags: u8,
+    pub window: u16,
+    /// payload bytes
+    pub len: usize,
+    /// the options of the ip header
+    pub ip_options: IpOptionSet,
+}
+
+impl SegmentInfo {
+    pub fn has(&self, control: TcpControl) -> bool {
+        self.flags & flag_bit(control) != 0
+    }
+}
+
+fn flag_bit(control: TcpControl) -> u8 {
+    match control {
+        TcpControl::FIN => 0x01,
+        TcpControl::SYN => 0x02,
+        TcpControl::RST => 0x04,
+        TcpControl::PSH => 0x08,
+        TcpControl::ACK => 0x10,
+        TcpControl::URG => 0x20,
+    }
+}
+
+/// A frame as seen by the capture callbacks
+#[derive(Debug, Copy, Clone)]
+pub struct CapturedFrame<'a> {
+    pub direction: Direction,
+    pub timestamp: SystemTime,
+    /// the whole frame, link header included
+    pub frame: &'a [u8],
+    /// bytes in front of the ip header
+    pub link_header_len: usize,
+    /// `None` if the frame is no well formed ipv4 tcp segment
+    pub segment: Option<SegmentInfo>,
+}
+
+impl<'a> CapturedFrame<'a> {
+    pub(crate) fn new(direction: Direction, frame: &'a [u8], link_header_len: usize) -> Self {
+        Self {
+            direction,
+            timestamp: SystemTime::now(),
+            frame,
+            link_header_len,
+            segment: parse(frame, link_header_len),
+        }
+    }
+
+    /// the ip packet without the link header
+    pub fn packet(&self) -> &'a [u8] {
+        &self.frame[self.link_header_len.min(self.frame.len())..]
+    }
+
+    pub fn to_owned(&self) -> CapturedPacket {
+        CapturedPacket {
+            direction: self.direction,
+            timestamp: self.timestamp,
+            frame: self.frame.to_vec(),
+            link_header_len: self.link_header_len,
+            segment: self.segment,
+        }
+    }
+}
+
+fn parse(frame: &[u8], link_header_len: usize) -> Option<SegmentInfo> {
+    let segment = Segment::parse(frame, link_header_len).ok()?;
+    let tcp = segment.tcp();
+    Some(SegmentInfo {
+        quad: segment.quad(),
+        seq: tcp.sequence_number(),
+        ack: tcp.acknowledgment_number(),
+        flags: tcp.slice()[13],
+        window: tcp.window_size(),
+        len: segment.payload().len(),
+        ip_options: IpOptionSet::of(segment.ip().options()).unwrap_or_default(),
+    })
+}
+
+/// Owned copy of a `CapturedFrame`, stored by `CaptureRing`
+#[derive(Debug, Clone)]
+pub struct CapturedPacket {
+    pub direction: Direction,
+    pub timestamp: SystemTime,
+    pub frame: Vec<u8>,
+    pub link_header_len: usize,
+    pub segment: Option<SegmentInfo>,
+}
+
+/// Selects the captured frames, either built directly or parsed from a
+/// tcpdump like expression, e.g. `port 80 and (syn or rst)`
+///
+/// primitives: `in`, `out`, `tcp`, `[src|dst] host <ipv4>`, `[src|dst] port <port>`,
+/// the flags `syn`, `ack`, `fin`, `rst`, `psh`, `urg` and `ipopts` for ip options or `srcroute`
+/// for a source route among them, combined with `not`, `and`, `or` and parentheses. everything but `in` and `out` only matches tcp segments
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Filter {
+    All,
+    Direction(Direction),
+    /// any tcp segment
+    Tcp,
+    Host(Ipv4Addr),
+    SrcHost(Ipv4Addr),
+    DstHost(Ipv4Addr),
+    Port(u16),
+    SrcPort(u16),
+    DstPort(u16),
+    /// the flag is set
+    Flag(TcpControl),
+    /// the ip header has options
+    IpOptions,
+    /// the ip header has a loose or strict source route
+    SourceRoute,
+    /// both directions of the connection
+    Connection(Quad),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+impl Filter {
+    pub fn and(self, other: Filter) -> Filter {
+        Filter::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Filter) -> Filter {
+        Filter::Or(Box::new(self), Box::new(other))
+    }
+
+    pub fn matches(&self, frame: &CapturedFrame) -> bool {
+        let segment = frame.segment.as_ref();
+        let with_segment = |f: &dyn Fn(&SegmentInfo) -> bool| segment.is_some_and(f);
+        match self {
+            Filter::All => true,
+            Filter::Direction(direction) => frame.direction == *direction,
+            Filter::Tcp => segment.is_some(),
+            Filter::Host(ip) => with_segment(&|s| s.quad.src().ip() == *ip || s.quad.dest().ip() == *ip),
+            Filter::SrcHost(ip) => with_segment(&|s| s.quad.src().ip() == *ip),
+            Filter::DstHost(ip) => with_segment(&|s| s.quad.dest().ip() == *ip),
+            Filter::Port(port) => with_segment(&|s| s.quad.src().port() == *port || s.quad.dest().port() == *port),
+            Filter::SrcPort(port) => with_segment(&|s| s.quad.src().port() == *port),
+            Filter::DstPort(port) => with_segment(&|s| s.quad.dest().port() == *port),
+            Filter::Flag(control) => with_segment(&|s| s.has(*control)),
+            Filter::IpOptions => with_segment(&|s| !s.ip_options.is_empty()),
+            Filter::SourceRoute => with_segment(&|s| s.ip_options.is_source_routed()),
+            Filter::Connection(quad) => with_segment(&|s| s.quad == *quad || s.quad == quad.reverse()),
+            Filter::Not(filter) => !filter.matches(frame),
+            Filter::And(a, b) => a.matches(frame) && b.matches(frame),
+            Filter::Or(a, b) => a.matches(frame) || b.matches(frame),
+        }
+    }
+}
+
+impl Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        Filter::Not(Box::new(self))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = result::Error;
+
+    fn from_str(s: &str) -> result::Result<Self> {
+        let spaced = s.replace('(', " ( ").replace(')', " ) ");
+        let tokens: Vec<&str> = spaced.split_whitespace().collect();
+        if tokens.is_empty() {
+            return Ok(Filter::All);
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let filter = parser.or()?;
+        match parser.next() {
+            None => Ok(filter),
+            Some(token) => Err(invalid(format!("unexpected `{}`", token)).into()),
+        }
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+/// recursive descent over the tokens, `or` binds weaker than `and`
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn expect_next(&mut self, what: &str) -> Result<&'a str> {
+        self.next().ok_or_else(|| invalid(format!("expected {}", what)))
+    }
+
+    fn or(&mut self) -> Result<Filter> {
+        let mut filter = self.and()?;
+        while self.peek() == Some("or") {
+            self.pos += 1;
+            filter = filter.or(self.and()?);
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter> {
+        let mut filter = self.unary()?;
+        while self.peek() == Some("and") {
+            self.pos += 1;
+            filter = filter.and(self.unary()?);
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter> {
+        match self.expect_next("a filter")? {
+            "not" => Ok(!self.unary()?),
+            "(" => {
+                let filter = self.or()?;
+                match self.next() {
+                    Some(")") => Ok(filter),
+                    _ => Err(invalid("missing `)`".to_string())),
+                }
+            }
+            "in" => Ok(Filter::Direction(Direction::In)),
+            "out" => Ok(Filter::Direction(Direction::Out)),
+            "tcp" => Ok(Filter::Tcp),
+            "syn" => Ok(Filter::Flag(TcpControl::SYN)),
+            "ack" => Ok(Filter::Flag(TcpControl::ACK)),
+            "fin" => Ok(Filter::Flag(TcpControl::FIN)),
+            "rst" => Ok(Filter::Flag(TcpControl::RST)),
+            "psh" => Ok(Filter::Flag(TcpControl::PSH)),
+            "urg" => Ok(Filter::Flag(TcpControl::URG)),
+            "ipopts" => Ok(Filter::IpOptions),
+            "srcroute" => Ok(Filter::SourceRoute),
+            "host" => Ok(Filter::Host(self.host()?)),
+            "port" => Ok(Filter::Port(self.port()?)),
+            "src" => match self.expect_next("`host` or `port`")? {
+                "host" => Ok(Filter::SrcHost(self.host()?)),
+                "port" => Ok(Filter::SrcPort(self.port()?)),
+                other => Err(invalid(format!("unexpected `{}` after `src`", other))),
+            },
+            "dst" => match self.expect_next("`host` or `port`")? {
+                "host" => Ok(Filter::DstHost(self.host()?)),
+                "port" => Ok(Filter::DstPort(self.port()?)),
+                other => Err(invalid(format!("unexpected `{}` after `dst`", other))),
+            },
+            other => Err(invalid(format!("unknown filter `{}`", other))),
+        }
+    }
+
+    fn host(&mut self) -> Result<Ipv4Addr> {
+        let token = self.expect_next("an address")?;
+        token.parse().map_err(|_| invalid(format!("invalid address `{}`", token)))
+    }
+
+    fn port(&mut self) -> Result<u16> {
+        let token = self.expect_next("a port")?;
+        token.parse().map_err(|_| invalid(format!("invalid port `{}`", token)))
+    }
+}
+
+/// Bounded buffer of captured frames, the oldest frame is dropped when it's full.
+/// clones share the buffer
+#[derive(Debug, Clone)]
+pub struct CaptureRing {
+    frames: Arc<Mutex<VecDeque<CapturedPacket>>>,
+    capacity: usize,
+    dropped: Arc<AtomicU64>,
+}
+
+impl CaptureRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<CapturedPacket>> {
+        self.frames.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push(&self, frame: &CapturedFrame) {
+        if self.capacity == 0 {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let mut frames = self.lock();
+        if frames.len() == self.capacity {
+            frames.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        frames.push_back(frame.to_owned());
+    }
+
+    /// the oldest frame
+    pub fn pop(&self) -> Option<CapturedPacket> {
+        self.lock().pop_front()
+    }
+
+    /// every frame, oldest first
+    pub fn drain(&self) -> Vec<CapturedPacket> {
+        self.lock().drain(..).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// frames lost because the ring was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Where captured frames go
+pub enum Sink {
+    /// called on the thread processing the packets while the stack is locked,
+    /// the callback must not use sockets of the same stack
+    Callback(Box<dyn FnMut(&CapturedFrame) + Send>),
+    Ring(CaptureRing),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct CaptureId(u64);
+
+/// Captures registered on a stack
+#[derive(Default)]
+pub(crate) struct Captures {
+    entries: Mutex<Vec<(CaptureId, Filter, Sink)>>,
+    next_id: AtomicU64,
+}
+
+impl Captures {
+    fn lock(&self) -> MutexGuard<'_, Vec<(CaptureId, Filter, Sink)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn add(&self, filter: Filter, sink: Sink) -> CaptureId {
+        let id = CaptureId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.lock().push((id, filter, sink));
+        id
+    }
+
+    pub(crate) fn remove(&self, id: CaptureId) -> bool {
+        let mut entries = self.lock();
+        let len = entries.len();
+        entries.retain(|(other, _, _)| *other != id);
+        entries.len() != len
+    }
+
+    /// a capture is registered
+    pub(crate) fn is_active(&self) -> bool {
+        !self.lock().is_empty()
+    }
+
+    /// hand the frame to every capture whose filter matches
+    pub(crate) fn capture(&self, direction: Direction, frame: &[u8], link_header_len: usize) {
+        let mut entries = self.lock();
+        if entries.is_empty() {
+            return;
+        }
+        let frame = CapturedFrame::new(direction, frame, link_header_len);
+        for (_, filter, sink) in entries.iter_mut() {
+            if !filter.matches(&frame) {
+                continue;
+            }
+            match sink {
+                Sink::Callback(callback) => callback(&frame),
+                Sink::Ring(ring) => ring.push(&frame),
+            }
+        }
+    }
+}
+
+/// Captures the frames sent through the device
+pub(crate) struct Capturing<'a, L: ?Sized> {
+    device: &'a mut L,
+    captures: &'a Captures,
+}
+
+impl<'a, L: DataLayer + ?Sized> Capturing<'a, L> {
+    pub(crate) fn new(device: &'a mut L, captures: &'a Captures) -> Self {
+        Self { device, captures }
+    }
+}
+
+impl<'a, L: DataLayer + ?Sized> DataLayer for Capturing<'a, L> {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let n = self.device.send(data)?;
+        self.captures.capture(Direction::Out, data, self.device.header_len());
+        Ok(n)
+    }
+
+    /// captured as the large frame handed to the device, like tcpdump does with TSO
+    fn send_segmented(&mut self, bufs: &[IoSlice], mss: usize) -> Result<usize> {
+        let n = self.device.send_segmented(bufs, mss)?;
+        if self.captures.is_active() {
+            self.captures.capture(Direction::Out, &gather(bufs), self.device.header_len());
+        }
+        Ok(n)
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        self.device.recv(data)
+    }
+
+    /// the frame is only gathered when a capture is registered
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        let n = self.device.send_vectored(bufs)?;
+        if self.captures.is_active() {
+            self.captures.capture(Direction::Out, &gather(bufs), self.device.header_len());
+        }
+        Ok(n)
+    }
+
+    fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize> {
+        let n = self.device.send_batch(frames)?;
+        for frame in &frames[..n] {
+            self.captures.capture(Direction::Out, frame, self.device.header_len());
+        }
+        Ok(n)
+    }
+
+    fn header_len(&self) -> usize {
+        self.device.header_len()
+    }
+
+    fn mtu(&self) -> usize {
+        self.device.mtu()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.device.capabilities()
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.device.set_nonblocking(nonblocking)
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.device.raw_fd()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.device.flush()
+    }
+}
+
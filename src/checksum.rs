@@ -0,0 +1,80 @@
+/// Internet checksum (RFC 1071) accumulated over any number of slices,
+/// e.g. a header and a payload gathered from the send queue
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Checksum {
+    sum: u64,
+    /// last byte of an odd length slice, paired with the first byte of the next one
+    odd: Option<u8>,
+}
+
+impl Checksum {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// pseudo header of a tcp or udp checksum, `len` covers header and payload
+    pub fn ipv4_pseudo_header(src: [u8; 4], dest: [u8; 4], protocol: u8, len: usize) -> Self {
+        let mut checksum = Self::new();
+        checksum.add(&src);
+        checksum.add(&dest);
+        checksum.add(&[0, protocol]);
+        checksum.add(&(len as u16).to_be_bytes());
+        checksum
+    }
+
+    pub fn add(&mut self, mut data: &[u8]) {
+        if let Some(high) = self.odd.take() {
+            match data.split_first() {
+                Some((low, rest)) => {
+                    self.sum += u16::from_be_bytes([high, *low]) as u64;
+                    data = rest;
+                }
+                None => {
+                    self.odd = Some(high);
+                    return;
+                }
+            }
+        }
+        let mut words = data.chunks_exact(2);
+        for word in &mut words {
+            self.sum += u16::from_be_bytes([word[0], word[1]]) as u64;
+        }
+        self.odd = words.remainder().first().copied();
+    }
+
+    /// ones' complement of the folded sum
+    pub fn finish(self) -> u16 {
+        !self.fold()
+    }
+
+    /// the folded sum without the complement, what a device offloading the
+    /// checksum expects in the checksum field (the pseudo header sum)
+    pub fn partial(self) -> u16 {
+        self.fold()
+    }
+
+    fn fold(self) -> u16 {
+        let mut sum = self.sum;
+        if let Some(high) = self.odd {
+            sum += (high as u64) << 8;
+        }
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        sum as u16
+    }
+}
+
+/// `checksum` after the bytes `old` of the data it covers were replaced by `new`,
+/// without summing the data again (RFC 1624). the bytes start at an even offset
+/// and `old` and `new` have the same even length
+pub fn adjust(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    let word = |pair: &[u8]| u16::from_be_bytes([pair[0], pair[1]]) as u64;
+    let mut sum = !checksum as u64;
+    sum += old.chunks_exact(2).map(|pair| !word(pair) & 0xffff).sum::<u64>();
+    sum += new.chunks_exact(2).map(word).sum::<u64>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,54 @@
+use alloc::sync::Arc;
+use core::fmt::Debug;
+
+use crate::time::Instant;
+
+/// Source of the current time of the timers and connections of a stack,
+/// a simulation replaces the system clock with a virtual one and without `std`
+/// the caller reads a timer of the device
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// `Instant::now`
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// the clock used when none is configured
+#[cfg(feature = "std")]
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// Time standing still at the epoch, the clock of a connection without `std` until
+/// `TcpConnection::set_clock` gives it the one of the device
+#[cfg(not(feature = "std"))]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct StoppedClock;
+
+#[cfg(not(feature = "std"))]
+impl Clock for StoppedClock {
+    fn now(&self) -> Instant {
+        Instant::EPOCH
+    }
+}
+
+/// the clock of a connection created without one
+#[cfg(feature = "std")]
+pub fn default_clock() -> Arc<dyn Clock> {
+    system_clock()
+}
+
+/// the clock of a connection created without one
+#[cfg(not(feature = "std"))]
+pub fn default_clock() -> Arc<dyn Clock> {
+    Arc::new(StoppedClock)
+}
@@ -0,0 +1,631 @@
+use std::io::{Error, ErrorKind};
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{system_clock, Clock};
+use crate::data_link::arp::{MacAddr, DEFAULT_ARP_TIMEOUT};
+use crate::data_link::ethernet::VLAN_ID_MAX;
+use crate::firewall::{Rule, Verdict};
+
+use crate::meta::{ETHERNET_MTU, IP_HEADER_MAXIMUM_SIZE, TCP_HEADER_MAXIMUM_SIZE};
+use crate::result;
+use crate::tcp::congestion::CongestionAlgorithm;
+use crate::tcp::connection::ConnectionConfig;
+use crate::tcp::shaper::RateLimit;
+use crate::timer::DEFAULT_TIMER_RESOLUTION;
+
+pub const DEFAULT_INTERFACE: &str = "tcp0";
+/// IANA dynamic port range
+pub const DEFAULT_EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+/// memory all the connections of a stack may hold out of order
+pub const DEFAULT_REASSEMBLY_MEMORY: usize = 16 * 1024 * 1024;
+/// ICMP errors per second a stack sends, like the `icmp_ratelimit` of linux
+pub const DEFAULT_ICMP_ERRORS: RateLimit = RateLimit::new(1000, 50);
+
+/// Frames exchanged with the interface
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DeviceMode {
+    /// ip packets
+    Tun,
+    /// ethernet frames
+    Tap,
+}
+
+/// What happens to the connections of an address the stack loses
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AddressChange {
+    /// abort them with a RST, the applications get `AddrNotAvailable`
+    Reset,
+    /// connections of a replaced address go on from the new one, the peer has to accept
+    /// segments from it (a NAT or a peer tracking its clients by port does), the others are reset
+    Migrate,
+}
+
+/// What happens to received packets with a loose or strict source route option
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SourceRoute {
+    /// drop them silently, the default (RFC 7126 4.3)
+    Drop,
+    /// take them like any other packet, the firewall can tell them by their options.
+    /// the replies don't follow the route back
+    Flag,
+}
+
+/// Changes to the configuration of a running stack, see `NetStack::reconfigure`.
+/// what isn't set stays as it is
+#[derive(Default)]
+pub struct Reconfigure {
+    pub(crate) log_level: Option<log::LevelFilter>,
+    pub(crate) congestion: Option<CongestionAlgorithm>,
+    pub(crate) rate_limit: Option<Option<RateLimit>>,
+    pub(crate) egress_limit: Option<Option<RateLimit>>,
+    pub(crate) icmp_errors: Option<Option<RateLimit>>,
+    pub(crate) firewall: Option<(Vec<Rule>, Verdict)>,
+}
+
+impl Reconfigure {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// most verbose log records passed on, the filter of the logger (`RUST_LOG`) still applies
+    pub fn log_level(mut self, level: log::LevelFilter) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// algorithm of the connections opened or accepted from now on, the open ones keep theirs
+    pub fn congestion(mut self, algorithm: CongestionAlgorithm) -> Self {
+        self.congestion = Some(algorithm);
+        self
+    }
+
+    /// cap of every connection, the open ones included, `None` lifts it
+    pub fn rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// cap of all the connections together, the bucket starts full. `None` lifts it
+    pub fn egress_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.egress_limit = Some(limit);
+        self
+    }
+
+    /// limit of the ICMP errors, `None` sends none
+    pub fn icmp_errors(mut self, limit: Option<RateLimit>) -> Self {
+        self.icmp_errors = Some(limit);
+        self
+    }
+
+    /// replace the firewall rules and policy at once, segments see either the old or the new ones
+    pub fn firewall(mut self, rules: Vec<Rule>, policy: Verdict) -> Self {
+        self.firewall = Some((rules, policy));
+        self
+    }
+}
+
+/// Configuration of a `NetStack`, created with `StackConfig::builder()`
+#[derive(Debug, Clone)]
+pub struct StackConfig {
+    interface: String,
+    mode: DeviceMode,
+    addrs: Vec<Ipv4Addr>,
+    mtu: usize,
+    /// the mtu was configured, otherwise it is the one of the device
+    mtu_set: bool,
+    ephemeral_ports: RangeInclusive<u16>,
+    connection: ConnectionConfig,
+    timer_resolution: Duration,
+    queues: usize,
+    shards: Option<usize>,
+    clock: Arc<dyn Clock>,
+    host_addr: Option<(Ipv4Addr, u8)>,
+    transparent: bool,
+    reassembly_memory: usize,
+    address_change: AddressChange,
+    source_route: SourceRoute,
+    packet_info: bool,
+    egress_limit: Option<RateLimit>,
+    icmp_errors: Option<RateLimit>,
+    mac: Option<MacAddr>,
+    gateway: Option<(Ipv4Addr, u8)>,
+    vlan: Option<(u16, u8)>,
+    arp_timeout: Duration,
+    reopen: Option<Duration>,
+    gro: bool,
+}
+
+impl StackConfig {
+    pub fn builder() -> StackConfigBuilder {
+        StackConfigBuilder::default()
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    pub fn mode(&self) -> DeviceMode {
+        self.mode
+    }
+
+    /// the first one is the source address of active opens
+    pub fn addrs(&self) -> &[Ipv4Addr] {
+        &self.addrs
+    }
+
+    /// largest ip packet sent, the segment size derives from it
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// take the mtu of the device unless one was configured, which is lowered to it,
+    /// and fit the segment size to the mtu
+    pub(crate) fn clamp_mtu(mut self, device_mtu: usize) -> Self {
+        let headers = IP_HEADER_MAXIMUM_SIZE + TCP_HEADER_MAXIMUM_SIZE;
+        if device_mtu > headers && (device_mtu < self.mtu || !self.mtu_set) {
+            self.mtu = device_mtu;
+            self.connection.fit_mss(device_mtu - headers);
+        }
+        self
+    }
+
+    pub fn ephemeral_ports(&self) -> RangeInclusive<u16> {
+        self.ephemeral_ports.clone()
+    }
+
+    /// used by sockets created without explicit options
+    pub fn connection(&self) -> &ConnectionConfig {
+        &self.connection
+    }
+
+    pub fn timer_resolution(&self) -> Duration {
+        self.timer_resolution
+    }
+
+    /// queues of the interface, each one processed by its own thread
+    pub fn queues(&self) -> usize {
+        self.queues
+    }
+
+    /// tables the connections are split into, each behind its own lock, at least one per queue
+    pub fn shards(&self) -> usize {
+        self.shards.unwrap_or(self.queues).max(self.queues)
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// address and prefix length of the kernel side of the interface, if `NetStack::new` configures it
+    pub fn host_addr(&self) -> Option<(Ipv4Addr, u8)> {
+        self.host_addr
+    }
+
+    /// segments to any address are accepted, not only those to the addresses of the stack
+    pub fn transparent(&self) -> bool {
+        self.transparent
+    }
+
+    /// memory for out of order segments, shared by the connections of the stack
+    pub fn reassembly_memory(&self) -> usize {
+        self.reassembly_memory
+    }
+
+    /// what `NetStack::replace_addr` and `NetStack::remove_addr` do to the connections of the address
+    pub fn address_change(&self) -> AddressChange {
+        self.address_change
+    }
+
+    /// what happens to source routed packets
+    pub fn source_route(&self) -> SourceRoute {
+        self.source_route
+    }
+
+    /// the TUN interface is opened without IFF_NO_PI
+    pub fn packet_info(&self) -> bool {
+        self.packet_info
+    }
+
+    /// cap on the data all connections of the stack send together
+    pub fn egress_limit(&self) -> Option<RateLimit> {
+        self.egress_limit
+    }
+
+    /// messages per second of the Port and Protocol Unreachable errors answering packets
+    /// of other protocols than tcp, `None` sends none
+    pub fn icmp_errors(&self) -> Option<RateLimit> {
+        self.icmp_errors
+    }
+
+    /// ethernet address of the stack in TAP mode, a random one if `None`
+    pub fn mac(&self) -> Option<MacAddr> {
+        self.mac
+    }
+
+    /// gateway and prefix length of the subnet of the stack in TAP mode
+    pub fn gateway(&self) -> Option<(Ipv4Addr, u8)> {
+        self.gateway
+    }
+
+    /// id and priority of the VLAN the stack is on in TAP mode
+    pub fn vlan(&self) -> Option<(u16, u8)> {
+        self.vlan
+    }
+
+    /// how long a learnt ARP mapping is used before it is confirmed again
+    pub fn arp_timeout(&self) -> Duration {
+        self.arp_timeout
+    }
+
+    /// how often `NetStack::new` tries to open the interface again after it went away
+    pub fn reopen(&self) -> Option<Duration> {
+        self.reopen
+    }
+
+    /// whether consecutive segments of a connection are merged before they are processed
+    pub fn gro(&self) -> bool {
+        self.gro
+    }
+}
+
+pub struct StackConfigBuilder {
+    interface: String,
+    mode: DeviceMode,
+    addrs: Vec<Ipv4Addr>,
+    mtu: Option<usize>,
+    ephemeral_ports: RangeInclusive<u16>,
+    connection: ConnectionConfig,
+    timer_resolution: Duration,
+    queues: usize,
+    shards: Option<usize>,
+    clock: Arc<dyn Clock>,
+    host_addr: Option<(Ipv4Addr, u8)>,
+    transparent: bool,
+    reassembly_memory: usize,
+    address_change: AddressChange,
+    source_route: SourceRoute,
+    packet_info: bool,
+    egress_limit: Option<RateLimit>,
+    icmp_errors: Option<RateLimit>,
+    mac: Option<MacAddr>,
+    gateway: Option<(Ipv4Addr, u8)>,
+    vlan: Option<(u16, u8)>,
+    arp_timeout: Duration,
+    reopen: Option<Duration>,
+    gro: bool,
+}
+
+impl Default for StackConfigBuilder {
+    fn default() -> Self {
+        Self {
+            interface: DEFAULT_INTERFACE.to_string(),
+            mode: DeviceMode::Tun,
+            addrs: Vec::new(),
+            mtu: None,
+            ephemeral_ports: DEFAULT_EPHEMERAL_PORTS,
+            connection: ConnectionConfig::default(),
+            timer_resolution: DEFAULT_TIMER_RESOLUTION,
+            queues: 1,
+            shards: None,
+            clock: system_clock(),
+            host_addr: None,
+            transparent: false,
+            reassembly_memory: DEFAULT_REASSEMBLY_MEMORY,
+            address_change: AddressChange::Reset,
+            source_route: SourceRoute::Drop,
+            packet_info: false,
+            egress_limit: None,
+            icmp_errors: Some(DEFAULT_ICMP_ERRORS),
+            mac: None,
+            gateway: None,
+            vlan: None,
+            arp_timeout: DEFAULT_ARP_TIMEOUT,
+            reopen: None,
+            gro: false,
+        }
+    }
+}
+
+impl StackConfigBuilder {
+    /// name of the TUN/TAP interface opened by `NetStack::new`
+    pub fn interface<S: Into<String>>(mut self, name: S) -> Self {
+        self.interface = name.into();
+        self
+    }
+
+    pub fn mode(mut self, mode: DeviceMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// add an address of the stack, at least one is required
+    pub fn addr(mut self, addr: Ipv4Addr) -> Self {
+        self.addrs.push(addr);
+        self
+    }
+
+    /// largest ip packet sent, e.g. 9000 on a link with jumbo frames. by default the one
+    /// of the device, or `ETHERNET_MTU` for an interface `NetStack::new` configures
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// local ports of active opens
+    pub fn ephemeral_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.ephemeral_ports = ports;
+        self
+    }
+
+    pub fn connection(mut self, config: ConnectionConfig) -> Self {
+        self.connection = config;
+        self
+    }
+
+    pub fn timer_resolution(mut self, resolution: Duration) -> Self {
+        self.timer_resolution = resolution;
+        self
+    }
+
+    /// open the interface with IFF_MULTI_QUEUE and one worker thread per queue (Linux),
+    /// connections are spread over the workers by the hash of their addresses
+    pub fn queues(mut self, queues: usize) -> Self {
+        self.queues = queues;
+        self
+    }
+
+    /// split the connection table into `shards` tables picked by the hash of the quad, each
+    /// behind its own lock, so the sockets of thousands of connections and the drivers don't
+    /// wait for one another. one per queue by default, the queues drive the shards in turn
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
+    /// time source of the timers and connections, the system clock by default
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// let `NetStack::new` set up the interface it opens (Linux): the kernel side gets `addr`
+    /// in a subnet of `prefix_len` bits, which should hold the addresses of the stack, the mtu of
+    /// the interface is set to `mtu` and the link is brought up
+    pub fn host_addr(mut self, addr: Ipv4Addr, prefix_len: u8) -> Self {
+        self.host_addr = Some((addr, prefix_len));
+        self
+    }
+
+    /// accept segments to every address routed to the interface, like IP_TRANSPARENT,
+    /// a connection keeps the address its peer dialed as local address.
+    /// `TcpListener::bind_transparent` then accepts connections to any address and port
+    pub fn transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+
+    pub fn congestion(mut self, algorithm: CongestionAlgorithm) -> Self {
+        self.connection.set_congestion(algorithm);
+        self
+    }
+
+    /// full segments the connections start sending with, see `ConnectionConfig::set_initial_window`
+    pub fn initial_window(mut self, segments: usize) -> Self {
+        self.connection.set_initial_window(segments);
+        self
+    }
+
+    /// bound the memory of the segments all connections hold out of order, a peer sending
+    /// far ahead of the data it owes can't take more. `ConnectionConfig::set_reassembly_limit`
+    /// bounds a single connection
+    pub fn reassembly_memory(mut self, bytes: usize) -> Self {
+        self.reassembly_memory = bytes;
+        self
+    }
+
+    /// reset the connections of a removed or replaced address, the default,
+    /// or move those of a replaced one to the new address
+    pub fn address_change(mut self, policy: AddressChange) -> Self {
+        self.address_change = policy;
+        self
+    }
+
+    /// drop packets with a source route option, the default, or take them
+    pub fn source_route(mut self, policy: SourceRoute) -> Self {
+        self.source_route = policy;
+        self
+    }
+
+    /// open a single queue TUN interface with packet information, the default is IFF_NO_PI.
+    /// the device adds and strips the header, the stack only sees ip packets
+    pub fn packet_info(mut self, packet_info: bool) -> Self {
+        self.packet_info = packet_info;
+        self
+    }
+
+    /// delay the ACK of a single segment up to `timeout` hoping data piggybacks it,
+    /// see `ConnectionConfig::set_delayed_ack`
+    pub fn delayed_ack(mut self, timeout: Duration) -> Self {
+        self.connection.set_delayed_ack(Some(timeout));
+        self
+    }
+
+    /// probe the connections idle for `idle`, see `ConnectionConfig::set_keep_alive`
+    pub fn keep_alive(mut self, idle: Option<Duration>) -> Self {
+        self.connection.set_keep_alive(idle);
+        self
+    }
+
+    /// abort the connections whose data stays unacknowledged for `timeout` and tell the peers,
+    /// see `ConnectionConfig::set_user_timeout`
+    pub fn user_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connection.set_user_timeout(timeout);
+        self
+    }
+
+    /// build segments of up to 64KB and cut them at the mss in the data link layer when the
+    /// device can't, see `ConnectionConfig::set_gso`
+    pub fn gso(mut self, gso: bool) -> Self {
+        self.connection.set_gso(gso);
+        self
+    }
+
+    /// pace the segments of the connections over their round trip, see `ConnectionConfig::set_pacing`
+    pub fn pacing(mut self, pacing: bool) -> Self {
+        self.connection.set_pacing(pacing);
+        self
+    }
+
+    /// cap the bytes per second of data all the connections send together,
+    /// `rate_limit` caps each connection on its own
+    pub fn egress_limit(mut self, limit: RateLimit) -> Self {
+        self.egress_limit = Some(limit);
+        self
+    }
+
+    /// limit the ICMP errors, in messages per second, `None` sends none and leaves
+    /// udp traceroutes to the stack without last hop
+    pub fn icmp_errors(mut self, limit: Option<RateLimit>) -> Self {
+        self.icmp_errors = limit;
+        self
+    }
+
+    /// the ethernet address of the stack in TAP mode
+    pub fn mac(mut self, mac: MacAddr) -> Self {
+        self.mac = Some(mac);
+        self
+    }
+
+    /// in TAP mode, send the packets to addresses outside the subnet of `prefix_len` bits
+    /// of the stack addresses to `gateway`, without one every address is on the link
+    pub fn gateway(mut self, gateway: Ipv4Addr, prefix_len: u8) -> Self {
+        self.gateway = Some((gateway, prefix_len));
+        self
+    }
+
+    /// in TAP mode, attach to the VLAN `id` of a trunk: the frames carry an 802.1Q tag
+    /// with `id` and the `priority` (PCP, 0 to 7), the ones of other VLANs are dropped
+    pub fn vlan(mut self, id: u16, priority: u8) -> Self {
+        self.vlan = Some((id, priority));
+        self
+    }
+
+    /// confirm the ARP mappings learnt longer than `timeout` ago before using them further
+    pub fn arp_timeout(mut self, timeout: Duration) -> Self {
+        self.arp_timeout = timeout;
+        self
+    }
+
+    /// when the interface of `NetStack::new` goes away, e.g. it was deleted, open it again
+    /// every `interval` until it's back instead of failing the sockets with
+    /// `Error::DeviceGone`. the connections survive if the peers wait long enough.
+    /// not for multi-queue interfaces
+    pub fn reopen(mut self, interval: Option<Duration>) -> Self {
+        self.reopen = interval;
+        self
+    }
+
+    /// merge consecutive in-order segments of a connection received together into one before
+    /// they are processed and acknowledged, for devices without GRO, see `EventLoop::set_gro`
+    pub fn gro(mut self, gro: bool) -> Self {
+        self.gro = gro;
+        self
+    }
+
+    /// cap the bytes per second of data every connection sends, see `ConnectionConfig::set_rate_limit`
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.connection.set_rate_limit(Some(limit));
+        self
+    }
+
+    /// shrink the advertised windows when the reassembly memory runs out
+    pub fn pressure_window(mut self, shrink: bool) -> Self {
+        self.connection.set_pressure_window(shrink);
+        self
+    }
+
+    /// validate the configuration, segments are clamped to fit the MTU
+    pub fn build(self) -> result::Result<StackConfig> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidInput, msg.to_string());
+        if self.addrs.is_empty() {
+            return Err(invalid("the stack needs an address").into());
+        }
+        let headers = IP_HEADER_MAXIMUM_SIZE + TCP_HEADER_MAXIMUM_SIZE;
+        let mtu = self.mtu.unwrap_or(ETHERNET_MTU);
+        if mtu <= headers || mtu > u16::MAX as usize {
+            return Err(invalid("mtu too small for the tcp/ip headers or larger than 64KB").into());
+        }
+        if self.ephemeral_ports.is_empty() || *self.ephemeral_ports.start() == 0 {
+            return Err(invalid("empty ephemeral port range").into());
+        }
+        if self.timer_resolution == Duration::from_secs(0) {
+            return Err(invalid("timer resolution must not be zero").into());
+        }
+        if self.queues == 0 {
+            return Err(invalid("the interface needs a queue").into());
+        }
+        if self.shards == Some(0) {
+            return Err(invalid("the connection table needs a shard").into());
+        }
+        if self.packet_info && self.queues > 1 {
+            return Err(invalid("the queues of a multi-queue interface have no packet information").into());
+        }
+        if self.mode == DeviceMode::Tap && (self.queues > 1 || self.packet_info) {
+            return Err(invalid("a tap interface has a single queue without packet information").into());
+        }
+        if self.reopen.is_some() && self.queues > 1 {
+            return Err(invalid("a multi-queue interface can't be reopened").into());
+        }
+        if self.reopen == Some(Duration::from_secs(0)) {
+            return Err(invalid("reopen interval must not be zero").into());
+        }
+        if self.gateway.is_some_and(|(_, prefix_len)| prefix_len > 32) {
+            return Err(invalid("prefix longer than 32 bits").into());
+        }
+        if self.vlan.is_some_and(|(id, priority)| id == 0 || id > VLAN_ID_MAX || priority > 7) {
+            return Err(invalid("vlan id out of 1 to 4094 or priority above 7").into());
+        }
+        if let Some((addr, prefix_len)) = self.host_addr {
+            if prefix_len > 32 {
+                return Err(invalid("prefix longer than 32 bits").into());
+            }
+            if self.addrs.contains(&addr) {
+                return Err(invalid("the host address is an address of the stack").into());
+            }
+        }
+        let mut connection = self.connection;
+        connection.fit_mss(mtu - headers);
+        // the retransmission timer only fires on the ticks of the timer wheel
+        connection.set_clock_granularity(connection.clock_granularity().max(self.timer_resolution));
+        Ok(StackConfig {
+            interface: self.interface,
+            mode: self.mode,
+            addrs: self.addrs,
+            mtu,
+            mtu_set: self.mtu.is_some(),
+            ephemeral_ports: self.ephemeral_ports,
+            connection,
+            timer_resolution: self.timer_resolution,
+            queues: self.queues,
+            shards: self.shards,
+            clock: self.clock,
+            host_addr: self.host_addr,
+            transparent: self.transparent,
+            reassembly_memory: self.reassembly_memory,
+            address_change: self.address_change,
+            source_route: self.source_route,
+            packet_info: self.packet_info,
+            egress_limit: self.egress_limit,
+            icmp_errors: self.icmp_errors,
+            mac: self.mac,
+            gateway: self.gateway,
+            vlan: self.vlan,
+            arp_timeout: self.arp_timeout,
+            reopen: self.reopen,
+            gro: self.gro,
+        })
+    }
+}
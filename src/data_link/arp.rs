@@ -0,0 +1,371 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// how long a learnt mapping is used before it is confirmed again, like base_reachable_time of Linux
+pub const DEFAULT_ARP_TIMEOUT: Duration = Duration::from_secs(60);
+/// least time between two requests for the same address
+pub const ARP_RETRANS_TIME: Duration = Duration::from_secs(1);
+/// packets kept per address until it is resolved, the oldest one is dropped first
+pub const ARP_PENDING_LIMIT: usize = 16;
+
+/// hardware type and protocol of ethernet and ipv4, the only ones the cache resolves
+const HTYPE_ETHERNET: u16 = 1;
+const PTYPE_IPV4: u16 = 0x0800;
+pub(crate) const OP_REQUEST: u16 = 1;
+pub(crate) const OP_REPLY: u16 = 2;
+/// an ARP packet for ethernet and ipv4 (RFC 826)
+const ARP_PACKET_LEN: usize = 28;
+
+/// An ethernet address
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+    pub const ZERO: MacAddr = MacAddr([0; 6]);
+
+    /// a random locally administered unicast address
+    pub fn random() -> Result<Self> {
+        let mut bytes = [0; 6];
+        File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+        bytes[0] = bytes[0] & 0xfc | 0x02;
+        Ok(MacAddr(bytes))
+    }
+
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// group addresses, broadcast included
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+impl fmt::Debug for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// six hexadecimal octets separated by colons, e.g. `02:00:5e:10:00:01`
+impl FromStr for MacAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid mac address `{}`", s));
+        let mut bytes = [0; 6];
+        let mut parts = s.split(':');
+        for byte in &mut bytes {
+            let part = parts.next().filter(|part| part.len() == 2).ok_or_else(invalid)?;
+            *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        match parts.next() {
+            None => Ok(MacAddr(bytes)),
+            Some(_) => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EntryKind {
+    /// inserted by the application, never expires nor changes
+    Static,
+    /// learnt from the network
+    Dynamic,
+    /// a request is out, nothing answered yet
+    Incomplete,
+}
+
+/// One mapping of the cache, see `ArpCache::entries`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ArpEntry {
+    pub ip: Ipv4Addr,
+    /// `None` while incomplete
+    pub mac: Option<MacAddr>,
+    pub kind: EntryKind,
+    /// since the mapping was learnt or confirmed, or since the first request of an incomplete one
+    pub age: Duration,
+    /// packets waiting for the address to be resolved
+    pub pending: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    mac: Option<MacAddr>,
+    kind: EntryKind,
+    updated: Instant,
+    requested: Option<Instant>,
+    pending: VecDeque<Vec<u8>>,
+}
+
+struct Inner {
+    entries: HashMap<Ipv4Addr, Entry>,
+    /// the addresses the link answers requests for
+    locals: Vec<Ipv4Addr>,
+    timeout: Duration,
+    /// addresses to announce with a gratuitous ARP
+    announcements: Vec<Ipv4Addr>,
+    /// wake the driver of the device, it sends the announcements
+    kick: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+/// What sending to an address of the link takes
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Resolution {
+    /// send to `mac`, with a request confirming a stale mapping if one is due
+    Resolved { mac: MacAddr, request: bool },
+    /// the packet waits for the mapping, with a request to send if one is due
+    Pending { request: bool },
+}
+
+/// The ARP cache of an ethernet link (RFC 826): the mappings of the addresses of the
+/// neighbours to their ethernet addresses, learnt from the requests and replies seen and
+/// the ones the application inserted. clones share the cache, the application inspects
+/// and seeds the one of a stack through `NetStack::arp`
+#[derive(Clone)]
+pub struct ArpCache {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ArpCache {
+    /// a cache answering for `locals`, which are announced once the link is up
+    pub fn new(locals: &[Ipv4Addr]) -> Self {
+        let inner = Inner {
+            entries: HashMap::new(),
+            locals: locals.to_vec(),
+            timeout: DEFAULT_ARP_TIMEOUT,
+            announcements: locals.to_vec(),
+            kick: None,
+        };
+        Self { inner: Arc::new(Mutex::new(inner)) }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// every mapping, ordered by address
+    pub fn entries(&self) -> Vec<ArpEntry> {
+        let now = Instant::now();
+        let mut entries: Vec<ArpEntry> = self.lock().entries.iter()
+            .map(|(ip, entry)| ArpEntry {
+                ip: *ip,
+                mac: entry.mac,
+                kind: entry.kind,
+                age: now.saturating_duration_since(entry.updated),
+                pending: entry.pending.len(),
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.ip);
+        entries
+    }
+
+    /// the ethernet address of `ip`, stale mappings included
+    pub fn get(&self, ip: Ipv4Addr) -> Option<MacAddr> {
+        self.lock().entries.get(&ip).and_then(|entry| entry.mac)
+    }
+
+    /// map `ip` to `mac` for good, the network can't change it. packets waiting for
+    /// `ip` leave with the next flush of the link
+    pub fn insert_static(&self, ip: Ipv4Addr, mac: MacAddr) {
+        let mut inner = self.lock();
+        let entry = inner.entries.entry(ip).or_insert_with(|| Entry::new(None, EntryKind::Static));
+        entry.mac = Some(mac);
+        entry.kind = EntryKind::Static;
+        entry.updated = Instant::now();
+        let pending = !entry.pending.is_empty();
+        inner.kick_if(pending);
+    }
+
+    /// forget the mapping of `ip`, static or not, and the packets waiting for it
+    pub fn remove(&self, ip: Ipv4Addr) -> bool {
+        self.lock().entries.remove(&ip).is_some()
+    }
+
+    /// forget every mapping learnt from the network, the static ones stay
+    pub fn clear(&self) {
+        self.lock().entries.retain(|_, entry| entry.kind == EntryKind::Static);
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.lock().timeout
+    }
+
+    /// learnt mappings older than `timeout` are confirmed with a request when they are used
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.lock().timeout = timeout;
+    }
+
+    /// send a gratuitous ARP for `ip`, an address of the link, so the neighbours update
+    /// their caches, e.g. after it moved to this host
+    pub fn announce(&self, ip: Ipv4Addr) -> Result<()> {
+        let mut inner = self.lock();
+        if !inner.locals.contains(&ip) {
+            return Err(Error::new(ErrorKind::AddrNotAvailable, "not an address of the link"));
+        }
+        inner.announcements.push(ip);
+        inner.kick_if(true);
+        Ok(())
+    }
+
+    /// the addresses of the link are now `locals`, the new ones are announced
+    pub(crate) fn set_locals(&self, locals: &[Ipv4Addr]) {
+        let mut inner = self.lock();
+        let new: Vec<Ipv4Addr> = locals.iter().filter(|ip| !inner.locals.contains(ip)).copied().collect();
+        inner.locals = locals.to_vec();
+        inner.kick_if(!new.is_empty());
+        inner.announcements.extend(new);
+    }
+
+    /// `kick` wakes the driver of the link, right away if there are addresses to announce
+    pub(crate) fn set_kick(&self, kick: Arc<dyn Fn() + Send + Sync>) {
+        let mut inner = self.lock();
+        inner.kick = Some(kick);
+        let announce = !inner.announcements.is_empty();
+        inner.kick_if(announce);
+    }
+
+    pub(crate) fn is_local(&self, ip: Ipv4Addr) -> bool {
+        self.lock().locals.contains(&ip)
+    }
+
+    /// the ethernet address to send `packet` for `ip` to, or keep the packet until a reply comes
+    pub(crate) fn resolve(&self, ip: Ipv4Addr, packet: impl FnOnce() -> Vec<u8>) -> Resolution {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        let timeout = inner.timeout;
+        let entry = inner.entries.entry(ip).or_insert_with(|| Entry::new(None, EntryKind::Incomplete));
+        let due = entry.requested.is_none_or(|requested| now.saturating_duration_since(requested) >= ARP_RETRANS_TIME);
+        match entry.mac {
+            Some(mac) => {
+                // a stale mapping is used while a request confirms it
+                let stale = entry.kind == EntryKind::Dynamic && now.saturating_duration_since(entry.updated) >= timeout;
+                let request = stale && due;
+                if request {
+                    entry.requested = Some(now);
+                }
+                Resolution::Resolved { mac, request }
+            }
+            None => {
+                if entry.pending.len() == ARP_PENDING_LIMIT {
+                    entry.pending.pop_front();
+                }
+                entry.pending.push_back(packet());
+                if due {
+                    entry.requested = Some(now);
+                }
+                Resolution::Pending { request: due }
+            }
+        }
+    }
+
+    /// the sender of an ARP packet maps `ip` to `mac`. the mapping is taken if it is known
+    /// already or `solicited`, the packet was for us. returns the packets waiting for it
+    pub(crate) fn learn(&self, ip: Ipv4Addr, mac: MacAddr, solicited: bool) -> Vec<Vec<u8>> {
+        let mut inner = self.lock();
+        let entry = match inner.entries.get_mut(&ip) {
+            Some(entry) => entry,
+            None if solicited => inner.entries.entry(ip).or_insert_with(|| Entry::new(None, EntryKind::Dynamic)),
+            None => return Vec::new(),
+        };
+        if entry.kind != EntryKind::Static {
+            entry.mac = Some(mac);
+            entry.kind = EntryKind::Dynamic;
+            entry.updated = Instant::now();
+            entry.requested = None;
+        }
+        entry.pending.drain(..).collect()
+    }
+
+    /// the packets waiting for addresses which got resolved in the meantime, by a static entry
+    pub(crate) fn take_resolved(&self) -> Vec<(MacAddr, Vec<u8>)> {
+        let mut inner = self.lock();
+        let mut packets = Vec::new();
+        for entry in inner.entries.values_mut() {
+            if let Some(mac) = entry.mac {
+                packets.extend(entry.pending.drain(..).map(|packet| (mac, packet)));
+            }
+        }
+        packets
+    }
+
+    pub(crate) fn take_announcements(&self) -> Vec<Ipv4Addr> {
+        std::mem::take(&mut self.lock().announcements)
+    }
+}
+
+impl fmt::Debug for ArpCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArpCache").field("entries", &self.entries()).finish()
+    }
+}
+
+impl Entry {
+    fn new(mac: Option<MacAddr>, kind: EntryKind) -> Self {
+        Self { mac, kind, updated: Instant::now(), requested: None, pending: VecDeque::new() }
+    }
+}
+
+impl Inner {
+    fn kick_if(&self, work: bool) {
+        if let (true, Some(kick)) = (work, &self.kick) {
+            kick();
+        }
+    }
+}
+
+/// An ARP packet of ethernet and ipv4
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct ArpPacket {
+    pub(crate) op: u16,
+    pub(crate) sender_mac: MacAddr,
+    pub(crate) sender_ip: Ipv4Addr,
+    pub(crate) target_mac: MacAddr,
+    pub(crate) target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// `None` for other hardware or protocol types and truncated packets
+    pub(crate) fn parse(packet: &[u8]) -> Option<Self> {
+        let packet = packet.get(..ARP_PACKET_LEN)?;
+        let be16 = |at: usize| u16::from_be_bytes([packet[at], packet[at + 1]]);
+        if be16(0) != HTYPE_ETHERNET || be16(2) != PTYPE_IPV4 || packet[4] != 6 || packet[5] != 4 {
+            return None;
+        }
+        let mac = |at: usize| MacAddr([packet[at], packet[at + 1], packet[at + 2], packet[at + 3], packet[at + 4], packet[at + 5]]);
+        let ip = |at: usize| Ipv4Addr::new(packet[at], packet[at + 1], packet[at + 2], packet[at + 3]);
+        Some(Self { op: be16(6), sender_mac: mac(8), sender_ip: ip(14), target_mac: mac(18), target_ip: ip(24) })
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; ARP_PACKET_LEN] {
+        let mut packet = [0; ARP_PACKET_LEN];
+        packet[..2].copy_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        packet[2..4].copy_from_slice(&PTYPE_IPV4.to_be_bytes());
+        packet[4] = 6;
+        packet[5] = 4;
+        packet[6..8].copy_from_slice(&self.op.to_be_bytes());
+        packet[8..14].copy_from_slice(&self.sender_mac.0);
+        packet[14..18].copy_from_slice(&self.sender_ip.octets());
+        packet[18..24].copy_from_slice(&self.target_mac.0);
+        packet[24..28].copy_from_slice(&self.target_ip.octets());
+        packet
+    }
+}
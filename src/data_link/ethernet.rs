@@ -0,0 +1,254 @@
+use std::io::Result;
+use std::net::Ipv4Addr;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+use crate::data_link::arp::{ArpCache, ArpPacket, MacAddr, Resolution, OP_REPLY, OP_REQUEST};
+use crate::data_link::{Capabilities, DataLayer};
+use crate::meta::ETHERNET_HEADER_SIZE;
+use crate::net_types::EtherType;
+
+/// shortest frame on the wire without the frame check sequence, shorter ones are padded
+const ETHERNET_MIN_FRAME: usize = 60;
+/// the 802.1Q tag between the source address and the ethertype
+pub const VLAN_TAG_SIZE: usize = 4;
+/// the ids 0 and 4095 are reserved, 0 tags a frame with a priority only
+pub const VLAN_ID_MAX: u16 = 4094;
+
+/// Ethernet framing over a link carrying ethernet frames, e.g. a TAP device, for a stack
+/// which sends and receives ip packets. the next hop of every packet is resolved with the
+/// `ArpCache` of the link, requests for the addresses of the cache are answered and the
+/// frames which aren't ipv4 or ARP are dropped. on a VLAN every frame carries its 802.1Q tag,
+/// frames of other VLANs and untagged ones are dropped. priority tagged frames count as untagged
+pub struct Ethernet<L> {
+    inner: L,
+    mac: MacAddr,
+    arp: ArpCache,
+    /// packets outside the subnet of `prefix_len` bits of the local addresses go through it
+    gateway: Option<(Ipv4Addr, u8)>,
+    /// id and priority of the VLAN the link is on
+    vlan: Option<(u16, u8)>,
+    frame: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl<L: DataLayer> Ethernet<L> {
+    /// frames leave with `mac` as source, every neighbour is on the link
+    pub fn new(inner: L, mac: MacAddr, arp: ArpCache) -> Self {
+        let frame = vec![0; ETHERNET_HEADER_SIZE + VLAN_TAG_SIZE + inner.mtu()];
+        Self { inner, mac, arp, gateway: None, vlan: None, frame, out: Vec::new() }
+    }
+
+    /// send the packets to addresses outside the subnet of `prefix_len` bits of the local
+    /// addresses to `gateway`
+    pub fn with_gateway(mut self, gateway: Ipv4Addr, prefix_len: u8) -> Self {
+        self.gateway = Some((gateway, prefix_len));
+        self
+    }
+
+    /// tag the frames with the VLAN `id` and the `priority` (PCP) of 0 to 7, the
+    /// caller checks that the id is one of 1 to `VLAN_ID_MAX`
+    pub fn with_vlan(mut self, id: u16, priority: u8) -> Self {
+        self.vlan = Some((id & 0x0fff, priority & 0x07));
+        self
+    }
+
+    pub fn vlan(&self) -> Option<u16> {
+        self.vlan.map(|(id, _)| id)
+    }
+
+    pub fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    pub fn arp(&self) -> &ArpCache {
+        &self.arp
+    }
+
+    pub fn into_inner(self) -> L {
+        self.inner
+    }
+
+    /// the neighbour the packet to `dst` is sent to
+    fn next_hop(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Ipv4Addr {
+        match self.gateway {
+            Some((gateway, prefix_len)) if !same_subnet(src, dst, prefix_len) => gateway,
+            _ => dst,
+        }
+    }
+
+    fn send_frame(&mut self, dst: MacAddr, ether_type: EtherType, payload: &[u8]) -> Result<usize> {
+        self.out.clear();
+        self.out.extend_from_slice(&dst.0);
+        self.out.extend_from_slice(&self.mac.0);
+        if let Some((id, priority)) = self.vlan {
+            self.out.extend_from_slice(&u16::from(EtherType::IEEE8021Q).to_be_bytes());
+            self.out.extend_from_slice(&(u16::from(priority) << 13 | id).to_be_bytes());
+        }
+        self.out.extend_from_slice(&u16::from(ether_type).to_be_bytes());
+        self.out.extend_from_slice(payload);
+        if self.out.len() < ETHERNET_MIN_FRAME {
+            self.out.resize(ETHERNET_MIN_FRAME, 0);
+        }
+        self.inner.send(&self.out)?;
+        Ok(payload.len())
+    }
+
+    fn send_arp(&mut self, dst: MacAddr, packet: ArpPacket) -> Result<()> {
+        self.send_frame(dst, EtherType::Arp, &packet.to_bytes())?;
+        Ok(())
+    }
+
+    /// broadcast who has `target`, tell `sender`
+    fn request(&mut self, sender: Ipv4Addr, target: Ipv4Addr) -> Result<()> {
+        let request = ArpPacket { op: OP_REQUEST, sender_mac: self.mac, sender_ip: sender, target_mac: MacAddr::ZERO, target_ip: target };
+        self.send_arp(MacAddr::BROADCAST, request)
+    }
+
+    /// learn the sender of an ARP packet, answer a request for one of our addresses
+    fn on_arp(&mut self, packet: ArpPacket) -> Result<()> {
+        let for_us = self.arp.is_local(packet.target_ip);
+        // an address probe (RFC 5227) has no sender address to learn
+        if !packet.sender_ip.is_unspecified() {
+            for waiting in self.arp.learn(packet.sender_ip, packet.sender_mac, for_us) {
+                self.send_frame(packet.sender_mac, EtherType::IPv4, &waiting)?;
+            }
+        }
+        if packet.op == OP_REQUEST && for_us && packet.sender_ip != packet.target_ip {
+            let reply = ArpPacket {
+                op: OP_REPLY,
+                sender_mac: self.mac,
+                sender_ip: packet.target_ip,
+                target_mac: packet.sender_mac,
+                target_ip: packet.sender_ip,
+            };
+            self.send_arp(packet.sender_mac, reply)?;
+        }
+        Ok(())
+    }
+}
+
+impl<L: DataLayer> DataLayer for Ethernet<L> {
+    /// a packet to a neighbour without mapping waits in the cache, it counts as sent.
+    /// anything but ipv4 is dropped
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        if EtherType::of_ip_packet(data) != Some(EtherType::IPv4) || data.len() < 20 {
+            return Ok(data.len());
+        }
+        let src = Ipv4Addr::new(data[12], data[13], data[14], data[15]);
+        let dst = Ipv4Addr::new(data[16], data[17], data[18], data[19]);
+        if dst.is_broadcast() {
+            return self.send_frame(MacAddr::BROADCAST, EtherType::IPv4, data);
+        }
+        if dst.is_multicast() {
+            // the lower 23 bits of the group in 01:00:5e:00:00:00 (RFC 1112)
+            let [_, b, c, d] = dst.octets();
+            return self.send_frame(MacAddr([0x01, 0x00, 0x5e, b & 0x7f, c, d]), EtherType::IPv4, data);
+        }
+        let next_hop = self.next_hop(src, dst);
+        match self.arp.resolve(next_hop, || data.to_vec()) {
+            Resolution::Resolved { mac, request } => {
+                if request {
+                    self.request(src, next_hop)?;
+                }
+                self.send_frame(mac, EtherType::IPv4, data)
+            }
+            Resolution::Pending { request } => {
+                if request {
+                    self.request(src, next_hop)?;
+                }
+                Ok(data.len())
+            }
+        }
+    }
+
+    /// the next ipv4 packet to our address, broadcast or a group, the ARP packets
+    /// in between are taken care of
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        loop {
+            let n = self.inner.recv(&mut self.frame)?;
+            // nothing was read, the caller decides whether to wait
+            if n == 0 {
+                return Ok(0);
+            }
+            if n < ETHERNET_HEADER_SIZE {
+                continue;
+            }
+            let dst = MacAddr([self.frame[0], self.frame[1], self.frame[2], self.frame[3], self.frame[4], self.frame[5]]);
+            let (vlan, header_len) = match EtherType::from([self.frame[12], self.frame[13]]) {
+                EtherType::IEEE8021Q if n >= ETHERNET_HEADER_SIZE + VLAN_TAG_SIZE => {
+                    (u16::from_be_bytes([self.frame[14], self.frame[15]]) & 0x0fff, ETHERNET_HEADER_SIZE + VLAN_TAG_SIZE)
+                }
+                EtherType::IEEE8021Q => continue,
+                _ => (0, ETHERNET_HEADER_SIZE),
+            };
+            if Some(vlan).filter(|id| *id != 0) != self.vlan() {
+                continue;
+            }
+            let ether_type = EtherType::from([self.frame[header_len - 2], self.frame[header_len - 1]]);
+            match ether_type {
+                EtherType::Arp => {
+                    if let Some(packet) = ArpPacket::parse(&self.frame[header_len..n]) {
+                        self.on_arp(packet)?;
+                    }
+                }
+                EtherType::IPv4 if dst == self.mac || dst.is_multicast() => {
+                    let payload = &self.frame[header_len..n];
+                    // short frames were padded, the ip header knows the length of the packet
+                    let len = match payload.get(2..4).map(|len| u16::from_be_bytes([len[0], len[1]]) as usize) {
+                        Some(len) if len <= payload.len() => len,
+                        _ => payload.len(),
+                    };
+                    let len = len.min(data.len());
+                    data[..len].copy_from_slice(&payload[..len]);
+                    return Ok(len);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.inner.mtu()
+    }
+
+    /// the checksums pass through the framing untouched, the segmentation offloads don't
+    fn capabilities(&self) -> Capabilities {
+        let offload = self.inner.capabilities();
+        let mut capabilities = Capabilities::empty();
+        for checksum in [Capabilities::TX_CHECKSUM, Capabilities::RX_CHECKSUM] {
+            if offload.contains(checksum) {
+                capabilities = capabilities | checksum;
+            }
+        }
+        capabilities
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+
+    /// the gratuitous ARP of the addresses to announce and the packets waiting for
+    /// addresses which got a static entry
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        for ip in self.arp.take_announcements() {
+            // a request with the address as sender and target, the form neighbours take best (RFC 5227 3)
+            self.request(ip, ip)?;
+        }
+        for (mac, packet) in self.arp.take_resolved() {
+            self.send_frame(mac, EtherType::IPv4, &packet)?;
+        }
+        Ok(())
+    }
+}
+
+fn same_subnet(a: Ipv4Addr, b: Ipv4Addr, prefix_len: u8) -> bool {
+    let mask = u32::MAX.checked_shl(32 - prefix_len.min(32) as u32).unwrap_or(0);
+    u32::from(a) & mask == u32::from(b) & mask
+}
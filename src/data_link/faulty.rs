@@ -0,0 +1,316 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{system_clock, Clock};
+use crate::rng::Rng;
+
+use super::{Capabilities, DataLayer};
+
+/// Adverse conditions applied to the frames of one direction of a `FaultyLink`
+#[derive(Debug, Copy, Clone)]
+pub struct FaultConfig {
+    /// probability a frame is dropped
+    pub loss: f64,
+    /// probability a bit after the link header is flipped
+    pub corrupt: f64,
+    /// probability a frame is delivered twice
+    pub duplicate: f64,
+    /// probability a frame is held back by `reorder_delay`
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+    /// added to every frame
+    pub delay: Duration,
+    /// a random extra delay up to `jitter`
+    pub jitter: Duration,
+    /// bytes per second, frames wait for the link to be free
+    pub rate: Option<u64>,
+    /// frames waiting for the link before new ones are dropped
+    pub queue_limit: usize,
+    /// seed of the random decisions
+    pub seed: u64,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            corrupt: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::from_millis(0),
+            delay: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+            rate: None,
+            queue_limit: 1000,
+            seed: 1,
+        }
+    }
+}
+
+/// What a `FaultyLink` did to the frames of one direction
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct FaultStats {
+    pub frames: u64,
+    pub dropped: u64,
+    pub corrupted: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    /// dropped because `queue_limit` frames were waiting
+    pub overflows: u64,
+}
+
+/// a held frame, ordered by release time and then by arrival
+struct Held {
+    at: Instant,
+    seq: u64,
+    frame: Vec<u8>,
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    /// reversed, the heap pops the earliest frame first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+/// Frames waiting until their release time, frames due at the same time keep their order
+#[derive(Default)]
+pub(crate) struct DelayQueue {
+    held: BinaryHeap<Held>,
+    seq: u64,
+}
+
+impl DelayQueue {
+    pub(crate) fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    pub(crate) fn push(&mut self, at: Instant, frame: Vec<u8>) {
+        self.seq += 1;
+        self.held.push(Held { at, seq: self.seq, frame });
+    }
+
+    /// the earliest frame if it's due at `now`
+    pub(crate) fn pop_due(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if self.held.peek()?.at > now {
+            return None;
+        }
+        self.held.pop().map(|held| held.frame)
+    }
+
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.held.peek().map(|held| held.at)
+    }
+}
+
+/// one direction of the link
+struct Faults {
+    config: FaultConfig,
+    rng: Rng,
+    queue: DelayQueue,
+    /// when the frames already queued have been transmitted at `rate`
+    link_free: Option<Instant>,
+    stats: FaultStats,
+}
+
+impl Faults {
+    fn new(config: FaultConfig) -> Self {
+        Self {
+            rng: Rng::new(config.seed),
+            config,
+            queue: DelayQueue::default(),
+            link_free: None,
+            stats: FaultStats::default(),
+        }
+    }
+
+    /// apply the faults to `frame` and queue what is left of it
+    fn admit(&mut self, now: Instant, frame: &[u8], header_len: usize) {
+        let config = self.config;
+        self.stats.frames += 1;
+        if self.rng.chance(config.loss) {
+            self.stats.dropped += 1;
+            return;
+        }
+        if self.queue.len() >= config.queue_limit {
+            self.stats.overflows += 1;
+            return;
+        }
+        let mut frame = frame.to_vec();
+        if frame.len() > header_len && self.rng.chance(config.corrupt) {
+            let at = header_len + self.rng.below(frame.len() - header_len);
+            frame[at] ^= 1 << self.rng.below(8);
+            self.stats.corrupted += 1;
+        }
+        let mut at = now;
+        if let Some(rate) = config.rate.filter(|rate| *rate > 0) {
+            let start = self.link_free.map_or(now, |free| free.max(now));
+            let nanos = frame.len() as u128 * 1_000_000_000 / rate as u128;
+            at = start + Duration::from_nanos(nanos as u64);
+            self.link_free = Some(at);
+        }
+        at += config.delay + self.rng.duration(config.jitter);
+        if self.rng.chance(config.reorder) {
+            at += config.reorder_delay;
+            self.stats.reordered += 1;
+        }
+        if self.rng.chance(config.duplicate) {
+            self.stats.duplicated += 1;
+            self.queue.push(at, frame.clone());
+        }
+        self.queue.push(at, frame);
+    }
+}
+
+/// Decorator dropping, corrupting, duplicating, reordering, delaying and rate limiting the
+/// frames of a device, to test applications against a bad network in userspace.
+///
+/// held frames are released by the next `send` or `recv`, so the link has no file
+/// descriptor and the stack polls it. offloads are hidden, the checksums of the
+/// stack must catch the corrupted frames
+pub struct FaultyLink<L: DataLayer> {
+    inner: L,
+    outbound: Faults,
+    inbound: Faults,
+    clock: Arc<dyn Clock>,
+}
+
+impl<L: DataLayer> FaultyLink<L> {
+    /// the same faults in both directions, with different random decisions
+    pub fn new(inner: L, config: FaultConfig) -> Self {
+        let inbound = FaultConfig {
+            seed: config.seed.rotate_left(32) ^ 0x9e37_79b9_7f4a_7c15,
+            ..config
+        };
+        Self::with_config(inner, config, inbound)
+    }
+
+    pub fn with_config(inner: L, outbound: FaultConfig, inbound: FaultConfig) -> Self {
+        Self {
+            inner,
+            outbound: Faults::new(outbound),
+            inbound: Faults::new(inbound),
+            clock: system_clock(),
+        }
+    }
+
+    /// time source of the delays, e.g. a simulated clock
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// faults of the frames sent from now on
+    pub fn set_outbound(&mut self, config: FaultConfig) {
+        self.outbound.rng = Rng::new(config.seed);
+        self.outbound.config = config;
+    }
+
+    /// faults of the frames received from now on
+    pub fn set_inbound(&mut self, config: FaultConfig) {
+        self.inbound.rng = Rng::new(config.seed);
+        self.inbound.config = config;
+    }
+
+    pub fn outbound_stats(&self) -> FaultStats {
+        self.outbound.stats
+    }
+
+    pub fn inbound_stats(&self) -> FaultStats {
+        self.inbound.stats
+    }
+
+    /// when the next held frame is released, in either direction
+    pub fn next_release(&self) -> Option<Instant> {
+        match (self.outbound.queue.next_due(), self.inbound.queue.next_due()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn get_ref(&self) -> &L {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut L {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> L {
+        self.inner
+    }
+
+    /// send the outbound frames which are due
+    fn release(&mut self, now: Instant) -> Result<()> {
+        while let Some(frame) = self.outbound.queue.pop_due(now) {
+            self.inner.send(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl<L: DataLayer> DataLayer for FaultyLink<L> {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let now = self.clock.now();
+        let header_len = self.inner.header_len();
+        self.outbound.admit(now, data, header_len);
+        self.release(now)?;
+        Ok(data.len())
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        let now = self.clock.now();
+        self.release(now)?;
+        let header_len = self.inner.header_len();
+        loop {
+            if let Some(frame) = self.inbound.queue.pop_due(now) {
+                let n = frame.len().min(data.len());
+                data[..n].copy_from_slice(&frame[..n]);
+                return Ok(n);
+            }
+            match self.inner.recv(data) {
+                Ok(n) => self.inbound.admit(now, &data[..n], header_len),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    return Err(Error::new(ErrorKind::WouldBlock, "no frame due"));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn header_len(&self) -> usize {
+        self.inner.header_len()
+    }
+
+    fn mtu(&self) -> usize {
+        self.inner.mtu()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::empty()
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
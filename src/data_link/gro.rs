@@ -0,0 +1,179 @@
+//! Generic receive offload for devices without GRO: consecutive in-order segments of a
+//! connection drained from the device in one go are merged into one large segment, so the
+//! tcp layer processes and acknowledges them once
+
+use crate::checksum::Checksum;
+
+const TCP: u8 = 6;
+/// flags a merged segment may carry, anything else is processed on its own
+const ACK: u8 = 0x10;
+const PSH: u8 = 0x08;
+/// the largest ip packet a merged segment may grow to
+const MAX_PACKET: usize = u16::MAX as usize;
+
+/// Holds back the last tcp/ipv4 segment received and appends the payload of the
+/// segments continuing it
+#[derive(Debug)]
+pub struct Coalescer {
+    header_len: usize,
+    /// the device verified the checksums, see `Capabilities::RX_CHECKSUM`
+    offloaded: bool,
+    held: Vec<u8>,
+    /// segments merged into `held`, 0 when nothing is held
+    segments: usize,
+}
+
+/// where the headers of a mergeable frame end and what must match to merge it
+struct Headers {
+    ihl: usize,
+    payload: usize,
+    seq: u32,
+    flags: u8,
+}
+
+impl Coalescer {
+    /// frames with a link header of `header_len` bytes
+    pub fn new(header_len: usize, offloaded: bool) -> Self {
+        Self {
+            header_len,
+            offloaded,
+            held: Vec::new(),
+            segments: 0,
+        }
+    }
+
+    /// keep `frame` to merge the following segments into it, or append its payload to the
+    /// held segment it continues. false if it can't be merged, the caller takes the held
+    /// segment and tries again or processes `frame` as it is
+    pub fn push(&mut self, frame: &[u8]) -> bool {
+        let new = match self.parse(frame) {
+            Some(headers) => headers,
+            None => return false,
+        };
+        if self.segments == 0 {
+            self.held.clear();
+            self.held.extend_from_slice(frame);
+            self.segments = 1;
+            return true;
+        }
+        let held = self.parse_held();
+        let continues = held.flags & PSH == 0
+            && held.seq.wrapping_add((self.held.len() - held.payload) as u32) == new.seq
+            && held.payload == new.payload
+            && self.held.len() + frame.len() - new.payload <= self.header_len + MAX_PACKET
+            && self.same_connection(frame, new.ihl);
+        if !continues {
+            return false;
+        }
+        self.held.extend_from_slice(&frame[new.payload..]);
+        // a PSH ends the merged segment, the data is delivered with it
+        let tcp = self.header_len + new.ihl;
+        self.held[tcp + 13] |= new.flags & PSH;
+        self.segments += 1;
+        true
+    }
+
+    /// the held segment, with the lengths and checksums fixed if others were merged into it
+    pub fn take(&mut self) -> Option<Vec<u8>> {
+        match self.segments {
+            0 => return None,
+            1 => {}
+            _ => self.finish(),
+        }
+        self.segments = 0;
+        Some(std::mem::take(&mut self.held))
+    }
+
+    /// segments merged into the held one
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// the headers of a data segment carrying only ACK and PSH whose checksum is valid
+    fn parse(&self, frame: &[u8]) -> Option<Headers> {
+        let ip = frame.get(self.header_len..)?;
+        if ip.len() < 20 || ip[0] >> 4 != 4 || ip[9] != TCP {
+            return None;
+        }
+        let ihl = (ip[0] & 0x0f) as usize * 4;
+        let total = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+        // fragments, ip options and padded frames are left alone
+        let fragment = u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff != 0;
+        if ihl != 20 || fragment || total != ip.len() {
+            return None;
+        }
+        let tcp = &ip[ihl..];
+        let tcp_len = (*tcp.get(12)? >> 4) as usize * 4;
+        if tcp_len < 20 || tcp.len() <= tcp_len {
+            return None;
+        }
+        let flags = tcp[13];
+        if flags & !PSH != ACK {
+            return None;
+        }
+        if !self.offloaded {
+            let src = [ip[12], ip[13], ip[14], ip[15]];
+            let dst = [ip[16], ip[17], ip[18], ip[19]];
+            let mut checksum = Checksum::ipv4_pseudo_header(src, dst, TCP, tcp.len());
+            checksum.add(tcp);
+            if checksum.finish() != 0 {
+                return None;
+            }
+        }
+        Some(Headers {
+            ihl,
+            payload: self.header_len + ihl + tcp_len,
+            seq: u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]),
+            flags,
+        })
+    }
+
+    fn parse_held(&self) -> Headers {
+        let ip = &self.held[self.header_len..];
+        let ihl = (ip[0] & 0x0f) as usize * 4;
+        let tcp = &ip[ihl..];
+        Headers {
+            ihl,
+            payload: self.header_len + ihl + (tcp[12] >> 4) as usize * 4,
+            seq: u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]),
+            flags: tcp[13],
+        }
+    }
+
+    /// same link header, addresses, ports, ack, window and options, only the ip id,
+    /// lengths, checksums, sequence number and PSH may differ
+    fn same_connection(&self, frame: &[u8], ihl: usize) -> bool {
+        let (ip, tcp) = (self.header_len, self.header_len + ihl);
+        let options = tcp + (frame[tcp + 12] >> 4) as usize * 4;
+        let same = [
+            0..ip,
+            ip + 1..ip + 2,
+            ip + 8..ip + 10,
+            ip + 12..ip + 20,
+            tcp..tcp + 4,
+            tcp + 8..tcp + 13,
+            tcp + 14..tcp + 16,
+            tcp + 20..options,
+        ];
+        same.iter().all(|range| self.held[range.clone()] == frame[range.clone()])
+    }
+
+    /// the ip length and both checksums of the merged segment
+    fn finish(&mut self) {
+        let link = self.header_len;
+        let (ip, tcp) = self.held[link..].split_at_mut(20);
+        let total = 20 + tcp.len();
+        ip[2..4].copy_from_slice(&(total as u16).to_be_bytes());
+        ip[10..12].copy_from_slice(&[0, 0]);
+        let mut checksum = Checksum::new();
+        checksum.add(ip);
+        ip[10..12].copy_from_slice(&checksum.finish().to_be_bytes());
+
+        let src = [ip[12], ip[13], ip[14], ip[15]];
+        let dst = [ip[16], ip[17], ip[18], ip[19]];
+        tcp[16..18].copy_from_slice(&[0, 0]);
+        let mut checksum = Checksum::ipv4_pseudo_header(src, dst, TCP, tcp.len());
+        checksum.add(tcp);
+        tcp[16..18].copy_from_slice(&checksum.finish().to_be_bytes());
+    }
+}
@@ -0,0 +1,75 @@
+//! Generic segmentation offload for devices without TSO: the tcp layer builds one large
+//! segment and its headers once, the default `DataLayer::send_segmented` cuts it here into
+//! segments of the mss, each with a copy of the headers patched for its place
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::checksum::Checksum;
+use crate::io::{Error, ErrorKind, Result};
+
+const TCP: u8 = 6;
+const FIN: u8 = 0x01;
+const CWR: u8 = 0x80;
+
+/// the frames of at most `mss` bytes of payload the tcp/ipv4 packet behind the link header
+/// of `header_len` bytes in `frame` is made of. every ip packet gets its own id, the FIN
+/// goes with the last segment and CWR with the first, like the tcp layer would have sent them
+/// one by one. the tcp checksums only cover the pseudo header when the device fills them in
+/// (`offloaded`)
+pub fn segment(frame: &[u8], header_len: usize, mss: usize, offloaded: bool) -> Result<Vec<Vec<u8>>> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, "not a tcp/ipv4 packet");
+    let ip = frame.get(header_len..).filter(|ip| ip.len() >= 20 && ip[0] >> 4 == 4 && ip[9] == TCP).ok_or_else(invalid)?;
+    if mss == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "segments need an mss"));
+    }
+    let ihl = (ip[0] & 0x0f) as usize * 4;
+    let tcp_len = ip.get(ihl + 12).map(|offset| (offset >> 4) as usize * 4).ok_or_else(invalid)?;
+    let headers = header_len + ihl + tcp_len;
+    let payload = frame.get(headers..).ok_or_else(invalid)?;
+    if payload.len() <= mss {
+        return Ok(vec![frame.to_vec()]);
+    }
+    let id = u16::from_be_bytes([ip[4], ip[5]]);
+    let tcp = &ip[ihl..];
+    let seq = u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]);
+    let flags = tcp[13];
+    let (src, dst) = ([ip[12], ip[13], ip[14], ip[15]], [ip[16], ip[17], ip[18], ip[19]]);
+
+    let count = payload.len().div_ceil(mss);
+    let mut frames = Vec::with_capacity(count);
+    for (i, chunk) in payload.chunks(mss).enumerate() {
+        let mut out = Vec::with_capacity(headers + chunk.len());
+        out.extend_from_slice(&frame[..headers]);
+        out.extend_from_slice(chunk);
+        let (ip, tcp) = out[header_len..].split_at_mut(ihl);
+
+        ip[2..4].copy_from_slice(&((ihl + tcp_len + chunk.len()) as u16).to_be_bytes());
+        ip[4..6].copy_from_slice(&id.wrapping_add(i as u16).to_be_bytes());
+        ip[10..12].copy_from_slice(&[0, 0]);
+        let mut checksum = Checksum::new();
+        checksum.add(ip);
+        ip[10..12].copy_from_slice(&checksum.finish().to_be_bytes());
+
+        tcp[4..8].copy_from_slice(&seq.wrapping_add((i * mss) as u32).to_be_bytes());
+        let mut segment_flags = flags;
+        if i > 0 {
+            segment_flags &= !CWR;
+        }
+        if i + 1 < count {
+            segment_flags &= !FIN;
+        }
+        tcp[13] = segment_flags;
+        tcp[16..18].copy_from_slice(&[0, 0]);
+        let mut checksum = Checksum::ipv4_pseudo_header(src, dst, TCP, tcp.len());
+        let sum = if offloaded {
+            checksum.partial()
+        } else {
+            checksum.add(tcp);
+            checksum.finish()
+        };
+        tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+        frames.push(out);
+    }
+    Ok(frames)
+}
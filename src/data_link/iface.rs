@@ -0,0 +1,94 @@
+//! Configuration of a network interface with the SIOCGIF*/SIOCSIF* ioctls,
+//! what `ip addr add` and `ip link set` do for the kernel side of a TUN interface
+
+use std::io::{Error, ErrorKind, Result};
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+/// socket the interface ioctls are issued on
+struct Control {
+    sock: OwnedFd,
+    req: libc::ifreq,
+}
+
+impl Control {
+    fn new(name: &str) -> Result<Self> {
+        let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+        if name.is_empty() || name.len() >= req.ifr_name.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid interface name"));
+        }
+        for (dst, src) in req.ifr_name.iter_mut().zip(name.bytes()) {
+            *dst = src as libc::c_char;
+        }
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Self { sock, req })
+    }
+
+    fn ioctl(&mut self, request: libc::c_ulong) -> Result<()> {
+        if unsafe { libc::ioctl(self.sock.as_raw_fd(), request, &mut self.req as *mut libc::ifreq) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// mtu of the interface `name` (SIOCGIFMTU)
+pub fn mtu(name: &str) -> Result<usize> {
+    let mut control = Control::new(name)?;
+    control.ioctl(libc::SIOCGIFMTU)?;
+    Ok(unsafe { control.req.ifr_ifru.ifru_mtu } as usize)
+}
+
+/// largest ip packet the interface carries (SIOCSIFMTU)
+pub fn set_mtu(name: &str, mtu: usize) -> Result<()> {
+    if mtu > libc::c_int::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "mtu too large"));
+    }
+    let mut control = Control::new(name)?;
+    control.req.ifr_ifru.ifru_mtu = mtu as libc::c_int;
+    control.ioctl(libc::SIOCSIFMTU)
+}
+
+/// give the interface `addr` in a subnet of `prefix_len` bits (SIOCSIFADDR, SIOCSIFNETMASK),
+/// the kernel routes the subnet to the interface once it's up
+pub fn set_addr(name: &str, addr: Ipv4Addr, prefix_len: u8) -> Result<()> {
+    if prefix_len > 32 {
+        return Err(Error::new(ErrorKind::InvalidInput, "prefix longer than 32 bits"));
+    }
+    let netmask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+    let mut control = Control::new(name)?;
+    control.req.ifr_ifru.ifru_addr = sockaddr(addr);
+    control.ioctl(libc::SIOCSIFADDR)?;
+    control.req.ifr_ifru.ifru_netmask = sockaddr(Ipv4Addr::from(netmask));
+    control.ioctl(libc::SIOCSIFNETMASK)
+}
+
+/// bring the link up or down (SIOCSIFFLAGS)
+pub fn set_up(name: &str, up: bool) -> Result<()> {
+    let mut control = Control::new(name)?;
+    control.ioctl(libc::SIOCGIFFLAGS)?;
+    let flags = (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+    unsafe {
+        if up {
+            control.req.ifr_ifru.ifru_flags |= flags;
+        } else {
+            control.req.ifr_ifru.ifru_flags &= !flags;
+        }
+    }
+    control.ioctl(libc::SIOCSIFFLAGS)
+}
+
+fn sockaddr(addr: Ipv4Addr) -> libc::sockaddr {
+    let sin = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: 0,
+        sin_addr: libc::in_addr { s_addr: u32::from_ne_bytes(addr.octets()) },
+        sin_zero: [0; 8],
+    };
+    // sockaddr_in and sockaddr have the same size
+    unsafe { std::mem::transmute(sin) }
+}
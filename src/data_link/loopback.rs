@@ -0,0 +1,131 @@
+use std::io::{Error, ErrorKind, Result};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use super::{BufferHandle, Capabilities, DataLayer};
+use crate::meta::ETHERNET_MTU;
+
+/// In-memory link between two endpoints
+/// every frame sent on one endpoint is received by the other one,
+/// so two stacks can talk to each other without a TUN device or root privileges
+pub struct Loopback {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    /// `None` means recv blocks until a frame arrives
+    read_timeout: Option<Duration>,
+    nonblocking: bool,
+    capabilities: Capabilities,
+    mtu: usize,
+}
+
+impl Loopback {
+    /// create two connected endpoints
+    pub fn pair() -> (Loopback, Loopback) {
+        let (a_tx, b_rx) = mpsc::channel();
+        let (b_tx, a_rx) = mpsc::channel();
+        (Self::new(a_tx, a_rx), Self::new(b_tx, b_rx))
+    }
+
+    fn new(tx: Sender<Vec<u8>>, rx: Receiver<Vec<u8>>) -> Self {
+        Self {
+            tx,
+            rx,
+            read_timeout: None,
+            nonblocking: false,
+            capabilities: Capabilities::empty(),
+            mtu: ETHERNET_MTU,
+        }
+    }
+
+    /// recv returns `ErrorKind::TimedOut` if no frame arrives within `timeout`
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// frames can't be corrupted in memory, so both endpoints may skip checksums.
+    /// `TX_CHECKSUM` on one end needs `RX_CHECKSUM` on the other
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// the mtu the endpoint reports, `ETHERNET_MTU` by default. frames aren't checked against it
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+    }
+
+    /// non-blocking receive, return `None` if no frame is waiting
+    pub fn try_recv(&mut self, data: &mut [u8]) -> Result<Option<usize>> {
+        match self.rx.try_recv() {
+            Ok(frame) => Ok(Some(copy_frame(&frame, data))),
+            Err(mpsc::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::TryRecvError::Disconnected) => Err(peer_closed()),
+        }
+    }
+}
+
+impl DataLayer for Loopback {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.tx.send(data.to_vec()).map_err(|_| peer_closed())?;
+        Ok(data.len())
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        if self.nonblocking {
+            return self.try_recv(data)?
+                .ok_or_else(|| Error::new(ErrorKind::WouldBlock, "no frame waiting"));
+        }
+        let frame = match self.read_timeout {
+            None => self.rx.recv().map_err(|_| peer_closed())?,
+            Some(timeout) => match self.rx.recv_timeout(timeout) {
+                Ok(frame) => frame,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(Error::new(ErrorKind::TimedOut, "loopback read timed out"));
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(peer_closed()),
+            }
+        };
+        Ok(copy_frame(&frame, data))
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [BufferHandle]) -> Result<usize> {
+        let (first, rest) = match bufs.split_first_mut() {
+            Some(split) => split,
+            None => return Ok(0),
+        };
+        let n = self.recv(first.space())?;
+        first.set_len(n);
+        let mut filled = 1;
+        for buf in rest {
+            match self.try_recv(buf.space())? {
+                Some(n) => buf.set_len(n),
+                None => break,
+            }
+            filled += 1;
+        }
+        Ok(filled)
+    }
+}
+
+/// like a real device, a frame larger than the buffer is truncated
+fn copy_frame(frame: &[u8], data: &mut [u8]) -> usize {
+    let n = frame.len().min(data.len());
+    data[..n].copy_from_slice(&frame[..n]);
+    n
+}
+
+fn peer_closed() -> Error {
+    Error::new(ErrorKind::BrokenPipe, "loopback peer closed")
+}
@@ -1,17 +1,406 @@
-use std::io::Result;
+#[cfg(feature = "std")]
+pub mod loopback;
+#[cfg(feature = "std")]
+pub mod arp;
+#[cfg(feature = "std")]
+pub mod ethernet;
+#[cfg(feature = "std")]
+pub mod faulty;
+#[cfg(feature = "std")]
+pub mod gro;
+pub mod gso;
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub mod iface;
+#[cfg(feature = "std")]
+pub mod pcap;
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub mod packet_socket;
+#[cfg(feature = "std")]
+pub mod tun;
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::BitOr;
+#[cfg(all(unix, feature = "std"))]
+use std::io::IoSliceMut;
+#[cfg(all(unix, feature = "std"))]
+use std::os::unix::io::RawFd;
+
+#[cfg(feature = "std")]
+use crate::buffer::{BufferPool, PooledBuf};
+use crate::io::{Error, ErrorKind, IoSlice, Result};
+use crate::meta::ETHERNET_MTU;
+
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub(crate) use self::iface::mtu as interface_mtu;
+
+/// Work a device does for the stack, see `DataLayer::capabilities`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// the device fills in the checksums of sent segments
+    pub const TX_CHECKSUM: Capabilities = Capabilities(0b0001);
+    /// the device verified the checksums of received segments
+    pub const RX_CHECKSUM: Capabilities = Capabilities(0b0010);
+    /// the device splits segments larger than the mss (TCP segmentation offload)
+    pub const TSO: Capabilities = Capabilities(0b0100);
+    /// received segments may be coalesced into packets of up to 64KB (generic receive offload)
+    pub const GRO: Capabilities = Capabilities(0b1000);
+
+    pub const fn empty() -> Self {
+        Capabilities(0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, rhs: Capabilities) -> Capabilities {
+        Capabilities(self.0 | rhs.0)
+    }
+}
+
+/// A link the stack sends and receives ip packets on. object safe, so a
+/// `Box<dyn DataLayer>` or `&mut dyn DataLayer` works wherever a device is expected
 pub trait DataLayer {
     fn send(&mut self, data: &[u8]) -> Result<usize>;
 
     fn recv(&mut self, data: &mut [u8]) -> Result<usize>;
+
+    /// length of the link specific header in front of the ip header of every frame,
+    /// e.g. the packet information of a TUN device
+    fn header_len(&self) -> usize {
+        0
+    }
+
+    /// largest ip packet the link carries, the link header not included
+    fn mtu(&self) -> usize {
+        ETHERNET_MTU
+    }
+
+    /// offloads of the device, with `TX_CHECKSUM` the tcp checksum of sent segments
+    /// only covers the pseudo header and with `RX_CHECKSUM` received ones aren't verified
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::empty()
+    }
+
+    /// in non-blocking mode recv returns `ErrorKind::WouldBlock` instead of waiting for a frame
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> Result<()> {
+        Err(Error::new(ErrorKind::Unsupported, "device does not support non-blocking mode"))
+    }
+
+    /// file descriptor which becomes readable when a frame is waiting,
+    /// devices without one are polled by the event loop
+    #[cfg(all(unix, feature = "std"))]
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// send one frame made of several slices, e.g. headers and a payload which stays
+    /// in the send queue. the default copies them into one buffer, backends with
+    /// a scatter-gather write like writev override it
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        self.send(&gather(bufs))
+    }
+
+    /// send one frame with a tcp payload larger than `mss` as segments of at most `mss`
+    /// bytes. a device with `TSO` cuts it itself, the default cuts it in software (GSO)
+    /// and sends the segments as a batch
+    fn send_segmented(&mut self, bufs: &[IoSlice], mss: usize) -> Result<usize> {
+        let frame = gather(bufs);
+        let offloaded = self.capabilities().contains(Capabilities::TX_CHECKSUM);
+        let segments = gso::segment(&frame, self.header_len(), mss, offloaded)?;
+        let frames: Vec<&[u8]> = segments.iter().map(Vec::as_slice).collect();
+        self.send_batch(&frames)?;
+        Ok(frame.len())
+    }
+
+    /// send several frames, return the number of frames sent.
+    /// backends override this when the device can take many frames per syscall
+    fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize> {
+        for frame in frames {
+            self.send(frame)?;
+        }
+        Ok(frames.len())
+    }
+
+    /// block until at least one frame arrives, then fill as many `bufs` as possible
+    /// without blocking again, return the number of buffers filled
+    #[cfg(feature = "std")]
+    fn recv_batch(&mut self, bufs: &mut [BufferHandle]) -> Result<usize> {
+        match bufs.first_mut() {
+            Some(buf) => {
+                let n = self.recv(buf.space())?;
+                buf.set_len(n);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// send the frames the device queued on its own, e.g. ARP announcements,
+    /// called whenever the stack sends what the applications queued
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<L: DataLayer + ?Sized> DataLayer for &mut L {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        (**self).send(data)
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        (**self).recv(data)
+    }
+
+    fn header_len(&self) -> usize {
+        (**self).header_len()
+    }
+
+    fn mtu(&self) -> usize {
+        (**self).mtu()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        (**self).set_nonblocking(nonblocking)
+    }
+
+    #[cfg(all(unix, feature = "std"))]
+    fn raw_fd(&self) -> Option<RawFd> {
+        (**self).raw_fd()
+    }
+
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        (**self).send_vectored(bufs)
+    }
+
+    fn send_segmented(&mut self, bufs: &[IoSlice], mss: usize) -> Result<usize> {
+        (**self).send_segmented(bufs, mss)
+    }
+
+    fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize> {
+        (**self).send_batch(frames)
+    }
+
+    #[cfg(feature = "std")]
+    fn recv_batch(&mut self, bufs: &mut [BufferHandle]) -> Result<usize> {
+        (**self).recv_batch(bufs)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
 }
 
-impl DataLayer for tun_tap::Iface {
+impl<L: DataLayer + ?Sized> DataLayer for Box<L> {
     fn send(&mut self, data: &[u8]) -> Result<usize> {
-        self.send(data)
+        (**self).send(data)
     }
 
     fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
-        self.recv(data)
+        (**self).recv(data)
+    }
+
+    fn header_len(&self) -> usize {
+        (**self).header_len()
+    }
+
+    fn mtu(&self) -> usize {
+        (**self).mtu()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        (**self).set_nonblocking(nonblocking)
+    }
+
+    #[cfg(all(unix, feature = "std"))]
+    fn raw_fd(&self) -> Option<RawFd> {
+        (**self).raw_fd()
+    }
+
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        (**self).send_vectored(bufs)
+    }
+
+    fn send_segmented(&mut self, bufs: &[IoSlice], mss: usize) -> Result<usize> {
+        (**self).send_segmented(bufs, mss)
+    }
+
+    fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize> {
+        (**self).send_batch(frames)
+    }
+
+    #[cfg(feature = "std")]
+    fn recv_batch(&mut self, bufs: &mut [BufferHandle]) -> Result<usize> {
+        (**self).recv_batch(bufs)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+/// Receive buffer used by `recv_batch`, holds at most one frame
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct BufferHandle {
+    buf: PooledBuf,
+    len: usize,
+}
+
+#[cfg(feature = "std")]
+impl BufferHandle {
+    pub fn new(capacity: usize) -> Self {
+        let mut buf = PooledBuf::unpooled(capacity);
+        buf.resize(capacity);
+        Self { buf, len: 0 }
+    }
+
+    /// buffer large enough for any frame of an ethernet sized link
+    pub fn with_mtu(header_len: usize) -> Self {
+        Self::new(ETHERNET_MTU + header_len)
+    }
+
+    /// a buffer of `pool`, returned to it when the handle is dropped
+    pub fn from_pool(pool: &BufferPool) -> Self {
+        let mut buf = pool.get();
+        buf.resize(pool.buffer_size());
+        Self { buf, len: 0 }
+    }
+
+    /// the received frame
+    pub fn frame(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// the whole buffer, to be filled by a backend
+    pub fn space(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.buf.len(), "frame length exceeds buffer capacity");
+        self.len = len;
+    }
+}
+
+/// receive buffer for frames of `device` carrying ip packets of up to `mtu` bytes,
+/// or coalesced ones of up to 64KB when the device does GRO
+#[cfg(feature = "std")]
+pub(crate) fn recv_buffer_len<L: DataLayer + ?Sized>(device: &L, mtu: usize) -> usize {
+    let packet = if device.capabilities().contains(Capabilities::GRO) { u16::MAX as usize } else { mtu };
+    device.header_len() + packet
+}
+
+/// the slices of a vectored frame copied into one buffer
+#[cfg(feature = "std")]
+pub(crate) fn gather(bufs: &[IoSlice]) -> PooledBuf {
+    let mut frame = BufferPool::global().get();
+    for buf in bufs {
+        frame.extend_from_slice(buf);
+    }
+    frame
+}
+
+/// the slices of a vectored frame copied into one buffer
+#[cfg(not(feature = "std"))]
+pub(crate) fn gather(bufs: &[IoSlice]) -> Vec<u8> {
+    bufs.iter().flat_map(|buf| buf.iter().copied()).collect()
+}
+
+/// the device went away: its interface was deleted or the file descriptor detached from it
+/// (EBADFD, ENODEV or ENXIO), or the other end of a `Loopback` was dropped
+#[cfg(feature = "std")]
+pub fn is_device_gone(e: &Error) -> bool {
+    #[cfg(unix)]
+    if let Some(code) = e.raw_os_error() {
+        #[cfg(target_os = "linux")]
+        if code == libc::EBADFD {
+            return true;
+        }
+        return code == libc::ENODEV || code == libc::ENXIO;
+    }
+    e.raw_os_error().is_none() && e.kind() == ErrorKind::BrokenPipe
+}
+
+/// slices a single writev or readv takes, UIO_MAXIOV on Linux and IOV_MAX on macOS
+#[cfg(all(unix, feature = "std"))]
+const MAX_IOVECS: usize = 1024;
+
+/// write one frame made of `bufs` with a single writev, retried when a signal interrupts it
+#[cfg(all(unix, feature = "std"))]
+pub(crate) fn writev(fd: RawFd, bufs: &[IoSlice]) -> Result<usize> {
+    let count = bufs.len().min(MAX_IOVECS) as libc::c_int;
+    // IoSlice is guaranteed to be ABI compatible with iovec
+    loop {
+        let n = unsafe { libc::writev(fd, bufs.as_ptr() as *const libc::iovec, count) };
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+        let e = Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// read one frame into `bufs` with a single readv, retried when a signal interrupts it
+#[cfg(all(unix, feature = "std"))]
+pub(crate) fn readv(fd: RawFd, bufs: &mut [IoSliceMut]) -> Result<usize> {
+    let count = bufs.len().min(MAX_IOVECS) as libc::c_int;
+    // IoSliceMut is guaranteed to be ABI compatible with iovec
+    loop {
+        let n = unsafe { libc::readv(fd, bufs.as_mut_ptr() as *mut libc::iovec, count) };
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+        let e = Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// toggle O_NONBLOCK of a device file descriptor
+#[cfg(all(unix, feature = "std"))]
+pub(crate) fn set_fd_nonblocking(fd: RawFd, nonblocking: bool) -> Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(Error::last_os_error());
+    }
+    let flags = if nonblocking { flags | libc::O_NONBLOCK } else { flags & !libc::O_NONBLOCK };
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+        return Err(Error::last_os_error());
     }
+    Ok(())
 }
@@ -0,0 +1,220 @@
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use libc::{c_int, c_uint, c_void, iovec, mmsghdr, sock_filter, sock_fprog, sockaddr_ll, socklen_t};
+
+use crate::meta::{ETHERNET_HEADER_SIZE, ETHERNET_MTU};
+
+use super::{interface_mtu, set_fd_nonblocking, BufferHandle, DataLayer};
+
+/// DataLayer on top of an `AF_PACKET` raw socket bound to a physical NIC
+/// frames carry the ethernet header.
+/// Requires CAP_NET_RAW
+pub struct PacketSocket {
+    fd: RawFd,
+    ifindex: c_int,
+    mtu: usize,
+}
+
+impl PacketSocket {
+    /// open a raw socket receiving every frame of the interface `ifname`
+    pub fn bind(ifname: &str) -> Result<Self> {
+        let name = CString::new(ifname).map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid interface name"))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) } as c_int;
+        if ifindex == 0 {
+            return Err(Error::last_os_error());
+        }
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, protocol as c_int) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let mtu = interface_mtu(ifname).unwrap_or(ETHERNET_MTU);
+        let socket = Self { fd, ifindex, mtu };
+
+        let mut addr: sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = protocol;
+        addr.sll_ifindex = ifindex;
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<sockaddr_ll>() as socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(socket)
+    }
+
+    /// only deliver frames accepted by the classic BPF `program`,
+    /// the kernel drops everything else before it is copied to user space
+    pub fn attach_filter(&mut self, program: &[sock_filter]) -> Result<()> {
+        let prog = sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_ptr() as *mut sock_filter,
+        };
+        self.set_option(libc::SOL_SOCKET, libc::SO_ATTACH_FILTER, &prog)
+    }
+
+    /// receive frames not addressed to the NIC as well
+    pub fn set_promiscuous(&mut self, on: bool) -> Result<()> {
+        let mreq = libc::packet_mreq {
+            mr_ifindex: self.ifindex,
+            mr_type: libc::PACKET_MR_PROMISC as u16,
+            mr_alen: 0,
+            mr_address: [0; 8],
+        };
+        let option = if on { libc::PACKET_ADD_MEMBERSHIP } else { libc::PACKET_DROP_MEMBERSHIP };
+        self.set_option(libc::SOL_PACKET, option, &mreq)
+    }
+
+    pub fn ifindex(&self) -> c_int {
+        self.ifindex
+    }
+
+    fn set_option<T>(&mut self, level: c_int, name: c_int, value: &T) -> Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd,
+                level,
+                name,
+                value as *const T as *const c_void,
+                mem::size_of::<T>() as socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl DataLayer for PacketSocket {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let n = unsafe { libc::send(self.fd, data.as_ptr() as *const c_void, data.len(), 0) };
+        if n < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        let n = unsafe { libc::recv(self.fd, data.as_mut_ptr() as *mut c_void, data.len(), 0) };
+        if n < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn header_len(&self) -> usize {
+        ETHERNET_HEADER_SIZE
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        set_fd_nonblocking(self.fd, nonblocking)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.fd)
+    }
+
+    /// one sendmmsg(2) for the whole batch
+    fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize> {
+        let mut iovecs: Vec<iovec> = frames.iter()
+            .map(|frame| iovec { iov_base: frame.as_ptr() as *mut c_void, iov_len: frame.len() })
+            .collect();
+        let mut msgs: Vec<mmsghdr> = iovecs.iter_mut().map(mmsg).collect();
+        let n = unsafe { libc::sendmmsg(self.fd, msgs.as_mut_ptr(), msgs.len() as c_uint, 0) };
+        if n < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    /// one recvmmsg(2) waiting for the first frame only
+    fn recv_batch(&mut self, bufs: &mut [BufferHandle]) -> Result<usize> {
+        let mut iovecs: Vec<iovec> = bufs.iter_mut()
+            .map(|buf| {
+                let space = buf.space();
+                iovec { iov_base: space.as_mut_ptr() as *mut c_void, iov_len: space.len() }
+            })
+            .collect();
+        let mut msgs: Vec<mmsghdr> = iovecs.iter_mut().map(mmsg).collect();
+        let n = unsafe {
+            libc::recvmmsg(self.fd, msgs.as_mut_ptr(), msgs.len() as c_uint, libc::MSG_WAITFORONE, std::ptr::null_mut())
+        };
+        if n < 0 {
+            return Err(Error::last_os_error());
+        }
+        for (buf, msg) in bufs.iter_mut().zip(&msgs).take(n as usize) {
+            buf.set_len(msg.msg_len as usize);
+        }
+        Ok(n as usize)
+    }
+}
+
+fn mmsg(iov: &mut iovec) -> mmsghdr {
+    let mut msg: mmsghdr = unsafe { mem::zeroed() };
+    msg.msg_hdr.msg_iov = iov;
+    msg.msg_hdr.msg_iovlen = 1;
+    msg
+}
+
+impl AsRawFd for PacketSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// same as the `BPF_STMT` macro of linux/filter.h
+pub fn bpf_stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter { code: code as u16, jt: 0, jf: 0, k }
+}
+
+/// same as the `BPF_JUMP` macro of linux/filter.h
+pub fn bpf_jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter { code: code as u16, jt, jf, k }
+}
+
+/// BPF program accepting IPv4 TCP frames whose source or destination port is `port`,
+/// equivalent to `tcpdump -dd "ip and tcp port <port>"` without fragments
+pub fn tcp_port_filter(port: u16) -> Vec<sock_filter> {
+    use libc::{BPF_ABS, BPF_B, BPF_H, BPF_IND, BPF_JEQ, BPF_JMP, BPF_JSET, BPF_K, BPF_LD, BPF_LDX, BPF_MSH, BPF_RET};
+    let port = port as u32;
+    vec![
+        // ethernet type
+        bpf_stmt(BPF_LD | BPF_H | BPF_ABS, 12),
+        bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, 0x0800, 0, 10),
+        // ip protocol
+        bpf_stmt(BPF_LD | BPF_B | BPF_ABS, 23),
+        bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, 6, 0, 8),
+        // fragment offset must be zero to see the tcp header
+        bpf_stmt(BPF_LD | BPF_H | BPF_ABS, 20),
+        bpf_jump(BPF_JMP | BPF_JSET | BPF_K, 0x1fff, 6, 0),
+        // x = ip header length
+        bpf_stmt(BPF_LDX | BPF_B | BPF_MSH, 14),
+        bpf_stmt(BPF_LD | BPF_H | BPF_IND, 14),
+        bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, port, 2, 0),
+        bpf_stmt(BPF_LD | BPF_H | BPF_IND, 16),
+        bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, port, 0, 1),
+        bpf_stmt(BPF_RET | BPF_K, 0x0004_0000),
+        bpf_stmt(BPF_RET | BPF_K, 0),
+    ]
+}
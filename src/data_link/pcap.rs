@@ -0,0 +1,275 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, IoSlice, Read, Result, Write};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{gather, Capabilities, DataLayer};
+
+/// Ethernet frames, see http://www.tcpdump.org/linktypes.html
+pub const LINKTYPE_ETHERNET: u32 = 1;
+/// Raw IPv4/IPv6 packets without any link header
+pub const LINKTYPE_RAW: u32 = 101;
+
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+const DEFAULT_SNAPLEN: u32 = 65535;
+
+/// Write frames into a libpcap file which can be opened with Wireshark or tcpdump
+pub struct PcapWriter<W: Write> {
+    out: W,
+    snaplen: u32,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    /// create (or truncate) a pcap file at `path`
+    pub fn create<P: AsRef<Path>>(path: P, link_type: u32) -> Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), link_type)
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// write the global header to `out`
+    pub fn new(mut out: W, link_type: u32) -> Result<Self> {
+        out.write_all(&MAGIC_MICROS.to_le_bytes())?;
+        out.write_all(&VERSION_MAJOR.to_le_bytes())?;
+        out.write_all(&VERSION_MINOR.to_le_bytes())?;
+        // thiszone and sigfigs, always zero
+        out.write_all(&0_i32.to_le_bytes())?;
+        out.write_all(&0_u32.to_le_bytes())?;
+        out.write_all(&DEFAULT_SNAPLEN.to_le_bytes())?;
+        out.write_all(&link_type.to_le_bytes())?;
+        Ok(Self {
+            out,
+            snaplen: DEFAULT_SNAPLEN,
+        })
+    }
+
+    /// append one frame stamped with the current time
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let caplen = frame.len().min(self.snaplen as usize);
+        self.out.write_all(&(now.as_secs() as u32).to_le_bytes())?;
+        self.out.write_all(&now.subsec_micros().to_le_bytes())?;
+        self.out.write_all(&(caplen as u32).to_le_bytes())?;
+        self.out.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.out.write_all(&frame[..caplen])?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Read frames from a libpcap file, both byte orders
+/// and the nanosecond variant of the format are accepted
+pub struct PcapReader<R: Read> {
+    input: R,
+    swapped: bool,
+    link_type: u32,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// read and validate the global header
+    pub fn new(mut input: R) -> Result<Self> {
+        let mut header = [0_u8; 24];
+        input.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let swapped = if magic == MAGIC_MICROS || magic == MAGIC_NANOS {
+            false
+        } else if magic.swap_bytes() == MAGIC_MICROS || magic.swap_bytes() == MAGIC_NANOS {
+            true
+        } else {
+            return Err(Error::new(ErrorKind::InvalidData, "not a pcap file"));
+        };
+        let mut reader = Self {
+            input,
+            swapped,
+            link_type: 0,
+        };
+        reader.link_type = reader.u32_at(&header, 20);
+        Ok(reader)
+    }
+
+    pub fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    /// return the next captured frame, `None` at the end of the file
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut header = [0_u8; 16];
+        match self.input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let caplen = self.u32_at(&header, 8) as usize;
+        let mut frame = vec![0_u8; caplen];
+        self.input.read_exact(&mut frame)?;
+        Ok(Some(frame))
+    }
+
+    fn u32_at(&self, buf: &[u8], at: usize) -> u32 {
+        let value = u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+        if self.swapped { value.swap_bytes() } else { value }
+    }
+}
+
+/// DataLayer replaying the frames of a capture file into the stack
+/// frames sent by the stack are dropped, optionally recorded into a writer
+pub struct PcapReplay<R: Read> {
+    reader: PcapReader<R>,
+    /// bytes of link header the stack expects in front of the ip header
+    /// (`TUN_SIZE` for a raw `Iface` with packet info), filled with zeros
+    offset: usize,
+    sent: Option<PcapWriter<BufWriter<File>>>,
+}
+
+impl PcapReplay<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P, offset: usize) -> Result<Self> {
+        Ok(Self::new(PcapReader::open(path)?, offset))
+    }
+}
+
+impl<R: Read> PcapReplay<R> {
+    pub fn new(reader: PcapReader<R>, offset: usize) -> Self {
+        Self {
+            reader,
+            offset,
+            sent: None,
+        }
+    }
+
+    /// record every frame the stack sends into `path`
+    pub fn record_sent<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.sent = Some(PcapWriter::create(path, self.reader.link_type())?);
+        Ok(())
+    }
+}
+
+impl<R: Read> DataLayer for PcapReplay<R> {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        if let Some(sent) = self.sent.as_mut() {
+            sent.write_frame(&data[self.offset.min(data.len())..])?;
+        }
+        Ok(data.len())
+    }
+
+    /// return `ErrorKind::UnexpectedEof` once the capture is exhausted
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        let frame = self.reader.next_frame()?
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "end of capture"))?;
+        let n = (self.offset + frame.len()).min(data.len());
+        let offset = self.offset.min(n);
+        data[..offset].iter_mut().for_each(|b| *b = 0);
+        data[offset..n].copy_from_slice(&frame[..n - offset]);
+        Ok(n)
+    }
+
+    fn header_len(&self) -> usize {
+        self.offset
+    }
+
+    /// reading a file never blocks
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// DataLayer decorator recording every frame sent and received into a pcap file
+pub struct PcapRecorder<L: DataLayer, W: Write> {
+    inner: L,
+    pcap: PcapWriter<W>,
+    /// bytes of link header stripped before a frame is written,
+    /// e.g. `TUN_SIZE` so a raw `Iface` can be captured as `LINKTYPE_RAW`
+    offset: usize,
+}
+
+impl<L: DataLayer, W: Write> PcapRecorder<L, W> {
+    pub fn new(inner: L, pcap: PcapWriter<W>, offset: usize) -> Self {
+        Self {
+            inner,
+            pcap,
+            offset,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut L {
+        &mut self.inner
+    }
+
+    /// flush the capture and return the wrapped layer
+    pub fn into_inner(mut self) -> Result<L> {
+        self.pcap.flush()?;
+        Ok(self.inner)
+    }
+
+    fn record(&mut self, frame: &[u8]) -> Result<()> {
+        self.pcap.write_frame(&frame[self.offset.min(frame.len())..])
+    }
+}
+
+impl<L: DataLayer, W: Write> DataLayer for PcapRecorder<L, W> {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let n = self.inner.send(data)?;
+        self.record(&data[..n])?;
+        Ok(n)
+    }
+
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        let n = self.inner.send_vectored(bufs)?;
+        self.record(&gather(bufs))?;
+        Ok(n)
+    }
+
+    fn send_segmented(&mut self, bufs: &[IoSlice], mss: usize) -> Result<usize> {
+        let n = self.inner.send_segmented(bufs, mss)?;
+        self.record(&gather(bufs))?;
+        Ok(n)
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        let n = self.inner.recv(data)?;
+        self.record(&data[..n])?;
+        Ok(n)
+    }
+
+    fn header_len(&self) -> usize {
+        self.inner.header_len()
+    }
+
+    fn mtu(&self) -> usize {
+        self.inner.mtu()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
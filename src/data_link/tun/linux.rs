@@ -0,0 +1,199 @@
+use std::io::{Error, IoSlice, IoSliceMut, Result};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use libc::c_int;
+use tun_tap::{Iface, Mode};
+
+use crate::data_link::{iface, interface_mtu, readv, set_fd_nonblocking, writev, BufferHandle, Capabilities, DataLayer};
+use crate::meta::{ETHERNET_MTU, TUN_SIZE};
+use crate::net_types::EtherType;
+use crate::reader_writer::tuntap_header;
+
+/// TUN device backed by /dev/net/tun, frames are bare ip packets,
+/// or ethernet frames for a device opened with `tap`
+pub struct Tun {
+    iface: Iface,
+    mtu: usize,
+    offload: Capabilities,
+    /// the kernel side prefixes every frame with the 4 bytes packet information
+    /// header (flags and ethertype), added and stripped here
+    packet_info: bool,
+}
+
+impl Tun {
+    /// open the interface with IFF_NO_PI
+    pub fn open(name: &str) -> Result<Self> {
+        Self::open_with(name, false)
+    }
+
+    /// open the interface with packet information, for tools on the kernel side
+    /// expecting it, the stack still sees bare ip packets
+    pub fn with_packet_info(name: &str) -> Result<Self> {
+        Self::open_with(name, true)
+    }
+
+    /// open a TAP interface without packet information, the frames are ethernet frames,
+    /// see `Ethernet`. `mtu` is the one of the ip packets in them
+    pub fn tap(name: &str) -> Result<Self> {
+        Self::with_iface(Iface::without_packet_info(name, Mode::Tap)?, false)
+    }
+
+    fn open_with(name: &str, packet_info: bool) -> Result<Self> {
+        let iface = if packet_info {
+            Iface::new(name, Mode::Tun)?
+        } else {
+            Iface::without_packet_info(name, Mode::Tun)?
+        };
+        Self::with_iface(iface, packet_info)
+    }
+
+    fn with_iface(iface: Iface, packet_info: bool) -> Result<Self> {
+        // a fresh tun device has the ethernet mtu unless it was configured before
+        let mtu = interface_mtu(iface.name()).unwrap_or(ETHERNET_MTU);
+        Ok(Self { iface, mtu, offload: Capabilities::empty(), packet_info })
+    }
+
+    pub fn packet_info(&self) -> bool {
+        self.packet_info
+    }
+
+    /// the name chosen by the kernel if `open` was given a pattern like "tun%d"
+    pub fn name(&self) -> &str {
+        self.iface.name()
+    }
+
+    /// change the mtu of the interface, `mtu` reports the new one
+    pub fn set_mtu(&mut self, mtu: usize) -> Result<()> {
+        iface::set_mtu(self.iface.name(), mtu)?;
+        self.mtu = mtu;
+        Ok(())
+    }
+
+    /// size of the kernel side queue of frames written to the device (TUNSETSNDBUF),
+    /// a larger queue lets `send_batch` push bursts without ENOBUFS
+    pub fn set_send_buffer(&mut self, bytes: usize) -> Result<()> {
+        let size = bytes.min(c_int::MAX as usize) as c_int;
+        if unsafe { libc::ioctl(self.as_raw_fd(), libc::TUNSETSNDBUF, &size as *const c_int) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// let the kernel hand over segments without a complete checksum (TUNSETOFFLOAD with
+    /// TUN_F_CSUM), they come from the local host and `capabilities` reports `RX_CHECKSUM`.
+    /// sent segments need a full checksum until the device carries a virtio net header
+    pub fn set_checksum_offload(&mut self, enabled: bool) -> Result<()> {
+        let flags = if enabled { libc::TUN_F_CSUM } else { 0 };
+        if unsafe { libc::ioctl(self.as_raw_fd(), libc::TUNSETOFFLOAD, flags as libc::c_ulong) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        self.offload = if enabled { Capabilities::RX_CHECKSUM } else { Capabilities::empty() };
+        Ok(())
+    }
+
+    /// wait at most `timeout_ms` for the device to become readable
+    fn readable(&self, timeout_ms: c_int) -> Result<bool> {
+        let mut fd = libc::pollfd {
+            fd: self.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ret = unsafe { libc::poll(&mut fd, 1, timeout_ms) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(ret > 0 && fd.revents & libc::POLLIN != 0)
+    }
+}
+
+impl DataLayer for Tun {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.send_vectored(&[IoSlice::new(data)])
+    }
+
+    /// the packet information lands in its own buffer
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        if !self.packet_info {
+            return self.iface.recv(data);
+        }
+        let mut info = [0u8; TUN_SIZE];
+        let n = readv(self.as_raw_fd(), &mut [IoSliceMut::new(&mut info), IoSliceMut::new(data)])?;
+        Ok(n.saturating_sub(TUN_SIZE))
+    }
+
+    /// one writev is one frame for a tun fd
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        if !self.packet_info {
+            return writev(self.as_raw_fd(), bufs);
+        }
+        let info = packet_info(bufs.iter().find(|buf| !buf.is_empty()).map_or(&[][..], |buf| &buf[..]));
+        let mut frame = Vec::with_capacity(bufs.len() + 1);
+        frame.push(IoSlice::new(&info));
+        frame.extend_from_slice(bufs);
+        Ok(writev(self.as_raw_fd(), &frame)?.saturating_sub(TUN_SIZE))
+    }
+
+    /// the mtu of the interface when it was opened
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.offload
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        set_fd_nonblocking(self.as_raw_fd(), nonblocking)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+
+    /// a tun fd hands out one frame per read, so the batch is drained with
+    /// as many reads as there are frames already queued in the kernel
+    fn recv_batch(&mut self, bufs: &mut [BufferHandle]) -> Result<usize> {
+        let mut filled = 0;
+        for buf in bufs.iter_mut() {
+            if filled > 0 && !self.readable(0)? {
+                break;
+            }
+            let n = self.recv(buf.space())?;
+            buf.set_len(n);
+            filled += 1;
+        }
+        Ok(filled)
+    }
+}
+
+impl AsRawFd for Tun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.iface.as_raw_fd()
+    }
+}
+
+/// the packet information header in front of `packet`: no flags and ETH_P_IP or ETH_P_IPV6,
+/// the kernel drops a packet of any other proto
+fn packet_info(packet: &[u8]) -> [u8; TUN_SIZE] {
+    tuntap_header(0, EtherType::of_ip_packet(packet).unwrap_or(EtherType::Unknown(0)))
+}
+
+/// `Iface::new` always asks for packet information, the frames carry it.
+/// the proto field of sent frames is filled in from the ip packet behind it
+impl DataLayer for Iface {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        if data.len() <= TUN_SIZE {
+            return Iface::send(self, data);
+        }
+        let info = packet_info(&data[TUN_SIZE..]);
+        writev(self.as_raw_fd(), &[IoSlice::new(&info), IoSlice::new(&data[TUN_SIZE..])])
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        Iface::recv(self, data)
+    }
+
+    fn header_len(&self) -> usize {
+        TUN_SIZE
+    }
+}
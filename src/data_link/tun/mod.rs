@@ -0,0 +1,60 @@
+//! Platform specific TUN devices
+//!
+//! every backend exposes the same `Tun` type, the Linux backend is always available,
+//! the macOS and Windows ones are enabled with the `utun` and `wintun` features.
+//! `NetStack` and the sockets need unix, on Windows `Tun` is a `DataLayer` the caller drives.
+//! on Linux `VnetTun` adds checksum and segmentation offload through the virtio net header
+//! and `TunQueue` opens the queues of a multi-queue interface
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+pub mod vnet;
+#[cfg(target_os = "linux")]
+mod multi_queue;
+#[cfg(all(target_os = "macos", feature = "utun"))]
+mod utun;
+#[cfg(all(windows, feature = "wintun"))]
+mod wintun;
+
+#[cfg(target_os = "linux")]
+pub use self::linux::Tun;
+#[cfg(target_os = "linux")]
+pub use self::vnet::VnetTun;
+#[cfg(target_os = "linux")]
+pub use self::multi_queue::TunQueue;
+#[cfg(all(target_os = "macos", feature = "utun"))]
+pub use self::utun::Tun;
+#[cfg(all(windows, feature = "wintun"))]
+pub use self::wintun::Tun;
+
+/// open /dev/net/tun and attach it to the interface `name` with the IFF_* `flags`,
+/// return the file and the interface name chosen by the kernel
+#[cfg(target_os = "linux")]
+fn open_tun(name: &str, flags: libc::c_int) -> std::io::Result<(std::fs::File, String)> {
+    use std::io::{Error, ErrorKind};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_CLOEXEC)
+        .open("/dev/net/tun")?;
+    let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+    if name.len() >= req.ifr_name.len() {
+        return Err(Error::new(ErrorKind::InvalidInput, "interface name too long"));
+    }
+    for (dst, src) in req.ifr_name.iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    req.ifr_ifru.ifru_flags = flags as libc::c_short;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::TUNSETIFF, &mut req as *mut libc::ifreq) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    let name = req.ifr_name.iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8 as char)
+        .collect();
+    Ok((file, name))
+}
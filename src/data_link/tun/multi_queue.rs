@@ -0,0 +1,80 @@
+use std::fs::File;
+use std::io::{IoSlice, Read, Result, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::data_link::{iface, interface_mtu, set_fd_nonblocking, writev, DataLayer};
+use crate::meta::ETHERNET_MTU;
+
+use super::open_tun;
+
+/// One queue of a TUN interface opened with IFF_MULTI_QUEUE, without packet information.
+/// the kernel spreads the received flows over the queues and keeps steering a flow
+/// to the queue its packets were last written to
+pub struct TunQueue {
+    file: File,
+    name: String,
+    mtu: usize,
+}
+
+impl TunQueue {
+    /// open or create the interface `name` with `queues` queues,
+    /// every queue is a separate device for its own worker thread
+    pub fn open(name: &str, queues: usize) -> Result<Vec<TunQueue>> {
+        let flags = libc::IFF_TUN | libc::IFF_NO_PI | libc::IFF_MULTI_QUEUE;
+        let mut opened = Vec::with_capacity(queues);
+        let mut name = name.to_string();
+        for _ in 0..queues {
+            let (file, chosen) = open_tun(&name, flags)?;
+            // a pattern like "tun%d" must resolve to the same interface for every queue
+            name = chosen;
+            opened.push(file);
+        }
+        let mtu = interface_mtu(&name).unwrap_or(ETHERNET_MTU);
+        Ok(opened.into_iter()
+            .map(|file| TunQueue { file, name: name.clone(), mtu })
+            .collect())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// change the mtu of the interface, `mtu` reports the new one
+    pub fn set_mtu(&mut self, mtu: usize) -> Result<()> {
+        iface::set_mtu(&self.name, mtu)?;
+        self.mtu = mtu;
+        Ok(())
+    }
+}
+
+impl DataLayer for TunQueue {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.file.write(data)
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        self.file.read(data)
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        set_fd_nonblocking(self.as_raw_fd(), nonblocking)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        writev(self.as_raw_fd(), bufs)
+    }
+}
+
+impl AsRawFd for TunQueue {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
@@ -0,0 +1,138 @@
+use std::ffi::CStr;
+use std::io::{Error, ErrorKind, IoSlice, IoSliceMut, Result};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use libc::{c_char, c_void, ctl_info, sockaddr_ctl, socklen_t};
+
+use crate::data_link::{readv, set_fd_nonblocking, writev, DataLayer};
+use crate::meta::TUN_SIZE;
+
+const UTUN_CONTROL_NAME: &[u8] = b"com.apple.net.utun_control\0";
+
+/// macOS utun device
+/// the kernel puts the 4 bytes address family in network byte order in front of every
+/// packet, it is added to the sent packets and taken off the received ones
+pub struct Tun {
+    fd: RawFd,
+    name: String,
+}
+
+impl Tun {
+    /// `name` must be "utunN", an empty name lets the kernel pick a free unit
+    pub fn open(name: &str) -> Result<Self> {
+        let unit = match name {
+            "" => 0,
+            name => name.strip_prefix("utun")
+                .and_then(|n| n.parse::<u32>().ok())
+                .map(|n| n + 1)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "utun device name must be utunN"))?,
+        };
+        let fd = unsafe { libc::socket(libc::PF_SYSTEM, libc::SOCK_DGRAM, libc::SYSPROTO_CONTROL) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let mut tun = Self { fd, name: String::new() };
+
+        let mut info: ctl_info = unsafe { mem::zeroed() };
+        for (dst, src) in info.ctl_name.iter_mut().zip(UTUN_CONTROL_NAME) {
+            *dst = *src as c_char;
+        }
+        if unsafe { libc::ioctl(fd, libc::CTLIOCGINFO, &mut info as *mut ctl_info) } < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let addr = sockaddr_ctl {
+            sc_len: mem::size_of::<sockaddr_ctl>() as u8,
+            sc_family: libc::AF_SYSTEM as u8,
+            ss_sysaddr: libc::AF_SYS_CONTROL as u16,
+            sc_id: info.ctl_id,
+            sc_unit: unit,
+            sc_reserved: [0; 5],
+        };
+        let ret = unsafe {
+            libc::connect(
+                fd,
+                &addr as *const sockaddr_ctl as *const libc::sockaddr,
+                mem::size_of::<sockaddr_ctl>() as socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut ifname = [0 as c_char; libc::IFNAMSIZ];
+        let mut len = ifname.len() as socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SYSPROTO_CONTROL,
+                libc::UTUN_OPT_IFNAME,
+                ifname.as_mut_ptr() as *mut c_void,
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        tun.name = unsafe { CStr::from_ptr(ifname.as_ptr()) }.to_string_lossy().into_owned();
+        Ok(tun)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl DataLayer for Tun {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.send_vectored(&[IoSlice::new(data)])
+    }
+
+    /// the address family lands in its own buffer
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        let mut family = [0u8; TUN_SIZE];
+        let n = readv(self.fd, &mut [IoSliceMut::new(&mut family), IoSliceMut::new(data)])?;
+        Ok(n.saturating_sub(TUN_SIZE))
+    }
+
+    /// one writev is one packet for a utun socket
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        let family = family(bufs.iter().find(|buf| !buf.is_empty()).map_or(&[][..], |buf| &buf[..]));
+        let mut frame = Vec::with_capacity(bufs.len() + 1);
+        frame.push(IoSlice::new(&family));
+        frame.extend_from_slice(bufs);
+        Ok(writev(self.fd, &frame)?.saturating_sub(TUN_SIZE))
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        set_fd_nonblocking(self.fd, nonblocking)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.fd)
+    }
+}
+
+/// the address family word in front of `packet`, AF_INET or AF_INET6 by its ip version
+fn family(packet: &[u8]) -> [u8; TUN_SIZE] {
+    let family = match packet.first().map(|byte| byte >> 4) {
+        Some(6) => libc::AF_INET6,
+        _ => libc::AF_INET,
+    };
+    (family as u32).to_be_bytes()
+}
+
+impl AsRawFd for Tun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Tun {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
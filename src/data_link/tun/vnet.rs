@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io::{Error, IoSlice, Read, Result};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use libc::{c_int, c_ulong};
+
+use crate::data_link::{iface, interface_mtu, set_fd_nonblocking, writev, Capabilities, DataLayer};
+use crate::meta::ETHERNET_MTU;
+
+use super::open_tun;
+
+/// size of `VirtioNetHdr` on the wire, the one without `num_buffers`
+pub const VNET_HDR_LEN: usize = 10;
+
+/// the checksum starting at `csum_start` still has to be computed
+pub const VIRTIO_NET_HDR_F_NEEDS_CSUM: u8 = 1;
+/// the checksum of the packet was verified
+pub const VIRTIO_NET_HDR_F_DATA_VALID: u8 = 2;
+
+pub const VIRTIO_NET_HDR_GSO_NONE: u8 = 0;
+pub const VIRTIO_NET_HDR_GSO_TCPV4: u8 = 1;
+pub const VIRTIO_NET_HDR_GSO_TCPV6: u8 = 4;
+/// the segments carry the ECN CWR flag
+pub const VIRTIO_NET_HDR_GSO_ECN: u8 = 0x80;
+
+/// offset of the checksum field in the tcp header
+const TCP_CHECKSUM_OFFSET: u16 = 16;
+const TCP: u8 = 6;
+
+/// Header in front of every packet of a TUN device opened with IFF_VNET_HDR,
+/// describes pending checksums and segmentation (virtio spec 5.1.6).
+/// fields are in native byte order, as the kernel uses for a legacy header
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct VirtioNetHdr {
+    pub flags: u8,
+    pub gso_type: u8,
+    /// length of the ip and tcp headers of a GSO packet
+    pub hdr_len: u16,
+    /// payload of every segment cut from a GSO packet
+    pub gso_size: u16,
+    pub csum_start: u16,
+    pub csum_offset: u16,
+}
+
+impl VirtioNetHdr {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let data = data.get(..VNET_HDR_LEN)?;
+        let field = |at: usize| u16::from_ne_bytes([data[at], data[at + 1]]);
+        Some(Self {
+            flags: data[0],
+            gso_type: data[1],
+            hdr_len: field(2),
+            gso_size: field(4),
+            csum_start: field(6),
+            csum_offset: field(8),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; VNET_HDR_LEN] {
+        let mut buf = [0; VNET_HDR_LEN];
+        buf[0] = self.flags;
+        buf[1] = self.gso_type;
+        buf[2..4].copy_from_slice(&self.hdr_len.to_ne_bytes());
+        buf[4..6].copy_from_slice(&self.gso_size.to_ne_bytes());
+        buf[6..8].copy_from_slice(&self.csum_start.to_ne_bytes());
+        buf[8..10].copy_from_slice(&self.csum_offset.to_ne_bytes());
+        buf
+    }
+
+    /// a GSO packet which the kernel coalesced from several segments
+    pub fn is_gso(&self) -> bool {
+        self.gso_type & !VIRTIO_NET_HDR_GSO_ECN != VIRTIO_NET_HDR_GSO_NONE
+    }
+
+    /// header of an outgoing ipv4 packet whose tcp checksum only covers the pseudo header,
+    /// with `mss` the kernel also cuts the payload into segments of that size
+    fn for_tcp(ip: &[u8], mss: Option<usize>) -> Self {
+        let ihl = ip.first().map_or(0, |b| (b & 0xf) as usize * 4);
+        if ip.get(9) != Some(&TCP) {
+            return Self::default();
+        }
+        let mut hdr = Self {
+            flags: VIRTIO_NET_HDR_F_NEEDS_CSUM,
+            csum_start: ihl as u16,
+            csum_offset: TCP_CHECKSUM_OFFSET,
+            ..Self::default()
+        };
+        if let Some(mss) = mss {
+            let data_offset = ip.get(ihl + 12).map_or(0, |b| (b >> 4) as usize * 4);
+            hdr.gso_type = VIRTIO_NET_HDR_GSO_TCPV4;
+            hdr.gso_size = mss.min(u16::MAX as usize) as u16;
+            hdr.hdr_len = (ihl + data_offset) as u16;
+        }
+        hdr
+    }
+}
+
+/// TUN device with a virtio net header in front of every packet, so the kernel does
+/// the checksums, cuts large segments (TSO) and hands over coalesced ones (GRO).
+/// the stack sees the header as the link header of `header_len` bytes
+pub struct VnetTun {
+    file: File,
+    name: String,
+    mtu: usize,
+}
+
+impl VnetTun {
+    /// open or create the interface `name` with IFF_VNET_HDR and checksum and TSO offload
+    pub fn open(name: &str) -> Result<Self> {
+        let (file, name) = open_tun(name, libc::IFF_TUN | libc::IFF_NO_PI | libc::IFF_VNET_HDR)?;
+        let fd = file.as_raw_fd();
+        let hdr_len = VNET_HDR_LEN as c_int;
+        if unsafe { libc::ioctl(fd, libc::TUNSETVNETHDRSZ, &hdr_len as *const c_int) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        let offload = libc::TUN_F_CSUM | libc::TUN_F_TSO4;
+        if unsafe { libc::ioctl(fd, libc::TUNSETOFFLOAD, offload as c_ulong) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        let mtu = interface_mtu(&name).unwrap_or(ETHERNET_MTU);
+        Ok(Self { file, name, mtu })
+    }
+
+    /// the name chosen by the kernel if `open` was given a pattern like "tun%d"
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// change the mtu of the interface, `mtu` reports the new one
+    pub fn set_mtu(&mut self, mtu: usize) -> Result<()> {
+        iface::set_mtu(&self.name, mtu)?;
+        self.mtu = mtu;
+        Ok(())
+    }
+
+    /// write the frame with a header filled in from its ip and tcp headers,
+    /// the zeroed link header written by the stack is replaced
+    fn write_frame(&mut self, bufs: &[IoSlice], mss: Option<usize>) -> Result<usize> {
+        let mut skip = VNET_HDR_LEN;
+        let mut rest = Vec::with_capacity(bufs.len() + 1);
+        for buf in bufs {
+            if skip >= buf.len() {
+                skip -= buf.len();
+                continue;
+            }
+            rest.push(&buf[skip..]);
+            skip = 0;
+        }
+        let hdr = VirtioNetHdr::for_tcp(rest.first().copied().unwrap_or(&[]), mss).to_bytes();
+        let iov: Vec<IoSlice> = std::iter::once(&hdr[..]).chain(rest).map(IoSlice::new).collect();
+        writev(self.as_raw_fd(), &iov)
+    }
+}
+
+impl DataLayer for VnetTun {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.write_frame(&[IoSlice::new(data)], None)
+    }
+
+    /// coalesced packets are delivered whole, their checksum may be pending
+    /// (`VIRTIO_NET_HDR_F_NEEDS_CSUM`), which the stack accepts as `RX_CHECKSUM`
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        self.file.read(data)
+    }
+
+    fn header_len(&self) -> usize {
+        VNET_HDR_LEN
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::TX_CHECKSUM | Capabilities::RX_CHECKSUM | Capabilities::TSO | Capabilities::GRO
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        set_fd_nonblocking(self.as_raw_fd(), nonblocking)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        self.write_frame(bufs, None)
+    }
+
+    fn send_segmented(&mut self, bufs: &[IoSlice], mss: usize) -> Result<usize> {
+        self.write_frame(bufs, Some(mss))
+    }
+}
+
+impl AsRawFd for VnetTun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
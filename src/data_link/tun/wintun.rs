@@ -0,0 +1,191 @@
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+use std::time::Duration;
+
+use crate::data_link::DataLayer;
+
+type Handle = *mut c_void;
+
+/// ring buffer size of a session, must be a power of two between 128KiB and 64MiB
+const RING_CAPACITY: u32 = 0x40_0000;
+const ERROR_NO_MORE_ITEMS: u32 = 259;
+const INFINITE: u32 = 0xFFFF_FFFF;
+const WAIT_TIMEOUT: u32 = 0x102;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn LoadLibraryW(name: *const u16) -> Handle;
+    fn GetProcAddress(module: Handle, name: *const c_char) -> *mut c_void;
+    fn FreeLibrary(module: Handle) -> i32;
+    fn WaitForSingleObject(handle: Handle, millis: u32) -> u32;
+    fn GetLastError() -> u32;
+}
+
+/// functions exported by wintun.dll, see wintun.h
+struct Api {
+    module: Handle,
+    create_adapter: unsafe extern "system" fn(*const u16, *const u16, *const c_void) -> Handle,
+    close_adapter: unsafe extern "system" fn(Handle),
+    start_session: unsafe extern "system" fn(Handle, u32) -> Handle,
+    end_session: unsafe extern "system" fn(Handle),
+    get_read_wait_event: unsafe extern "system" fn(Handle) -> Handle,
+    receive_packet: unsafe extern "system" fn(Handle, *mut u32) -> *mut u8,
+    release_receive_packet: unsafe extern "system" fn(Handle, *const u8),
+    allocate_send_packet: unsafe extern "system" fn(Handle, u32) -> *mut u8,
+    send_packet: unsafe extern "system" fn(Handle, *const u8),
+}
+
+macro_rules! load_fn {
+    ($module:expr, $name:expr) => {{
+        let f = GetProcAddress($module, concat!($name, "\0").as_ptr() as *const c_char);
+        if f.is_null() {
+            FreeLibrary($module);
+            return Err(Error::new(ErrorKind::NotFound, concat!("wintun.dll does not export ", $name)));
+        }
+        mem::transmute(f)
+    }};
+}
+
+impl Api {
+    /// wintun.dll must be next to the executable or in the dll search path
+    fn load() -> Result<Self> {
+        unsafe {
+            let module = LoadLibraryW(wide("wintun.dll").as_ptr());
+            if module.is_null() {
+                return Err(Error::last_os_error());
+            }
+            Ok(Self {
+                module,
+                create_adapter: load_fn!(module, "WintunCreateAdapter"),
+                close_adapter: load_fn!(module, "WintunCloseAdapter"),
+                start_session: load_fn!(module, "WintunStartSession"),
+                end_session: load_fn!(module, "WintunEndSession"),
+                get_read_wait_event: load_fn!(module, "WintunGetReadWaitEvent"),
+                receive_packet: load_fn!(module, "WintunReceivePacket"),
+                release_receive_packet: load_fn!(module, "WintunReleaseReceivePacket"),
+                allocate_send_packet: load_fn!(module, "WintunAllocateSendPacket"),
+                send_packet: load_fn!(module, "WintunSendPacket"),
+            })
+        }
+    }
+}
+
+/// Windows TUN adapter provided by the wintun driver
+/// frames are raw ip packets without any link header
+pub struct Tun {
+    api: Api,
+    adapter: Handle,
+    session: Handle,
+    read_event: Handle,
+    name: String,
+    nonblocking: bool,
+    read_timeout: Option<Duration>,
+}
+
+// wintun sessions can be used from any thread
+unsafe impl Send for Tun {}
+
+impl Tun {
+    /// create an adapter named `name`, requires administrator privileges
+    pub fn open(name: &str) -> Result<Self> {
+        let api = Api::load()?;
+        unsafe {
+            let adapter = (api.create_adapter)(wide(name).as_ptr(), wide("tcp-stack").as_ptr(), ptr::null());
+            if adapter.is_null() {
+                let err = Error::last_os_error();
+                FreeLibrary(api.module);
+                return Err(err);
+            }
+            let session = (api.start_session)(adapter, RING_CAPACITY);
+            if session.is_null() {
+                let err = Error::last_os_error();
+                (api.close_adapter)(adapter);
+                FreeLibrary(api.module);
+                return Err(err);
+            }
+            let read_event = (api.get_read_wait_event)(session);
+            Ok(Self {
+                api,
+                adapter,
+                session,
+                read_event,
+                name: name.to_owned(),
+                nonblocking: false,
+                read_timeout: None,
+            })
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// `None` blocks `recv` until a packet arrives, otherwise it fails with `ErrorKind::TimedOut`
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+}
+
+impl DataLayer for Tun {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        unsafe {
+            let packet = (self.api.allocate_send_packet)(self.session, data.len() as u32);
+            if packet.is_null() {
+                return Err(Error::last_os_error());
+            }
+            ptr::copy_nonoverlapping(data.as_ptr(), packet, data.len());
+            (self.api.send_packet)(self.session, packet);
+        }
+        Ok(data.len())
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        loop {
+            unsafe {
+                let mut size = 0_u32;
+                let packet = (self.api.receive_packet)(self.session, &mut size);
+                if !packet.is_null() {
+                    let n = (size as usize).min(data.len());
+                    ptr::copy_nonoverlapping(packet, data.as_mut_ptr(), n);
+                    (self.api.release_receive_packet)(self.session, packet);
+                    return Ok(n);
+                }
+                if GetLastError() != ERROR_NO_MORE_ITEMS {
+                    return Err(Error::last_os_error());
+                }
+                if self.nonblocking {
+                    return Err(ErrorKind::WouldBlock.into());
+                }
+                let millis = self.read_timeout.map_or(INFINITE, |timeout| timeout.as_millis().min(INFINITE as u128 - 1) as u32);
+                if WaitForSingleObject(self.read_event, millis) == WAIT_TIMEOUT {
+                    return Err(ErrorKind::TimedOut.into());
+                }
+            }
+        }
+    }
+
+    /// `recv` returns `ErrorKind::WouldBlock` when the ring is empty, the adapter has no
+    /// file descriptor to wait for
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+}
+
+impl Drop for Tun {
+    fn drop(&mut self) {
+        unsafe {
+            (self.api.end_session)(self.session);
+            (self.api.close_adapter)(self.adapter);
+            FreeLibrary(self.api.module);
+        }
+    }
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::capture::{CaptureId, CapturedFrame, Captures, Direction, Filter, SegmentInfo, Sink};
+use crate::clock::Clock;
+use crate::netstat::TimerKind;
+use crate::observer::{ConnectionObserver, ObserverId, Observers};
+use crate::socket_addr::Quad;
+use crate::stack::NetStack;
+use crate::tcp::vars::{TcpControl, TcpState};
+
+/// Something that happened to a connection, seen from the local end
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Event {
+    /// a segment was sent (`Out`) or received (`In`)
+    Segment { direction: Direction, segment: SegmentInfo },
+    State { from: TcpState, to: TcpState },
+    /// a timer of the connection expired
+    Timer(TimerKind),
+}
+
+/// An event and when it happened, relative to the start of the recording
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Entry {
+    pub at: Duration,
+    pub event: Event,
+}
+
+#[derive(Default)]
+struct Log {
+    /// connections with at least one segment, by (local, remote) quad
+    connections: BTreeMap<Quad, Vec<Entry>>,
+    /// events of connections without a segment yet, e.g. CLOSED -> SYN-SENT
+    /// before the SYN went out, dropped if the connection closes first
+    pending: HashMap<Quad, Vec<Entry>>,
+}
+
+struct Recorder {
+    log: Mutex<Log>,
+    clock: Arc<dyn Clock>,
+    start: Instant,
+}
+
+impl Recorder {
+    fn log(&self) -> MutexGuard<'_, Log> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn entry(&self, event: Event) -> Entry {
+        Entry { at: self.clock.now().saturating_duration_since(self.start), event }
+    }
+
+    fn on_segment(&self, direction: Direction, segment: SegmentInfo) {
+        let quad = match direction {
+            Direction::Out => segment.quad,
+            Direction::In => segment.quad.reverse(),
+        };
+        let entry = self.entry(Event::Segment { direction, segment });
+        let mut log = self.log();
+        let pending = log.pending.remove(&quad);
+        let entries = log.connections.entry(quad).or_default();
+        entries.extend(pending.into_iter().flatten());
+        entries.push(entry);
+    }
+
+    fn on_event(&self, quad: &Quad, event: Event) {
+        let entry = self.entry(event);
+        let mut log = self.log();
+        if let Some(entries) = log.connections.get_mut(quad) {
+            entries.push(entry);
+        } else if let Event::State { to: TcpState::Closed, .. } = event {
+            log.pending.remove(quad);
+        } else {
+            log.pending.entry(*quad).or_default().push(entry);
+        }
+    }
+}
+
+impl ConnectionObserver for Recorder {
+    fn on_state_change(&self, quad: &Quad, from: TcpState, to: TcpState) {
+        self.on_event(quad, Event::State { from, to });
+    }
+
+    fn on_timer(&self, quad: &Quad, kind: TimerKind) {
+        self.on_event(quad, Event::Timer(kind));
+    }
+}
+
+/// Records the segments, state changes and expired timers of the connections of
+/// a stack to draw them as sequence diagrams, until it is dropped
+///
+/// every event is kept in memory, narrow the recorded connections with the filter
+pub struct Recording {
+    recorder: Arc<Recorder>,
+    captures: Arc<Captures>,
+    observers: Arc<Observers>,
+    capture: CaptureId,
+    observer: ObserverId,
+}
+
+impl Recording {
+    /// record the connections with segments matching `filter`, start before
+    /// connecting to also see the handshake
+    pub fn start(stack: &NetStack, filter: Filter) -> Self {
+        let (captures, observers, clock) = stack.hooks();
+        let start = clock.now();
+        let recorder = Arc::new(Recorder { log: Mutex::default(), clock, start });
+        let sink = recorder.clone();
+        let capture = captures.add(filter.and(Filter::Tcp), Sink::Callback(Box::new(move |frame: &CapturedFrame| {
+            if let Some(segment) = frame.segment {
+                sink.on_segment(frame.direction, segment);
+            }
+        })));
+        let observer = observers.add(recorder.clone());
+        Self { recorder, captures, observers, capture, observer }
+    }
+
+    /// the recorded connections as (local, remote) quads
+    pub fn connections(&self) -> Vec<Quad> {
+        self.recorder.log().connections.keys().copied().collect()
+    }
+
+    /// events of the connection so far, empty if nothing was recorded for it
+    pub fn entries(&self, quad: &Quad) -> Vec<Entry> {
+        self.recorder.log().connections.get(quad).cloned().unwrap_or_default()
+    }
+
+    /// the diagram of the connection so far
+    pub fn ladder(&self, quad: &Quad) -> Ladder {
+        Ladder::new(*quad, self.entries(quad))
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        self.captures.remove(self.capture);
+        self.observers.remove(self.observer);
+    }
+}
+
+/// Sequence diagram of one connection, the local end on the left
+///
+/// sequence numbers are relative to the first one seen from each end, like wireshark,
+/// so the handshake reads `seq=0` and the first byte of data `seq=1`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Ladder {
+    quad: Quad,
+    entries: Vec<Entry>,
+}
+
+/// A line of a diagram, the events with their text
+enum Line {
+    Arrow { direction: Direction, label: String },
+    Note(String),
+}
+
+impl Ladder {
+    pub fn new(quad: Quad, entries: Vec<Entry>) -> Self {
+        Self { quad, entries }
+    }
+
+    pub fn quad(&self) -> Quad {
+        self.quad
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    fn lines(&self) -> Vec<(Duration, Line)> {
+        // first sequence numbers sent by the local and the remote end
+        let mut local = None;
+        let mut remote = None;
+        self.entries.iter().map(|entry| {
+            let line = match entry.event {
+                Event::Segment { direction, segment } => {
+                    let (sender, receiver) = match direction {
+                        Direction::Out => (&mut local, remote),
+                        Direction::In => (&mut remote, local),
+                    };
+                    let isn = *sender.get_or_insert(segment.seq);
+                    Line::Arrow { direction, label: label(&segment, isn, receiver) }
+                }
+                Event::State { from, to } => Line::Note(format!("{} -> {}", from, to)),
+                Event::Timer(TimerKind::Retransmit) => Line::Note("retransmission timer expired".to_string()),
+                Event::Timer(TimerKind::TimeWait) => Line::Note("TIME-WAIT timer expired".to_string()),
+                Event::Timer(TimerKind::KeepAlive) => Line::Note("keep-alive probe".to_string()),
+                Event::Timer(TimerKind::WindowProbe) => Line::Note("zero window probe".to_string()),
+            };
+            (entry.at, line)
+        }).collect()
+    }
+
+    /// text ladder diagram with the time of each event, also the `Display` output
+    pub fn ascii(&self) -> String {
+        let lines = self.lines();
+        let (local, remote) = (self.quad.src().to_string(), self.quad.dest().to_string());
+        let width = lines.iter()
+            .map(|(_, line)| match line {
+                Line::Arrow { label, .. } => label.len() + 8,
+                Line::Note(note) => note.len() + 2,
+            })
+            .chain(Some(local.len() + remote.len() + 2))
+            .fold(40, usize::max);
+        let mut out = format!("{:9}{:<w$}{}\n", "", local, remote, w = width + 2 - remote.len());
+        for (at, line) in lines {
+            let middle = match line {
+                Line::Arrow { direction: Direction::Out, label } => format!("-- {} {:->w$}", label, ">", w = width - label.len() - 4),
+                Line::Arrow { direction: Direction::In, label } => format!("<-- {} {:-<w$}", label, "", w = width - label.len() - 5),
+                Line::Note(note) => format!(" {:<w$}", note, w = width - 1),
+            };
+            out.push_str(&format!("{:>7.3}s |{}|\n", at.as_secs_f64(), middle));
+        }
+        out
+    }
+
+    /// Mermaid `sequenceDiagram`
+    pub fn mermaid(&self) -> String {
+        let mut out = format!("sequenceDiagram\n    participant L as {}\n    participant R as {}\n", self.quad.src(), self.quad.dest());
+        for (_, line) in self.lines() {
+            match line {
+                Line::Arrow { direction: Direction::Out, label } => out.push_str(&format!("    L->>R: {}\n", label)),
+                Line::Arrow { direction: Direction::In, label } => out.push_str(&format!("    R->>L: {}\n", label)),
+                Line::Note(note) => out.push_str(&format!("    Note left of L: {}\n", note)),
+            }
+        }
+        out
+    }
+
+    /// PlantUML sequence diagram
+    pub fn plantuml(&self) -> String {
+        let mut out = format!("@startuml\nparticipant \"{}\" as L\nparticipant \"{}\" as R\n", self.quad.src(), self.quad.dest());
+        for (_, line) in self.lines() {
+            match line {
+                Line::Arrow { direction: Direction::Out, label } => out.push_str(&format!("L -> R : {}\n", label)),
+                Line::Arrow { direction: Direction::In, label } => out.push_str(&format!("R -> L : {}\n", label)),
+                Line::Note(note) => out.push_str(&format!("note left of L : {}\n", note)),
+            }
+        }
+        out.push_str("@enduml\n");
+        out
+    }
+}
+
+impl fmt::Display for Ladder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.ascii())
+    }
+}
+
+/// e.g. `SYN,ACK seq=0 ack=1 win=65535` or `ACK,PSH seq=1 ack=1 win=512 len=100`
+fn label(segment: &SegmentInfo, isn: u32, peer_isn: Option<u32>) -> String {
+    let flags = [
+        (TcpControl::SYN, "SYN"),
+        (TcpControl::FIN, "FIN"),
+        (TcpControl::RST, "RST"),
+        (TcpControl::PSH, "PSH"),
+        (TcpControl::ACK, "ACK"),
+        (TcpControl::URG, "URG"),
+    ];
+    let flags: Vec<_> = flags.iter().filter(|(flag, _)| segment.has(*flag)).map(|(_, name)| *name).collect();
+    let mut label = format!("{} seq={}", flags.join(","), segment.seq.wrapping_sub(isn)).trim_start().to_string();
+    if segment.has(TcpControl::ACK) {
+        label.push_str(&format!(" ack={}", segment.ack.wrapping_sub(peer_isn.unwrap_or(0))));
+    }
+    label.push_str(&format!(" win={}", segment.window));
+    if segment.len > 0 {
+        label.push_str(&format!(" len={}", segment.len));
+    }
+    label
+}
@@ -0,0 +1,225 @@
+//! A stub resolver (RFC 1035) asking a recursive server over the udp sockets of a stack,
+//! so names resolve inside the stack when it is the data plane of a VPN
+//!
+//! A and AAAA queries only, no search domains and no fallback to tcp for truncated answers
+
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::rng::Rng;
+use crate::socket::{TcpStream, UdpSocket};
+use crate::stack::NetStack;
+
+pub const PORT: u16 = 53;
+/// how long a query waits for its answer before it is sent again
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+
+/// a udp answer without EDNS fits this
+const MAX_MESSAGE: usize = 512;
+const HEADER_LEN: usize = 12;
+const CLASS_IN: u16 = 1;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+
+/// Type of a resource record the resolver asks for
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RecordType {
+    A = 1,
+    Aaaa = 28,
+}
+
+/// Resolves host names with the recursive server `server`, every query from a
+/// fresh ephemeral port with a random id
+pub struct Resolver<'a> {
+    stack: &'a NetStack,
+    server: SocketAddrV4,
+    timeout: Duration,
+    attempts: u32,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(stack: &'a NetStack, server: SocketAddrV4) -> Self {
+        Self { stack, server, timeout: DEFAULT_TIMEOUT, attempts: DEFAULT_ATTEMPTS }
+    }
+
+    /// how long each attempt waits for the answer
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// how often a query is sent before it fails with `ErrorKind::TimedOut`, at least once
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    pub fn server(&self) -> SocketAddrV4 {
+        self.server
+    }
+
+    /// the ipv6 and ipv4 addresses of `host`, in the order `TcpStream::connect_host` wants
+    /// them. an address literal is returned as it is, `ErrorKind::NotFound` if the name
+    /// doesn't exist or has no address
+    pub fn lookup(&self, host: &str) -> Result<Vec<IpAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let v6 = self.query(host, RecordType::Aaaa);
+        let v4 = self.query(host, RecordType::A);
+        let addrs = match (v6, v4) {
+            (Err(e), Err(_)) => return Err(e),
+            (v6, v4) => v6.unwrap_or_default().into_iter().chain(v4.unwrap_or_default()).collect::<Vec<_>>(),
+        };
+        if addrs.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "no address for the host"));
+        }
+        Ok(addrs)
+    }
+
+    /// the ipv4 addresses of `host`
+    pub fn lookup_ipv4(&self, host: &str) -> Result<Vec<Ipv4Addr>> {
+        if let Ok(ip) = host.parse::<Ipv4Addr>() {
+            return Ok(vec![ip]);
+        }
+        let addrs = self.query(host, RecordType::A)?;
+        Ok(addrs.into_iter().filter_map(|addr| match addr {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        }).collect())
+    }
+
+    /// resolve `host` and connect to `port` of whichever address answers first
+    pub fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+        TcpStream::connect_host(self.stack, &self.lookup(host)?, port)
+    }
+
+    /// the addresses of the records of type `kind` in the answer for `name`, empty if the
+    /// name has none. sent again after each timeout
+    pub fn query(&self, name: &str, kind: RecordType) -> Result<Vec<IpAddr>> {
+        let socket = UdpSocket::bind(self.stack, 0)?;
+        let id = query_id(socket.local_addr().port());
+        let query = encode_query(id, name, kind)?;
+        let mut buf = [0; MAX_MESSAGE];
+        for _ in 0..self.attempts {
+            socket.send_to(&query, self.server)?;
+            let deadline = Instant::now() + self.timeout;
+            loop {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left == Duration::from_secs(0) {
+                    break;
+                }
+                socket.set_read_timeout(Some(left))?;
+                let (n, from) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(e) if e.kind() == ErrorKind::TimedOut => break,
+                    Err(e) => return Err(e),
+                };
+                // a late or forged answer, keep waiting for ours
+                if from != self.server {
+                    continue;
+                }
+                match decode_answer(id, kind, &buf[..n]) {
+                    Some(answer) => return answer,
+                    None => continue,
+                }
+            }
+        }
+        Err(Error::new(ErrorKind::TimedOut, "no answer from the name server"))
+    }
+}
+
+/// a query id which is hard to guess from outside
+fn query_id(port: u16) -> u16 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_nanos() as u64).unwrap_or(0);
+    Rng::new(nanos ^ ((port as u64) << 48)).next_u64() as u16
+}
+
+/// a query with recursion desired for the records of type `kind` of `name`
+pub fn encode_query(id: u16, name: &str, kind: RecordType) -> Result<Vec<u8>> {
+    let mut message = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // one question, no answer, authority or additional records
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    let invalid = || Error::new(ErrorKind::InvalidInput, "not a valid host name");
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > 253 {
+        return Err(invalid());
+    }
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid());
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&(kind as u16).to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+/// the addresses in the answer to the query `id`, `None` if the message isn't that answer
+pub fn decode_answer(id: u16, kind: RecordType, message: &[u8]) -> Option<Result<Vec<IpAddr>>> {
+    if message.len() < HEADER_LEN || u16::from_be_bytes([message[0], message[1]]) != id {
+        return None;
+    }
+    let flags = u16::from_be_bytes([message[2], message[3]]);
+    if flags & FLAG_RESPONSE == 0 {
+        return None;
+    }
+    match flags & 0x000f {
+        0 => {}
+        3 => return Some(Err(Error::new(ErrorKind::NotFound, "no such host"))),
+        rcode => return Some(Err(Error::other(format!("name server error, rcode {}", rcode)))),
+    }
+    if flags & FLAG_TRUNCATED != 0 {
+        return Some(Err(Error::other("truncated answer")));
+    }
+    let questions = u16::from_be_bytes([message[4], message[5]]);
+    let answers = u16::from_be_bytes([message[6], message[7]]);
+    let mut at = HEADER_LEN;
+    for _ in 0..questions {
+        at = skip_name(message, at)? + 4;
+    }
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        at = skip_name(message, at)?;
+        let record = message.get(at..at + 10)?;
+        let (rtype, class) = (u16::from_be_bytes([record[0], record[1]]), u16::from_be_bytes([record[2], record[3]]));
+        let len = u16::from_be_bytes([record[8], record[9]]) as usize;
+        let data = message.get(at + 10..at + 10 + len)?;
+        at += 10 + len;
+        // the CNAMEs leading to the addresses are skipped
+        if class != CLASS_IN || rtype != kind as u16 {
+            continue;
+        }
+        match (kind, data.len()) {
+            (RecordType::A, 4) => addrs.push(IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3]))),
+            (RecordType::Aaaa, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                addrs.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => return None,
+        }
+    }
+    Some(Ok(addrs))
+}
+
+/// the offset after the name at `at`, a compression pointer ends it
+fn skip_name(message: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(at)? as usize;
+        match len {
+            0 => return Some(at + 1),
+            len if len & 0xc0 == 0xc0 => return Some(at + 2).filter(|end| *end <= message.len()),
+            len if len & 0xc0 == 0 => at += 1 + len,
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+#[cfg(not(target_os = "linux"))]
+use crate::data_link::set_fd_nonblocking;
+
+/// Non-blocking file descriptor which becomes readable once signaled,
+/// an eventfd on linux and a pipe elsewhere
+pub struct EventFd {
+    read: RawFd,
+    write: RawFd,
+}
+
+impl EventFd {
+    #[cfg(target_os = "linux")]
+    pub fn new() -> Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Self { read: fd, write: fd })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new() -> Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        let event = Self { read: fds[0], write: fds[1] };
+        for &fd in &fds {
+            set_fd_nonblocking(fd, true)?;
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        Ok(event)
+    }
+
+    /// make the fd readable, signaling twice before a `clear` is the same as once
+    pub fn signal(&self) -> Result<()> {
+        let one = 1_u64.to_ne_bytes();
+        let n = unsafe { libc::write(self.write, one.as_ptr() as *const libc::c_void, one.len()) };
+        if n < 0 {
+            let err = Error::last_os_error();
+            // the counter or the pipe is full, the fd is readable anyway
+            if err.kind() != ErrorKind::WouldBlock {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// consume every pending signal
+    pub fn clear(&self) -> Result<()> {
+        let mut buf = [0_u8; 64];
+        loop {
+            let n = unsafe { libc::read(self.read, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n < 0 {
+                let err = Error::last_os_error();
+                return match err.kind() {
+                    ErrorKind::WouldBlock => Ok(()),
+                    ErrorKind::Interrupted => continue,
+                    _ => Err(err),
+                };
+            }
+            // an eventfd is reset by a single read
+            if n == 0 || self.read == self.write {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// the end to register with epoll/kqueue
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.read
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            if self.write != self.read {
+                libc::close(self.write);
+            }
+        }
+    }
+}
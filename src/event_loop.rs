@@ -0,0 +1,286 @@
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token, Waker};
+use tracing::{debug, info, warn};
+
+use crate::clock::{system_clock, Clock};
+use crate::buffer::{BufferPool, PooledBuf};
+use crate::data_link::gro::Coalescer;
+use crate::data_link::{is_device_gone, recv_buffer_len, Capabilities, DataLayer};
+use crate::meta::ETHERNET_MTU;
+use crate::result;
+use crate::timer::{TimerId, TimerWheel, DEFAULT_TIMER_RESOLUTION, DEFAULT_WHEEL_SLOTS};
+
+const DEVICE: Token = Token(0);
+const WAKER: Token = Token(1);
+/// how often a device without file descriptor (e.g. Loopback) is checked for frames
+pub const DEVICE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const EVENTS_CAPACITY: usize = 64;
+
+/// opens the device again after it went away, see `EventLoop::set_reopen`
+pub type Reopen<L> = Box<dyn FnMut() -> std::io::Result<L> + Send>;
+
+/// What a handler can touch while it processes an event
+pub struct Context<'a, L, T> {
+    pub device: &'a mut L,
+    pub timers: &'a mut TimerWheel<T>,
+    pub now: Instant,
+}
+
+/// Callbacks of the event loop, `T` is the payload of the timers
+pub trait Handler<L: DataLayer, T> {
+    /// a frame, including the link header, was received from the device
+    fn on_frame(&mut self, cx: &mut Context<L, T>, frame: &[u8]) -> result::Result<()>;
+
+    fn on_timer(&mut self, cx: &mut Context<L, T>, id: TimerId, timer: T) -> result::Result<()>;
+
+    /// another thread called `Waker::wake` on `EventLoop::waker()`
+    fn on_wakeup(&mut self, _cx: &mut Context<L, T>) -> result::Result<()> {
+        Ok(())
+    }
+
+    /// checked after every iteration of `EventLoop::run`
+    fn should_stop(&self) -> bool {
+        false
+    }
+}
+
+/// Single threaded loop multiplexing the device, the timer wheel and wakeups
+/// from application threads, so timers fire even when no packet arrives
+pub struct EventLoop<L: DataLayer, T> {
+    poll: Poll,
+    events: Events,
+    waker: Arc<Waker>,
+    device: L,
+    timers: TimerWheel<T>,
+    /// receive buffer taken from `BufferPool::global`
+    buf: PooledBuf,
+    mtu: usize,
+    /// the device has no file descriptor and must be checked periodically
+    polled: bool,
+    /// how a device which went away is replaced and how often it's tried
+    reopen: Option<(Reopen<L>, Duration)>,
+    /// merges the segments of a connection drained in one go, see `EventLoop::set_gro`
+    gro: Option<Coalescer>,
+}
+
+impl<L: DataLayer, T> EventLoop<L, T> {
+    /// switch `device` to non-blocking mode and register it
+    pub fn new(device: L) -> result::Result<Self> {
+        Self::with_options(device, ETHERNET_MTU, DEFAULT_TIMER_RESOLUTION)
+    }
+
+    /// frames up to `mtu` bytes after the link header, timers rounded to `timer_resolution`
+    pub fn with_options(device: L, mtu: usize, timer_resolution: Duration) -> result::Result<Self> {
+        Self::with_clock(device, mtu, timer_resolution, system_clock())
+    }
+
+    /// the timers follow `clock`, the loop still waits for the device in real time
+    pub fn with_clock(
+        mut device: L,
+        mtu: usize,
+        timer_resolution: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> result::Result<Self> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let polled = register(&poll, &mut device)?;
+        let mut buf = BufferPool::global().get();
+        buf.resize(recv_buffer_len(&device, mtu));
+        Ok(Self {
+            poll,
+            events: Events::with_capacity(EVENTS_CAPACITY),
+            waker,
+            device,
+            timers: TimerWheel::with_clock(clock, timer_resolution, DEFAULT_WHEEL_SLOTS),
+            buf,
+            mtu,
+            polled,
+            reopen: None,
+            gro: None,
+        })
+    }
+
+    /// once the device is gone `run` calls `reopen` every `interval` until it returns a new
+    /// device, which takes the place of the old one, instead of failing with `Error::DeviceGone`
+    pub fn set_reopen(&mut self, reopen: Reopen<L>, interval: Duration) {
+        self.reopen = Some((reopen, interval));
+    }
+
+    /// merge consecutive in-order segments of a connection drained from the device in one
+    /// go before the handler sees them, unless the device coalesces them already
+    /// (`Capabilities::GRO`)
+    pub fn set_gro(&mut self, enabled: bool) {
+        let capabilities = self.device.capabilities();
+        self.gro = if enabled && !capabilities.contains(Capabilities::GRO) {
+            let offloaded = capabilities.contains(Capabilities::RX_CHECKSUM);
+            Some(Coalescer::new(self.device.header_len(), offloaded))
+        } else {
+            None
+        };
+    }
+
+    /// handle used by other threads to interrupt the loop
+    pub fn waker(&self) -> Arc<Waker> {
+        self.waker.clone()
+    }
+
+    pub fn device_mut(&mut self) -> &mut L {
+        &mut self.device
+    }
+
+    pub fn timers_mut(&mut self) -> &mut TimerWheel<T> {
+        &mut self.timers
+    }
+
+    /// run until the handler asks to stop, or the device went away and can't be reopened
+    pub fn run<H: Handler<L, T>>(&mut self, handler: &mut H) -> result::Result<()> {
+        while !handler.should_stop() {
+            match self.run_once(handler, None) {
+                Err(result::Error::DeviceGone) if self.reopen.is_some() => self.reopen(handler)?,
+                res => res?,
+            }
+        }
+        Ok(())
+    }
+
+    /// wait for one round of events, at most `timeout` or until the next timer is due.
+    /// fails with `Error::DeviceGone` once the device went away, see `is_device_gone`
+    pub fn run_once<H: Handler<L, T>>(&mut self, handler: &mut H, timeout: Option<Duration>) -> result::Result<()> {
+        self.turn(handler, timeout).map_err(|e| match e {
+            result::Error::StdIOError(ref io) if is_device_gone(io) => result::Error::DeviceGone,
+            e => e,
+        })
+    }
+
+    /// replace the device which went away, the timers keep running meanwhile
+    fn reopen<H: Handler<L, T>>(&mut self, handler: &mut H) -> result::Result<()> {
+        warn!("device gone, reopening it");
+        if let Some(fd) = self.device.raw_fd() {
+            let _ = self.poll.registry().deregister(&mut SourceFd(&fd));
+        }
+        let (reopen, interval) = self.reopen.as_mut().expect("reopen set");
+        let interval = *interval;
+        while !handler.should_stop() {
+            match reopen() {
+                Ok(mut device) => {
+                    self.polled = register(&self.poll, &mut device)?;
+                    self.buf.resize(recv_buffer_len(&device, self.mtu));
+                    self.device = device;
+                    info!("device reopened");
+                    return Ok(());
+                }
+                Err(e) => debug!(error = ?e, "reopen device"),
+            }
+            thread::sleep(interval);
+            let now = self.timers.now();
+            let mut cx = Context { device: &mut self.device, timers: &mut self.timers, now };
+            for (id, timer) in cx.timers.expire(now) {
+                // the old device fails the sends, the retransmissions pick them up later
+                if let Err(e) = handler.on_timer(&mut cx, id, timer) {
+                    debug!(error = ?e, "timer without device");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn turn<H: Handler<L, T>>(&mut self, handler: &mut H, timeout: Option<Duration>) -> result::Result<()> {
+        let mut wait = min_timeout(timeout, self.timers.next_timeout(self.timers.now()));
+        if self.polled {
+            wait = min_timeout(wait, Some(DEVICE_POLL_INTERVAL));
+        }
+        match self.poll.poll(&mut self.events, wait) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut readable = self.polled;
+        let mut woken = false;
+        for event in self.events.iter() {
+            match event.token() {
+                DEVICE => readable = true,
+                WAKER => woken = true,
+                _ => {}
+            }
+        }
+
+        let now = self.timers.now();
+        let mut cx = Context {
+            device: &mut self.device,
+            timers: &mut self.timers,
+            now,
+        };
+        if woken {
+            handler.on_wakeup(&mut cx)?;
+        }
+        // readiness is edge triggered, drain everything queued in the device
+        while readable {
+            match cx.device.recv(&mut self.buf) {
+                // an empty read has no frame in it, the next readiness starts over
+                Ok(0) => readable = false,
+                Ok(n) => match &mut self.gro {
+                    Some(gro) => coalesce(gro, handler, &mut cx, &self.buf[..n])?,
+                    None => handler.on_frame(&mut cx, &self.buf[..n])?,
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => readable = false,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if let Some(held) = self.gro.as_mut().and_then(Coalescer::take) {
+            handler.on_frame(&mut cx, &held)?;
+        }
+        cx.now = cx.timers.now();
+        for (id, timer) in cx.timers.expire(cx.now) {
+            handler.on_timer(&mut cx, id, timer)?;
+        }
+        Ok(())
+    }
+}
+
+/// hand `frame` to `gro`, the segment it held before goes to the handler when `frame`
+/// doesn't continue it
+fn coalesce<L: DataLayer, T, H: Handler<L, T>>(
+    gro: &mut Coalescer,
+    handler: &mut H,
+    cx: &mut Context<L, T>,
+    frame: &[u8],
+) -> result::Result<()> {
+    if gro.push(frame) {
+        return Ok(());
+    }
+    if let Some(held) = gro.take() {
+        handler.on_frame(cx, &held)?;
+    }
+    if !gro.push(frame) {
+        handler.on_frame(cx, frame)?;
+    }
+    Ok(())
+}
+
+/// switch `device` to non-blocking mode and register its fd, true if it has none and
+/// must be polled
+fn register<L: DataLayer>(poll: &Poll, device: &mut L) -> result::Result<bool> {
+    device.set_nonblocking(true)?;
+    match device.raw_fd() {
+        Some(fd) => {
+            poll.registry().register(&mut SourceFd(&fd), DEVICE, Interest::READABLE)?;
+            Ok(false)
+        }
+        None => Ok(true),
+    }
+}
+
+fn min_timeout(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
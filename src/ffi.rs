@@ -0,0 +1,542 @@
+//! C API, see `include/tcp_stack.h`
+//!
+//! the stack, its listeners and streams are opaque handles the caller owns: each one is
+//! created by a `tcp_stack_*` function writing it to an out pointer and released by its
+//! `*_close` function, exactly once, `tcp_stack_close` says what becomes of the listeners and
+//! streams of a closed stack. a handle may be used by several threads at once, e.g. one
+//! reading and one writing a stream, but not while it's closed.
+//!
+//! functions return `TCP_STACK_ERROR_OK` or a negative `TcpStackError`, reads and writes the
+//! byte count or a negative error. a panic is caught at the boundary and reported as
+//! `TCP_STACK_ERROR_PANIC`, it never unwinds into C.
+//!
+//! the library is built with `cargo rustc --release --lib --features capi --crate-type cdylib`
+//! (or `staticlib`) and the header regenerated with `cbindgen --output include/tcp_stack.h`
+
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::StackConfig;
+use crate::observer::{ConnectionObserver, ObserverId};
+use crate::socket::{TcpListener, TcpStream};
+use crate::socket_addr::Quad;
+use crate::stack::NetStack;
+use crate::tcp::vars::TcpState;
+
+/// A stack, from `tcp_stack_open`
+pub struct TcpStackNet {
+    stack: NetStack,
+}
+
+/// A listening socket, from `tcp_stack_listen`
+pub struct TcpStackListener {
+    listener: TcpListener,
+}
+
+/// A connection, from `tcp_stack_accept` or `tcp_stack_connect`
+pub struct TcpStackStream {
+    stream: TcpStream,
+}
+
+/// Result codes, `TCP_STACK_ERROR_OK` or a negative error
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TcpStackError {
+    Ok = 0,
+    /// a null handle, a malformed string or an out of range value
+    InvalidArgument = -1,
+    /// a `try_` function would have to wait
+    WouldBlock = -2,
+    ConnectionRefused = -3,
+    ConnectionReset = -4,
+    TimedOut = -5,
+    AddrInUse = -6,
+    AddrNotAvailable = -7,
+    /// the connection isn't established or was shut down for writing
+    NotConnected = -8,
+    /// the device needs privileges the process lacks, e.g. CAP_NET_ADMIN for a TUN interface
+    PermissionDenied = -9,
+    /// any other failure, the log has the details
+    Io = -10,
+    /// a bug in the stack, the panic was caught
+    Panic = -11,
+}
+
+impl From<&io::Error> for TcpStackError {
+    fn from(e: &io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::InvalidInput => TcpStackError::InvalidArgument,
+            ErrorKind::WouldBlock => TcpStackError::WouldBlock,
+            ErrorKind::ConnectionRefused => TcpStackError::ConnectionRefused,
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => TcpStackError::ConnectionReset,
+            ErrorKind::TimedOut => TcpStackError::TimedOut,
+            ErrorKind::AddrInUse => TcpStackError::AddrInUse,
+            ErrorKind::AddrNotAvailable => TcpStackError::AddrNotAvailable,
+            ErrorKind::NotConnected | ErrorKind::BrokenPipe => TcpStackError::NotConnected,
+            ErrorKind::PermissionDenied => TcpStackError::PermissionDenied,
+            _ => TcpStackError::Io,
+        }
+    }
+}
+
+/// Configuration of `tcp_stack_open`, zeroed fields get the defaults
+#[repr(C)]
+pub struct TcpStackConfig {
+    /// name of the TUN interface, NULL for "tcp0"
+    pub interface: *const c_char,
+    /// address of the stack, e.g. "192.168.3.2", required
+    pub addr: *const c_char,
+    /// address/prefix the interface gets on the host, e.g. "192.168.3.1/24", NULL for none
+    pub host: *const c_char,
+    /// 0 for the mtu of the interface, 1500 when it is configured with `host`
+    pub mtu: usize,
+    /// queues of the interface, each one served by its own thread, 0 for 1
+    pub queues: usize,
+}
+
+/// States of a connection, as passed to a `TcpStackStateCallback`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TcpStackState {
+    Closed,
+    Listen,
+    SynReceived,
+    SynSent,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl From<TcpState> for TcpStackState {
+    fn from(state: TcpState) -> Self {
+        match state {
+            TcpState::Closed => TcpStackState::Closed,
+            TcpState::Listen => TcpStackState::Listen,
+            TcpState::SynReceived => TcpStackState::SynReceived,
+            TcpState::SynSent => TcpStackState::SynSent,
+            TcpState::Established => TcpStackState::Established,
+            TcpState::FinWait1 => TcpStackState::FinWait1,
+            TcpState::FinWait2 => TcpStackState::FinWait2,
+            TcpState::CloseWait => TcpStackState::CloseWait,
+            TcpState::Closing => TcpStackState::Closing,
+            TcpState::LastAck => TcpStackState::LastAck,
+            TcpState::TimeWait => TcpStackState::TimeWait,
+        }
+    }
+}
+
+/// (local, remote) endpoints of a connection, addresses in host byte order
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct TcpStackQuad {
+    pub local_addr: u32,
+    pub local_port: u16,
+    pub remote_addr: u32,
+    pub remote_port: u16,
+}
+
+impl From<&Quad> for TcpStackQuad {
+    fn from(quad: &Quad) -> Self {
+        Self {
+            local_addr: quad.src().ipv4().map_or(0, u32::from),
+            local_port: quad.src().port(),
+            remote_addr: quad.dest().ipv4().map_or(0, u32::from),
+            remote_port: quad.dest().port(),
+        }
+    }
+}
+
+/// called with the `user` pointer given to `tcp_stack_on_state_change` when a connection
+/// changes state. it runs on a thread of the stack while the stack is locked: it must return
+/// quickly and must not call any `tcp_stack_*` function
+pub type TcpStackStateCallback = extern "C" fn(user: *mut c_void, quad: *const TcpStackQuad, from: TcpStackState, to: TcpStackState);
+
+/// the callback and its user pointer, the caller vouches that both may be used from any thread
+struct StateCallback {
+    callback: TcpStackStateCallback,
+    user: *mut c_void,
+}
+
+unsafe impl Send for StateCallback {}
+unsafe impl Sync for StateCallback {}
+
+impl ConnectionObserver for StateCallback {
+    fn on_state_change(&self, quad: &Quad, from: TcpState, to: TcpState) {
+        let quad = TcpStackQuad::from(quad);
+        // a callback must not unwind, but a panicking one mustn't take down the stack either
+        let _ = panic::catch_unwind(|| (self.callback)(self.user, &quad, from.into(), to.into()));
+    }
+}
+
+/// run `f` and turn its error or panic into a code
+fn guard<F: FnOnce() -> Result<c_int, TcpStackError>>(f: F) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(code)) => code,
+        Ok(Err(e)) => e as c_int,
+        Err(_) => TcpStackError::Panic as c_int,
+    }
+}
+
+/// `guard` for functions returning a byte count
+fn guard_len<F: FnOnce() -> Result<usize, TcpStackError>>(f: F) -> isize {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(len)) => len.min(isize::MAX as usize) as isize,
+        Ok(Err(e)) => e as isize,
+        Err(_) => TcpStackError::Panic as isize,
+    }
+}
+
+fn io<T>(result: io::Result<T>) -> Result<T, TcpStackError> {
+    result.map_err(|e| TcpStackError::from(&e))
+}
+
+/// the handle behind `ptr`
+unsafe fn handle<'a, T>(ptr: *const T) -> Result<&'a T, TcpStackError> {
+    ptr.as_ref().ok_or(TcpStackError::InvalidArgument)
+}
+
+/// the string behind `ptr`, `None` for NULL
+unsafe fn string<'a>(ptr: *const c_char) -> Result<Option<&'a str>, TcpStackError> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(ptr).to_str().map(Some).map_err(|_| TcpStackError::InvalidArgument)
+}
+
+/// hand `value` to C through `out`
+unsafe fn give<T>(out: *mut *mut T, value: T) -> Result<c_int, TcpStackError> {
+    if out.is_null() {
+        return Err(TcpStackError::InvalidArgument);
+    }
+    *out = Box::into_raw(Box::new(value));
+    Ok(TcpStackError::Ok as c_int)
+}
+
+fn parse_subnet(s: &str) -> Result<(Ipv4Addr, u8), TcpStackError> {
+    let (addr, prefix_len) = s.split_once('/').unwrap_or((s, "24"));
+    let addr = addr.parse().map_err(|_| TcpStackError::InvalidArgument)?;
+    let prefix_len = prefix_len.parse().ok().filter(|len| *len <= 32).ok_or(TcpStackError::InvalidArgument)?;
+    Ok((addr, prefix_len))
+}
+
+/// open the TUN interface of `config` and start the stack on it
+///
+/// # Safety
+/// `config` points to a configuration whose strings are NULL or nul terminated,
+/// `out` to writable memory for the handle
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_open(config: *const TcpStackConfig, out: *mut *mut TcpStackNet) -> c_int {
+    guard(|| {
+        let config = handle(config)?;
+        let addr = string(config.addr)?.ok_or(TcpStackError::InvalidArgument)?;
+        let mut builder = StackConfig::builder()
+            .addr(addr.parse().map_err(|_| TcpStackError::InvalidArgument)?);
+        if let Some(interface) = string(config.interface)? {
+            builder = builder.interface(interface);
+        }
+        if let Some(host) = string(config.host)? {
+            let (host, prefix_len) = parse_subnet(host)?;
+            builder = builder.host_addr(host, prefix_len);
+        }
+        if config.mtu != 0 {
+            builder = builder.mtu(config.mtu);
+        }
+        if config.queues != 0 {
+            builder = builder.queues(config.queues);
+        }
+        let stack = io(builder.build().and_then(NetStack::new).map_err(io::Error::from))?;
+        give(out, TcpStackNet { stack })
+    })
+}
+
+/// stop the stack and release the handle. its connections are reset and its listeners
+/// closed: reads on their handles return end of file, writes and accepts fail with
+/// `TCP_STACK_ERROR_NOT_CONNECTED`, the blocked ones too, and each handle is still released
+/// by its own `*_close`
+///
+/// # Safety
+/// `stack` is NULL or a handle from `tcp_stack_open` not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_close(stack: *mut TcpStackNet) {
+    if !stack.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let stack = Box::from_raw(stack);
+            stack.stack.shutdown(Duration::ZERO);
+        }));
+    }
+}
+
+/// a descriptor turning readable when a connection or listener of the stack becomes ready,
+/// for epoll or poll together with the `tcp_stack_try_*` functions. it stays readable
+/// until `tcp_stack_clear_readiness`
+///
+/// # Safety
+/// `stack` is a handle from `tcp_stack_open`
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_readiness_fd(stack: *const TcpStackNet) -> c_int {
+    guard(|| io(handle(stack)?.stack.readiness_fd()))
+}
+
+/// # Safety
+/// `stack` is a handle from `tcp_stack_open`
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_clear_readiness(stack: *const TcpStackNet) -> c_int {
+    guard(|| io(handle(stack)?.stack.clear_readiness()).map(|_| TcpStackError::Ok as c_int))
+}
+
+/// call `callback` with `user` on every state change of a connection, return an id for
+/// `tcp_stack_remove_callback` or a negative error
+///
+/// # Safety
+/// `stack` is a handle from `tcp_stack_open`, `user` stays valid until the callback is
+/// removed and may be used from any thread
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_on_state_change(
+    stack: *const TcpStackNet,
+    callback: Option<TcpStackStateCallback>,
+    user: *mut c_void,
+) -> i64 {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let stack = handle(stack)?;
+        let callback = callback.ok_or(TcpStackError::InvalidArgument)?;
+        Ok::<_, TcpStackError>(stack.stack.add_observer(Arc::new(StateCallback { callback, user })))
+    }));
+    match result {
+        Ok(Ok(id)) => id.as_u64() as i64,
+        Ok(Err(e)) => e as i64,
+        Err(_) => TcpStackError::Panic as i64,
+    }
+}
+
+/// stop calling the callback `id`, once this returns it won't be called again
+///
+/// # Safety
+/// `stack` is a handle from `tcp_stack_open`
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_remove_callback(stack: *const TcpStackNet, id: i64) -> c_int {
+    guard(|| {
+        let stack = handle(stack)?;
+        let id = u64::try_from(id).map_err(|_| TcpStackError::InvalidArgument)?;
+        match stack.stack.remove_observer(ObserverId::from_u64(id)) {
+            true => Ok(TcpStackError::Ok as c_int),
+            false => Err(TcpStackError::InvalidArgument),
+        }
+    })
+}
+
+/// listen on `port` of every address of the stack
+///
+/// # Safety
+/// `stack` is a handle from `tcp_stack_open`, `out` points to writable memory for the handle
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_listen(stack: *const TcpStackNet, port: u16, out: *mut *mut TcpStackListener) -> c_int {
+    guard(|| {
+        let listener = io(TcpListener::bind(&handle(stack)?.stack, port))?;
+        give(out, TcpStackListener { listener })
+    })
+}
+
+/// wait for the next established connection
+///
+/// # Safety
+/// `listener` is a handle from `tcp_stack_listen`, `out` points to writable memory for the handle
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_accept(listener: *const TcpStackListener, out: *mut *mut TcpStackStream) -> c_int {
+    guard(|| {
+        let stream = io(handle(listener)?.listener.accept())?;
+        give(out, TcpStackStream { stream })
+    })
+}
+
+/// `TCP_STACK_ERROR_WOULD_BLOCK` if no connection is waiting
+///
+/// # Safety
+/// like `tcp_stack_accept`
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_try_accept(listener: *const TcpStackListener, out: *mut *mut TcpStackStream) -> c_int {
+    guard(|| {
+        let stream = io(handle(listener)?.listener.try_accept())?;
+        give(out, TcpStackStream { stream })
+    })
+}
+
+/// stop listening, connections not accepted yet are reset
+///
+/// # Safety
+/// `listener` is NULL or a handle from `tcp_stack_listen` not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_listener_close(listener: *mut TcpStackListener) {
+    if !listener.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(listener))));
+    }
+}
+
+/// connect to `addr`, e.g. "192.168.3.1:80", and wait up to `timeout_ms` milliseconds for
+/// the handshake, 0 waits as long as the retransmissions go on
+///
+/// # Safety
+/// `stack` is a handle from `tcp_stack_open`, `addr` a nul terminated string and `out`
+/// points to writable memory for the handle
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_connect(
+    stack: *const TcpStackNet,
+    addr: *const c_char,
+    timeout_ms: u32,
+    out: *mut *mut TcpStackStream,
+) -> c_int {
+    guard(|| {
+        let stack = handle(stack)?;
+        let addr: SocketAddrV4 = string(addr)?
+            .and_then(|addr| addr.parse().ok())
+            .ok_or(TcpStackError::InvalidArgument)?;
+        let stream = match timeout_ms {
+            0 => io(TcpStream::connect(&stack.stack, addr))?,
+            ms => io(TcpStream::connect_timeout(&stack.stack, addr, Duration::from_millis(ms as u64)))?,
+        };
+        give(out, TcpStackStream { stream })
+    })
+}
+
+/// read up to `len` bytes into `buf`, waiting for data. return the bytes read,
+/// 0 at end of file, or a negative error
+///
+/// # Safety
+/// `stream` is a handle from `tcp_stack_accept` or `tcp_stack_connect`, `buf` is valid for
+/// writes of `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_read(stream: *const TcpStackStream, buf: *mut u8, len: usize) -> isize {
+    guard_len(|| {
+        let stream = handle(stream)?;
+        let buf = bytes_mut(buf, len)?;
+        io(io::Read::read(&mut &stream.stream, buf))
+    })
+}
+
+/// like `tcp_stack_read` but `TCP_STACK_ERROR_WOULD_BLOCK` if nothing arrived
+///
+/// # Safety
+/// like `tcp_stack_read`
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_try_read(stream: *const TcpStackStream, buf: *mut u8, len: usize) -> isize {
+    guard_len(|| {
+        let stream = handle(stream)?;
+        io(stream.stream.try_read(bytes_mut(buf, len)?))
+    })
+}
+
+/// queue `len` bytes of `buf`, waiting while the send buffer is full. return the bytes
+/// queued or a negative error
+///
+/// # Safety
+/// `stream` is a handle from `tcp_stack_accept` or `tcp_stack_connect`, `buf` is valid for
+/// reads of `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_write(stream: *const TcpStackStream, buf: *const u8, len: usize) -> isize {
+    guard_len(|| {
+        let stream = handle(stream)?;
+        io(io::Write::write(&mut &stream.stream, bytes(buf, len)?))
+    })
+}
+
+/// like `tcp_stack_write` but `TCP_STACK_ERROR_WOULD_BLOCK` if the send buffer is full
+///
+/// # Safety
+/// like `tcp_stack_write`
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_try_write(stream: *const TcpStackStream, buf: *const u8, len: usize) -> isize {
+    guard_len(|| {
+        let stream = handle(stream)?;
+        io(stream.stream.try_write(bytes(buf, len)?))
+    })
+}
+
+/// send a FIN after the queued data, reading goes on
+///
+/// # Safety
+/// `stream` is a handle from `tcp_stack_accept` or `tcp_stack_connect`
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_shutdown(stream: *const TcpStackStream) -> c_int {
+    guard(|| io(handle(stream)?.stream.shutdown()).map(|_| TcpStackError::Ok as c_int))
+}
+
+/// reset the connection, queued data is discarded
+///
+/// # Safety
+/// `stream` is a handle from `tcp_stack_accept` or `tcp_stack_connect`
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_abort(stream: *const TcpStackStream) -> c_int {
+    guard(|| io(handle(stream)?.stream.abort()).map(|_| TcpStackError::Ok as c_int))
+}
+
+/// endpoints of the connection
+///
+/// # Safety
+/// `stream` is a handle from `tcp_stack_accept` or `tcp_stack_connect`, `out` points to
+/// writable memory
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_stream_quad(stream: *const TcpStackStream, out: *mut TcpStackQuad) -> c_int {
+    guard(|| {
+        let stream = handle(stream)?;
+        let out = out.as_mut().ok_or(TcpStackError::InvalidArgument)?;
+        *out = TcpStackQuad::from(&stream.stream.quad());
+        Ok(TcpStackError::Ok as c_int)
+    })
+}
+
+/// close the connection and release the handle, queued data is still sent
+///
+/// # Safety
+/// `stream` is NULL or a handle from `tcp_stack_accept` or `tcp_stack_connect` not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn tcp_stack_stream_close(stream: *mut TcpStackStream) {
+    if !stream.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(stream))));
+    }
+}
+
+/// static description of a result code
+#[no_mangle]
+pub extern "C" fn tcp_stack_strerror(code: c_int) -> *const c_char {
+    let msg: &'static [u8] = match code {
+        0 => b"success\0",
+        -1 => b"invalid argument\0",
+        -2 => b"operation would block\0",
+        -3 => b"connection refused\0",
+        -4 => b"connection reset\0",
+        -5 => b"operation timed out\0",
+        -6 => b"address in use\0",
+        -7 => b"address not available\0",
+        -8 => b"not connected\0",
+        -9 => b"permission denied\0",
+        -10 => b"i/o error\0",
+        -11 => b"panic in the stack\0",
+        _ => b"unknown error\0",
+    };
+    msg.as_ptr() as *const c_char
+}
+
+unsafe fn bytes<'a>(buf: *const u8, len: usize) -> Result<&'a [u8], TcpStackError> {
+    match len {
+        0 => Ok(&[]),
+        _ if buf.is_null() => Err(TcpStackError::InvalidArgument),
+        _ => Ok(std::slice::from_raw_parts(buf, len)),
+    }
+}
+
+unsafe fn bytes_mut<'a>(buf: *mut u8, len: usize) -> Result<&'a mut [u8], TcpStackError> {
+    match len {
+        0 => Ok(&mut []),
+        _ if buf.is_null() => Err(TcpStackError::InvalidArgument),
+        _ => Ok(std::slice::from_raw_parts_mut(buf, len)),
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::capture::{CapturedFrame, Direction, Filter, SegmentInfo};
+
+/// What happens to an inbound packet
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Verdict {
+    /// the segment goes on to its connection or listener
+    Accept,
+    /// the segment is silently discarded
+    Drop,
+    /// the segment is discarded and answered with a RST, like a closed port, packets of
+    /// other protocols with an ICMP Destination Unreachable
+    Reject,
+}
+
+impl Verdict {
+    fn from_u8(verdict: u8) -> Self {
+        match verdict {
+            0 => Verdict::Accept,
+            1 => Verdict::Drop,
+            _ => Verdict::Reject,
+        }
+    }
+}
+
+/// decides on a segment or passes it on with `None`
+pub type RuleFn = dyn Fn(&SegmentInfo) -> Option<Verdict> + Send + Sync;
+
+/// A rule of the inbound filter chain
+pub enum Rule {
+    /// segments matching the filter get the verdict, e.g. `"not dst port 22".parse()`
+    Match(Filter, Verdict),
+    /// the closure decides, `None` passes the segment to the next rule. it is called on
+    /// the thread processing the packets while the stack is locked, it must not use sockets
+    /// of the same stack
+    Closure(Box<RuleFn>),
+}
+
+impl Rule {
+    pub fn new(filter: Filter, verdict: Verdict) -> Self {
+        Rule::Match(filter, verdict)
+    }
+
+    pub fn from_fn<F>(rule: F) -> Self
+        where F: Fn(&SegmentInfo) -> Option<Verdict> + Send + Sync + 'static {
+        Rule::Closure(Box::new(rule))
+    }
+
+    fn verdict(&self, frame: &CapturedFrame) -> Option<Verdict> {
+        match self {
+            Rule::Match(filter, verdict) => filter.matches(frame).then_some(*verdict),
+            Rule::Closure(rule) => frame.segment.as_ref().and_then(rule),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct RuleId(u64);
+
+/// The inbound filter chain of a stack, shared by its shards
+///
+/// the rules are tried in the order they were added, the first verdict wins and
+/// segments no rule decides on get the policy, `Accept` by default
+#[derive(Default)]
+pub(crate) struct Firewall {
+    rules: RwLock<Vec<(RuleId, Rule)>>,
+    policy: AtomicU8,
+    next_id: AtomicU64,
+}
+
+impl Firewall {
+    fn read(&self) -> RwLockReadGuard<'_, Vec<(RuleId, Rule)>> {
+        self.rules.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Vec<(RuleId, Rule)>> {
+        self.rules.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn add(&self, rule: Rule) -> RuleId {
+        let id = RuleId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.write().push((id, rule));
+        id
+    }
+
+    pub(crate) fn remove(&self, id: RuleId) -> bool {
+        let mut rules = self.write();
+        let len = rules.len();
+        rules.retain(|(other, _)| *other != id);
+        rules.len() != len
+    }
+
+    /// swap the whole chain and the policy, a segment is checked against either the old
+    /// or the new ones. returns the ids of `rules`
+    pub(crate) fn replace(&self, rules: Vec<Rule>, policy: Verdict) -> Vec<RuleId> {
+        let rules: Vec<(RuleId, Rule)> = rules.into_iter()
+            .map(|rule| (RuleId(self.next_id.fetch_add(1, Ordering::Relaxed)), rule))
+            .collect();
+        let ids = rules.iter().map(|(id, _)| *id).collect();
+        let mut chain = self.write();
+        *chain = rules;
+        self.set_policy(policy);
+        ids
+    }
+
+    pub(crate) fn policy(&self) -> Verdict {
+        Verdict::from_u8(self.policy.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_policy(&self, policy: Verdict) {
+        self.policy.store(policy as u8, Ordering::Relaxed);
+    }
+
+    /// verdict on the packet received in `frame`, tcp or not
+    pub(crate) fn check(&self, frame: &[u8], link_header_len: usize) -> Verdict {
+        let rules = self.read();
+        if rules.is_empty() {
+            return self.policy();
+        }
+        let frame = CapturedFrame::new(Direction::In, frame, link_header_len);
+        rules.iter()
+            .find_map(|(_, rule)| rule.verdict(&frame))
+            .unwrap_or_else(|| self.policy())
+    }
+}
@@ -0,0 +1,197 @@
+//! Entry points for coverage guided fuzzers
+//!
+//! built with `--cfg fuzzing`, as cargo fuzz does, or with the `fuzz` feature. a target
+//! only has to hand its input over, e.g. `fuzz_target!(|data: &[u8]| fuzz_parse_frame(data))`.
+//! both functions must never panic, whatever the input
+
+use std::io::Result;
+use std::net::Ipv4Addr;
+
+use crate::data_link::DataLayer;
+use crate::reader_writer::{RawReader, RawWriter, Segment};
+use crate::socket_addr::{Addr, Quad};
+use crate::tcp::connection::{ConnectionConfig, TcpConnection};
+use crate::tcp::packet::TcpIpHeader;
+use crate::tcp::vars::TcpState;
+
+/// parse `data` as a received frame, the first byte is the length of the link header
+/// in front of the ip packet which makes up the rest
+pub fn fuzz_parse_frame(data: &[u8]) {
+    let (link, frame) = match data.split_first() {
+        Some((link, frame)) => (*link as usize % 16, frame),
+        None => return,
+    };
+    let mut raw = RawReader::from_slice(frame, frame.len(), link);
+    let _ = raw.offset_data();
+    if !matches!(raw.is_ipv4_packet(), Ok(true)) {
+        let _ = raw.ipv6_header();
+        return;
+    }
+    if let Ok(ip) = raw.ipv4_header() {
+        let _ = ip.to_header().calc_header_checksum();
+    }
+    let _ = raw.tcp_ip_header();
+    let _ = raw.data_offset();
+    if let Ok(segment) = raw.segment() {
+        let _ = segment.checksum_valid();
+        let _ = segment.quad();
+        for option in segment.tcp().options_iterator() {
+            let _ = option;
+        }
+    }
+    let _ = Segment::parse(frame, link);
+}
+
+/// drive a passive connection with `events`, each one is the segment of a peer or a call
+/// of the application. sequence and acknowledgment numbers of the segments are offsets
+/// from the ones the connection sent last, so most of them land near its windows
+pub fn fuzz_connection(events: &[u8]) {
+    let mut input = Input(events);
+    let local = Addr::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+    let remote = Addr::new(Ipv4Addr::new(10, 0, 0, 2), 4000);
+    let mut sink = Sink::default();
+    let peer_iss = input.u32();
+    let syn = match frame(remote, local, peer_iss, None, input.u16(), TcpFlags::SYN, &[]) {
+        Some(syn) => syn,
+        None => return,
+    };
+    let segment = match Segment::parse(&syn, 0) {
+        Ok(segment) => segment,
+        Err(_) => return,
+    };
+    let mut config = ConnectionConfig::default();
+    config.set_init_send_seq_number(input.u32());
+    config.set_mss(536 + input.u8() as usize);
+    let mut conn = match TcpConnection::accept_with_config(&mut sink, segment.ip(), segment.tcp(), &[], config) {
+        Ok(Some(conn)) => conn,
+        _ => return,
+    };
+    let mut buf = [0; 1024];
+    while !input.is_empty() {
+        match input.u8() % 10 {
+            0..=3 => {
+                let flags = input.u8();
+                let seq = sink.ack.wrapping_add(input.u8() as i8 as u32);
+                let ack = sink.nxt.wrapping_add(input.u8() as i8 as u32);
+                let ack = if flags & TcpFlags::ACK != 0 { Some(ack) } else { None };
+                let window = input.u16();
+                let len = input.u8() as usize * 8;
+                let payload = input.bytes(len);
+                if let Some(frame) = frame(remote, local, seq, ack, window, flags, &payload) {
+                    if let Ok(segment) = Segment::parse(&frame, 0) {
+                        let _ = conn.on_segment(&mut sink, segment.tcp(), segment.payload());
+                    }
+                }
+            }
+            4 => {
+                let len = input.u8() as usize * 16;
+                let payload = input.bytes(len);
+                conn.write(&payload);
+            }
+            5 => {
+                let len = input.u8() as usize * 4;
+                conn.read(&mut buf[..len]);
+            }
+            6 => conn.close(),
+            7 => conn.abort(),
+            8 if conn.state() == TcpState::TimeWait => conn.expire_time_wait(),
+            _ => {
+                let _ = conn.transmit(&mut sink);
+            }
+        }
+        let _ = conn.stats();
+        let _ = (conn.bytes_available(), conn.send_space(), conn.is_readable(), conn.is_writable());
+    }
+}
+
+/// the fuzzer input read from the front, exhausted input reads as zeros
+struct Input<'a>(&'a [u8]);
+
+impl Input<'_> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn u8(&mut self) -> u8 {
+        match self.0.split_first() {
+            Some((byte, rest)) => {
+                self.0 = rest;
+                *byte
+            }
+            None => 0,
+        }
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_be_bytes([self.u8(), self.u8()])
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_be_bytes([self.u8(), self.u8(), self.u8(), self.u8()])
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let len = len.min(self.0.len());
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        bytes.to_vec()
+    }
+}
+
+/// the bits of the flags byte of a tcp header
+struct TcpFlags;
+
+impl TcpFlags {
+    const FIN: u8 = 0x01;
+    const SYN: u8 = 0x02;
+    const RST: u8 = 0x04;
+    const PSH: u8 = 0x08;
+    const ACK: u8 = 0x10;
+    const URG: u8 = 0x20;
+}
+
+/// an ipv4 packet with a valid checksum sent from `src` to `dest`
+fn frame(src: Addr, dest: Addr, seq: u32, ack: Option<u32>, window: u16, flags: u8, payload: &[u8]) -> Option<Vec<u8>> {
+    let mut header = TcpIpHeader::from_quad(&Quad::new(src, dest), seq, window, 64).ok()?;
+    let tcp = &mut header.tcp_header;
+    tcp.fin = flags & TcpFlags::FIN != 0;
+    tcp.syn = flags & TcpFlags::SYN != 0;
+    tcp.rst = flags & TcpFlags::RST != 0;
+    tcp.psh = flags & TcpFlags::PSH != 0;
+    tcp.urg = flags & TcpFlags::URG != 0;
+    if let Some(ack) = ack {
+        header.set_ack_number(ack);
+    }
+    header.finalize(payload).ok()?;
+    let mut writer = RawWriter::new(0);
+    writer.write_header(&header).ok()?;
+    writer.write_payload(payload).ok()?;
+    Some(writer.finalize().ok()?.to_vec())
+}
+
+/// Device of the connection, remembers what it sent last instead of sending it
+#[derive(Default)]
+struct Sink {
+    /// acknowledgment number of the last segment, what the connection expects next
+    ack: u32,
+    /// sequence number after the last segment
+    nxt: u32,
+}
+
+impl DataLayer for Sink {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        if let Ok(segment) = Segment::parse(data, 0) {
+            let tcp = segment.tcp();
+            let len = segment.payload().len() as u32 + tcp.syn() as u32 + tcp.fin() as u32;
+            if tcp.ack() {
+                self.ack = tcp.acknowledgment_number();
+            }
+            self.nxt = tcp.sequence_number().wrapping_add(len);
+        }
+        Ok(data.len())
+    }
+
+    fn recv(&mut self, _data: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+}
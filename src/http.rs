@@ -0,0 +1,228 @@
+//! Minimal HTTP/1.1 server on the socket API
+//!
+//! serves static bodies by path with keep-alive and pipelining, enough for an end to end
+//! test of the data path or a benchmark with a standard HTTP load generator.
+//! request bodies are skipped and chunked requests aren't supported
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::thread;
+
+use crate::socket::{TcpListener, TcpStream};
+
+/// largest request line and headers accepted
+pub const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+/// Request line and headers of a request, header names are lowercase
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// minor version of HTTP/1.x
+    pub version: u8,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// parse the head of a request, everything before the empty line
+    pub fn parse(head: &[u8]) -> Option<Self> {
+        let head = std::str::from_utf8(head).ok()?;
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next()?.split(' ');
+        let method = request_line.next().filter(|method| !method.is_empty())?;
+        let path = request_line.next().filter(|path| path.starts_with('/') || *path == "*")?;
+        let version = match request_line.next()? {
+            "HTTP/1.0" => 0,
+            "HTTP/1.1" => 1,
+            _ => return None,
+        };
+        if request_line.next().is_some() {
+            return None;
+        }
+        let mut headers = Vec::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':')?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+        Some(Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            version,
+            headers,
+        })
+    }
+
+    /// value of the header `name`, given in lowercase
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// HTTP/1.1 keeps the connection unless told otherwise, HTTP/1.0 only when asked to
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("connection").map(|value| value.to_ascii_lowercase());
+        match connection.as_deref() {
+            Some("close") => false,
+            Some("keep-alive") => true,
+            _ => self.version == 1,
+        }
+    }
+
+    fn content_length(&self) -> Option<usize> {
+        match self.header("content-length") {
+            Some(len) => len.parse().ok(),
+            None => Some(0),
+        }
+    }
+}
+
+/// A static response body
+#[derive(Debug, Clone)]
+struct Resource {
+    content_type: String,
+    body: Arc<[u8]>,
+}
+
+/// Serves the bodies added with `route`, one thread per connection
+#[derive(Debug, Clone, Default)]
+pub struct HttpServer {
+    routes: HashMap<String, Resource>,
+}
+
+impl HttpServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// answer GET and HEAD requests of `path` with `body`
+    pub fn route<B: Into<Arc<[u8]>>>(mut self, path: &str, content_type: &str, body: B) -> Self {
+        self.routes.insert(path.to_string(), Resource {
+            content_type: content_type.to_string(),
+            body: body.into(),
+        });
+        self
+    }
+
+    /// accept connections until the listener fails
+    pub fn serve(self, listener: &TcpListener) -> io::Result<()> {
+        let server = Arc::new(self);
+        loop {
+            let stream = listener.accept()?;
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(e) = server.handle(&stream) {
+                    debug!(peer = %stream.peer_addr(), error = %e, "http connection failed");
+                }
+            });
+        }
+    }
+
+    /// answer the requests of one connection until it's closed
+    pub fn handle(&self, stream: &TcpStream) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(MAX_HEAD_SIZE);
+        while let Some(head_len) = read_head(stream, &mut buf)? {
+            let request = Request::parse(&buf[..head_len - 4]);
+            let keep_alive = request.as_ref().is_some_and(|request| request.keep_alive());
+            let body_len = request.as_ref().and_then(|request| request.content_length());
+            let response = match (&request, body_len) {
+                (Some(request), Some(_)) => self.respond(request),
+                _ => Response::error(400, "Bad Request"),
+            };
+            response.write_to(stream, keep_alive && body_len.is_some())?;
+            if !keep_alive || body_len.is_none() {
+                break;
+            }
+            buf.drain(..head_len);
+            skip_body(stream, &mut buf, body_len.unwrap_or(0))?;
+        }
+        stream.shutdown()?;
+        Ok(())
+    }
+
+    fn respond(&self, request: &Request) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            return Response::error(405, "Method Not Allowed");
+        }
+        let path = request.path.split('?').next().unwrap_or("");
+        match self.routes.get(path) {
+            Some(resource) => Response {
+                status: 200,
+                reason: "OK",
+                content_type: resource.content_type.clone(),
+                body: resource.body.clone(),
+                head: request.method == "HEAD",
+            },
+            None => Response::error(404, "Not Found"),
+        }
+    }
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: String,
+    body: Arc<[u8]>,
+    /// a HEAD request, the headers describe the body which isn't sent
+    head: bool,
+}
+
+impl Response {
+    fn error(status: u16, reason: &'static str) -> Self {
+        Self {
+            status,
+            reason,
+            content_type: "text/plain".to_string(),
+            body: format!("{} {}\n", status, reason).into_bytes().into(),
+            head: false,
+        }
+    }
+
+    fn write_to(&self, stream: &TcpStream, keep_alive: bool) -> io::Result<()> {
+        let connection = if keep_alive { "keep-alive" } else { "close" };
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+            self.status, self.reason, self.content_type, self.body.len(), connection,
+        );
+        let mut writer = stream;
+        writer.write_all(head.as_bytes())?;
+        if !self.head {
+            writer.write_all(&self.body)?;
+        }
+        Ok(())
+    }
+}
+
+/// read until `buf` holds a complete head, return its length with the empty line
+/// or `None` if the peer closed the connection between two requests
+fn read_head(stream: &TcpStream, buf: &mut Vec<u8>) -> io::Result<Option<usize>> {
+    let mut chunk = [0; 4096];
+    let mut reader = stream;
+    loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            return Ok(Some(end + 4));
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "request head too large"));
+        }
+        let n = reader.read(&mut chunk)?;
+        if n == 0 {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// drop the `len` bytes of a request body, some may already be in `buf`
+fn skip_body(stream: &TcpStream, buf: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    let buffered = len.min(buf.len());
+    buf.drain(..buffered);
+    let rest = (len - buffered) as u64;
+    let skipped = io::copy(&mut stream.take(rest), &mut io::sink())?;
+    if skipped < rest {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,178 @@
+//! ICMP (RFC 792) messages
+//!
+//! errors quote the ip header and the first 8 bytes of data of the packet they are
+//! about, enough for traceroute to match them with its probe by the udp ports or the
+//! echo identifier
+
+use std::net::Ipv4Addr;
+
+use crate::checksum::Checksum;
+use crate::tcp::connection::DEFAULT_TIME_TO_LIVE;
+
+pub const PROTOCOL: u8 = 1;
+pub const ECHO_REPLY: u8 = 0;
+pub const DESTINATION_UNREACHABLE: u8 = 3;
+pub const ECHO_REQUEST: u8 = 8;
+pub const TIME_EXCEEDED: u8 = 11;
+/// code of a Time Exceeded message, the time to live ran out in transit
+pub const TTL_EXCEEDED_IN_TRANSIT: u8 = 0;
+/// bytes of the original datagram after its ip header an error quotes
+pub const QUOTED_DATA: usize = 8;
+
+const IP_HEADER_LEN: usize = 20;
+const ICMP_HEADER_LEN: usize = 8;
+const UDP_PROTOCOL: u8 = 17;
+
+/// An ICMP error and the type and code it is sent with
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IcmpError {
+    /// no route to the network of the destination
+    NetUnreachable,
+    HostUnreachable,
+    /// the destination doesn't speak the transport protocol
+    ProtocolUnreachable,
+    /// nothing listens on the udp port, the last hop of a traceroute
+    PortUnreachable,
+    /// the packet is too big for the next hop and may not be fragmented, RFC 1191
+    FragmentationNeeded { mtu: u16 },
+    /// the time to live ran out in transit, a hop of a traceroute
+    TimeExceeded,
+}
+
+impl IcmpError {
+    pub fn kind(&self) -> u8 {
+        match self {
+            IcmpError::TimeExceeded => TIME_EXCEEDED,
+            _ => DESTINATION_UNREACHABLE,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            IcmpError::NetUnreachable => 0,
+            IcmpError::HostUnreachable => 1,
+            IcmpError::ProtocolUnreachable => 2,
+            IcmpError::PortUnreachable => 3,
+            IcmpError::FragmentationNeeded { .. } => 4,
+            IcmpError::TimeExceeded => TTL_EXCEEDED_IN_TRANSIT,
+        }
+    }
+
+    /// the second word of the message, unused but for the next hop mtu
+    fn rest(&self) -> [u8; 4] {
+        match self {
+            IcmpError::FragmentationNeeded { mtu } => {
+                let mtu = mtu.to_be_bytes();
+                [0, 0, mtu[0], mtu[1]]
+            }
+            _ => [0; 4],
+        }
+    }
+
+    /// the error a host answers a packet of `protocol` it has no use for with
+    pub fn unreachable(protocol: u8) -> Self {
+        match protocol {
+            UDP_PROTOCOL => IcmpError::PortUnreachable,
+            _ => IcmpError::ProtocolUnreachable,
+        }
+    }
+}
+
+/// Builds the ICMP messages a host or router sends from `src`
+#[derive(Debug, Copy, Clone)]
+pub struct IcmpBuilder {
+    src: Ipv4Addr,
+    ttl: u8,
+}
+
+impl IcmpBuilder {
+    pub fn new(src: Ipv4Addr) -> Self {
+        Self { src, ttl: DEFAULT_TIME_TO_LIVE }
+    }
+
+    /// time to live of the messages
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// ip packet with `error` about `original` to its source, quoting its ip header and
+    /// the first 8 bytes of its data. `None` if no error may be sent about it
+    pub fn error(&self, error: IcmpError, original: &[u8]) -> Option<Vec<u8>> {
+        let (ihl, len) = packet_len(original)?;
+        // no error about a fragment other than the first one
+        if u16::from_be_bytes([original[6], original[7]]) & 0x1fff != 0 {
+            return None;
+        }
+        // nor about an error, which could start an endless exchange
+        if original[9] == PROTOCOL && original.get(ihl).is_some_and(|kind| !matches!(kind, 0 | 8 | 13 | 14 | 15 | 16)) {
+            return None;
+        }
+        // nor about a packet to or from a group of hosts
+        let (source, dest) = addrs(original);
+        if [source, dest].iter().any(|addr| addr.is_broadcast() || addr.is_multicast()) || source.is_unspecified() {
+            return None;
+        }
+        let mut message = vec![error.kind(), error.code(), 0, 0];
+        message.extend_from_slice(&error.rest());
+        message.extend_from_slice(&original[..len.min(ihl + QUOTED_DATA)]);
+        Some(self.message(source, message))
+    }
+
+    /// Echo Reply to the Echo Request in `request`, with its identifier, sequence number
+    /// and data. `None` if it is no well formed request
+    pub fn echo_reply(&self, request: &[u8]) -> Option<Vec<u8>> {
+        let (ihl, len) = packet_len(request)?;
+        let message = &request[ihl..len];
+        if request[9] != PROTOCOL || message.len() < ICMP_HEADER_LEN || message[0] != ECHO_REQUEST {
+            return None;
+        }
+        let mut checksum = Checksum::new();
+        checksum.add(message);
+        let (source, _) = addrs(request);
+        if checksum.finish() != 0 || source.is_broadcast() || source.is_multicast() || source.is_unspecified() {
+            return None;
+        }
+        let mut message = message.to_vec();
+        message[..4].copy_from_slice(&[ECHO_REPLY, 0, 0, 0]);
+        Some(self.message(source, message))
+    }
+
+    /// ip packet to `dest` with the ICMP `message`, its checksum filled in
+    fn message(&self, dest: Ipv4Addr, mut message: Vec<u8>) -> Vec<u8> {
+        let mut checksum = Checksum::new();
+        checksum.add(&message);
+        message[2..4].copy_from_slice(&checksum.finish().to_be_bytes());
+        self.ip_packet(dest, &message)
+    }
+
+    fn ip_packet(&self, dest: Ipv4Addr, payload: &[u8]) -> Vec<u8> {
+        let len = (IP_HEADER_LEN + payload.len()) as u16;
+        let mut packet = Vec::with_capacity(IP_HEADER_LEN + payload.len());
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, self.ttl, PROTOCOL, 0, 0]);
+        packet.extend_from_slice(&self.src.octets());
+        packet.extend_from_slice(&dest.octets());
+        let mut checksum = Checksum::new();
+        checksum.add(&packet);
+        packet[10..12].copy_from_slice(&checksum.finish().to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+}
+
+/// header length and total length of the ipv4 packet, without the padding of the link
+fn packet_len(packet: &[u8]) -> Option<(usize, usize)> {
+    let ihl = (*packet.first()? & 0xf) as usize * 4;
+    if packet[0] >> 4 != 4 || ihl < IP_HEADER_LEN || packet.len() < ihl {
+        return None;
+    }
+    let len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    (len >= ihl).then_some((ihl, len.min(packet.len())))
+}
+
+fn addrs(packet: &[u8]) -> (Ipv4Addr, Ipv4Addr) {
+    (Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]),
+     Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]))
+}
@@ -0,0 +1,100 @@
+//! The io types of the devices and errors of the crate
+//!
+//! with the `std` feature they are the ones of `std::io`. without it an error is its kind and
+//! a static message, enough for a driver to say why a frame couldn't be sent
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, IoSlice, Result};
+
+#[cfg(not(feature = "std"))]
+pub use self::bare::{Error, ErrorKind, IoSlice, Result};
+
+#[cfg(not(feature = "std"))]
+mod bare {
+    use core::fmt;
+    use core::ops::Deref;
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// The kinds of `std::io::ErrorKind` the crate uses
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        NotFound,
+        ConnectionRefused,
+        ConnectionReset,
+        ConnectionAborted,
+        NotConnected,
+        AddrInUse,
+        AddrNotAvailable,
+        NetworkDown,
+        BrokenPipe,
+        AlreadyExists,
+        WouldBlock,
+        ResourceBusy,
+        InvalidInput,
+        InvalidData,
+        TimedOut,
+        Interrupted,
+        Unsupported,
+        UnexpectedEof,
+        Other,
+    }
+
+    impl fmt::Display for ErrorKind {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt::Debug::fmt(self, f)
+        }
+    }
+
+    /// An error of a device, `kind` and what happened
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: &'static str,
+    }
+
+    impl Error {
+        pub const fn new(kind: ErrorKind, message: &'static str) -> Self {
+            Self { kind, message }
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Self { kind, message: "" }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.message {
+                "" => write!(f, "{}", self.kind),
+                message => f.write_str(message),
+            }
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    /// A slice of a frame sent in parts, see `DataLayer::send_vectored`
+    #[derive(Debug, Copy, Clone)]
+    pub struct IoSlice<'a>(&'a [u8]);
+
+    impl<'a> IoSlice<'a> {
+        pub fn new(buf: &'a [u8]) -> Self {
+            IoSlice(buf)
+        }
+    }
+
+    impl Deref for IoSlice<'_> {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            self.0
+        }
+    }
+}
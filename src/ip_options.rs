@@ -0,0 +1,140 @@
+//! IPv4 options (RFC 791) of received packets
+//!
+//! the stack sends no options and doesn't act on any, it only looks at them to decide if a
+//! packet is taken at all: source routed packets are dropped unless the configuration says
+//! otherwise (RFC 7126 4.3), the router alert is ignored like a host should (RFC 2113) and
+//! the firewall sees which options a segment carried
+use crate::result::{Error, Result};
+
+pub const KIND_END_OF_LIST: u8 = 0;
+pub const KIND_NOP: u8 = 1;
+pub const KIND_SECURITY: u8 = 130;
+pub const KIND_LOOSE_SOURCE_ROUTE: u8 = 131;
+pub const KIND_RECORD_ROUTE: u8 = 7;
+pub const KIND_STRICT_SOURCE_ROUTE: u8 = 137;
+pub const KIND_TIMESTAMP: u8 = 68;
+pub const KIND_ROUTER_ALERT: u8 = 148;
+
+/// One option of an ipv4 header, the data borrows the header
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IpOption<'a> {
+    /// no-operation, aligns the next option
+    Nop,
+    /// route the sender wants the packet to take, other routers allowed in between
+    LooseSourceRoute(&'a [u8]),
+    /// route the sender wants the packet to take, hop by hop
+    StrictSourceRoute(&'a [u8]),
+    /// addresses of the routers on the way
+    RecordRoute(&'a [u8]),
+    /// timestamps of the routers on the way
+    Timestamp(&'a [u8]),
+    /// routers should look at the packet closer (RFC 2113), 0 for every packet
+    RouterAlert(u16),
+    /// an option this stack doesn't know
+    Unknown { kind: u8, data: &'a [u8] },
+}
+
+impl IpOption<'_> {
+    pub fn kind(&self) -> u8 {
+        match self {
+            IpOption::Nop => KIND_NOP,
+            IpOption::LooseSourceRoute(_) => KIND_LOOSE_SOURCE_ROUTE,
+            IpOption::StrictSourceRoute(_) => KIND_STRICT_SOURCE_ROUTE,
+            IpOption::RecordRoute(_) => KIND_RECORD_ROUTE,
+            IpOption::Timestamp(_) => KIND_TIMESTAMP,
+            IpOption::RouterAlert(_) => KIND_ROUTER_ALERT,
+            IpOption::Unknown { kind, .. } => *kind,
+        }
+    }
+}
+
+/// iterate over the options of an ipv4 header, `Ipv4HeaderSlice::options`
+pub fn parse(raw: &[u8]) -> IpOptions<'_> {
+    IpOptions { raw }
+}
+
+/// Iterator over the options of an ipv4 header, it stops at the end of option list
+/// and after the first malformed option
+#[derive(Debug, Clone)]
+pub struct IpOptions<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> IpOptions<'a> {
+    fn next_option(&mut self) -> Result<Option<IpOption<'a>>> {
+        let kind = match self.raw.first() {
+            None | Some(&KIND_END_OF_LIST) => return Ok(None),
+            Some(&kind) => kind,
+        };
+        if kind == KIND_NOP {
+            self.raw = &self.raw[1..];
+            return Ok(Some(IpOption::Nop));
+        }
+        let len = match self.raw.get(1) {
+            Some(&len) if len >= 2 && len as usize <= self.raw.len() => len as usize,
+            _ => return Err(Error::InvalidOption(kind)),
+        };
+        let data = &self.raw[2..len];
+        self.raw = &self.raw[len..];
+        let option = match (kind, data.len()) {
+            // a pointer and at least one address
+            (KIND_LOOSE_SOURCE_ROUTE, n) if n >= 1 => IpOption::LooseSourceRoute(data),
+            (KIND_STRICT_SOURCE_ROUTE, n) if n >= 1 => IpOption::StrictSourceRoute(data),
+            (KIND_RECORD_ROUTE, n) if n >= 1 => IpOption::RecordRoute(data),
+            (KIND_TIMESTAMP, n) if n >= 2 => IpOption::Timestamp(data),
+            (KIND_ROUTER_ALERT, 2) => IpOption::RouterAlert(u16::from_be_bytes([data[0], data[1]])),
+            (KIND_LOOSE_SOURCE_ROUTE, _) | (KIND_STRICT_SOURCE_ROUTE, _) | (KIND_RECORD_ROUTE, _)
+            | (KIND_TIMESTAMP, _) | (KIND_ROUTER_ALERT, _) => return Err(Error::InvalidOption(kind)),
+            _ => IpOption::Unknown { kind, data },
+        };
+        Ok(Some(option))
+    }
+}
+
+impl<'a> Iterator for IpOptions<'a> {
+    type Item = Result<IpOption<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let option = self.next_option();
+        if option.is_err() {
+            self.raw = &[];
+        }
+        option.transpose()
+    }
+}
+
+/// The options a header carries as a set of option numbers, the lower five bits of
+/// the kind, which is what the firewall gets to see
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct IpOptionSet(u32);
+
+impl IpOptionSet {
+    /// the options of the header `raw`, an error if one is malformed
+    pub fn of(raw: &[u8]) -> Result<Self> {
+        let mut set = Self::default();
+        for option in parse(raw) {
+            match option? {
+                IpOption::Nop => {}
+                option => set.insert(option.kind()),
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn insert(&mut self, kind: u8) {
+        self.0 |= 1 << (kind & 0x1f);
+    }
+
+    pub fn contains(&self, kind: u8) -> bool {
+        self.0 & 1 << (kind & 0x1f) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// a loose or strict source route
+    pub fn is_source_routed(&self) -> bool {
+        self.contains(KIND_LOOSE_SOURCE_ROUTE) || self.contains(KIND_STRICT_SOURCE_ROUTE)
+    }
+}
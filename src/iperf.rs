@@ -0,0 +1,637 @@
+//! Throughput tests with iperf3 (TCP only) over the socket API
+//!
+//! speaks the control protocol of iperf3: the client sends a cookie and the parameters of the
+//! test on a control connection, opens the data connections, each starting with the cookie,
+//! and tells the server when the time is up, then both ends exchange their byte counts as JSON.
+//! `serve` runs the tests of `iperf3 -c` on the host, `run` measures against `iperf3 -s`.
+//! UDP, SCTP, bidirectional tests and the interval reports aren't supported
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::SocketAddrV4;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::rng::Rng;
+use crate::socket::{TcpListener, TcpStream};
+use crate::stack::NetStack;
+
+pub const DEFAULT_PORT: u16 = 5201;
+/// bytes written at once, the default of iperf3 for TCP
+pub const DEFAULT_LEN: usize = 128 * 1024;
+pub const DEFAULT_TIME: Duration = Duration::from_secs(10);
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 36 characters and a NUL
+const COOKIE_SIZE: usize = 37;
+const COOKIE_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+/// larger parameter or result messages are refused
+const MAX_JSON: usize = 64 * 1024;
+const MAX_LEN: usize = 1 << 20;
+/// how often blocked data connections check if the test is over
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// states sent on the control connection, a signed byte
+const TEST_START: u8 = 1;
+const TEST_RUNNING: u8 = 2;
+const TEST_END: u8 = 4;
+const PARAM_EXCHANGE: u8 = 9;
+const CREATE_STREAMS: u8 = 10;
+const SERVER_TERMINATE: u8 = 11;
+const CLIENT_TERMINATE: u8 = 12;
+const EXCHANGE_RESULTS: u8 = 13;
+const DISPLAY_RESULTS: u8 = 14;
+const IPERF_DONE: u8 = 16;
+const ACCESS_DENIED: u8 = -1i8 as u8;
+const SERVER_ERROR: u8 = -2i8 as u8;
+
+/// What the client asks for
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Params {
+    /// data connections
+    pub parallel: usize,
+    /// how long the client lets the data flow, iperf3 only knows whole seconds
+    pub time: Duration,
+    /// bytes written at once
+    pub len: usize,
+    /// the server sends and the client receives
+    pub reverse: bool,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            parallel: 1,
+            time: DEFAULT_TIME,
+            len: DEFAULT_LEN,
+            reverse: false,
+        }
+    }
+}
+
+impl Params {
+    fn to_json(self) -> String {
+        let secs = self.time.as_secs() + (self.time.subsec_nanos() > 0) as u64;
+        let reverse = if self.reverse { ",\"reverse\":true" } else { "" };
+        format!(
+            "{{\"tcp\":true,\"omit\":0,\"time\":{},\"num\":0,\"blockcount\":0,\"parallel\":{},\"len\":{}{},\
+             \"pacing_timer\":1000,\"client_version\":\"3.9\"}}",
+            secs, self.parallel, self.len, reverse,
+        )
+    }
+
+    /// the parameters of a TCP test, `None` for anything else
+    fn from_json(json: &Json) -> Option<Self> {
+        if !json.flag("tcp") || json.flag("udp") || json.flag("sctp") || json.flag("bidirectional") {
+            return None;
+        }
+        Some(Self {
+            parallel: json.count("parallel").unwrap_or(1).clamp(1, 128) as usize,
+            time: Duration::from_secs(json.count("time").unwrap_or(DEFAULT_TIME.as_secs())),
+            len: json.count("len").map_or(DEFAULT_LEN, |len| len.clamp(1, MAX_LEN as u64) as usize),
+            reverse: json.flag("reverse"),
+        })
+    }
+}
+
+/// Bytes one end of a test sent or received on all its data connections
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Report {
+    pub bytes: u64,
+    pub duration: Duration,
+    /// segments retransmitted, only known by the sender
+    pub retransmits: u64,
+}
+
+impl Report {
+    pub fn bits_per_second(&self) -> f64 {
+        self.bytes as f64 * 8.0 / self.duration.as_secs_f64().max(1e-9)
+    }
+
+    /// the results message of the `streams` this report sums up
+    fn to_json(streams: &[Report]) -> String {
+        let streams: Vec<String> = streams.iter()
+            .enumerate()
+            .map(|(i, stream)| format!(
+                "{{\"id\":{},\"bytes\":{},\"retransmits\":{},\"jitter\":0,\"errors\":0,\"packets\":0,\
+                 \"start_time\":0,\"end_time\":{}}}",
+                stream_id(i), stream.bytes, stream.retransmits, stream.duration.as_secs_f64(),
+            ))
+            .collect();
+        format!(
+            "{{\"cpu_util_total\":0,\"cpu_util_user\":0,\"cpu_util_system\":0,\"sender_has_retransmits\":1,\
+             \"streams\":[{}]}}",
+            streams.join(","),
+        )
+    }
+
+    /// the sums over the streams of the results of the other end
+    fn from_json(json: &Json) -> Self {
+        let streams = match json.get("streams") {
+            Some(Json::Array(streams)) => streams.as_slice(),
+            _ => &[],
+        };
+        // iperf3 says -1 retransmits when it doesn't know
+        let sum = |key| streams.iter().filter_map(|stream| stream.count(key)).sum::<u64>();
+        let end = streams.iter().filter_map(|stream| stream.number("end_time")).filter(|end| end.is_finite()).fold(0.0, f64::max);
+        Self {
+            bytes: sum("bytes"),
+            duration: Duration::from_secs_f64(end.min(u32::MAX as f64)),
+            retransmits: sum("retransmits"),
+        }
+    }
+}
+
+/// Both directions of a test as one end saw it, the other end's counts come from its results
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Outcome {
+    pub sent: Report,
+    pub received: Report,
+}
+
+/// run the test of the next client connecting to `listener`, connections with the cookie of
+/// another test are closed
+pub fn serve(listener: &TcpListener) -> Result<Outcome> {
+    let control = listener.accept()?;
+    control.set_nodelay(true)?;
+    let cookie = read_cookie(&control)?;
+    send_state(&control, PARAM_EXCHANGE)?;
+    let params = match Params::from_json(&read_json(&control)?) {
+        Some(params) => params,
+        None => {
+            send_state(&control, ACCESS_DENIED)?;
+            return Err(Error::new(ErrorKind::Unsupported, "only TCP tests in one direction"));
+        }
+    };
+    send_state(&control, CREATE_STREAMS)?;
+    let mut streams = Vec::with_capacity(params.parallel);
+    while streams.len() < params.parallel {
+        let stream = listener.accept()?;
+        if read_cookie(&stream)? == cookie {
+            streams.push(stream);
+        }
+    }
+    send_state(&control, TEST_START)?;
+    send_state(&control, TEST_RUNNING)?;
+    let test = Test::start(streams, params.len, !params.reverse);
+    loop {
+        match read_state(&control)? {
+            TEST_END => break,
+            CLIENT_TERMINATE => return Err(Error::new(ErrorKind::ConnectionAborted, "the client ended the test")),
+            _ => {}
+        }
+    }
+    let (local, reports, streams) = test.stop()?;
+    send_state(&control, EXCHANGE_RESULTS)?;
+    let remote = Report::from_json(&read_json(&control)?);
+    write_json(&control, &Report::to_json(&reports))?;
+    send_state(&control, DISPLAY_RESULTS)?;
+    // the client may close without saying it's done
+    let _ = read_state(&control);
+    close(&control, &streams)?;
+    Ok(outcome(local, remote, !params.reverse))
+}
+
+/// a test against the iperf3 server at `addr` from `stack`
+pub fn run(stack: &NetStack, addr: SocketAddrV4, params: Params) -> Result<Outcome> {
+    let control = TcpStream::connect_timeout(stack, addr, CONNECT_TIMEOUT)?;
+    control.set_nodelay(true)?;
+    let cookie = make_cookie();
+    (&control).write_all(&cookie)?;
+    let mut streams = Vec::with_capacity(params.parallel);
+    let mut local = None;
+    let mut results = None;
+    loop {
+        match read_state(&control)? {
+            PARAM_EXCHANGE => write_json(&control, &params.to_json())?,
+            CREATE_STREAMS => {
+                for _ in 0..params.parallel {
+                    let stream = TcpStream::connect_timeout(stack, addr, CONNECT_TIMEOUT)?;
+                    (&stream).write_all(&cookie)?;
+                    streams.push(stream);
+                }
+            }
+            TEST_START => {}
+            TEST_RUNNING => {
+                let test = Test::start(std::mem::take(&mut streams), params.len, params.reverse);
+                thread::sleep(params.time);
+                let (report, reports, done) = test.stop()?;
+                local = Some((report, reports));
+                streams = done;
+                send_state(&control, TEST_END)?;
+            }
+            EXCHANGE_RESULTS => {
+                let (report, reports) = local.take().ok_or_else(|| protocol("results before the test"))?;
+                write_json(&control, &Report::to_json(&reports))?;
+                let remote = Report::from_json(&read_json(&control)?);
+                results = Some(outcome(report, remote, params.reverse));
+            }
+            DISPLAY_RESULTS => {
+                send_state(&control, IPERF_DONE)?;
+                close(&control, &streams)?;
+                return results.ok_or_else(|| protocol("results never exchanged"));
+            }
+            ACCESS_DENIED => return Err(Error::new(ErrorKind::PermissionDenied, "the server is busy")),
+            SERVER_ERROR => {
+                let mut codes = [0; 8];
+                (&control).read_exact(&mut codes)?;
+                let code = i32::from_be_bytes([codes[0], codes[1], codes[2], codes[3]]);
+                return Err(Error::other(format!("iperf3 server error {}", code)));
+            }
+            SERVER_TERMINATE => return Err(Error::new(ErrorKind::ConnectionAborted, "the server ended the test")),
+            _ => {}
+        }
+    }
+}
+
+/// `local` is what this end received when `receiver`
+fn outcome(local: Report, remote: Report, receiver: bool) -> Outcome {
+    match receiver {
+        true => Outcome { sent: remote, received: local },
+        false => Outcome { sent: local, received: remote },
+    }
+}
+
+fn close(control: &TcpStream, streams: &[TcpStream]) -> Result<()> {
+    for stream in streams {
+        let _ = stream.shutdown();
+    }
+    control.shutdown()
+}
+
+/// The data connections of a running test, each one sending or receiving on its own thread
+struct Test {
+    stop: Arc<AtomicBool>,
+    start: Instant,
+    workers: Vec<thread::JoinHandle<Result<(u64, TcpStream)>>>,
+    receive: bool,
+}
+
+impl Test {
+    fn start(streams: Vec<TcpStream>, len: usize, receive: bool) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let workers = streams.into_iter()
+            .map(|stream| {
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    let bytes = match receive {
+                        true => receive_until(&stream, &stop, len)?,
+                        false => send_until(&stream, &stop, len)?,
+                    };
+                    Ok((bytes, stream))
+                })
+            })
+            .collect();
+        Self { stop, start: Instant::now(), workers, receive }
+    }
+
+    /// end the test: the report of this end, the ones of every connection and the connections
+    fn stop(mut self) -> Result<(Report, Vec<Report>, Vec<TcpStream>)> {
+        self.stop.store(true, Ordering::Release);
+        let duration = self.start.elapsed();
+        let mut total = Report { bytes: 0, duration, retransmits: 0 };
+        let mut reports = Vec::with_capacity(self.workers.len());
+        let mut streams = Vec::with_capacity(self.workers.len());
+        for worker in std::mem::take(&mut self.workers) {
+            let (bytes, stream) = worker.join().expect("iperf stream thread panicked")?;
+            let retransmits = if self.receive { 0 } else { stream.stats()?.retransmits };
+            total.bytes += bytes;
+            total.retransmits += retransmits;
+            reports.push(Report { bytes, duration, retransmits });
+            streams.push(stream);
+        }
+        Ok((total, reports, streams))
+    }
+}
+
+impl Drop for Test {
+    /// the control connection failed, the workers end with the test
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+    }
+}
+
+fn receive_until(stream: &TcpStream, stop: &AtomicBool, len: usize) -> Result<u64> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut buf = vec![0; len];
+    let mut bytes = 0;
+    while !stop.load(Ordering::Acquire) {
+        match (&*stream).read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => bytes += n as u64,
+            Err(ref e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(bytes)
+}
+
+fn send_until(stream: &TcpStream, stop: &AtomicBool, len: usize) -> Result<u64> {
+    stream.set_write_timeout(Some(POLL_INTERVAL))?;
+    let buf = vec![0x5a; len];
+    let (mut bytes, mut at) = (0, 0);
+    while !stop.load(Ordering::Acquire) {
+        match (&*stream).write(&buf[at..]) {
+            Ok(n) => {
+                bytes += n as u64;
+                at = (at + n) % len;
+            }
+            Err(ref e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(bytes)
+}
+
+/// iperf3 numbers the streams 1, 3, 4, ... and matches the results by it
+fn stream_id(i: usize) -> usize {
+    match i {
+        0 => 1,
+        i => i + 2,
+    }
+}
+
+fn make_cookie() -> [u8; COOKIE_SIZE] {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_nanos() as u64).unwrap_or(0);
+    let mut rng = Rng::new(nanos);
+    let mut cookie = [0; COOKIE_SIZE];
+    for c in &mut cookie[..COOKIE_SIZE - 1] {
+        *c = COOKIE_CHARS[rng.below(COOKIE_CHARS.len())];
+    }
+    cookie
+}
+
+fn read_cookie(stream: &TcpStream) -> Result<[u8; COOKIE_SIZE]> {
+    let mut cookie = [0; COOKIE_SIZE];
+    (&*stream).read_exact(&mut cookie)?;
+    Ok(cookie)
+}
+
+fn send_state(control: &TcpStream, state: u8) -> Result<()> {
+    (&*control).write_all(&[state])
+}
+
+fn read_state(control: &TcpStream) -> Result<u8> {
+    let mut state = [0];
+    (&*control).read_exact(&mut state)?;
+    Ok(state[0])
+}
+
+/// a JSON message after its length, 4 bytes in network byte order
+fn read_json(control: &TcpStream) -> Result<Json> {
+    let mut len = [0; 4];
+    (&*control).read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_JSON {
+        return Err(protocol("JSON message too large"));
+    }
+    let mut json = vec![0; len];
+    (&*control).read_exact(&mut json)?;
+    let json = String::from_utf8(json).map_err(|_| protocol("JSON message isn't UTF-8"))?;
+    Json::parse(&json)
+}
+
+fn write_json(control: &TcpStream, json: &str) -> Result<()> {
+    let mut message = Vec::with_capacity(4 + json.len());
+    message.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    message.extend_from_slice(json.as_bytes());
+    (&*control).write_all(&message)
+}
+
+/// A JSON value as iperf3 sends it, objects keep their members in order
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser { text: text.as_bytes(), at: 0 };
+        let value = parser.value(0)?;
+        parser.space();
+        match parser.at == text.len() {
+            true => Ok(value),
+            false => Err(protocol("trailing characters after the JSON value")),
+        }
+    }
+
+    /// the member `key` of an object
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        self.get(key) == Some(&Json::Bool(true))
+    }
+
+    fn number(&self, key: &str) -> Option<f64> {
+        match self.get(key) {
+            Some(Json::Number(number)) => Some(*number),
+            _ => None,
+        }
+    }
+
+    /// a whole number that isn't negative
+    fn count(&self, key: &str) -> Option<u64> {
+        self.number(key).filter(|number| *number >= 0.0 && number.fract() == 0.0).map(|number| number as u64)
+    }
+}
+
+/// Recursive descent over the bytes of a JSON text
+struct Parser<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+
+impl Parser<'_> {
+    /// arrays and objects nested deeper are refused rather than growing the stack
+    const MAX_DEPTH: usize = 32;
+
+    fn value(&mut self, depth: usize) -> Result<Json> {
+        if depth > Self::MAX_DEPTH {
+            return Err(protocol("JSON nested too deep"));
+        }
+        self.space();
+        match self.peek() {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            _ => Err(protocol("expected a JSON value")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Json> {
+        self.at += 1;
+        let mut members = Vec::new();
+        self.space();
+        if self.eat(b'}') {
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.space();
+            if self.peek() != Some(b'"') {
+                return Err(protocol("expected the name of a JSON member"));
+            }
+            let name = self.string()?;
+            self.space();
+            if !self.eat(b':') {
+                return Err(protocol("expected ':' after the name of a JSON member"));
+            }
+            members.push((name, self.value(depth + 1)?));
+            self.space();
+            if self.eat(b'}') {
+                return Ok(Json::Object(members));
+            }
+            if !self.eat(b',') {
+                return Err(protocol("expected ',' or '}' in a JSON object"));
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Json> {
+        self.at += 1;
+        let mut values = Vec::new();
+        self.space();
+        if self.eat(b']') {
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value(depth + 1)?);
+            self.space();
+            if self.eat(b']') {
+                return Ok(Json::Array(values));
+            }
+            if !self.eat(b',') {
+                return Err(protocol("expected ',' or ']' in a JSON array"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.at += 1;
+        let mut string = Vec::new();
+        loop {
+            match self.next() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let unescaped = match self.next() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode()?,
+                        _ => return Err(protocol("invalid escape in a JSON string")),
+                    };
+                    string.extend_from_slice(unescaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(byte) if byte >= 0x20 => string.push(byte),
+                _ => return Err(protocol("unterminated JSON string")),
+            }
+        }
+        // the text is UTF-8 and escapes are pushed encoded
+        String::from_utf8(string).map_err(|_| protocol("JSON message isn't UTF-8"))
+    }
+
+    /// the code point after `\u`, a surrogate pair takes two escapes, lone surrogates
+    /// become U+FFFD
+    fn unicode(&mut self) -> Result<char> {
+        let high = self.hex()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return Ok(char::from_u32(high).unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+        if !self.text[self.at..].starts_with(b"\\u") {
+            return Ok(char::REPLACEMENT_CHARACTER);
+        }
+        self.at += 2;
+        let low = self.hex()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Ok(char::REPLACEMENT_CHARACTER);
+        }
+        Ok(char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex(&mut self) -> Result<u32> {
+        let digits = self.text.get(self.at..self.at + 4).ok_or_else(|| protocol("short \\u escape in a JSON string"))?;
+        let digits = std::str::from_utf8(digits).map_err(|_| protocol("invalid \\u escape in a JSON string"))?;
+        let value = u32::from_str_radix(digits, 16).map_err(|_| protocol("invalid \\u escape in a JSON string"))?;
+        self.at += 4;
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.at;
+        self.eat(b'-');
+        let digits = |parser: &mut Self| {
+            let from = parser.at;
+            while let Some(b'0'..=b'9') = parser.peek() {
+                parser.at += 1;
+            }
+            parser.at > from
+        };
+        let mut valid = digits(self);
+        if self.eat(b'.') {
+            valid &= digits(self);
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            if !self.eat(b'+') {
+                self.eat(b'-');
+            }
+            valid &= digits(self);
+        }
+        let number = std::str::from_utf8(&self.text[start..self.at]).ok().filter(|_| valid).and_then(|number| number.parse().ok());
+        number.map(Json::Number).ok_or_else(|| protocol("invalid JSON number"))
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json> {
+        match self.text[self.at..].starts_with(literal.as_bytes()) {
+            true => {
+                self.at += literal.len();
+                Ok(value)
+            }
+            false => Err(protocol("expected a JSON value")),
+        }
+    }
+
+    fn space(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.at += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let eaten = self.peek() == Some(byte);
+        self.at += eaten as usize;
+        eaten
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.at).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek();
+        self.at += byte.is_some() as usize;
+        byte
+    }
+}
+
+fn protocol(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
@@ -1,15 +1,93 @@
+//! A userspace tcp/ip stack
+//!
+//! with the default `std` feature the stack runs on TUN interfaces and other devices with its
+//! own threads and sockets. without it the crate is `no_std` + `alloc`: there is no stack, no
+//! threads and no system clock, but `tcp::connection::TcpConnection` with its packets,
+//! congestion control and buffers runs on a `data_link::DataLayer` of the caller. the caller
+//! feeds it the received segments, calls `on_timeout` at its `next_timeout` and gives it a
+//! `clock::Clock` reading a timer of the device with `set_clock`, until then time stands still.
+//! observers, the rate limit shared by a stack, checkpoints, TCP-AO and MPTCP need `std`. CI
+//! builds the crate with `cargo build --no-default-features --target thumbv7em-none-eabihf`
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 #[macro_use]
-extern crate log;
+extern crate tracing;
+#[cfg(feature = "std")]
 extern crate pretty_env_logger;
 
 pub mod net_types;
 pub mod tcp;
 pub mod data_link;
 pub mod result;
+pub mod io;
+pub mod time;
+#[cfg(feature = "std")]
 pub mod reader_writer;
+pub mod socket_addr;
 pub mod meta;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod timer;
+#[cfg(feature = "std")]
+pub mod table;
+#[cfg(feature = "std")]
+pub mod metrics;
+pub mod netstat;
+#[cfg(feature = "std")]
+pub mod capture;
+pub mod observer;
+#[cfg(feature = "std")]
+pub mod firewall;
+#[cfg(feature = "std")]
+pub mod nat;
+#[cfg(feature = "std")]
+pub mod router;
+#[cfg(feature = "std")]
+pub mod buffer;
+pub mod checksum;
+pub mod ip_options;
+#[cfg(feature = "std")]
+pub mod icmp;
+#[cfg(feature = "std")]
+pub mod udp;
+#[cfg(feature = "std")]
+mod rng;
+#[cfg(all(unix, feature = "std"))]
+pub mod event_fd;
+#[cfg(all(unix, feature = "std"))]
+pub mod event_loop;
+#[cfg(all(unix, feature = "std"))]
+pub mod stack;
+#[cfg(all(unix, feature = "std"))]
+pub mod socket;
+#[cfg(all(unix, feature = "std"))]
+pub mod http;
+#[cfg(all(unix, feature = "std"))]
+pub mod iperf;
+#[cfg(all(unix, feature = "std"))]
+pub mod dns;
+#[cfg(all(unix, feature = "std"))]
+pub mod proxy;
+#[cfg(all(unix, feature = "std"))]
+pub mod bridge;
+#[cfg(all(feature = "capi", any(target_os = "linux", all(target_os = "macos", feature = "utun"))))]
+pub mod ffi;
+#[cfg(all(unix, feature = "mptcp"))]
+pub mod mptcp;
+#[cfg(all(unix, feature = "sim"))]
+pub mod sim;
+#[cfg(all(unix, feature = "diagram"))]
+pub mod diagram;
+#[cfg(all(feature = "std", any(fuzzing, feature = "fuzz")))]
+pub mod fuzz;
+#[cfg(feature = "testing")]
+pub mod testing;
 
+#[cfg(feature = "std")]
 pub fn init_log() {
     pretty_env_logger::init();
     debug!("start logging");
-}
\ No newline at end of file
+}
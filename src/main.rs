@@ -1,43 +1,543 @@
+//! Command line tool running the stack on a TUN interface, its commands double as
+//! examples of the socket API
+//!
+//! ```text
+//! tcp-stack listen --port 8080          echo server, `--sink` discards and reports the throughput
+//! tcp-stack connect 192.168.3.1:8080    interactive client, stdin lines are sent
+//! tcp-stack ping 192.168.3.1:22         round-trip time of the handshake
+//! tcp-stack nc 192.168.3.1:5001 < file  netcat, `nc --listen 5001 > file` on the other end
+//! tcp-stack http --port 8080           static pages for an HTTP benchmark
+//! tcp-stack iperf                       server for `iperf3 -c 192.168.3.2` on the host
+//! tcp-stack iperf 192.168.3.1:5201 -P 4 client of `iperf3 -s`, `-R` to receive
+//! tcp-stack proxy --port 8080 --to 127.0.0.1:80
+//! tcp-stack gateway                     forward every connection to its destination
+//! tcp-stack gateway --socks5 1080       SOCKS5 proxy
+//! tcp-stack exec --port 7 -- cat        inetd, the program serves each connection on stdin/stdout
+//! ```
+//!
+//! the interface gets the host address and is brought up by the stack itself,
+//! opening it needs CAP_NET_ADMIN. SIGINT or SIGTERM shut the stack down gracefully,
+//! the connections get `--shutdown-grace` seconds to close, a second signal exits right away.
+//! SIGUSR2 lowers the log level a step and SIGUSR1 raises it, up to what `RUST_LOG` enables
+
 extern crate tcp_stack;
 
-use std::collections::HashMap;
-use std::env;
+use std::io::{self, BufRead, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use tun_tap::{self, Iface};
+use clap::{Args, Parser, Subcommand};
 
-use tcp_stack::meta::{ETHERNET_MTU, TUN_SIZE};
-use tcp_stack::reader_writer::{Quad, RawReader};
+use tcp_stack::bridge;
+use tcp_stack::config::{Reconfigure, StackConfig, DEFAULT_INTERFACE};
+use tcp_stack::http::HttpServer;
+use tcp_stack::iperf::{self, Outcome, Params};
+use tcp_stack::proxy::{relay, Gateway, Mode};
 use tcp_stack::result;
-use tcp_stack::tcp::connection::TcpConnection;
+use tcp_stack::socket::{TcpListener, TcpStream};
+use tcp_stack::stack::NetStack;
+#[cfg(feature = "tcp-ao")]
+use tcp_stack::tcp::ao::Mkt;
+use tcp_stack::tcp::congestion::{CongestionAlgorithm, DEFAULT_INITIAL_WINDOW};
+use tcp_stack::tcp::shaper::RateLimit;
+
+#[derive(Parser)]
+#[command(name = "tcp-stack", about = "a userspace tcp stack on a TUN interface")]
+struct Cli {
+    #[command(flatten)]
+    stack: StackArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct StackArgs {
+    /// TUN interface to open or create
+    #[arg(long, global = true, default_value = DEFAULT_INTERFACE)]
+    interface: String,
+    /// address of the stack
+    #[arg(long, global = true, default_value = "192.168.3.2")]
+    addr: Ipv4Addr,
+    /// address/prefix of the kernel side of the interface
+    #[arg(long, global = true, default_value = "192.168.3.1/24", value_parser = parse_subnet)]
+    host: (Ipv4Addr, u8),
+    #[arg(long, global = true, default_value_t = 1500)]
+    mtu: usize,
+    /// queues of the interface, each one served by its own thread
+    #[arg(long, global = true, default_value_t = 1)]
+    queues: usize,
+    /// tables the connections are split into, one per queue by default
+    #[arg(long, global = true)]
+    shards: Option<usize>,
+    /// congestion control of the connections, newreno or bbr (paced)
+    #[arg(long, global = true, default_value = "newreno", value_parser = parse_congestion)]
+    congestion: CongestionAlgorithm,
+    /// full segments the connections start sending with
+    #[arg(long, global = true, default_value_t = DEFAULT_INITIAL_WINDOW, value_name = "SEGMENTS")]
+    initial_window: usize,
+    /// build large segments and cut them at the mss in the stack (GSO)
+    #[arg(long, global = true)]
+    gso: bool,
+    /// merge consecutive segments of a connection before processing them (GRO)
+    #[arg(long, global = true)]
+    gro: bool,
+    /// open the interface with the packet information header (without IFF_NO_PI)
+    #[arg(long, global = true)]
+    packet_info: bool,
+    /// cap the bytes per second all connections send together, with bursts of 100ms
+    #[arg(long, global = true, value_name = "BYTES_PER_SEC")]
+    egress_limit: Option<u64>,
+    /// probe the connections idle for this many seconds
+    #[arg(long, global = true, value_name = "SECS")]
+    keep_alive: Option<u64>,
+    /// abort the connections whose data stays unacknowledged this many seconds,
+    /// advertised to the peers with the user timeout option
+    #[arg(long, global = true, value_name = "SECS")]
+    user_timeout: Option<u64>,
+    /// open the interface again every this many milliseconds after it went away,
+    /// instead of failing the connections
+    #[arg(long, global = true, value_name = "MILLIS")]
+    reopen: Option<u64>,
+    /// seconds the connections get to close after SIGINT or SIGTERM before they are reset
+    #[arg(long, global = true, default_value_t = 5, value_name = "SECS")]
+    shutdown_grace: u64,
+    /// sign the connections with a TCP-AO key, ALGORITHM:SENDID:RECVID:SECRET@PEER[/PREFIX] with
+    /// hmac-sha1, aes-128-cmac or hmac-sha256 and a 0x prefix for a hex secret. repeat it for
+    /// more keys, the first one of a peer is used until it asks for another
+    #[arg(long = "ao-key", global = true, value_name = "KEY")]
+    #[cfg(feature = "tcp-ao")]
+    ao_keys: Vec<Mkt>,
+}
 
-fn main() -> result::Result<()> {
-    env::set_var("RUST_LOG", "debug");
+#[derive(Subcommand)]
+enum Command {
+    /// accept connections and echo what they send
+    Listen {
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// discard the data and report the throughput instead of echoing
+        #[arg(long)]
+        sink: bool,
+    },
+    /// connect to a peer, send the lines of stdin and print what arrives
+    Connect {
+        #[arg(value_name = "ADDR")]
+        peer: SocketAddrV4,
+    },
+    /// measure the round-trip time of handshakes with a peer, the stack answers ICMP echo
+    /// requests but sends none of its own
+    Ping {
+        #[arg(value_name = "ADDR")]
+        peer: SocketAddrV4,
+        #[arg(long, default_value_t = 4)]
+        count: u32,
+        /// milliseconds between the probes
+        #[arg(long, default_value_t = 1000)]
+        interval: u64,
+    },
+    /// netcat: bridge stdin and stdout with a connection to a peer or, with `--listen`,
+    /// with the first connection accepted on `port`. stdin EOF sends FIN and the
+    /// peer's FIN closes stdout, the other direction goes on until it's closed too
+    Nc {
+        #[arg(value_name = "ADDR", required_unless_present = "listen")]
+        peer: Option<SocketAddrV4>,
+        /// wait for a connection on this port instead of connecting
+        #[arg(short, long, value_name = "PORT", conflicts_with = "peer")]
+        listen: Option<u16>,
+    },
+    /// serve `/` and a `/blob` of `--blob-size` bytes over HTTP/1.1 with keep-alive
+    Http {
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        #[arg(long, default_value_t = 64 * 1024)]
+        blob_size: usize,
+    },
+    /// throughput test with iperf3: serve the tests of `iperf3 -c` on `port` or, with an
+    /// address, run one against `iperf3 -s`
+    Iperf {
+        #[arg(value_name = "ADDR")]
+        server: Option<SocketAddrV4>,
+        #[arg(long, default_value_t = iperf::DEFAULT_PORT, conflicts_with = "server")]
+        port: u16,
+        /// seconds the data flows
+        #[arg(short, long, default_value_t = iperf::DEFAULT_TIME.as_secs())]
+        time: u64,
+        /// data connections
+        #[arg(short = 'P', long, default_value_t = 1)]
+        parallel: usize,
+        /// bytes written at once
+        #[arg(short, long, default_value_t = iperf::DEFAULT_LEN)]
+        len: usize,
+        /// the server sends
+        #[arg(short = 'R', long)]
+        reverse: bool,
+    },
+    /// accept connections on the stack and forward them to `to` through the host's tcp
+    Proxy {
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        #[arg(long)]
+        to: std::net::SocketAddr,
+    },
+    /// forward the connections to any address routed to the interface to their destination
+    /// through the host's tcp, or with `--socks5` to the destination a SOCKS5 client asks for
+    Gateway {
+        /// serve SOCKS5 on this port instead of forwarding transparently
+        #[arg(long, value_name = "PORT")]
+        socks5: Option<u16>,
+    },
+    /// run a program for every connection accepted on `port` with the connection as its
+    /// stdin and stdout, the addresses are in TCPREMOTEIP, TCPREMOTEPORT, TCPLOCALIP and
+    /// TCPLOCALPORT like with tcpserver
+    Exec {
+        #[arg(long)]
+        port: u16,
+        #[arg(value_name = "PROGRAM", required = true, trailing_var_arg = true)]
+        program: Vec<String>,
+    },
+}
+
+/// how long connect and ping wait for the handshake
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// a signal started the shutdown, the commands failing meanwhile wait for it to end the process
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+fn main() {
     tcp_stack::init_log();
-    // let mut status: HashMap<Quad, TcpConnection> = HashMap::new();
-    // do we need IFF_NO_PI?
-    let mut iface = Iface::new("tcp0", tun_tap::Mode::Tun)?;
-    // MTU 1500
-    let mut mtu_buf = [0_u8; ETHERNET_MTU];
+    let cli = Cli::parse();
+    if let Err(e) = run(cli) {
+        if SHUTTING_DOWN.load(Ordering::Acquire) {
+            loop {
+                thread::park();
+            }
+        }
+        eprintln!("tcp-stack: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> result::Result<()> {
+    let transparent = matches!(cli.command, Command::Gateway { socks5: None });
+    // blocked before the stack starts its threads, they inherit the mask
+    let signals = block_signals()?;
+    let stack = Arc::new(open(&cli.stack, transparent)?);
+    handle_signals(signals, stack.clone(), Duration::from_secs(cli.stack.shutdown_grace))?;
+    match cli.command {
+        Command::Listen { port, sink } => listen(&stack, port, sink),
+        Command::Connect { peer } => connect(&stack, peer),
+        Command::Ping { peer, count, interval } => ping(&stack, peer, count, Duration::from_millis(interval)),
+        Command::Nc { peer, listen } => nc(&stack, peer, listen),
+        Command::Http { port, blob_size } => http(&stack, port, blob_size),
+        Command::Iperf { server, port, time, parallel, len, reverse } => {
+            let params = Params { parallel, time: Duration::from_secs(time), len, reverse };
+            iperf(&stack, server, port, params)
+        }
+        Command::Proxy { port, to } => proxy(&stack, port, to),
+        Command::Gateway { socks5 } => gateway(&stack, socks5),
+        Command::Exec { port, program } => exec(&stack, port, &program),
+    }
+}
+
+fn open(args: &StackArgs, transparent: bool) -> result::Result<NetStack> {
+    let (host, prefix_len) = args.host;
+    let mut config = StackConfig::builder()
+        .interface(args.interface.as_str())
+        .addr(args.addr)
+        .host_addr(host, prefix_len)
+        .mtu(args.mtu)
+        .queues(args.queues)
+        .transparent(transparent)
+        .congestion(args.congestion)
+        .initial_window(args.initial_window)
+        .gso(args.gso)
+        .gro(args.gro)
+        .pacing(matches!(args.congestion, CongestionAlgorithm::Bbr))
+        .keep_alive(args.keep_alive.map(Duration::from_secs))
+        .user_timeout(args.user_timeout.map(Duration::from_secs))
+        .reopen(args.reopen.map(Duration::from_millis))
+        .packet_info(args.packet_info);
+    if let Some(shards) = args.shards {
+        config = config.shards(shards);
+    }
+    if let Some(rate) = args.egress_limit {
+        config = config.egress_limit(RateLimit::new(rate, (rate / 10).max(args.mtu as u64) as usize));
+    }
+    let stack = NetStack::new(config.build()?)?;
+    #[cfg(feature = "tcp-ao")]
+    for mkt in &args.ao_keys {
+        stack.add_auth_key(mkt.clone())?;
+    }
+    Ok(stack)
+}
+
+/// SIGINT, SIGTERM, SIGUSR1 and SIGUSR2, blocked in the calling thread and the ones it starts from now on
+fn block_signals() -> io::Result<libc::sigset_t> {
+    // SAFETY: the set is initialized by sigemptyset before it's used
+    unsafe {
+        let mut signals = std::mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::sigaddset(&mut signals, libc::SIGUSR1);
+        libc::sigaddset(&mut signals, libc::SIGUSR2);
+        match libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut()) {
+            0 => Ok(signals),
+            e => Err(io::Error::from_raw_os_error(e)),
+        }
+    }
+}
+
+/// the first SIGINT or SIGTERM shuts `stack` down and exits, the second one exits right away.
+/// SIGUSR1 and SIGUSR2 change the log level
+fn handle_signals(signals: libc::sigset_t, stack: Arc<NetStack>, grace: Duration) -> io::Result<()> {
+    thread::Builder::new().name("signals".to_string()).spawn(move || loop {
+        let mut signal = 0;
+        // SAFETY: `signals` is an initialized set blocked in every thread
+        unsafe { libc::sigwait(&signals, &mut signal) };
+        match signal {
+            libc::SIGUSR1 | libc::SIGUSR2 => {
+                let level = step_log_level(log::max_level(), signal == libc::SIGUSR1);
+                stack.reconfigure(Reconfigure::new().log_level(level));
+                eprintln!("tcp-stack: log level {}", level);
+            }
+            _ if SHUTTING_DOWN.swap(true, Ordering::AcqRel) => process::exit(130),
+            _ => {
+                eprintln!("tcp-stack: signal {}, closing the connections", signal);
+                let stack = stack.clone();
+                thread::spawn(move || {
+                    let reset = stack.shutdown(grace);
+                    if reset > 0 {
+                        eprintln!("tcp-stack: reset {} connections", reset);
+                    }
+                    process::exit(0);
+                });
+            }
+        }
+    })?;
+    Ok(())
+}
+
+fn step_log_level(level: log::LevelFilter, up: bool) -> log::LevelFilter {
+    use log::LevelFilter::*;
+    let levels = [Off, Error, Warn, Info, Debug, Trace];
+    let i = levels.iter().position(|other| *other == level).unwrap_or(0);
+    if up {
+        levels[(i + 1).min(levels.len() - 1)]
+    } else {
+        levels[i.saturating_sub(1)]
+    }
+}
+
+fn parse_subnet(s: &str) -> Result<(Ipv4Addr, u8), String> {
+    let (addr, prefix_len) = s.split_once('/').unwrap_or((s, "24"));
+    let addr = addr.parse().map_err(|e| format!("{}: {}", addr, e))?;
+    let prefix_len = prefix_len.parse().ok().filter(|len| *len <= 32)
+        .ok_or_else(|| format!("invalid prefix length {}", prefix_len))?;
+    Ok((addr, prefix_len))
+}
+
+fn parse_congestion(s: &str) -> Result<CongestionAlgorithm, String> {
+    match s {
+        "newreno" => Ok(CongestionAlgorithm::NewReno),
+        "bbr" => Ok(CongestionAlgorithm::Bbr),
+        _ => Err(format!("unknown congestion control {}", s)),
+    }
+}
+
+fn listen(stack: &NetStack, port: u16, sink: bool) -> result::Result<()> {
+    let listener = TcpListener::bind(stack, port)?;
+    println!("listening on {}", listener.local_addr());
+    loop {
+        let stream = listener.accept()?;
+        thread::spawn(move || {
+            let peer = stream.peer_addr();
+            let res = if sink { discard(&stream) } else { echo(&stream) };
+            if let Err(e) = res {
+                eprintln!("{}: {}", peer, e);
+            }
+        });
+    }
+}
+
+fn echo(stream: &TcpStream) -> io::Result<()> {
+    let mut reader = stream;
+    let mut writer = stream;
+    io::copy(&mut reader, &mut writer)?;
+    stream.shutdown()?;
+    Ok(())
+}
+
+fn discard(stream: &TcpStream) -> io::Result<()> {
+    let start = Instant::now();
+    let bytes = io::copy(&mut &*stream, &mut io::sink())?;
+    let secs = start.elapsed().as_secs_f64();
+    println!("{}: {} bytes in {:.2}s, {:.2} Mbit/s", stream.peer_addr(), bytes, secs,
+             bytes as f64 * 8.0 / secs.max(1e-9) / 1e6);
+    stream.shutdown()?;
+    Ok(())
+}
+
+fn connect(stack: &NetStack, addr: SocketAddrV4) -> result::Result<()> {
+    let stream = Arc::new(TcpStream::connect_timeout(stack, addr, CONNECT_TIMEOUT)?);
+    println!("connected to {} from {}", addr, stream.local_addr());
+    let reader = {
+        let stream = stream.clone();
+        thread::spawn(move || io::copy(&mut &*stream, &mut io::stdout()))
+    };
+    for line in io::stdin().lock().lines() {
+        let mut line = line?;
+        line.push('\n');
+        (&*stream).write_all(line.as_bytes())?;
+    }
+    stream.shutdown()?;
+    reader.join().expect("reader thread panicked")?;
+    Ok(())
+}
+
+fn nc(stack: &NetStack, peer: Option<SocketAddrV4>, listen: Option<u16>) -> result::Result<()> {
+    let stream = match (peer, listen) {
+        (_, Some(port)) => TcpListener::bind(stack, port)?.accept()?,
+        (Some(peer), None) => TcpStream::connect_timeout(stack, peer, CONNECT_TIMEOUT)?,
+        (None, None) => unreachable!("clap requires an address or --listen"),
+    };
+    let stream = Arc::new(stream);
+    let sender = {
+        let stream = stream.clone();
+        thread::spawn(move || -> io::Result<()> {
+            io::copy(&mut io::stdin().lock(), &mut &*stream)?;
+            stream.shutdown()
+        })
+    };
+    let mut stdout = io::stdout().lock();
+    io::copy(&mut &*stream, &mut stdout)?;
+    stdout.flush()?;
+    // the reader of the pipe sees end of file while stdin may still be sent
+    unsafe { libc::close(libc::STDOUT_FILENO) };
+    sender.join().expect("sender thread panicked")?;
+    // let the peer acknowledge the FIN before the stack goes away
+    let _ = stream.wait_sent(CONNECT_TIMEOUT);
+    Ok(())
+}
+
+fn ping(stack: &NetStack, addr: SocketAddrV4, count: u32, interval: Duration) -> result::Result<()> {
+    let mut times = Vec::new();
+    for seq in 0..count {
+        if seq > 0 {
+            thread::sleep(interval);
+        }
+        let start = Instant::now();
+        let stream = TcpStream::start_connect(stack, addr, stack.default_options())?;
+        match stream.wait(Some(CONNECT_TIMEOUT)) {
+            Ok(stream) => {
+                let rtt = start.elapsed();
+                println!("handshake with {}: seq={} time={:.3} ms", addr, seq, rtt.as_secs_f64() * 1e3);
+                times.push(rtt);
+                // reset instead of closing, like a port scanner
+                stream.set_linger(Some(Duration::from_secs(0)))?;
+            }
+            Err(e) => println!("handshake with {}: seq={} {}", addr, seq, e),
+        }
+    }
+    let ms = |d: Duration| d.as_secs_f64() * 1e3;
+    if let (Some(min), Some(max)) = (times.iter().min(), times.iter().max()) {
+        let avg = times.iter().sum::<Duration>() / times.len() as u32;
+        println!("{} probes, {} answered, min/avg/max = {:.3}/{:.3}/{:.3} ms",
+                 count, times.len(), ms(*min), ms(avg), ms(*max));
+    } else {
+        println!("{} probes, 0 answered", count);
+    }
+    Ok(())
+}
+
+fn http(stack: &NetStack, port: u16, blob_size: usize) -> result::Result<()> {
+    let listener = TcpListener::bind(stack, port)?;
+    println!("serving http on {}", listener.local_addr());
+    let blob: Vec<u8> = (0..blob_size).map(|i| b'a' + (i % 26) as u8).collect();
+    HttpServer::new()
+        .route("/", "text/plain", &b"hello from tcp-stack\n"[..])
+        .route("/blob", "application/octet-stream", blob)
+        .serve(&listener)?;
+    Ok(())
+}
+
+fn iperf(stack: &NetStack, server: Option<SocketAddrV4>, port: u16, params: Params) -> result::Result<()> {
+    let report = |outcome: &Outcome| {
+        let (sent, received) = (outcome.sent, outcome.received);
+        println!("sender   {} bytes in {:.2}s, {:.2} Mbit/s, {} retransmits", sent.bytes,
+                 sent.duration.as_secs_f64(), sent.bits_per_second() / 1e6, sent.retransmits);
+        println!("receiver {} bytes in {:.2}s, {:.2} Mbit/s", received.bytes,
+                 received.duration.as_secs_f64(), received.bits_per_second() / 1e6);
+    };
+    if let Some(server) = server {
+        report(&iperf::run(stack, server, params)?);
+        return Ok(());
+    }
+    let listener = TcpListener::bind(stack, port)?;
+    println!("iperf3 server on {}", listener.local_addr());
     loop {
-        let n = iface.recv(&mut mtu_buf)?;
-        // https://git.kernel.org/pub/scm/linux/kernel/git/torvalds/linux.git/tree/Documentation/networking/tuntap.rst
-        // check tuntap.rst 3.2 Frame format
-        let mut raw = RawReader::from_slice(&mtu_buf, n, TUN_SIZE);
-        if !raw.is_ipv4_packet() {
-            continue;
-        }
-        let (ip_header, tcp_header) = match raw.tcp_ip_header() {
-            Ok((ip, tcp)) => { (ip, tcp) }
+        match iperf::serve(&listener) {
+            Ok(outcome) => report(&outcome),
+            Err(e) => eprintln!("iperf3 test failed: {}", e),
+        }
+    }
+}
+
+fn proxy(stack: &NetStack, port: u16, to: std::net::SocketAddr) -> result::Result<()> {
+    let listener = TcpListener::bind(stack, port)?;
+    println!("forwarding {} to {}", listener.local_addr(), to);
+    loop {
+        let stream = listener.accept()?;
+        let peer = stream.peer_addr();
+        let upstream = match std::net::TcpStream::connect(to) {
+            Ok(upstream) => upstream,
             Err(e) => {
-                println!("{:?}", e);
+                eprintln!("{}: connect {}: {}", peer, to, e);
+                stream.abort()?;
                 continue;
             }
         };
-        let buf = &mtu_buf[TUN_SIZE + ip_header.slice().len() + tcp_header.slice().len()..n];
-        TcpConnection::accept(&mut iface, &ip_header, &tcp_header, buf)?;
-        // let quad = Quad::from_tcpip_header(&ip_header, &tcp_header);
+        thread::spawn(move || {
+            if let Err(e) = relay(&stream, &upstream) {
+                eprintln!("{}: {}: {}", peer, to, e);
+            }
+        });
     }
 }
 
+fn gateway(stack: &NetStack, socks5: Option<u16>) -> result::Result<()> {
+    let (listener, mode) = match socks5 {
+        Some(port) => (TcpListener::bind(stack, port)?, Mode::Socks5),
+        None => (TcpListener::bind_transparent(stack)?, Mode::Transparent),
+    };
+    match mode {
+        Mode::Socks5 => println!("SOCKS5 on {}", listener.local_addr()),
+        Mode::Transparent => println!("forwarding the connections routed to the interface"),
+    }
+    Gateway::new(mode).serve(&listener)?;
+    Ok(())
+}
 
-pub fn handle_connection() {}
\ No newline at end of file
+fn exec(stack: &NetStack, port: u16, program: &[String]) -> result::Result<()> {
+    let listener = TcpListener::bind(stack, port)?;
+    println!("running {} for the connections to {}", program[0], listener.local_addr());
+    loop {
+        let stream = listener.accept()?;
+        let (peer, local) = (stream.peer_addr(), stream.local_addr());
+        let mut command = process::Command::new(&program[0]);
+        command.args(&program[1..])
+            .env("TCPREMOTEIP", peer.ip().to_string())
+            .env("TCPREMOTEPORT", peer.port().to_string())
+            .env("TCPLOCALIP", local.ip().to_string())
+            .env("TCPLOCALPORT", local.port().to_string());
+        match bridge::spawn(stream, &mut command) {
+            // reap it when it exits
+            Ok(mut child) => drop(thread::spawn(move || child.wait())),
+            Err(e) => eprintln!("{}: {}: {}", peer, program[0], e),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+use std::io::{IoSlice, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+use crate::data_link::{gather, Capabilities, DataLayer};
+use crate::tcp::vars::TcpState;
+
+/// Stack wide counters of RFC 1213, the ones linux shows in `/proc/net/snmp`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub(crate) ip_in_receives: AtomicU64,
+    pub(crate) ip_in_hdr_errors: AtomicU64,
+    pub(crate) ip_in_addr_errors: AtomicU64,
+    pub(crate) ip_in_unknown_protos: AtomicU64,
+    pub(crate) ip_in_delivers: AtomicU64,
+    pub(crate) ip_out_requests: AtomicU64,
+    pub(crate) tcp_active_opens: AtomicU64,
+    pub(crate) tcp_passive_opens: AtomicU64,
+    pub(crate) tcp_attempt_fails: AtomicU64,
+    pub(crate) tcp_estab_resets: AtomicU64,
+    pub(crate) tcp_in_segs: AtomicU64,
+    pub(crate) tcp_out_segs: AtomicU64,
+    pub(crate) tcp_retrans_segs: AtomicU64,
+    pub(crate) tcp_in_errs: AtomicU64,
+    pub(crate) tcp_out_rsts: AtomicU64,
+    pub(crate) tcp_in_csum_errors: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn inc(counter: &AtomicU64) {
+        Self::add(counter, 1);
+    }
+
+    /// count the connection changing from state `from` to `to`
+    pub(crate) fn on_transition(&self, from: TcpState, to: TcpState) {
+        if to != TcpState::Closed {
+            return;
+        }
+        match from {
+            TcpState::SynSent | TcpState::SynReceived => Self::inc(&self.tcp_attempt_fails),
+            TcpState::Established | TcpState::CloseWait => Self::inc(&self.tcp_estab_resets),
+            _ => {}
+        }
+    }
+
+    /// copy of the counters, `curr_estab` is filled in by the stack
+    pub fn snapshot(&self) -> Snmp {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Snmp {
+            ip: IpMib {
+                in_receives: get(&self.ip_in_receives),
+                in_hdr_errors: get(&self.ip_in_hdr_errors),
+                in_addr_errors: get(&self.ip_in_addr_errors),
+                in_unknown_protos: get(&self.ip_in_unknown_protos),
+                in_delivers: get(&self.ip_in_delivers),
+                out_requests: get(&self.ip_out_requests),
+            },
+            tcp: TcpMib {
+                active_opens: get(&self.tcp_active_opens),
+                passive_opens: get(&self.tcp_passive_opens),
+                attempt_fails: get(&self.tcp_attempt_fails),
+                estab_resets: get(&self.tcp_estab_resets),
+                curr_estab: 0,
+                in_segs: get(&self.tcp_in_segs),
+                out_segs: get(&self.tcp_out_segs),
+                retrans_segs: get(&self.tcp_retrans_segs),
+                in_errs: get(&self.tcp_in_errs),
+                out_rsts: get(&self.tcp_out_rsts),
+                in_csum_errors: get(&self.tcp_in_csum_errors),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct IpMib {
+    /// frames carrying an ipv4 packet
+    pub in_receives: u64,
+    /// malformed headers and bad header checksums
+    pub in_hdr_errors: u64,
+    /// packets to an address of someone else
+    pub in_addr_errors: u64,
+    pub in_unknown_protos: u64,
+    /// packets handed to tcp
+    pub in_delivers: u64,
+    pub out_requests: u64,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct TcpMib {
+    pub active_opens: u64,
+    pub passive_opens: u64,
+    /// SYN-SENT or SYN-RECEIVED to CLOSED
+    pub attempt_fails: u64,
+    /// ESTABLISHED or CLOSE-WAIT to CLOSED
+    pub estab_resets: u64,
+    /// connections in ESTABLISHED or CLOSE-WAIT right now
+    pub curr_estab: u64,
+    pub in_segs: u64,
+    /// RSTs and retransmissions included
+    pub out_segs: u64,
+    pub retrans_segs: u64,
+    /// segments too short or otherwise malformed, checksum failures included
+    pub in_errs: u64,
+    pub out_rsts: u64,
+    pub in_csum_errors: u64,
+}
+
+/// Snapshot of the counters, see `NetStack::snmp`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Snmp {
+    pub ip: IpMib,
+    pub tcp: TcpMib,
+}
+
+/// the `/proc/net/snmp` layout, a line of names followed by a line of values per protocol
+impl fmt::Display for Snmp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ip = &self.ip;
+        writeln!(f, "Ip: InReceives InHdrErrors InAddrErrors InUnknownProtos InDelivers OutRequests")?;
+        writeln!(f, "Ip: {} {} {} {} {} {}",
+                 ip.in_receives, ip.in_hdr_errors, ip.in_addr_errors,
+                 ip.in_unknown_protos, ip.in_delivers, ip.out_requests)?;
+        let tcp = &self.tcp;
+        writeln!(f, "Tcp: ActiveOpens PassiveOpens AttemptFails EstabResets CurrEstab \
+                     InSegs OutSegs RetransSegs InErrs OutRsts InCsumErrors")?;
+        writeln!(f, "Tcp: {} {} {} {} {} {} {} {} {} {} {}",
+                 tcp.active_opens, tcp.passive_opens, tcp.attempt_fails, tcp.estab_resets, tcp.curr_estab,
+                 tcp.in_segs, tcp.out_segs, tcp.retrans_segs, tcp.in_errs, tcp.out_rsts, tcp.in_csum_errors)
+    }
+}
+
+/// counters as `tcp_stack_ip_*` and `tcp_stack_tcp_*`, `curr_estab` as a gauge
+#[cfg(feature = "metrics")]
+impl Snmp {
+    /// hand the values to the recorder installed for the `metrics` facade
+    pub fn publish(&self) {
+        let ip = &self.ip;
+        let tcp = &self.tcp;
+        let counters = [
+            ("tcp_stack_ip_in_receives", ip.in_receives),
+            ("tcp_stack_ip_in_hdr_errors", ip.in_hdr_errors),
+            ("tcp_stack_ip_in_addr_errors", ip.in_addr_errors),
+            ("tcp_stack_ip_in_unknown_protos", ip.in_unknown_protos),
+            ("tcp_stack_ip_in_delivers", ip.in_delivers),
+            ("tcp_stack_ip_out_requests", ip.out_requests),
+            ("tcp_stack_tcp_active_opens", tcp.active_opens),
+            ("tcp_stack_tcp_passive_opens", tcp.passive_opens),
+            ("tcp_stack_tcp_attempt_fails", tcp.attempt_fails),
+            ("tcp_stack_tcp_estab_resets", tcp.estab_resets),
+            ("tcp_stack_tcp_in_segs", tcp.in_segs),
+            ("tcp_stack_tcp_out_segs", tcp.out_segs),
+            ("tcp_stack_tcp_retrans_segs", tcp.retrans_segs),
+            ("tcp_stack_tcp_in_errs", tcp.in_errs),
+            ("tcp_stack_tcp_out_rsts", tcp.out_rsts),
+            ("tcp_stack_tcp_in_csum_errors", tcp.in_csum_errors),
+        ];
+        for (name, value) in counters {
+            ::metrics::counter!(name).absolute(value);
+        }
+        ::metrics::gauge!("tcp_stack_tcp_curr_estab").set(tcp.curr_estab as f64);
+    }
+}
+
+/// Counts the ip packets and tcp segments sent through the device
+pub(crate) struct Metered<'a, L: ?Sized> {
+    device: &'a mut L,
+    metrics: &'a Metrics,
+}
+
+impl<'a, L: DataLayer + ?Sized> Metered<'a, L> {
+    pub(crate) fn new(device: &'a mut L, metrics: &'a Metrics) -> Self {
+        Self { device, metrics }
+    }
+
+    /// the headers normally come in the first slice, otherwise the frame is gathered
+    fn count_vectored(&self, bufs: &[IoSlice]) {
+        let first = bufs.first().map_or(&[][..], |buf| &buf[..]);
+        let ip = first.get(self.device.header_len()..).unwrap_or(&[]);
+        let headers = ip.first().map_or(usize::MAX, |b| (b & 0xf) as usize * 4 + 14);
+        if ip.len() >= headers || bufs.len() <= 1 {
+            self.count(first);
+        } else {
+            self.count(&gather(bufs));
+        }
+    }
+
+    fn count(&self, frame: &[u8]) {
+        let ip = match frame.get(self.device.header_len()..) {
+            Some(ip) if !ip.is_empty() && ip[0] >> 4 == 4 => ip,
+            _ => return,
+        };
+        Metrics::inc(&self.metrics.ip_out_requests);
+        const TCP: u8 = 6;
+        let ihl = (ip[0] & 0xf) as usize * 4;
+        if ip.get(9) != Some(&TCP) {
+            return;
+        }
+        Metrics::inc(&self.metrics.tcp_out_segs);
+        // the flags byte of the tcp header
+        if let Some(flags) = ip.get(ihl + 13) {
+            if flags & 0x04 != 0 {
+                Metrics::inc(&self.metrics.tcp_out_rsts);
+            }
+        }
+    }
+}
+
+impl<'a, L: DataLayer + ?Sized> DataLayer for Metered<'a, L> {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let n = self.device.send(data)?;
+        self.count(data);
+        Ok(n)
+    }
+
+    /// counted as one segment, the device does the segmentation
+    fn send_segmented(&mut self, bufs: &[IoSlice], mss: usize) -> Result<usize> {
+        let n = self.device.send_segmented(bufs, mss)?;
+        self.count_vectored(bufs);
+        Ok(n)
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        self.device.recv(data)
+    }
+
+    fn send_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        let n = self.device.send_vectored(bufs)?;
+        self.count_vectored(bufs);
+        Ok(n)
+    }
+
+    fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize> {
+        let n = self.device.send_batch(frames)?;
+        for frame in &frames[..n] {
+            self.count(frame);
+        }
+        Ok(n)
+    }
+
+    fn header_len(&self) -> usize {
+        self.device.header_len()
+    }
+
+    fn mtu(&self) -> usize {
+        self.device.mtu()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.device.capabilities()
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.device.set_nonblocking(nonblocking)
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.device.raw_fd()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.device.flush()
+    }
+}
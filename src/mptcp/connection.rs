@@ -0,0 +1,383 @@
+//! The data level of an MPTCP connection, the byte stream the application reads and writes
+//! and the subflows carrying it
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{ErrorKind, Result};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::task::Waker;
+
+use crate::socket_addr::Quad;
+use crate::tcp::connection::ConnectionConfig;
+use crate::tcp::ring::RingBuffer;
+
+use super::idsn;
+
+/// A subflow of the connection, by the quad of its `TcpConnection`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SubflowInfo {
+    pub quad: Quad,
+    /// id of the local address of the subflow, 0 for the one of the first subflow
+    pub addr_id: u8,
+    /// the subflow closed or failed
+    pub closed: bool,
+}
+
+pub struct MptcpConnection {
+    local_key: u64,
+    remote_key: Option<u64>,
+    /// the peer didn't take MPTCP, the single subflow carries the stream as plain tcp
+    fallback: bool,
+    /// the first subflow completed its handshake
+    established: bool,
+    config: ConnectionConfig,
+    /// bytes written by the application starting at `data_una`
+    outgoing: RingBuffer,
+    data_una: u64,
+    /// the first byte not handed to a subflow yet
+    data_nxt: u64,
+    /// right edge of the data level window of the peer, it never shrinks
+    snd_wnd_end: u64,
+    /// data handed to a subflow which failed before it was acknowledged, sent again first
+    reinject: VecDeque<(u64, usize)>,
+    /// the application closed the stream, a DATA_FIN follows the last byte
+    fin_pending: bool,
+    /// the peer acknowledged the DATA_FIN
+    fin_acked: bool,
+    /// bytes received in order and not read by the application yet
+    incoming: RingBuffer,
+    rcv_nxt: u64,
+    /// data received ahead of `rcv_nxt` on another subflow
+    out_of_order: BTreeMap<u64, Vec<u8>>,
+    /// data sequence number of the DATA_FIN of the peer
+    peer_fin: Option<u64>,
+    eof: bool,
+    subflows: Vec<SubflowInfo>,
+    /// local addresses by their id
+    addr_ids: Vec<(IpAddr, u8)>,
+    error: Option<ErrorKind>,
+    pub(crate) read_waker: Option<Waker>,
+    pub(crate) write_waker: Option<Waker>,
+    /// wake the drivers of the stack, they hand data to the subflows
+    kick: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl MptcpConnection {
+    pub(crate) fn new(local_key: u64, config: ConnectionConfig, kick: Arc<dyn Fn() + Send + Sync>) -> Self {
+        let data_nxt = idsn(local_key).wrapping_add(1);
+        Self {
+            local_key,
+            remote_key: None,
+            fallback: false,
+            established: false,
+            config,
+            outgoing: RingBuffer::new(),
+            data_una: data_nxt,
+            data_nxt,
+            snd_wnd_end: data_nxt,
+            reinject: VecDeque::new(),
+            fin_pending: false,
+            fin_acked: false,
+            incoming: RingBuffer::new(),
+            rcv_nxt: 0,
+            out_of_order: BTreeMap::new(),
+            peer_fin: None,
+            eof: false,
+            subflows: Vec::new(),
+            addr_ids: Vec::new(),
+            error: None,
+            read_waker: None,
+            write_waker: None,
+            kick,
+        }
+    }
+
+    pub fn local_key(&self) -> u64 {
+        self.local_key
+    }
+
+    pub fn remote_key(&self) -> Option<u64> {
+        self.remote_key
+    }
+
+    /// the keys were exchanged, the data of the peer starts after its IDSN
+    pub(crate) fn set_remote_key(&mut self, key: u64) {
+        self.remote_key = Some(key);
+        self.rcv_nxt = idsn(key).wrapping_add(1);
+    }
+
+    /// plain tcp from now on, the sequence numbers of the only subflow number the data from 1
+    pub(crate) fn fall_back(&mut self) {
+        self.fallback = true;
+        self.remote_key = None;
+        self.data_una = 1;
+        self.data_nxt = 1;
+        self.snd_wnd_end = u64::MAX;
+        self.rcv_nxt = 1;
+    }
+
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.established
+    }
+
+    pub(crate) fn on_established(&mut self) {
+        if !self.established {
+            self.established = true;
+            self.wake_writer();
+        }
+    }
+
+    /// a subflow may join: the keys are known and the stream is still open
+    pub(crate) fn accepts_join(&self) -> bool {
+        !self.fallback && self.remote_key.is_some() && !self.eof && self.error.is_none()
+    }
+
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    pub fn subflows(&self) -> &[SubflowInfo] {
+        &self.subflows
+    }
+
+    /// the id of the local address `ip`, a new one for an address no subflow used yet
+    pub(crate) fn local_addr_id(&mut self, ip: IpAddr) -> u8 {
+        if let Some((_, id)) = self.addr_ids.iter().find(|(addr, _)| *addr == ip) {
+            return *id;
+        }
+        let id = self.addr_ids.len() as u8;
+        self.addr_ids.push((ip, id));
+        id
+    }
+
+    pub(crate) fn add_subflow(&mut self, quad: Quad, addr_id: u8) {
+        if !self.subflows.iter().any(|subflow| subflow.quad == quad) {
+            self.subflows.push(SubflowInfo { quad, addr_id, closed: false });
+        }
+    }
+
+    /// the subflow `quad` is gone, what it had in flight is sent on the others. once none is
+    /// left the stream fails with `error` or ends if the subflows closed in order
+    pub(crate) fn on_subflow_closed(&mut self, quad: Quad, unacked: &[(u64, usize)], error: Option<ErrorKind>) {
+        debug!(%quad, ?error, reinjected = unacked.len(), "subflow closed");
+        for subflow in self.subflows.iter_mut().filter(|subflow| subflow.quad == quad) {
+            subflow.closed = true;
+        }
+        self.reinject.extend(unacked.iter().copied());
+        if self.subflows.iter().all(|subflow| subflow.closed) {
+            match error {
+                Some(error) if !self.eof => self.error = Some(error),
+                _ => self.eof = true,
+            }
+            self.wake_reader();
+            self.wake_writer();
+        }
+        (self.kick)();
+    }
+
+    /// `ErrorKind::ConnectionReset` and the like once every subflow failed
+    pub fn error(&self) -> Option<ErrorKind> {
+        self.error
+    }
+
+    /// the peer acknowledged the data up to `ack` and opened its window `window` bytes beyond it
+    pub(crate) fn on_data_ack(&mut self, ack: u64, window: u16) {
+        let acked = ack.wrapping_sub(self.data_una);
+        if acked > 0 && acked <= self.data_nxt.wrapping_sub(self.data_una) + 1 {
+            let consumed = (acked as usize).min(self.outgoing.len());
+            self.outgoing.consume(consumed);
+            self.data_una = self.data_una.wrapping_add(consumed as u64);
+            if self.fin_pending && ack == self.data_nxt.wrapping_add(1) && self.outgoing.is_empty() {
+                self.fin_acked = true;
+            }
+            self.wake_writer();
+        }
+        if !self.fallback {
+            self.snd_wnd_end = self.snd_wnd_end.max(ack.wrapping_add(window as u64));
+        }
+    }
+
+    /// next bytes for a subflow, at most `max`: data a failed subflow didn't get through
+    /// first, then new data inside the window of the peer
+    pub(crate) fn pull(&mut self, max: usize) -> Option<(u64, Vec<u8>)> {
+        while let Some((dsn, len)) = self.reinject.pop_front() {
+            let end = dsn.wrapping_add(len as u64);
+            if end <= self.data_una {
+                continue;
+            }
+            let start = dsn.max(self.data_una);
+            let n = ((end - start) as usize).min(max);
+            if (end - start) as usize > n {
+                self.reinject.push_front((start + n as u64, (end - start) as usize - n));
+            }
+            return Some((start, self.peek(start, n)));
+        }
+        let unsent = self.data_una.wrapping_add(self.outgoing.len() as u64).wrapping_sub(self.data_nxt);
+        let window = self.snd_wnd_end.saturating_sub(self.data_nxt);
+        let n = unsent.min(window).min(max as u64) as usize;
+        if n == 0 {
+            return None;
+        }
+        let dsn = self.data_nxt;
+        self.data_nxt = self.data_nxt.wrapping_add(n as u64);
+        Some((dsn, self.peek(dsn, n)))
+    }
+
+    fn peek(&self, dsn: u64, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        self.outgoing.peek(dsn.wrapping_sub(self.data_una) as usize, &mut bytes);
+        bytes
+    }
+
+    /// the next data sequence number expected, the DATA_FIN of the peer counts once it arrived
+    pub(crate) fn data_ack(&self) -> u64 {
+        self.rcv_nxt
+    }
+
+    /// the first data sequence number not acknowledged yet
+    pub(crate) fn data_una(&self) -> u64 {
+        self.data_una
+    }
+
+    /// the bytes starting at `dsn` arrived on a subflow, return how many of them
+    /// it may drop: those received before and those which fit in the receive buffer
+    pub(crate) fn on_data(&mut self, dsn: u64, data: &[u8]) -> usize {
+        let end = dsn.wrapping_add(data.len() as u64);
+        if end <= self.rcv_nxt {
+            return data.len();
+        }
+        let space = self.config.recv_buffer_size().saturating_sub(self.incoming.len() + self.queued());
+        if dsn > self.rcv_nxt {
+            // ahead of a gap another subflow fills, kept if the buffer has room for it
+            if self.out_of_order.contains_key(&dsn) {
+                return data.len();
+            }
+            if data.len() > space {
+                return 0;
+            }
+            self.out_of_order.insert(dsn, data.to_vec());
+            return data.len();
+        }
+        let skip = (self.rcv_nxt - dsn) as usize;
+        let n = (data.len() - skip).min(self.config.recv_buffer_size().saturating_sub(self.incoming.len()));
+        self.deliver(&data[skip..skip + n]);
+        while let Some(entry) = self.out_of_order.first_entry() {
+            if *entry.key() > self.rcv_nxt {
+                break;
+            }
+            let (dsn, queued) = entry.remove_entry();
+            let skip = (self.rcv_nxt - dsn).min(queued.len() as u64) as usize;
+            self.deliver(&queued[skip..]);
+        }
+        self.check_fin();
+        skip + n
+    }
+
+    fn queued(&self) -> usize {
+        self.out_of_order.values().map(Vec::len).sum()
+    }
+
+    fn deliver(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.incoming.push(data);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(data.len() as u64);
+        self.wake_reader();
+    }
+
+    /// the peer sends nothing after `dsn`
+    pub(crate) fn on_data_fin(&mut self, dsn: u64) {
+        if self.peer_fin.is_none() {
+            self.peer_fin = Some(dsn);
+            self.check_fin();
+        }
+    }
+
+    fn check_fin(&mut self) {
+        if !self.eof && self.peer_fin == Some(self.rcv_nxt) {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.eof = true;
+            self.wake_reader();
+        }
+    }
+
+    /// the data sequence number of our DATA_FIN once the application closed the stream
+    pub(crate) fn data_fin(&self) -> Option<u64> {
+        self.fin_pending.then_some(self.data_una.wrapping_add(self.outgoing.len() as u64))
+    }
+
+    /// every byte was handed to a subflow after the application closed the stream,
+    /// the subflows can send their FIN
+    pub(crate) fn is_closing(&self) -> bool {
+        self.fin_pending && self.reinject.is_empty()
+            && self.data_nxt == self.data_una.wrapping_add(self.outgoing.len() as u64)
+    }
+
+    pub fn is_fin_acked(&self) -> bool {
+        self.fin_acked
+    }
+
+    /// move received bytes into `buf`, `Ok(0)` at end of file and `ErrorKind::WouldBlock`
+    /// if nothing arrived
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if !self.incoming.is_empty() {
+            return Ok(self.incoming.read(buf));
+        }
+        if let Some(error) = self.error {
+            return Err(error.into());
+        }
+        if buf.is_empty() || self.eof {
+            return Ok(0);
+        }
+        Err(ErrorKind::WouldBlock.into())
+    }
+
+    /// queue bytes to be sent, `ErrorKind::WouldBlock` if the send buffer is full
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        if let Some(error) = self.error {
+            return Err(error.into());
+        }
+        if self.fin_pending || self.eof && self.subflows.iter().all(|subflow| subflow.closed) {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let n = data.len().min(self.config.send_buffer_size().saturating_sub(self.outgoing.len()));
+        if n == 0 {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        self.outgoing.push(&data[..n]);
+        Ok(n)
+    }
+
+    /// close the sending side, the peer reads end of file after the data already written
+    pub fn close(&mut self) {
+        self.fin_pending = true;
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.eof && self.incoming.is_empty()
+    }
+
+    pub(crate) fn kick(&self) {
+        (self.kick)()
+    }
+
+    fn wake_reader(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_writer(&mut self) {
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
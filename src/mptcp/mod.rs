@@ -0,0 +1,133 @@
+//! Multipath TCP (RFC 8684), experimental
+//!
+//! an MPTCP connection spreads one byte stream over several tcp connections, the subflows,
+//! e.g. from the different addresses of a host. the first subflow exchanges the keys with
+//! MP_CAPABLE, more subflows join it with MP_JOIN and the data sequence mappings of the DSS
+//! option tell where the bytes of a subflow belong in the stream. the subflows are ordinary
+//! `TcpConnection`s of the stack with a `Subflow` attached, the stream itself is
+//! `socket::MptcpStream`.
+//!
+//! not supported: DSS checksums (a peer requiring them gets plain tcp), ADD_ADDR and
+//! REMOVE_ADDR, backup subflows, MP_FAIL and data level retransmissions other than the
+//! reinjection of what a failed subflow had in flight
+pub mod connection;
+pub mod options;
+pub mod subflow;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex, Weak};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::tcp::connection::ConnectionConfig;
+
+use self::connection::MptcpConnection;
+use self::options::{MptcpOption, CAPABLE_CHECKSUM, VERSION};
+use self::subflow::Subflow;
+
+/// room the MPTCP option takes in every segment of a subflow, a DSS with 64 bit
+/// numbers and a mapping, padded
+pub const OPTION_LEN: usize = 28;
+
+/// the token naming the connection of `key` in MP_JOIN, the most significant
+/// 32 bits of its SHA-256 (RFC 8684 3.2)
+pub fn token(key: u64) -> u32 {
+    let hash = Sha256::digest(key.to_be_bytes());
+    u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]])
+}
+
+/// the initial data sequence number of the sender of `key`, the least significant
+/// 64 bits of its SHA-256, the first byte of data follows it
+pub fn idsn(key: u64) -> u64 {
+    let hash = Sha256::digest(key.to_be_bytes());
+    let mut low = [0; 8];
+    low.copy_from_slice(&hash[24..]);
+    u64::from_be_bytes(low)
+}
+
+/// HMAC-SHA256 of MP_JOIN keyed with `key_a` followed by `key_b` over `nonce_a` followed by
+/// `nonce_b`, a is the side sending it
+pub(crate) fn join_mac(key_a: u64, key_b: u64, nonce_a: u32, nonce_b: u32) -> [u8; 32] {
+    let mut key = [0; 16];
+    key[..8].copy_from_slice(&key_a.to_be_bytes());
+    key[8..].copy_from_slice(&key_b.to_be_bytes());
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("hmac takes any key length");
+    mac.update(&nonce_a.to_be_bytes());
+    mac.update(&nonce_b.to_be_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+/// 64 bits from the random source of the system, for keys and nonces
+pub(crate) fn random() -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// The MPTCP connections of a stack by their token, where MP_JOIN finds the one it joins
+pub(crate) struct Registry {
+    tokens: Mutex<HashMap<u32, Weak<Mutex<MptcpConnection>>>>,
+    /// wake the drivers of every shard, the subflows of a connection may be in any
+    kick: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl Registry {
+    pub(crate) fn new(kick: Arc<dyn Fn() + Send + Sync>) -> Self {
+        Self { tokens: Mutex::new(HashMap::new()), kick }
+    }
+
+    /// a new connection with a key whose token no other one of the stack has
+    pub(crate) fn create(&self, config: ConnectionConfig) -> io::Result<Arc<Mutex<MptcpConnection>>> {
+        let mut tokens = self.tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        tokens.retain(|_, conn| conn.strong_count() > 0);
+        let key = loop {
+            let key = random()?;
+            if !tokens.contains_key(&token(key)) {
+                break key;
+            }
+        };
+        let conn = Arc::new(Mutex::new(MptcpConnection::new(key, config, self.kick.clone())));
+        tokens.insert(token(key), Arc::downgrade(&conn));
+        Ok(conn)
+    }
+
+    /// a connection of plain tcp behind the MPTCP interface, for a peer which didn't ask for MPTCP
+    pub(crate) fn fallback(&self, config: ConnectionConfig) -> Arc<Mutex<MptcpConnection>> {
+        let mut conn = MptcpConnection::new(0, config, self.kick.clone());
+        conn.fall_back();
+        Arc::new(Mutex::new(conn))
+    }
+
+    pub(crate) fn get(&self, token: u32) -> Option<Arc<Mutex<MptcpConnection>>> {
+        let tokens = self.tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        tokens.get(&token).and_then(Weak::upgrade)
+    }
+
+    /// the subflow of the SYN `options` open a connection with on a listener taking MPTCP,
+    /// a fallback to plain tcp if the peer didn't ask for version 1 without checksums
+    pub(crate) fn listen(&self, options: &[MptcpOption], config: ConnectionConfig) -> io::Result<Subflow> {
+        let capable = options.iter().any(|option| matches!(option,
+            MptcpOption::Capable { version: VERSION, flags, sender_key: None, .. } if flags & CAPABLE_CHECKSUM == 0));
+        if !capable {
+            return Ok(Subflow::plain(self.fallback(config)));
+        }
+        Ok(Subflow::accept_capable(self.create(config)?))
+    }
+
+    /// the subflow of the MP_JOIN SYN `options` carry, `None` if there is none.
+    /// `Some(Err)` if the connection to join is unknown or doesn't take subflows
+    pub(crate) fn join(&self, options: &[MptcpOption]) -> Option<Result<Subflow, u32>> {
+        options.iter().find_map(|option| match *option {
+            MptcpOption::JoinSyn { token, nonce, .. } => {
+                let subflow = self.get(token)
+                    .and_then(|conn| Subflow::accept_join(conn, nonce))
+                    .ok_or(token);
+                Some(subflow)
+            }
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,223 @@
+//! The MPTCP option (RFC 8684 3), one tcp option of kind 30 whose first byte
+//! tells the subtype
+
+use std::convert::TryInto;
+
+use crate::tcp::options::{self, TcpOption};
+
+pub const SUBTYPE_MP_CAPABLE: u8 = 0;
+pub const SUBTYPE_MP_JOIN: u8 = 1;
+pub const SUBTYPE_DSS: u8 = 2;
+pub const SUBTYPE_ADD_ADDR: u8 = 3;
+pub const SUBTYPE_REMOVE_ADDR: u8 = 4;
+pub const SUBTYPE_MP_PRIO: u8 = 5;
+pub const SUBTYPE_MP_FAIL: u8 = 6;
+pub const SUBTYPE_MP_FASTCLOSE: u8 = 7;
+pub const SUBTYPE_MP_TCPRST: u8 = 8;
+
+/// MPTCP version 1, the one of RFC 8684
+pub const VERSION: u8 = 1;
+/// MP_CAPABLE flag A: the sender requires DSS checksums
+pub const CAPABLE_CHECKSUM: u8 = 0x80;
+/// MP_CAPABLE flag H: keys are authenticated with HMAC-SHA256
+pub const CAPABLE_HMAC_SHA256: u8 = 0x01;
+
+const DSS_DATA_FIN: u8 = 0x10;
+const DSS_DSN64: u8 = 0x08;
+const DSS_MAPPING: u8 = 0x04;
+const DSS_ACK64: u8 = 0x02;
+const DSS_ACK: u8 = 0x01;
+const JOIN_BACKUP: u8 = 0x01;
+
+/// Data sequence mapping: `len` bytes starting at the relative subflow sequence number
+/// `subflow_seq` carry the data starting at `dsn`. a DATA_FIN takes the last number
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Mapping {
+    pub dsn: u64,
+    pub subflow_seq: u32,
+    pub len: u16,
+}
+
+/// Data Sequence Signal, the mapping and the acknowledgment of the data level
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Dss {
+    /// the next data sequence number expected, only the lower half unless `ack64`
+    pub data_ack: Option<u64>,
+    pub ack64: bool,
+    /// only the lower half of the `dsn` unless `dsn64`
+    pub mapping: Option<Mapping>,
+    pub dsn64: bool,
+    pub data_fin: bool,
+    pub checksum: Option<u16>,
+}
+
+/// One MPTCP option
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MptcpOption {
+    /// MP_CAPABLE, no key in the SYN, the one of the sender in the SYN,ACK and both in
+    /// the third ACK, which may also map the first data of the connection
+    Capable {
+        version: u8,
+        flags: u8,
+        sender_key: Option<u64>,
+        receiver_key: Option<u64>,
+        data_len: Option<u16>,
+    },
+    /// MP_JOIN of a SYN, the token of the connection joined and a nonce of the sender
+    JoinSyn { backup: bool, addr_id: u8, token: u32, nonce: u32 },
+    /// MP_JOIN of a SYN,ACK, the truncated HMAC proves the receiver knows the keys
+    JoinSynAck { backup: bool, addr_id: u8, mac: u64, nonce: u32 },
+    /// MP_JOIN of the third ACK, the HMAC of the initiator
+    JoinAck { mac: [u8; 20] },
+    Dss(Dss),
+    /// a subtype this stack ignores, e.g. ADD_ADDR
+    Other { subtype: u8, data: Vec<u8> },
+}
+
+impl MptcpOption {
+    pub fn subtype(&self) -> u8 {
+        match self {
+            MptcpOption::Capable { .. } => SUBTYPE_MP_CAPABLE,
+            MptcpOption::JoinSyn { .. } | MptcpOption::JoinSynAck { .. } | MptcpOption::JoinAck { .. } => SUBTYPE_MP_JOIN,
+            MptcpOption::Dss(_) => SUBTYPE_DSS,
+            MptcpOption::Other { subtype, .. } => *subtype,
+        }
+    }
+
+    /// the tcp option carrying it
+    pub fn to_option(&self) -> TcpOption {
+        let mut data = Vec::with_capacity(28);
+        let subtype = self.subtype() << 4;
+        match self {
+            MptcpOption::Capable { version, flags, sender_key, receiver_key, data_len } => {
+                data.push(subtype | (version & 0xf));
+                data.push(*flags);
+                for key in sender_key.iter().chain(receiver_key) {
+                    data.extend_from_slice(&key.to_be_bytes());
+                }
+                if let Some(len) = data_len {
+                    data.extend_from_slice(&len.to_be_bytes());
+                }
+            }
+            MptcpOption::JoinSyn { backup, addr_id, token, nonce } => {
+                data.extend_from_slice(&[subtype | *backup as u8, *addr_id]);
+                data.extend_from_slice(&token.to_be_bytes());
+                data.extend_from_slice(&nonce.to_be_bytes());
+            }
+            MptcpOption::JoinSynAck { backup, addr_id, mac, nonce } => {
+                data.extend_from_slice(&[subtype | *backup as u8, *addr_id]);
+                data.extend_from_slice(&mac.to_be_bytes());
+                data.extend_from_slice(&nonce.to_be_bytes());
+            }
+            MptcpOption::JoinAck { mac } => {
+                data.extend_from_slice(&[subtype, 0]);
+                data.extend_from_slice(mac);
+            }
+            MptcpOption::Dss(dss) => {
+                let mut flags = 0;
+                if dss.data_ack.is_some() {
+                    flags |= DSS_ACK | if dss.ack64 { DSS_ACK64 } else { 0 };
+                }
+                if dss.mapping.is_some() {
+                    flags |= DSS_MAPPING | if dss.dsn64 { DSS_DSN64 } else { 0 };
+                }
+                if dss.data_fin {
+                    flags |= DSS_DATA_FIN;
+                }
+                data.extend_from_slice(&[subtype, flags]);
+                if let Some(ack) = dss.data_ack {
+                    push_seq(&mut data, ack, dss.ack64);
+                }
+                if let Some(mapping) = dss.mapping {
+                    push_seq(&mut data, mapping.dsn, dss.dsn64);
+                    data.extend_from_slice(&mapping.subflow_seq.to_be_bytes());
+                    data.extend_from_slice(&mapping.len.to_be_bytes());
+                    if let Some(checksum) = dss.checksum {
+                        data.extend_from_slice(&checksum.to_be_bytes());
+                    }
+                }
+            }
+            MptcpOption::Other { data: rest, .. } => data.extend_from_slice(rest),
+        }
+        TcpOption::Multipath(data)
+    }
+
+    /// the option in the data of `TcpOption::Multipath`, `None` if it's malformed
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let subtype = data.first()? >> 4;
+        let be16 = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        let be32 = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().expect("4 bytes"));
+        let be64 = |at: usize| u64::from_be_bytes(data[at..at + 8].try_into().expect("8 bytes"));
+        let option = match (subtype, data.len()) {
+            (SUBTYPE_MP_CAPABLE, 2 | 10 | 18 | 20 | 22) => MptcpOption::Capable {
+                version: data[0] & 0xf,
+                flags: data[1],
+                sender_key: (data.len() >= 10).then(|| be64(2)),
+                receiver_key: (data.len() >= 18).then(|| be64(10)),
+                data_len: (data.len() >= 20).then(|| be16(18)),
+            },
+            (SUBTYPE_MP_JOIN, 10) => MptcpOption::JoinSyn {
+                backup: data[0] & JOIN_BACKUP != 0,
+                addr_id: data[1],
+                token: be32(2),
+                nonce: be32(6),
+            },
+            (SUBTYPE_MP_JOIN, 14) => MptcpOption::JoinSynAck {
+                backup: data[0] & JOIN_BACKUP != 0,
+                addr_id: data[1],
+                mac: be64(2),
+                nonce: be32(10),
+            },
+            (SUBTYPE_MP_JOIN, 22) => MptcpOption::JoinAck { mac: data[2..].try_into().expect("20 bytes") },
+            (SUBTYPE_DSS, n) if n >= 2 => {
+                let flags = data[1];
+                let (ack64, dsn64) = (flags & DSS_ACK64 != 0, flags & DSS_DSN64 != 0);
+                let mut at = 2;
+                let mut seq = |wide: bool| {
+                    let len = if wide { 8 } else { 4 };
+                    let value = data.get(at..at + len).map(|_| if wide { be64(at) } else { be32(at) as u64 });
+                    at += len;
+                    value
+                };
+                let data_ack = match flags & DSS_ACK {
+                    0 => None,
+                    _ => Some(seq(ack64)?),
+                };
+                let dsn = match flags & DSS_MAPPING {
+                    0 => None,
+                    _ => Some(seq(dsn64)?),
+                };
+                let (mapping, checksum) = match dsn {
+                    Some(dsn) if n == at + 6 || n == at + 8 => {
+                        let mapping = Mapping { dsn, subflow_seq: be32(at), len: be16(at + 4) };
+                        (Some(mapping), (n == at + 8).then(|| be16(at + 6)))
+                    }
+                    None if n == at => (None, None),
+                    _ => return None,
+                };
+                MptcpOption::Dss(Dss { data_ack, ack64, mapping, dsn64, data_fin: flags & DSS_DATA_FIN != 0, checksum })
+            }
+            (SUBTYPE_MP_CAPABLE | SUBTYPE_MP_JOIN | SUBTYPE_DSS, _) => return None,
+            (subtype, _) => MptcpOption::Other { subtype, data: data.to_vec() },
+        };
+        Some(option)
+    }
+
+    /// the well formed MPTCP options in the options of a header, e.g. `TcpHeaderSlice::options()`
+    pub fn find(raw: &[u8]) -> Vec<MptcpOption> {
+        options::parse(raw)
+            .filter_map(|option| match option {
+                Ok(TcpOption::Multipath(data)) => MptcpOption::parse(&data),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn push_seq(data: &mut Vec<u8>, seq: u64, wide: bool) {
+    if wide {
+        data.extend_from_slice(&seq.to_be_bytes());
+    } else {
+        data.extend_from_slice(&(seq as u32).to_be_bytes());
+    }
+}
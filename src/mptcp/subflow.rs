@@ -0,0 +1,456 @@
+//! The MPTCP part of a `TcpConnection` carrying a subflow: the handshake options and the
+//! data sequence mappings between the subflow and the data level
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use etherparse::TcpHeaderSlice;
+
+use crate::socket_addr::Quad;
+use crate::tcp::vars::seq_le;
+
+use super::connection::MptcpConnection;
+use super::options::{Dss, Mapping, MptcpOption, CAPABLE_CHECKSUM, CAPABLE_HMAC_SHA256, VERSION};
+use super::{idsn, join_mac, random, token};
+
+/// What the connection does with a segment after the subflow looked at its options
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Verdict {
+    Accept,
+    /// acknowledge it right away, like the fourth ACK of MP_JOIN
+    Ack,
+    /// the handshake failed, reset the subflow
+    Reset,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Stage {
+    /// active, the SYN asks for MPTCP
+    CapableSyn,
+    /// passive, the SYN,ACK carries our key and the third ACK has to echo it
+    CapableSynAck,
+    /// active, both keys go with every segment until the peer sends a DSS
+    CapableAck,
+    /// active, the SYN carries the token of the connection joined
+    JoinSyn,
+    /// passive, the third ACK has to prove the peer knows the keys
+    JoinSynAck,
+    /// active, the HMAC goes with every segment until the peer acknowledges it
+    JoinAck,
+    Established,
+    /// the peer didn't take MPTCP, the subflow is the plain tcp connection
+    Fallback,
+}
+
+/// A subflow of `MptcpConnection`, kept by the `TcpConnection` carrying it
+#[derive(Clone)]
+pub struct Subflow {
+    meta: Arc<Mutex<MptcpConnection>>,
+    stage: Stage,
+    /// id of the local address
+    addr_id: u8,
+    local_nonce: u32,
+    remote_nonce: u32,
+    iss: u32,
+    irs: u32,
+    /// the data queued in the subflow, by relative subflow sequence number
+    send_maps: VecDeque<Mapping>,
+    /// mappings of the peer for the data received, with 64 bit data sequence numbers
+    recv_maps: VecDeque<Mapping>,
+    /// relative sequence number acknowledged last, the data level follows it in fallback
+    una: u32,
+    /// the data level was told the subflow closed
+    detached: bool,
+}
+
+impl Subflow {
+    fn new(meta: Arc<Mutex<MptcpConnection>>, stage: Stage) -> Self {
+        Self {
+            meta,
+            stage,
+            addr_id: 0,
+            local_nonce: 0,
+            remote_nonce: 0,
+            iss: 0,
+            irs: 0,
+            send_maps: VecDeque::new(),
+            recv_maps: VecDeque::new(),
+            una: 1,
+            detached: false,
+        }
+    }
+
+    /// the first subflow of `meta`, its SYN asks for MPTCP
+    pub fn connect(meta: Arc<Mutex<MptcpConnection>>) -> Self {
+        Subflow::new(meta, Stage::CapableSyn)
+    }
+
+    /// another subflow of the established connection `meta`
+    pub fn join(meta: Arc<Mutex<MptcpConnection>>) -> io::Result<Self> {
+        let mut subflow = Subflow::new(meta, Stage::JoinSyn);
+        subflow.local_nonce = random()? as u32;
+        Ok(subflow)
+    }
+
+    /// the connection of a peer which didn't ask for MPTCP
+    pub(crate) fn plain(meta: Arc<Mutex<MptcpConnection>>) -> Self {
+        Subflow::new(meta, Stage::Fallback)
+    }
+
+    pub(crate) fn accept_capable(meta: Arc<Mutex<MptcpConnection>>) -> Self {
+        Subflow::new(meta, Stage::CapableSynAck)
+    }
+
+    /// `None` if `meta` takes no more subflows
+    pub(crate) fn accept_join(meta: Arc<Mutex<MptcpConnection>>, nonce: u32) -> Option<Self> {
+        if !lock(&meta).accepts_join() {
+            return None;
+        }
+        let mut subflow = Subflow::new(meta, Stage::JoinSynAck);
+        subflow.local_nonce = random().ok()? as u32;
+        subflow.remote_nonce = nonce;
+        Some(subflow)
+    }
+
+    /// the subflow is carried by the connection `quad` with the initial sequence numbers
+    /// `iss` and `irs`, the latter is taken from the SYN,ACK of an active open
+    pub(crate) fn attach(&mut self, quad: Quad, iss: u32, irs: u32) {
+        self.iss = iss;
+        self.irs = irs;
+        let mut meta = lock(&self.meta);
+        self.addr_id = meta.local_addr_id(quad.src().ip());
+        meta.add_subflow(quad, self.addr_id);
+    }
+
+    pub fn meta(&self) -> &Arc<Mutex<MptcpConnection>> {
+        &self.meta
+    }
+
+    pub fn is_fallback(&self) -> bool {
+        self.stage == Stage::Fallback
+    }
+
+    /// keys or HMACs were exchanged, or the subflow fell back to tcp
+    pub fn is_established(&self) -> bool {
+        matches!(self.stage, Stage::CapableAck | Stage::Established | Stage::Fallback)
+    }
+
+    pub(crate) fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    /// look at the MPTCP options of a segment before the connection processes it
+    pub(crate) fn on_segment(&mut self, tcp: &TcpHeaderSlice, window: u16) -> Verdict {
+        if tcp.rst() || self.detached {
+            return Verdict::Accept;
+        }
+        let options = MptcpOption::find(tcp.options());
+        let handshake = tcp.syn() && tcp.ack();
+        let third_ack = tcp.ack() && !tcp.syn();
+        let meta = self.meta.clone();
+        let mut meta = lock(&meta);
+        match self.stage {
+            Stage::CapableSyn if handshake => {
+                self.irs = tcp.sequence_number();
+                let key = options.iter().find_map(|option| match *option {
+                    MptcpOption::Capable { version: VERSION, flags, sender_key: Some(key), .. }
+                        if flags & CAPABLE_CHECKSUM == 0 => Some(key),
+                    _ => None,
+                });
+                match key {
+                    Some(key) => {
+                        meta.set_remote_key(key);
+                        let ack = meta.data_una();
+                        meta.on_data_ack(ack, window);
+                        self.stage = Stage::CapableAck;
+                    }
+                    None => {
+                        debug!("the peer doesn't take MPTCP, falling back to tcp");
+                        meta.fall_back();
+                        self.stage = Stage::Fallback;
+                    }
+                }
+                return Verdict::Accept;
+            }
+            Stage::CapableSynAck if third_ack => {
+                let local_key = meta.local_key();
+                let keys = options.iter().find_map(|option| match *option {
+                    MptcpOption::Capable { sender_key: Some(key), receiver_key: Some(receiver), .. }
+                        if receiver == local_key => Some(key),
+                    _ => None,
+                });
+                match keys {
+                    Some(key) => {
+                        meta.set_remote_key(key);
+                        let ack = meta.data_una();
+                        meta.on_data_ack(ack, window);
+                        self.stage = Stage::Established;
+                    }
+                    None => {
+                        debug!("the third ACK doesn't echo our key, falling back to tcp");
+                        meta.fall_back();
+                        self.stage = Stage::Fallback;
+                    }
+                }
+            }
+            Stage::JoinSyn if handshake => {
+                self.irs = tcp.sequence_number();
+                let remote_key = match meta.remote_key() {
+                    Some(key) => key,
+                    None => return Verdict::Reset,
+                };
+                let local_key = meta.local_key();
+                let local_nonce = self.local_nonce;
+                let nonce = options.iter().find_map(|option| match *option {
+                    MptcpOption::JoinSynAck { mac, nonce, .. }
+                        if mac.to_be_bytes() == join_mac(remote_key, local_key, nonce, local_nonce)[..8] => Some(nonce),
+                    _ => None,
+                });
+                match nonce {
+                    Some(nonce) => {
+                        self.remote_nonce = nonce;
+                        self.stage = Stage::JoinAck;
+                    }
+                    None => {
+                        debug!("SYN,ACK of MP_JOIN without a valid HMAC");
+                        return Verdict::Reset;
+                    }
+                }
+                return Verdict::Accept;
+            }
+            Stage::JoinSynAck if third_ack => {
+                let remote_key = match meta.remote_key() {
+                    Some(key) => key,
+                    None => return Verdict::Reset,
+                };
+                let expected = join_mac(remote_key, meta.local_key(), self.remote_nonce, self.local_nonce);
+                let valid = options.iter().any(|option| matches!(option, MptcpOption::JoinAck { mac } if mac[..] == expected[..20]));
+                if !valid {
+                    debug!("third ACK of MP_JOIN without a valid HMAC");
+                    return Verdict::Reset;
+                }
+                self.stage = Stage::Established;
+                return Verdict::Ack;
+            }
+            Stage::JoinAck if third_ack => {
+                self.stage = Stage::Established;
+                meta.kick();
+            }
+            Stage::CapableAck if options.iter().any(|option| matches!(option, MptcpOption::Dss(_))) => {
+                self.stage = Stage::Established;
+            }
+            _ => {}
+        }
+        if matches!(self.stage, Stage::Established | Stage::CapableAck | Stage::JoinAck) {
+            for option in &options {
+                match *option {
+                    MptcpOption::Dss(ref dss) => self.on_dss(&mut meta, dss, window),
+                    // the first data may come with the keys, mapped from the IDSN of the peer
+                    MptcpOption::Capable { data_len: Some(len), .. } if len > 0 => {
+                        let subflow_seq = tcp.sequence_number().wrapping_sub(self.irs);
+                        if let Some(key) = meta.remote_key() {
+                            self.on_mapping(Mapping { dsn: idsn(key).wrapping_add(1), subflow_seq, len });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Verdict::Accept
+    }
+
+    fn on_dss(&mut self, meta: &mut MptcpConnection, dss: &Dss, window: u16) {
+        if let Some(ack) = dss.data_ack {
+            let ack = if dss.ack64 { ack } else { expand(meta.data_una(), ack as u32) };
+            meta.on_data_ack(ack, window);
+        }
+        let mapping = match dss.mapping {
+            Some(mapping) => mapping,
+            None => return,
+        };
+        let dsn = if dss.dsn64 { mapping.dsn } else { expand(meta.data_ack(), mapping.dsn as u32) };
+        // a DATA_FIN without data has subflow sequence number 0 and takes the length 1
+        if dss.data_fin && mapping.subflow_seq == 0 && mapping.len == 1 {
+            meta.on_data_fin(dsn);
+            return;
+        }
+        let len = mapping.len - dss.data_fin as u16;
+        if len > 0 {
+            self.on_mapping(Mapping { dsn, subflow_seq: mapping.subflow_seq, len });
+        }
+        if dss.data_fin {
+            meta.on_data_fin(dsn.wrapping_add(len as u64));
+        }
+    }
+
+    /// a mapping of the peer, segments retransmitted repeat it
+    fn on_mapping(&mut self, mapping: Mapping) {
+        if !self.recv_maps.iter().any(|known| known.subflow_seq == mapping.subflow_seq) {
+            self.recv_maps.push_back(mapping);
+        }
+    }
+
+    /// the MPTCP option of a segment sent from `seq` with `len` bytes of data
+    pub(crate) fn option(&self, syn: bool, fin: bool, seq: u32, len: usize) -> Option<MptcpOption> {
+        let meta = lock(&self.meta);
+        let option = match self.stage {
+            Stage::CapableSyn if syn => MptcpOption::Capable {
+                version: VERSION,
+                flags: CAPABLE_HMAC_SHA256,
+                sender_key: None,
+                receiver_key: None,
+                data_len: None,
+            },
+            Stage::CapableSynAck if syn => MptcpOption::Capable {
+                version: VERSION,
+                flags: CAPABLE_HMAC_SHA256,
+                sender_key: Some(meta.local_key()),
+                receiver_key: None,
+                data_len: None,
+            },
+            // only the first data is mapped by MP_CAPABLE, a FIN sends the DATA_FIN
+            Stage::CapableAck if !fin => MptcpOption::Capable {
+                version: VERSION,
+                flags: CAPABLE_HMAC_SHA256,
+                sender_key: Some(meta.local_key()),
+                receiver_key: meta.remote_key(),
+                data_len: (len > 0).then(|| self.mapping_at(seq)).flatten().map(|mapping| mapping.len),
+            },
+            Stage::JoinSyn if syn => MptcpOption::JoinSyn {
+                backup: false,
+                addr_id: self.addr_id,
+                token: token(meta.remote_key()?),
+                nonce: self.local_nonce,
+            },
+            Stage::JoinSynAck if syn => {
+                let mac = join_mac(meta.local_key(), meta.remote_key()?, self.local_nonce, self.remote_nonce);
+                MptcpOption::JoinSynAck {
+                    backup: false,
+                    addr_id: self.addr_id,
+                    mac: u64::from_be_bytes(mac[..8].try_into().expect("8 bytes")),
+                    nonce: self.local_nonce,
+                }
+            }
+            Stage::JoinAck => {
+                let mac = join_mac(meta.local_key(), meta.remote_key()?, self.local_nonce, self.remote_nonce);
+                MptcpOption::JoinAck { mac: mac[..20].try_into().expect("20 bytes") }
+            }
+            Stage::Established | Stage::CapableAck => {
+                let mut dss = Dss { data_ack: Some(meta.data_ack()), ack64: true, dsn64: true, ..Dss::default() };
+                if len > 0 {
+                    dss.mapping = self.mapping_at(seq);
+                }
+                if let (true, Some(dsn)) = (fin, meta.data_fin()) {
+                    dss.data_fin = true;
+                    dss.mapping = Some(Mapping { dsn, subflow_seq: 0, len: 1 });
+                }
+                MptcpOption::Dss(dss)
+            }
+            _ => return None,
+        };
+        Some(option)
+    }
+
+    /// the mapping of the data sent from `seq`
+    fn mapping_at(&self, seq: u32) -> Option<Mapping> {
+        let rel = seq.wrapping_sub(self.iss);
+        self.send_maps.iter()
+            .find(|mapping| seq_le(mapping.subflow_seq, rel) && seq_lt_end(rel, mapping))
+            .copied()
+    }
+
+    /// bytes a segment sent from `seq` may carry without crossing the end of its mapping
+    pub(crate) fn segment_limit(&self, seq: u32) -> Option<usize> {
+        let rel = seq.wrapping_sub(self.iss);
+        self.mapping_at(seq).map(|mapping| mapping.subflow_seq.wrapping_add(mapping.len as u32).wrapping_sub(rel) as usize)
+    }
+
+    /// at most `max` bytes of the data level for the subflow to queue at `seq`, mapped there
+    pub(crate) fn pull(&mut self, seq: u32, max: usize) -> Option<Vec<u8>> {
+        let rel = seq.wrapping_sub(self.iss);
+        match self.stage {
+            Stage::Established | Stage::Fallback => {}
+            // MP_CAPABLE maps the first data only
+            Stage::CapableAck if rel == 1 => {}
+            _ => return None,
+        }
+        let (dsn, bytes) = lock(&self.meta).pull(max.min(u16::MAX as usize))?;
+        if self.stage != Stage::Fallback {
+            self.send_maps.push_back(Mapping { dsn, subflow_seq: rel, len: bytes.len() as u16 });
+        }
+        Some(bytes)
+    }
+
+    /// the peer acknowledged the subflow up to `una`, the data level follows in fallback
+    pub(crate) fn on_acked(&mut self, una: u32) {
+        let rel = una.wrapping_sub(self.iss);
+        while let Some(mapping) = self.send_maps.front() {
+            if !seq_le(mapping.subflow_seq.wrapping_add(mapping.len as u32), rel) {
+                break;
+            }
+            self.send_maps.pop_front();
+        }
+        let acked = rel.wrapping_sub(self.una);
+        if self.stage == Stage::Fallback && acked > 0 && acked < u32::MAX / 2 {
+            let mut meta = lock(&self.meta);
+            let ack = meta.data_una().wrapping_add(acked as u64);
+            meta.on_data_ack(ack, 0);
+        }
+        if seq_le(self.una, rel) {
+            self.una = rel;
+        }
+    }
+
+    /// the data sequence number of the byte received at `seq` and how many bytes after it
+    /// the same mapping covers, `None` while the mapping is unknown
+    pub(crate) fn recv_mapping(&mut self, seq: u32) -> Option<(u64, usize)> {
+        let rel = seq.wrapping_sub(self.irs);
+        if self.stage == Stage::Fallback {
+            return Some((lock(&self.meta).data_ack(), usize::MAX));
+        }
+        // the data before `rel` was handed to the data level
+        self.recv_maps.retain(|mapping| !seq_le(mapping.subflow_seq.wrapping_add(mapping.len as u32), rel));
+        self.recv_maps.iter()
+            .find(|mapping| seq_le(mapping.subflow_seq, rel))
+            .map(|mapping| {
+                let offset = rel.wrapping_sub(mapping.subflow_seq);
+                (mapping.dsn.wrapping_add(offset as u64), (mapping.len as u32 - offset) as usize)
+            })
+    }
+
+    /// the subflow closed, in order if `error` is `None`. what it didn't get
+    /// acknowledged is sent on the other subflows
+    pub(crate) fn detach(&mut self, quad: Quad, error: Option<ErrorKind>) {
+        if self.detached {
+            return;
+        }
+        self.detached = true;
+        let unacked: Vec<(u64, usize)> = self.send_maps.drain(..)
+            .map(|mapping| (mapping.dsn, mapping.len as usize))
+            .collect();
+        lock(&self.meta).on_subflow_closed(quad, &unacked, error);
+    }
+}
+
+fn seq_lt_end(rel: u32, mapping: &Mapping) -> bool {
+    rel.wrapping_sub(mapping.subflow_seq) < mapping.len as u32
+}
+
+/// the 64 bit number whose lower half is `low` closest to `reference`
+fn expand(reference: u64, low: u32) -> u64 {
+    let candidate = (reference & !0xffff_ffff) | low as u64;
+    let half = 1u64 << 31;
+    if candidate > reference.wrapping_add(half) && candidate >= 1 << 32 {
+        candidate - (1 << 32)
+    } else if candidate.wrapping_add(half) < reference {
+        candidate.wrapping_add(1 << 32)
+    } else {
+        candidate
+    }
+}
+
+pub(crate) fn lock(meta: &Mutex<MptcpConnection>) -> MutexGuard<'_, MptcpConnection> {
+    meta.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
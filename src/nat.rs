@@ -0,0 +1,416 @@
+//! Source NAT between two devices
+//!
+//! `NatRouter` forwards the tcp packets of an inside device, e.g. the link of a user stack,
+//! to an outside one with the source rewritten to the external address and a port of the
+//! NAT, and the answers back to the inside host. like RFC 5382 the mapping of an inside
+//! address is the same for every remote (endpoint independent) while only remotes the
+//! inside host sent to get in (address and port dependent filtering)
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind, Result};
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use etherparse::IpNumber;
+use tracing::{debug, trace, warn};
+
+use crate::checksum::adjust;
+use crate::clock::{system_clock, Clock};
+use crate::data_link::{recv_buffer_len, DataLayer};
+use crate::router::decrement_ttl;
+use crate::socket_addr::Addr;
+
+/// idle time of an established connection before it is forgotten, the minimum of RFC 5382
+pub const DEFAULT_ESTABLISHED_TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60 + 4 * 60);
+/// idle time of a connection being opened or closed (RFC 5382)
+pub const DEFAULT_TRANSITORY_TIMEOUT: Duration = Duration::from_secs(4 * 60);
+/// how often the table is searched for idle connections
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+
+#[derive(Debug, Clone)]
+pub struct NatConfig {
+    external: Ipv4Addr,
+    ports: RangeInclusive<u16>,
+    established_timeout: Duration,
+    transitory_timeout: Duration,
+}
+
+impl NatConfig {
+    /// translate to `external`, the address of the outside device
+    pub fn new(external: Ipv4Addr) -> Self {
+        Self {
+            external,
+            ports: 1024..=65535,
+            established_timeout: DEFAULT_ESTABLISHED_TIMEOUT,
+            transitory_timeout: DEFAULT_TRANSITORY_TIMEOUT,
+        }
+    }
+
+    pub fn external(&self) -> Ipv4Addr {
+        self.external
+    }
+
+    pub fn ports(&self) -> RangeInclusive<u16> {
+        self.ports.clone()
+    }
+
+    /// external ports handed to the inside addresses
+    pub fn set_ports(&mut self, ports: RangeInclusive<u16>) {
+        self.ports = ports;
+    }
+
+    pub fn established_timeout(&self) -> Duration {
+        self.established_timeout
+    }
+
+    pub fn set_established_timeout(&mut self, timeout: Duration) {
+        self.established_timeout = timeout;
+    }
+
+    pub fn transitory_timeout(&self) -> Duration {
+        self.transitory_timeout
+    }
+
+    /// idle time of a connection before its handshake completed or after it closed
+    pub fn set_transitory_timeout(&mut self, timeout: Duration) {
+        self.transitory_timeout = timeout;
+    }
+}
+
+/// Progress of a translated connection, it decides its idle timeout
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NatState {
+    /// the inside host sent a SYN, nothing came back yet
+    Opening,
+    Established,
+    /// both ends sent a FIN or one a RST
+    Closing,
+}
+
+/// A translated connection
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct NatEntry {
+    pub inside: Addr,
+    pub remote: Addr,
+    /// the address the remote sees
+    pub external: Addr,
+    pub state: NatState,
+    pub last_seen: Instant,
+    fin_out: bool,
+    fin_in: bool,
+}
+
+/// Connections and mappings of a NAT
+///
+/// the packets are rewritten in place, the ip and tcp checksums are adjusted for the
+/// changed address and port. only unfragmented ipv4 tcp packets are translated
+#[derive(Debug)]
+pub struct NatTable {
+    config: NatConfig,
+    /// by (inside, remote)
+    connections: HashMap<(Addr, Addr), NatEntry>,
+    /// external port and connections of an inside address
+    mappings: HashMap<Addr, (u16, usize)>,
+    /// inside address of an external port
+    ports: HashMap<u16, Addr>,
+    next_port: u16,
+}
+
+impl NatTable {
+    pub fn new(config: NatConfig) -> Self {
+        let next_port = *config.ports.start();
+        Self {
+            config,
+            connections: HashMap::new(),
+            mappings: HashMap::new(),
+            ports: HashMap::new(),
+            next_port,
+        }
+    }
+
+    pub fn config(&self) -> &NatConfig {
+        &self.config
+    }
+
+    /// translated connections
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &NatEntry> {
+        self.connections.values()
+    }
+
+    /// the connection from `inside` to `remote`
+    pub fn get(&self, inside: &Addr, remote: &Addr) -> Option<&NatEntry> {
+        self.connections.get(&(*inside, *remote))
+    }
+
+    /// rewrite the source of a packet leaving the inside, false if it must be dropped:
+    /// no tcp, no mapping for a segment other than a SYN or no external port left
+    pub fn outbound(&mut self, packet: &mut [u8], now: Instant) -> bool {
+        let tcp = match parse(packet) {
+            Some(tcp) => tcp,
+            None => return false,
+        };
+        let (inside, remote) = (addr(packet, 12, tcp), addr(packet, 16, tcp + 2));
+        let flags = packet[tcp + 13];
+        if !self.connections.contains_key(&(inside, remote)) {
+            if flags & SYN == 0 || flags & RST != 0 {
+                return false;
+            }
+            let port = match self.map(inside) {
+                Some(port) => port,
+                None => {
+                    warn!(%inside, "no external port left");
+                    return false;
+                }
+            };
+            let external = Addr::v4(self.config.external, port);
+            debug!(%inside, %remote, %external, "new nat connection");
+            self.connections.insert((inside, remote), NatEntry {
+                inside,
+                remote,
+                external,
+                state: NatState::Opening,
+                last_seen: now,
+                fin_out: false,
+                fin_in: false,
+            });
+        }
+        let entry = self.connections.get_mut(&(inside, remote)).expect("inserted above");
+        entry.last_seen = now;
+        entry.fin_out |= flags & FIN != 0;
+        update_state(entry, flags);
+        let port = entry.external.port();
+        rewrite(packet, tcp, 12, tcp, self.config.external, port);
+        true
+    }
+
+    /// rewrite the destination of a packet arriving from the outside, false if it must
+    /// be dropped: no tcp or no connection of an inside host to its source
+    pub fn inbound(&mut self, packet: &mut [u8], now: Instant) -> bool {
+        let tcp = match parse(packet) {
+            Some(tcp) => tcp,
+            None => return false,
+        };
+        let (remote, external) = (addr(packet, 12, tcp), addr(packet, 16, tcp + 2));
+        if external.ipv4() != Some(self.config.external) {
+            return false;
+        }
+        let inside = match self.ports.get(&external.port()) {
+            Some(inside) => *inside,
+            None => return false,
+        };
+        let entry = match self.connections.get_mut(&(inside, remote)) {
+            Some(entry) => entry,
+            None => {
+                trace!(%remote, %external, "no nat connection");
+                return false;
+            }
+        };
+        let flags = packet[tcp + 13];
+        entry.last_seen = now;
+        entry.fin_in |= flags & FIN != 0;
+        if entry.state == NatState::Opening && flags & RST == 0 {
+            entry.state = NatState::Established;
+        }
+        update_state(entry, flags);
+        let ip = inside.ipv4().expect("inside addresses are ipv4");
+        rewrite(packet, tcp, 16, tcp + 2, ip, inside.port());
+        true
+    }
+
+    /// forget the connections idle for longer than their timeout and the mappings
+    /// without connections
+    pub fn expire(&mut self, now: Instant) {
+        let (established, transitory) = (self.config.established_timeout, self.config.transitory_timeout);
+        let mut expired = Vec::new();
+        self.connections.retain(|_, entry| {
+            let timeout = match entry.state {
+                NatState::Established => established,
+                NatState::Opening | NatState::Closing => transitory,
+            };
+            let alive = now.saturating_duration_since(entry.last_seen) < timeout;
+            if !alive {
+                debug!(inside = %entry.inside, remote = %entry.remote, "nat connection expired");
+                expired.push(entry.inside);
+            }
+            alive
+        });
+        for inside in expired {
+            self.unmap(inside);
+        }
+    }
+
+    /// the external port of `inside`, a new one for its first connection
+    fn map(&mut self, inside: Addr) -> Option<u16> {
+        if let Some((port, connections)) = self.mappings.get_mut(&inside) {
+            *connections += 1;
+            return Some(*port);
+        }
+        // the inside port is kept if it is free, like most NATs
+        let port = Some(inside.port())
+            .filter(|port| self.config.ports.contains(port) && !self.ports.contains_key(port))
+            .or_else(|| self.free_port())?;
+        self.mappings.insert(inside, (port, 1));
+        self.ports.insert(port, inside);
+        Some(port)
+    }
+
+    fn unmap(&mut self, inside: Addr) {
+        if let Some((port, connections)) = self.mappings.get_mut(&inside) {
+            *connections -= 1;
+            if *connections == 0 {
+                let port = *port;
+                self.mappings.remove(&inside);
+                self.ports.remove(&port);
+            }
+        }
+    }
+
+    fn free_port(&mut self) -> Option<u16> {
+        let (start, end) = (*self.config.ports.start(), *self.config.ports.end());
+        let count = (end - start) as usize + 1;
+        for _ in 0..count {
+            let port = self.next_port;
+            self.next_port = if port >= end || port < start { start } else { port + 1 };
+            if (start..=end).contains(&port) && !self.ports.contains_key(&port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+}
+
+/// offset of the tcp header of an unfragmented ipv4 tcp packet
+fn parse(packet: &[u8]) -> Option<usize> {
+    let ihl = (*packet.first()? & 0xf) as usize * 4;
+    let fragmented = packet.get(6..8).is_some_and(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) & 0x3fff != 0);
+    let valid = packet[0] >> 4 == 4 && ihl >= 20 && packet.len() >= ihl + 20;
+    (valid && !fragmented && packet[9] == IpNumber::TCP.0).then_some(ihl)
+}
+
+fn addr(packet: &[u8], ip: usize, port: usize) -> Addr {
+    let ip = Ipv4Addr::new(packet[ip], packet[ip + 1], packet[ip + 2], packet[ip + 3]);
+    Addr::v4(ip, u16::from_be_bytes([packet[port], packet[port + 1]]))
+}
+
+/// replace the address at `ip` and the port at `port`, adjusting both checksums
+fn rewrite(packet: &mut [u8], tcp: usize, ip: usize, port: usize, new_ip: Ipv4Addr, new_port: u16) {
+    let old_ip: [u8; 4] = packet[ip..ip + 4].try_into().expect("4 bytes");
+    let old_port = [packet[port], packet[port + 1]];
+    let (new_ip, new_port) = (new_ip.octets(), new_port.to_be_bytes());
+    packet[ip..ip + 4].copy_from_slice(&new_ip);
+    packet[port..port + 2].copy_from_slice(&new_port);
+    let ip_checksum = u16::from_be_bytes([packet[10], packet[11]]);
+    packet[10..12].copy_from_slice(&adjust(ip_checksum, &old_ip, &new_ip).to_be_bytes());
+    // the address is part of the pseudo header
+    let at = tcp + 16;
+    let tcp_checksum = u16::from_be_bytes([packet[at], packet[at + 1]]);
+    let tcp_checksum = adjust(adjust(tcp_checksum, &old_ip, &new_ip), &old_port, &new_port);
+    packet[at..at + 2].copy_from_slice(&tcp_checksum.to_be_bytes());
+}
+
+fn update_state(entry: &mut NatEntry, flags: u8) {
+    if flags & RST != 0 || (entry.fin_out && entry.fin_in) {
+        entry.state = NatState::Closing;
+    }
+}
+
+/// Forwards the tcp packets between an inside and an outside device through a `NatTable`
+///
+/// both devices must carry bare ip packets, without a link header
+pub struct NatRouter<I: DataLayer, O: DataLayer> {
+    inside: I,
+    outside: O,
+    table: NatTable,
+    clock: Arc<dyn Clock>,
+    next_expiry: Option<Instant>,
+    buf: Vec<u8>,
+}
+
+impl<I: DataLayer, O: DataLayer> NatRouter<I, O> {
+    pub fn new(inside: I, outside: O, config: NatConfig) -> Result<Self> {
+        Self::with_clock(inside, outside, config, system_clock())
+    }
+
+    /// idle connections expire by `clock`, e.g. a `SimClock`
+    pub fn with_clock(mut inside: I, mut outside: O, config: NatConfig, clock: Arc<dyn Clock>) -> Result<Self> {
+        if inside.header_len() != 0 || outside.header_len() != 0 {
+            return Err(Error::new(ErrorKind::Unsupported, "nat needs devices without link header"));
+        }
+        inside.set_nonblocking(true)?;
+        outside.set_nonblocking(true)?;
+        let len = recv_buffer_len(&inside, inside.mtu()).max(recv_buffer_len(&outside, outside.mtu()));
+        Ok(Self {
+            inside,
+            outside,
+            table: NatTable::new(config),
+            clock,
+            next_expiry: None,
+            buf: vec![0; len],
+        })
+    }
+
+    pub fn table(&self) -> &NatTable {
+        &self.table
+    }
+
+    pub fn inside_mut(&mut self) -> &mut I {
+        &mut self.inside
+    }
+
+    pub fn outside_mut(&mut self) -> &mut O {
+        &mut self.outside
+    }
+
+    /// forward every queued packet of both devices and forget idle connections,
+    /// return the number of packets forwarded
+    pub fn process(&mut self) -> Result<usize> {
+        let now = self.clock.now();
+        let mut forwarded = 0;
+        while let Some(n) = recv(&mut self.inside, &mut self.buf)? {
+            let packet = &mut self.buf[..n];
+            if decrement_ttl(packet) && self.table.outbound(packet, now) {
+                self.outside.send(packet)?;
+                forwarded += 1;
+            }
+        }
+        while let Some(n) = recv(&mut self.outside, &mut self.buf)? {
+            let packet = &mut self.buf[..n];
+            if decrement_ttl(packet) && self.table.inbound(packet, now) {
+                self.inside.send(packet)?;
+                forwarded += 1;
+            }
+        }
+        if self.next_expiry.is_none_or(|at| at <= now) {
+            self.table.expire(now);
+            self.next_expiry = Some(now + EXPIRY_INTERVAL);
+        }
+        Ok(forwarded)
+    }
+}
+
+/// the next frame of a non-blocking device, `None` once it has none left
+fn recv<L: DataLayer>(device: &mut L, buf: &mut [u8]) -> Result<Option<usize>> {
+    loop {
+        match device.recv(buf) {
+            Ok(n) => return Ok(Some(n)),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -37,10 +37,10 @@ impl From<u16> for EtherType {
 	}
 }
 
-impl Into<u16> for EtherType {
-	fn into(self) -> u16 {
+impl From<EtherType> for u16 {
+	fn from(ty: EtherType) -> Self {
 		use EtherType::*;
-		match self {
+		match ty {
 			IPv4 => 0x0800,
 			IPv6 => 0x86DD,
 			Arp => 0x0806,
@@ -64,6 +64,17 @@ impl From<[u8; 2]> for EtherType {
 	}
 }
 
+impl EtherType {
+	/// the ethertype of an ip packet from its version field
+	pub fn of_ip_packet(packet: &[u8]) -> Option<Self> {
+		match packet.first().map(|b| b >> 4) {
+			Some(4) => Some(EtherType::IPv4),
+			Some(6) => Some(EtherType::IPv6),
+			_ => None,
+		}
+	}
+}
+
 
 // https://www.iana.org/assignments/protocol-numbers/protocol-numbers.xhtml
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -105,4 +116,55 @@ impl From<u8> for Protocol {
 			other => UnSupport(other)
 		}
 	}
-}
\ No newline at end of file
+}
+/// Differentiated services code point (RFC 2474), the upper six bits of the type of service byte,
+/// the lower two are ECN
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Dscp(u8);
+
+impl Dscp {
+	/// best effort, the default
+	pub const CS0: Dscp = Dscp(0);
+	/// lower effort (RFC 8622), bulk transfers yielding to everything else
+	pub const LE: Dscp = Dscp(1);
+	pub const CS1: Dscp = Dscp(8);
+	pub const AF11: Dscp = Dscp(10);
+	pub const AF12: Dscp = Dscp(12);
+	pub const AF13: Dscp = Dscp(14);
+	pub const CS2: Dscp = Dscp(16);
+	pub const AF21: Dscp = Dscp(18);
+	pub const AF22: Dscp = Dscp(20);
+	pub const AF23: Dscp = Dscp(22);
+	pub const CS3: Dscp = Dscp(24);
+	pub const AF31: Dscp = Dscp(26);
+	pub const AF32: Dscp = Dscp(28);
+	pub const AF33: Dscp = Dscp(30);
+	pub const CS4: Dscp = Dscp(32);
+	pub const AF41: Dscp = Dscp(34);
+	pub const AF42: Dscp = Dscp(36);
+	pub const AF43: Dscp = Dscp(38);
+	pub const CS5: Dscp = Dscp(40);
+	/// expedited forwarding (RFC 3246), low loss and latency for e.g. voice
+	pub const EF: Dscp = Dscp(46);
+	pub const CS6: Dscp = Dscp(48);
+	pub const CS7: Dscp = Dscp(56);
+
+	/// `None` if `value` doesn't fit six bits
+	pub fn new(value: u8) -> Option<Self> {
+		(value < 64).then_some(Dscp(value))
+	}
+
+	pub fn value(self) -> u8 {
+		self.0
+	}
+
+	/// the code point of a type of service byte
+	pub fn from_tos(tos: u8) -> Self {
+		Dscp(tos >> 2)
+	}
+
+	/// the type of service byte of this code point and the ECN bits of `tos`
+	pub fn with_ecn_of(self, tos: u8) -> u8 {
+		self.0 << 2 | tos & 0b11
+	}
+}
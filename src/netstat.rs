@@ -0,0 +1,70 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::fmt::Write;
+use core::net::SocketAddr;
+use core::time::Duration;
+
+use crate::tcp::vars::TcpState;
+
+/// Timer pending on a connection
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TimerKind {
+    /// 2*MSL in TIME-WAIT before the connection is forgotten
+    TimeWait,
+    /// retransmission of unacknowledged data or the end of the handshake
+    Retransmit,
+    /// probe of an idle connection
+    KeepAlive,
+    /// probe of the closed window of the peer
+    WindowProbe,
+}
+
+impl fmt::Display for TimerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimerKind::TimeWait => write!(f, "timewait"),
+            TimerKind::Retransmit => write!(f, "on"),
+            TimerKind::KeepAlive => write!(f, "keepalive"),
+            TimerKind::WindowProbe => write!(f, "probe"),
+        }
+    }
+}
+
+/// One line of `NetStack::connections`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ConnectionInfo {
+    pub local: SocketAddr,
+    /// `0.0.0.0:0` for listeners
+    pub remote: SocketAddr,
+    pub state: TcpState,
+    /// bytes received but not read yet, for listeners the connections waiting for `accept`
+    pub recv_queue: usize,
+    /// bytes written but not acknowledged yet
+    pub send_queue: usize,
+    /// the timer which fires next and the time left
+    pub timer: Option<(TimerKind, Duration)>,
+}
+
+/// `netstat -o` like line, without a header
+impl fmt::Display for ConnectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let timer = match self.timer {
+            Some((kind, left)) => format!("{} ({:.2})", kind, left.as_secs_f64()),
+            None => "off".to_string(),
+        };
+        write!(f, "{:<8}{:<8}{:<23}{:<23}{:<14}{}",
+               self.recv_queue, self.send_queue, self.local.to_string(), self.remote.to_string(),
+               self.state.to_string(), timer)
+    }
+}
+
+/// the connections as a table with a header line, for logs and debugging output
+pub fn format_table(connections: &[ConnectionInfo]) -> String {
+    let mut table = format!("{:<8}{:<8}{:<23}{:<23}{:<14}{}\n",
+                            "Recv-Q", "Send-Q", "Local Address", "Foreign Address", "State", "Timer");
+    for conn in connections {
+        let _ = writeln!(table, "{}", conn);
+    }
+    table
+}
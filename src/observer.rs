@@ -0,0 +1,119 @@
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "std")]
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::netstat::TimerKind;
+use crate::socket_addr::Quad;
+use crate::tcp::vars::TcpState;
+
+/// Events of the connections of a stack pushed to the application, see `NetStack::add_observer`
+///
+/// the methods are called on the thread processing the packets while the stack is locked,
+/// they must not use sockets of the same stack nor add or remove observers. `quad` is the
+/// (local, remote) pair of the connection. every method does nothing by default
+pub trait ConnectionObserver: Send + Sync {
+    /// the connection moved from state `from` to `to`
+    fn on_state_change(&self, _quad: &Quad, _from: TcpState, _to: TcpState) {}
+
+    /// the handshake completed
+    fn on_established(&self, _quad: &Quad) {}
+
+    /// `data` was received in order and queued for the application
+    fn on_data(&self, _quad: &Quad, _data: &[u8]) {}
+
+    /// the peer closed its side
+    fn on_fin(&self, _quad: &Quad) {}
+
+    /// the peer reset the connection
+    fn on_reset(&self, _quad: &Quad) {}
+
+    /// `len` bytes from `seq` were sent again
+    fn on_retransmit(&self, _quad: &Quad, _seq: u32, _len: usize) {}
+
+    /// the `kind` timer of the connection expired
+    fn on_timer(&self, _quad: &Quad, _kind: TimerKind) {}
+
+    /// the peer answered a keep-alive probe after `rtt`
+    fn on_keep_alive_answered(&self, _quad: &Quad, _rtt: Duration) {}
+
+    /// the last `unanswered` keep-alive probes went without answer and the next one is
+    /// due. `last_rtt` is how long the peer took for the last probe it answered. the
+    /// strictest verdict of the observers counts
+    fn on_keep_alive_unanswered(&self, _quad: &Quad, _unanswered: u32, _last_rtt: Option<Duration>) -> KeepAliveVerdict {
+        KeepAliveVerdict::Default
+    }
+}
+
+/// What a connection does about a peer which doesn't answer its keep-alive probes,
+/// from the most lenient to the strictest
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub enum KeepAliveVerdict {
+    /// abort once `ConnectionConfig::keep_alive_probes` went unanswered
+    #[default]
+    Default,
+    /// send the next probe, past the limit too
+    Probe,
+    /// give up on the peer, the connection times out
+    Abort,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ObserverId(u64);
+
+impl ObserverId {
+    #[cfg(feature = "capi")]
+    pub(crate) fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    #[cfg(feature = "capi")]
+    pub(crate) fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+/// Observers registered on a stack, shared by its shards and connections
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct Observers {
+    entries: RwLock<Vec<(ObserverId, Arc<dyn ConnectionObserver>)>>,
+    next_id: AtomicU64,
+}
+
+#[cfg(feature = "std")]
+impl Observers {
+    fn read(&self) -> RwLockReadGuard<'_, Vec<(ObserverId, Arc<dyn ConnectionObserver>)>> {
+        self.entries.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Vec<(ObserverId, Arc<dyn ConnectionObserver>)>> {
+        self.entries.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn add(&self, observer: Arc<dyn ConnectionObserver>) -> ObserverId {
+        let id = ObserverId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.write().push((id, observer));
+        id
+    }
+
+    pub(crate) fn remove(&self, id: ObserverId) -> bool {
+        let mut entries = self.write();
+        let len = entries.len();
+        entries.retain(|(other, _)| *other != id);
+        entries.len() != len
+    }
+
+    /// call `event` with every observer
+    pub(crate) fn notify<F: Fn(&dyn ConnectionObserver)>(&self, event: F) {
+        for (_, observer) in self.read().iter() {
+            event(observer.as_ref());
+        }
+    }
+
+    /// the greatest answer of the observers to `question`, the default without any
+    pub(crate) fn decide<T: Ord + Default, F: Fn(&dyn ConnectionObserver) -> T>(&self, question: F) -> T {
+        self.read().iter().map(|(_, observer)| question(observer.as_ref())).max().unwrap_or_default()
+    }
+}
@@ -0,0 +1,359 @@
+//! Gateway forwarding the connections of the stack to the host network
+//!
+//! every connection accepted on the TUN interface is connected to its destination with the
+//! host's tcp and the two are bridged, the classic userspace TUN gateway. the destination is
+//! either the address the peer dialed, with a `StackConfig::transparent` stack and a
+//! `TcpListener::bind_transparent` listener, or the one asked for with a SOCKS5 CONNECT
+//! (RFC 1928, without authentication).
+//!
+//! each direction is pumped by its own thread with blocking writes: while the receiving side
+//! doesn't take the data the pump stops reading and the window of the sending side closes.
+//! a FIN is forwarded as a shutdown of the other side, a reset as a reset
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, ToSocketAddrs};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::socket::{TcpListener, TcpStream};
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// SOCKS5 reply codes
+const SUCCEEDED: u8 = 0;
+const GENERAL_FAILURE: u8 = 1;
+const NETWORK_UNREACHABLE: u8 = 3;
+const HOST_UNREACHABLE: u8 = 4;
+const CONNECTION_REFUSED: u8 = 5;
+const COMMAND_NOT_SUPPORTED: u8 = 7;
+const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+/// How the gateway learns the destination of a connection
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Mode {
+    /// the local address of the connection, what the peer dialed
+    Transparent,
+    /// a SOCKS5 CONNECT request sent by the peer
+    Socks5,
+}
+
+/// Bridges the connections of a listener to the host network, one thread per direction
+#[derive(Debug, Clone)]
+pub struct Gateway {
+    mode: Mode,
+    connect_timeout: Duration,
+}
+
+impl Gateway {
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+
+    /// how long to wait for the host to connect to a destination
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// accept connections until the listener fails
+    pub fn serve(self, listener: &TcpListener) -> io::Result<()> {
+        let gateway = Arc::new(self);
+        loop {
+            let stream = listener.accept()?;
+            let gateway = gateway.clone();
+            thread::spawn(move || {
+                if let Err(e) = gateway.handle(&stream) {
+                    debug!(peer = %stream.peer_addr(), local = %stream.local_addr(), error = %e, "gateway connection failed");
+                }
+            });
+        }
+    }
+
+    /// connect `stream` to its destination and relay until both directions are closed,
+    /// the stream is reset if the destination can't be reached
+    pub fn handle(&self, stream: &TcpStream) -> io::Result<()> {
+        let upstream = match self.mode {
+            Mode::Transparent => {
+                let dest = SocketAddr::from(stream.local_addr());
+                match std::net::TcpStream::connect_timeout(&dest, self.connect_timeout) {
+                    Ok(upstream) => upstream,
+                    Err(e) => {
+                        let _ = stream.abort();
+                        return Err(e);
+                    }
+                }
+            }
+            Mode::Socks5 => self.socks5(stream)?,
+        };
+        let (sent, received) = relay(stream, &upstream)?;
+        debug!(peer = %stream.peer_addr(), upstream = ?upstream.peer_addr(), sent, received, "gateway connection closed");
+        Ok(())
+    }
+
+    /// the SOCKS5 negotiation, return the connection to the requested destination
+    fn socks5(&self, stream: &TcpStream) -> io::Result<std::net::TcpStream> {
+        let mut io = stream;
+        let mut head = [0; 2];
+        io.read_exact(&mut head)?;
+        if head[0] != SOCKS_VERSION {
+            return Err(io::Error::new(ErrorKind::InvalidData, "not a SOCKS5 client"));
+        }
+        let mut methods = vec![0; head[1] as usize];
+        io.read_exact(&mut methods)?;
+        if !methods.contains(&NO_AUTHENTICATION) {
+            io.write_all(&[SOCKS_VERSION, NO_ACCEPTABLE_METHOD])?;
+            return Err(io::Error::new(ErrorKind::PermissionDenied, "client requires authentication"));
+        }
+        io.write_all(&[SOCKS_VERSION, NO_AUTHENTICATION])?;
+
+        let mut request = [0; 4];
+        io.read_exact(&mut request)?;
+        let dest = match request[3] {
+            ATYP_IPV4 => {
+                let mut addr = [0; 4];
+                io.read_exact(&mut addr)?;
+                Destination::Addr(IpAddr::from(addr))
+            }
+            ATYP_IPV6 => {
+                let mut addr = [0; 16];
+                io.read_exact(&mut addr)?;
+                Destination::Addr(IpAddr::from(addr))
+            }
+            ATYP_DOMAIN => {
+                let mut len = [0];
+                io.read_exact(&mut len)?;
+                let mut name = vec![0; len[0] as usize];
+                io.read_exact(&mut name)?;
+                let name = String::from_utf8(name)
+                    .map_err(|_| io::Error::new(ErrorKind::InvalidData, "domain name is not utf-8"))?;
+                Destination::Domain(name)
+            }
+            _ => {
+                reply(stream, ADDRESS_TYPE_NOT_SUPPORTED, None)?;
+                return Err(io::Error::new(ErrorKind::InvalidData, "unknown address type"));
+            }
+        };
+        let mut port = [0; 2];
+        io.read_exact(&mut port)?;
+        let port = u16::from_be_bytes(port);
+        if request[0] != SOCKS_VERSION || request[1] != CMD_CONNECT {
+            reply(stream, COMMAND_NOT_SUPPORTED, None)?;
+            return Err(io::Error::new(ErrorKind::Unsupported, "only CONNECT is supported"));
+        }
+
+        match self.connect(&dest, port) {
+            Ok(upstream) => {
+                reply(stream, SUCCEEDED, upstream.local_addr().ok())?;
+                Ok(upstream)
+            }
+            Err(e) => {
+                reply(stream, reply_code(&e), None)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// the first address of `dest` accepting the connection
+    fn connect(&self, dest: &Destination, port: u16) -> io::Result<std::net::TcpStream> {
+        let addrs: Vec<SocketAddr> = match dest {
+            Destination::Addr(addr) => vec![SocketAddr::new(*addr, port)],
+            Destination::Domain(name) => (name.as_str(), port).to_socket_addrs()?.collect(),
+        };
+        let mut last = io::Error::new(ErrorKind::NotFound, "no address for the destination");
+        for addr in addrs {
+            match std::net::TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                Ok(upstream) => return Ok(upstream),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+}
+
+/// Destination of a SOCKS5 request
+enum Destination {
+    Addr(IpAddr),
+    Domain(String),
+}
+
+fn reply(stream: &TcpStream, code: u8, bound: Option<SocketAddr>) -> io::Result<()> {
+    let bound = bound.unwrap_or_else(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0));
+    let mut msg = vec![SOCKS_VERSION, code, 0];
+    match bound.ip() {
+        IpAddr::V4(addr) => {
+            msg.push(ATYP_IPV4);
+            msg.extend_from_slice(&addr.octets());
+        }
+        IpAddr::V6(addr) => {
+            msg.push(ATYP_IPV6);
+            msg.extend_from_slice(&addr.octets());
+        }
+    }
+    msg.extend_from_slice(&bound.port().to_be_bytes());
+    let mut io = stream;
+    io.write_all(&msg)
+}
+
+fn reply_code(e: &io::Error) -> u8 {
+    match (e.kind(), e.raw_os_error()) {
+        (ErrorKind::ConnectionRefused, _) => CONNECTION_REFUSED,
+        (_, Some(libc::ENETUNREACH)) => NETWORK_UNREACHABLE,
+        (_, Some(libc::EHOSTUNREACH)) | (ErrorKind::TimedOut, _) | (ErrorKind::NotFound, _) => HOST_UNREACHABLE,
+        _ => GENERAL_FAILURE,
+    }
+}
+
+/// Which side of a pump failed
+enum PumpError {
+    Read(io::Error),
+    Write(io::Error),
+}
+
+/// copy until `from` ends, return the number of bytes copied
+fn pump<R: Read, W: Write>(mut from: R, mut to: W) -> Result<u64, PumpError> {
+    let mut buf = [0; 16 * 1024];
+    let mut copied = 0;
+    loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) => return Ok(copied),
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(PumpError::Read(e)),
+        };
+        to.write_all(&buf[..n]).map_err(PumpError::Write)?;
+        copied += n as u64;
+    }
+}
+
+/// The host side of a relayed connection, read and written by a thread each
+pub trait Upstream: Sync {
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize>;
+
+    /// the connection sent its FIN, nothing more comes
+    fn shutdown_write(&self) -> io::Result<()>;
+
+    /// the connection failed or was reset, abort the other side too
+    fn reset(&self);
+}
+
+impl Upstream for std::net::TcpStream {
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        (&mut &*self).read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        (&mut &*self).write(buf)
+    }
+
+    fn shutdown_write(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+
+    /// the connection is reset when it's closed and the pump reading it stops
+    fn reset(&self) {
+        let linger = libc::linger { l_onoff: 1, l_linger: 0 };
+        unsafe {
+            libc::setsockopt(
+                self.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_LINGER,
+                &linger as *const libc::linger as *const libc::c_void,
+                std::mem::size_of::<libc::linger>() as libc::socklen_t,
+            );
+        }
+        let _ = self.shutdown(Shutdown::Read);
+    }
+}
+
+impl Upstream for UnixStream {
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        (&mut &*self).read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        (&mut &*self).write(buf)
+    }
+
+    fn shutdown_write(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+
+    /// a unix socket has no reset, both directions end
+    fn reset(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+/// `Read` and `Write` of an `Upstream` for the pumps
+struct Host<'a, U: ?Sized>(&'a U);
+
+impl<U: Upstream + ?Sized> Read for Host<'_, U> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<U: Upstream + ?Sized> Write for Host<'_, U> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// bridge `stream` and `upstream` until both directions are closed, return the bytes
+/// sent upstream and received from upstream. a FIN closes the other side for writing,
+/// a reset or a failure resets the other side
+pub fn relay<U: Upstream + ?Sized>(stream: &TcpStream, upstream: &U) -> io::Result<(u64, u64)> {
+    thread::scope(|scope| {
+        let down = scope.spawn(|| match pump(Host(upstream), stream) {
+            Ok(received) => {
+                // the stream may be reset already, the other pump reports it
+                let _ = stream.shutdown();
+                Ok(received)
+            }
+            Err(PumpError::Read(e)) => {
+                let _ = stream.abort();
+                Err(e)
+            }
+            Err(PumpError::Write(e)) => {
+                upstream.reset();
+                Err(e)
+            }
+        });
+        let up = match pump(stream, Host(upstream)) {
+            Ok(sent) => {
+                let _ = upstream.shutdown_write();
+                Ok(sent)
+            }
+            Err(PumpError::Read(e)) => {
+                upstream.reset();
+                Err(e)
+            }
+            Err(PumpError::Write(e)) => {
+                let _ = stream.abort();
+                Err(e)
+            }
+        };
+        let down = down.join().expect("relay thread panicked");
+        Ok((up?, down?))
+    })
+}
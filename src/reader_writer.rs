@@ -1,54 +1,20 @@
-use std::io::{BufWriter, Write};
-use std::net::Ipv4Addr;
+use std::io::Write;
 
-use etherparse::{Ipv4HeaderSlice, Ipv6HeaderSlice, TcpHeaderSlice};
-use etherparse::WriteError;
+use etherparse::err::{ipv4, ipv6, tcp, LenError};
+use etherparse::{IpNumber, Ipv4HeaderSlice, Ipv6HeaderSlice, TcpHeaderSlice};
 
-use crate::meta::{ETHERNET_MTU, TUN_SIZE};
+use crate::buffer::{BufferPool, PooledBuf};
+use crate::meta::TUN_SIZE;
+use crate::net_types::EtherType;
 use crate::result;
-use crate::result::Error;
+use crate::socket_addr::Quad;
+use crate::tcp::options::{self, Options};
 use crate::tcp::packet::TcpIpHeader;
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
-pub struct Quad {
-    src: Addr,
-    dest: Addr,
-}
-
-impl Quad {
-    pub fn new(src: Addr, dest: Addr) -> Self {
-        Self {
-            src,
-            dest,
-        }
-    }
-    pub fn from_tcpip_header<'a>(ip_header: &Ipv4HeaderSlice<'a>, tcp_header: &TcpHeaderSlice<'a>) -> Self {
-        Self::new(
-            Addr::new(ip_header.source_addr(), tcp_header.source_port()),
-            Addr::new(ip_header.destination_addr(), tcp_header.destination_port()),
-        )
-    }
-}
-
-
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
-pub struct Addr {
-    ip: Ipv4Addr,
-    port: u16,
-}
-
-impl Addr {
-    pub fn new(ip: Ipv4Addr, port: u16) -> Self {
-        Self {
-            ip,
-            port,
-        }
-    }
-}
-
+/// Reads the headers of a received frame in place. every accessor checks the length of the
+/// frame first, a frame too short for what is asked gives `Error::Truncated`
 pub struct RawReader<'a> {
-    /// the offset of ip header
-    /// this is zero If no packet info needs to be provided add corresponding flag with tuntap
+    /// the offset of ip header, the length of the link header of the device
     offset: usize,
     /// thw raw buffer
     buf: &'a [u8],
@@ -57,38 +23,62 @@ pub struct RawReader<'a> {
 }
 
 impl<'a> RawReader<'a> {
-    pub fn from_slice(buf: &'a [u8], nread: usize, offset: usize) -> RawReader {
+    /// the frame is the first `nread` bytes of `buf`, at most all of them
+    pub fn from_slice(buf: &'a [u8], nread: usize, offset: usize) -> RawReader<'a> {
         Self {
             offset,
             buf,
-            len: nread,
+            len: nread.min(buf.len()),
             data_offset: None,
         }
     }
 
-    pub fn offset_data(&self) -> &'a [u8] {
-        &self.buf[..self.offset]
+    /// the link header in front of the ip packet
+    pub fn offset_data(&self) -> result::Result<&'a [u8]> {
+        self.check_link_header()?;
+        Ok(&self.buf[..self.offset])
+    }
+
+    /// the ip packet behind the link header, with the padding of the frame
+    pub fn packet(&self) -> result::Result<&'a [u8]> {
+        self.check_link_header()?;
+        Ok(&self.buf[self.offset..self.len])
+    }
+
+    fn check_link_header(&self) -> result::Result<()> {
+        if self.offset > self.len {
+            return Err(result::Error::Truncated { needed: self.offset, available: self.len });
+        }
+        Ok(())
     }
 
     pub fn ipv4_header(&self) -> result::Result<Ipv4HeaderSlice<'a>> {
-        Ok(Ipv4HeaderSlice::from_slice(&self.buf[self.offset..self.len])?)
+        Ipv4HeaderSlice::from_slice(self.packet()?).map_err(|e| match e {
+            ipv4::HeaderSliceError::Len(e) => self.len_error(e, 0),
+            ipv4::HeaderSliceError::Content(e) => e.into(),
+        })
     }
 
     pub fn ipv6_header(&self) -> result::Result<Ipv6HeaderSlice<'a>> {
-        Ok(Ipv6HeaderSlice::from_slice(&self.buf[self.offset..self.len])?)
+        Ipv6HeaderSlice::from_slice(self.packet()?).map_err(|e| match e {
+            ipv6::HeaderSliceError::Len(e) => self.len_error(e, 0),
+            ipv6::HeaderSliceError::Content(e) => e.into(),
+        })
+    }
+
+    pub fn is_ipv6_packet(&self) -> result::Result<bool> {
+        Ok(self.version()? == 6)
     }
 
-    pub fn is_ipv6_packet(&self) -> bool {
-        let version = self.buf[self.offset + 0] >> 4;
-        version == 6
+    pub fn is_ipv4_packet(&self) -> result::Result<bool> {
+        Ok(self.version()? == 4)
     }
 
-    pub fn is_ipv4_packet(&self) -> bool {
-        let version = {
-            let value = self.buf[self.offset + 0];
-            value >> 4
-        };
-        version == 4
+    fn version(&self) -> result::Result<u8> {
+        match self.packet()?.first() {
+            Some(first) => Ok(first >> 4),
+            None => Err(self.truncated(1)),
+        }
     }
 
     pub fn tcp_header(&mut self) -> result::Result<TcpHeaderSlice<'a>> {
@@ -98,8 +88,14 @@ impl<'a> RawReader<'a> {
 
     pub fn tcp_ip_header(&mut self) -> result::Result<(Ipv4HeaderSlice<'a>, TcpHeaderSlice<'a>)> {
         let ipheader = self.ipv4_header()?;
+        if ipheader.protocol() != IpNumber::TCP {
+            return Err(result::Error::UnsupportedProtocol(ipheader.protocol().0));
+        }
         let ip_h_len = ipheader.slice().len();
-        let tcp_h = TcpHeaderSlice::from_slice(&self.buf[self.offset + ip_h_len..self.len])?;
+        let tcp_h = TcpHeaderSlice::from_slice(&self.packet()?[ip_h_len..]).map_err(|e| match e {
+            tcp::HeaderSliceError::Len(e) => self.len_error(e, ip_h_len),
+            tcp::HeaderSliceError::Content(e) => e.into(),
+        })?;
         let tcp_len = tcp_h.slice().len();
         if self.data_offset.is_none() {
             self.data_offset = Some(self.offset + ip_h_len + tcp_len);
@@ -107,57 +103,157 @@ impl<'a> RawReader<'a> {
         Ok((ipheader, tcp_h))
     }
 
-    pub fn data_offset(&mut self) -> usize {
-        if self.data_offset.is_none() {
-            self.tcp_header()?;
+    /// where the tcp payload starts in the frame
+    pub fn data_offset(&mut self) -> result::Result<usize> {
+        match self.data_offset {
+            Some(offset) => Ok(offset),
+            None => {
+                let (ip, tcp) = self.tcp_ip_header()?;
+                Ok(self.offset + ip.slice().len() + tcp.slice().len())
+            }
+        }
+    }
+
+    /// the tcp segment of the frame, headers and payload borrow the frame
+    pub fn segment(&mut self) -> result::Result<Segment<'a>> {
+        let (ip, tcp) = self.tcp_ip_header()?;
+        let start = self.data_offset()?;
+        // ignore the ethernet padding after the ip packet
+        let end = self.offset + ip.total_len() as usize;
+        if end > self.len {
+            return Err(self.truncated(ip.total_len() as usize));
+        }
+        // the total length doesn't even cover the headers
+        if end < start {
+            return Err(result::Error::Truncated { needed: start - self.offset, available: ip.total_len() as usize });
         }
-        self.data_offset.unwrap()
+        Ok(Segment { ip, tcp, payload: &self.buf[start..end] })
+    }
+
+    /// the ip packet is shorter than the `needed` bytes
+    fn truncated(&self, needed: usize) -> result::Error {
+        result::Error::Truncated { needed, available: self.len.saturating_sub(self.offset) }
+    }
+
+    /// a header running past the end of the packet, `at` bytes into it, is truncated
+    fn len_error(&self, e: LenError, at: usize) -> result::Error {
+        self.truncated(at + e.required_len)
+    }
+}
+
+/// A received tcp segment, the headers are parsed in place and the payload is a slice of the frame
+#[derive(Debug, Clone)]
+pub struct Segment<'a> {
+    ip: Ipv4HeaderSlice<'a>,
+    tcp: TcpHeaderSlice<'a>,
+    payload: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    /// parse the ipv4 packet after `link_header_len` bytes of `frame`
+    pub fn parse(frame: &'a [u8], link_header_len: usize) -> result::Result<Self> {
+        RawReader::from_slice(frame, frame.len(), link_header_len).segment()
+    }
+
+    pub fn ip(&self) -> &Ipv4HeaderSlice<'a> {
+        &self.ip
+    }
+
+    pub fn tcp(&self) -> &TcpHeaderSlice<'a> {
+        &self.tcp
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    pub fn options(&self) -> Options<'_> {
+        options::parse(self.tcp.options())
+    }
+
+    /// sender and receiver
+    pub fn quad(&self) -> Quad {
+        Quad::from_tcpip_header(&self.ip, &self.tcp)
+    }
+
+    /// the tcp checksum matches the pseudo header, header and payload
+    pub fn checksum_valid(&self) -> result::Result<bool> {
+        Ok(self.tcp.calc_checksum_ipv4(&self.ip, self.payload)? == self.tcp.checksum())
     }
 }
 
 
+/// `struct tun_pi`: the flags in host byte order and the ethertype of the packet in network byte order
+pub fn tuntap_header(flags: u16, proto: EtherType) -> [u8; TUN_SIZE] {
+    let [f0, f1] = flags.to_ne_bytes();
+    let [p0, p1] = u16::from(proto).to_be_bytes();
+    [f0, f1, p0, p1]
+}
+
 pub struct RawWriter {
     offset: usize,
-    buf: BufWriter<Vec<u8>>,
+    buf: PooledBuf,
 }
 
 impl RawWriter {
+    /// a frame without link header, devices add their own framing
     pub fn with_default_offset() -> Self {
-        Self::new(TUN_SIZE)
+        Self::new(0)
     }
     pub fn change_offset(&mut self, offset: usize) {
         self.offset = offset;
     }
 
+    /// bytes written so far
     pub fn buffer(&self) -> &[u8] {
-        self.buf.buffer()
+        &self.buf
     }
 
+    /// a frame of up to `capacity` bytes without reallocation, e.g. the mtu and the link header
     pub fn with_capacity(capacity: usize) -> Self {
-        assert!(capacity <= ETHERNET_MTU, "capacity must less or equal ETHERNET_MTU(1500)");
         Self {
-            offset: TUN_SIZE,
-            buf: BufWriter::new(Vec::with_capacity(capacity)),
+            offset: 0,
+            buf: PooledBuf::unpooled(capacity),
         }
     }
+
+    /// the frame is built in a buffer of `BufferPool::global`
     pub fn new(offset: usize) -> Self {
+        Self::with_pool(offset, BufferPool::global())
+    }
+
+    pub fn with_pool(offset: usize, pool: &BufferPool) -> Self {
         Self {
             offset,
-            buf: BufWriter::new(Vec::with_capacity(ETHERNET_MTU)),
+            buf: pool.get(),
         }
     }
 
-    pub fn write_tuntap_header(&mut self, version: u16, flags: u16) -> result::Result<()> {
-        let ver_buf: [u8; 2] = version.to_le_bytes();
-        let flag_buf: [u8; 2] = flags.to_le_bytes();
-        self.buf.write(&ver_buf)?;
-        self.buf.write(&flag_buf)?;
+    /// return the built frame, its buffer goes back to the pool once dropped
+    pub fn finalize(self) -> result::Result<PooledBuf> {
+        Ok(self.buf)
+    }
+
+    /// the packet information of a TUN device without IFF_NO_PI
+    pub fn write_tuntap_header(&mut self, flags: u16, proto: EtherType) -> result::Result<()> {
+        self.buf.write_all(&tuntap_header(flags, proto))?;
         Ok(())
     }
 
     pub fn write_header(&mut self, packet: &TcpIpHeader) -> result::Result<()> {
-        packet.ip_header.write(&mut self.buf)?;
-        packet.tcp_header.write(&mut self.buf)?;
+        self.buf.write_all(&packet.ip_header_bytes())?;
+        self.buf.write_all(&packet.tcp_header.to_bytes())?;
+        Ok(())
+    }
+
+    /// reserve `len` bytes of link header in front of the ip header
+    pub fn write_link_header(&mut self, len: usize) -> result::Result<()> {
+        self.buf.write_all(&vec![0_u8; len])?;
+        Ok(())
+    }
+
+    pub fn write_payload(&mut self, payload: &[u8]) -> result::Result<()> {
+        self.buf.write_all(payload)?;
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,25 +1,108 @@
-pub type Result<T> = std::result::Result<T, Error>;
+use core::net::{IpAddr, SocketAddr};
 
+use thiserror::Error;
 
-#[derive(Debug)]
+use crate::io;
+use crate::tcp::vars::TcpState;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
 pub enum Error {
-    StdIOError(std::io::Error),
-    WriteError(etherparse::WriteError),
-    ReadError(etherparse::ReadError),
-    ValueError(etherparse::ValueError),
+    #[error(transparent)]
+    StdIOError(#[from] io::Error),
+    #[error("read packet: {0}")]
+    Ipv4Header(#[from] etherparse::err::ipv4::HeaderError),
+    #[error("read packet: {0}")]
+    Ipv6Header(#[from] etherparse::err::ipv6::HeaderError),
+    #[error("read packet: {0}")]
+    TcpHeader(#[from] etherparse::err::tcp::HeaderError),
+    #[error("invalid packet field: {0}")]
+    ValueError(#[from] etherparse::err::ValueTooBigError<usize>),
+    #[error("connection reset by peer")]
+    ConnectionReset,
+    #[error("connection refused")]
+    ConnectionRefused,
+    #[error("operation timed out")]
+    TimedOut,
+    /// the operation needs the connection in another state
+    #[error("invalid in state {0}")]
+    InvalidState(TcpState),
+    /// `len` bytes don't fit into a window of `window` bytes
+    #[error("{len} bytes exceed the window of {window} bytes")]
+    WindowOverflow { len: usize, window: usize },
+    #[error("checksum mismatch")]
+    ChecksumMismatch,
+    #[error("address {0} already in use")]
+    AddressInUse(SocketAddr),
+    /// the local address of the connection was removed from the stack
+    #[error("address {0} removed")]
+    AddressRemoved(IpAddr),
+    /// the stack only speaks ipv4
+    #[error("address family of {0} not supported")]
+    AddressFamily(SocketAddr),
+    /// a tcp option with a length its kind doesn't allow or running past the header
+    #[error("malformed tcp option of kind {0}")]
+    InvalidOption(u8),
+    /// options don't fit into the 40 bytes of a tcp or ip header
+    #[error("{0} bytes of options exceed the header")]
+    OptionsTooLong(usize),
+    /// a TCP-AO key of the peer has the same SendID or RecvID
+    #[error("TCP-AO key id {0} already in use")]
+    KeyIdInUse(u8),
+    /// the TCP-AO key is the current or the RNext key of a connection
+    #[error("TCP-AO key {0} in use by a connection")]
+    KeyInUse(u8),
+    /// the connection has no TCP-AO key with the id
+    #[error("no TCP-AO key with id {0}")]
+    UnknownKey(u8),
+    /// `NetStack::shutdown` is in progress
+    #[error("stack is shutting down")]
+    ShuttingDown,
+    /// the frame ends before a header or the length its ip header gives, `needed`
+    /// bytes were read where only `available` are
+    #[error("truncated packet: {needed} bytes needed, {available} available")]
+    Truncated { needed: usize, available: usize },
+    /// ip protocol number the stack doesn't handle
+    #[error("unsupported protocol {0}")]
+    UnsupportedProtocol(u8),
+    /// the network device was removed, or its file descriptor detached from it
+    #[error("network device gone")]
+    DeviceGone,
 }
 
-macro_rules! impl_error {
-    ($err:ty,$en:ident) => {
-        impl From<$err> for Error {
-            fn from(err: $err) -> Self {
-                Error::$en(err)
-            }
+impl Error {
+    /// the closest `std::io::ErrorKind`, used when the error becomes an `io::Error`
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::StdIOError(e) => e.kind(),
+            Error::Ipv4Header(_) | Error::Ipv6Header(_) | Error::TcpHeader(_) | Error::ValueError(_) => io::ErrorKind::InvalidData,
+            Error::ConnectionReset => io::ErrorKind::ConnectionReset,
+            Error::ConnectionRefused => io::ErrorKind::ConnectionRefused,
+            Error::TimedOut => io::ErrorKind::TimedOut,
+            Error::InvalidState(_) => io::ErrorKind::NotConnected,
+            Error::WindowOverflow { .. } | Error::ChecksumMismatch => io::ErrorKind::InvalidData,
+            Error::InvalidOption(_) | Error::OptionsTooLong(_) => io::ErrorKind::InvalidData,
+            Error::Truncated { .. } => io::ErrorKind::UnexpectedEof,
+            Error::AddressInUse(_) => io::ErrorKind::AddrInUse,
+            Error::AddressRemoved(_) => io::ErrorKind::AddrNotAvailable,
+            Error::ShuttingDown => io::ErrorKind::ConnectionAborted,
+            Error::DeviceGone => io::ErrorKind::NetworkDown,
+            Error::KeyIdInUse(_) => io::ErrorKind::AlreadyExists,
+            Error::KeyInUse(_) => io::ErrorKind::ResourceBusy,
+            Error::UnknownKey(_) => io::ErrorKind::NotFound,
+            Error::AddressFamily(_) | Error::UnsupportedProtocol(_) => io::ErrorKind::Unsupported,
         }
-    };
+    }
 }
 
-impl_error!(std::io::Error,StdIOError);
-impl_error!(etherparse::WriteError,WriteError);
-impl_error!(etherparse::ReadError,ReadError);
-impl_error!(etherparse::ValueError,ValueError);
+/// io errors are unwrapped, the others keep the error as the source of an `io::Error` of the same kind
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::StdIOError(e) => e,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+/// xorshift64*, the same sequence for the same seed on every platform,
+/// for the random decisions of simulated and faulty links
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // zero is a fixed point of xorshift
+        Rng(seed.max(1))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// true with probability `p`
+    pub(crate) fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    /// uniformly distributed in `0..n`
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        match n {
+            0 => 0,
+            n => (self.next_u64() % n as u64) as usize,
+        }
+    }
+
+    /// uniformly distributed in `0..=max`
+    pub(crate) fn duration(&mut self, max: Duration) -> Duration {
+        match max.as_nanos() as u64 {
+            0 => max,
+            nanos => Duration::from_nanos(self.next_u64() % (nanos + 1)),
+        }
+    }
+}
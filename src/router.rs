@@ -0,0 +1,239 @@
+//! IP forwarding between interfaces
+//!
+//! `Router` reads the packets of its interfaces and sends them out of the interface
+//! the routing table picks for their destination with the time to live decremented.
+//! a packet whose time to live runs out is answered with ICMP Time Exceeded, so
+//! traceroute shows the router, one without route with Destination Unreachable. a
+//! user stack joins through one end of a `Loopback` pair attached as an interface
+
+use std::io::{Error, ErrorKind, Result};
+use std::net::Ipv4Addr;
+
+use tracing::{debug, trace};
+
+use crate::checksum::{adjust, Checksum};
+use crate::data_link::{recv_buffer_len, DataLayer};
+use crate::icmp::{IcmpBuilder, IcmpError};
+
+/// flag of the fragment field, the packet may not be fragmented
+const DONT_FRAGMENT: u8 = 0x40;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct InterfaceId(usize);
+
+/// Packets to `dest`/`prefix_len` leave through `interface`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Route {
+    pub dest: Ipv4Addr,
+    pub prefix_len: u8,
+    pub interface: InterfaceId,
+}
+
+impl Route {
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::MAX.checked_shl(32 - self.prefix_len.min(32) as u32).unwrap_or(0);
+        u32::from(addr) & mask == u32::from(self.dest) & mask
+    }
+}
+
+/// Routes picked by longest prefix match
+#[derive(Debug, Clone, Default)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add `route`, replacing one to the same prefix
+    pub fn add(&mut self, route: Route) {
+        self.remove(route.dest, route.prefix_len);
+        self.routes.push(route);
+    }
+
+    /// return false if there was no route to the prefix
+    pub fn remove(&mut self, dest: Ipv4Addr, prefix_len: u8) -> bool {
+        let len = self.routes.len();
+        self.routes.retain(|route| (route.dest, route.prefix_len) != (dest, prefix_len));
+        self.routes.len() != len
+    }
+
+    /// the most specific route to `addr`
+    pub fn lookup(&self, addr: Ipv4Addr) -> Option<&Route> {
+        self.routes.iter()
+            .filter(|route| route.contains(addr))
+            .max_by_key(|route| route.prefix_len)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+}
+
+/// What happened to the packets a router received
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ForwardingStats {
+    pub forwarded: u64,
+    /// dropped, their time to live ran out
+    pub ttl_exceeded: u64,
+    /// ICMP errors sent
+    pub icmp_errors: u64,
+    pub no_route: u64,
+    /// larger than the mtu of the outgoing interface
+    pub too_big: u64,
+    /// addressed to the router itself, it serves nothing
+    pub local: u64,
+    /// no ipv4 packet or a bad header checksum
+    pub malformed: u64,
+}
+
+struct Interface {
+    device: Box<dyn DataLayer + Send>,
+    addr: Ipv4Addr,
+}
+
+/// Forwards ipv4 packets between interfaces carrying bare ip packets, without link header
+#[derive(Default)]
+pub struct Router {
+    interfaces: Vec<Interface>,
+    routes: RoutingTable,
+    stats: ForwardingStats,
+    buf: Vec<u8>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// attach `device` with the address `addr`, its subnet `addr`/`prefix_len` is routed to it
+    pub fn add_interface<L: DataLayer + Send + 'static>(&mut self, mut device: L, addr: Ipv4Addr, prefix_len: u8) -> Result<InterfaceId> {
+        if device.header_len() != 0 {
+            return Err(Error::new(ErrorKind::Unsupported, "forwarding needs devices without link header"));
+        }
+        device.set_nonblocking(true)?;
+        let len = recv_buffer_len(&device, device.mtu());
+        self.buf.resize(self.buf.len().max(len), 0);
+        let interface = InterfaceId(self.interfaces.len());
+        self.interfaces.push(Interface { device: Box::new(device), addr });
+        self.routes.add(Route { dest: addr, prefix_len, interface });
+        Ok(interface)
+    }
+
+    pub fn routes(&self) -> &RoutingTable {
+        &self.routes
+    }
+
+    pub fn routes_mut(&mut self) -> &mut RoutingTable {
+        &mut self.routes
+    }
+
+    pub fn stats(&self) -> ForwardingStats {
+        self.stats
+    }
+
+    /// forward every queued packet of every interface, return the number forwarded
+    pub fn process(&mut self) -> Result<usize> {
+        let forwarded = self.stats.forwarded;
+        let mut buf = std::mem::take(&mut self.buf);
+        let result = (0..self.interfaces.len()).try_for_each(|ingress| {
+            loop {
+                match self.interfaces[ingress].device.recv(&mut buf) {
+                    Ok(n) => self.forward(InterfaceId(ingress), &mut buf[..n])?,
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        });
+        self.buf = buf;
+        result.map(|_| (self.stats.forwarded - forwarded) as usize)
+    }
+
+    fn forward(&mut self, ingress: InterfaceId, packet: &mut [u8]) -> Result<()> {
+        let ihl = match header_len(packet) {
+            Some(ihl) => ihl,
+            None => {
+                self.stats.malformed += 1;
+                return Ok(());
+            }
+        };
+        let dest = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+        if self.interfaces.iter().any(|interface| interface.addr == dest) {
+            self.stats.local += 1;
+            return Ok(());
+        }
+        // the packet is quoted by errors as it arrived, the time to live goes down last
+        if packet[8] <= 1 {
+            self.stats.ttl_exceeded += 1;
+            trace!(%dest, "time to live exceeded");
+            return self.send_error(ingress, IcmpError::TimeExceeded, packet);
+        }
+        let egress = match self.routes.lookup(dest) {
+            Some(route) => route.interface,
+            None => {
+                self.stats.no_route += 1;
+                debug!(%dest, "no route");
+                return self.send_error(ingress, IcmpError::NetUnreachable, packet);
+            }
+        };
+        let mtu = self.interfaces[egress.0].device.mtu();
+        if packet.len() > mtu {
+            self.stats.too_big += 1;
+            // without fragmentation a packet which allows it is lost like on a congested link
+            if packet[6] & DONT_FRAGMENT != 0 {
+                let mtu = mtu.min(u16::MAX as usize) as u16;
+                return self.send_error(ingress, IcmpError::FragmentationNeeded { mtu }, packet);
+            }
+            return Ok(());
+        }
+        decrement_ttl(&mut packet[..ihl]);
+        self.interfaces[egress.0].device.send(packet)?;
+        self.stats.forwarded += 1;
+        Ok(())
+    }
+
+    /// send `error` about `packet` from the address of `ingress` along the route to its
+    /// source, or back out of `ingress`
+    fn send_error(&mut self, ingress: InterfaceId, error: IcmpError, packet: &[u8]) -> Result<()> {
+        let icmp = IcmpBuilder::new(self.interfaces[ingress.0].addr);
+        let message = match icmp.error(error, packet) {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let dest = Ipv4Addr::new(message[16], message[17], message[18], message[19]);
+        let egress = self.routes.lookup(dest).map_or(ingress, |route| route.interface);
+        self.interfaces[egress.0].device.send(&message)?;
+        self.stats.icmp_errors += 1;
+        Ok(())
+    }
+}
+
+/// length of the header of a well formed ipv4 packet
+fn header_len(packet: &[u8]) -> Option<usize> {
+    let ihl = (*packet.first()? & 0xf) as usize * 4;
+    if packet[0] >> 4 != 4 || ihl < 20 || packet.len() < ihl {
+        return None;
+    }
+    let mut checksum = Checksum::new();
+    checksum.add(&packet[..ihl]);
+    (checksum.finish() == 0).then_some(ihl)
+}
+
+/// decrement the time to live of the packet to forward, false if it ran out
+pub(crate) fn decrement_ttl(packet: &mut [u8]) -> bool {
+    if packet.len() < 20 {
+        return false;
+    }
+    match packet[8..10] {
+        [ttl, protocol] if ttl > 1 => {
+            let checksum = u16::from_be_bytes([packet[10], packet[11]]);
+            packet[8] = ttl - 1;
+            packet[10..12].copy_from_slice(&adjust(checksum, &[ttl, protocol], &[ttl - 1, protocol]).to_be_bytes());
+            true
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,307 @@
+//! Deterministic simulation
+//!
+//! stacks created with `NetStack::manual` and a `SimClock` in their `StackConfig` talk over
+//! `SimNetwork` links, `Simulation` drives them and moves the virtual clock straight to the
+//! next frame delivery or timer, so delays and timeouts cost no real time and a seed
+//! reproduces the same losses, duplicates and reorderings on every run
+
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+use crate::data_link::faulty::DelayQueue;
+use crate::data_link::DataLayer;
+use crate::result;
+use crate::rng::Rng;
+use crate::stack::StackDriver;
+
+/// Virtual clock which only moves when it's advanced
+#[derive(Debug, Clone)]
+pub struct SimClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimClock {
+    /// starts at the current system time, `Instant` has no other origin
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.lock() += by;
+    }
+
+    /// move forward to `at`, the clock never goes back
+    pub fn advance_to(&self, at: Instant) {
+        let mut now = self.lock();
+        if at > *now {
+            *now = at;
+        }
+    }
+
+    /// the clock of a `StackConfig`
+    pub fn as_clock(&self) -> Arc<dyn Clock> {
+        Arc::new(self.clone())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Instant> {
+        self.now.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> Instant {
+        *self.lock()
+    }
+}
+
+/// Behaviour of one direction of a `SimNetwork` link
+#[derive(Debug, Copy, Clone)]
+pub struct LinkConditions {
+    /// one way delay of every frame
+    pub delay: Duration,
+    /// a random extra delay up to `jitter`, frames can overtake each other
+    pub jitter: Duration,
+    /// probability a frame is dropped
+    pub loss: f64,
+    /// probability a frame is held back by `reorder_delay`
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+    /// probability a frame is delivered twice
+    pub duplicate: f64,
+    /// seed of the random decisions
+    pub seed: u64,
+}
+
+impl Default for LinkConditions {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+            loss: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::from_millis(0),
+            duplicate: 0.0,
+            seed: 1,
+        }
+    }
+}
+
+/// What happened to the frames sent in one direction
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct LinkStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+}
+
+/// one direction of a link
+struct Channel {
+    conditions: LinkConditions,
+    rng: Rng,
+    in_flight: DelayQueue,
+    stats: LinkStats,
+}
+
+impl Channel {
+    fn new(conditions: LinkConditions) -> Self {
+        Self {
+            rng: Rng::new(conditions.seed),
+            conditions,
+            in_flight: DelayQueue::default(),
+            stats: LinkStats::default(),
+        }
+    }
+
+    fn send(&mut self, now: Instant, frame: &[u8]) {
+        let conditions = self.conditions;
+        self.stats.sent += 1;
+        if self.rng.chance(conditions.loss) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let copies = if self.rng.chance(conditions.duplicate) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut at = now + conditions.delay + self.rng.duration(conditions.jitter);
+            if self.rng.chance(conditions.reorder) {
+                self.stats.reordered += 1;
+                at += conditions.reorder_delay;
+            }
+            self.in_flight.push(at, frame.to_vec());
+        }
+    }
+
+    /// the next frame due at `now`
+    fn deliver(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let frame = self.in_flight.pop_due(now)?;
+        self.stats.delivered += 1;
+        Some(frame)
+    }
+}
+
+/// Endpoint of a simulated link, frames are received once the `SimClock` reached
+/// their delivery time. `recv` never blocks, the clock wouldn't move while waiting
+pub struct SimNetwork {
+    clock: SimClock,
+    tx: Arc<Mutex<Channel>>,
+    rx: Arc<Mutex<Channel>>,
+}
+
+impl SimNetwork {
+    /// two connected endpoints with the same `conditions` in both directions
+    pub fn pair(clock: &SimClock, conditions: LinkConditions) -> (SimNetwork, SimNetwork) {
+        let reverse = LinkConditions {
+            seed: conditions.seed.rotate_left(32) ^ 0x9e37_79b9_7f4a_7c15,
+            ..conditions
+        };
+        Self::pair_with(clock, conditions, reverse)
+    }
+
+    /// `a_to_b` applies to the frames sent by the first endpoint
+    pub fn pair_with(clock: &SimClock, a_to_b: LinkConditions, b_to_a: LinkConditions) -> (SimNetwork, SimNetwork) {
+        let a_to_b = Arc::new(Mutex::new(Channel::new(a_to_b)));
+        let b_to_a = Arc::new(Mutex::new(Channel::new(b_to_a)));
+        let a = SimNetwork { clock: clock.clone(), tx: a_to_b.clone(), rx: b_to_a.clone() };
+        let b = SimNetwork { clock: clock.clone(), tx: b_to_a, rx: a_to_b };
+        (a, b)
+    }
+
+    /// conditions of the frames sent from now on
+    pub fn set_conditions(&mut self, conditions: LinkConditions) {
+        let mut tx = lock(&self.tx);
+        tx.rng = Rng::new(conditions.seed);
+        tx.conditions = conditions;
+    }
+
+    /// what happened to the frames sent by this endpoint
+    pub fn stats(&self) -> LinkStats {
+        lock(&self.tx).stats
+    }
+
+    /// when the next frame for this endpoint arrives
+    pub fn next_delivery(&self) -> Option<Instant> {
+        lock(&self.rx).in_flight.next_due()
+    }
+}
+
+impl DataLayer for SimNetwork {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        lock(&self.tx).send(self.clock.now(), data);
+        Ok(data.len())
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<usize> {
+        let frame = lock(&self.rx).deliver(self.clock.now())
+            .ok_or_else(|| Error::new(ErrorKind::WouldBlock, "no frame due"))?;
+        // like a real device, a frame larger than the buffer is truncated
+        let n = frame.len().min(data.len());
+        data[..n].copy_from_slice(&frame[..n]);
+        Ok(n)
+    }
+
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn lock(channel: &Mutex<Channel>) -> MutexGuard<'_, Channel> {
+    channel.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Drives the stacks of a simulation and advances their shared clock
+/// from one event to the next
+pub struct Simulation {
+    clock: SimClock,
+    drivers: Vec<StackDriver<SimNetwork>>,
+}
+
+impl Simulation {
+    pub fn new(clock: SimClock) -> Self {
+        Self {
+            clock,
+            drivers: Vec::new(),
+        }
+    }
+
+    pub fn clock(&self) -> &SimClock {
+        &self.clock
+    }
+
+    /// the driver returned by `NetStack::manual` for a stack using `clock`
+    pub fn add(&mut self, driver: StackDriver<SimNetwork>) {
+        self.drivers.push(driver);
+    }
+
+    /// let every stack receive its due frames, send what is queued and run its timers
+    pub fn process(&mut self) -> result::Result<()> {
+        for driver in &mut self.drivers {
+            driver.process()?;
+        }
+        Ok(())
+    }
+
+    /// earliest frame delivery or timer of all stacks
+    pub fn next_event(&mut self) -> Option<Instant> {
+        let now = self.clock.now();
+        self.drivers.iter_mut()
+            .flat_map(|driver| {
+                let timer = driver.next_timeout().map(|timeout| now + timeout);
+                let frame = driver.device_mut().next_delivery();
+                timer.into_iter().chain(frame)
+            })
+            .min()
+    }
+
+    /// process, then jump to the next event, return false once nothing is pending
+    pub fn step(&mut self) -> result::Result<bool> {
+        self.process()?;
+        match self.next_event() {
+            Some(at) => {
+                self.clock.advance_to(at);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// step until `done` returns true, false if it didn't within `limit` of virtual time
+    pub fn run_until<F: FnMut() -> bool>(&mut self, limit: Duration, mut done: F) -> result::Result<bool> {
+        let deadline = self.clock.now() + limit;
+        loop {
+            // what `done` queued is sent by the next `process`
+            if done() {
+                return Ok(true);
+            }
+            self.process()?;
+            match self.next_event() {
+                Some(at) if at <= deadline => self.clock.advance_to(at),
+                _ => {
+                    self.clock.advance_to(deadline);
+                    self.process()?;
+                    return Ok(done());
+                }
+            }
+        }
+    }
+
+    /// advance the clock by `duration`, processing every event on the way
+    pub fn run_for(&mut self, duration: Duration) -> result::Result<()> {
+        self.run_until(duration, || false).map(|_| ())
+    }
+}
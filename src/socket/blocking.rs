@@ -0,0 +1,309 @@
+use std::io::{Error, ErrorKind, IoSlice, Read, Result, Write};
+use std::net::{IpAddr, SocketAddrV4};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use crate::result;
+
+use crate::stack::NetStack;
+
+use super::{Connecting, TcpListener, TcpStream, UdpSocket, WritePolicy};
+
+/// time a Happy Eyeballs attempt has before the next one starts, RFC 8305 recommends 250 ms
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Waker signaling a condvar, lets a thread sleep until the packet processing wakes it
+#[derive(Default)]
+struct Signal {
+    notified: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Signal {
+    /// sleep until woken, `ErrorKind::TimedOut` once `deadline` passed
+    fn wait(&self, deadline: Option<Instant>) -> Result<()> {
+        let mut notified = self.notified.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while !*notified {
+            notified = match deadline {
+                None => self.cond.wait(notified).unwrap_or_else(|poisoned| poisoned.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(result::Error::TimedOut.into());
+                    }
+                    self.cond.wait_timeout(notified, deadline - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+            };
+        }
+        *notified = false;
+        Ok(())
+    }
+}
+
+impl Wake for Signal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        *self.notified.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = true;
+        self.cond.notify_one();
+    }
+}
+
+/// call `poll` until it's ready, the thread sleeps in between
+pub(super) fn block_on<T, F>(timeout: Option<Duration>, mut poll: F) -> Result<T>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<Result<T>>,
+{
+    let signal = Arc::new(Signal::default());
+    let waker = Waker::from(signal.clone());
+    let mut cx = Context::from_waker(&waker);
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        if let Poll::Ready(res) = poll(&mut cx) {
+            return res;
+        }
+        signal.wait(deadline)?;
+    }
+}
+
+/// the addresses alternating between the families, ipv6 first (RFC 8305 4), each
+/// family in the order it was given
+fn interleave(addrs: &[IpAddr]) -> Vec<IpAddr> {
+    let (mut v6, mut v4) = (addrs.iter().filter(|addr| addr.is_ipv6()), addrs.iter().filter(|addr| addr.is_ipv4()));
+    let mut order = Vec::with_capacity(addrs.len());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return order,
+            (a, b) => order.extend(a.into_iter().chain(b).copied()),
+        }
+    }
+}
+
+/// the slices of `bufs` after the first `n` bytes
+fn skip<'a>(bufs: &'a [IoSlice], mut n: usize) -> Vec<IoSlice<'a>> {
+    let mut rest = Vec::with_capacity(bufs.len());
+    for buf in bufs {
+        if n >= buf.len() {
+            n -= buf.len();
+            continue;
+        }
+        rest.push(IoSlice::new(&buf[n..]));
+        n = 0;
+    }
+    rest
+}
+
+/// same rule as `std::net::TcpStream`, a zero timeout is refused
+pub(super) fn check_timeout(timeout: Option<Duration>) -> Result<()> {
+    if timeout == Some(Duration::from_secs(0)) {
+        return Err(Error::new(ErrorKind::InvalidInput, "cannot set a 0 duration timeout"));
+    }
+    Ok(())
+}
+
+impl TcpListener {
+    /// wait for the next established connection
+    pub fn accept(&self) -> Result<TcpStream> {
+        block_on(None, |cx| self.poll_accept(cx))
+    }
+}
+
+impl Connecting {
+    /// wait for the handshake, at most `timeout`. the connection is cancelled if it
+    /// failed or `ErrorKind::TimedOut` came first
+    pub fn wait(self, timeout: Option<Duration>) -> Result<TcpStream> {
+        block_on(timeout, |cx| self.poll_established(cx))?;
+        Ok(self.into_stream())
+    }
+}
+
+impl TcpStream {
+    /// `connect` which gives up after `timeout` with `ErrorKind::TimedOut`, the embryonic
+    /// connection is torn down and its port freed. a zero timeout is refused
+    pub fn connect_timeout(stack: &NetStack, addr: SocketAddrV4, timeout: Duration) -> Result<Self> {
+        check_timeout(Some(timeout))?;
+        Self::start_connect(stack, addr, stack.default_options())?.wait(Some(timeout))
+    }
+
+    /// Happy Eyeballs (RFC 8305): connect to `port` of whichever of `addrs` answers first.
+    /// the attempts alternate between ipv6 and ipv4 and start `CONNECTION_ATTEMPT_DELAY`
+    /// apart, or right away once the one before failed. the first established connection
+    /// wins and the others are cancelled, the error of the last attempt is returned if
+    /// none succeeds. the stack is ipv4 only, its ipv6 attempts fail with `ErrorKind::Unsupported`
+    pub fn connect_host(stack: &NetStack, addrs: &[IpAddr], port: u16) -> Result<Self> {
+        let signal = Arc::new(Signal::default());
+        let waker = Waker::from(signal.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut next = interleave(addrs).into_iter();
+        let mut attempts: Vec<Connecting> = Vec::new();
+        let mut last = Error::new(ErrorKind::InvalidInput, "no address to connect to");
+        let mut next_start = Instant::now();
+        loop {
+            if Instant::now() >= next_start {
+                if let Some(ip) = next.next() {
+                    next_start = Instant::now() + CONNECTION_ATTEMPT_DELAY;
+                    let attempt = match ip {
+                        IpAddr::V4(ip) => Self::start_connect(stack, SocketAddrV4::new(ip, port), stack.default_options()),
+                        IpAddr::V6(_) => Err(Error::new(ErrorKind::Unsupported, "the stack is ipv4 only")),
+                    };
+                    match attempt {
+                        Ok(connecting) => attempts.push(connecting),
+                        Err(e) => {
+                            last = e;
+                            next_start = Instant::now();
+                            continue;
+                        }
+                    }
+                }
+            }
+            let mut i = 0;
+            while i < attempts.len() {
+                match attempts[i].poll_established(&mut cx) {
+                    Poll::Ready(Ok(())) => return Ok(attempts.swap_remove(i).into_stream()),
+                    Poll::Ready(Err(e)) => {
+                        last = e;
+                        attempts.swap_remove(i);
+                        next_start = Instant::now();
+                    }
+                    Poll::Pending => i += 1,
+                }
+            }
+            let more = next.len() > 0;
+            if attempts.is_empty() && !more {
+                return Err(last);
+            }
+            if more && Instant::now() >= next_start {
+                continue;
+            }
+            // woken by an attempt or the start of the next one
+            match signal.wait(Some(next_start).filter(|_| more)) {
+                Err(e) if e.kind() == ErrorKind::TimedOut => {}
+                res => res?,
+            }
+        }
+    }
+
+    /// wait for the handshake, at most `timeout`
+    pub fn wait_established(&self, timeout: Option<Duration>) -> Result<()> {
+        block_on(timeout, |cx| self.poll_established(cx))
+    }
+
+    /// `read` which leaves the bytes in the receive buffer, the next read returns them again
+    pub fn peek(&self, buf: &mut [u8]) -> Result<usize> {
+        let timeout = self.read_timeout()?;
+        block_on(timeout, |cx| self.poll_peek(cx, buf))
+    }
+
+    /// wait until `buf` can be filled without taking the bytes, within the read timeout. a
+    /// parser can look at a header and then `read_exact` the frame it announces, which neither
+    /// blocks nor loses a partial read to a timeout once the frame is there
+    pub fn peek_exact(&self, buf: &mut [u8]) -> Result<()> {
+        let timeout = self.read_timeout()?;
+        block_on(timeout, |cx| self.poll_peek_exact(cx, buf))
+    }
+
+    /// wait for the peer to acknowledge everything after a `shutdown`, at most `timeout`
+    pub fn wait_sent(&self, timeout: Duration) -> Result<()> {
+        block_on(Some(timeout), |cx| self.poll_sent(cx))
+    }
+
+    /// `None` blocks reads until data arrives, otherwise they fail with `ErrorKind::TimedOut`
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        check_timeout(timeout)?;
+        self.with_socket(|sock| {
+            sock.read_timeout = timeout;
+            Ok(())
+        })
+    }
+
+    /// `None` blocks writes until the send buffer has room, otherwise they fail with `ErrorKind::TimedOut`
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        check_timeout(timeout)?;
+        self.with_socket(|sock| {
+            sock.write_timeout = timeout;
+            Ok(())
+        })
+    }
+
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        self.with_socket(|sock| Ok(sock.read_timeout))
+    }
+
+    pub fn write_timeout(&self) -> Result<Option<Duration>> {
+        self.with_socket(|sock| Ok(sock.write_timeout))
+    }
+}
+
+impl UdpSocket {
+    /// wait for the next datagram, within the read timeout
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4)> {
+        let timeout = self.read_timeout()?;
+        block_on(timeout, |cx| self.poll_recv_from(cx, buf))
+    }
+}
+
+/// blocks until data or FIN arrives, `Ok(0)` is end of file
+impl Read for &TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let timeout = self.read_timeout()?;
+        block_on(timeout, |cx| self.poll_read(cx, buf))
+    }
+}
+
+/// blocks while the send buffer is full, with `WritePolicy::Block` until everything was queued
+impl Write for &TcpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        let timeout = self.write_timeout()?;
+        if self.write_policy()? != WritePolicy::Block {
+            return block_on(timeout, |cx| self.poll_write_vectored(cx, bufs));
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let len: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let mut written = 0;
+        while written < len {
+            let rest = skip(bufs, written);
+            let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            match block_on(timeout, |cx| self.poll_write_vectored(cx, &rest)) {
+                Ok(n) => written += n,
+                // what was queued before the timeout or the error counts
+                Err(_) if written > 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(written)
+    }
+
+    /// written bytes are handed to the stack right away
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        (&*self).write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (&*self).flush()
+    }
+}
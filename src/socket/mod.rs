@@ -0,0 +1,885 @@
+use std::convert::TryFrom;
+use std::future::Future;
+use std::io::{Error, ErrorKind, IoSlice, Result};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ops::BitOr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::net_types::Dscp;
+use crate::result;
+use crate::socket_addr::{Addr, Quad};
+use crate::stack::{Listener, NetStack, Shared, Socket};
+#[cfg(feature = "tcp-ao")]
+use crate::tcp::ao::{AuthStatus, Authenticator};
+#[cfg(feature = "serde")]
+use crate::tcp::checkpoint::Checkpoint;
+use crate::tcp::connection::ConnectionConfig;
+use crate::tcp::ring::{Watermark, Watermarks};
+use crate::tcp::rtt::RttEstimator;
+use crate::tcp::shaper::RateLimit;
+use crate::tcp::stats::ConnectionStats;
+use crate::tcp::vars::TcpState;
+
+#[cfg(feature = "mptcp")]
+pub use self::mptcp::{MptcpListener, MptcpStream};
+pub use self::blocking::CONNECTION_ATTEMPT_DELAY;
+pub use self::options::{SocketOptions, WritePolicy};
+pub use self::udp::UdpSocket;
+
+mod blocking;
+#[cfg(feature = "mptcp")]
+mod mptcp;
+mod options;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+mod udp;
+
+/// Readiness of a socket, as reported by `NetStack::poll_readiness`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub struct Interest(u8);
+
+impl Interest {
+    /// data or end of file can be read, or a connection accepted
+    pub const READABLE: Interest = Interest(0b0001);
+    /// the send buffer has room
+    pub const WRITABLE: Interest = Interest(0b0010);
+    /// the peer closed its side or the connection is gone
+    pub const HUP: Interest = Interest(0b0100);
+    /// the connection was reset
+    pub const ERROR: Interest = Interest(0b1000);
+
+    pub const fn empty() -> Self {
+        Interest(0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: Interest) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_readable(self) -> bool {
+        self.contains(Interest::READABLE)
+    }
+
+    pub fn is_writable(self) -> bool {
+        self.contains(Interest::WRITABLE)
+    }
+
+    pub fn is_hup(self) -> bool {
+        self.contains(Interest::HUP)
+    }
+
+    pub fn is_error(self) -> bool {
+        self.contains(Interest::ERROR)
+    }
+}
+
+impl BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, rhs: Interest) -> Interest {
+        Interest(self.0 | rhs.0)
+    }
+}
+
+/// Passive open on a port of a `NetStack`
+pub struct TcpListener {
+    shared: Arc<Shared>,
+    addr: SocketAddrV4,
+}
+
+impl TcpListener {
+    /// accept connections to `port` on every address of the stack
+    pub fn bind(stack: &NetStack, port: u16) -> Result<Self> {
+        Self::bind_addr(stack, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))
+    }
+
+    /// accept connections to `addr`, an address of the stack or `0.0.0.0`. a listener on an
+    /// address takes its connections before one on `0.0.0.0` and the same port, which needs
+    /// `SocketOptions::reuse_addr`
+    pub fn bind_addr(stack: &NetStack, addr: SocketAddrV4) -> Result<Self> {
+        Self::bind_with(stack, addr, stack.default_options())
+    }
+
+    /// accept connections to any address and port no other listener takes, the stack must be
+    /// `StackConfig::transparent`. `local_addr` of the streams is the destination the peer dialed
+    pub fn bind_transparent(stack: &NetStack) -> Result<Self> {
+        Self::bind_addr(stack, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))
+    }
+
+    /// `bind_addr` with the options the accepted connections inherit instead of the defaults
+    /// of the stack, e.g. `stack.default_options().connection(config)` for a listener of its
+    /// own window, ttl, keep-alive and congestion control
+    pub fn bind_with(stack: &NetStack, addr: SocketAddrV4, options: SocketOptions) -> Result<Self> {
+        let shared = stack.shared().clone();
+        shared.listen(Addr::from(addr), options)?;
+        Ok(Self { shared, addr })
+    }
+
+    /// options of the connections accepted from now on
+    pub fn set_options(&self, options: SocketOptions) -> Result<()> {
+        let options = self.shared.fit(options);
+        for shard in 0..self.shared.shards() {
+            self.with_listener(shard, |listener| listener.options = options)?;
+        }
+        Ok(())
+    }
+
+    pub fn options(&self) -> Result<SocketOptions> {
+        self.with_listener(0, |listener| listener.options)
+    }
+
+    /// the copy of the listener in `shard`, every shard of the stack has one
+    fn with_listener<T, F: FnOnce(&mut Listener) -> T>(&self, shard: usize, f: F) -> Result<T> {
+        let mut state = self.shared.lock_shard(shard);
+        state.table.listener_mut(&Addr::from(self.addr))
+            .map(f)
+            .ok_or_else(|| {
+                if self.shared.is_device_gone() {
+                    return result::Error::DeviceGone.into();
+                }
+                Error::new(ErrorKind::NotConnected, "listener closed")
+            })
+    }
+
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.addr
+    }
+
+    pub fn readiness(&self) -> Interest {
+        self.shared.listener_readiness(&Addr::from(self.addr))
+    }
+
+    /// return an established connection, `ErrorKind::WouldBlock` if none is waiting
+    pub fn try_accept(&self) -> Result<TcpStream> {
+        self.try_accept_quad().map(|quad| TcpStream::new(self.shared.clone(), quad))
+    }
+
+    /// like `try_accept`, the task is woken when a connection is established
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<Result<TcpStream>> {
+        self.poll_accept_quad(cx).map_ok(|quad| TcpStream::new(self.shared.clone(), quad))
+    }
+
+    fn try_accept_quad(&self) -> Result<Quad> {
+        for shard in 0..self.shared.shards() {
+            if let Some(quad) = self.with_listener(shard, |listener| listener.backlog.pop_front())? {
+                return Ok(quad);
+            }
+        }
+        Err(ErrorKind::WouldBlock.into())
+    }
+
+    fn poll_accept_quad(&self, cx: &mut Context<'_>) -> Poll<Result<Quad>> {
+        for shard in 0..self.shared.shards() {
+            // registered under the same lock as the check, a connection queued later wakes the task
+            let accepted = self.with_listener(shard, |listener| {
+                let quad = listener.backlog.pop_front();
+                if quad.is_none() {
+                    listener.waker = Some(cx.waker().clone());
+                }
+                quad
+            });
+            match accepted {
+                Ok(Some(quad)) => return Poll::Ready(Ok(quad)),
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        self.shared.unlisten(&Addr::from(self.addr));
+        self.shared.notify_all();
+    }
+}
+
+/// One connection of a `NetStack`, the connection is closed when the stream is dropped
+pub struct TcpStream {
+    shared: Arc<Shared>,
+    quad: Quad,
+}
+
+impl TcpStream {
+    fn new(shared: Arc<Shared>, quad: Quad) -> Self {
+        Self { shared, quad }
+    }
+
+    /// open a connection to `addr` and wait for the handshake, see `connect_timeout` to
+    /// give up earlier than the SYN retries do and `start_connect` to not wait at all
+    pub fn connect(stack: &NetStack, addr: SocketAddrV4) -> Result<Self> {
+        Self::connect_with(stack, addr, stack.default_options())
+    }
+
+    pub fn connect_with(stack: &NetStack, addr: SocketAddrV4, options: SocketOptions) -> Result<Self> {
+        Self::start_connect(stack, addr, options)?.wait(None)
+    }
+
+    /// start the handshake with `addr` and return right away, the connection is
+    /// cancelled if the handle goes before the handshake completed
+    pub fn start_connect(stack: &NetStack, addr: SocketAddrV4, options: SocketOptions) -> Result<Connecting> {
+        let shared = stack.shared().clone();
+        let quad = shared.connect(Addr::from(addr), options)?;
+        shared.notify(&quad);
+        Ok(Connecting { stream: Some(Self::new(shared, quad)) })
+    }
+
+    /// the connection `checkpoint` was taken of, e.g. by `NetStack::checkpoint` before the
+    /// process restarted. it goes on with the config of the checkpoint
+    #[cfg(feature = "serde")]
+    pub fn restore(stack: &NetStack, checkpoint: Checkpoint) -> Result<Self> {
+        let shared = stack.shared().clone();
+        let options = SocketOptions { config: *checkpoint.config(), ..stack.default_options() };
+        let quad = shared.restore(checkpoint, options)?;
+        shared.notify(&quad);
+        Ok(Self::new(shared, quad))
+    }
+
+    /// the address the connection sends from, which changes if it migrated
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.with_socket(|sock| Ok(v4(sock.conn.quad().src())))
+            .unwrap_or_else(|_| v4(self.quad.src()))
+    }
+
+    pub fn peer_addr(&self) -> SocketAddrV4 {
+        v4(self.quad.dest())
+    }
+
+    pub fn state(&self) -> TcpState {
+        self.with_socket(|sock| Ok(sock.conn.state())).unwrap_or(TcpState::Closed)
+    }
+
+    /// (local, remote) key of the connection, see `NetStack::poll_readiness`
+    pub fn quad(&self) -> Quad {
+        self.quad
+    }
+
+    pub fn readiness(&self) -> Interest {
+        self.with_socket(|sock| Ok(sock.readiness())).unwrap_or(Interest::HUP)
+    }
+
+    /// `ErrorKind::WouldBlock` while the handshake is in progress
+    pub fn try_established(&self) -> Result<()> {
+        self.with_socket(|sock| established(sock))
+    }
+
+    /// ready once the handshake completed or failed
+    pub fn poll_established(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.with_socket(|sock| {
+            let res = established(sock);
+            if would_block(&res) {
+                sock.write_waker = Some(cx.waker().clone());
+            }
+            Ok(res)
+        }).and_then(|res| res).into_poll()
+    }
+
+    /// read received bytes, `Ok(0)` at end of file and `ErrorKind::WouldBlock` if nothing arrived
+    pub fn try_read(&self, buf: &mut [u8]) -> Result<usize> {
+        let (res, window_update) = self.with_socket(|sock| Ok((read(sock, buf), sock.conn.has_pending_ack())))?;
+        if window_update {
+            self.shared.notify(&self.quad);
+        }
+        res
+    }
+
+    pub fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        let (res, window_update) = match self.with_socket(|sock| {
+            let res = read(sock, buf);
+            if would_block(&res) {
+                sock.read_waker = Some(cx.waker().clone());
+            }
+            Ok((res, sock.conn.has_pending_ack()))
+        }) {
+            Ok(res) => res,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        if window_update {
+            self.shared.notify(&self.quad);
+        }
+        res.into_poll()
+    }
+
+    /// bytes received and not read yet, a read of up to that many returns right away
+    pub fn bytes_available(&self) -> Result<usize> {
+        self.with_socket(|sock| Ok(sock.conn.bytes_available()))
+    }
+
+    /// `try_read` which leaves the bytes in the receive buffer, the next read returns them again
+    pub fn try_peek(&self, buf: &mut [u8]) -> Result<usize> {
+        self.with_socket(|sock| peek(sock, buf))
+    }
+
+    pub fn poll_peek(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        self.with_socket(|sock| {
+            let res = peek(sock, buf);
+            if would_block(&res) {
+                sock.read_waker = Some(cx.waker().clone());
+            }
+            Ok(res)
+        }).and_then(|res| res).into_poll()
+    }
+
+    /// ready once `buf` can be filled, the bytes stay in the receive buffer. fails with
+    /// `ErrorKind::UnexpectedEof` when the peer closed before sending that many and with
+    /// `ErrorKind::InvalidInput` when they don't fit into the receive buffer
+    pub fn poll_peek_exact(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<()>> {
+        self.with_socket(|sock| {
+            let res = peek_exact(sock, buf);
+            if would_block(&res) {
+                sock.read_waker = Some(cx.waker().clone());
+            }
+            Ok(res)
+        }).and_then(|res| res).into_poll()
+    }
+
+    /// queue bytes to be sent, `ErrorKind::WouldBlock` if the send buffer is full
+    pub fn try_write(&self, buf: &[u8]) -> Result<usize> {
+        self.try_write_vectored(&[IoSlice::new(buf)])
+    }
+
+    /// `try_write` of several slices
+    pub fn try_write_vectored(&self, bufs: &[IoSlice]) -> Result<usize> {
+        let res = self.with_socket(|sock| write(sock, bufs));
+        if res.is_ok() {
+            self.shared.notify(&self.quad);
+        }
+        res
+    }
+
+    pub fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.poll_write_vectored(cx, &[IoSlice::new(buf)])
+    }
+
+    /// `poll_write` of several slices, queued in one go
+    pub fn poll_write_vectored(&self, cx: &mut Context<'_>, bufs: &[IoSlice]) -> Poll<Result<usize>> {
+        let res = self.with_socket(|sock| {
+            let res = write(sock, bufs);
+            if would_block(&res) {
+                sock.write_waker = Some(cx.waker().clone());
+            }
+            Ok(res)
+        }).and_then(|res| res);
+        if res.is_ok() {
+            self.shared.notify(&self.quad);
+        }
+        res.into_poll()
+    }
+
+    /// ready once the connection was closed and the peer acknowledged everything
+    pub fn poll_sent(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.with_socket(|sock| {
+            let res = sent(sock);
+            if would_block(&res) {
+                sock.write_waker = Some(cx.waker().clone());
+            }
+            Ok(res)
+        }).and_then(|res| res).into_poll()
+    }
+
+    /// close the sending side, the peer reads end of file after the data already written
+    pub fn shutdown(&self) -> Result<()> {
+        self.with_socket(|sock| {
+            sock.conn.close();
+            Ok(())
+        })?;
+        self.shared.notify(&self.quad);
+        Ok(())
+    }
+
+    /// `ErrorKind::TimedOut` if retransmissions went unanswered R1 times since the last call,
+    /// the connection goes on until R2 elapses, see `ConnectionConfig::set_r1`
+    pub fn take_error(&self) -> Result<Option<Error>> {
+        let soft_error = self.with_socket(|sock| Ok(sock.conn.take_soft_error()))?;
+        Ok(soft_error.then(|| Error::new(ErrorKind::TimedOut, "retransmissions unanswered")))
+    }
+
+    /// reset the connection instead of closing it, queued data is dropped
+    pub fn abort(&self) -> Result<()> {
+        self.with_socket(|sock| {
+            sock.conn.abort();
+            Ok(())
+        })?;
+        self.shared.notify(&self.quad);
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<ConnectionStats> {
+        self.with_socket(|sock| Ok(sock.conn.stats()))
+    }
+
+    /// keys of the TCP-AO rotation, `None` if the connection is not signed
+    #[cfg(feature = "tcp-ao")]
+    pub fn auth_status(&self) -> Result<Option<AuthStatus>> {
+        self.with_socket(|sock| Ok(sock.conn.authenticator().map(Authenticator::status)))
+    }
+
+    /// sign the segments with the TCP-AO key of `send_id` from now on
+    #[cfg(feature = "tcp-ao")]
+    pub fn set_auth_key(&self, send_id: u8) -> Result<()> {
+        self.with_authenticator(|auth| auth.set_current(send_id), send_id)
+    }
+
+    /// ask the peer to sign with the TCP-AO key of `recv_id`, it does once it got a segment
+    /// asking for it
+    #[cfg(feature = "tcp-ao")]
+    pub fn set_auth_rnext(&self, recv_id: u8) -> Result<()> {
+        self.with_authenticator(|auth| auth.set_rnext(recv_id), recv_id)
+    }
+
+    #[cfg(feature = "tcp-ao")]
+    fn with_authenticator<F>(&self, f: F, id: u8) -> Result<()>
+        where F: FnOnce(&mut Authenticator) -> result::Result<()> {
+        self.with_socket(|sock| match sock.conn.authenticator_mut() {
+            Some(auth) => Ok(f(auth)?),
+            None => Err(result::Error::UnknownKey(id).into()),
+        })
+    }
+
+    /// round-trip time estimate and retransmission timeout of the connection
+    pub fn rtt(&self) -> Result<RttEstimator> {
+        self.with_socket(|sock| Ok(*sock.conn.rtt()))
+    }
+
+    pub fn ttl(&self) -> Result<u8> {
+        self.with_config(|config| config.ttl())
+    }
+
+    pub fn set_ttl(&self, ttl: u8) -> Result<()> {
+        self.with_config(|config| config.set_ttl(ttl))
+    }
+
+    pub fn min_ttl(&self) -> Result<u8> {
+        self.with_config(|config| config.min_ttl())
+    }
+
+    /// drop packets of the peer arriving with a lower ttl from now on, like IP_MINTTL
+    pub fn set_min_ttl(&self, ttl: u8) -> Result<()> {
+        self.with_config(|config| config.set_min_ttl(ttl))
+    }
+
+    pub fn tos(&self) -> Result<u8> {
+        self.with_config(|config| config.tos())
+    }
+
+    /// type of service byte of outgoing ip packets, DSCP << 2 | ECN
+    pub fn set_tos(&self, tos: u8) -> Result<()> {
+        self.with_config(|config| config.set_tos(tos))
+    }
+
+    pub fn dscp(&self) -> Result<Dscp> {
+        self.with_config(|config| config.dscp())
+    }
+
+    /// code point of outgoing ip packets from the next segment on, the ECN bits stay
+    pub fn set_dscp(&self, dscp: Dscp) -> Result<()> {
+        self.with_config(|config| config.set_dscp(dscp))
+    }
+
+    /// the code point the peer marked its last packet with, `None` before the first one
+    pub fn received_dscp(&self) -> Result<Option<Dscp>> {
+        self.with_socket(|sock| Ok(sock.conn.received_dscp()))
+    }
+
+    /// Nagle's algorithm is off, small segments leave right away like with TCP_NODELAY
+    pub fn nodelay(&self) -> Result<bool> {
+        self.with_config(|config| !config.nagle())
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<()> {
+        self.with_config(|config| config.set_nagle(!nodelay))
+    }
+
+    pub fn pacing(&self) -> Result<bool> {
+        self.with_config(|config| config.pacing())
+    }
+
+    /// spread the segments over the round trip at the pacing rate
+    pub fn set_pacing(&self, pacing: bool) -> Result<()> {
+        self.with_config(|config| config.set_pacing(pacing))
+    }
+
+    pub fn rate_limit(&self) -> Result<Option<RateLimit>> {
+        self.with_config(|config| config.rate_limit())
+    }
+
+    /// cap the bytes per second this connection sends, on top of the limit of the stack
+    pub fn set_rate_limit(&self, limit: Option<RateLimit>) -> Result<()> {
+        self.with_config(|config| config.set_rate_limit(limit))
+    }
+
+    pub fn keep_alive(&self) -> Result<Option<Duration>> {
+        self.with_config(|config| config.keep_alive())
+    }
+
+    /// probe the peer once the connection was idle for `idle`, like SO_KEEPALIVE
+    pub fn set_keep_alive(&self, idle: Option<Duration>) -> Result<()> {
+        self.with_config(|config| config.set_keep_alive(idle))
+    }
+
+    /// how long data may stay unacknowledged before the connection times out,
+    /// ours, the one the peer advertised or R2
+    pub fn user_timeout(&self) -> Result<Duration> {
+        self.with_socket(|sock| Ok(sock.conn.user_timeout()))
+    }
+
+    /// like TCP_USER_TIMEOUT, `None` goes back to the one of the peer or R2.
+    /// the peer learns it from the next data sent if none was acknowledged yet
+    pub fn set_user_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.with_config(|config| config.set_user_timeout(timeout))
+    }
+
+    pub fn recv_buffer_size(&self) -> Result<usize> {
+        self.with_config(|config| config.recv_buffer_size())
+    }
+
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<()> {
+        self.with_config(|config| config.set_recv_buffer_size(size))
+    }
+
+    pub fn recv_buffer_max(&self) -> Result<usize> {
+        self.with_config(|config| config.recv_buffer_max())
+    }
+
+    /// largest size receive buffer autotuning grows to
+    pub fn set_recv_buffer_max(&self, size: usize) -> Result<()> {
+        self.with_config(|config| config.set_recv_buffer_max(size))
+    }
+
+    pub fn send_buffer_size(&self) -> Result<usize> {
+        self.with_config(|config| config.send_buffer_size())
+    }
+
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<()> {
+        self.with_config(|config| config.set_send_buffer_size(size))
+    }
+
+    pub fn send_buffer_max(&self) -> Result<usize> {
+        self.with_config(|config| config.send_buffer_max())
+    }
+
+    /// largest size send buffer autotuning grows to
+    pub fn set_send_buffer_max(&self, size: usize) -> Result<()> {
+        self.with_config(|config| config.set_send_buffer_max(size))
+    }
+
+    pub fn write_policy(&self) -> Result<WritePolicy> {
+        self.with_socket(|sock| Ok(sock.write_policy))
+    }
+
+    /// what writes do when the send buffer is full
+    pub fn set_write_policy(&self, policy: WritePolicy) -> Result<()> {
+        self.with_socket(|sock| {
+            sock.write_policy = policy;
+            Ok(())
+        })
+    }
+
+    pub fn recv_low_watermark(&self) -> Result<usize> {
+        self.with_config(|config| config.recv_low_watermark())
+    }
+
+    /// bytes which have to be received before `readiness` reports `READABLE`, like SO_RCVLOWAT
+    pub fn set_recv_low_watermark(&self, bytes: usize) -> Result<()> {
+        self.with_config(|config| config.set_recv_low_watermark(bytes))
+    }
+
+    pub fn send_low_watermark(&self) -> Result<usize> {
+        self.with_config(|config| config.send_low_watermark())
+    }
+
+    /// free send buffer space needed before `readiness` reports `WRITABLE`, like SO_SNDLOWAT
+    pub fn set_send_low_watermark(&self, bytes: usize) -> Result<()> {
+        self.with_config(|config| config.set_send_low_watermark(bytes))
+    }
+
+    /// call `listener` when the received and unread bytes reach `watermarks.high`
+    /// and again when reads drain them to `watermarks.low`
+    pub fn watch_recv_queue<F>(&self, watermarks: Watermarks, listener: F) -> Result<()>
+    where
+        F: Fn(Watermark) + Send + Sync + 'static,
+    {
+        self.with_socket(|sock| {
+            sock.conn.watch_recv_queue(watermarks, Some(Arc::new(listener)));
+            Ok(())
+        })
+    }
+
+    /// call `listener` when the unacknowledged bytes reach `watermarks.high`
+    /// and again when the peer acknowledged enough to drain them to `watermarks.low`
+    pub fn watch_send_queue<F>(&self, watermarks: Watermarks, listener: F) -> Result<()>
+    where
+        F: Fn(Watermark) + Send + Sync + 'static,
+    {
+        self.with_socket(|sock| {
+            sock.conn.watch_send_queue(watermarks, Some(Arc::new(listener)));
+            Ok(())
+        })
+    }
+
+    pub fn linger(&self) -> Result<Option<Duration>> {
+        self.with_socket(|sock| Ok(sock.linger))
+    }
+
+    /// `None` closes in the background when the stream is dropped,
+    /// `Some(timeout)` blocks the drop until the queued data is acknowledged or `timeout` elapsed,
+    /// a zero timeout resets the connection
+    pub fn set_linger(&self, linger: Option<Duration>) -> Result<()> {
+        self.with_socket(|sock| {
+            sock.linger = linger;
+            Ok(())
+        })
+    }
+
+    /// tear the connection down without a FIN, the drop forgets it
+    fn cancel(self) {
+        let _ = self.with_socket(|sock| {
+            sock.conn.abort();
+            Ok(())
+        });
+    }
+
+    fn with_config<T, F: FnOnce(&mut ConnectionConfig) -> T>(&self, f: F) -> Result<T> {
+        self.with_socket(|sock| Ok(f(sock.conn.config_mut())))
+    }
+
+    fn with_socket<T, F: FnOnce(&mut Socket) -> Result<T>>(&self, f: F) -> Result<T> {
+        let mut state = self.shared.lock(&self.quad);
+        match state.table.get_mut(&self.quad) {
+            Some(sock) => f(sock),
+            None => Err(ErrorKind::NotConnected.into()),
+        }
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        match self.linger() {
+            Ok(Some(linger)) if linger == Duration::from_secs(0) => {
+                let _ = self.with_socket(|sock| {
+                    sock.conn.abort();
+                    Ok(())
+                });
+            }
+            Ok(Some(linger)) => {
+                let _ = self.shutdown().and_then(|()| self.wait_sent(linger));
+            }
+            _ => {}
+        }
+        self.shared.lock(&self.quad).release(self.quad);
+        self.shared.notify(&self.quad);
+    }
+}
+
+/// A connection in its handshake, from `TcpStream::start_connect`. as a future it
+/// resolves to the stream once established. a failed handshake, `cancel` or dropping
+/// the handle before the handshake completed tears the embryonic connection down and
+/// frees its ephemeral port
+pub struct Connecting {
+    stream: Option<TcpStream>,
+}
+
+impl Connecting {
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.stream().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> SocketAddrV4 {
+        self.stream().peer_addr()
+    }
+
+    pub fn quad(&self) -> Quad {
+        self.stream().quad()
+    }
+
+    /// `ErrorKind::WouldBlock` while the handshake is in progress
+    pub fn try_established(&self) -> Result<()> {
+        self.stream().try_established()
+    }
+
+    /// ready once the handshake completed or failed
+    pub fn poll_established(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.stream().poll_established(cx)
+    }
+
+    /// the established stream, `ErrorKind::WouldBlock` while the handshake is in progress
+    pub fn try_into_stream(self) -> Result<TcpStream> {
+        self.try_established()?;
+        Ok(self.into_stream())
+    }
+
+    /// give up on the connection, a RST answers the peer's SYN-ACK if it still comes
+    pub fn cancel(mut self) {
+        if let Some(stream) = self.stream.take() {
+            stream.cancel();
+        }
+    }
+
+    fn stream(&self) -> &TcpStream {
+        self.stream.as_ref().expect("connecting stream")
+    }
+
+    fn into_stream(mut self) -> TcpStream {
+        self.stream.take().expect("connecting stream")
+    }
+}
+
+impl Future for Connecting {
+    type Output = Result<TcpStream>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.poll_established(cx) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(self.stream.take().expect("polled after completion"))),
+            Poll::Ready(Err(e)) => {
+                if let Some(stream) = self.stream.take() {
+                    stream.cancel();
+                }
+                Poll::Ready(Err(e))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for Connecting {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            stream.cancel();
+        }
+    }
+}
+
+/// the peer reset the connection, it timed out or the device of the stack went away
+fn failed(sock: &Socket) -> Result<()> {
+    if sock.device_gone {
+        return Err(result::Error::DeviceGone.into());
+    }
+    if sock.conn.is_reset() {
+        return Err(result::Error::ConnectionReset.into());
+    }
+    if sock.conn.is_timed_out() {
+        return Err(result::Error::TimedOut.into());
+    }
+    if sock.conn.is_addr_removed() {
+        return Err(result::Error::AddressRemoved(sock.conn.quad().src().ip()).into());
+    }
+    Ok(())
+}
+
+/// ready once everything written, FIN included, was acknowledged
+fn sent(sock: &Socket) -> Result<()> {
+    failed(sock)?;
+    match sock.conn.state() {
+        TcpState::FinWait2 | TcpState::TimeWait | TcpState::Closed => Ok(()),
+        _ => Err(ErrorKind::WouldBlock.into()),
+    }
+}
+
+fn established(sock: &Socket) -> Result<()> {
+    if sock.device_gone {
+        return Err(result::Error::DeviceGone.into());
+    }
+    if sock.conn.is_reset() {
+        return Err(result::Error::ConnectionRefused.into());
+    }
+    if sock.conn.is_timed_out() {
+        return Err(result::Error::TimedOut.into());
+    }
+    match sock.conn.state() {
+        TcpState::SynSent | TcpState::SynReceived => Err(ErrorKind::WouldBlock.into()),
+        state @ (TcpState::Closed | TcpState::Listen) => Err(result::Error::InvalidState(state).into()),
+        _ => Ok(()),
+    }
+}
+
+fn read(sock: &mut Socket, buf: &mut [u8]) -> Result<usize> {
+    if sock.conn.bytes_available() > 0 {
+        return Ok(sock.conn.read(buf));
+    }
+    failed(sock)?;
+    if buf.is_empty() || sock.conn.is_eof() || sock.conn.state() == TcpState::Closed {
+        return Ok(0);
+    }
+    Err(ErrorKind::WouldBlock.into())
+}
+
+fn peek(sock: &Socket, buf: &mut [u8]) -> Result<usize> {
+    if sock.conn.bytes_available() > 0 {
+        return Ok(sock.conn.peek(buf));
+    }
+    failed(sock)?;
+    if buf.is_empty() || sock.conn.is_eof() || sock.conn.state() == TcpState::Closed {
+        return Ok(0);
+    }
+    Err(ErrorKind::WouldBlock.into())
+}
+
+fn peek_exact(sock: &Socket, buf: &mut [u8]) -> Result<()> {
+    if sock.conn.bytes_available() >= buf.len() {
+        sock.conn.peek(buf);
+        return Ok(());
+    }
+    failed(sock)?;
+    if sock.conn.is_fin_received() || sock.conn.state() == TcpState::Closed {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    let config = sock.conn.config();
+    if buf.len() > config.recv_buffer_size().max(config.recv_buffer_max()) {
+        return Err(Error::new(ErrorKind::InvalidInput, "more than the receive buffer holds"));
+    }
+    Err(ErrorKind::WouldBlock.into())
+}
+
+fn write(sock: &mut Socket, bufs: &[IoSlice]) -> Result<usize> {
+    failed(sock)?;
+    if sock.conn.is_write_closed() {
+        return Err(ErrorKind::BrokenPipe.into());
+    }
+    if bufs.iter().all(|buf| buf.is_empty()) {
+        return Ok(0);
+    }
+    let written = match sock.write_policy {
+        WritePolicy::Whole => sock.conn.write_whole(bufs),
+        WritePolicy::Partial | WritePolicy::Block => sock.conn.write_vectored(bufs),
+    };
+    if written == 0 {
+        sock.write_need = match sock.write_policy {
+            WritePolicy::Whole => bufs.iter().map(|buf| buf.len()).sum(),
+            WritePolicy::Partial | WritePolicy::Block => 1,
+        };
+        return Err(ErrorKind::WouldBlock.into());
+    }
+    sock.write_need = 0;
+    Ok(written)
+}
+
+/// connections of the stack are ipv4 only
+fn v4(addr: Addr) -> SocketAddrV4 {
+    SocketAddrV4::try_from(addr).expect("ipv4 connection")
+}
+
+fn would_block<T>(res: &Result<T>) -> bool {
+    matches!(res, Err(e) if e.kind() == ErrorKind::WouldBlock)
+}
+
+trait IntoPoll<T> {
+    fn into_poll(self) -> Poll<Result<T>>;
+}
+
+impl<T> IntoPoll<T> for Result<T> {
+    /// `ErrorKind::WouldBlock` becomes `Poll::Pending`
+    fn into_poll(self) -> Poll<Result<T>> {
+        match self {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Poll::Pending,
+            res => Poll::Ready(res),
+        }
+    }
+}
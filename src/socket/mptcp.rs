@@ -0,0 +1,255 @@
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::mptcp::connection::{MptcpConnection, SubflowInfo};
+use crate::mptcp::subflow::{self, Subflow};
+use crate::socket_addr::{Addr, Quad};
+use crate::stack::{NetStack, Shared};
+
+use super::blocking::block_on;
+use super::{v4, would_block, IntoPoll, SocketOptions, TcpListener};
+
+/// Passive open of MPTCP connections (RFC 8684), experimental. peers which don't ask for
+/// MPTCP get a stream over plain tcp
+pub struct MptcpListener {
+    inner: TcpListener,
+}
+
+impl MptcpListener {
+    /// accept connections to `port` on every address of the stack
+    pub fn bind(stack: &NetStack, port: u16) -> Result<Self> {
+        Self::bind_with(stack, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port), stack.default_options())
+    }
+
+    pub fn bind_with(stack: &NetStack, addr: SocketAddrV4, options: SocketOptions) -> Result<Self> {
+        let inner = TcpListener::bind_with(stack, addr, SocketOptions { mptcp: true, ..options })?;
+        Ok(Self { inner })
+    }
+
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.inner.local_addr()
+    }
+
+    /// return a connection whose first subflow is established, `ErrorKind::WouldBlock`
+    /// if none is waiting
+    pub fn try_accept(&self) -> Result<MptcpStream> {
+        self.inner.try_accept_quad().and_then(|quad| self.stream(quad))
+    }
+
+    /// like `try_accept`, the task is woken when a connection is established
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<Result<MptcpStream>> {
+        self.inner.poll_accept_quad(cx).map(|res| res.and_then(|quad| self.stream(quad)))
+    }
+
+    /// wait for the next established connection
+    pub fn accept(&self) -> Result<MptcpStream> {
+        block_on(None, |cx| self.poll_accept(cx))
+    }
+
+    /// the connection of the subflow `quad`, which it takes over
+    fn stream(&self, quad: Quad) -> Result<MptcpStream> {
+        let shared = self.inner.shared.clone();
+        let subflow = shared.lock(&quad).release_subflow(quad);
+        match subflow {
+            Some(subflow) => Ok(MptcpStream::new(shared, subflow.meta().clone(), v4(quad.dest()))),
+            None => Err(ErrorKind::NotConnected.into()),
+        }
+    }
+}
+
+/// An MPTCP connection (RFC 8684), experimental: one stream over subflows from the addresses
+/// of the stack to one address of the peer. the connection is closed when the stream is dropped
+pub struct MptcpStream {
+    shared: Arc<Shared>,
+    conn: Arc<Mutex<MptcpConnection>>,
+    peer: SocketAddrV4,
+}
+
+impl MptcpStream {
+    fn new(shared: Arc<Shared>, conn: Arc<Mutex<MptcpConnection>>, peer: SocketAddrV4) -> Self {
+        Self { shared, conn, peer }
+    }
+
+    /// start the handshake of the first subflow with `addr` and return immediately,
+    /// see `poll_established` to wait for it
+    pub fn connect(stack: &NetStack, addr: SocketAddrV4) -> Result<Self> {
+        Self::connect_with(stack, addr, stack.default_options())
+    }
+
+    pub fn connect_with(stack: &NetStack, addr: SocketAddrV4, options: SocketOptions) -> Result<Self> {
+        let shared = stack.shared().clone();
+        let conn = shared.mptcp().create(options.config)?;
+        let quad = shared.connect_subflow(None, Addr::from(addr), options, Subflow::connect(conn.clone()))?;
+        shared.notify(&quad);
+        Ok(Self::new(shared, conn, addr))
+    }
+
+    /// open another subflow from the address `local` of the stack, `ErrorKind::Unsupported`
+    /// if the peer didn't take MPTCP
+    pub fn add_subflow(&self, local: Ipv4Addr) -> Result<Quad> {
+        let options = {
+            let conn = self.lock();
+            if conn.is_fallback() {
+                return Err(Error::new(ErrorKind::Unsupported, "the peer doesn't take MPTCP"));
+            }
+            if !conn.accepts_join() {
+                return Err(ErrorKind::NotConnected.into());
+            }
+            SocketOptions { config: *conn.config(), ..SocketOptions::default() }
+        };
+        let subflow = Subflow::join(self.conn.clone())?;
+        let quad = self.shared.connect_subflow(Some(local), Addr::from(self.peer), options, subflow)?;
+        self.shared.notify(&quad);
+        Ok(quad)
+    }
+
+    /// the subflows opened so far, closed ones included
+    pub fn subflows(&self) -> Vec<SubflowInfo> {
+        self.lock().subflows().to_vec()
+    }
+
+    /// the peer didn't take MPTCP, the stream is one plain tcp connection
+    pub fn is_fallback(&self) -> bool {
+        self.lock().is_fallback()
+    }
+
+    pub fn peer_addr(&self) -> SocketAddrV4 {
+        self.peer
+    }
+
+    /// `ErrorKind::WouldBlock` while the handshake of the first subflow is in progress
+    pub fn try_established(&self) -> Result<()> {
+        established(&self.lock())
+    }
+
+    /// ready once the handshake of the first subflow completed or failed
+    pub fn poll_established(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut conn = self.lock();
+        let res = established(&conn);
+        if would_block(&res) {
+            conn.write_waker = Some(cx.waker().clone());
+        }
+        res.into_poll()
+    }
+
+    /// wait for the handshake of the first subflow, at most `timeout`
+    pub fn wait_established(&self, timeout: Option<Duration>) -> Result<()> {
+        block_on(timeout, |cx| self.poll_established(cx))
+    }
+
+    /// read received bytes, `Ok(0)` at end of file and `ErrorKind::WouldBlock` if nothing arrived
+    pub fn try_read(&self, buf: &mut [u8]) -> Result<usize> {
+        let res = self.lock().read(buf);
+        self.on_read(&res);
+        res
+    }
+
+    pub fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        let res = {
+            let mut conn = self.lock();
+            let res = conn.read(buf);
+            if would_block(&res) {
+                conn.read_waker = Some(cx.waker().clone());
+            }
+            res
+        };
+        self.on_read(&res);
+        res.into_poll()
+    }
+
+    /// the subflows may hold data the stream had no room for
+    fn on_read(&self, res: &Result<usize>) {
+        if matches!(res, Ok(n) if *n > 0) {
+            self.lock().kick();
+        }
+    }
+
+    /// queue bytes to be sent, `ErrorKind::WouldBlock` if the send buffer is full
+    pub fn try_write(&self, buf: &[u8]) -> Result<usize> {
+        let mut conn = self.lock();
+        let res = conn.write(buf);
+        if res.is_ok() {
+            conn.kick();
+        }
+        res
+    }
+
+    pub fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let mut conn = self.lock();
+        let res = conn.write(buf);
+        match &res {
+            Ok(_) => conn.kick(),
+            Err(_) if would_block(&res) => conn.write_waker = Some(cx.waker().clone()),
+            Err(_) => {}
+        }
+        res.into_poll()
+    }
+
+    /// close the sending side, the peer reads end of file after the data already written
+    pub fn shutdown(&self) -> Result<()> {
+        let mut conn = self.lock();
+        conn.close();
+        conn.kick();
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MptcpConnection> {
+        subflow::lock(&self.conn)
+    }
+}
+
+impl Drop for MptcpStream {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+fn established(conn: &MptcpConnection) -> Result<()> {
+    if let Some(error) = conn.error() {
+        return Err(error.into());
+    }
+    if conn.is_established() {
+        return Ok(());
+    }
+    if conn.is_eof() {
+        return Err(ErrorKind::NotConnected.into());
+    }
+    Err(ErrorKind::WouldBlock.into())
+}
+
+/// blocks until data or the DATA_FIN arrives, `Ok(0)` is end of file
+impl Read for &MptcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        block_on(None, |cx| self.poll_read(cx, buf))
+    }
+}
+
+/// blocks while the send buffer is full
+impl Write for &MptcpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        block_on(None, |cx| self.poll_write(cx, buf))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for MptcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for MptcpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (&*self).flush()
+    }
+}
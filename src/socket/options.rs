@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use crate::net_types::Dscp;
+use crate::tcp::congestion::CongestionAlgorithm;
+use crate::tcp::connection::ConnectionConfig;
+
+/// What a write does when the send buffer can't take all the data
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum WritePolicy {
+    /// queue what fits, `ErrorKind::WouldBlock` only if nothing does
+    #[default]
+    Partial,
+    /// like `Partial`, but the blocking `Write` of `TcpStream` waits until all the data is queued
+    Block,
+    /// queue all the data or nothing and fail with `ErrorKind::WouldBlock`,
+    /// more than the buffer holds is queued once it's empty
+    Whole,
+}
+
+/// Options applied when a socket is created, see `TcpListener::bind_with`
+/// and `TcpStream::connect_with`. Connections accepted by a listener inherit its options
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SocketOptions {
+    pub(crate) config: ConnectionConfig,
+    pub(crate) linger: Option<Duration>,
+    pub(crate) reuse_addr: bool,
+    pub(crate) write_policy: WritePolicy,
+    /// the listener takes MPTCP, see `MptcpListener`
+    #[cfg(feature = "mptcp")]
+    pub(crate) mptcp: bool,
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// every connection setting of `config`, the other options stay. the mss is kept
+    /// below the one the mtu of the stack allows
+    pub fn connection(mut self, config: ConnectionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn connection_config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    /// time to live of outgoing ip packets
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.config.set_ttl(ttl);
+        self
+    }
+
+    /// drop packets of the peer arriving with a lower ttl, see `ConnectionConfig::set_min_ttl`
+    pub fn min_ttl(mut self, ttl: u8) -> Self {
+        self.config.set_min_ttl(ttl);
+        self
+    }
+
+    /// ttl security for a directly connected peer, see `ConnectionConfig::set_gtsm`
+    pub fn gtsm(mut self, gtsm: bool) -> Self {
+        self.config.set_gtsm(gtsm);
+        self
+    }
+
+    /// type of service byte of outgoing ip packets, DSCP << 2 | ECN
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.config.set_tos(tos);
+        self
+    }
+
+    /// code point of outgoing ip packets, e.g. `Dscp::EF` for latency critical streams
+    pub fn dscp(mut self, dscp: Dscp) -> Self {
+        self.config.set_dscp(dscp);
+        self
+    }
+
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.config.set_recv_buffer_size(size);
+        self
+    }
+
+    /// largest size receive buffer autotuning grows to, see `ConnectionConfig::set_recv_buffer_max`
+    pub fn recv_buffer_max(mut self, size: usize) -> Self {
+        self.config.set_recv_buffer_max(size);
+        self
+    }
+
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.config.set_send_buffer_size(size);
+        self
+    }
+
+    /// largest size send buffer autotuning grows to, see `ConnectionConfig::set_send_buffer_max`
+    pub fn send_buffer_max(mut self, size: usize) -> Self {
+        self.config.set_send_buffer_max(size);
+        self
+    }
+
+    /// what writes do when the send buffer is full
+    pub fn write_policy(mut self, policy: WritePolicy) -> Self {
+        self.write_policy = policy;
+        self
+    }
+
+    /// bytes which have to be received before the stream is reported readable
+    pub fn recv_low_watermark(mut self, bytes: usize) -> Self {
+        self.config.set_recv_low_watermark(bytes);
+        self
+    }
+
+    /// free send buffer space needed before the stream is reported writable
+    pub fn send_low_watermark(mut self, bytes: usize) -> Self {
+        self.config.set_send_low_watermark(bytes);
+        self
+    }
+
+    pub fn congestion(mut self, algorithm: CongestionAlgorithm) -> Self {
+        self.config.set_congestion(algorithm);
+        self
+    }
+
+    /// probe the connections idle for `idle`, see `ConnectionConfig::set_keep_alive`
+    pub fn keep_alive(mut self, idle: Option<Duration>) -> Self {
+        self.config.set_keep_alive(idle);
+        self
+    }
+
+    /// detect spurious retransmission timeouts, see `ConnectionConfig::set_frto`
+    pub fn frto(mut self, frto: bool) -> Self {
+        self.config.set_frto(frto);
+        self
+    }
+
+    /// send timestamps if the peer does, see `ConnectionConfig::set_timestamps`
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.config.set_timestamps(timestamps);
+        self
+    }
+
+    /// what dropping a stream does, see `TcpStream::set_linger`
+    pub fn linger(mut self, linger: Option<Duration>) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    /// allow `bind` while connections still use the port (e.g. in TIME-WAIT)
+    /// and next to a listener of an overlapping address
+    pub fn reuse_addr(mut self, reuse: bool) -> Self {
+        self.reuse_addr = reuse;
+        self
+    }
+}
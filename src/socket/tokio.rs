@@ -0,0 +1,112 @@
+use std::future::poll_fn;
+use std::io::{IoSlice, Result};
+use std::net::SocketAddrV4;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::stack::NetStack;
+use crate::tcp::vars::TcpState;
+
+use super::{TcpListener, TcpStream};
+
+/// `TcpListener` for async code, usually on a stack started by `NetStack::spawn`
+pub struct AsyncTcpListener {
+    inner: TcpListener,
+}
+
+impl AsyncTcpListener {
+    pub fn bind(stack: &NetStack, port: u16) -> Result<Self> {
+        Ok(Self { inner: TcpListener::bind(stack, port)? })
+    }
+
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.inner.local_addr()
+    }
+
+    /// wait for the next established connection
+    pub async fn accept(&self) -> Result<AsyncTcpStream> {
+        let inner = poll_fn(|cx| self.inner.poll_accept(cx)).await?;
+        Ok(AsyncTcpStream { inner })
+    }
+}
+
+/// `TcpStream` implementing tokio's `AsyncRead` and `AsyncWrite`
+pub struct AsyncTcpStream {
+    inner: TcpStream,
+}
+
+impl AsyncTcpStream {
+    /// open a connection and wait for the handshake to complete, dropping the future
+    /// before, e.g. in `tokio::time::timeout`, cancels the connection
+    pub async fn connect(stack: &NetStack, addr: SocketAddrV4) -> Result<Self> {
+        let inner = TcpStream::start_connect(stack, addr, stack.default_options())?.await?;
+        Ok(Self { inner })
+    }
+
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.inner.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> SocketAddrV4 {
+        self.inner.peer_addr()
+    }
+
+    pub fn state(&self) -> TcpState {
+        self.inner.state()
+    }
+
+    /// see `TcpStream::bytes_available`
+    pub fn bytes_available(&self) -> Result<usize> {
+        self.inner.bytes_available()
+    }
+
+    /// read without taking the bytes, the next read returns them again
+    pub async fn peek(&self, buf: &mut [u8]) -> Result<usize> {
+        poll_fn(|cx| self.inner.poll_peek(cx, buf)).await
+    }
+
+    /// wait until `buf` can be filled without taking the bytes, see `TcpStream::poll_peek_exact`
+    pub async fn peek_exact(&self, buf: &mut [u8]) -> Result<()> {
+        poll_fn(|cx| self.inner.poll_peek_exact(cx, buf)).await
+    }
+
+    pub fn into_inner(self) -> TcpStream {
+        self.inner
+    }
+}
+
+impl AsyncRead for AsyncTcpStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let n = match self.inner.poll_read(cx, buf.initialize_unfilled()) {
+            Poll::Ready(res) => res?,
+            Poll::Pending => return Poll::Pending,
+        };
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for AsyncTcpStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.inner.poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice]) -> Poll<Result<usize>> {
+        self.inner.poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    /// written bytes are handed to the stack right away
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(self.inner.shutdown())
+    }
+}
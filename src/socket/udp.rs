@@ -0,0 +1,93 @@
+use std::io::{ErrorKind, Result};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::result;
+use crate::stack::{NetStack, Shared};
+use crate::udp::Binding;
+
+use super::IntoPoll;
+
+/// A udp port of a `NetStack`, unbound when the socket is dropped
+pub struct UdpSocket {
+    shared: Arc<Shared>,
+    local: SocketAddrV4,
+}
+
+impl UdpSocket {
+    /// bind `port` of every address of the stack, 0 picks a free ephemeral port
+    pub fn bind(stack: &NetStack, port: u16) -> Result<Self> {
+        Self::bind_addr(stack, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))
+    }
+
+    /// bind `addr`, an address of the stack or `0.0.0.0`
+    pub fn bind_addr(stack: &NetStack, addr: SocketAddrV4) -> Result<Self> {
+        let shared = stack.shared().clone();
+        let local = shared.bind_udp(addr)?;
+        Ok(Self { shared, local })
+    }
+
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.local
+    }
+
+    /// queue `buf` as one datagram to `addr`, larger than the mtu allows is `ErrorKind::InvalidInput`
+    pub fn send_to(&self, buf: &[u8], addr: SocketAddrV4) -> Result<usize> {
+        self.shared.send_udp(self.local, addr, buf)?;
+        Ok(buf.len())
+    }
+
+    /// the next datagram and its sender, the part which doesn't fit `buf` is discarded.
+    /// `ErrorKind::WouldBlock` if none arrived
+    pub fn try_recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4)> {
+        self.with_binding(|binding| recv_from(binding, buf))
+    }
+
+    pub fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<(usize, SocketAddrV4)>> {
+        self.with_binding(|binding| {
+            let res = recv_from(binding, buf);
+            if matches!(&res, Err(e) if e.kind() == ErrorKind::WouldBlock) {
+                binding.waker = Some(cx.waker().clone());
+            }
+            res
+        }).into_poll()
+    }
+
+    /// `None` blocks `recv_from` until a datagram arrives, otherwise it fails with `ErrorKind::TimedOut`
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        super::blocking::check_timeout(timeout)?;
+        self.with_binding(|binding| {
+            binding.read_timeout = timeout;
+            Ok(())
+        })
+    }
+
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        self.with_binding(|binding| Ok(binding.read_timeout))
+    }
+
+    fn with_binding<T, F: FnOnce(&mut Binding) -> Result<T>>(&self, f: F) -> Result<T> {
+        if self.shared.is_device_gone() {
+            return Err(result::Error::DeviceGone.into());
+        }
+        match self.shared.lock_udp().binding_mut(&self.local) {
+            Some(binding) => f(binding),
+            None => Err(ErrorKind::NotConnected.into()),
+        }
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        self.shared.lock_udp().unbind(&self.local);
+    }
+}
+
+fn recv_from(binding: &mut Binding, buf: &mut [u8]) -> Result<(usize, SocketAddrV4)> {
+    let (src, datagram) = binding.queue.pop_front().ok_or(ErrorKind::WouldBlock)?;
+    let n = datagram.len().min(buf.len());
+    buf[..n].copy_from_slice(&datagram[..n]);
+    Ok((n, src))
+}
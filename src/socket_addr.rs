@@ -0,0 +1,153 @@
+use core::convert::TryFrom;
+use core::fmt;
+use core::net::{AddrParseError, IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use core::str::FromStr;
+
+use etherparse::{Ipv4HeaderSlice, TcpHeaderSlice};
+
+use crate::result;
+
+/// The two ends of a connection, ordered by source then destination.
+/// written and parsed as `1.2.3.4:80 -> 5.6.7.8:443`
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quad {
+    src: Addr,
+    dest: Addr,
+}
+
+impl Quad {
+    pub fn new(src: Addr, dest: Addr) -> Self {
+        Self {
+            src,
+            dest,
+        }
+    }
+
+    pub fn from_tcpip_header<'a>(ip_header: &Ipv4HeaderSlice<'a>, tcp_header: &TcpHeaderSlice<'a>) -> Self {
+        Self::new(
+            Addr::new(ip_header.source_addr(), tcp_header.source_port()),
+            Addr::new(ip_header.destination_addr(), tcp_header.destination_port()),
+        )
+    }
+
+    pub fn src(&self) -> Addr {
+        self.src
+    }
+
+    pub fn dest(&self) -> Addr {
+        self.dest
+    }
+
+    /// swap source and destination, a received packet is turned into
+    /// the (local, remote) quad connections are keyed by
+    pub fn reverse(&self) -> Self {
+        Self::new(self.dest, self.src)
+    }
+}
+
+impl fmt::Display for Quad {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.src, self.dest)
+    }
+}
+
+impl FromStr for Quad {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // without an arrow the empty destination fails to parse
+        let (src, dest) = s.split_once("->").unwrap_or((s, ""));
+        Ok(Self::new(src.trim().parse()?, dest.trim().parse()?))
+    }
+}
+
+/// An ip address and a port, ipv4 addresses sort before ipv6 ones
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Addr {
+    ip: IpAddr,
+    port: u16,
+}
+
+impl Addr {
+    pub fn new(ip: impl Into<IpAddr>, port: u16) -> Self {
+        Self {
+            ip: ip.into(),
+            port,
+        }
+    }
+
+    pub const fn v4(ip: Ipv4Addr, port: u16) -> Self {
+        Self {
+            ip: IpAddr::V4(ip),
+            port,
+        }
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// the address if it's an ipv4 one
+    pub fn ipv4(&self) -> Option<Ipv4Addr> {
+        match self.ip {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        SocketAddr::from(*self).fmt(f)
+    }
+}
+
+impl FromStr for Addr {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse::<SocketAddr>()?.into())
+    }
+}
+
+impl From<SocketAddr> for Addr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::new(addr.ip(), addr.port())
+    }
+}
+
+impl From<SocketAddrV4> for Addr {
+    fn from(addr: SocketAddrV4) -> Self {
+        Self::new(*addr.ip(), addr.port())
+    }
+}
+
+impl From<SocketAddrV6> for Addr {
+    fn from(addr: SocketAddrV6) -> Self {
+        Self::new(*addr.ip(), addr.port())
+    }
+}
+
+impl From<Addr> for SocketAddr {
+    fn from(addr: Addr) -> Self {
+        SocketAddr::new(addr.ip, addr.port)
+    }
+}
+
+/// fails for an ipv6 address
+impl TryFrom<Addr> for SocketAddrV4 {
+    type Error = result::Error;
+
+    fn try_from(addr: Addr) -> result::Result<Self> {
+        match addr.ipv4() {
+            Some(ip) => Ok(SocketAddrV4::new(ip, addr.port)),
+            None => Err(result::Error::AddressFamily(addr.into())),
+        }
+    }
+}
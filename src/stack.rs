@@ -0,0 +1,2060 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+#[cfg(feature = "tcp-ao")]
+use std::net::IpAddr;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::ops::RangeInclusive;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock};
+use std::task::Waker;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use etherparse::{IpNumber, Ipv4HeaderSlice, TcpHeaderSlice};
+
+use crate::capture::{CaptureId, CaptureRing, CapturedFrame, Captures, Capturing, Direction, Filter, Sink};
+use crate::clock::Clock;
+use crate::config::{AddressChange, DeviceMode, Reconfigure, SourceRoute, StackConfig};
+#[cfg(any(target_os = "linux", all(target_os = "macos", feature = "utun")))]
+use crate::data_link::tun::Tun;
+#[cfg(target_os = "linux")]
+use crate::data_link::iface;
+#[cfg(target_os = "linux")]
+use crate::data_link::tun::TunQueue;
+use crate::data_link::arp::{ArpCache, MacAddr};
+use crate::data_link::ethernet::Ethernet;
+#[cfg(feature = "tokio")]
+use crate::data_link::gro::Coalescer;
+use crate::data_link::{is_device_gone, recv_buffer_len, Capabilities, DataLayer};
+use crate::event_fd::EventFd;
+use crate::event_loop::{Context, EventLoop, Handler, Reopen};
+use crate::firewall::{Firewall, Rule, RuleId, Verdict};
+use crate::icmp::{self, IcmpBuilder, IcmpError};
+use crate::ip_options::IpOptionSet;
+use crate::meta::{IP_HEADER_MAXIMUM_SIZE, TCP_HEADER_MAXIMUM_SIZE};
+use crate::metrics::{Metered, Metrics, Snmp};
+#[cfg(feature = "mptcp")]
+use crate::mptcp::options::MptcpOption;
+#[cfg(feature = "mptcp")]
+use crate::mptcp::subflow::{self, Subflow};
+#[cfg(feature = "mptcp")]
+use crate::mptcp::Registry;
+use crate::netstat::{ConnectionInfo, TimerKind};
+use crate::observer::{ConnectionObserver, ObserverId, Observers};
+use crate::reader_writer::RawReader;
+use crate::result;
+use crate::socket_addr::{Addr, Quad};
+use crate::socket::{Interest, SocketOptions, WritePolicy};
+#[cfg(feature = "serde")]
+use crate::tcp::checkpoint::Checkpoint;
+#[cfg(feature = "tcp-ao")]
+use crate::tcp::ao::{KeyChain, Mkt};
+use crate::tcp::connection::{send_reset, ConnectionConfig, TcpConnection, MSL};
+use crate::tcp::reassembly::ReassemblyBudget;
+use crate::tcp::shaper::Shaper;
+use crate::table::{rss_hash, SocketTable};
+use crate::tcp::vars::TcpState;
+use crate::udp::{self, UdpTable};
+use crate::timer::{TimerId, TimerWheel, DEFAULT_WHEEL_SLOTS};
+
+/// how often `NetStack::shutdown` looks whether the connections closed
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// how long `NetStack::shutdown` waits for the drivers to send the resets
+const SHUTDOWN_RESET_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum StackTimer {
+    TimeWait(Quad),
+    /// the retransmission timer or the handshake deadline of the connection
+    Retransmit(Quad),
+}
+
+/// A connection and the application tasks waiting on it
+pub(crate) struct Socket {
+    pub(crate) conn: TcpConnection,
+    pub(crate) read_waker: Option<Waker>,
+    pub(crate) write_waker: Option<Waker>,
+    /// free send buffer the blocked writer waits for
+    pub(crate) write_need: usize,
+    pub(crate) write_policy: WritePolicy,
+    /// limits of the blocking `Read` and `Write` implementations of `TcpStream`
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    /// how long dropping the stream waits for the queued data to be acknowledged,
+    /// zero resets the connection
+    pub(crate) linger: Option<Duration>,
+    /// the listener which created it, until `accept` hands it out
+    pending_accept: Option<Addr>,
+    /// the application dropped its handle
+    released: bool,
+    /// the TIME-WAIT timer and when it fires
+    time_wait: Option<(TimerId, Instant)>,
+    /// the timer of `TcpConnection::next_timeout` and when it fires
+    retransmit: Option<(TimerId, Instant)>,
+    /// readiness last reported through the readiness fd
+    readiness: Interest,
+    /// state and retransmissions already counted in the stack metrics
+    state: TcpState,
+    retransmits: u64,
+    /// the device of the stack went away, the connection can't go on
+    pub(crate) device_gone: bool,
+}
+
+impl Socket {
+    fn new(conn: TcpConnection, options: &SocketOptions, pending_accept: Option<Addr>) -> Self {
+        Self {
+            linger: options.linger,
+            read_waker: None,
+            write_waker: None,
+            write_need: 0,
+            write_policy: options.write_policy,
+            read_timeout: None,
+            write_timeout: None,
+            pending_accept,
+            released: false,
+            time_wait: None,
+            retransmit: None,
+            readiness: Interest::empty(),
+            state: conn.state(),
+            retransmits: 0,
+            device_gone: false,
+            conn,
+        }
+    }
+
+    pub(crate) fn readiness(&self) -> Interest {
+        let conn = &self.conn;
+        let mut readiness = Interest::empty();
+        if conn.is_reset() || conn.is_timed_out() || conn.is_addr_removed() || self.device_gone {
+            return Interest::READABLE | Interest::WRITABLE | Interest::HUP | Interest::ERROR;
+        }
+        if conn.is_readable() || conn.is_eof() || conn.state() == TcpState::Closed {
+            readiness = readiness | Interest::READABLE;
+        }
+        if conn.is_synchronized() && !conn.is_write_closed() && conn.is_writable() {
+            readiness = readiness | Interest::WRITABLE;
+        }
+        if conn.is_eof() || conn.state() == TcpState::Closed {
+            readiness = readiness | Interest::HUP;
+        }
+        readiness
+    }
+
+    /// count what changed since the last call
+    fn observe(&mut self, metrics: &Metrics) {
+        let state = self.conn.state();
+        if state != self.state {
+            metrics.on_transition(self.state, state);
+            self.state = state;
+        }
+        let retransmits = self.conn.stats().retransmits;
+        Metrics::add(&metrics.tcp_retrans_segs, retransmits - self.retransmits);
+        self.retransmits = retransmits;
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        // a writer waiting for more room than the ACKs freed keeps sleeping
+        if self.conn.send_space() < self.write_need && !self.conn.is_write_closed() {
+            return;
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Established connections waiting for `accept`
+pub(crate) struct Listener {
+    pub(crate) backlog: VecDeque<Quad>,
+    pub(crate) waker: Option<Waker>,
+    /// inherited by the accepted connections
+    pub(crate) options: SocketOptions,
+}
+
+impl Listener {
+    fn new(options: SocketOptions) -> Self {
+        Self {
+            backlog: VecDeque::new(),
+            waker: None,
+            options,
+        }
+    }
+}
+
+/// Connections which moved to another local address: the quad they use now
+/// and the one they're still kept under, shared by the shards
+type Migrations = Arc<RwLock<HashMap<Quad, Quad>>>;
+
+/// Change of the addresses of a stack, published to the connection table of every shard
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AddrEvent {
+    Added(Ipv4Addr),
+    Removed(Ipv4Addr),
+    /// the address changed, like after a VPN reconnect
+    Replaced { old: Ipv4Addr, new: Ipv4Addr },
+}
+
+/// Everything shared between the packet processing driver and the socket handles
+pub(crate) struct StackState {
+    /// the first one is the source address of active opens
+    pub(crate) addrs: Vec<Ipv4Addr>,
+    /// accept segments to addresses other than `addrs`
+    transparent: bool,
+    ephemeral_ports: RangeInclusive<u16>,
+    /// largest ip packet sent, the mss of the connections derives from it
+    mtu: usize,
+    /// used by sockets created without explicit options
+    pub(crate) options: SocketOptions,
+    pub(crate) table: SocketTable<Socket, Listener>,
+    next_port: u16,
+    /// signaled when a connection or listener becomes ready, created on demand
+    events: Option<Arc<EventFd>>,
+    metrics: Arc<Metrics>,
+    captures: Arc<Captures>,
+    observers: Arc<Observers>,
+    firewall: Arc<Firewall>,
+    clock: Arc<dyn Clock>,
+    /// out of order memory of all the shards
+    reassembly: ReassemblyBudget,
+    /// rate limit of all the shards
+    shaper: Option<Shaper>,
+    /// limit of the ICMP errors of all the shards, one token a message
+    icmp_errors: Option<Shaper>,
+    address_change: AddressChange,
+    source_route: SourceRoute,
+    migrations: Migrations,
+    /// udp ports of all the shards
+    udp: Arc<Mutex<UdpTable>>,
+    /// TCP-AO keys of all the shards
+    #[cfg(feature = "tcp-ao")]
+    keys: Arc<KeyChain>,
+    /// MPTCP connections of all the shards, subflows join them by token
+    #[cfg(feature = "mptcp")]
+    mptcp: Arc<Registry>,
+}
+
+/// What every shard of a stack has a handle of
+#[derive(Clone)]
+struct Common {
+    metrics: Arc<Metrics>,
+    captures: Arc<Captures>,
+    observers: Arc<Observers>,
+    firewall: Arc<Firewall>,
+    reassembly: ReassemblyBudget,
+    shaper: Option<Shaper>,
+    icmp_errors: Option<Shaper>,
+    migrations: Migrations,
+    udp: Arc<Mutex<UdpTable>>,
+    #[cfg(feature = "tcp-ao")]
+    keys: Arc<KeyChain>,
+    #[cfg(feature = "mptcp")]
+    mptcp: Arc<Registry>,
+}
+
+impl StackState {
+    fn new(config: &StackConfig, common: Common) -> Self {
+        let Common {
+            metrics, captures, observers, firewall, reassembly, shaper, icmp_errors, migrations, udp,
+            #[cfg(feature = "tcp-ao")]
+            keys,
+            #[cfg(feature = "mptcp")]
+            mptcp,
+        } = common;
+        Self {
+            addrs: config.addrs().to_vec(),
+            transparent: config.transparent(),
+            ephemeral_ports: config.ephemeral_ports(),
+            mtu: config.mtu(),
+            options: SocketOptions {
+                config: *config.connection(),
+                ..SocketOptions::default()
+            },
+            table: SocketTable::new(),
+            next_port: *config.ephemeral_ports().start(),
+            events: None,
+            metrics,
+            captures,
+            observers,
+            firewall,
+            clock: config.clock().clone(),
+            reassembly,
+            shaper,
+            icmp_errors,
+            address_change: config.address_change(),
+            source_route: config.source_route(),
+            migrations,
+            udp,
+            #[cfg(feature = "tcp-ao")]
+            keys,
+            #[cfg(feature = "mptcp")]
+            mptcp,
+        }
+    }
+
+    pub(crate) fn addr(&self) -> Ipv4Addr {
+        self.addrs[0]
+    }
+
+    pub(crate) fn listener_readiness(&self, local: &Addr) -> Interest {
+        match self.table.listener(local) {
+            Some(listener) if !listener.backlog.is_empty() => Interest::READABLE,
+            Some(_) => Interest::empty(),
+            None => Interest::HUP,
+        }
+    }
+
+    fn signal_readiness(&self) {
+        if let Some(events) = &self.events {
+            if let Err(e) = events.signal() {
+                warn!(error = ?e, "signal readiness");
+            }
+        }
+    }
+
+    /// the segments of connections with `options` fit the mtu, like the ones of the default options
+    fn fit(&self, mut options: SocketOptions) -> SocketOptions {
+        options.config.fit_mss(self.mtu - IP_HEADER_MAXIMUM_SIZE - TCP_HEADER_MAXIMUM_SIZE);
+        options
+    }
+
+    /// `local` is the stack address or `0.0.0.0`, `0.0.0.0:0` in a transparent stack
+    fn can_listen(&self, local: Addr, options: &SocketOptions) -> io::Result<()> {
+        if local.port() == 0 && (!local.ip().is_unspecified() || !self.transparent) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "port 0 needs a transparent stack"));
+        }
+        if !local.ip().is_unspecified() && !local.ipv4().is_some_and(|ip| self.addrs.contains(&ip)) {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "not an address of the stack"));
+        }
+        let in_use = || io::Error::from(result::Error::AddressInUse(local.into()));
+        if !options.reuse_addr {
+            let overlapping = self.table.listeners_on_port(local.port())
+                .any(|addr| addr.ip() == local.ip() || addr.ip().is_unspecified() || local.ip().is_unspecified());
+            if overlapping || self.table.has_connection_on_port(local.port()) {
+                return Err(in_use());
+            }
+        }
+        Ok(())
+    }
+
+    /// connections established but never accepted are closed
+    pub(crate) fn unlisten(&mut self, local: &Addr) {
+        if let Some(listener) = self.table.unlisten(local) {
+            // a task waiting in accept finds the listener gone
+            if let Some(waker) = listener.waker {
+                waker.wake();
+            }
+            for quad in listener.backlog {
+                self.release(quad);
+            }
+        }
+        let embryonic: Vec<Quad> = self.table.iter()
+            .filter(|(_, sock)| sock.pending_accept == Some(*local))
+            .map(|(quad, _)| *quad)
+            .collect();
+        for quad in embryonic {
+            self.release(quad);
+        }
+    }
+
+    /// apply what `changes` holds but the log level and the firewall, the shapers are
+    /// shared with the other shards
+    fn reconfigure(&mut self, changes: &Reconfigure, shaper: &Option<Option<Shaper>>, icmp_errors: &Option<Option<Shaper>>) {
+        let update = |config: &mut ConnectionConfig| {
+            if let Some(congestion) = changes.congestion {
+                config.set_congestion(congestion);
+            }
+            if let Some(limit) = changes.rate_limit {
+                config.set_rate_limit(limit);
+            }
+        };
+        update(&mut self.options.config);
+        for (_, listener) in self.table.listeners_mut() {
+            update(&mut listener.options.config);
+        }
+        if let Some(shaper) = shaper {
+            self.shaper = shaper.clone();
+        }
+        if let Some(icmp_errors) = icmp_errors {
+            self.icmp_errors = icmp_errors.clone();
+        }
+        // the open connections keep their congestion control, the rest applies to them
+        for (_, sock) in self.table.iter_mut() {
+            if let Some(limit) = changes.rate_limit {
+                sock.conn.config_mut().set_rate_limit(limit);
+            }
+            if shaper.is_some() {
+                sock.conn.set_shaper(self.shaper.clone());
+            }
+        }
+    }
+
+    /// stop listening and close every connection, the FINs leave with the next flush
+    fn drain(&mut self) {
+        let listeners: Vec<Addr> = self.table.listeners().map(|(local, _)| *local).collect();
+        for local in listeners {
+            self.unlisten(&local);
+        }
+        for quad in self.table.quads() {
+            if let Some(sock) = self.table.get_mut(&quad) {
+                sock.conn.close();
+            }
+        }
+    }
+
+    /// the device went away for good: the listeners close and the tasks waiting on the
+    /// connections find them failed, nothing can be sent anymore
+    fn on_device_gone(&mut self) {
+        let listeners: Vec<Addr> = self.table.listeners().map(|(local, _)| *local).collect();
+        for local in listeners {
+            self.unlisten(&local);
+        }
+        for quad in self.table.quads() {
+            if let Some(sock) = self.table.get_mut(&quad) {
+                sock.device_gone = true;
+                if let Some(waker) = sock.read_waker.take() {
+                    waker.wake();
+                }
+                if let Some(waker) = sock.write_waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+        self.signal_readiness();
+    }
+
+    /// connections the peer hasn't closed yet, TIME-WAIT counts as closed
+    fn open_connections(&self) -> usize {
+        self.table.iter()
+            .filter(|(_, sock)| !matches!(sock.conn.state(), TcpState::Closed | TcpState::TimeWait))
+            .count()
+    }
+
+    /// reset the open connections, the RSTs leave with the next flush
+    fn abort_open(&mut self) -> usize {
+        let mut aborted = 0;
+        for quad in self.table.quads() {
+            if let Some(sock) = self.table.get_mut(&quad) {
+                if !matches!(sock.conn.state(), TcpState::Closed | TcpState::TimeWait) {
+                    sock.conn.abort();
+                    aborted += 1;
+                }
+            }
+        }
+        aborted
+    }
+
+    /// active open from `local`, the SYN leaves with the next flush
+    fn connect(&mut self, local: Addr, remote: Addr, options: SocketOptions) -> Quad {
+        let mut conn = TcpConnection::open(local, remote, options.config);
+        conn.set_clock(self.clock.clone());
+        conn.set_reassembly_budget(self.reassembly.clone());
+        conn.set_shaper(self.shaper.clone());
+        conn.set_observers(self.observers.clone());
+        #[cfg(feature = "tcp-ao")]
+        conn.set_authenticator(self.keys.authenticator(&conn.quad()));
+        let quad = conn.quad();
+        Metrics::inc(&self.metrics.tcp_active_opens);
+        self.table.insert(quad, Socket::new(conn, &options, None));
+        quad
+    }
+
+    /// the connection of `checkpoint` taken up again, the ACK leaves with the next flush
+    #[cfg(feature = "serde")]
+    fn restore(&mut self, checkpoint: Checkpoint, options: SocketOptions) -> Quad {
+        let mut conn = TcpConnection::restore(checkpoint, self.clock.clone());
+        conn.set_reassembly_budget(self.reassembly.clone());
+        conn.set_shaper(self.shaper.clone());
+        conn.set_observers(self.observers.clone());
+        #[cfg(feature = "tcp-ao")]
+        conn.set_authenticator(self.keys.authenticator(&conn.quad()));
+        let quad = conn.quad();
+        let mut sock = Socket::new(conn, &options, None);
+        // counted by the stack the checkpoint was taken in
+        sock.retransmits = sock.conn.stats().retransmits;
+        self.table.insert(quad, sock);
+        quad
+    }
+
+    /// the application dropped its handle, close the connection
+    /// and forget it once it's closed
+    pub(crate) fn release(&mut self, quad: Quad) {
+        if let Some(sock) = self.table.get_mut(&quad) {
+            sock.released = true;
+            sock.conn.close();
+            sock.observe(&self.metrics);
+            if sock.conn.state() == TcpState::Closed {
+                self.forget(quad);
+            }
+        }
+    }
+
+    /// the MPTCP connection the subflow `quad` belongs to takes over, the subflow
+    /// is forgotten once it's closed
+    #[cfg(feature = "mptcp")]
+    pub(crate) fn release_subflow(&mut self, quad: Quad) -> Option<Subflow> {
+        let sock = self.table.get_mut(&quad)?;
+        sock.released = true;
+        let subflow = sock.conn.subflow().cloned();
+        if sock.conn.state() == TcpState::Closed {
+            self.forget(quad);
+        }
+        subflow
+    }
+
+    /// drop the connection `quad` and the quad it migrated to
+    fn forget(&mut self, quad: Quad) {
+        if let Some(sock) = self.table.remove(&quad) {
+            if sock.conn.quad() != quad {
+                write_migrations(&self.migrations).remove(&sock.conn.quad());
+            }
+        }
+    }
+
+    /// the connections sending from `old` go on from `new` or are reset,
+    /// `taken` tells if a quad is used in another shard
+    fn on_addr_lost(&mut self, old: Ipv4Addr, new: Option<Ipv4Addr>, taken: &dyn Fn(&Quad) -> bool) {
+        for key in self.table.quads() {
+            let quad = match self.table.get(&key) {
+                Some(sock) if sock.conn.quad().src().ip() == old => sock.conn.quad(),
+                _ => continue,
+            };
+            let mut migrations = write_migrations(&self.migrations);
+            let moved = match new {
+                Some(new) if self.address_change == AddressChange::Migrate => {
+                    Some(Quad::new(Addr::new(new, quad.src().port()), quad.dest()))
+                }
+                _ => None,
+            };
+            let sock = match self.table.get_mut(&key) {
+                Some(sock) => sock,
+                None => continue,
+            };
+            match moved {
+                Some(moved) if moved == key || (!taken(&moved) && !migrations.contains_key(&moved)) => {
+                    sock.conn.migrate(moved.src().ip());
+                    migrations.remove(&quad);
+                    if moved != key {
+                        migrations.insert(moved, key);
+                    }
+                }
+                _ => sock.conn.on_addr_removed(),
+            }
+            sock.wake();
+        }
+    }
+
+    pub(crate) fn on_frame<L: DataLayer + ?Sized>(
+        &mut self,
+        device: &mut L,
+        timers: &mut TimerWheel<StackTimer>,
+        frame: &[u8],
+    ) -> result::Result<()> {
+        let (metrics, captures) = (self.metrics.clone(), self.captures.clone());
+        let mut metered = Metered::new(device, &metrics);
+        let device = &mut Capturing::new(&mut metered, &captures);
+        let link = device.header_len();
+        captures.capture(Direction::In, frame, link);
+        if frame.len() <= link {
+            return Ok(());
+        }
+        let mut raw = RawReader::from_slice(frame, frame.len(), link);
+        if !raw.is_ipv4_packet()? {
+            return Ok(());
+        }
+        Metrics::inc(&metrics.ip_in_receives);
+        let ip = match raw.ipv4_header() {
+            Ok(ip) if ip.to_header().calc_header_checksum() == ip.header_checksum() => ip,
+            Ok(_) => {
+                Metrics::inc(&metrics.ip_in_hdr_errors);
+                return Ok(());
+            }
+            Err(e) => {
+                Metrics::inc(&metrics.ip_in_hdr_errors);
+                return Err(e);
+            }
+        };
+        if !self.transparent && !self.addrs.contains(&ip.destination_addr()) {
+            Metrics::inc(&metrics.ip_in_addr_errors);
+            return Ok(());
+        }
+        let options = match IpOptionSet::of(ip.options()) {
+            Ok(options) => options,
+            Err(_) => {
+                Metrics::inc(&metrics.ip_in_hdr_errors);
+                return Ok(());
+            }
+        };
+        if options.is_source_routed() && self.source_route == SourceRoute::Drop {
+            debug!(src = %ip.source_addr(), "source routed packet dropped");
+            return Ok(());
+        }
+        // every protocol goes through the firewall, the rules on ports and flags only match tcp
+        let verdict = self.firewall.check(frame, link);
+        if verdict == Verdict::Drop {
+            return Ok(());
+        }
+        if verdict == Verdict::Reject && ip.protocol() != IpNumber::TCP {
+            let error = IcmpError::unreachable(ip.protocol().0);
+            return self.send_icmp_error(device, error, ip.destination_addr(), &frame[link..]);
+        }
+        if ip.protocol().0 == icmp::PROTOCOL {
+            Metrics::inc(&metrics.ip_in_delivers);
+            return self.on_icmp(device, ip.destination_addr(), &frame[link..]);
+        }
+        if ip.protocol().0 == udp::PROTOCOL && lock_udp(&self.udp).deliver(&frame[link..]) {
+            Metrics::inc(&metrics.ip_in_delivers);
+            return Ok(());
+        }
+        if ip.protocol() != IpNumber::TCP {
+            Metrics::inc(&metrics.ip_in_unknown_protos);
+            let error = IcmpError::unreachable(ip.protocol().0);
+            return self.send_icmp_error(device, error, ip.destination_addr(), &frame[link..]);
+        }
+        Metrics::inc(&metrics.ip_in_delivers);
+        Metrics::inc(&metrics.tcp_in_segs);
+        let segment = match raw.segment() {
+            Ok(segment) => segment,
+            Err(e) => {
+                Metrics::inc(&metrics.tcp_in_errs);
+                return Err(e);
+            }
+        };
+        // the device verified it already
+        let offloaded = device.capabilities().contains(Capabilities::RX_CHECKSUM);
+        if !offloaded && !segment.checksum_valid()? {
+            Metrics::inc(&metrics.tcp_in_csum_errors);
+            Metrics::inc(&metrics.tcp_in_errs);
+            return Err(result::Error::ChecksumMismatch);
+        }
+        let (ip, tcp, data) = (segment.ip(), segment.tcp(), segment.payload());
+        if verdict == Verdict::Reject {
+            return send_reset(device, ip, tcp, data);
+        }
+
+        let quad = home(&self.migrations, segment.quad().reverse());
+        if let Some(sock) = self.table.get_mut(&quad) {
+            if !sock.conn.on_ip_header(ip) {
+                return Ok(());
+            }
+            sock.conn.on_segment(device, tcp, data)?;
+            self.update(quad, timers);
+            return Ok(());
+        }
+        let syn = tcp.syn() && !tcp.ack() && !tcp.rst();
+        // MP_JOIN goes to any address and port of the connection joined
+        #[cfg(feature = "mptcp")]
+        if syn {
+            match self.mptcp.join(&MptcpOption::find(tcp.options())) {
+                Some(Ok(subflow)) => {
+                    let options = SocketOptions { config: *subflow::lock(subflow.meta()).config(), ..self.options };
+                    if let Some(conn) = TcpConnection::accept_subflow(device, ip, tcp, data, options.config, subflow)? {
+                        self.insert_accepted(quad, conn, &options, None).released = true;
+                    }
+                    return Ok(());
+                }
+                Some(Err(token)) => {
+                    debug!(%quad, token, "MP_JOIN of no connection");
+                    return send_reset(device, ip, tcp, data);
+                }
+                None => {}
+            }
+        }
+        match self.table.find_listener(quad.src()) {
+            Some(local) if syn => {
+                let options = match self.table.listener(&local) {
+                    Some(listener) => listener.options,
+                    None => return Ok(()),
+                };
+                if let Some(conn) = self.passive_open(device, ip, tcp, data, &options)? {
+                    self.insert_accepted(quad, conn, &options, Some(local));
+                }
+            }
+            _ => send_reset(device, ip, tcp, data)?,
+        }
+        Ok(())
+    }
+
+    /// the connection a SYN opens on a listener with `options`
+    fn passive_open<L: DataLayer + ?Sized>(
+        &self,
+        device: &mut L,
+        ip: &Ipv4HeaderSlice,
+        tcp: &TcpHeaderSlice,
+        data: &[u8],
+        options: &SocketOptions,
+    ) -> result::Result<Option<TcpConnection>> {
+        #[cfg(feature = "tcp-ao")]
+        if let Some(auth) = self.keys.authenticator(&Quad::from_tcpip_header(ip, tcp).reverse()) {
+            return TcpConnection::accept_authenticated(device, ip, tcp, data, options.config, auth);
+        }
+        #[cfg(feature = "mptcp")]
+        if options.mptcp {
+            let subflow = self.mptcp.listen(&MptcpOption::find(tcp.options()), options.config)?;
+            return TcpConnection::accept_subflow(device, ip, tcp, data, options.config, subflow);
+        }
+        TcpConnection::accept_with_config(device, ip, tcp, data, options.config)
+    }
+
+    /// put a connection of a passive open in the table, `pending_accept` is the listener
+    /// handing it out
+    fn insert_accepted(&mut self, quad: Quad, mut conn: TcpConnection, options: &SocketOptions, pending_accept: Option<Addr>) -> &mut Socket {
+        conn.set_clock(self.clock.clone());
+        conn.set_reassembly_budget(self.reassembly.clone());
+        conn.set_shaper(self.shaper.clone());
+        conn.set_observers(self.observers.clone());
+        Metrics::inc(&self.metrics.tcp_passive_opens);
+        self.table.insert(quad, Socket::new(conn, options, pending_accept));
+        self.table.get_mut(&quad).expect("inserted")
+    }
+
+    /// answer a ping, for `traceroute -I` among others
+    fn on_icmp<L: DataLayer + ?Sized>(&mut self, device: &mut L, local: Ipv4Addr, packet: &[u8]) -> result::Result<()> {
+        match IcmpBuilder::new(local).echo_reply(packet) {
+            Some(reply) => send_ip(device, &reply),
+            None => Ok(()),
+        }
+    }
+
+    /// send `error` about `packet` from the address it was sent to, within the rate limit
+    fn send_icmp_error<L: DataLayer + ?Sized>(
+        &mut self,
+        device: &mut L,
+        error: IcmpError,
+        local: Ipv4Addr,
+        packet: &[u8],
+    ) -> result::Result<()> {
+        let limit = match &self.icmp_errors {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let message = match IcmpBuilder::new(local).error(error, packet) {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let now = self.clock.now();
+        if limit.ready_at(1, now).is_some() {
+            trace!(?error, "ICMP error rate limited");
+            return Ok(());
+        }
+        limit.charge(1, now);
+        send_ip(device, &message)
+    }
+
+    pub(crate) fn on_timer<L: DataLayer + ?Sized>(
+        &mut self,
+        device: &mut L,
+        timers: &mut TimerWheel<StackTimer>,
+        timer: StackTimer,
+    ) -> result::Result<()> {
+        match timer {
+            StackTimer::TimeWait(quad) => {
+                if let Some(sock) = self.table.get_mut(&quad) {
+                    sock.time_wait = None;
+                    sock.conn.expire_time_wait();
+                    self.update(quad, timers);
+                }
+            }
+            StackTimer::Retransmit(quad) => {
+                let (metrics, captures) = (self.metrics.clone(), self.captures.clone());
+                let mut metered = Metered::new(device, &metrics);
+                let device = &mut Capturing::new(&mut metered, &captures);
+                if let Some(sock) = self.table.get_mut(&quad) {
+                    sock.retransmit = None;
+                    sock.conn.on_timeout(device)?;
+                    self.update(quad, timers);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// send what the applications queued since the last flush
+    pub(crate) fn flush<L: DataLayer + ?Sized>(&mut self, device: &mut L, timers: &mut TimerWheel<StackTimer>) -> result::Result<()> {
+        device.flush()?;
+        let (metrics, captures) = (self.metrics.clone(), self.captures.clone());
+        let mut metered = Metered::new(device, &metrics);
+        let device = &mut Capturing::new(&mut metered, &captures);
+        let datagrams = lock_udp(&self.udp).take_outbox();
+        for packet in datagrams {
+            send_ip(device, &packet)?;
+        }
+        for quad in self.table.quads() {
+            if let Some(sock) = self.table.get_mut(&quad) {
+                sock.conn.transmit(device)?;
+            }
+            self.update(quad, timers);
+        }
+        Ok(())
+    }
+
+    /// wake the application and take care of state transitions after the connection changed
+    fn update(&mut self, quad: Quad, timers: &mut TimerWheel<StackTimer>) {
+        let sock = match self.table.get_mut(&quad) {
+            Some(sock) => sock,
+            None => return,
+        };
+        sock.wake();
+        sock.observe(&self.metrics);
+        let mut signal = false;
+        let readiness = sock.readiness();
+        if readiness != sock.readiness {
+            sock.readiness = readiness;
+            signal = sock.pending_accept.is_none() && !sock.released;
+        }
+        let accepted = match sock.pending_accept {
+            Some(local) if sock.conn.is_synchronized() => {
+                sock.pending_accept = None;
+                Some(local)
+            }
+            _ => None,
+        };
+        let deadline = sock.conn.next_timeout();
+        if deadline != sock.retransmit.map(|(_, at)| at) {
+            if let Some((id, _)) = sock.retransmit.take() {
+                timers.cancel(id);
+            }
+            if let Some(deadline) = deadline {
+                let now = timers.now();
+                let id = timers.schedule(now, deadline.saturating_duration_since(now), StackTimer::Retransmit(quad));
+                sock.retransmit = Some((id, deadline));
+            }
+        }
+        let state = sock.conn.state();
+        if state == TcpState::TimeWait && sock.time_wait.is_none() {
+            let now = timers.now();
+            let id = timers.schedule(now, MSL * 2, StackTimer::TimeWait(quad));
+            sock.time_wait = Some((id, now + MSL * 2));
+        }
+
+        if let Some(local) = accepted {
+            match self.table.listener_mut(&local) {
+                Some(listener) => {
+                    listener.backlog.push_back(quad);
+                    if let Some(waker) = listener.waker.take() {
+                        waker.wake();
+                    }
+                    signal = true;
+                }
+                // the listener is gone, nobody will accept the connection
+                None => self.release(quad),
+            }
+        }
+        if let Some(sock) = self.table.get_mut(&quad) {
+            if state == TcpState::Closed && (sock.released || sock.pending_accept.is_some()) {
+                if let Some((id, _)) = sock.time_wait.take() {
+                    timers.cancel(id);
+                }
+                if let Some((id, _)) = sock.retransmit.take() {
+                    timers.cancel(id);
+                }
+                self.forget(quad);
+            }
+        }
+        if signal {
+            self.signal_readiness();
+        }
+    }
+}
+
+fn lock_udp(udp: &Mutex<UdpTable>) -> MutexGuard<'_, UdpTable> {
+    udp.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// send the ip `packet` behind an empty link header
+fn send_ip<L: DataLayer + ?Sized>(device: &mut L, packet: &[u8]) -> result::Result<()> {
+    let mut frame = vec![0; device.header_len()];
+    frame.extend_from_slice(packet);
+    device.send(&frame)?;
+    Ok(())
+}
+
+/// Part of the connection table and the driver processing it
+struct Shard {
+    state: Mutex<StackState>,
+    /// tell the driver the applications queued something
+    notify: Arc<dyn Fn() + Send + Sync>,
+    /// index of the driver
+    driver: usize,
+}
+
+/// The shards of a stack, a connection lives in the one picked by the `rss_hash`
+/// of its quad and listeners are in all of them. a driver locks one shard at a time,
+/// code locking several ones does it in index order
+pub(crate) struct Shared {
+    shards: Vec<Shard>,
+    stop: AtomicBool,
+    /// `NetStack::shutdown` is in progress, no new listeners or connections
+    draining: AtomicBool,
+    /// a driver lost its device, see `StackState::on_device_gone`
+    device_gone: AtomicBool,
+    migrations: Migrations,
+    udp: Arc<Mutex<UdpTable>>,
+    /// the neighbours of a stack on an ethernet link, see `NetStack::with_ethernet`
+    arp: OnceLock<ArpCache>,
+    #[cfg(feature = "mptcp")]
+    mptcp: Arc<Registry>,
+}
+
+impl Shared {
+    /// `StackConfig::shards` shards, at least one per notifier of a driver, which
+    /// drive them in turn
+    fn new(config: &StackConfig, notifiers: Vec<Box<dyn Fn() + Send + Sync>>) -> Self {
+        let notifiers: Vec<Arc<dyn Fn() + Send + Sync>> = notifiers.into_iter().map(Arc::from).collect();
+        #[cfg(feature = "mptcp")]
+        let kick = {
+            let notifiers = notifiers.clone();
+            Arc::new(move || notifiers.iter().for_each(|notify| notify()))
+        };
+        let common = Common {
+            metrics: Arc::new(Metrics::new()),
+            captures: Arc::new(Captures::default()),
+            observers: Arc::new(Observers::default()),
+            firewall: Arc::new(Firewall::default()),
+            reassembly: ReassemblyBudget::new(config.reassembly_memory()),
+            shaper: config.egress_limit().map(Shaper::new),
+            icmp_errors: config.icmp_errors().map(Shaper::new),
+            migrations: Migrations::default(),
+            udp: Arc::default(),
+            #[cfg(feature = "tcp-ao")]
+            keys: Arc::new(KeyChain::default()),
+            #[cfg(feature = "mptcp")]
+            mptcp: Arc::new(Registry::new(kick)),
+        };
+        let shards = (0..config.shards().max(notifiers.len()))
+            .map(|shard| {
+                let driver = shard % notifiers.len();
+                let notify = notifiers[driver].clone();
+                Shard { state: Mutex::new(StackState::new(config, common.clone())), notify, driver }
+            })
+            .collect();
+        Self {
+            shards,
+            stop: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            device_gone: AtomicBool::new(false),
+            migrations: common.migrations,
+            udp: common.udp,
+            arp: OnceLock::new(),
+            #[cfg(feature = "mptcp")]
+            mptcp: common.mptcp,
+        }
+    }
+
+    pub(crate) fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub(crate) fn shard_of(&self, quad: &Quad) -> usize {
+        match self.shards.len() {
+            1 => 0,
+            n => rss_hash(quad) as usize % n,
+        }
+    }
+
+    /// the shard of the connection `quad`
+    pub(crate) fn lock(&self, quad: &Quad) -> MutexGuard<'_, StackState> {
+        self.lock_shard(self.shard_of(quad))
+    }
+
+    pub(crate) fn lock_shard(&self, shard: usize) -> MutexGuard<'_, StackState> {
+        self.shards[shard].state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_all(&self) -> Vec<MutexGuard<'_, StackState>> {
+        (0..self.shards.len()).map(|shard| self.lock_shard(shard)).collect()
+    }
+
+    /// wake the driver of the connection `quad`
+    pub(crate) fn notify(&self, quad: &Quad) {
+        (self.shards[self.shard_of(quad)].notify)()
+    }
+
+    pub(crate) fn notify_all(&self) {
+        for shard in &self.shards {
+            (shard.notify)()
+        }
+    }
+
+    /// send what the applications queued on the shards of `driver`
+    fn flush<L: DataLayer + ?Sized>(
+        &self,
+        driver: usize,
+        device: &mut L,
+        timers: &mut TimerWheel<StackTimer>,
+    ) -> result::Result<()> {
+        for (shard, _) in self.shards.iter().enumerate().filter(|(_, shard)| shard.driver == driver) {
+            self.lock_shard(shard).flush(device, timers)?;
+        }
+        Ok(())
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Acquire)
+    }
+
+    fn check_draining(&self) -> io::Result<()> {
+        if self.is_device_gone() {
+            return Err(result::Error::DeviceGone.into());
+        }
+        if self.draining.load(Ordering::Acquire) {
+            return Err(result::Error::ShuttingDown.into());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_device_gone(&self) -> bool {
+        self.device_gone.load(Ordering::Acquire)
+    }
+
+    /// fail the listeners and connections of every shard, a stack is one device even
+    /// when each queue has its own driver
+    fn on_device_gone(&self) {
+        self.device_gone.store(true, Ordering::Release);
+        for shard in 0..self.shards.len() {
+            self.lock_shard(shard).on_device_gone();
+        }
+        self.lock_udp().wake_all();
+    }
+
+    fn open_connections(&self) -> usize {
+        (0..self.shards.len()).map(|shard| self.lock_shard(shard).open_connections()).sum()
+    }
+
+    /// wait until the connections are closed or `deadline` passed, the drivers do the work
+    fn wait_closed(&self, deadline: Instant) -> usize {
+        loop {
+            let open = self.open_connections();
+            if open == 0 || Instant::now() >= deadline {
+                return open;
+            }
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+    }
+
+    /// `local` is the stack address or `0.0.0.0`
+    pub(crate) fn listen(&self, local: Addr, options: SocketOptions) -> io::Result<()> {
+        self.check_draining()?;
+        let mut states = self.lock_all();
+        let options = states[0].fit(options);
+        for state in &states {
+            state.can_listen(local, &options)?;
+        }
+        for state in &mut states {
+            state.table.listen(local, Listener::new(options))
+                .map_err(|_| io::Error::from(result::Error::AddressInUse(local.into())))?;
+        }
+        Ok(())
+    }
+
+    /// bind the udp port `local`, an address of the stack or `0.0.0.0`. port 0 picks an ephemeral one
+    pub(crate) fn bind_udp(&self, local: SocketAddrV4) -> io::Result<SocketAddrV4> {
+        self.check_draining()?;
+        let state = self.lock_shard(0);
+        if !local.ip().is_unspecified() && !state.addrs.contains(local.ip()) {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "not an address of the stack"));
+        }
+        let mut udp = self.lock_udp();
+        udp.bind(local, state.ephemeral_ports.clone())
+    }
+
+    /// queue a datagram from `local`, which is `0.0.0.0` for the first address of the
+    /// stack, and wake a driver to send it
+    pub(crate) fn send_udp(&self, local: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> io::Result<()> {
+        self.check_draining()?;
+        let packet = {
+            let state = self.lock_shard(0);
+            if IP_HEADER_MAXIMUM_SIZE + udp::HEADER_LEN + payload.len() > state.mtu {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "datagram larger than the mtu"));
+            }
+            let ip = if local.ip().is_unspecified() { state.addr() } else { *local.ip() };
+            udp::datagram(SocketAddrV4::new(ip, local.port()), dst, state.options.config.ttl(), payload)
+        };
+        self.lock_udp().send(packet);
+        (self.shards[0].notify)();
+        Ok(())
+    }
+
+    pub(crate) fn lock_udp(&self) -> MutexGuard<'_, UdpTable> {
+        lock_udp(&self.udp)
+    }
+
+    /// `options` with the mss the mtu of the stack allows
+    pub(crate) fn fit(&self, options: SocketOptions) -> SocketOptions {
+        self.lock_shard(0).fit(options)
+    }
+
+    pub(crate) fn unlisten(&self, local: &Addr) {
+        for shard in 0..self.shards.len() {
+            self.lock_shard(shard).unlisten(local);
+        }
+    }
+
+    pub(crate) fn listener_readiness(&self, local: &Addr) -> Interest {
+        let mut readiness = Interest::empty();
+        for shard in 0..self.shards.len() {
+            readiness = readiness | self.lock_shard(shard).listener_readiness(local);
+        }
+        if readiness.is_hup() {
+            return Interest::HUP;
+        }
+        readiness
+    }
+
+    /// active open from an ephemeral port free in every shard
+    pub(crate) fn connect(&self, remote: Addr, options: SocketOptions) -> io::Result<Quad> {
+        self.connect_from(None, remote, options, |_| {})
+    }
+
+    /// active open of a subflow of an MPTCP connection from the address `local`, the
+    /// first one of the stack by default
+    #[cfg(feature = "mptcp")]
+    pub(crate) fn connect_subflow(&self, local: Option<Ipv4Addr>, remote: Addr, options: SocketOptions, subflow: Subflow) -> io::Result<Quad> {
+        self.connect_from(local, remote, options, |sock| {
+            sock.conn.set_subflow(subflow);
+            sock.released = true;
+        })
+    }
+
+    #[cfg(feature = "mptcp")]
+    pub(crate) fn mptcp(&self) -> &Arc<Registry> {
+        &self.mptcp
+    }
+
+    /// `setup` is called with the socket before the SYN is sent
+    fn connect_from<F: FnOnce(&mut Socket)>(&self, local: Option<Ipv4Addr>, remote: Addr, options: SocketOptions, setup: F) -> io::Result<Quad> {
+        self.check_draining()?;
+        let mut states = self.lock_all();
+        let options = states[0].fit(options);
+        let ip = local.unwrap_or_else(|| states[0].addr());
+        if !states[0].addrs.contains(&ip) {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "not an address of the stack"));
+        }
+        let (first, last) = (*states[0].ephemeral_ports.start(), *states[0].ephemeral_ports.end());
+        let count = (last - first) as usize + 1;
+        for _ in 0..count {
+            let port = states[0].next_port;
+            states[0].next_port = if port >= last { first } else { port + 1 };
+            if states.iter().any(|state| state.table.port_in_use(port)) {
+                continue;
+            }
+            let local = Addr::new(ip, port);
+            let shard = self.shard_of(&Quad::new(local, remote));
+            let quad = states[shard].connect(local, remote, options);
+            if let Some(sock) = states[shard].table.get_mut(&quad) {
+                setup(sock);
+            }
+            return Ok(quad);
+        }
+        Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "no free ephemeral port"))
+    }
+
+    /// put the connection of `checkpoint` in its shard, its local address has to
+    /// belong to the stack and no connection may use the quad already
+    #[cfg(feature = "serde")]
+    pub(crate) fn restore(&self, checkpoint: Checkpoint, options: SocketOptions) -> io::Result<Quad> {
+        self.check_draining()?;
+        let mut states = self.lock_all();
+        let quad = checkpoint.quad();
+        let local = quad.src();
+        if !states[0].transparent && !local.ipv4().is_some_and(|ip| states[0].addrs.contains(&ip)) {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "not an address of the stack"));
+        }
+        if states.iter().any(|state| state.table.get(&quad).is_some()) {
+            return Err(result::Error::AddressInUse(local.into()).into());
+        }
+        let shard = self.shard_of(&quad);
+        Ok(states[shard].restore(checkpoint, options))
+    }
+
+    /// the readiness fd, created on demand and signaled by every shard
+    fn events(&self) -> io::Result<Arc<EventFd>> {
+        let mut states = self.lock_all();
+        if let Some(events) = &states[0].events {
+            return Ok(events.clone());
+        }
+        let events = Arc::new(EventFd::new()?);
+        for state in &mut states {
+            state.events = Some(events.clone());
+        }
+        Ok(events)
+    }
+
+    /// apply `event` to the addresses and the connections of every shard,
+    /// the drivers send the resets and the segments from the new address
+    fn on_addr_event(&self, event: AddrEvent) -> io::Result<()> {
+        let mut states = self.lock_all();
+        let mut addrs = states[0].addrs.clone();
+        let (old, new) = match event {
+            AddrEvent::Added(addr) => (None, Some(addr)),
+            AddrEvent::Removed(addr) => (Some(addr), None),
+            AddrEvent::Replaced { old, new } => (Some(old), Some(new)),
+        };
+        if let Some(old) = old {
+            if !addrs.contains(&old) {
+                return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "not an address of the stack"));
+            }
+        }
+        if old == new {
+            return Ok(());
+        }
+        match (old, new) {
+            // the replacement takes the place of the old address, the source of active opens too
+            (Some(old), Some(new)) if !addrs.contains(&new) => {
+                for addr in addrs.iter_mut().filter(|addr| **addr == old) {
+                    *addr = new;
+                }
+            }
+            (old, new) => {
+                addrs.retain(|addr| Some(*addr) != old);
+                if let Some(new) = new.filter(|new| !addrs.contains(new)) {
+                    addrs.push(new);
+                }
+            }
+        }
+        if addrs.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the stack needs an address"));
+        }
+        for state in &mut states {
+            state.addrs = addrs.clone();
+        }
+        if let Some(arp) = self.arp.get() {
+            arp.set_locals(&addrs);
+        }
+        if let Some(old) = old {
+            debug!(?event, "address change");
+            for shard in 0..states.len() {
+                let (before, rest) = states.split_at_mut(shard);
+                let (state, after) = rest.split_first_mut().expect("shard in range");
+                let taken = |quad: &Quad| before.iter().chain(after.iter()).any(|other| other.table.get(quad).is_some());
+                state.on_addr_lost(old, new, &taken);
+            }
+            for state in &states {
+                state.signal_readiness();
+            }
+            drop(states);
+            self.notify_all();
+        }
+        Ok(())
+    }
+
+    /// process `frame` in the shard of its connection, whichever queue it arrived on
+    fn on_frame<L: DataLayer + ?Sized>(
+        &self,
+        device: &mut L,
+        timers: &mut TimerWheel<StackTimer>,
+        frame: &[u8],
+    ) -> result::Result<()> {
+        let shard = match self.shards.len() {
+            1 => 0,
+            _ => frame_quad(&frame[device.header_len().min(frame.len())..])
+                .map_or(0, |quad| self.shard_of(&home(&self.migrations, quad))),
+        };
+        self.lock_shard(shard).on_frame(device, timers, frame)
+    }
+
+    fn on_timer<L: DataLayer + ?Sized>(
+        &self,
+        device: &mut L,
+        timers: &mut TimerWheel<StackTimer>,
+        timer: StackTimer,
+    ) -> result::Result<()> {
+        let shard = match timer {
+            StackTimer::TimeWait(quad) | StackTimer::Retransmit(quad) => self.shard_of(&quad),
+        };
+        self.lock_shard(shard).on_timer(device, timers, timer)
+    }
+}
+
+fn write_migrations(migrations: &Migrations) -> std::sync::RwLockWriteGuard<'_, HashMap<Quad, Quad>> {
+    migrations.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// the table key of the connection `quad`, the one it had before migrating
+fn home(migrations: &Migrations, quad: Quad) -> Quad {
+    let migrations = migrations.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    migrations.get(&quad).copied().unwrap_or(quad)
+}
+
+/// (local, remote) quad of a received ipv4 tcp packet, read without validating it
+fn frame_quad(packet: &[u8]) -> Option<Quad> {
+    let ihl = (*packet.first()? & 0xf) as usize * 4;
+    if packet[0] >> 4 != 4 || packet.get(9) != Some(&(IpNumber::TCP.0)) {
+        return None;
+    }
+    let ip = |at: usize| packet.get(at..at + 4).map(|b| Ipv4Addr::new(b[0], b[1], b[2], b[3]));
+    let port = |at: usize| packet.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    let remote = Addr::new(ip(12)?, port(ihl)?);
+    let local = Addr::new(ip(16)?, port(ihl + 2)?);
+    Some(Quad::new(local, remote))
+}
+
+/// Drives shards of the stack from the thread of an event loop
+struct StackHandler {
+    shared: Arc<Shared>,
+    driver: usize,
+}
+
+impl<L: DataLayer> Handler<L, StackTimer> for StackHandler {
+    fn on_frame(&mut self, cx: &mut Context<L, StackTimer>, frame: &[u8]) -> result::Result<()> {
+        if let Err(e) = self.shared.on_frame(cx.device, cx.timers, frame) {
+            warn!(error = ?e, "drop frame");
+        }
+        Ok(())
+    }
+
+    fn on_timer(&mut self, cx: &mut Context<L, StackTimer>, _id: TimerId, timer: StackTimer) -> result::Result<()> {
+        self.shared.on_timer(cx.device, cx.timers, timer)
+    }
+
+    fn on_wakeup(&mut self, cx: &mut Context<L, StackTimer>) -> result::Result<()> {
+        self.shared.flush(self.driver, cx.device, cx.timers)
+    }
+
+    fn should_stop(&self) -> bool {
+        self.shared.stopped()
+    }
+}
+
+/// A TCP/IP stack owning a device and processing its packets in the background,
+/// sockets are created with `TcpListener::bind` and `TcpStream::connect`
+pub struct NetStack {
+    shared: Arc<Shared>,
+    drivers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl NetStack {
+    /// open the interface of `config` and process its packets on a dedicated thread,
+    /// or one thread per queue with `StackConfigBuilder::queues`. with
+    /// `StackConfigBuilder::reopen` the interface is opened again when it goes away
+    #[cfg(any(target_os = "linux", all(target_os = "macos", feature = "utun")))]
+    pub fn new(config: StackConfig) -> result::Result<Self> {
+        let reopen = |open: fn(&StackConfig) -> result::Result<Tun>| {
+            let config = config.clone();
+            config.reopen().map(|_| Box::new(move || open(&config).map_err(io::Error::from)) as Reopen<Tun>)
+        };
+        match config.mode() {
+            DeviceMode::Tun if config.queues() > 1 => Self::multi_queue(open_queues(&config)?, config),
+            DeviceMode::Tun => Self::start(vec![open_tun(&config)?], reopen(open_tun), config),
+            DeviceMode::Tap => Self::ethernet(open_tap(&config)?, reopen(open_tap), config),
+        }
+    }
+
+    /// process the packets of `device` on a dedicated thread, with the mtu of the device unless
+    /// the config has a lower one
+    pub fn with_device<L: DataLayer + Send + 'static>(device: L, config: StackConfig) -> result::Result<Self> {
+        Self::multi_queue(vec![device], config)
+    }
+
+    /// like `with_device` for a link of ethernet frames, e.g. a TAP device: the packets are
+    /// framed with the `mac` and `vlan` of `config` and sent to the neighbours, or the `gateway`,
+    /// ARP resolves. the addresses of the stack are announced with a gratuitous ARP, see `arp`
+    pub fn with_ethernet<L: DataLayer + Send + 'static>(device: L, config: StackConfig) -> result::Result<Self> {
+        Self::ethernet(device, None, config)
+    }
+
+    /// `with_ethernet`, a reopened device gets the framing and ARP cache of the old one
+    fn ethernet<L: DataLayer + Send + 'static>(device: L, reopen: Option<Reopen<L>>, config: StackConfig) -> result::Result<Self> {
+        let mac = match config.mac() {
+            Some(mac) => mac,
+            None => MacAddr::random()?,
+        };
+        let arp = ArpCache::new(config.addrs());
+        arp.set_timeout(config.arp_timeout());
+        let frame = {
+            let (arp, gateway, vlan) = (arp.clone(), config.gateway(), config.vlan());
+            move |device: L| {
+                let mut device = Ethernet::new(device, mac, arp.clone());
+                if let Some((gateway, prefix_len)) = gateway {
+                    device = device.with_gateway(gateway, prefix_len);
+                }
+                if let Some((id, priority)) = vlan {
+                    device = device.with_vlan(id, priority);
+                }
+                device
+            }
+        };
+        let reopen = reopen.map(|mut reopen| {
+            let frame = frame.clone();
+            Box::new(move || reopen().map(&frame)) as Reopen<Ethernet<L>>
+        });
+        let stack = Self::start(vec![frame(device)], reopen, config)?;
+        let shared = Arc::downgrade(&stack.shared);
+        arp.set_kick(Arc::new(move || {
+            if let Some(shared) = shared.upgrade() {
+                shared.notify_all();
+            }
+        }));
+        let _ = stack.shared.arp.set(arp);
+        Ok(stack)
+    }
+
+    /// process the packets of every queue of a device on its own thread, e.g. the queues
+    /// of `TunQueue::open`. connections are sharded by `rss_hash` so each worker only
+    /// locks its own part of the table once the kernel steers the flow to its queue
+    pub fn multi_queue<L: DataLayer + Send + 'static>(devices: Vec<L>, config: StackConfig) -> result::Result<Self> {
+        Self::start(devices, None, config)
+    }
+
+    /// one driver per device, the one of a single device replaces it with `reopen`
+    /// every `StackConfig::reopen` after it went away
+    fn start<L: DataLayer + Send + 'static>(
+        devices: Vec<L>,
+        reopen: Option<Reopen<L>>,
+        config: StackConfig,
+    ) -> result::Result<Self> {
+        let mtu = devices.iter().map(|device| device.mtu()).min()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no device queue"))?;
+        let config = config.clamp_mtu(mtu);
+        let mut event_loops = Vec::with_capacity(devices.len());
+        for device in devices {
+            let mut event_loop = EventLoop::with_clock(device, config.mtu(), config.timer_resolution(), config.clock().clone())?;
+            event_loop.set_gro(config.gro());
+            event_loops.push(event_loop);
+        }
+        if let (Some(reopen), Some(interval), [event_loop]) = (reopen, config.reopen(), &mut event_loops[..]) {
+            event_loop.set_reopen(reopen, interval);
+        }
+        let notifiers = event_loops.iter()
+            .map(|event_loop| {
+                let waker = event_loop.waker();
+                Box::new(move || {
+                    let _ = waker.wake();
+                }) as Box<dyn Fn() + Send + Sync>
+            })
+            .collect();
+        let shared = Arc::new(Shared::new(&config, notifiers));
+        let mut stack = Self {
+            shared,
+            drivers: Mutex::new(Vec::with_capacity(event_loops.len())),
+        };
+        let queues = event_loops.len();
+        for (driver, mut event_loop) in event_loops.into_iter().enumerate() {
+            let mut handler = StackHandler { shared: stack.shared.clone(), driver };
+            let name = match queues {
+                1 => "tcp-stack".to_string(),
+                _ => format!("tcp-stack-{}", driver),
+            };
+            // dropping the stack on error stops the workers already running
+            let driver = thread::Builder::new()
+                .name(name)
+                .spawn(move || {
+                    if let Err(e) = event_loop.run(&mut handler) {
+                        error!(error = ?e, driver, "stack stopped");
+                        if matches!(e, result::Error::DeviceGone) {
+                            handler.shared.on_device_gone();
+                        }
+                    }
+                })?;
+            stack.drivers.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).push(driver);
+        }
+        Ok(stack)
+    }
+
+    /// no background processing, the caller runs the returned driver from its own event loop
+    pub fn manual<L: DataLayer>(mut device: L, config: StackConfig) -> result::Result<(Self, StackDriver<L>)> {
+        let config = config.clamp_mtu(device.mtu());
+        device.set_nonblocking(true)?;
+        let events = Arc::new(EventFd::new()?);
+        let kick = events.clone();
+        let shared = Arc::new(Shared::new(&config, vec![Box::new(move || {
+            if let Err(e) = kick.signal() {
+                warn!(error = ?e, "signal readiness");
+            }
+        })]));
+        for mut state in shared.lock_all() {
+            state.events = Some(events.clone());
+        }
+        let driver = StackDriver {
+            buf: vec![0; recv_buffer_len(&device, config.mtu())],
+            device,
+            timers: timer_wheel(&config),
+            shared: shared.clone(),
+        };
+        Ok((Self { shared, drivers: Mutex::new(Vec::new()) }, driver))
+    }
+
+    /// stop taking connections and close the ones there are: listeners go away, new
+    /// connections and listeners are refused and every connection sends its FIN after the
+    /// data queued. once the peers closed theirs too, or `graceful` passed, the connections
+    /// left are reset and the drivers stop. returns how many were reset.
+    ///
+    /// streams the application still holds see the connections closed or reset. a stack
+    /// from `NetStack::manual` needs its driver running meanwhile
+    pub fn shutdown(&self, graceful: Duration) -> usize {
+        self.shared.draining.store(true, Ordering::Release);
+        for shard in 0..self.shared.shards() {
+            self.shared.lock_shard(shard).drain();
+        }
+        self.shared.notify_all();
+        let open = self.shared.wait_closed(Instant::now() + graceful);
+        let mut reset = 0;
+        if open > 0 {
+            for shard in 0..self.shared.shards() {
+                reset += self.shared.lock_shard(shard).abort_open();
+            }
+            self.shared.notify_all();
+            // the drivers send the resets before they stop
+            self.shared.wait_closed(Instant::now() + SHUTDOWN_RESET_TIMEOUT);
+        }
+        info!(reset, "stack shut down");
+        self.stop();
+        reset
+    }
+
+    /// stop the drivers and wait for them
+    fn stop(&self) {
+        self.shared.stop.store(true, Ordering::Release);
+        self.shared.notify_all();
+        let drivers: Vec<JoinHandle<()>> = self.drivers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).drain(..).collect();
+        for driver in drivers {
+            let _ = driver.join();
+        }
+    }
+
+    /// source address of active opens
+    pub fn local_addr(&self) -> Ipv4Addr {
+        self.shared.lock_shard(0).addr()
+    }
+
+    pub fn addrs(&self) -> Vec<Ipv4Addr> {
+        self.shared.lock_shard(0).addrs.clone()
+    }
+
+    /// the configured mtu, or the one of the device when none was
+    pub fn mtu(&self) -> usize {
+        self.shared.lock_shard(0).mtu
+    }
+
+    /// accept connections to `addr` too
+    pub fn add_addr(&self, addr: Ipv4Addr) -> io::Result<()> {
+        self.shared.on_addr_event(AddrEvent::Added(addr))
+    }
+
+    /// stop using `addr`, its connections are reset with `AddrNotAvailable`,
+    /// listeners bound to it get no connections until it's added again
+    pub fn remove_addr(&self, addr: Ipv4Addr) -> io::Result<()> {
+        self.shared.on_addr_event(AddrEvent::Removed(addr))
+    }
+
+    /// the interface got `new` instead of `old`, see `StackConfigBuilder::address_change`
+    /// for what happens to the connections of `old`
+    pub fn replace_addr(&self, old: Ipv4Addr, new: Ipv4Addr) -> io::Result<()> {
+        self.shared.on_addr_event(AddrEvent::Replaced { old, new })
+    }
+
+    /// options of sockets created without explicit ones, from `StackConfig::connection`
+    pub fn default_options(&self) -> SocketOptions {
+        self.shared.lock_shard(0).options
+    }
+
+    /// readable whenever a connection or listener became ready,
+    /// and for manual stacks whenever the driver has work to do
+    pub fn readiness_fd(&self) -> io::Result<RawFd> {
+        Ok(self.shared.events()?.as_raw_fd())
+    }
+
+    /// consume the signals of `readiness_fd`, readiness is then checked with `poll_readiness`
+    pub fn clear_readiness(&self) -> io::Result<()> {
+        match &self.shared.lock_shard(0).events {
+            Some(events) => events.clear(),
+            None => Ok(()),
+        }
+    }
+
+    /// readiness of the connection `quad` (local, remote), `Interest::HUP` if it doesn't exist
+    pub fn poll_readiness(&self, quad: Quad) -> Interest {
+        self.shared.lock(&quad).table.get(&quad)
+            .map(|sock| sock.readiness())
+            .unwrap_or(Interest::HUP)
+    }
+
+    /// `Interest::READABLE` when an established connection waits for `accept` on `local`
+    pub fn listener_readiness(&self, local: SocketAddrV4) -> Interest {
+        self.shared.listener_readiness(&Addr::from(local))
+    }
+
+    /// every connection and listener, like `netstat`, see `netstat::format_table`
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let states = self.shared.lock_all();
+        let now = states[0].clock.now();
+        let unspecified = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+        // every shard has the listeners, each with its own backlog
+        let listeners = states[0].table.listeners().map(|(local, _)| ConnectionInfo {
+            local: (*local).into(),
+            remote: unspecified,
+            state: TcpState::Listen,
+            recv_queue: states.iter()
+                .filter_map(|state| state.table.listener(local))
+                .map(|listener| listener.backlog.len())
+                .sum(),
+            send_queue: 0,
+            timer: None,
+        });
+        // sorted by quad, the order of the shards and their tables changes from run to run
+        let mut socks: Vec<&Socket> = states.iter().flat_map(|state| state.table.iter()).map(|(_, sock)| sock).collect();
+        socks.sort_by_key(|sock| sock.conn.quad());
+        let connections = socks.into_iter().map(|sock| ConnectionInfo {
+            local: sock.conn.quad().src().into(),
+            remote: sock.conn.quad().dest().into(),
+            state: sock.conn.state(),
+            recv_queue: sock.conn.bytes_available(),
+            send_queue: sock.conn.send_queue_len(),
+            timer: sock.time_wait.map(|(_, deadline)| (TimerKind::TimeWait, deadline))
+                .or(sock.retransmit.map(|(_, deadline)| match sock.conn.keep_alive_deadline() {
+                    Some(probe) if probe == deadline => (TimerKind::KeepAlive, deadline),
+                    _ if sock.conn.persist_deadline() == Some(deadline) => (TimerKind::WindowProbe, deadline),
+                    _ => (TimerKind::Retransmit, deadline),
+                }))
+                .map(|(kind, deadline)| (kind, deadline.saturating_duration_since(now))),
+        });
+        listeners.chain(connections).collect()
+    }
+
+    /// checkpoints of the synchronized connections sorted by quad, see `TcpStream::restore`
+    #[cfg(feature = "serde")]
+    pub fn checkpoint(&self) -> Vec<Checkpoint> {
+        let states = self.shared.lock_all();
+        let mut checkpoints: Vec<Checkpoint> = states.iter()
+            .flat_map(|state| state.table.iter())
+            .filter_map(|(_, sock)| sock.conn.checkpoint())
+            .collect();
+        checkpoints.sort_by_key(|checkpoint| checkpoint.quad());
+        checkpoints
+    }
+
+    /// call `callback` with every frame sent or received which matches `filter`,
+    /// it runs on the packet processing thread while the stack is locked
+    pub fn capture<F>(&self, filter: Filter, callback: F) -> CaptureId
+        where F: FnMut(&CapturedFrame) + Send + 'static {
+        self.shared.lock_shard(0).captures.add(filter, Sink::Callback(Box::new(callback)))
+    }
+
+    /// keep the last `capacity` frames matching `filter` in the returned ring
+    pub fn capture_ring(&self, filter: Filter, capacity: usize) -> (CaptureId, CaptureRing) {
+        let ring = CaptureRing::new(capacity);
+        let id = self.shared.lock_shard(0).captures.add(filter, Sink::Ring(ring.clone()));
+        (id, ring)
+    }
+
+    /// return false if the capture was already removed
+    pub fn remove_capture(&self, id: CaptureId) -> bool {
+        self.shared.lock_shard(0).captures.remove(id)
+    }
+
+    /// push the events of every connection to `observer`, existing ones included
+    pub fn add_observer(&self, observer: Arc<dyn ConnectionObserver>) -> ObserverId {
+        self.shared.lock_shard(0).observers.add(observer)
+    }
+
+    /// return false if the observer was already removed
+    pub fn remove_observer(&self, id: ObserverId) -> bool {
+        self.shared.lock_shard(0).observers.remove(id)
+    }
+
+    /// change the configuration of the running stack. the new settings apply to the
+    /// connections opened from now on and, where they can change midway, the rate limits,
+    /// to the open ones too. it only takes the locks of the stack for a moment, a thread
+    /// waiting for signals may call it. returns the ids of the firewall rules of `changes`
+    pub fn reconfigure(&self, mut changes: Reconfigure) -> Vec<RuleId> {
+        if let Some(level) = changes.log_level {
+            log::set_max_level(level);
+        }
+        let mut rules = Vec::new();
+        let mut states = self.shared.lock_all();
+        if let Some((chain, policy)) = changes.firewall.take() {
+            rules = states[0].firewall.replace(chain, policy);
+        }
+        let shaper = changes.egress_limit.map(|limit| limit.map(Shaper::new));
+        let icmp_errors = changes.icmp_errors.map(|limit| limit.map(Shaper::new));
+        for state in &mut states {
+            state.reconfigure(&changes, &shaper, &icmp_errors);
+        }
+        drop(states);
+        info!(log_level = ?changes.log_level, congestion = ?changes.congestion, rate_limit = ?changes.rate_limit,
+              egress_limit = ?changes.egress_limit, rules = rules.len(), "reconfigured");
+        self.shared.notify_all();
+        rules
+    }
+
+    /// sign the connections with the peers of `mkt` with TCP-AO from now on. connections
+    /// with keys of the peer already get it too, e.g. to rotate to it, the others stay unsigned
+    #[cfg(feature = "tcp-ao")]
+    pub fn add_auth_key(&self, mkt: Mkt) -> io::Result<()> {
+        let mut states = self.shared.lock_all();
+        states[0].keys.add(mkt.clone())?;
+        for state in states.iter_mut() {
+            for quad in state.table.quads() {
+                let auth = state.table.get_mut(&quad).and_then(|sock| sock.conn.authenticator_mut());
+                if let Some(auth) = auth.filter(|_| mkt.matches(quad.dest().ip())) {
+                    auth.add_key(mkt.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// forget the key of `peer/prefix_len` with `send_id`, it fails with `ResourceBusy` while
+    /// a connection signs with it or asks for it. return false if there is no such key
+    #[cfg(feature = "tcp-ao")]
+    pub fn remove_auth_key(&self, peer: IpAddr, prefix_len: u8, send_id: u8) -> io::Result<bool> {
+        let mut states = self.shared.lock_all();
+        let owns = |mkt: &Mkt| mkt.peer() == (peer, prefix_len) && mkt.send_id() == send_id;
+        let in_use = states.iter()
+            .flat_map(|state| state.table.iter())
+            .filter_map(|(_, sock)| sock.conn.authenticator())
+            .any(|auth| auth.keys().iter().any(|mkt| owns(mkt) && auth.in_use(mkt)));
+        if in_use {
+            return Err(result::Error::KeyInUse(send_id).into());
+        }
+        if states[0].keys.remove((peer, prefix_len), send_id).is_none() {
+            return Ok(false);
+        }
+        for state in states.iter_mut() {
+            for quad in state.table.quads() {
+                if let Some(auth) = state.table.get_mut(&quad).and_then(|sock| sock.conn.authenticator_mut()) {
+                    auth.remove_key(owns);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// the TCP-AO keys new connections take theirs from
+    #[cfg(feature = "tcp-ao")]
+    pub fn auth_keys(&self) -> Vec<Mkt> {
+        self.shared.lock_shard(0).keys.keys()
+    }
+
+    /// append `rule` to the chain deciding on the received segments before
+    /// they reach their connection or listener
+    pub fn add_rule(&self, rule: Rule) -> RuleId {
+        self.shared.lock_shard(0).firewall.add(rule)
+    }
+
+    /// return false if the rule was already removed
+    pub fn remove_rule(&self, id: RuleId) -> bool {
+        self.shared.lock_shard(0).firewall.remove(id)
+    }
+
+    /// verdict on the segments no rule decides on, `Accept` by default
+    pub fn set_firewall_policy(&self, policy: Verdict) {
+        self.shared.lock_shard(0).firewall.set_policy(policy);
+    }
+
+    pub fn firewall_policy(&self) -> Verdict {
+        self.shared.lock_shard(0).firewall.policy()
+    }
+
+    /// the ARP cache of a stack from `with_ethernet`, to look at the neighbours, seed
+    /// static entries and announce addresses
+    pub fn arp(&self) -> Option<&ArpCache> {
+        self.shared.arp.get()
+    }
+
+    /// stack wide counters, like `/proc/net/snmp`
+    pub fn snmp(&self) -> Snmp {
+        let states = self.shared.lock_all();
+        let mut snmp = states[0].metrics.snapshot();
+        snmp.tcp.curr_estab = states.iter()
+            .flat_map(|state| state.table.iter())
+            .filter(|(_, sock)| matches!(sock.state, TcpState::Established | TcpState::CloseWait))
+            .count() as u64;
+        snmp
+    }
+
+    /// hand the counters of the stack, the number of connections per state and the
+    /// counters of every connection to the recorder of the `metrics` facade, call it
+    /// periodically or before every scrape
+    ///
+    /// the series of a forgotten connection keep their last values until the recorder
+    /// drops them, e.g. the idle timeout of the prometheus exporter
+    #[cfg(feature = "metrics")]
+    pub fn publish_metrics(&self) {
+        const STATES: [TcpState; 10] = [
+            TcpState::Listen, TcpState::SynSent, TcpState::SynReceived, TcpState::Established,
+            TcpState::FinWait1, TcpState::FinWait2, TcpState::CloseWait, TcpState::Closing,
+            TcpState::LastAck, TcpState::TimeWait,
+        ];
+        self.snmp().publish();
+        let connections = self.connections();
+        for state in STATES {
+            let count = connections.iter().filter(|info| info.state == state).count();
+            ::metrics::gauge!("tcp_stack_connections", "state" => state.to_string()).set(count as f64);
+        }
+        let stats: Vec<_> = self.shared.lock_all().iter()
+            .flat_map(|state| state.table.iter())
+            .map(|(_, sock)| (sock.conn.quad(), sock.conn.stats()))
+            .collect();
+        for (quad, stats) in stats {
+            stats.publish(&quad);
+        }
+    }
+
+    pub(crate) fn shared(&self) -> &Arc<Shared> {
+        &self.shared
+    }
+
+    /// captures, observers and clock of the stack, for hooks that outlive a lock
+    #[cfg(feature = "diagram")]
+    pub(crate) fn hooks(&self) -> (Arc<Captures>, Arc<Observers>, Arc<dyn Clock>) {
+        let state = self.shared.lock_shard(0);
+        (state.captures.clone(), state.observers.clone(), state.clock.clone())
+    }
+}
+
+impl Drop for NetStack {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_tun(config: &StackConfig) -> result::Result<Tun> {
+    let mut tun = if config.packet_info() {
+        Tun::with_packet_info(config.interface())?
+    } else {
+        Tun::open(config.interface())?
+    };
+    if config.host_addr().is_some() {
+        tun.set_mtu(config.mtu())?;
+        configure_interface(tun.name(), config)?;
+    }
+    Ok(tun)
+}
+
+#[cfg(target_os = "linux")]
+fn open_tap(config: &StackConfig) -> result::Result<Tun> {
+    let mut tap = Tun::tap(config.interface())?;
+    if config.host_addr().is_some() {
+        tap.set_mtu(config.mtu())?;
+        configure_interface(tap.name(), config)?;
+    }
+    Ok(tap)
+}
+
+#[cfg(target_os = "linux")]
+fn open_queues(config: &StackConfig) -> result::Result<Vec<TunQueue>> {
+    let mut queues = TunQueue::open(config.interface(), config.queues())?;
+    if config.host_addr().is_some() {
+        for queue in &mut queues {
+            queue.set_mtu(config.mtu())?;
+        }
+        configure_interface(queues[0].name(), config)?;
+    }
+    Ok(queues)
+}
+
+/// what `ip addr add` and `ip link set up` would do for the kernel side of the interface
+#[cfg(target_os = "linux")]
+fn configure_interface(name: &str, config: &StackConfig) -> io::Result<()> {
+    if let Some((addr, prefix_len)) = config.host_addr() {
+        iface::set_addr(name, addr, prefix_len)?;
+        iface::set_up(name, true)?;
+        debug!(interface = name, %addr, prefix_len, mtu = config.mtu(), "interface configured");
+    }
+    Ok(())
+}
+
+#[cfg(all(target_os = "macos", feature = "utun"))]
+fn open_tun(config: &StackConfig) -> result::Result<Tun> {
+    if config.host_addr().is_some() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "interface configuration needs Linux").into());
+    }
+    Ok(Tun::open(config.interface())?)
+}
+
+#[cfg(all(target_os = "macos", feature = "utun"))]
+fn open_tap(_config: &StackConfig) -> result::Result<Tun> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "tap interfaces need Linux").into())
+}
+
+#[cfg(all(target_os = "macos", feature = "utun"))]
+fn open_queues(_config: &StackConfig) -> result::Result<Vec<Tun>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "multi-queue interfaces need Linux").into())
+}
+
+fn timer_wheel(config: &StackConfig) -> TimerWheel<StackTimer> {
+    TimerWheel::with_clock(config.clock().clone(), config.timer_resolution(), DEFAULT_WHEEL_SLOTS)
+}
+
+/// Processes the packets of a stack created by `NetStack::manual`,
+/// wait for `device_fd` and `NetStack::readiness_fd` at most `next_timeout`
+/// and then call `process`
+pub struct StackDriver<L: DataLayer> {
+    device: L,
+    timers: TimerWheel<StackTimer>,
+    shared: Arc<Shared>,
+    buf: Vec<u8>,
+}
+
+impl<L: DataLayer> StackDriver<L> {
+    /// `None` if the device must be polled by calling `process` periodically
+    pub fn device_fd(&self) -> Option<RawFd> {
+        self.device.raw_fd()
+    }
+
+    /// time until the next timer, `None` if nothing is scheduled
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.timers.next_timeout(self.timers.now())
+    }
+
+    pub fn device_mut(&mut self) -> &mut L {
+        &mut self.device
+    }
+
+    /// receive every queued frame, send what the applications queued and run the expired timers
+    pub fn process(&mut self) -> result::Result<()> {
+        loop {
+            match self.device.recv(&mut self.buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if let Err(e) = self.shared.on_frame(&mut self.device, &mut self.timers, &self.buf[..n]) {
+                        warn!(error = ?e, "drop frame");
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if is_device_gone(&e) => {
+                    self.shared.on_device_gone();
+                    return Err(result::Error::DeviceGone);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.shared.flush(0, &mut self.device, &mut self.timers)?;
+        let now = self.timers.now();
+        for (_, timer) in self.timers.expire(now) {
+            self.shared.on_timer(&mut self.device, &mut self.timers, timer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl NetStack {
+    /// process the packets of `device` on a task of the current tokio runtime,
+    /// must be called from within the runtime
+    pub fn spawn<L: DataLayer + Send + 'static>(mut device: L, config: StackConfig) -> result::Result<Self> {
+        use tokio::io::unix::AsyncFd;
+
+        let config = config.clamp_mtu(device.mtu());
+        device.set_nonblocking(true)?;
+        let readiness = match device.raw_fd() {
+            // SAFETY: the fd stays open as long as the device, which lives in the driver task
+            // next to the `AsyncFd` and is dropped after it
+            Some(fd) => Some(unsafe {
+                AsyncFd::register_with_interest(DeviceFd(fd), tokio::io::Interest::READABLE).map_err(io::Error::from)?
+            }),
+            None => None,
+        };
+        let notify = Arc::new(tokio::sync::Notify::new());
+        let kick = notify.clone();
+        let shared = Arc::new(Shared::new(&config, vec![Box::new(move || kick.notify_one())]));
+        let driver = shared.clone();
+        let timers = timer_wheel(&config);
+        let mtu = config.mtu();
+        let capabilities = device.capabilities();
+        let gro = (config.gro() && !capabilities.contains(Capabilities::GRO))
+            .then(|| Coalescer::new(device.header_len(), capabilities.contains(Capabilities::RX_CHECKSUM)));
+        tokio::spawn(async move {
+            if let Err(e) = drive(device, readiness, driver.clone(), notify, timers, mtu, gro).await {
+                error!(error = ?e, "stack stopped");
+                if matches!(e, result::Error::DeviceGone) {
+                    driver.on_device_gone();
+                }
+            }
+        });
+        Ok(Self { shared, drivers: Mutex::new(Vec::new()) })
+    }
+}
+
+/// the device fd polled by tokio, the device keeps ownership of it
+#[cfg(feature = "tokio")]
+struct DeviceFd(std::os::unix::io::RawFd);
+
+#[cfg(feature = "tokio")]
+impl std::os::unix::io::AsRawFd for DeviceFd {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.0
+    }
+}
+
+/// how long the driver task sleeps when no timer is pending
+#[cfg(feature = "tokio")]
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "tokio")]
+async fn drive<L: DataLayer>(
+    mut device: L,
+    readiness: Option<tokio::io::unix::AsyncFd<DeviceFd>>,
+    shared: Arc<Shared>,
+    notify: Arc<tokio::sync::Notify>,
+    mut timers: TimerWheel<StackTimer>,
+    mtu: usize,
+    mut gro: Option<Coalescer>,
+) -> result::Result<()> {
+    use crate::event_loop::DEVICE_POLL_INTERVAL;
+
+    let mut buf = vec![0; recv_buffer_len(&device, mtu)];
+    while !shared.stopped() {
+        let mut wait = timers.next_timeout(timers.now()).unwrap_or(IDLE_TIMEOUT);
+        if readiness.is_none() {
+            wait = wait.min(DEVICE_POLL_INTERVAL);
+        }
+        let readable = async {
+            match &readiness {
+                // clear before draining, a frame arriving meanwhile sets it again
+                Some(fd) => fd.readable().await.map(|mut guard| guard.clear_ready()),
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            ready = readable => ready?,
+            _ = notify.notified() => shared.flush(0, &mut device, &mut timers)?,
+            _ = tokio::time::sleep(wait) => {}
+        }
+
+        loop {
+            match device.recv(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let frame = &buf[..n];
+                    if let Some(gro) = &mut gro {
+                        if gro.push(frame) {
+                            continue;
+                        }
+                        if let Some(held) = gro.take() {
+                            if let Err(e) = shared.on_frame(&mut device, &mut timers, &held) {
+                                warn!(error = ?e, "drop frame");
+                            }
+                        }
+                        if gro.push(frame) {
+                            continue;
+                        }
+                    }
+                    if let Err(e) = shared.on_frame(&mut device, &mut timers, frame) {
+                        warn!(error = ?e, "drop frame");
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if is_device_gone(&e) => return Err(result::Error::DeviceGone),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if let Some(held) = gro.as_mut().and_then(Coalescer::take) {
+            if let Err(e) = shared.on_frame(&mut device, &mut timers, &held) {
+                warn!(error = ?e, "drop frame");
+            }
+        }
+        let now = timers.now();
+        for (_, timer) in timers.expire(now) {
+            shared.on_timer(&mut device, &mut timers, timer)?;
+        }
+    }
+    Ok(())
+}
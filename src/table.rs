@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::socket_addr::{Addr, Quad};
+
+/// Connections and listeners of a stack
+///
+/// connections are stored densely so the timer processing walks a plain slice,
+/// an index keyed by the full quad gives O(1) lookup of incoming segments.
+/// listeners are keyed by their local address, `0.0.0.0` accepts on every address
+/// and `0.0.0.0:0` on every address and port not taken by another listener
+pub struct SocketTable<C, L> {
+    entries: Vec<(Quad, C)>,
+    /// quad (local, remote) -> position in `entries`
+    index: HashMap<Quad, usize>,
+    listeners: HashMap<Addr, L>,
+    /// local port -> number of connections and listeners using it
+    ports: HashMap<u16, usize>,
+}
+
+impl<C, L> Default for SocketTable<C, L> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            index: HashMap::new(),
+            listeners: HashMap::new(),
+            ports: HashMap::new(),
+        }
+    }
+}
+
+impl<C, L> SocketTable<C, L> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, quad: &Quad) -> bool {
+        self.index.contains_key(quad)
+    }
+
+    pub fn get(&self, quad: &Quad) -> Option<&C> {
+        self.index.get(quad).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn get_mut(&mut self, quad: &Quad) -> Option<&mut C> {
+        match self.index.get(quad) {
+            Some(&i) => Some(&mut self.entries[i].1),
+            None => None,
+        }
+    }
+
+    /// return the connection previously stored under `quad`
+    pub fn insert(&mut self, quad: Quad, conn: C) -> Option<C> {
+        if let Some(&i) = self.index.get(&quad) {
+            return Some(std::mem::replace(&mut self.entries[i].1, conn));
+        }
+        self.index.insert(quad, self.entries.len());
+        self.entries.push((quad, conn));
+        self.acquire_port(quad.src().port());
+        None
+    }
+
+    pub fn remove(&mut self, quad: &Quad) -> Option<C> {
+        let i = self.index.remove(quad)?;
+        let (_, conn) = self.entries.swap_remove(i);
+        if let Some((moved, _)) = self.entries.get(i) {
+            self.index.insert(*moved, i);
+        }
+        self.release_port(quad.src().port());
+        Some(conn)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Quad, &C)> {
+        self.entries.iter().map(|(quad, conn)| (quad, conn))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&Quad, &mut C)> {
+        self.entries.iter_mut().map(|(quad, conn)| (&*quad, conn))
+    }
+
+    /// snapshot of the keys, for loops which insert or remove connections
+    pub fn quads(&self) -> Vec<Quad> {
+        self.entries.iter().map(|(quad, _)| *quad).collect()
+    }
+
+    /// fails if the exact address is taken, a wildcard and a specific address
+    /// on the same port may coexist, the specific one wins the lookup
+    pub fn listen(&mut self, local: Addr, listener: L) -> Result<(), L> {
+        if self.listeners.contains_key(&local) {
+            return Err(listener);
+        }
+        self.listeners.insert(local, listener);
+        self.acquire_port(local.port());
+        Ok(())
+    }
+
+    pub fn unlisten(&mut self, local: &Addr) -> Option<L> {
+        let listener = self.listeners.remove(local)?;
+        self.release_port(local.port());
+        Some(listener)
+    }
+
+    /// address of the listener accepting connections to `local`
+    pub fn find_listener(&self, local: Addr) -> Option<Addr> {
+        if self.listeners.contains_key(&local) {
+            return Some(local);
+        }
+        let wildcard = Addr::new(Ipv4Addr::UNSPECIFIED, local.port());
+        if self.listeners.contains_key(&wildcard) {
+            return Some(wildcard);
+        }
+        let any = Addr::new(Ipv4Addr::UNSPECIFIED, 0);
+        if self.listeners.contains_key(&any) {
+            return Some(any);
+        }
+        None
+    }
+
+    pub fn listener(&self, local: &Addr) -> Option<&L> {
+        self.listeners.get(local)
+    }
+
+    pub fn listener_mut(&mut self, local: &Addr) -> Option<&mut L> {
+        self.listeners.get_mut(local)
+    }
+
+    pub fn listeners(&self) -> impl Iterator<Item = (&Addr, &L)> {
+        self.listeners.iter()
+    }
+
+    pub fn listeners_mut(&mut self) -> impl Iterator<Item = (&Addr, &mut L)> {
+        self.listeners.iter_mut()
+    }
+
+    /// local addresses listening on `port`
+    pub fn listeners_on_port(&self, port: u16) -> impl Iterator<Item = &Addr> {
+        self.listeners.keys().filter(move |addr| addr.port() == port)
+    }
+
+    /// a connection, not a listener, uses the local `port`
+    pub fn has_connection_on_port(&self, port: u16) -> bool {
+        self.port_in_use(port) && self.entries.iter().any(|(quad, _)| quad.src().port() == port)
+    }
+
+    /// a listener or a connection uses the local `port`
+    pub fn port_in_use(&self, port: u16) -> bool {
+        self.ports.contains_key(&port)
+    }
+
+    fn acquire_port(&mut self, port: u16) {
+        *self.ports.entry(port).or_insert(0) += 1;
+    }
+
+    fn release_port(&mut self, port: u16) {
+        if let Some(count) = self.ports.get_mut(&port) {
+            *count -= 1;
+            if *count == 0 {
+                self.ports.remove(&port);
+            }
+        }
+    }
+}
+
+/// secret key of the Toeplitz hash, the default of most NIC drivers
+const RSS_KEY: [u8; 40] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
+/// Toeplitz hash of the (local, remote) `quad` the way a NIC doing receive side scaling
+/// hashes the packets of the connection: remote address, local address, remote port, local port
+pub fn rss_hash(quad: &Quad) -> u32 {
+    let (local, remote) = (quad.src(), quad.dest());
+    let mut input = Vec::with_capacity(36);
+    match (remote.ip(), local.ip()) {
+        (IpAddr::V4(remote), IpAddr::V4(local)) => {
+            input.extend_from_slice(&remote.octets());
+            input.extend_from_slice(&local.octets());
+        }
+        (remote, local) => {
+            input.extend_from_slice(&ipv6_octets(remote));
+            input.extend_from_slice(&ipv6_octets(local));
+        }
+    }
+    input.extend_from_slice(&remote.port().to_be_bytes());
+    input.extend_from_slice(&local.port().to_be_bytes());
+
+    let mut hash = 0;
+    let mut window = u32::from_be_bytes([RSS_KEY[0], RSS_KEY[1], RSS_KEY[2], RSS_KEY[3]]);
+    for (i, byte) in input.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                hash ^= window;
+            }
+            window = window << 1 | (RSS_KEY[i + 4] >> (7 - bit) & 1) as u32;
+        }
+    }
+    hash
+}
+
+fn ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
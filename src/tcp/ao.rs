@@ -0,0 +1,639 @@
+//! TCP Authentication Option (RFC 5925)
+//!
+//! a master key tuple (MKT) is a secret shared with the peers of an address prefix and the
+//! ids the two ends know it by. every segment of a connection with keys carries a MAC computed
+//! with a traffic key, derived from the master key and the ISNs of the connection (RFC 5926),
+//! over the sequence number extension, the pseudo header, the header and the data. segments
+//! without a valid MAC are dropped.
+//!
+//! the peers may hold several keys, the KeyID of a segment names the one it was signed with
+//! and its RNextKeyID the one its sender wants to receive with next. a connection switches its
+//! current key to the one the peer asks for, so a key is rotated by adding the new one at both
+//! ends, asking for it with `TcpStream::set_auth_rnext` and removing the old one once the
+//! peer signs with the new one
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use aes::Aes128;
+use cmac::Cmac;
+use etherparse::TcpHeaderSlice;
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::result::{Error, Result};
+use crate::socket_addr::Quad;
+use crate::tcp::options::{self, TcpOption, KIND_AUTHENTICATION, KIND_END_OF_LIST, KIND_NOP};
+use crate::tcp::packet::TcpIpHeader;
+use crate::tcp::vars::{seq_ge, seq_gt};
+
+/// MAC length of the algorithms of RFC 5926, 96 bits
+pub const DEFAULT_MAC_LEN: usize = 12;
+/// label of the key derivation (RFC 5926 3.1.1)
+const KDF_LABEL: &[u8] = b"TCP-AO";
+/// length of the fixed tcp header, where the options start
+const TCP_HEADER_LEN: usize = 20;
+
+/// The algorithm computing the MACs and deriving the traffic keys
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum MacAlgorithm {
+    /// HMAC-SHA-1-96 (RFC 5926), required by RFC 5925
+    HmacSha1,
+    /// AES-128-CMAC-96 (RFC 5926), required by RFC 5925
+    AesCmac128,
+    /// HMAC-SHA-256, not in RFC 5926 but offered by Linux as `hmac(sha256)`
+    HmacSha256,
+}
+
+impl MacAlgorithm {
+    /// bytes of the untruncated MAC and of the traffic keys
+    pub fn output_len(self) -> usize {
+        match self {
+            MacAlgorithm::HmacSha1 => 20,
+            MacAlgorithm::AesCmac128 => 16,
+            MacAlgorithm::HmacSha256 => 32,
+        }
+    }
+
+    /// the traffic key of `context`, a single iteration of the KDF gives all its bits (RFC 5926 3.1.1)
+    fn derive(self, master: &[u8], context: &[u8]) -> Vec<u8> {
+        let bits = (self.output_len() as u16 * 8).to_be_bytes();
+        let input: [&[u8]; 4] = [&[1], KDF_LABEL, context, &bits];
+        match self {
+            MacAlgorithm::HmacSha1 => compute::<Hmac<Sha1>>(master, &input),
+            MacAlgorithm::HmacSha256 => compute::<Hmac<Sha256>>(master, &input),
+            MacAlgorithm::AesCmac128 if master.len() == 16 => compute::<Cmac<Aes128>>(master, &input),
+            // other lengths are first turned into a 128 bit key (RFC 5926 3.1.1.2)
+            MacAlgorithm::AesCmac128 => {
+                let key = compute::<Cmac<Aes128>>(&[0; 16], &[master]);
+                compute::<Cmac<Aes128>>(&key, &input)
+            }
+        }
+    }
+
+    fn mac(self, key: &[u8], input: &[&[u8]]) -> Vec<u8> {
+        match self {
+            MacAlgorithm::HmacSha1 => compute::<Hmac<Sha1>>(key, input),
+            MacAlgorithm::AesCmac128 => compute::<Cmac<Aes128>>(key, input),
+            MacAlgorithm::HmacSha256 => compute::<Hmac<Sha256>>(key, input),
+        }
+    }
+
+    /// compare the leftmost bytes of the MAC in constant time
+    fn verify(self, key: &[u8], input: &[&[u8]], mac: &[u8]) -> bool {
+        match self {
+            MacAlgorithm::HmacSha1 => feed::<Hmac<Sha1>>(key, input).verify_truncated_left(mac).is_ok(),
+            MacAlgorithm::AesCmac128 => feed::<Cmac<Aes128>>(key, input).verify_truncated_left(mac).is_ok(),
+            MacAlgorithm::HmacSha256 => feed::<Hmac<Sha256>>(key, input).verify_truncated_left(mac).is_ok(),
+        }
+    }
+}
+
+impl fmt::Display for MacAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MacAlgorithm::HmacSha1 => "hmac-sha1",
+            MacAlgorithm::AesCmac128 => "aes-128-cmac",
+            MacAlgorithm::HmacSha256 => "hmac-sha256",
+        })
+    }
+}
+
+/// the names of `Display` and the ones of the Linux crypto API
+impl FromStr for MacAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "hmac-sha1" | "hmac(sha1)" => Ok(MacAlgorithm::HmacSha1),
+            "aes-128-cmac" | "cmac(aes128)" | "cmac(aes)" => Ok(MacAlgorithm::AesCmac128),
+            "hmac-sha256" | "hmac(sha256)" => Ok(MacAlgorithm::HmacSha256),
+            _ => Err(format!("unknown MAC algorithm {}", s)),
+        }
+    }
+}
+
+fn feed<M: Mac + KeyInit>(key: &[u8], input: &[&[u8]]) -> M {
+    // the traffic keys of CMAC have the length it needs, HMAC takes any
+    let mut mac = <M as KeyInit>::new_from_slice(key).expect("key length of the algorithm");
+    for part in input {
+        mac.update(part);
+    }
+    mac
+}
+
+fn compute<M: Mac + KeyInit>(key: &[u8], input: &[&[u8]]) -> Vec<u8> {
+    feed::<M>(key, input).finalize().into_bytes().to_vec()
+}
+
+/// A master key tuple, the key shared with the peers of a prefix
+#[derive(Clone)]
+pub struct Mkt {
+    peer: IpAddr,
+    prefix_len: u8,
+    send_id: u8,
+    recv_id: u8,
+    algorithm: MacAlgorithm,
+    key: Vec<u8>,
+    mac_len: usize,
+    include_options: bool,
+}
+
+impl Mkt {
+    /// the key of the connections with `peer`, the segments sent carry `send_id` and the
+    /// ones received `recv_id`, the peer has them the other way round
+    pub fn new(peer: IpAddr, send_id: u8, recv_id: u8, algorithm: MacAlgorithm, key: &[u8]) -> Self {
+        Self {
+            peer,
+            prefix_len: if peer.is_ipv4() { 32 } else { 128 },
+            send_id,
+            recv_id,
+            algorithm,
+            key: key.to_vec(),
+            mac_len: DEFAULT_MAC_LEN,
+            include_options: true,
+        }
+    }
+
+    /// the key of every peer in `peer/len`
+    pub fn prefix(mut self, len: u8) -> Self {
+        self.prefix_len = len;
+        self
+    }
+
+    /// bytes of the MAC sent, up to the output of the algorithm
+    pub fn mac_len(mut self, len: usize) -> Self {
+        self.mac_len = len.clamp(1, self.algorithm.output_len());
+        self
+    }
+
+    /// the MAC doesn't cover the options other than TCP-AO, for middleboxes changing them
+    pub fn exclude_options(mut self) -> Self {
+        self.include_options = false;
+        self
+    }
+
+    pub fn peer(&self) -> (IpAddr, u8) {
+        (self.peer, self.prefix_len)
+    }
+
+    pub fn send_id(&self) -> u8 {
+        self.send_id
+    }
+
+    pub fn recv_id(&self) -> u8 {
+        self.recv_id
+    }
+
+    pub fn algorithm(&self) -> MacAlgorithm {
+        self.algorithm
+    }
+
+    pub fn matches(&self, ip: IpAddr) -> bool {
+        let (peer, ip, bits) = match (self.peer, ip) {
+            (IpAddr::V4(peer), IpAddr::V4(ip)) => (u32::from(peer) as u128, u32::from(ip) as u128, 32),
+            (IpAddr::V6(peer), IpAddr::V6(ip)) => (u128::from(peer), u128::from(ip), 128),
+            _ => return false,
+        };
+        let len = (self.prefix_len as u32).min(bits);
+        len == 0 || (peer ^ ip) >> (bits - len) == 0
+    }
+
+    /// bytes of the option in a header
+    fn option_len(&self) -> usize {
+        4 + self.mac_len
+    }
+
+    /// the traffic key of segments from `src` to `dest` of `quad`
+    fn traffic_key(&self, quad: &Quad, src_isn: u32, dest_isn: u32) -> Vec<u8> {
+        let mut context = Vec::with_capacity(44);
+        for addr in [quad.src(), quad.dest()] {
+            match addr.ip() {
+                IpAddr::V4(ip) => context.extend_from_slice(&ip.octets()),
+                IpAddr::V6(ip) => context.extend_from_slice(&ip.octets()),
+            }
+        }
+        context.extend_from_slice(&quad.src().port().to_be_bytes());
+        context.extend_from_slice(&quad.dest().port().to_be_bytes());
+        context.extend_from_slice(&src_isn.to_be_bytes());
+        context.extend_from_slice(&dest_isn.to_be_bytes());
+        self.algorithm.derive(&self.key, &context)
+    }
+}
+
+/// the secret is left out
+impl fmt::Debug for Mkt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mkt")
+            .field("peer", &self.peer)
+            .field("prefix_len", &self.prefix_len)
+            .field("send_id", &self.send_id)
+            .field("recv_id", &self.recv_id)
+            .field("algorithm", &self.algorithm)
+            .field("mac_len", &self.mac_len)
+            .field("include_options", &self.include_options)
+            .finish()
+    }
+}
+
+/// `ALGORITHM:SENDID:RECVID:SECRET@PEER[/PREFIX]`, a secret starting with `0x` is hex,
+/// e.g. `hmac-sha1:1:2:secret@192.168.3.1`
+impl FromStr for Mkt {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (key, peer) = s.rsplit_once('@').ok_or_else(|| format!("no peer in {}", s))?;
+        let mut parts = key.splitn(4, ':');
+        let (algorithm, send_id, recv_id, secret) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(algorithm), Some(send_id), Some(recv_id), Some(secret)) => (algorithm, send_id, recv_id, secret),
+            _ => return Err(format!("expected ALGORITHM:SENDID:RECVID:SECRET in {}", key)),
+        };
+        let id = |id: &str| id.parse::<u8>().map_err(|e| format!("key id {}: {}", id, e));
+        let secret = match secret.strip_prefix("0x") {
+            Some(hex) if hex.len() % 2 == 0 => (0..hex.len()).step_by(2)
+                .map(|at| u8::from_str_radix(&hex[at..at + 2], 16))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| format!("secret {}: {}", secret, e))?,
+            Some(_) => return Err(format!("odd number of hex digits in {}", secret)),
+            None => secret.as_bytes().to_vec(),
+        };
+        let (ip, prefix_len) = match peer.split_once('/') {
+            Some((ip, len)) => (ip, Some(len.parse::<u8>().map_err(|e| format!("prefix {}: {}", len, e))?)),
+            None => (peer, None),
+        };
+        let ip = ip.parse::<IpAddr>().map_err(|e| format!("{}: {}", ip, e))?;
+        let mkt = Mkt::new(ip, id(send_id)?, id(recv_id)?, algorithm.parse()?, &secret);
+        Ok(match prefix_len {
+            Some(len) => mkt.prefix(len),
+            None => mkt,
+        })
+    }
+}
+
+/// The keys of a stack, connections take those of their peer when they are opened
+#[derive(Debug, Default)]
+pub(crate) struct KeyChain {
+    keys: RwLock<Vec<Mkt>>,
+}
+
+impl KeyChain {
+    fn read(&self) -> RwLockReadGuard<'_, Vec<Mkt>> {
+        self.keys.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Vec<Mkt>> {
+        self.keys.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// a peer can't have two keys with the same SendID or RecvID (RFC 5925 3.1)
+    pub(crate) fn add(&self, mkt: Mkt) -> Result<()> {
+        let mut keys = self.write();
+        if let Some(other) = keys.iter().find(|other| other.peer() == mkt.peer()
+            && (other.send_id == mkt.send_id || other.recv_id == mkt.recv_id)) {
+            let id = if other.send_id == mkt.send_id { mkt.send_id } else { mkt.recv_id };
+            return Err(Error::KeyIdInUse(id));
+        }
+        keys.push(mkt);
+        Ok(())
+    }
+
+    /// the key of `peer` with `send_id`
+    pub(crate) fn remove(&self, peer: (IpAddr, u8), send_id: u8) -> Option<Mkt> {
+        let mut keys = self.write();
+        let at = keys.iter().position(|mkt| mkt.peer() == peer && mkt.send_id == send_id)?;
+        Some(keys.remove(at))
+    }
+
+    pub(crate) fn keys(&self) -> Vec<Mkt> {
+        self.read().clone()
+    }
+
+    /// the authenticator of a connection with `quad.dest()`, `None` if it has no keys
+    pub(crate) fn authenticator(&self, quad: &Quad) -> Option<Authenticator> {
+        let keys: Vec<_> = self.read().iter().filter(|mkt| mkt.matches(quad.dest().ip())).cloned().collect();
+        Authenticator::new(keys)
+    }
+}
+
+/// Why a segment was not authentic
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AuthFailure {
+    /// the connection has keys and the segment no TCP-AO
+    Missing,
+    /// the KeyID is the RecvID of no key of the connection
+    UnknownKey(u8),
+    /// the MAC doesn't match the segment
+    BadMac,
+}
+
+/// Where the key rotation of a connection stands
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AuthStatus {
+    /// SendID of the key the segments are signed with
+    pub current_key: u8,
+    /// RecvID sent in the RNextKeyID of the segments
+    pub rnext_key: u8,
+    /// RNextKeyID of the last authentic segment received
+    pub peer_rnext_key: Option<u8>,
+    /// sequence number extensions, the times the sequence numbers wrapped around
+    pub send_sne: u32,
+    pub recv_sne: u32,
+}
+
+/// The TCP-AO state of a connection
+#[derive(Debug, Clone)]
+pub struct Authenticator {
+    keys: Vec<Mkt>,
+    /// index of the key the segments are signed with
+    current: usize,
+    rnext: u8,
+    peer_rnext: Option<u8>,
+    /// traffic keys of `keys` for the ISNs of the connection
+    traffic: Option<((u32, u32), Vec<TrafficKeys>)>,
+    send_sne: Option<SequenceExtension>,
+    recv_sne: Option<SequenceExtension>,
+}
+
+impl Authenticator {
+    /// sign with the first of `keys` and ask for it, `None` without keys
+    pub fn new(keys: Vec<Mkt>) -> Option<Self> {
+        let rnext = keys.first()?.recv_id;
+        Some(Self {
+            keys,
+            current: 0,
+            rnext,
+            peer_rnext: None,
+            traffic: None,
+            send_sne: None,
+            recv_sne: None,
+        })
+    }
+
+    pub fn keys(&self) -> &[Mkt] {
+        &self.keys
+    }
+
+    pub fn status(&self) -> AuthStatus {
+        AuthStatus {
+            current_key: self.keys[self.current].send_id,
+            rnext_key: self.rnext,
+            peer_rnext_key: self.peer_rnext,
+            send_sne: self.send_sne.map_or(0, |sne| sne.sne),
+            recv_sne: self.recv_sne.map_or(0, |sne| sne.sne),
+        }
+    }
+
+    /// bytes the option takes in every header
+    pub fn option_len(&self) -> usize {
+        self.keys[self.current].option_len()
+    }
+
+    pub(crate) fn add_key(&mut self, mkt: Mkt) {
+        self.keys.push(mkt);
+        self.traffic = None;
+    }
+
+    /// the key signs the segments or is the one asked for
+    pub(crate) fn in_use(&self, mkt: &Mkt) -> bool {
+        let current = &self.keys[self.current];
+        (current.peer(), current.send_id) == (mkt.peer(), mkt.send_id) || mkt.recv_id == self.rnext
+    }
+
+    /// drop the keys matching `f`, but the ones in use
+    pub(crate) fn remove_key<F: Fn(&Mkt) -> bool>(&mut self, f: F) {
+        let current = self.keys[self.current].clone();
+        let rnext = self.rnext;
+        self.keys.retain(|mkt| !f(mkt) || mkt.recv_id == rnext || (mkt.peer(), mkt.send_id) == (current.peer(), current.send_id));
+        self.current = self.keys.iter().position(|mkt| (mkt.peer(), mkt.send_id) == (current.peer(), current.send_id)).unwrap_or(0);
+        self.traffic = None;
+    }
+
+    /// sign the next segments with the key of `send_id`
+    pub fn set_current(&mut self, send_id: u8) -> Result<()> {
+        self.current = self.keys.iter().position(|mkt| mkt.send_id == send_id).ok_or(Error::UnknownKey(send_id))?;
+        Ok(())
+    }
+
+    /// ask the peer to sign with the key of `recv_id`
+    pub fn set_rnext(&mut self, recv_id: u8) -> Result<()> {
+        if !self.keys.iter().any(|mkt| mkt.recv_id == recv_id) {
+            return Err(Error::UnknownKey(recv_id));
+        }
+        self.rnext = recv_id;
+        Ok(())
+    }
+
+    /// the traffic keys for the ISNs of a synchronized connection
+    fn traffic(&mut self, quad: &Quad, isns: (u32, u32)) -> &[TrafficKeys] {
+        if !matches!(&self.traffic, Some((cached, _)) if *cached == isns) {
+            let (local, remote) = isns;
+            let keys = self.keys.iter()
+                .map(|mkt| TrafficKeys {
+                    send: mkt.traffic_key(quad, local, remote),
+                    recv: mkt.traffic_key(&quad.reverse(), remote, local),
+                })
+                .collect();
+            self.traffic = Some((isns, keys));
+        }
+        self.traffic.as_ref().map_or(&[], |(_, keys)| keys)
+    }
+
+    /// add the option with the MAC of the segment to `packet`, a SYN is signed with the ISN of
+    /// the peer as 0 (RFC 5926 3.1.1)
+    pub fn sign(&mut self, quad: &Quad, packet: &mut TcpIpHeader, payload: &[&[u8]], iss: u32, irs: u32) -> Result<()> {
+        let mkt = &self.keys[self.current];
+        let option = TcpOption::Authentication {
+            key_id: mkt.send_id,
+            rnext_key_id: self.rnext,
+            mac: vec![0; mkt.mac_len],
+        };
+        // the other options stay, TCP-AO goes last
+        let mut options: Vec<TcpOption> = packet.options().filter_map(|option| option.ok()).collect();
+        options.push(option);
+        packet.set_options(&options)?;
+        let (syn, ack, seq) = (packet.tcp_header.syn, packet.tcp_header.ack, packet.tcp_header.sequence_number);
+        let sne = self.send_sne.get_or_insert_with(|| SequenceExtension::new(iss));
+        sne.advance(seq);
+        let sne = sne.of(seq);
+        let (current, algorithm, mac_len, include_options) = (self.current, mkt.algorithm, mkt.mac_len, mkt.include_options);
+        let key = if syn && !ack {
+            mkt.traffic_key(quad, iss, 0)
+        } else {
+            self.traffic(quad, (iss, irs))[current].send.clone()
+        };
+        let header = packet.tcp_header.to_bytes();
+        let covered = covered_header(&header, include_options).ok_or(Error::InvalidOption(KIND_AUTHENTICATION))?;
+        let len = header.len() + payload.iter().map(|part| part.len()).sum::<usize>();
+        let pseudo = pseudo_header(quad, len);
+        let extension = sne.to_be_bytes();
+        let mut input: Vec<&[u8]> = vec![&extension, &pseudo, &covered];
+        input.extend_from_slice(payload);
+        let mut mac = algorithm.mac(&key, &input);
+        mac.truncate(mac_len);
+        if let Some(TcpOption::Authentication { mac: field, .. }) = options.last_mut() {
+            *field = mac;
+        }
+        packet.set_options(&options)
+    }
+
+    /// check the MAC of a segment received on `quad`, it switches to the key the peer asks for.
+    /// return true if it did
+    pub fn verify(
+        &mut self,
+        quad: &Quad,
+        tcp: &TcpHeaderSlice,
+        data: &[u8],
+        iss: u32,
+        irs: u32,
+    ) -> std::result::Result<bool, AuthFailure> {
+        let (key_id, rnext_key_id, mac) = options::parse(tcp.options())
+            .filter_map(|option| option.ok())
+            .find_map(|option| match option {
+                TcpOption::Authentication { key_id, rnext_key_id, mac } => Some((key_id, rnext_key_id, mac)),
+                _ => None,
+            })
+            .ok_or(AuthFailure::Missing)?;
+        let at = self.keys.iter().position(|mkt| mkt.recv_id == key_id).ok_or(AuthFailure::UnknownKey(key_id))?;
+        let mkt = &self.keys[at];
+        if mac.len() != mkt.mac_len {
+            return Err(AuthFailure::BadMac);
+        }
+        let seq = tcp.sequence_number();
+        // the sequence number of a SYN is the ISN of the peer
+        let irs = if tcp.syn() { seq } else { irs };
+        let mut sne = self.recv_sne.unwrap_or_else(|| SequenceExtension::new(irs));
+        let (algorithm, include_options) = (mkt.algorithm, mkt.include_options);
+        let key = if tcp.syn() && !tcp.ack() {
+            mkt.traffic_key(&quad.reverse(), irs, 0)
+        } else {
+            self.traffic(quad, (iss, irs))[at].recv.clone()
+        };
+        let header = tcp.slice();
+        let covered = covered_header(header, include_options).ok_or(AuthFailure::Missing)?;
+        let pseudo = pseudo_header(&quad.reverse(), header.len() + data.len());
+        let extension = sne.of(seq).to_be_bytes();
+        if !algorithm.verify(&key, &[&extension, &pseudo, &covered, data], &mac) {
+            return Err(AuthFailure::BadMac);
+        }
+        sne.advance(seq);
+        self.recv_sne = Some(sne);
+        self.peer_rnext = Some(rnext_key_id);
+        if rnext_key_id == self.keys[self.current].send_id {
+            return Ok(false);
+        }
+        match self.keys.iter().position(|mkt| mkt.send_id == rnext_key_id) {
+            Some(at) => {
+                self.current = at;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// The keys of the segments other than SYNs of one MKT
+#[derive(Debug, Clone)]
+struct TrafficKeys {
+    send: Vec<u8>,
+    recv: Vec<u8>,
+}
+
+/// The high order 32 bits of the sequence numbers of one direction (RFC 5925 6.2)
+#[derive(Debug, Copy, Clone)]
+struct SequenceExtension {
+    /// highest sequence number seen so far
+    prev: u32,
+    sne: u32,
+}
+
+impl SequenceExtension {
+    fn new(isn: u32) -> Self {
+        Self { prev: isn, sne: 0 }
+    }
+
+    /// the SNE of `seq`, one more past a wrap around and one less for a segment from before it
+    fn of(&self, seq: u32) -> u32 {
+        match (seq_ge(seq, self.prev), seq < self.prev) {
+            (true, true) => self.sne.wrapping_add(1),
+            (false, false) => self.sne.wrapping_sub(1),
+            _ => self.sne,
+        }
+    }
+
+    fn advance(&mut self, seq: u32) {
+        if seq_gt(seq, self.prev) {
+            self.sne = self.of(seq);
+            self.prev = seq;
+        }
+    }
+}
+
+/// the ip pseudo header of a segment of `len` bytes from `quad.src()` to `quad.dest()`
+fn pseudo_header(quad: &Quad, len: usize) -> Vec<u8> {
+    let mut pseudo = Vec::with_capacity(40);
+    match (quad.src().ip(), quad.dest().ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dest)) => {
+            pseudo.extend_from_slice(&src.octets());
+            pseudo.extend_from_slice(&dest.octets());
+            pseudo.extend_from_slice(&[0, etherparse::IpNumber::TCP.0]);
+            pseudo.extend_from_slice(&u16::try_from(len).unwrap_or(u16::MAX).to_be_bytes());
+        }
+        (src, dest) => {
+            for ip in [src, dest] {
+                let ip = match ip {
+                    IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                    IpAddr::V6(ip) => ip,
+                };
+                pseudo.extend_from_slice(&ip.octets());
+            }
+            pseudo.extend_from_slice(&(len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, etherparse::IpNumber::TCP.0]);
+        }
+    }
+    pseudo
+}
+
+/// the header as the MAC covers it, with the checksum and the MAC zeroed and without the
+/// options other than TCP-AO when they are excluded. `None` if it has no TCP-AO
+fn covered_header(header: &[u8], include_options: bool) -> Option<Vec<u8>> {
+    let (at, len) = find_option(header.get(TCP_HEADER_LEN..)?, KIND_AUTHENTICATION)?;
+    let option = TCP_HEADER_LEN + at..TCP_HEADER_LEN + at + len;
+    let mut covered = if include_options {
+        header.to_vec()
+    } else {
+        let mut covered = header[..TCP_HEADER_LEN].to_vec();
+        covered.extend_from_slice(&header[option.clone()]);
+        covered
+    };
+    covered[16..18].fill(0);
+    let mac = if include_options { option.start + 4..option.end } else { TCP_HEADER_LEN + 4..covered.len() };
+    covered[mac].fill(0);
+    Some(covered)
+}
+
+/// offset and length of the first option of `kind` in `raw`
+fn find_option(raw: &[u8], kind: u8) -> Option<(usize, usize)> {
+    let mut at = 0;
+    while at < raw.len() {
+        match raw[at] {
+            KIND_END_OF_LIST => return None,
+            KIND_NOP => at += 1,
+            other => {
+                let len = *raw.get(at + 1)? as usize;
+                if len < 2 || at + len > raw.len() {
+                    return None;
+                }
+                if other == kind && len >= 4 {
+                    return Some((at, len));
+                }
+                at += len;
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,92 @@
+use core::time::Duration;
+
+use crate::time::Instant;
+
+use super::vars::seq_ge;
+
+/// largest receive buffer autotuning grows to
+pub const DEFAULT_RECV_BUFFER_MAX: usize = 4 * 1024 * 1024;
+/// largest send buffer autotuning grows to
+pub const DEFAULT_SEND_BUFFER_MAX: usize = 4 * 1024 * 1024;
+
+/// send buffer which keeps a congestion window of `cwnd` in flight and as much queued
+/// behind it, so the window never waits for the application to refill the buffer
+pub fn send_buffer_for(cwnd: usize) -> usize {
+    cwnd.saturating_mul(2)
+}
+
+/// Grows the receive buffer to twice what the application reads in a round trip,
+/// like the receive buffer autotuning of Linux (dynamic right-sizing)
+///
+/// the buffer keeps up with the bandwidth-delay product of the path as long as the
+/// application keeps up with the data, a slow reader leaves it as it is. without window
+/// scaling the advertised window stays below 64KB, the larger buffer holds what the
+/// application didn't read yet without closing the window
+#[derive(Debug, Clone, Default)]
+pub struct RecvAutotune {
+    /// buffer size found so far, 0 until it grew once
+    size: usize,
+    /// bytes read in the current round trip, since `start`
+    copied: usize,
+    start: Option<Instant>,
+    /// most bytes read in one round trip so far
+    space: usize,
+    /// round trip seen by the receiver, the time a window of data takes to arrive
+    rtt: Option<Duration>,
+    /// end of the window advertised when the measurement started and when it did
+    rtt_mark: Option<(u32, Instant)>,
+}
+
+impl RecvAutotune {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// buffer size autotuning found, 0 until it grew once
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// round trip estimated from the data received, `None` before the first full window
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// in order data moved rcv.nxt to `nxt`, `window` was advertised before it arrived
+    pub fn on_data(&mut self, nxt: u32, window: u16, now: Instant) {
+        match self.rtt_mark {
+            Some((end, at)) if seq_ge(nxt, end) => {
+                let sample = now.saturating_duration_since(at);
+                // the smaller samples are closer to the round trip, the larger ones include the sender's pauses
+                self.rtt = Some(match self.rtt {
+                    Some(rtt) if sample < rtt => sample,
+                    Some(rtt) => rtt * 7 / 8 + sample / 8,
+                    None => sample,
+                });
+                self.rtt_mark = None;
+            }
+            Some(_) => {}
+            None => self.rtt_mark = Some((nxt.wrapping_add(window as u32), now)),
+        }
+    }
+
+    /// the application read `n` bytes, once a round trip passed the buffer grows to twice
+    /// what was read in it plus room for 16 segments, up to `max`
+    pub fn on_read(&mut self, n: usize, rtt: Option<Duration>, mss: usize, max: usize, now: Instant) {
+        self.copied += n;
+        let rtt = match self.rtt.or(rtt) {
+            Some(rtt) => rtt,
+            None => return,
+        };
+        let start = *self.start.get_or_insert(now);
+        if now.saturating_duration_since(start) < rtt {
+            return;
+        }
+        if self.copied > self.space {
+            self.space = self.copied;
+            self.size = self.size.max((2 * self.copied + 16 * mss).min(max));
+        }
+        self.copied = 0;
+        self.start = Some(now);
+    }
+}
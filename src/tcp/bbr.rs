@@ -0,0 +1,316 @@
+//! BBR congestion control, version 1
+//!
+//! a model of the path instead of losses drives the sender: the bottleneck bandwidth is the
+//! largest delivery rate of the last rounds, the propagation delay the smallest round-trip
+//! time of the last 10 seconds. data is paced at a gain times the bandwidth and the window
+//! holds a gain times their product. see "BBR: Congestion-Based Congestion Control"
+//! (Cardwell et al., 2016) and draft-cardwell-iccrg-bbr-congestion-control-00.
+//!
+//! the delivery rate is sampled over the last round trip from the acknowledged bytes,
+//! the stack doesn't keep per-segment delivery state, and application limited periods
+//! aren't detected. BBR needs pacing, see `ConnectionConfig::set_pacing`
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::time::Duration;
+
+use crate::tcp::congestion::CongestionControl;
+use crate::time::Instant;
+
+/// 2/ln(2), the smallest gain doubling the delivery rate every round in startup
+const HIGH_GAIN: f64 = 2.885;
+/// pacing gains of the probe bandwidth cycle, one phase per min rtt
+const PACING_GAIN_CYCLE: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+/// rounds the bandwidth filter remembers a sample
+const BW_FILTER_ROUNDS: u64 = 10;
+/// how long a min rtt sample is valid before probe rtt measures it again
+const MIN_RTT_WINDOW: Duration = Duration::from_secs(10);
+/// time spent with a tiny window in probe rtt
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+/// rounds without 25% more bandwidth after which startup found the bottleneck
+const FULL_BW_ROUNDS: u32 = 3;
+
+/// State of the BBR state machine
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BbrMode {
+    /// doubling the sending rate every round until the bandwidth stops growing
+    Startup,
+    /// draining the queue startup built
+    Drain,
+    /// cycling the pacing gain around the estimated bandwidth
+    ProbeBw,
+    /// a window of 4 segments for a moment to measure the propagation delay again
+    ProbeRtt,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bbr {
+    mss: usize,
+    mode: BbrMode,
+    cwnd: usize,
+    /// bytes acknowledged so far
+    delivered: u64,
+    /// delivered bytes at earlier ACKs, the rate is sampled over about one round trip
+    checkpoints: VecDeque<(Instant, u64)>,
+    /// windowed maximum of the delivery rate samples, by round
+    bw_filter: VecDeque<(u64, u64)>,
+    min_rtt: Option<Duration>,
+    min_rtt_stamp: Option<Instant>,
+    /// round trips counted by delivered bytes
+    round: u64,
+    next_round_delivered: u64,
+    /// bandwidth startup last grew to and rounds without growth since
+    full_bw: u64,
+    full_bw_rounds: u32,
+    filled_pipe: bool,
+    pacing_gain: f64,
+    cwnd_gain: f64,
+    cycle_index: usize,
+    cycle_stamp: Option<Instant>,
+    /// window before probe rtt, restored after it
+    prior_cwnd: usize,
+    probe_rtt_done: Option<Instant>,
+}
+
+impl Bbr {
+    pub fn new(mss: usize, initial_window: usize) -> Self {
+        Self {
+            mss,
+            mode: BbrMode::Startup,
+            cwnd: initial_window,
+            delivered: 0,
+            checkpoints: VecDeque::new(),
+            bw_filter: VecDeque::new(),
+            min_rtt: None,
+            min_rtt_stamp: None,
+            round: 0,
+            next_round_delivered: 0,
+            full_bw: 0,
+            full_bw_rounds: 0,
+            filled_pipe: false,
+            pacing_gain: HIGH_GAIN,
+            cwnd_gain: HIGH_GAIN,
+            cycle_index: 0,
+            cycle_stamp: None,
+            prior_cwnd: 0,
+            probe_rtt_done: None,
+        }
+    }
+
+    pub fn mode(&self) -> BbrMode {
+        self.mode
+    }
+
+    /// estimated bottleneck bandwidth in bytes per second, `None` before the first sample
+    pub fn bandwidth(&self) -> Option<u64> {
+        self.bw_filter.front().map(|(_, bw)| *bw)
+    }
+
+    /// estimated propagation delay
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.min_rtt
+    }
+
+    /// bandwidth-delay product scaled by `gain`
+    fn target(&self, gain: f64) -> Option<usize> {
+        let bdp = self.bandwidth()? as f64 * self.min_rtt?.as_secs_f64();
+        Some((bdp * gain) as usize + 3 * self.mss)
+    }
+
+    fn min_cwnd(&self) -> usize {
+        4 * self.mss
+    }
+
+    /// the delivery rate over the last round trip
+    fn sample_bandwidth(&mut self, now: Instant) {
+        self.checkpoints.push_back((now, self.delivered));
+        let window = match self.min_rtt {
+            Some(rtt) => rtt,
+            None => return,
+        };
+        // keep the newest checkpoint at least one round trip old
+        while self.checkpoints.len() > 2 && now.saturating_duration_since(self.checkpoints[1].0) >= window {
+            self.checkpoints.pop_front();
+        }
+        let (then, delivered) = self.checkpoints[0];
+        let elapsed = now.saturating_duration_since(then);
+        if elapsed.is_zero() || elapsed < window / 2 {
+            return;
+        }
+        let bw = ((self.delivered - delivered) as f64 / elapsed.as_secs_f64()) as u64;
+        while self.bw_filter.back().is_some_and(|(_, max)| *max <= bw) {
+            self.bw_filter.pop_back();
+        }
+        self.bw_filter.push_back((self.round, bw));
+        while self.bw_filter.front().is_some_and(|(round, _)| round + BW_FILTER_ROUNDS <= self.round) {
+            self.bw_filter.pop_front();
+        }
+    }
+
+    /// startup is over once three rounds didn't grow the bandwidth by a quarter
+    fn check_full_pipe(&mut self) {
+        let bw = match self.bandwidth() {
+            Some(bw) => bw,
+            None => return,
+        };
+        if bw as f64 >= self.full_bw as f64 * 1.25 {
+            self.full_bw = bw;
+            self.full_bw_rounds = 0;
+            return;
+        }
+        self.full_bw_rounds += 1;
+        if self.full_bw_rounds >= FULL_BW_ROUNDS {
+            self.filled_pipe = true;
+        }
+    }
+
+    fn enter_probe_bw(&mut self, now: Instant) {
+        self.mode = BbrMode::ProbeBw;
+        self.cwnd_gain = 2.0;
+        // start past the drain phase, the queue is empty
+        self.cycle_index = 2;
+        self.cycle_stamp = Some(now);
+        self.pacing_gain = PACING_GAIN_CYCLE[self.cycle_index];
+    }
+
+    fn advance_cycle(&mut self, in_flight: usize, now: Instant) {
+        let (stamp, min_rtt) = match (self.cycle_stamp, self.min_rtt) {
+            (Some(stamp), Some(min_rtt)) => (stamp, min_rtt),
+            _ => return,
+        };
+        let elapsed = now.saturating_duration_since(stamp) > min_rtt;
+        let drained = self.target(1.0).is_some_and(|bdp| in_flight <= bdp);
+        let next = match self.pacing_gain {
+            gain if gain > 1.0 => elapsed,
+            // the queue the probe built is gone, no need to wait the whole phase
+            gain if gain < 1.0 => elapsed || drained,
+            _ => elapsed,
+        };
+        if next {
+            self.cycle_index = (self.cycle_index + 1) % PACING_GAIN_CYCLE.len();
+            self.cycle_stamp = Some(now);
+            self.pacing_gain = PACING_GAIN_CYCLE[self.cycle_index];
+        }
+    }
+
+    fn update_min_rtt(&mut self, rtt: Option<Duration>, in_flight: usize, round_start: bool, now: Instant) {
+        let expired = self.min_rtt_stamp.is_some_and(|stamp| now.saturating_duration_since(stamp) > MIN_RTT_WINDOW);
+        if let Some(rtt) = rtt {
+            if expired || self.min_rtt.is_none_or(|min| rtt <= min) {
+                self.min_rtt = Some(rtt);
+                self.min_rtt_stamp = Some(now);
+            }
+        }
+        if expired && self.mode != BbrMode::ProbeRtt {
+            self.mode = BbrMode::ProbeRtt;
+            self.pacing_gain = 1.0;
+            self.prior_cwnd = self.cwnd.max(self.prior_cwnd);
+            self.probe_rtt_done = None;
+        }
+        if self.mode != BbrMode::ProbeRtt {
+            return;
+        }
+        match self.probe_rtt_done {
+            // wait for the window to drain to the minimum first
+            None if in_flight <= self.min_cwnd() => self.probe_rtt_done = Some(now + PROBE_RTT_DURATION),
+            Some(done) if now >= done && round_start => {
+                self.min_rtt_stamp = Some(now);
+                self.cwnd = self.cwnd.max(self.prior_cwnd);
+                self.prior_cwnd = 0;
+                if self.filled_pipe {
+                    self.enter_probe_bw(now);
+                } else {
+                    self.mode = BbrMode::Startup;
+                    self.pacing_gain = HIGH_GAIN;
+                    self.cwnd_gain = HIGH_GAIN;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn update_cwnd(&mut self, acked: usize) {
+        if self.mode == BbrMode::ProbeRtt {
+            self.cwnd = self.cwnd.min(self.min_cwnd());
+            return;
+        }
+        match self.target(self.cwnd_gain) {
+            Some(target) if self.filled_pipe => self.cwnd = (self.cwnd + acked).min(target),
+            Some(target) if self.cwnd < target => self.cwnd += acked,
+            Some(_) => {}
+            // no model yet, grow like slow start
+            None => self.cwnd += acked,
+        }
+        self.cwnd = self.cwnd.max(self.min_cwnd());
+    }
+}
+
+impl CongestionControl for Bbr {
+    fn name(&self) -> &'static str {
+        "bbr"
+    }
+
+    fn on_ack(&mut self, acked: usize, in_flight: usize, rtt: Option<Duration>, now: Instant) {
+        self.delivered += acked as u64;
+        let round_start = self.delivered >= self.next_round_delivered;
+        if round_start {
+            self.round += 1;
+            self.next_round_delivered = self.delivered + in_flight as u64;
+        }
+        if let Some(rtt) = rtt {
+            // the first sample sets the window of the rate samples
+            if self.min_rtt.is_none() {
+                self.min_rtt = Some(rtt);
+                self.min_rtt_stamp = Some(now);
+            }
+        }
+        self.sample_bandwidth(now);
+
+        match self.mode {
+            BbrMode::Startup => {
+                if round_start {
+                    self.check_full_pipe();
+                }
+                if self.filled_pipe {
+                    self.mode = BbrMode::Drain;
+                    self.pacing_gain = 1.0 / HIGH_GAIN;
+                }
+            }
+            BbrMode::Drain => {
+                if self.target(1.0).is_some_and(|bdp| in_flight <= bdp) {
+                    self.enter_probe_bw(now);
+                }
+            }
+            BbrMode::ProbeBw => self.advance_cycle(in_flight, now),
+            BbrMode::ProbeRtt => {}
+        }
+        self.update_min_rtt(rtt, in_flight, round_start, now);
+        self.update_cwnd(acked);
+    }
+
+    fn on_loss(&mut self, in_flight: usize, _now: Instant) {
+        // packet conservation, the model doesn't react to losses
+        self.cwnd = in_flight.max(self.min_cwnd());
+    }
+
+    fn on_timeout(&mut self, _in_flight: usize, _now: Instant) {
+        self.cwnd = self.mss;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    /// BBR has no slow start threshold
+    fn ssthresh(&self) -> usize {
+        usize::MAX
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        self.bandwidth().map(|bw| (bw as f64 * self.pacing_gain) as u64)
+    }
+
+    fn box_clone(&self) -> Box<dyn CongestionControl> {
+        Box::new(self.clone())
+    }
+}
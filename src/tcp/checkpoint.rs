@@ -0,0 +1,86 @@
+//! Checkpoint and restore of established connections
+//!
+//! a checkpoint holds what the peer has seen of a connection: the sequence spaces, the
+//! data not read or not acknowledged yet, the segments received out of order and the
+//! round-trip estimate. timers are kept as the time left when it was taken and restart
+//! from the restore, a process restarting within a few retransmission timeouts picks the
+//! connections up where they were. the congestion window is not kept, a restored
+//! connection starts from the initial window like after an idle period (RFC 5681 4.1)
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::socket_addr::Quad;
+use crate::tcp::connection::ConnectionConfig;
+use crate::tcp::rtt::RttEstimator;
+use crate::tcp::stats::ConnectionStats;
+use crate::tcp::vars::{ReceiveSequenceSpace, SendSequenceSpace, TcpState};
+
+/// A synchronized connection written down, see `TcpConnection::checkpoint`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub(crate) quad: Quad,
+    pub(crate) state: TcpState,
+    pub(crate) send_seq: SendSequenceSpace,
+    pub(crate) recv_seq: ReceiveSequenceSpace,
+    /// with the buffer sizes autotuning reached, the window doesn't shrink on restore
+    pub(crate) config: ConnectionConfig,
+    pub(crate) passive: bool,
+    /// bytes received in order and not read by the application yet
+    pub(crate) incoming: Vec<u8>,
+    /// bytes written by the application starting at snd.una
+    pub(crate) outgoing: Vec<u8>,
+    /// segments received ahead of rcv.nxt and the FIN among them
+    pub(crate) reassembly: Vec<(u32, Vec<u8>)>,
+    pub(crate) reassembly_fin: Option<u32>,
+    pub(crate) fin_pending: bool,
+    pub(crate) fin_sent: bool,
+    pub(crate) peer_fin: bool,
+    pub(crate) snd_max: u32,
+    pub(crate) max_snd_wnd: u16,
+    /// the user timeout the peer advertised
+    pub(crate) peer_user_timeout: Option<Duration>,
+    /// the last timestamp of the peer and how far our timestamp clock ran, the restored
+    /// connection keeps echoing it and its values don't go back (RFC 7323 5)
+    #[serde(default)]
+    pub(crate) ts_recent: Option<u32>,
+    #[serde(default)]
+    pub(crate) ts_clock: Option<Duration>,
+    pub(crate) rtt: RttEstimator,
+    pub(crate) stats: ConnectionStats,
+    pub(crate) timers: Timers,
+}
+
+impl Checkpoint {
+    /// local address as source, remote address as destination
+    pub fn quad(&self) -> Quad {
+        self.quad
+    }
+
+    pub fn state(&self) -> TcpState {
+        self.state
+    }
+
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    /// the restored connection runs with `config`, e.g. with a custom congestion control
+    pub fn config_mut(&mut self) -> &mut ConnectionConfig {
+        &mut self.config
+    }
+}
+
+/// Timers of a connection as the time left when the checkpoint was taken
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Timers {
+    /// until the retransmission timer fires
+    pub retransmit: Option<Duration>,
+    /// until the delayed ACK is sent
+    pub ack: Option<Duration>,
+    /// timer expirations since the last acknowledgment and for how long they went on,
+    /// R2 counts from the first one
+    pub retransmissions: u32,
+    pub retransmitting_for: Option<Duration>,
+}
@@ -0,0 +1,158 @@
+use alloc::boxed::Box;
+use core::fmt::Debug;
+use core::time::Duration;
+
+use crate::tcp::bbr::Bbr;
+use crate::time::Instant;
+
+/// Congestion control algorithm of one connection, sizes are in bytes
+pub trait CongestionControl: Debug + Send {
+    fn name(&self) -> &'static str;
+
+    /// `acked` bytes of new data were acknowledged, `rtt` is the sample taken from this ACK if any
+    fn on_ack(&mut self, acked: usize, in_flight: usize, rtt: Option<Duration>, now: Instant);
+
+    /// a loss was detected by duplicate ACKs, the segment is retransmitted right away
+    fn on_loss(&mut self, in_flight: usize, now: Instant);
+
+    /// the retransmission timer expired
+    fn on_timeout(&mut self, in_flight: usize, now: Instant);
+
+    /// the connection sent nothing for `idle`, longer than the retransmission timeout `rto`.
+    /// the window went unused and the path may have changed meanwhile, it is kept by default
+    fn on_idle(&mut self, _idle: Duration, _rto: Duration, _now: Instant) {}
+
+    /// bytes allowed in flight
+    fn cwnd(&self) -> usize;
+
+    fn ssthresh(&self) -> usize;
+
+    /// bytes per second the sender should not exceed, `None` sends as fast as the window allows
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+
+    fn box_clone(&self) -> Box<dyn CongestionControl>;
+}
+
+impl Clone for Box<dyn CongestionControl> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Algorithm created for every new connection
+#[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CongestionAlgorithm {
+    #[default]
+    NewReno,
+    /// model based BBR, meant to run with pacing
+    Bbr,
+    /// created by the function from the MSS and the initial window in bytes of the
+    /// connection, can't be serialized
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(fn(usize, usize) -> Box<dyn CongestionControl>),
+}
+
+impl CongestionAlgorithm {
+    /// the algorithm for a connection sending segments of `mss` bytes, starting with a window
+    /// of `initial_segments` of them
+    pub fn build(self, mss: usize, initial_segments: usize) -> Box<dyn CongestionControl> {
+        let initial = initial_window(mss, initial_segments);
+        match self {
+            CongestionAlgorithm::NewReno => Box::new(NewReno::new(mss, initial)),
+            CongestionAlgorithm::Bbr => Box::new(Bbr::new(mss, initial)),
+            CongestionAlgorithm::Custom(build) => build(mss, initial),
+        }
+    }
+}
+
+/// segments of the initial window (RFC 6928)
+pub const DEFAULT_INITIAL_WINDOW: usize = 10;
+
+/// initial window of `segments` full segments, at least one
+pub fn initial_window(mss: usize, segments: usize) -> usize {
+    segments.max(1).saturating_mul(mss)
+}
+
+/// Slow start and congestion avoidance of RFC 5681 with the window reduction of RFC 6582
+#[derive(Debug, Clone)]
+pub struct NewReno {
+    mss: usize,
+    /// the window at the start, and the most it restarts with after an idle period
+    initial: usize,
+    cwnd: usize,
+    ssthresh: usize,
+    /// bytes acknowledged since cwnd last grew in congestion avoidance
+    acked: usize,
+}
+
+impl NewReno {
+    pub fn new(mss: usize, initial_window: usize) -> Self {
+        Self {
+            mss,
+            initial: initial_window,
+            cwnd: initial_window,
+            ssthresh: usize::MAX,
+            acked: 0,
+        }
+    }
+
+    /// RFC 5681 equation (4)
+    fn reduce(&mut self, in_flight: usize) {
+        self.ssthresh = (in_flight / 2).max(2 * self.mss);
+        self.acked = 0;
+    }
+}
+
+impl CongestionControl for NewReno {
+    fn name(&self) -> &'static str {
+        "newreno"
+    }
+
+    fn on_ack(&mut self, acked: usize, _in_flight: usize, _rtt: Option<Duration>, _now: Instant) {
+        if self.cwnd < self.ssthresh {
+            self.cwnd += acked.min(self.mss);
+            return;
+        }
+        // one MSS per round trip
+        self.acked += acked;
+        if self.acked >= self.cwnd {
+            self.acked -= self.cwnd;
+            self.cwnd += self.mss;
+        }
+    }
+
+    fn on_loss(&mut self, in_flight: usize, _now: Instant) {
+        self.reduce(in_flight);
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_timeout(&mut self, in_flight: usize, _now: Instant) {
+        self.reduce(in_flight);
+        self.cwnd = self.mss;
+    }
+
+    /// the window is halved for every timeout of idle time, down to the restart window,
+    /// and ssthresh keeps 3/4 of it to get back there in slow start (RFC 7661 4.4, RFC 2861)
+    fn on_idle(&mut self, idle: Duration, rto: Duration, _now: Instant) {
+        let restart = self.initial.min(self.cwnd);
+        let periods = (idle.as_nanos() / rto.as_nanos().max(1)).min(usize::BITS as u128 - 1) as u32;
+        self.ssthresh = self.ssthresh.max(self.cwnd / 4 * 3);
+        self.cwnd = (self.cwnd >> periods).max(restart);
+        self.acked = 0;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    fn box_clone(&self) -> Box<dyn CongestionControl> {
+        Box::new(self.clone())
+    }
+}
@@ -1,61 +1,713 @@
-use std::net::{IpAddr, Ipv4Addr};
-use std::time;
-use std::time::Duration;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::net::IpAddr;
+use core::ops::Range;
+use core::time::Duration;
 
-use crossbeam_queue::ArrayQueue;
-use etherparse::{Ipv4Header, TcpHeader};
+use etherparse::{Ipv4HeaderSlice, TcpHeaderSlice};
+use tracing::Span;
 
-use crate::data_link::DataLayer;
-use crate::meta::ETHERNET_MTU;
-use crate::net_types::EtherType;
-use crate::net_types::Protocol::TCP;
+use crate::clock::{default_clock, Clock};
+use crate::data_link::{Capabilities, DataLayer};
+use crate::io::IoSlice;
+use crate::meta::{IP_HEADER_MAXIMUM_SIZE, TCP_HEADER_MAXIMUM_SIZE};
+use crate::net_types::Dscp;
+use crate::netstat::TimerKind;
+#[cfg(feature = "std")]
+use crate::observer::Observers;
+use crate::observer::{ConnectionObserver, KeepAliveVerdict};
+#[cfg(feature = "std")]
 use crate::reader_writer::RawWriter;
-// use crate::reader_writer::RawWriter;
 use crate::result;
+use crate::socket_addr::{Addr, Quad};
+#[cfg(feature = "mptcp")]
+use crate::mptcp::subflow::{self, Subflow, Verdict};
+#[cfg(feature = "mptcp")]
+use crate::mptcp::OPTION_LEN;
+#[cfg(feature = "tcp-ao")]
+use crate::tcp::ao::{AuthFailure, Authenticator};
+#[cfg(feature = "serde")]
+use crate::tcp::checkpoint::{Checkpoint, Timers};
+use crate::tcp::autotune::{send_buffer_for, RecvAutotune, DEFAULT_RECV_BUFFER_MAX, DEFAULT_SEND_BUFFER_MAX};
+use crate::tcp::congestion::{CongestionAlgorithm, CongestionControl, DEFAULT_INITIAL_WINDOW};
+use crate::tcp::options::{self, TcpOption};
 use crate::tcp::packet::TcpIpHeader;
+use crate::tcp::reassembly::{ReassemblyBudget, ReassemblyQueue, DEFAULT_REASSEMBLY_LIMIT};
+use crate::tcp::scheduler::{pacing_rate, Release, SendScheduler, SendState, Transmission};
+use crate::tcp::shaper::RateLimit;
+#[cfg(feature = "std")]
+use crate::tcp::shaper::Shaper;
+use crate::tcp::ring::{RingBuffer, WatermarkListener, Watermarks};
+use crate::tcp::rtt::{RttEstimator, DEFAULT_CLOCK_GRANULARITY, MAX_RTO};
+use crate::tcp::stats::ConnectionStats;
+use crate::time::Instant;
 
-use super::vars::{ReceiveSequenceSpace, SendSequenceSpace, TcpState};
+use super::vars::{seq_ge, seq_gt, seq_le, seq_lt, ReceiveSequenceSpace, SendSequenceSpace, TcpControl, TcpState};
 
 pub const DEFAULT_ISS: u32 = 0;
 pub const DEFAULT_WINDOWS_SIZE: u16 = 1024;
-pub const DEFAULT_RTT: u64 = 1 * 60;
 pub const TCP_DEFAULT_HANDLE_BUF_SIZE: usize = 5;
 pub const DEFAULT_TIME_TO_LIVE: u8 = 64;
+/// segment size assumed when the peer doesn't send the MSS option (RFC 1122 4.2.2.6)
+pub const DEFAULT_MSS: usize = 536;
+/// bytes the application can queue before writes are refused
+pub const DEFAULT_SEND_BUFFER_SIZE: usize = 64 * 1024;
+/// bytes received and not read yet before the window closes
+pub const DEFAULT_RECV_BUFFER_SIZE: usize = 64 * 1024;
+/// largest segment handed to a device with segmentation offload, the ip packet stays below 64KB
+pub const TSO_MAX_SEGMENT: usize = u16::MAX as usize - IP_HEADER_MAXIMUM_SIZE - TCP_HEADER_MAXIMUM_SIZE;
+/// maximum segment lifetime, a connection stays 2 MSL in TIME-WAIT
+pub const MSL: Duration = Duration::from_secs(30);
+/// retransmissions of the same segment before the application is told (RFC 1122 4.2.3.5)
+pub const DEFAULT_R1: u32 = 3;
+/// how long a segment is retransmitted before the connection is aborted
+pub const DEFAULT_R2: Duration = Duration::from_secs(100);
+/// idle connections are probed this often once keep-alive started (RFC 1122 4.2.3.6)
+pub const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(75);
+/// unanswered keep-alive probes after which the connection is aborted
+pub const DEFAULT_KEEP_ALIVE_PROBES: u32 = 9;
+/// the shortest user timeout a peer can make a connection take (RFC 5482 3.1)
+pub const USER_TIMEOUT_LOWER_LIMIT: Duration = Duration::from_secs(100);
+/// the longest user timeout the option can carry, 32767 minutes
+pub const USER_TIMEOUT_UPPER_LIMIT: Duration = Duration::from_secs(0x7fff * 60);
+/// duplicate ACKs which trigger a fast retransmit on a path not known to reorder (RFC 5681 3.2)
+pub const DEFAULT_DUPTHRESH: u32 = 3;
+/// the duplicate ACK threshold never adapts beyond this, like tcp_max_reordering of Linux
+pub const DEFAULT_MAX_DUPTHRESH: u32 = 300;
+/// how long a handshake may take, RFC 1122 asks for at least 3 minutes
+pub const DEFAULT_SYN_R2: Duration = Duration::from_secs(180);
+/// SYN,ACK retransmissions before an embryonic passive connection is dropped, about a minute
+pub const DEFAULT_SYNACK_RETRIES: u32 = 5;
+/// challenge ACKs a connection sends per second (RFC 5961 7)
+pub const DEFAULT_CHALLENGE_ACK_LIMIT: u32 = 100;
+/// delayed ACK timeout of Linux, RFC 1122 allows up to 500ms
+pub const DELAYED_ACK_TIMEOUT: Duration = Duration::from_millis(40);
+/// the timestamps option padded to a multiple of 4 bytes
+const TIMESTAMPS_LEN: usize = 12;
 
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnectionConfig {
     init_send_seq_number: u32,
-    window_size: u16,
-    send_rtt: time::Duration,
+    clock_granularity: Duration,
     ttl: u8,
+    min_ttl: u8,
+    tos: u8,
+    recv_buffer_size: usize,
+    recv_buffer_max: usize,
+    send_buffer_size: usize,
+    send_buffer_max: usize,
+    recv_low_watermark: usize,
+    send_low_watermark: usize,
+    mss: usize,
+    /// `set_mss` was called, the stack leaves the mss below its mtu instead of deriving it
+    #[cfg_attr(feature = "serde", serde(default))]
+    mss_set: bool,
+    congestion: CongestionAlgorithm,
+    initial_window: usize,
+    r1: u32,
+    r2: Duration,
+    syn_r2: Duration,
+    synack_retries: u32,
+    max_dupthresh: u32,
+    keep_alive: Option<Duration>,
+    keep_alive_interval: Duration,
+    keep_alive_probes: u32,
+    user_timeout: Option<Duration>,
+    accept_user_timeout: bool,
+    user_timeout_limits: (Duration, Duration),
+    reassembly_limit: usize,
+    pressure_window: bool,
+    challenge_ack_limit: u32,
+    delayed_ack: Option<Duration>,
+    nagle: bool,
+    pacing: bool,
+    gso: bool,
+    slow_start_after_idle: bool,
+    frto: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    timestamps: bool,
+    rate_limit: Option<RateLimit>,
 }
 
 impl Default for ConnectionConfig {
     fn default() -> Self {
         Self {
             init_send_seq_number: DEFAULT_ISS,
-            window_size: DEFAULT_WINDOWS_SIZE,
-            send_rtt: time::Duration::from_secs(DEFAULT_RTT),
+            clock_granularity: DEFAULT_CLOCK_GRANULARITY,
             ttl: DEFAULT_TIME_TO_LIVE,
+            min_ttl: 0,
+            tos: 0,
+            recv_buffer_size: DEFAULT_RECV_BUFFER_SIZE,
+            recv_buffer_max: DEFAULT_RECV_BUFFER_MAX,
+            send_buffer_size: DEFAULT_SEND_BUFFER_SIZE,
+            send_buffer_max: DEFAULT_SEND_BUFFER_MAX,
+            recv_low_watermark: 1,
+            send_low_watermark: 1,
+            mss: DEFAULT_MSS,
+            mss_set: false,
+            congestion: CongestionAlgorithm::default(),
+            initial_window: DEFAULT_INITIAL_WINDOW,
+            r1: DEFAULT_R1,
+            r2: DEFAULT_R2,
+            syn_r2: DEFAULT_SYN_R2,
+            synack_retries: DEFAULT_SYNACK_RETRIES,
+            max_dupthresh: DEFAULT_MAX_DUPTHRESH,
+            keep_alive: None,
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            keep_alive_probes: DEFAULT_KEEP_ALIVE_PROBES,
+            user_timeout: None,
+            accept_user_timeout: false,
+            user_timeout_limits: (USER_TIMEOUT_LOWER_LIMIT, USER_TIMEOUT_UPPER_LIMIT),
+            reassembly_limit: DEFAULT_REASSEMBLY_LIMIT,
+            pressure_window: false,
+            challenge_ack_limit: DEFAULT_CHALLENGE_ACK_LIMIT,
+            delayed_ack: None,
+            nagle: false,
+            pacing: false,
+            gso: false,
+            slow_start_after_idle: true,
+            frto: true,
+            timestamps: false,
+            rate_limit: None,
+        }
+    }
+}
+
+impl ConnectionConfig {
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+
+    pub fn min_ttl(&self) -> u8 {
+        self.min_ttl
+    }
+
+    /// packets of the peer arriving with a lower ttl are dropped, like IP_MINTTL. 0 takes any
+    pub fn set_min_ttl(&mut self, ttl: u8) {
+        self.min_ttl = ttl;
+    }
+
+    pub fn gtsm(&self) -> bool {
+        self.ttl == u8::MAX && self.min_ttl == u8::MAX
+    }
+
+    /// the generalized ttl security mechanism (RFC 5082) for a directly connected peer:
+    /// packets leave with a ttl of 255 and only those still having 255 are taken, a
+    /// spoofer further away can't get one through
+    pub fn set_gtsm(&mut self, gtsm: bool) {
+        if gtsm {
+            self.ttl = u8::MAX;
+            self.min_ttl = u8::MAX;
+        } else {
+            self.ttl = DEFAULT_TIME_TO_LIVE;
+            self.min_ttl = 0;
         }
     }
+
+    pub fn tos(&self) -> u8 {
+        self.tos
+    }
+
+    /// type of service byte of outgoing ip headers, DSCP << 2 | ECN
+    pub fn set_tos(&mut self, tos: u8) {
+        self.tos = tos;
+    }
+
+    pub fn dscp(&self) -> Dscp {
+        Dscp::from_tos(self.tos)
+    }
+
+    /// the code point of outgoing ip headers, the ECN bits of the type of service stay
+    pub fn set_dscp(&mut self, dscp: Dscp) {
+        self.tos = dscp.with_ecn_of(self.tos);
+    }
+
+    pub fn recv_buffer_size(&self) -> usize {
+        self.recv_buffer_size
+    }
+
+    /// the advertised window never exceeds 65535 without window scaling. like SO_RCVBUF
+    /// this fixes the size, `set_recv_buffer_max` afterwards lets autotuning grow it again
+    pub fn set_recv_buffer_size(&mut self, size: usize) {
+        self.recv_buffer_size = size;
+        self.recv_buffer_max = size;
+    }
+
+    pub fn recv_buffer_max(&self) -> usize {
+        self.recv_buffer_max
+    }
+
+    /// grow the receive buffer up to `size` with the bandwidth-delay product,
+    /// autotuning is off if it's not above `recv_buffer_size`
+    pub fn set_recv_buffer_max(&mut self, size: usize) {
+        self.recv_buffer_max = size;
+    }
+
+    pub fn send_buffer_size(&self) -> usize {
+        self.send_buffer_size
+    }
+
+    /// like SO_SNDBUF this fixes the size, `set_send_buffer_max` afterwards lets autotuning grow it again
+    pub fn set_send_buffer_size(&mut self, size: usize) {
+        self.send_buffer_size = size;
+        self.send_buffer_max = size;
+    }
+
+    pub fn send_buffer_max(&self) -> usize {
+        self.send_buffer_max
+    }
+
+    /// grow the send buffer up to `size` to twice the congestion window,
+    /// autotuning is off if it's not above `send_buffer_size`
+    pub fn set_send_buffer_max(&mut self, size: usize) {
+        self.send_buffer_max = size;
+    }
+
+    pub fn recv_low_watermark(&self) -> usize {
+        self.recv_low_watermark
+    }
+
+    /// bytes which have to be received before the connection is reported readable, like SO_RCVLOWAT
+    pub fn set_recv_low_watermark(&mut self, bytes: usize) {
+        self.recv_low_watermark = bytes.max(1);
+    }
+
+    pub fn send_low_watermark(&self) -> usize {
+        self.send_low_watermark
+    }
+
+    /// free send buffer space needed before the connection is reported writable, like SO_SNDLOWAT
+    pub fn set_send_low_watermark(&mut self, bytes: usize) {
+        self.send_low_watermark = bytes.max(1);
+    }
+
+    pub fn init_send_seq_number(&self) -> u32 {
+        self.init_send_seq_number
+    }
+
+    pub fn set_init_send_seq_number(&mut self, iss: u32) {
+        self.init_send_seq_number = iss;
+    }
+
+    /// largest segment sent and the one advertised in the MSS option, a stack derives it
+    /// from its mtu unless it is set. the segments sent are no larger than the peer's MSS
+    pub fn mss(&self) -> usize {
+        self.mss
+    }
+
+    pub fn set_mss(&mut self, mss: usize) {
+        self.mss = mss;
+        self.mss_set = true;
+    }
+
+    /// the largest segment a link takes is `limit`: a mss which was set stays below it,
+    /// otherwise it is the limit
+    #[cfg(feature = "std")]
+    pub(crate) fn fit_mss(&mut self, limit: usize) {
+        self.mss = if self.mss_set { self.mss.min(limit) } else { limit };
+    }
+
+    pub fn clock_granularity(&self) -> Duration {
+        self.clock_granularity
+    }
+
+    /// tick of the clock measuring round-trip times, the lower bound of the variance term of the timeout
+    pub fn set_clock_granularity(&mut self, granularity: Duration) {
+        self.clock_granularity = granularity;
+    }
+
+    pub fn congestion(&self) -> CongestionAlgorithm {
+        self.congestion
+    }
+
+    /// algorithm of the connections created from now on
+    pub fn set_congestion(&mut self, congestion: CongestionAlgorithm) {
+        self.congestion = congestion;
+    }
+
+    pub fn initial_window(&self) -> usize {
+        self.initial_window
+    }
+
+    /// full segments the congestion control starts with and restarts with after an idle
+    /// period, 10 by default (RFC 6928), at least 1 for constrained paths. with pacing
+    /// they are spread over the round trip of the handshake rather than sent in a burst
+    pub fn set_initial_window(&mut self, segments: usize) {
+        self.initial_window = segments.max(1);
+    }
+
+    pub fn r1(&self) -> u32 {
+        self.r1
+    }
+
+    /// retransmissions of the same segment after which the application gets a soft error
+    pub fn set_r1(&mut self, retransmissions: u32) {
+        self.r1 = retransmissions;
+    }
+
+    pub fn r2(&self) -> Duration {
+        self.r2
+    }
+
+    /// how long unacknowledged data is retransmitted before the connection times out
+    pub fn set_r2(&mut self, timeout: Duration) {
+        self.r2 = timeout;
+    }
+
+    pub fn syn_r2(&self) -> Duration {
+        self.syn_r2
+    }
+
+    /// how long the handshake may take before the connection times out
+    pub fn set_syn_r2(&mut self, timeout: Duration) {
+        self.syn_r2 = timeout;
+    }
+
+    pub fn synack_retries(&self) -> u32 {
+        self.synack_retries
+    }
+
+    /// SYN,ACK retransmissions after which a passive open which never completed is dropped
+    pub fn set_synack_retries(&mut self, retries: u32) {
+        self.synack_retries = retries;
+    }
+
+    pub fn max_dupthresh(&self) -> u32 {
+        self.max_dupthresh
+    }
+
+    /// the duplicate ACKs a fast retransmit waits for grow from 3 up to `dupthresh` on a path
+    /// which reorders segments, 3 keeps the standard threshold
+    pub fn set_max_dupthresh(&mut self, dupthresh: u32) {
+        self.max_dupthresh = dupthresh.max(DEFAULT_DUPTHRESH);
+    }
+
+    pub fn keep_alive(&self) -> Option<Duration> {
+        self.keep_alive
+    }
+
+    /// probe the peer once the connection was idle for `idle`, like SO_KEEPALIVE with
+    /// TCP_KEEPIDLE. off by default
+    pub fn set_keep_alive(&mut self, idle: Option<Duration>) {
+        self.keep_alive = idle;
+    }
+
+    pub fn keep_alive_interval(&self) -> Duration {
+        self.keep_alive_interval
+    }
+
+    /// time between keep-alive probes while they go unanswered
+    pub fn set_keep_alive_interval(&mut self, interval: Duration) {
+        self.keep_alive_interval = interval;
+    }
+
+    pub fn keep_alive_probes(&self) -> u32 {
+        self.keep_alive_probes
+    }
+
+    /// unanswered keep-alive probes after which the connection times out
+    pub fn set_keep_alive_probes(&mut self, probes: u32) {
+        self.keep_alive_probes = probes.max(1);
+    }
+
+    pub fn user_timeout(&self) -> Option<Duration> {
+        self.user_timeout
+    }
+
+    /// how long data may stay unacknowledged before the connection times out, in place of R2.
+    /// it is advertised to the peer with the user timeout option (RFC 5482) and a timeout
+    /// the peer advertises doesn't change it
+    pub fn set_user_timeout(&mut self, timeout: Option<Duration>) {
+        self.user_timeout = timeout;
+    }
+
+    pub fn accept_user_timeout(&self) -> bool {
+        self.accept_user_timeout
+    }
+
+    /// without a user timeout of our own, take the one the peer advertises within
+    /// `user_timeout_limits`. off by default
+    pub fn set_accept_user_timeout(&mut self, accept: bool) {
+        self.accept_user_timeout = accept;
+    }
+
+    pub fn user_timeout_limits(&self) -> (Duration, Duration) {
+        self.user_timeout_limits
+    }
+
+    /// the shortest and longest user timeout taken from a peer, 100 seconds and
+    /// 32767 minutes by default
+    pub fn set_user_timeout_limits(&mut self, lower: Duration, upper: Duration) {
+        self.user_timeout_limits = (lower, upper.max(lower));
+    }
+
+    pub fn reassembly_limit(&self) -> usize {
+        self.reassembly_limit
+    }
+
+    /// memory for segments received out of order, later ones are dropped until the gap is filled
+    pub fn set_reassembly_limit(&mut self, bytes: usize) {
+        self.reassembly_limit = bytes;
+    }
+
+    pub fn pressure_window(&self) -> bool {
+        self.pressure_window
+    }
+
+    /// while the reassembly memory of the stack runs out, advertise no more window than it has left
+    pub fn set_pressure_window(&mut self, shrink: bool) {
+        self.pressure_window = shrink;
+    }
+
+    pub fn challenge_ack_limit(&self) -> u32 {
+        self.challenge_ack_limit
+    }
+
+    /// challenge ACKs sent per second, those beyond are suppressed
+    pub fn set_challenge_ack_limit(&mut self, per_second: u32) {
+        self.challenge_ack_limit = per_second;
+    }
+
+    pub fn delayed_ack(&self) -> Option<Duration> {
+        self.delayed_ack
+    }
+
+    /// hold the ACK of a single in order segment up to `timeout`, so data written meanwhile
+    /// carries it. every second segment is acknowledged right away, `None` acknowledges each one
+    pub fn set_delayed_ack(&mut self, timeout: Option<Duration>) {
+        self.delayed_ack = timeout;
+    }
+
+    pub fn nagle(&self) -> bool {
+        self.nagle
+    }
+
+    /// hold a small segment back while data is unacknowledged, off like with TCP_NODELAY by default
+    pub fn set_nagle(&mut self, nagle: bool) {
+        self.nagle = nagle;
+    }
+
+    pub fn pacing(&self) -> bool {
+        self.pacing
+    }
+
+    /// spread the segments of a window over the round trip instead of sending them in a burst
+    pub fn set_pacing(&mut self, pacing: bool) {
+        self.pacing = pacing;
+    }
+
+    pub fn gso(&self) -> bool {
+        self.gso
+    }
+
+    /// hand the device segments of up to 64KB with the headers built once, a device
+    /// without TSO has the data link layer cut them at the mss (GSO). off by default
+    pub fn set_gso(&mut self, gso: bool) {
+        self.gso = gso;
+    }
+
+    pub fn slow_start_after_idle(&self) -> bool {
+        self.slow_start_after_idle
+    }
+
+    /// let the congestion control decay the window after the connection sent nothing for
+    /// longer than the retransmission timeout, instead of bursting it into a path which may
+    /// have changed (RFC 7661), on by default
+    pub fn set_slow_start_after_idle(&mut self, restart: bool) {
+        self.slow_start_after_idle = restart;
+    }
+
+    pub fn frto(&self) -> bool {
+        self.frto
+    }
+
+    /// tell spurious retransmission timeouts from the ACKs following them with F-RTO
+    /// and undo the reduction of the congestion window, on by default. connections with
+    /// timestamps use Eifel instead
+    pub fn set_frto(&mut self, frto: bool) {
+        self.frto = frto;
+    }
+
+    pub fn timestamps(&self) -> bool {
+        self.timestamps
+    }
+
+    /// send the timestamps option (RFC 7323) when the peer does too. the first ACK after a
+    /// retransmission timeout then tells if it was spurious (Eifel, RFC 3522) instead of F-RTO,
+    /// off by default, the option takes 12 bytes of every segment
+    pub fn set_timestamps(&mut self, timestamps: bool) {
+        self.timestamps = timestamps;
+    }
+
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit
+    }
+
+    /// cap the bytes per second the connection sends, retransmissions included, none by default
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.rate_limit = limit;
+    }
+}
+
+/// A retransmission timeout which may be spurious, how that is found out and the
+/// congestion control and round-trip estimator from before the timeout to undo it
+#[derive(Debug, Clone)]
+struct SpuriousTimeout {
+    detection: Detection,
+    congestion: Box<dyn CongestionControl>,
+    rtt: RttEstimator,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Detection {
+    /// Eifel (RFC 3522): the ACK of the original segment echoes an older timestamp than
+    /// `retransmitted`, the one the retransmission carried
+    Eifel { retransmitted: u32 },
+    /// progress of F-RTO (RFC 5682), for peers without timestamps
+    Frto(FrtoStage),
+}
+
+/// A fast retransmit waiting for the ACK which tells if the segment was lost or only
+/// reordered, with the congestion control from before to undo it
+#[derive(Debug, Clone)]
+struct FastRetransmit {
+    sent: Instant,
+    /// duplicate ACKs received before the ACK advanced
+    dup_acks: u32,
+    congestion: Box<dyn CongestionControl>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum FrtoStage {
+    /// the first unacknowledged segment was sent again, waiting for the first ACK
+    Retransmitted,
+    /// new segments were sent up to `window` bytes in flight instead of retransmissions,
+    /// the next ACK tells if the timeout was spurious
+    NewData { window: usize },
 }
 
 #[derive(Clone)]
 pub struct TcpConnection {
+    /// local and remote address
+    quad: Quad,
     /// Tcp connection state
     state: TcpState,
-    /// Wait `timeout` seconds, if no inbound packets are received, the connection is aborted.
-    timeout: Option<Duration>,
-    /// Wait `keep_alive` seconds, the keep alive packets will be sent
-    keep_alive: Option<Duration>,
+    /// the user timeout the peer advertised, within the limits of the config (RFC 5482)
+    peer_user_timeout: Option<Duration>,
+    /// the MSS option of our SYN, `config.mss` shrinks to the one of the peer
+    advertised_mss: usize,
+    /// type of service byte of the last packet of the peer
+    received_tos: Option<u8>,
+    /// when the last segment of the peer arrived, keep-alive probes start after it
+    last_heard: Option<Instant>,
+    /// keep-alive probes sent since then and when the last one was
+    keep_alive_probes: u32,
+    last_probe: Option<Instant>,
+    /// when data last left, new or retransmitted, the window is idle after it
+    last_sent: Option<Instant>,
     /// Send Sequence Variables
     send_seq: SendSequenceSpace,
     /// Receive Sequence Variables
     recv_seq: ReceiveSequenceSpace,
-    // pub(crate) incoming: ArrayQueue<u8>,
-    // pub(crate) wait_ack: ArrayQueue<u8>,
+    config: ConnectionConfig,
+    /// bytes received in order and not read by the application yet
+    incoming: RingBuffer,
+    recv_autotune: RecvAutotune,
+    /// send buffer size autotuning found, 0 until the first ACK
+    send_buffer_tuned: usize,
+    /// segments received ahead of rcv.nxt
+    reassembly: ReassemblyQueue,
+    /// bytes written by the application starting at snd.una,
+    /// the first ones are in flight and the rest is not sent yet
+    outgoing: RingBuffer,
+    /// created by a passive open, a reset in SYN-RECEIVED just deletes the TCB
+    passive: bool,
+    /// our SYN (or SYN,ACK) has to be sent by the next transmit
+    syn_pending: bool,
+    /// an ACK has to be sent by the next transmit
+    ack_pending: bool,
+    /// an ACK is owed and sent at this point unless a segment carries it before
+    ack_deadline: Option<Instant>,
+    /// in order segments received since the last ACK
+    segments_unacked: u32,
+    /// the application closed the connection, a FIN follows the last queued byte
+    fin_pending: bool,
+    /// our FIN has been sent, it occupies the sequence number snd.nxt - 1
+    fin_sent: bool,
+    /// the peer sent FIN, reads return end of file once `incoming` is drained
+    peer_fin: bool,
+    /// text and FIN which came with the SYN of the peer, processed once the
+    /// connection is established (RFC 793 3.9, LISTEN and SYN-SENT)
+    syn_text: Option<(Vec<u8>, bool)>,
+    /// the connection was reset by the peer
+    reset: bool,
+    /// the application aborted the connection, a RST is sent by the next transmit
+    rst_pending: bool,
+    /// the local address was removed from the stack and the connection aborted
+    addr_removed: bool,
+    /// the retransmissions went unanswered for R2, or the handshake for the SYN R2
+    timed_out: bool,
+    /// R1 retransmissions of the same segment went unanswered, cleared when taken or acknowledged
+    soft_error: bool,
+    /// when the retransmission timer fires
+    rto_deadline: Option<Instant>,
+    /// the window of the peer is closed with data waiting, it is probed at this point
+    persist_deadline: Option<Instant>,
+    /// window probes since the window closed, each one waits twice as long
+    window_probes: u32,
+    /// the handshake times out at this point
+    handshake_deadline: Option<Instant>,
+    /// timer expirations since the last acknowledgment and when the first one happened
+    retransmissions: u32,
+    retransmitting_since: Option<Instant>,
+    /// snd.max when the timer expired, ACKs below it retransmit the next segment right away
+    recovery_point: Option<u32>,
+    /// the last timeout may be spurious, the next ACKs tell
+    spurious_timeout: Option<SpuriousTimeout>,
+    /// TS.Recent, the timestamp of the peer echoed while both ends send the option
+    ts_recent: Option<u32>,
+    /// what the timestamps sent count the milliseconds from
+    ts_base: Option<Instant>,
+    /// duplicate ACKs in a row
+    dup_acks: u32,
+    /// duplicate ACKs which trigger a fast retransmit, above 3 once reordering was seen
+    dupthresh: u32,
+    /// the last fast retransmit may be spurious, the next ACK tells
+    fast_retransmit: Option<FastRetransmit>,
+    congestion: Box<dyn CongestionControl>,
+    scheduler: SendScheduler,
+    /// when the pacing or a rate limit lets the next segment leave, while one is waiting
+    pacing_deadline: Option<Instant>,
+    rtt: RttEstimator,
+    /// end of the segment timed for a round-trip sample and when it was sent
+    rtt_probe: Option<(u32, Instant)>,
+    /// end of the highest sequence number sent, what ends before it is a retransmission
+    snd_max: u32,
+    /// largest window the peer advertised, bounds how old an acceptable ACK may be (RFC 5961 5.2)
+    max_snd_wnd: u16,
+    /// start of the current second of challenge ACKs and how many were sent in it
+    challenge_acks: Option<(Instant, u32)>,
+    stats: ConnectionStats,
+    /// parent of the events of this connection
+    span: Span,
+    clock: Arc<dyn Clock>,
+    /// observers of the stack the connection belongs to
+    #[cfg(feature = "std")]
+    observers: Option<Arc<Observers>>,
+    /// the TCP-AO keys, every segment is signed and only authentic ones are accepted
+    #[cfg(feature = "tcp-ao")]
+    auth: Option<Authenticator>,
+    /// the connection carries a subflow of an MPTCP connection, which queues the data
+    #[cfg(feature = "mptcp")]
+    subflow: Option<Subflow>,
 }
 
 
@@ -105,136 +757,1971 @@ pub struct TcpConnection {
 //      ------------------------>|TIME WAIT|------------------>| CLOSED  |
 //                               +---------+                   +---------+
 impl TcpConnection {
-    fn create() -> Self {
+    fn create(quad: Quad, config: ConnectionConfig) -> Self {
         Self {
+            quad,
             state: TcpState::Closed,
-            timeout: None,
-            keep_alive: None,
+            peer_user_timeout: None,
+            advertised_mss: config.mss,
+            received_tos: None,
+            last_heard: None,
+            keep_alive_probes: 0,
+            last_probe: None,
+            last_sent: None,
             send_seq: SendSequenceSpace::default(),
             recv_seq: ReceiveSequenceSpace::default(),
-            // incoming: ArrayQueue::new(TCP_DEFAULT_HANDLE_BUF_SIZE),
-            // wait_ack: ArrayQueue::new(TCP_DEFAULT_HANDLE_BUF_SIZE),
-        }
-    }
-
-    pub fn connect<L: DataLayer>(iface: &mut L, ip: IpAddr, port: u16) -> result::Result<TcpConnection> {
-        // how to get local addr and free port?
-        let src_addr = Ipv4Addr::new(192, 168, 1, 1);
-        let source_port = 54466_u16;
-        let mut conn = TcpConnection::create();
-
-        let tcp_header = TcpHeader::new(
-            source_port,
-            port,
-            DEFAULT_ISS,
-            DEFAULT_WINDOWS_SIZE,
-        );
-
-        let ip_header = match ip {
-            IpAddr::V4(addr) => {
-                Ipv4Header::new(
-                    tcp_header.header_len(),
-                    DEFAULT_TIME_TO_LIVE,
-                    etherparse::IpTrafficClass::IPv4,
-                    src_addr.octets(),
-                    addr.octets(),
-                )
-            }
-            IpAddr::V6(_) => {
-                // not support right now
-                unimplemented!()
-            }
-        };
-
-        let mut packet = TcpIpHeader::from_tcpip_header(ip_header, tcp_header);
-        packet.snd_syn();
+            config,
+            incoming: RingBuffer::new(),
+            recv_autotune: RecvAutotune::new(),
+            send_buffer_tuned: 0,
+            reassembly: ReassemblyQueue::new(config.reassembly_limit, ReassemblyBudget::unlimited()),
+            outgoing: RingBuffer::new(),
+            passive: false,
+            syn_pending: false,
+            ack_pending: false,
+            ack_deadline: None,
+            segments_unacked: 0,
+            fin_pending: false,
+            fin_sent: false,
+            peer_fin: false,
+            syn_text: None,
+            reset: false,
+            rst_pending: false,
+            timed_out: false,
+            addr_removed: false,
+            soft_error: false,
+            rto_deadline: None,
+            persist_deadline: None,
+            window_probes: 0,
+            handshake_deadline: None,
+            retransmissions: 0,
+            retransmitting_since: None,
+            recovery_point: None,
+            spurious_timeout: None,
+            ts_recent: None,
+            ts_base: None,
+            dup_acks: 0,
+            dupthresh: DEFAULT_DUPTHRESH,
+            fast_retransmit: None,
+            congestion: config.congestion.build(config.mss, config.initial_window),
+            scheduler: SendScheduler::new(),
+            pacing_deadline: None,
+            rtt: RttEstimator::new(config.clock_granularity),
+            rtt_probe: None,
+            snd_max: 0,
+            max_snd_wnd: 0,
+            challenge_acks: None,
+            stats: ConnectionStats::default(),
+            clock: default_clock(),
+            #[cfg(feature = "std")]
+            observers: None,
+            #[cfg(feature = "tcp-ao")]
+            auth: None,
+            #[cfg(feature = "mptcp")]
+            subflow: None,
+            span: debug_span!("tcp", local = %quad.src(), remote = %quad.dest()),
+        }
+    }
 
-        let mut raw = RawWriter::new(0);
-        raw.write_header(&packet)?;
-        iface.send(raw.buffer())?;
+    /// active open, the SYN is sent by the next `transmit`
+    pub fn open(local: Addr, remote: Addr, config: ConnectionConfig) -> Self {
+        let mut conn = TcpConnection::create(Quad::new(local, remote), config);
+        conn.send_seq = SendSequenceSpace::from_seq_number(config.init_send_seq_number, 0);
+        conn.snd_max = config.init_send_seq_number;
+        conn.recv_seq.wnd = conn.recv_window();
+        conn.syn_pending = true;
+        conn.handshake_deadline = Some(conn.clock.now() + config.syn_r2);
         conn.set_state(TcpState::SynSent);
+        conn
+    }
+
+    /// active open sending the SYN right away
+    pub fn connect<L: DataLayer + ?Sized>(
+        iface: &mut L,
+        local: Addr,
+        remote: Addr,
+        config: ConnectionConfig,
+    ) -> result::Result<TcpConnection> {
+        let mut conn = TcpConnection::open(local, remote, config);
+        conn.transmit(iface)?;
         Ok(conn)
     }
 
-    fn from_recv_sequence(seq_number: u32, wnd: u16) -> Self {
-        Self {
-            state: TcpState::Closed,
-            timeout: None,
-            keep_alive: None,
-            send_seq: SendSequenceSpace::default(),
-            recv_seq: ReceiveSequenceSpace::from_seq_number(seq_number, wnd),
-            // incoming: ArrayQueue::new(TCP_DEFAULT_HANDLE_BUF_SIZE),
-            // wait_ack: ArrayQueue::new(TCP_DEFAULT_HANDLE_BUF_SIZE),
+    fn set_state(&mut self, state: TcpState) {
+        let from = self.state;
+        self.state = state;
+        if from == state {
+            return;
+        }
+        debug!(parent: &self.span, %from, to = %state, "state transition");
+        self.observe(|observer, quad| observer.on_state_change(quad, from, state));
+        match state {
+            TcpState::Established => self.observe(|observer, quad| observer.on_established(quad)),
+            TcpState::Closed if self.reset => self.observe(|observer, quad| observer.on_reset(quad)),
+            _ => {}
+        }
+        #[cfg(feature = "mptcp")]
+        if state == TcpState::Closed {
+            self.detach_subflow(from);
         }
     }
 
-    fn set_state(&mut self, state: TcpState) {
-        self.state = state
+    #[cfg(feature = "std")]
+    fn observe<F: Fn(&dyn ConnectionObserver, &Quad)>(&self, event: F) {
+        if let Some(observers) = &self.observers {
+            observers.notify(|observer| event(observer, &self.quad));
+        }
+    }
+
+    /// only the connections of a stack are observed
+    #[cfg(not(feature = "std"))]
+    fn observe<F: Fn(&dyn ConnectionObserver, &Quad)>(&self, _event: F) {}
+
+    /// the verdict of the observers, the default without any
+    #[cfg(feature = "std")]
+    fn decide<T: Ord + Default, F: Fn(&dyn ConnectionObserver, &Quad) -> T>(&self, question: F) -> T {
+        match &self.observers {
+            Some(observers) => observers.decide(|observer| question(observer, &self.quad)),
+            None => T::default(),
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn decide<T: Ord + Default, F: Fn(&dyn ConnectionObserver, &Quad) -> T>(&self, _question: F) -> T {
+        T::default()
+    }
+
+    pub fn state(&self) -> TcpState {
+        self.state
+    }
+
+    /// local address as source, remote address as destination
+    pub fn quad(&self) -> Quad {
+        self.quad
     }
 
+    /// close the sending side, the FIN is sent after the data already queued
     pub fn close(&mut self) {
-        self.state = TcpState::Closed
+        match self.state {
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent => self.set_state(TcpState::Closed),
+            _ => self.fin_pending = true,
+        }
     }
 
-    /// handle the first handshake
-    pub fn accept<'a, L: DataLayer>(
-        iface: &mut L,
-        ip: &'a etherparse::Ipv4HeaderSlice<'a>,
-        tcp: &'a etherparse::TcpHeaderSlice<'a>,
-        data: &'a [u8],
-    ) -> result::Result<Option<Self>> {
-        debug!("[{:?}:{}] -> [{:?}:{}] SYN: {}, SEQ:{} ,ACK_NUM: {} ACK:{}",
-               ip.source_addr(), tcp.source_port(),
-               ip.destination_addr(), tcp.destination_port(),
-               tcp.syn(),
-               tcp.sequence_number(),
-               tcp.acknowledgment_number()
-        );
-        // the first packet SYN flag must be set
-        if !tcp.syn() {
-            return Ok(None);
+    /// drop the queued data and reset the connection
+    pub fn abort(&mut self) {
+        self.incoming.clear();
+        self.reassembly.clear();
+        self.outgoing.clear();
+        if self.is_synchronized() || self.state == TcpState::SynReceived {
+            self.rst_pending = true;
+        } else {
+            self.set_state(TcpState::Closed);
         }
-        // we create the new connection cause it's first handshake
-        // and change send sequence number(nxt)
-        let mut conn = TcpConnection::from_recv_sequence(
-            tcp.sequence_number(),
-            tcp.window_size(),
-        );
-        // we just crate connection, now state is listen
-        // when we send response packet then state will change to SynRecv
-        conn.set_state(TcpState::Listen);
+    }
 
-        let mut handshake_packet = TcpIpHeader::with_rcv_tcpip_header(tcp, ip);
-        let mut writer = RawWriter::with_default_offset();
-
-        handshake(&mut conn, &mut handshake_packet, &mut writer)?;
-        debug!("[{:?}:{}] <- [{:?}:{}] SYN:{} SEQ:{} ACK_NUM:{},ACK:{}",
-               ip.destination_addr(), tcp.destination_port(),
-               ip.source_addr(), tcp.source_port(),
-               handshake_packet.tcp_header.syn,
-               handshake_packet.tcp_header.sequence_number,
-               handshake_packet.tcp_header.acknowledgment_number,
-               handshake_packet.tcp_header.ack
-        );
-        iface.send(&writer.buffer())?;
-        conn.set_state(TcpState::SynReceived);
-        Ok(Some(conn))
+    /// the local address changed to `ip`, segments leave from it from now on
+    pub fn migrate(&mut self, ip: IpAddr) {
+        debug!(parent: &self.span, from = %self.quad.src().ip(), to = %ip, "local address changed");
+        self.quad = Quad::new(Addr::new(ip, self.quad.src().port()), self.quad.dest());
     }
-}
 
-////         send SYN c_seq=x
-/// Client ------------------------------------> Server
-///          send SYN,ACK,s_seq=y,ack=x+1
-/// Client <----------------------------------- Server
-///          send ACK,ack=y+1,c_seq=x+1
-/// Client -----------------------------------> Server
-fn handshake(conn: &mut TcpConnection, handshake_packet: &mut TcpIpHeader, writer: &mut RawWriter) -> result::Result<()> {
-    // we have to set SYN and ACK flags
-    handshake_packet.handshake_resp();
-    handshake_packet.update_seq_number(&conn.send_seq, &conn.recv_seq);
-    // etherparse will calc checksum so we do need this step
-    // let checksum = handshake_packet.check_sum(&[])?;
-    // handshake_packet.tcp_header.checksum = checksum;
-    writer.write_header(handshake_packet)?;
-    Ok(())
+    /// the local address is gone, abort with a RST the peer gets if it's still reachable
+    pub fn on_addr_removed(&mut self) {
+        self.addr_removed = true;
+        self.abort();
+    }
+
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    /// buffer sizes apply to data queued from now on, ttl and tos to the next segment
+    pub fn config_mut(&mut self) -> &mut ConnectionConfig {
+        &mut self.config
+    }
+
+    /// time source of the rtt samples and the congestion control, the system clock by default
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+        // timed on the previous clock, the running timers start over
+        self.rtt_probe = None;
+        let now = self.clock.now();
+        if self.rto_deadline.is_some() {
+            self.rto_deadline = Some(now + self.rtt.rto());
+        }
+        if self.handshake_deadline.is_some() {
+            self.handshake_deadline = Some(now + self.config.syn_r2);
+        }
+        if self.persist_deadline.is_some() {
+            self.persist_deadline = Some(now + self.persist_interval());
+        }
+        if self.last_heard.is_some() {
+            self.last_heard = Some(now);
+        }
+        if self.last_sent.is_some() {
+            self.last_sent = Some(now);
+        }
+        self.last_probe = None;
+    }
+
+    /// report the events of the connection to `observers`, the stack sets them once the
+    /// connection is in SYN-SENT or SYN-RECEIVED, the first transition reported leaves it
+    #[cfg(feature = "std")]
+    pub(crate) fn set_observers(&mut self, observers: Arc<Observers>) {
+        self.observers = Some(observers);
+    }
+
+    /// rate limit shared with the other connections of the stack
+    #[cfg(feature = "std")]
+    pub fn set_shaper(&mut self, shaper: Option<Shaper>) {
+        self.scheduler.set_shaper(shaper);
+    }
+
+    /// sign the segments with TCP-AO and drop those of the peer without a valid MAC,
+    /// set before the SYN is sent
+    #[cfg(feature = "tcp-ao")]
+    pub fn set_authenticator(&mut self, auth: Option<Authenticator>) {
+        self.auth = auth;
+    }
+
+    #[cfg(feature = "tcp-ao")]
+    pub fn authenticator(&self) -> Option<&Authenticator> {
+        self.auth.as_ref()
+    }
+
+    /// e.g. to add keys and rotate them
+    #[cfg(feature = "tcp-ao")]
+    pub fn authenticator_mut(&mut self) -> Option<&mut Authenticator> {
+        self.auth.as_mut()
+    }
+
+    /// carry a subflow of an MPTCP connection, set before the SYN is sent
+    #[cfg(feature = "mptcp")]
+    pub fn set_subflow(&mut self, mut subflow: Subflow) {
+        subflow.attach(self.quad, self.send_seq.iss, self.recv_seq.irs);
+        self.subflow = Some(subflow);
+    }
+
+    #[cfg(feature = "mptcp")]
+    pub fn subflow(&self) -> Option<&Subflow> {
+        self.subflow.as_ref()
+    }
+
+    /// memory shared with the other connections for segments received out of order
+    pub fn set_reassembly_budget(&mut self, budget: ReassemblyBudget) {
+        self.reassembly.set_budget(budget);
+    }
+
+    /// segments received ahead of rcv.nxt
+    pub fn reassembly(&self) -> &ReassemblyQueue {
+        &self.reassembly
+    }
+
+    /// span of the events of this connection, keyed by the local and remote address
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// counters with the current congestion window
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            cwnd: self.congestion.cwnd(),
+            ssthresh: self.congestion.ssthresh(),
+            srtt: self.rtt.srtt(),
+            recv_buffer: self.recv_buffer_size(),
+            send_buffer: self.send_buffer_size(),
+            pacing_rate: self.pacing_rate().filter(|_| self.config.pacing),
+            ..self.stats
+        }
+    }
+
+    /// the connection written down to be restored later, maybe by another process.
+    /// `None` unless it's synchronized and not in TIME-WAIT or being reset
+    #[cfg(feature = "serde")]
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        if !self.is_synchronized() || self.state == TcpState::TimeWait || self.reset || self.rst_pending {
+            return None;
+        }
+        let now = self.clock.now();
+        let left = |deadline: Option<Instant>| deadline.map(|deadline| deadline.saturating_duration_since(now));
+        let bytes = |ring: &RingBuffer| {
+            let (first, second) = ring.slices(0);
+            [first, second].concat()
+        };
+        let mut config = self.config;
+        config.recv_buffer_size = self.recv_buffer_size();
+        config.send_buffer_size = self.send_buffer_size();
+        Some(Checkpoint {
+            quad: self.quad,
+            state: self.state,
+            send_seq: self.send_seq,
+            recv_seq: self.recv_seq,
+            config,
+            passive: self.passive,
+            incoming: bytes(&self.incoming),
+            outgoing: bytes(&self.outgoing),
+            reassembly: self.reassembly.iter().map(|(seq, data)| (seq, data.to_vec())).collect(),
+            reassembly_fin: self.reassembly.fin(),
+            fin_pending: self.fin_pending,
+            fin_sent: self.fin_sent,
+            peer_fin: self.peer_fin,
+            snd_max: self.snd_max,
+            max_snd_wnd: self.max_snd_wnd,
+            peer_user_timeout: self.peer_user_timeout,
+            ts_recent: self.ts_recent,
+            ts_clock: self.ts_base.map(|base| now.saturating_duration_since(base)),
+            rtt: self.rtt,
+            stats: self.stats,
+            timers: Timers {
+                retransmit: left(self.rto_deadline),
+                ack: left(self.ack_deadline),
+                retransmissions: self.retransmissions,
+                retransmitting_for: self.retransmitting_since.map(|since| now.saturating_duration_since(since)),
+            },
+        })
+    }
+
+    /// the connection `checkpoint` was taken of, its timers restart on `clock`. an ACK
+    /// goes out with the next `transmit` and tells the peer the window
+    #[cfg(feature = "serde")]
+    pub fn restore(checkpoint: Checkpoint, clock: Arc<dyn Clock>) -> Self {
+        let mut conn = TcpConnection::create(checkpoint.quad, checkpoint.config);
+        let now = clock.now();
+        conn.clock = clock;
+        conn.state = checkpoint.state;
+        conn.send_seq = checkpoint.send_seq;
+        conn.recv_seq = checkpoint.recv_seq;
+        conn.passive = checkpoint.passive;
+        conn.incoming.push(&checkpoint.incoming);
+        conn.outgoing.push(&checkpoint.outgoing);
+        for (seq, data) in &checkpoint.reassembly {
+            conn.reassembly.insert(*seq, data);
+        }
+        if let Some(fin) = checkpoint.reassembly_fin {
+            conn.reassembly.set_fin(fin);
+        }
+        conn.fin_pending = checkpoint.fin_pending;
+        conn.fin_sent = checkpoint.fin_sent;
+        conn.peer_fin = checkpoint.peer_fin;
+        conn.snd_max = checkpoint.snd_max;
+        conn.max_snd_wnd = checkpoint.max_snd_wnd;
+        conn.peer_user_timeout = checkpoint.peer_user_timeout;
+        conn.ts_recent = checkpoint.ts_recent;
+        conn.ts_base = checkpoint.ts_clock.and_then(|elapsed| now.checked_sub(elapsed));
+        // idle from now on as far as keep-alive goes
+        conn.last_heard = Some(now);
+        conn.rtt = checkpoint.rtt;
+        conn.stats = checkpoint.stats;
+        conn.dupthresh = (conn.stats.reordering + 1).clamp(DEFAULT_DUPTHRESH, conn.config.max_dupthresh);
+        let timers = checkpoint.timers;
+        conn.rto_deadline = timers.retransmit.map(|left| now + left);
+        conn.ack_deadline = timers.ack.map(|left| now + left);
+        conn.retransmissions = timers.retransmissions;
+        conn.retransmitting_since = timers.retransmitting_for.and_then(|elapsed| now.checked_sub(elapsed));
+        conn.ack_pending = true;
+        debug!(parent: &conn.span, state = %conn.state, "restored");
+        conn
+    }
+
+    /// round-trip time estimate and retransmission timeout
+    pub fn rtt(&self) -> &RttEstimator {
+        &self.rtt
+    }
+
+    /// snd.una, snd.nxt and the send window
+    pub fn send_sequence(&self) -> SendSequenceSpace {
+        self.send_seq
+    }
+
+    /// rcv.nxt and the receive window
+    pub fn recv_sequence(&self) -> ReceiveSequenceSpace {
+        self.recv_seq
+    }
+
+    /// an ACK, e.g. a window update, waits for the next transmit
+    pub fn has_pending_ack(&self) -> bool {
+        self.ack_pending
+    }
+
+    /// 2 MSL elapsed in TIME-WAIT
+    pub fn expire_time_wait(&mut self) {
+        if self.state == TcpState::TimeWait {
+            self.observe(|observer, quad| observer.on_timer(quad, TimerKind::TimeWait));
+            self.set_state(TcpState::Closed);
+        }
+    }
+
+    /// the peer reset the connection
+    pub fn is_reset(&self) -> bool {
+        self.reset
+    }
+
+    /// the retransmissions or the handshake took too long and the connection was aborted
+    pub fn is_timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// the connection was aborted because its local address was removed
+    pub fn is_addr_removed(&self) -> bool {
+        self.addr_removed
+    }
+
+    /// R1 retransmissions went unanswered since the last call, the connection goes on
+    pub fn take_soft_error(&mut self) -> bool {
+        core::mem::take(&mut self.soft_error)
+    }
+
+    /// when `on_timeout` has something to do
+    pub fn next_timeout(&self) -> Option<Instant> {
+        if self.state == TcpState::Closed {
+            return None;
+        }
+        let handshake = self.handshake_deadline.filter(|_| !self.is_synchronized());
+        [self.rto_deadline, handshake, self.ack_deadline, self.pacing_deadline, self.persist_deadline, self.keep_alive_deadline()]
+            .iter().flatten().min().copied()
+    }
+
+    /// when the closed window of the peer is probed next
+    pub fn persist_deadline(&self) -> Option<Instant> {
+        self.persist_deadline
+    }
+
+    /// when the next keep-alive probe is due: the connection is idle with keep-alive on
+    /// and nothing in flight (RFC 1122 4.2.3.6)
+    pub fn keep_alive_deadline(&self) -> Option<Instant> {
+        let idle = self.config.keep_alive?;
+        if !matches!(self.state, TcpState::Established | TcpState::CloseWait) || self.send_seq.in_flight() > 0 {
+            return None;
+        }
+        match self.last_probe {
+            Some(probe) => Some(probe + self.config.keep_alive_interval),
+            None => self.last_heard.map(|heard| heard + idle),
+        }
+    }
+
+    /// probe the idle peer with an ACK of an old sequence number, which it answers.
+    /// the connection times out once the probes went unanswered, unless the observers
+    /// decide otherwise
+    fn keep_alive<L: DataLayer + ?Sized>(&mut self, iface: &mut L) -> result::Result<()> {
+        let verdict = match self.keep_alive_probes {
+            0 => KeepAliveVerdict::Default,
+            unanswered => {
+                let last_rtt = self.stats.keep_alive_rtt;
+                self.decide(|observer, quad| observer.on_keep_alive_unanswered(quad, unanswered, last_rtt))
+            }
+        };
+        let exhausted = self.keep_alive_probes >= self.config.keep_alive_probes;
+        if verdict == KeepAliveVerdict::Abort || (exhausted && verdict == KeepAliveVerdict::Default) {
+            warn!(parent: &self.span, probes = self.keep_alive_probes, ?verdict, "keep-alive unanswered");
+            return self.time_out(iface);
+        }
+        self.observe(|observer, quad| observer.on_timer(quad, TimerKind::KeepAlive));
+        self.stats.keep_alive_probes += 1;
+        self.keep_alive_probes += 1;
+        self.last_probe = Some(self.clock.now());
+        trace!(parent: &self.span, probes = self.keep_alive_probes, "keep-alive probe");
+        self.emit(iface, self.send_seq.nxt.wrapping_sub(1), &[TcpControl::ACK], 0..0)
+    }
+
+    /// handle the timers which expired. when the delayed ACK or paced data is due, it is sent
+    /// with the data queued meanwhile. when the persist timer expired, the closed window of the
+    /// peer is probed. when the handshake deadline passed, the connection times out. when the
+    /// keep-alive timer expired, the idle peer is probed. when the retransmission timer expired,
+    /// the first unacknowledged segment is sent again with the timeout doubled (RFC 6298
+    /// 5.4-5.6), or the connection is aborted once R2 elapsed
+    pub fn on_timeout<L: DataLayer + ?Sized>(&mut self, iface: &mut L) -> result::Result<()> {
+        let now = self.clock.now();
+        let expired = |deadline: Option<Instant>| deadline.is_some_and(|deadline| deadline <= now);
+        if expired(self.ack_deadline) || expired(self.pacing_deadline) {
+            if expired(self.ack_deadline) {
+                self.ack_deadline = None;
+                self.ack_pending = true;
+            }
+            self.pacing_deadline = None;
+            self.transmit(iface)?;
+        }
+        if expired(self.persist_deadline) {
+            self.persist_deadline = None;
+            self.observe(|observer, quad| observer.on_timer(quad, TimerKind::WindowProbe));
+            self.scheduler.queue(Transmission::WindowProbe);
+            self.transmit(iface)?;
+        }
+        if !self.is_synchronized() && expired(self.handshake_deadline) {
+            debug!(parent: &self.span, "handshake timed out");
+            return self.time_out(iface);
+        }
+        if expired(self.keep_alive_deadline()) {
+            return self.keep_alive(iface);
+        }
+        if !expired(self.rto_deadline) {
+            return Ok(());
+        }
+        self.observe(|observer, quad| observer.on_timer(quad, TimerKind::Retransmit));
+        if matches!(self.state, TcpState::SynSent | TcpState::SynReceived) && self.send_seq.una == self.send_seq.iss {
+            return self.retransmit_handshake(iface);
+        }
+        let since = *self.retransmitting_since.get_or_insert(now);
+        if now.saturating_duration_since(since) >= self.user_timeout() {
+            warn!(parent: &self.span, retransmissions = self.retransmissions, "retransmission timed out");
+            return self.time_out(iface);
+        }
+        self.retransmissions += 1;
+        if self.retransmissions == self.config.r1 {
+            debug!(parent: &self.span, retransmissions = self.retransmissions, "retransmissions unanswered");
+            self.soft_error = true;
+        }
+        self.stats.timeouts += 1;
+        self.dup_acks = 0;
+        self.fast_retransmit = None;
+        // only the first timeout of a segment can be told from a delay spike
+        let detection = match self.ts_recent {
+            Some(_) => Some(Detection::Eifel { retransmitted: self.ts_now() }),
+            None => self.config.frto.then_some(Detection::Frto(FrtoStage::Retransmitted)),
+        };
+        self.spurious_timeout = detection.filter(|_| self.retransmissions == 1 && self.recovery_point.is_none())
+            .map(|detection| SpuriousTimeout { detection, congestion: self.congestion.clone(), rtt: self.rtt });
+        self.rtt.backoff();
+        self.congestion.on_timeout(self.data_in_flight(), now);
+        self.recovery_point = Some(self.snd_max);
+        debug!(parent: &self.span, una = self.send_seq.una, rto = ?self.rtt.rto(), "retransmission timeout");
+        self.rto_deadline = None;
+        self.scheduler.queue(Transmission::Retransmit);
+        self.transmit(iface)
+    }
+
+    /// our SYN or SYN,ACK went unanswered, send it again after 1s, 2s, 4s...
+    /// until the handshake deadline, or the SYN,ACK retries for a passive open
+    fn retransmit_handshake<L: DataLayer + ?Sized>(&mut self, iface: &mut L) -> result::Result<()> {
+        self.retransmissions += 1;
+        if self.passive && self.retransmissions > self.config.synack_retries {
+            debug!(parent: &self.span, retransmissions = self.retransmissions, "embryonic connection dropped");
+            return self.time_out(iface);
+        }
+        self.rtt.backoff();
+        debug!(parent: &self.span, rto = ?self.rtt.rto(), "handshake retransmission");
+        self.rto_deadline = None;
+        handshake(self, iface)
+    }
+
+    /// the handshake completed: the timer stops and if it was retransmitted without
+    /// a sample, data starts with a fresh timeout (RFC 6298 5.7)
+    fn on_handshake_complete(&mut self) {
+        if self.retransmissions > 0 {
+            self.rtt.restart();
+        }
+        self.retransmissions = 0;
+        self.rto_deadline = None;
+    }
+
+    /// abort without waiting for the peer, which may still be there
+    fn time_out<L: DataLayer + ?Sized>(&mut self, iface: &mut L) -> result::Result<()> {
+        self.timed_out = true;
+        self.rto_deadline = None;
+        self.incoming.clear();
+        self.outgoing.clear();
+        // the peer of an embryonic connection never answered
+        if self.is_synchronized() {
+            self.emit(iface, self.send_seq.nxt, &[TcpControl::RST], 0..0)?;
+        }
+        self.set_state(TcpState::Closed);
+        Ok(())
+    }
+
+    /// send the first segment which wasn't acknowledged again, the timer restarts
+    fn retransmit<L: DataLayer + ?Sized>(&mut self, iface: &mut L) -> result::Result<()> {
+        let in_flight = self.data_in_flight();
+        if in_flight > 0 {
+            let len = in_flight.min(self.full_segment());
+            #[cfg(feature = "mptcp")]
+            let len = self.mapped_len(self.send_seq.una, len);
+            // the FIN rode on the last data segment, it goes again with it
+            let fin = self.fin_sent && len == in_flight && self.send_seq.in_flight() as usize > in_flight;
+            self.emit(iface, self.send_seq.una, data_controls(fin), 0..len)
+        } else if self.fin_sent && self.send_seq.in_flight() > 0 {
+            self.emit(iface, self.send_seq.nxt.wrapping_sub(1), &[TcpControl::FIN, TcpControl::ACK], 0..0)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// the window of the peer is still closed with data waiting: an ACK of an old sequence
+    /// number makes the peer answer with its window, a window update may have been lost
+    fn probe_window<L: DataLayer + ?Sized>(&mut self, iface: &mut L) -> result::Result<()> {
+        if !matches!(self.state, TcpState::Established | TcpState::CloseWait) || self.send_seq.wnd > 0 {
+            return Ok(());
+        }
+        self.stats.window_probes += 1;
+        self.window_probes += 1;
+        trace!(parent: &self.span, probes = self.window_probes, "window probe");
+        self.emit(iface, self.send_seq.nxt.wrapping_sub(1), &[TcpControl::ACK], 0..0)
+    }
+
+    /// the timeout doubled for every unanswered window probe, like the retransmission timer
+    fn persist_interval(&self) -> Duration {
+        let interval = self.rtt.rto().checked_mul(1 << self.window_probes.min(16)).unwrap_or(MAX_RTO);
+        interval.min(MAX_RTO)
+    }
+
+    /// the handshake completed, data can be exchanged
+    pub fn is_synchronized(&self) -> bool {
+        !matches!(self.state, TcpState::Closed | TcpState::Listen | TcpState::SynSent | TcpState::SynReceived)
+    }
+
+    /// the peer won't send more data and everything received has been read
+    pub fn is_eof(&self) -> bool {
+        self.peer_fin && self.incoming.is_empty()
+    }
+
+    /// bytes ready to be read
+    pub fn bytes_available(&self) -> usize {
+        self.incoming.len()
+    }
+
+    /// the peer sent its FIN, what is buffered is all that arrives
+    pub fn is_fin_received(&self) -> bool {
+        self.peer_fin
+    }
+
+    /// bytes written but not acknowledged yet
+    pub fn send_queue_len(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    /// bytes which can be queued by `write` without being refused
+    pub fn send_space(&self) -> usize {
+        self.send_buffer_size().saturating_sub(self.outgoing.len())
+    }
+
+    /// the configured size or the larger one autotuning found
+    pub fn send_buffer_size(&self) -> usize {
+        self.config.send_buffer_size.max(self.send_buffer_tuned.min(self.config.send_buffer_max))
+    }
+
+    /// enough bytes to satisfy the receive low watermark arrived
+    pub fn is_readable(&self) -> bool {
+        self.incoming.len() >= self.config.recv_low_watermark.min(self.recv_buffer_size().max(1))
+    }
+
+    /// enough send buffer space to satisfy the send low watermark is free
+    pub fn is_writable(&self) -> bool {
+        let space = self.send_space();
+        space > 0 && space >= self.config.send_low_watermark.min(self.send_buffer_size())
+    }
+
+    /// report when the bytes received and not read reach `watermarks.high`
+    /// and when reading drains them to `watermarks.low`
+    pub fn watch_recv_queue(&mut self, watermarks: Watermarks, listener: Option<WatermarkListener>) {
+        self.incoming.set_watermarks(watermarks);
+        self.incoming.set_listener(listener);
+    }
+
+    /// report when the bytes written and not acknowledged reach `watermarks.high`
+    /// and when acknowledgments drain them to `watermarks.low`
+    pub fn watch_send_queue(&mut self, watermarks: Watermarks, listener: Option<WatermarkListener>) {
+        self.outgoing.set_watermarks(watermarks);
+        self.outgoing.set_listener(listener);
+    }
+
+    /// move received bytes into `buf`
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = self.incoming.read(buf);
+        self.on_read(n);
+        n
+    }
+
+    /// copy the first received bytes into `buf` and leave them for the next read
+    pub fn peek(&self, buf: &mut [u8]) -> usize {
+        self.incoming.peek(0, buf)
+    }
+
+    /// `n` bytes were taken from the receive buffer, the window may open
+    fn on_read(&mut self, n: usize) {
+        if self.config.recv_buffer_max > self.config.recv_buffer_size {
+            let (mss, max, now) = (self.config.mss, self.config.recv_buffer_max, self.clock.now());
+            self.recv_autotune.on_read(n, self.rtt.srtt(), mss, max, now);
+        }
+        let window = self.recv_window();
+        // the peer may be waiting for the window to open
+        if (self.recv_seq.wnd as usize) < DEFAULT_MSS && window as usize >= DEFAULT_MSS && self.is_synchronized() {
+            trace!(parent: &self.span, from = self.recv_seq.wnd, to = window, "receive window reopened");
+            self.ack_pending = true;
+        }
+        self.recv_seq.wnd = window;
+    }
+
+    /// the configured size or the larger one autotuning found
+    fn recv_buffer_size(&self) -> usize {
+        let tuned = self.recv_autotune.size().min(self.config.recv_buffer_max);
+        self.config.recv_buffer_size.max(tuned)
+    }
+
+    /// free space of the receive buffer
+    fn recv_window(&self) -> u16 {
+        let mut window = self.recv_buffer_size().saturating_sub(self.incoming.len());
+        let budget = self.reassembly.budget();
+        if self.config.pressure_window && budget.under_pressure() {
+            // segments beyond what the stack can hold out of order would be dropped anyway,
+            // in order data needs no reassembly memory and keeps a segment open
+            window = window.min(budget.available().max(self.config.mss));
+        }
+        window.min(u16::MAX as usize) as u16
+    }
+
+    /// the sending side was closed, `write` refuses everything
+    pub fn is_write_closed(&self) -> bool {
+        self.fin_pending || matches!(self.state, TcpState::Closed | TcpState::Listen)
+    }
+
+    /// queue bytes to be sent, return how many were accepted
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        if self.is_write_closed() {
+            return 0;
+        }
+        let n = data.len().min(self.send_space());
+        self.outgoing.push(&data[..n]);
+        n
+    }
+
+    /// queue all the slices or nothing, more than the send buffer holds is queued once it's empty
+    pub fn write_whole(&mut self, bufs: &[IoSlice]) -> usize {
+        let len: usize = bufs.iter().map(|buf| buf.len()).sum();
+        if self.is_write_closed() || (len > self.send_space() && !self.outgoing.is_empty()) {
+            return 0;
+        }
+        for buf in bufs {
+            self.outgoing.push(buf);
+        }
+        len
+    }
+
+    /// queue the slices in order like `write`, the segments are cut from the queue
+    /// regardless of the slice boundaries
+    pub fn write_vectored(&mut self, bufs: &[IoSlice]) -> usize {
+        let mut written = 0;
+        for buf in bufs {
+            let n = self.write(buf);
+            written += n;
+            if n < buf.len() {
+                break;
+            }
+        }
+        written
+    }
+
+    /// handle the first handshake
+    pub fn accept<'a, L: DataLayer + ?Sized>(
+        iface: &mut L,
+        ip: &'a etherparse::Ipv4HeaderSlice<'a>,
+        tcp: &'a etherparse::TcpHeaderSlice<'a>,
+        data: &'a [u8],
+    ) -> result::Result<Option<Self>> {
+        TcpConnection::accept_with_config(iface, ip, tcp, data, ConnectionConfig::default())
+    }
+
+    pub fn accept_with_config<'a, L: DataLayer + ?Sized>(
+        iface: &mut L,
+        ip: &'a etherparse::Ipv4HeaderSlice<'a>,
+        tcp: &'a etherparse::TcpHeaderSlice<'a>,
+        data: &'a [u8],
+        config: ConnectionConfig,
+    ) -> result::Result<Option<Self>> {
+        TcpConnection::passive_open(iface, ip, tcp, data, config, |_| {})
+    }
+
+    /// `accept_with_config` of a SYN signed with a key of `auth`, other SYNs are dropped
+    #[cfg(feature = "tcp-ao")]
+    pub fn accept_authenticated<'a, L: DataLayer + ?Sized>(
+        iface: &mut L,
+        ip: &'a etherparse::Ipv4HeaderSlice<'a>,
+        tcp: &'a etherparse::TcpHeaderSlice<'a>,
+        data: &'a [u8],
+        config: ConnectionConfig,
+        mut auth: Authenticator,
+    ) -> result::Result<Option<Self>> {
+        let quad = Quad::from_tcpip_header(ip, tcp).reverse();
+        if let Err(failure) = auth.verify(&quad, tcp, data, config.init_send_seq_number, tcp.sequence_number()) {
+            debug!(local = %quad.src(), remote = %quad.dest(), ?failure, "SYN not authentic");
+            return Ok(None);
+        }
+        TcpConnection::passive_open(iface, ip, tcp, data, config, |conn| conn.auth = Some(auth))
+    }
+
+    /// `accept_with_config` of the SYN of an MPTCP subflow
+    #[cfg(feature = "mptcp")]
+    pub fn accept_subflow<'a, L: DataLayer + ?Sized>(
+        iface: &mut L,
+        ip: &'a etherparse::Ipv4HeaderSlice<'a>,
+        tcp: &'a etherparse::TcpHeaderSlice<'a>,
+        data: &'a [u8],
+        config: ConnectionConfig,
+        subflow: Subflow,
+    ) -> result::Result<Option<Self>> {
+        TcpConnection::passive_open(iface, ip, tcp, data, config, |conn| conn.set_subflow(subflow))
+    }
+
+    /// `setup` is called before the SYN,ACK is sent
+    fn passive_open<'a, L: DataLayer + ?Sized, F: FnOnce(&mut Self)>(
+        iface: &mut L,
+        ip: &'a etherparse::Ipv4HeaderSlice<'a>,
+        tcp: &'a etherparse::TcpHeaderSlice<'a>,
+        data: &'a [u8],
+        config: ConnectionConfig,
+        setup: F,
+    ) -> result::Result<Option<Self>> {
+        // the first packet SYN flag must be set
+        if !tcp.syn() || tcp.ack() || tcp.rst() {
+            return Ok(None);
+        }
+        // we create the new connection cause it's first handshake
+        // and change send sequence number(nxt)
+        let quad = Quad::from_tcpip_header(ip, tcp).reverse();
+        if ip.ttl() < config.min_ttl {
+            debug!(local = %quad.src(), remote = %quad.dest(), ttl = ip.ttl(), "SYN below the minimum ttl");
+            return Ok(None);
+        }
+        let mut conn = TcpConnection::create(quad, config);
+        conn.on_peer_mss(tcp);
+        conn.on_syn_timestamps(tcp);
+        conn.recv_seq = ReceiveSequenceSpace::from_seq_number(tcp.sequence_number(), conn.recv_window());
+        conn.send_seq = SendSequenceSpace::from_seq_number(config.init_send_seq_number, tcp.window_size());
+        conn.send_seq.wl1 = tcp.sequence_number();
+        conn.snd_max = config.init_send_seq_number;
+        conn.passive = true;
+        conn.on_ip_header(ip);
+        conn.queue_syn_text(data, tcp.fin());
+        conn.on_heard(tcp);
+        // we just crate connection, now state is listen
+        // when we send response packet then state will change to SynRecv
+        debug!(parent: &conn.span, seq = tcp.sequence_number(), "passive open");
+        conn.set_state(TcpState::Listen);
+        conn.syn_pending = true;
+        conn.handshake_deadline = Some(conn.clock.now() + config.syn_r2);
+        setup(&mut conn);
+        conn.transmit(iface)?;
+        conn.set_state(TcpState::SynReceived);
+        Ok(Some(conn))
+    }
+
+    /// process a segment of this connection, see RFC 793 Section 3.9 SEGMENT ARRIVES
+    pub fn on_segment<L: DataLayer + ?Sized>(
+        &mut self,
+        iface: &mut L,
+        tcp: &TcpHeaderSlice,
+        data: &[u8],
+    ) -> result::Result<()> {
+        trace!(parent: &self.span, seq = tcp.sequence_number(), ack = tcp.acknowledgment_number(),
+               syn = tcp.syn(), ack_flag = tcp.ack(), fin = tcp.fin(), rst = tcp.rst(),
+               wnd = tcp.window_size(), len = data.len(), "segment in");
+        self.stats.segments_received += 1;
+        #[cfg(feature = "tcp-ao")]
+        if !self.authenticate(tcp, data) {
+            return Ok(());
+        }
+        self.on_heard(tcp);
+        #[cfg(feature = "mptcp")]
+        if let Some(subflow) = &mut self.subflow {
+            match subflow.on_segment(tcp, tcp.window_size()) {
+                Verdict::Accept => {}
+                Verdict::Ack => self.ack_pending = true,
+                Verdict::Reset => {
+                    self.outgoing.clear();
+                    self.rst_pending = true;
+                    return self.transmit(iface);
+                }
+            }
+        }
+        match self.state {
+            TcpState::Closed | TcpState::Listen => return Ok(()),
+            TcpState::SynSent => self.on_syn_sent(iface, tcp, data)?,
+            TcpState::Established if self.is_predicted(tcp, data) => self.on_predicted(tcp, data),
+            _ => self.on_synchronized(iface, tcp, data)?,
+        }
+        self.transmit(iface)
+    }
+
+    /// segments sent are no larger than the MSS option of the SYN of the peer, or
+    /// `DEFAULT_MSS` without one. the congestion window starts over in segments of that size
+    fn on_peer_mss(&mut self, tcp: &TcpHeaderSlice) {
+        let peer = options::parse(tcp.options()).filter_map(|option| option.ok()).find_map(|option| match option {
+            TcpOption::MaximumSegmentSize(mss) => Some(mss as usize),
+            _ => None,
+        });
+        let mss = self.advertised_mss.min(peer.unwrap_or(DEFAULT_MSS)).max(1);
+        if mss != self.config.mss {
+            debug!(parent: &self.span, mss, ?peer, "mss of the peer");
+            self.config.mss = mss;
+            self.congestion = self.config.congestion.build(mss, self.config.initial_window);
+        }
+    }
+
+    /// both ends send timestamps if they are configured and the SYN of the peer has them
+    fn on_syn_timestamps(&mut self, tcp: &TcpHeaderSlice) {
+        self.ts_recent = timestamps(tcp).filter(|_| self.config.timestamps).map(|(value, _)| value);
+    }
+
+    /// the timestamp of a segment which covers rcv.nxt is echoed from now on (RFC 7323 4.3)
+    fn on_peer_timestamp(&mut self, tcp: &TcpHeaderSlice) {
+        let recent = match self.ts_recent {
+            Some(recent) => recent,
+            None => return,
+        };
+        if let Some((value, _)) = timestamps(tcp) {
+            if seq_le(tcp.sequence_number(), self.recv_seq.nxt) && seq_ge(value, recent) {
+                self.ts_recent = Some(value);
+            }
+        }
+    }
+
+    /// the timestamp sent now, milliseconds since the first one
+    fn ts_now(&mut self) -> u32 {
+        let now = self.clock.now();
+        let base = *self.ts_base.get_or_insert(now);
+        now.saturating_duration_since(base).as_millis() as u32
+    }
+
+    /// the peer is alive, the keep-alive probes start over. it may have advertised its
+    /// user timeout, which we take if the config lets us
+    fn on_heard(&mut self, tcp: &TcpHeaderSlice) {
+        let now = self.clock.now();
+        self.last_heard = Some(now);
+        if let Some(probe) = self.last_probe {
+            let rtt = now.saturating_duration_since(probe);
+            self.stats.keep_alive_rtt = Some(rtt);
+            self.observe(|observer, quad| observer.on_keep_alive_answered(quad, rtt));
+        }
+        self.keep_alive_probes = 0;
+        self.last_probe = None;
+        if !self.config.accept_user_timeout || tcp.options().is_empty() {
+            return;
+        }
+        let advertised = options::parse(tcp.options()).filter_map(|option| option.ok()).find_map(|option| match option {
+            TcpOption::UserTimeout(timeout) => Some(timeout),
+            _ => None,
+        });
+        if let Some(timeout) = advertised {
+            let (lower, upper) = self.config.user_timeout_limits;
+            let timeout = timeout.max(lower).min(upper);
+            if self.peer_user_timeout != Some(timeout) {
+                debug!(parent: &self.span, ?timeout, "user timeout of the peer");
+                self.peer_user_timeout = Some(timeout);
+            }
+        }
+    }
+
+    /// the ip header of a packet of the peer, given to the connection before the segment it
+    /// carries. `false` if the segment has to be dropped, it arrived below the minimum ttl
+    pub fn on_ip_header(&mut self, ip: &Ipv4HeaderSlice) -> bool {
+        if ip.ttl() < self.config.min_ttl {
+            trace!(parent: &self.span, ttl = ip.ttl(), "segment below the minimum ttl");
+            self.stats.min_ttl_drops += 1;
+            return false;
+        }
+        self.received_tos = Some(ip.dcp().value() << 2 | ip.ecn().value());
+        true
+    }
+
+    /// type of service byte the peer sent its last packet with
+    pub fn received_tos(&self) -> Option<u8> {
+        self.received_tos
+    }
+
+    /// the code point the peer marked its last packet with
+    pub fn received_dscp(&self) -> Option<Dscp> {
+        self.received_tos.map(Dscp::from_tos)
+    }
+
+    /// how long data may stay unacknowledged: our user timeout, the one of the peer or R2
+    pub fn user_timeout(&self) -> Duration {
+        self.config.user_timeout.or(self.peer_user_timeout).unwrap_or(self.config.r2)
+    }
+
+    /// our user timeout goes with the SYN and the data until the peer acknowledged some,
+    /// so it got it unless a middlebox stripped it
+    fn advertises_user_timeout(&self, syn: bool, len: usize) -> Option<Duration> {
+        let unacked = seq_le(self.send_seq.una, self.send_seq.iss.wrapping_add(1));
+        self.config.user_timeout.filter(|_| syn || (len > 0 && unacked))
+    }
+
+    /// false if the connection has TCP-AO keys and the segment no valid MAC, it is silently
+    /// dropped (RFC 5925 7.3)
+    #[cfg(feature = "tcp-ao")]
+    fn authenticate(&mut self, tcp: &TcpHeaderSlice, data: &[u8]) -> bool {
+        let auth = match &mut self.auth {
+            Some(auth) => auth,
+            None => return true,
+        };
+        match auth.verify(&self.quad, tcp, data, self.send_seq.iss, self.recv_seq.irs) {
+            Ok(true) => {
+                let key = auth.status().current_key;
+                debug!(parent: &self.span, key, "TCP-AO key switched for the peer");
+                true
+            }
+            Ok(false) => true,
+            Err(failure) => {
+                match failure {
+                    AuthFailure::Missing => self.stats.auth_missing += 1,
+                    _ => self.stats.auth_failures += 1,
+                }
+                trace!(parent: &self.span, seq = tcp.sequence_number(), ?failure, "segment not authentic");
+                false
+            }
+        }
+    }
+
+    /// header prediction (TCP/IP Illustrated Vol. 2, 28.4): the next segment in order with only
+    /// ACK and PSH set, the window unchanged and nothing being retransmitted, which is either
+    /// a pure ACK of new data or data acknowledging nothing new. these pass every check of
+    /// SEGMENT ARRIVES and need none of its state changes
+    fn is_predicted(&self, tcp: &TcpHeaderSlice, data: &[u8]) -> bool {
+        if !tcp.ack() || tcp.syn() || tcp.fin() || tcp.rst() || tcp.urg() || tcp.ece() || tcp.cwr()
+            || tcp.sequence_number() != self.recv_seq.nxt
+            || tcp.window_size() != self.send_seq.wnd
+            || self.snd_max != self.send_seq.nxt
+            || self.recovery_point.is_some()
+            || self.dup_acks > 0 {
+            return false;
+        }
+        let ack = tcp.acknowledgment_number();
+        if data.is_empty() {
+            seq_gt(ack, self.send_seq.una) && seq_le(ack, self.send_seq.nxt) && !self.fin_sent
+                && self.send_seq.una != self.send_seq.iss
+        } else {
+            ack == self.send_seq.una && data.len() <= self.recv_seq.wnd as usize && self.reassembly.is_empty()
+        }
+    }
+
+    fn on_predicted(&mut self, tcp: &TcpHeaderSlice, data: &[u8]) {
+        self.stats.predicted += 1;
+        self.on_peer_timestamp(tcp);
+        let ack = tcp.acknowledgment_number();
+        self.send_seq.wl1 = tcp.sequence_number();
+        self.send_seq.wl2 = ack;
+        if data.is_empty() {
+            let acked = (ack.wrapping_sub(self.send_seq.una) as usize).min(self.outgoing.len());
+            self.outgoing.consume(acked);
+            self.send_seq.una = ack;
+            self.on_progress();
+            self.stats.bytes_acked += acked as u64;
+            let rtt = self.sample_rtt(ack);
+            let in_flight = self.data_in_flight();
+            self.congestion.on_ack(acked, in_flight, rtt, self.clock.now());
+            self.send_buffer_tuned = self.send_buffer_tuned.max(send_buffer_for(self.congestion.cwnd()));
+        } else {
+            self.deliver(data);
+            self.delay_ack();
+            self.recv_seq.wnd = self.recv_window();
+        }
+    }
+
+    fn on_syn_sent<L: DataLayer + ?Sized>(&mut self, iface: &mut L, tcp: &TcpHeaderSlice, data: &[u8]) -> result::Result<()> {
+        let ack = tcp.acknowledgment_number();
+        if tcp.ack() && !(seq_gt(ack, self.send_seq.iss) && seq_le(ack, self.send_seq.nxt)) {
+            if !tcp.rst() {
+                self.emit(iface, ack, &[TcpControl::RST], 0..0)?;
+            }
+            return Ok(());
+        }
+        if tcp.rst() {
+            if tcp.ack() {
+                self.reset = true;
+                self.set_state(TcpState::Closed);
+            }
+            return Ok(());
+        }
+        if !tcp.syn() {
+            return Ok(());
+        }
+        self.on_peer_mss(tcp);
+        self.on_syn_timestamps(tcp);
+        self.recv_seq = ReceiveSequenceSpace::from_seq_number(tcp.sequence_number(), self.recv_window());
+        if tcp.ack() {
+            self.send_seq.una = ack;
+            self.sample_rtt(ack);
+            self.on_handshake_complete();
+            self.set_window(tcp);
+            self.set_state(TcpState::Established);
+            self.ack_pending = true;
+            let (text, fin) = self.syn_text(data, tcp.fin());
+            self.on_text(tcp.sequence_number().wrapping_add(1), text, fin);
+        } else {
+            // simultaneous open, answer with SYN,ACK
+            self.set_state(TcpState::SynReceived);
+            self.syn_pending = true;
+            self.queue_syn_text(data, tcp.fin());
+        }
+        Ok(())
+    }
+
+    /// the part of the text of a SYN inside the window and its FIN if all of it fits, the
+    /// peer retransmits the rest and the FIN after it once they are acknowledged
+    fn syn_text<'a>(&self, data: &'a [u8], fin: bool) -> (&'a [u8], bool) {
+        let len = data.len().min(self.recv_seq.wnd as usize);
+        (&data[..len], fin && len == data.len())
+    }
+
+    /// keep the text of a SYN until the connection is established
+    fn queue_syn_text(&mut self, data: &[u8], fin: bool) {
+        if data.is_empty() && !fin {
+            return;
+        }
+        let (text, fin) = self.syn_text(data, fin);
+        self.syn_text = Some((text.to_vec(), fin));
+    }
+
+    /// the connection got established, what came with the SYN is processed now
+    fn on_syn_text(&mut self) {
+        if let Some((data, fin)) = self.syn_text.take() {
+            self.on_text(self.recv_seq.irs.wrapping_add(1), &data, fin);
+        }
+    }
+
+    fn on_synchronized<L: DataLayer + ?Sized>(&mut self, iface: &mut L, tcp: &TcpHeaderSlice, data: &[u8]) -> result::Result<()> {
+        let seq = tcp.sequence_number();
+        let seg_len = data.len() as u32 + tcp.syn() as u32 + tcp.fin() as u32;
+        // the peer retransmitted its SYN, our SYN,ACK was probably lost
+        if self.state == TcpState::SynReceived && tcp.syn() && !tcp.ack() && seq == self.recv_seq.irs {
+            self.syn_pending = true;
+            if self.syn_text.is_none() {
+                self.queue_syn_text(data, tcp.fin());
+            }
+            return Ok(());
+        }
+        // fourth check the SYN bit, done first: whatever its sequence number a SYN
+        // may be blind injection, only the real peer can answer the challenge ACK (RFC 5961 4.2)
+        if tcp.syn() && !tcp.rst() {
+            self.challenge_ack();
+            return Ok(());
+        }
+        // first check sequence number
+        if !self.recv_seq.acceptable(seq, seg_len) {
+            if tcp.rst() {
+                return Ok(());
+            }
+            if seg_len > 0 && seq_le(seq.wrapping_add(seg_len), self.recv_seq.nxt) {
+                self.stats.dup_segments += 1;
+                self.on_duplicate(data.len());
+            } else {
+                self.ack_pending = true;
+            }
+            return Ok(());
+        }
+        self.on_peer_timestamp(tcp);
+        // second check the RST bit, only an exact one resets the connection,
+        // others in the window get a challenge ACK (RFC 5961 3.2)
+        if tcp.rst() {
+            if seq != self.recv_seq.nxt {
+                self.challenge_ack();
+                return Ok(());
+            }
+            if !(self.passive && self.state == TcpState::SynReceived) {
+                self.reset = true;
+            }
+            self.set_state(TcpState::Closed);
+            return Ok(());
+        }
+        // fifth check the ACK field
+        if !tcp.ack() {
+            return Ok(());
+        }
+        if !self.on_ack(iface, tcp, data.len())? {
+            return Ok(());
+        }
+        self.on_text(seq, data, tcp.fin());
+        Ok(())
+    }
+
+    /// seventh process the segment text and eighth check the FIN bit
+    fn on_text(&mut self, seq: u32, data: &[u8], fin: bool) {
+        if !data.is_empty() && matches!(self.state, TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2) {
+            self.on_data(seq, data);
+        }
+        // only once every byte before it arrived
+        let fin = fin.then(|| seq.wrapping_add(data.len() as u32));
+        match fin {
+            Some(fin) if seq_gt(fin, self.recv_seq.nxt) => self.reassembly.set_fin(fin),
+            Some(fin) if fin == self.recv_seq.nxt => self.on_fin(),
+            // the segment filled the gap before a FIN received out of order
+            _ if self.reassembly.take_fin(self.recv_seq.nxt) => self.on_fin(),
+            _ => {}
+        }
+    }
+
+    /// return false if processing of the segment stops here
+    fn on_ack<L: DataLayer + ?Sized>(&mut self, iface: &mut L, tcp: &TcpHeaderSlice, data_len: usize) -> result::Result<bool> {
+        let ack = tcp.acknowledgment_number();
+        if self.state == TcpState::SynReceived {
+            if !self.send_seq.acceptable(ack) {
+                self.emit(iface, ack, &[TcpControl::RST], 0..0)?;
+                return Ok(false);
+            }
+            self.set_window(tcp);
+            self.on_handshake_complete();
+            self.set_state(TcpState::Established);
+            self.on_syn_text();
+        }
+        // ack of something not yet sent, or older than the largest window (RFC 5961 5.2)
+        if seq_gt(ack, self.send_seq.nxt) || seq_lt(ack, self.send_seq.una.wrapping_sub(self.max_snd_wnd as u32)) {
+            self.challenge_ack();
+            return Ok(false);
+        }
+        if seq_gt(ack, self.send_seq.una) {
+            let mut acked = ack.wrapping_sub(self.send_seq.una) as usize;
+            if self.send_seq.una == self.send_seq.iss {
+                // our SYN
+                acked -= 1;
+            }
+            if self.fin_sent && ack == self.send_seq.nxt {
+                acked -= 1;
+            }
+            let acked = acked.min(self.outgoing.len());
+            self.outgoing.consume(acked);
+            self.send_seq.una = ack;
+            self.on_reordering();
+            self.on_progress();
+            self.stats.bytes_acked += acked as u64;
+            let rtt = self.sample_rtt(ack);
+            let in_flight = self.data_in_flight();
+            self.congestion.on_ack(acked, in_flight, rtt, self.clock.now());
+            self.on_timeout_ack(true, tcp);
+            self.send_buffer_tuned = self.send_buffer_tuned.max(send_buffer_for(self.congestion.cwnd()));
+        } else if ack == self.send_seq.una && data_len == 0 && !tcp.syn() && !tcp.fin()
+            && tcp.window_size() == self.send_seq.wnd && self.data_in_flight() > 0 {
+            self.stats.dup_acks += 1;
+            self.on_timeout_ack(false, tcp);
+            self.on_dup_ack();
+        }
+        if seq_ge(ack, self.send_seq.una) {
+            self.update_window(tcp);
+        }
+        let fin_acked = self.fin_sent && ack == self.send_seq.nxt;
+        match self.state {
+            TcpState::FinWait1 if fin_acked => self.set_state(TcpState::FinWait2),
+            TcpState::Closing if fin_acked => self.set_state(TcpState::TimeWait),
+            TcpState::LastAck if fin_acked => {
+                self.set_state(TcpState::Closed);
+                return Ok(false);
+            }
+            TcpState::TimeWait => {
+                // retransmitted FIN of the peer
+                self.ack_pending = true;
+                return Ok(false);
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// a duplicate ACK: the peer got a segment above a hole. after `dupthresh` of them the
+    /// segment at snd.una is taken for lost and sent again right away (RFC 5681 3.2), the
+    /// partial ACKs which follow retransmit the next holes like after a timeout (RFC 6582)
+    fn on_dup_ack(&mut self) {
+        self.dup_acks += 1;
+        if let Some(fast) = &mut self.fast_retransmit {
+            fast.dup_acks += 1;
+        }
+        // a window holding fewer segments can't bring enough of them
+        let segments = (self.data_in_flight() / self.config.mss.max(1)) as u32;
+        let threshold = self.dupthresh.min(segments.saturating_sub(1)).max(DEFAULT_DUPTHRESH);
+        if self.dup_acks != threshold || self.recovery_point.is_some() {
+            return;
+        }
+        debug!(parent: &self.span, una = self.send_seq.una, dup_acks = self.dup_acks, "fast retransmit");
+        self.stats.fast_retransmits += 1;
+        let now = self.clock.now();
+        self.fast_retransmit = Some(FastRetransmit { sent: now, dup_acks: self.dup_acks, congestion: self.congestion.clone() });
+        self.congestion.on_loss(self.data_in_flight(), now);
+        self.recovery_point = Some(self.snd_max);
+        self.scheduler.queue(Transmission::Retransmit);
+    }
+
+    /// the ACK advanced after duplicate ACKs. it fills the hole sooner than the fast
+    /// retransmission could have made it there and back if the segment was only late,
+    /// or without any retransmission. the duplicate ACKs tell how far the segment was
+    /// reordered, the threshold grows past it and a spurious fast retransmit is undone
+    fn on_reordering(&mut self) {
+        let dup_acks = core::mem::take(&mut self.dup_acks);
+        let reordered = match self.fast_retransmit.take() {
+            Some(fast) => {
+                let elapsed = self.clock.now().saturating_duration_since(fast.sent);
+                let late = self.rtt.srtt().is_some_and(|srtt| elapsed < srtt / 2);
+                if late {
+                    debug!(parent: &self.span, una = self.send_seq.una, "spurious fast retransmit");
+                    self.stats.spurious_retransmits += 1;
+                    self.congestion = fast.congestion;
+                    self.recovery_point = None;
+                    self.scheduler.cancel(Transmission::Retransmit);
+                }
+                late.then_some(fast.dup_acks)
+            }
+            None if self.recovery_point.is_none() && dup_acks > 0 => Some(dup_acks),
+            None => None,
+        };
+        if let Some(extent) = reordered {
+            self.stats.reordering_events += 1;
+            self.stats.reordering = self.stats.reordering.max(extent);
+            let dupthresh = (extent + 1).clamp(DEFAULT_DUPTHRESH, self.config.max_dupthresh);
+            if dupthresh > self.dupthresh {
+                debug!(parent: &self.span, extent, dupthresh, "reordering");
+                self.dupthresh = dupthresh;
+            }
+        }
+    }
+
+    /// duplicate ACKs which trigger a fast retransmit, 3 unless the path reorders segments
+    pub fn dupthresh(&self) -> u32 {
+        self.dupthresh
+    }
+
+    /// new data was acknowledged, the peer is alive: restart the retransmission timer (RFC 6298 5.3)
+    /// and after a timeout retransmit the next segment the peer is missing
+    fn on_progress(&mut self) {
+        self.retransmissions = 0;
+        self.retransmitting_since = None;
+        self.soft_error = false;
+        self.rto_deadline = None;
+        match self.recovery_point {
+            Some(point) if seq_lt(self.send_seq.una, point) => self.scheduler.queue(Transmission::Retransmit),
+            _ => self.recovery_point = None,
+        }
+        if self.send_seq.in_flight() > 0 {
+            self.rto_deadline = Some(self.clock.now() + self.rtt.rto());
+        }
+    }
+
+    /// the ACK after a retransmission timeout. with timestamps it echoes the one of the
+    /// original segment if the timeout was spurious (Eifel). without, F-RTO sends new data
+    /// after the ACK of the retransmission instead of the segments sent before the timeout,
+    /// if the next ACK acknowledges some of those the originals arrived, a duplicate ACK falls
+    /// back to the retransmissions. a spurious timeout is undone (RFC 4015)
+    fn on_timeout_ack(&mut self, advanced: bool, tcp: &TcpHeaderSlice) {
+        let mut timeout = match self.spurious_timeout.take() {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let recovering = self.recovery_point.is_some_and(|point| seq_lt(self.send_seq.una, point));
+        let in_flight = self.data_in_flight();
+        let stage = match timeout.detection {
+            Detection::Eifel { retransmitted } if advanced => {
+                if timestamps(tcp).is_some_and(|(_, echo)| seq_lt(echo, retransmitted)) {
+                    self.undo_timeout(timeout);
+                }
+                return;
+            }
+            Detection::Eifel { .. } => {
+                self.spurious_timeout = Some(timeout);
+                return;
+            }
+            Detection::Frto(stage) => stage,
+        };
+        match stage {
+            FrtoStage::Retransmitted if advanced && recovering && self.outgoing.len() > in_flight => {
+                self.scheduler.cancel(Transmission::Retransmit);
+                timeout.detection = Detection::Frto(FrtoStage::NewData { window: in_flight + 2 * self.config.mss });
+                self.spurious_timeout = Some(timeout);
+            }
+            FrtoStage::NewData { .. } if advanced => self.undo_timeout(timeout),
+            FrtoStage::NewData { .. } => self.scheduler.queue(Transmission::Retransmit),
+            // the timeout was real, or nothing new can be sent to find out
+            FrtoStage::Retransmitted => {}
+        }
+    }
+
+    /// the congestion window and the timeout from before a spurious timeout come back and
+    /// the segments after the one retransmitted aren't sent again
+    fn undo_timeout(&mut self, timeout: SpuriousTimeout) {
+        debug!(parent: &self.span, una = self.send_seq.una, "spurious retransmission timeout");
+        self.stats.spurious_timeouts += 1;
+        self.congestion = timeout.congestion;
+        self.rtt = timeout.rtt;
+        self.recovery_point = None;
+        self.scheduler.cancel(Transmission::Retransmit);
+        let in_flight = self.data_in_flight();
+        self.rto_deadline = (in_flight > 0).then(|| self.clock.now() + self.rtt.rto());
+    }
+
+    /// the round-trip time of the timed segment once `ack` covers it
+    fn sample_rtt(&mut self, ack: u32) -> Option<Duration> {
+        let (end, sent) = self.rtt_probe?;
+        if seq_lt(ack, end) {
+            return None;
+        }
+        self.rtt_probe = None;
+        let rtt = self.clock.now().saturating_duration_since(sent);
+        self.rtt.sample(rtt);
+        trace!(parent: &self.span, rtt = ?rtt, srtt = ?self.rtt.srtt(), rto = ?self.rtt.rto(), "rtt sample");
+        Some(rtt)
+    }
+
+    /// time one segment at a time. Karn's rule: a retransmission cancels the measurement,
+    /// its ACK can't tell which transmission arrived
+    fn time_segment(&mut self, seq: u32, len: u32) {
+        let end = seq.wrapping_add(len);
+        if seq_gt(end, self.snd_max) {
+            self.snd_max = end;
+            if self.rtt_probe.is_none() {
+                self.rtt_probe = Some((end, self.clock.now()));
+            }
+        } else {
+            self.rtt_probe = None;
+            self.stats.retransmits += 1;
+            self.observe(|observer, quad| observer.on_retransmit(quad, seq, len as usize));
+        }
+    }
+
+    /// ACK with rcv.nxt, unless the connection sent its share of challenge ACKs this second
+    fn challenge_ack(&mut self) {
+        let now = self.clock.now();
+        let (start, sent) = match self.challenge_acks {
+            Some((start, sent)) if now < start + Duration::from_secs(1) => (start, sent),
+            _ => (now, 0),
+        };
+        if sent >= self.config.challenge_ack_limit {
+            self.stats.challenge_acks_suppressed += 1;
+            return;
+        }
+        self.challenge_acks = Some((start, sent + 1));
+        self.stats.challenge_acks += 1;
+        self.ack_pending = true;
+    }
+
+    /// window update rules of RFC 793 page 72
+    fn update_window(&mut self, tcp: &TcpHeaderSlice) {
+        let seq = tcp.sequence_number();
+        let ack = tcp.acknowledgment_number();
+        if seq_lt(self.send_seq.wl1, seq) || (self.send_seq.wl1 == seq && seq_le(self.send_seq.wl2, ack)) {
+            self.set_window(tcp);
+        }
+    }
+
+    fn set_window(&mut self, tcp: &TcpHeaderSlice) {
+        if self.send_seq.wnd != tcp.window_size() {
+            trace!(parent: &self.span, from = self.send_seq.wnd, to = tcp.window_size(), "peer window update");
+        }
+        self.send_seq.wnd = tcp.window_size();
+        if self.send_seq.wnd > 0 {
+            self.persist_deadline = None;
+            self.window_probes = 0;
+        }
+        self.max_snd_wnd = self.max_snd_wnd.max(tcp.window_size());
+        self.send_seq.wl1 = tcp.sequence_number();
+        self.send_seq.wl2 = tcp.acknowledgment_number();
+    }
+
+    fn on_data(&mut self, seq: u32, data: &[u8]) {
+        // the segment may start before rcv.nxt when it overlaps data already received
+        let skip = self.recv_seq.nxt.wrapping_sub(seq) as usize;
+        if seq_gt(seq, self.recv_seq.nxt) {
+            // the duplicate ACK tells the peer about the gap right away (RFC 5681 4.2)
+            self.ack_pending = true;
+            self.on_out_of_order(seq, data);
+            return;
+        }
+        if skip >= data.len() {
+            // duplicate, a FIN after it may be new
+            self.on_duplicate(data.len());
+            return;
+        }
+        if skip > 0 {
+            self.on_duplicate(skip);
+        }
+        let data = &data[skip..];
+        let len = data.len().min(self.recv_seq.wnd as usize);
+        self.deliver(&data[..len]);
+        if skip == 0 && self.reassembly.is_empty() {
+            self.delay_ack();
+        } else {
+            // retransmissions and filling a gap are acknowledged right away
+            self.ack_pending = true;
+        }
+        // the segment may have filled the gap before queued ones
+        while let Some(queued) = self.reassembly.pop(self.recv_seq.nxt) {
+            let space = self.recv_buffer_size().saturating_sub(self.incoming.len());
+            let len = queued.len().min(space);
+            self.deliver(&queued[..len]);
+            if len < queued.len() {
+                // the buffer shrank since they arrived, the rest is retransmitted
+                break;
+            }
+        }
+        self.recv_seq.wnd = self.recv_window();
+    }
+
+    /// `len` bytes of a segment arrived before, the peer retransmitted them because our
+    /// ACK got lost or is late: a pure ACK with rcv.nxt goes out right away
+    fn on_duplicate(&mut self, len: usize) {
+        self.stats.dup_bytes += len as u64;
+        self.ack_pending = true;
+    }
+
+    /// acknowledge every second segment now and a single one after the delayed ACK
+    /// timeout, unless data sent meanwhile carries the ACK (RFC 1122 4.2.3.2)
+    fn delay_ack(&mut self) {
+        self.segments_unacked += 1;
+        match self.config.delayed_ack {
+            Some(timeout) if self.segments_unacked < 2 => {
+                if self.ack_deadline.is_none() {
+                    self.ack_deadline = Some(self.clock.now() + timeout);
+                }
+            }
+            _ => self.ack_pending = true,
+        }
+    }
+
+    fn deliver(&mut self, data: &[u8]) {
+        self.incoming.push(data);
+        self.recv_seq.nxt = self.recv_seq.nxt.wrapping_add(data.len() as u32);
+        self.recv_autotune.on_data(self.recv_seq.nxt, self.recv_seq.wnd, self.clock.now());
+        self.stats.bytes_received += data.len() as u64;
+        self.observe(|observer, quad| observer.on_data(quad, data));
+    }
+
+    /// keep the part of the segment inside the window until the gap before it is filled
+    fn on_out_of_order(&mut self, seq: u32, data: &[u8]) {
+        let offset = seq.wrapping_sub(self.recv_seq.nxt) as usize;
+        let len = data.len().min((self.recv_seq.wnd as usize).saturating_sub(offset));
+        if len == 0 {
+            return;
+        }
+        self.stats.out_of_order += 1;
+        if !self.reassembly.insert(seq, &data[..len]) {
+            self.stats.reassembly_drops += 1;
+            trace!(parent: &self.span, seq, len, queued = self.reassembly.len(),
+                   budget = self.reassembly.budget().available(), "out of order segment dropped");
+        }
+        if self.config.pressure_window {
+            self.recv_seq.wnd = self.recv_window();
+        }
+    }
+
+    fn on_fin(&mut self) {
+        self.recv_seq.nxt = self.recv_seq.nxt.wrapping_add(1);
+        self.peer_fin = true;
+        self.ack_pending = true;
+        self.observe(|observer, quad| observer.on_fin(quad));
+        match self.state {
+            TcpState::SynReceived | TcpState::Established => self.set_state(TcpState::CloseWait),
+            TcpState::FinWait1 => self.set_state(TcpState::Closing),
+            TcpState::FinWait2 => self.set_state(TcpState::TimeWait),
+            _ => {}
+        }
+    }
+
+    /// send whatever is due: handshake, retransmission and window probe, new data, FIN and pending ACK
+    pub fn transmit<L: DataLayer + ?Sized>(&mut self, iface: &mut L) -> result::Result<()> {
+        #[cfg(feature = "mptcp")]
+        self.sync_subflow(self.max_segment(iface.capabilities()));
+        if self.rst_pending {
+            self.rst_pending = false;
+            self.emit(iface, self.send_seq.nxt, &[TcpControl::RST], 0..0)?;
+            self.set_state(TcpState::Closed);
+            return Ok(());
+        }
+        if self.syn_pending {
+            self.syn_pending = false;
+            return handshake(self, iface);
+        }
+        while let Some(transmission) = self.scheduler.pop() {
+            match transmission {
+                Transmission::Retransmit => self.retransmit(iface)?,
+                Transmission::WindowProbe => self.probe_window(iface)?,
+            }
+        }
+        if matches!(self.state, TcpState::Established | TcpState::CloseWait) {
+            self.transmit_data(iface)?;
+            let unsent = self.outgoing.len() - self.data_in_flight();
+            if self.fin_pending && !self.fin_sent && unsent == 0 {
+                self.emit(iface, self.send_seq.nxt, &[TcpControl::FIN, TcpControl::ACK], 0..0)?;
+                self.send_seq.nxt = self.send_seq.nxt.wrapping_add(1);
+                self.on_fin_sent();
+            }
+        }
+        if self.ack_pending && self.state != TcpState::Closed {
+            self.emit(iface, self.send_seq.nxt, &[TcpControl::ACK], 0..0)?;
+        }
+        Ok(())
+    }
+
+    /// the FIN is in the sequence space, alone or on the last data segment
+    fn on_fin_sent(&mut self) {
+        self.fin_sent = true;
+        let next = if self.state == TcpState::Established { TcpState::FinWait1 } else { TcpState::LastAck };
+        self.set_state(next);
+    }
+
+    /// data bytes sent but not acknowledged
+    fn data_in_flight(&self) -> usize {
+        let mut in_flight = self.send_seq.in_flight() as usize;
+        if self.fin_sent {
+            // unless the FIN was acknowledged already
+            in_flight = in_flight.saturating_sub(1);
+        }
+        in_flight.min(self.outgoing.len())
+    }
+
+    /// new data, as much and as soon as the scheduler lets it leave. after a shutdown
+    /// the segment with the last byte carries the FIN, rather than an empty one after it
+    fn transmit_data<L: DataLayer + ?Sized>(&mut self, iface: &mut L) -> result::Result<()> {
+        let max_segment = self.max_segment(iface.capabilities());
+        self.restart_after_idle();
+        loop {
+            let in_flight = self.data_in_flight();
+            let unsent = self.outgoing.len() - in_flight;
+            let cwnd = match &self.spurious_timeout {
+                Some(SpuriousTimeout { detection: Detection::Frto(FrtoStage::NewData { window }), .. }) => {
+                    self.congestion.cwnd().max(*window)
+                }
+                _ => self.congestion.cwnd(),
+            };
+            let state = SendState {
+                unsent,
+                in_flight,
+                rwnd: self.send_seq.wnd as usize,
+                cwnd: cwnd + self.limited_transmit(),
+                max_segment,
+                closing: self.fin_pending,
+            };
+            let now = self.clock.now();
+            let len = match self.scheduler.next(&self.config, &state, now) {
+                Release::Now(len) => len,
+                Release::At(at) => {
+                    self.pacing_deadline = Some(at);
+                    return Ok(());
+                }
+                Release::Blocked => {
+                    // nothing in flight brings the ACK which opens the window, the persist timer probes it
+                    if state.rwnd == 0 && unsent > 0 && in_flight == 0 && self.persist_deadline.is_none() {
+                        self.persist_deadline = Some(now + self.persist_interval());
+                    }
+                    return Ok(());
+                }
+            };
+            #[cfg(feature = "mptcp")]
+            let len = self.mapped_len(self.send_seq.nxt, len);
+            let fin = self.fin_pending && !self.fin_sent && len == unsent;
+            self.emit(iface, self.send_seq.nxt, data_controls(fin), in_flight..in_flight + len)?;
+            self.send_seq.nxt = self.send_seq.nxt.wrapping_add(len as u32 + fin as u32);
+            if in_flight + len > cwnd {
+                self.stats.limited_transmits += 1;
+            }
+            let rate = self.pacing_rate();
+            self.scheduler.on_sent(&self.config, len, rate, now);
+            if fin {
+                self.on_fin_sent();
+                return Ok(());
+            }
+        }
+    }
+
+    /// a new segment beyond the congestion window for each of the first two duplicate ACKs,
+    /// the ACKs it brings back let a small window reach the fast retransmit (RFC 3042)
+    fn limited_transmit(&self) -> usize {
+        if self.recovery_point.is_some() {
+            return 0;
+        }
+        self.dup_acks.min(2) as usize * self.config.mss
+    }
+
+    /// new data waits with nothing in flight and the connection sent nothing for longer
+    /// than the timeout: the congestion control decays the window it couldn't validate
+    fn restart_after_idle(&mut self) {
+        if !self.config.slow_start_after_idle || self.send_seq.in_flight() > 0 || self.outgoing.is_empty() {
+            return;
+        }
+        let (now, rto) = (self.clock.now(), self.rtt.rto());
+        let idle = match self.last_sent {
+            Some(sent) => now.saturating_duration_since(sent),
+            None => return,
+        };
+        if idle > rto {
+            debug!(parent: &self.span, ?idle, cwnd = self.congestion.cwnd(), "restart after idle");
+            self.congestion.on_idle(idle, rto, now);
+            // the idle time counts once
+            self.last_sent = Some(now);
+        }
+    }
+
+    /// a segment of a subflow ends where the mapping of its first byte does
+    #[cfg(feature = "mptcp")]
+    fn mapped_len(&self, seq: u32, len: usize) -> usize {
+        match self.subflow.as_ref().and_then(|subflow| subflow.segment_limit(seq)) {
+            Some(limit) => len.min(limit),
+            None => len,
+        }
+    }
+
+    /// exchange data with the MPTCP connection of the subflow: hand it what arrived in order,
+    /// take what it has to send as far as the windows allow and close once it's closing
+    #[cfg(feature = "mptcp")]
+    fn sync_subflow(&mut self, max_segment: usize) {
+        let mut subflow = match self.subflow.take() {
+            Some(subflow) if !subflow.is_detached() && self.is_synchronized() => subflow,
+            other => {
+                self.subflow = other;
+                return;
+            }
+        };
+        subflow.on_acked(self.send_seq.una);
+        while !self.incoming.is_empty() {
+            let available = self.incoming.len();
+            let head = self.recv_seq.nxt.wrapping_sub(available as u32 + self.peer_fin as u32);
+            let (dsn, mapped) = match subflow.recv_mapping(head) {
+                Some(mapping) => mapping,
+                None => break,
+            };
+            let mut data = vec![0; available.min(mapped)];
+            self.incoming.peek(0, &mut data);
+            let taken = subflow::lock(subflow.meta()).on_data(dsn, &data);
+            self.incoming.consume(taken);
+            self.on_read(taken);
+            if taken < data.len() {
+                break;
+            }
+        }
+        let mut meta = subflow::lock(subflow.meta());
+        if subflow.is_established() {
+            meta.on_established();
+        }
+        if subflow.is_fallback() && self.is_eof() {
+            let dsn = meta.data_ack();
+            meta.on_data_fin(dsn);
+        }
+        let closing = meta.is_closing();
+        drop(meta);
+        if closing && !self.fin_pending {
+            self.close();
+        }
+        if matches!(self.state, TcpState::Established | TcpState::CloseWait) && !self.fin_pending {
+            let window = (self.send_seq.wnd as usize).min(self.congestion.cwnd());
+            while self.outgoing.len() < window {
+                let seq = self.send_seq.una.wrapping_add(self.outgoing.len() as u32);
+                match subflow.pull(seq, max_segment.min(window - self.outgoing.len())) {
+                    Some(data) => self.outgoing.push(&data),
+                    None => break,
+                }
+            }
+        }
+        self.subflow = Some(subflow);
+    }
+
+    /// tell the MPTCP connection the subflow closed, `from` is the state it closed in
+    #[cfg(feature = "mptcp")]
+    fn detach_subflow(&mut self, from: TcpState) {
+        let error = if self.reset && from == TcpState::SynSent {
+            Some(crate::io::ErrorKind::ConnectionRefused)
+        } else if self.reset {
+            Some(crate::io::ErrorKind::ConnectionReset)
+        } else if self.timed_out {
+            Some(crate::io::ErrorKind::TimedOut)
+        } else if self.addr_removed {
+            Some(crate::io::ErrorKind::AddrNotAvailable)
+        } else {
+            None
+        };
+        let quad = self.quad;
+        if let Some(subflow) = &mut self.subflow {
+            subflow.detach(quad, error);
+        }
+    }
+
+    /// payload of the segments sent, a device with segmentation offload cuts large segments
+    /// at the mss itself, the data link layer does for the others with GSO. signed segments
+    /// leave as they are and leave room for the option
+    fn max_segment(&self, capabilities: Capabilities) -> usize {
+        // the user timeout option goes with the first data
+        let full = self.full_segment();
+        let mss = match self.advertises_user_timeout(false, 1) {
+            Some(timeout) => full.saturating_sub(TcpOption::UserTimeout(timeout).len()),
+            None => full,
+        };
+        #[cfg(feature = "tcp-ao")]
+        if let Some(auth) = &self.auth {
+            return mss.saturating_sub(auth.option_len()).max(1);
+        }
+        #[cfg(feature = "mptcp")]
+        if self.subflow.is_some() {
+            return mss.saturating_sub(OPTION_LEN).max(1);
+        }
+        let offload = capabilities.contains(Capabilities::TSO) || self.config.gso;
+        if offload && mss == full { TSO_MAX_SEGMENT } else { mss.max(1) }
+    }
+
+    /// payload of a full sized segment, the mss less the timestamps option
+    fn full_segment(&self) -> usize {
+        match self.ts_recent {
+            Some(_) => self.config.mss.saturating_sub(TIMESTAMPS_LEN).max(1),
+            None => self.config.mss,
+        }
+    }
+
+    /// bytes per second the data is paced at, the rate of the congestion control or one
+    /// derived from its window, `None` before the first round-trip sample
+    fn pacing_rate(&self) -> Option<u64> {
+        self.congestion.pacing_rate().or_else(|| {
+            let srtt = self.rtt.srtt()?;
+            Some(pacing_rate(self.congestion.cwnd(), self.congestion.ssthresh(), srtt))
+        })
+    }
+
+    /// build and send one segment carrying the bytes `data` of the send queue,
+    /// the ACK number is always rcv.nxt
+    fn emit<L: DataLayer + ?Sized>(&mut self, iface: &mut L, seq: u32, controls: &[TcpControl], data: Range<usize>) -> result::Result<()> {
+        let mut packet = TcpIpHeader::from_quad(&self.quad, seq, self.recv_seq.wnd, self.config.ttl)?;
+        packet.set_tos(self.config.tos);
+        for control in controls {
+            packet.set_control(*control);
+        }
+        if packet.tcp_header.ack {
+            packet.set_ack_number(self.recv_seq.nxt);
+            if !data.is_empty() && (self.ack_pending || self.ack_deadline.is_some()) {
+                self.stats.piggybacked_acks += 1;
+            }
+            self.ack_pending = false;
+            self.ack_deadline = None;
+            self.segments_unacked = 0;
+        }
+        // our SYN offers timestamps, once agreed they go on every segment but a RST
+        let stamped = match self.state {
+            TcpState::SynSent => self.config.timestamps,
+            _ => self.ts_recent.is_some(),
+        };
+        let stamp = (stamped && !packet.tcp_header.rst).then(|| self.ts_now());
+        // gathered straight from the queue, which may wrap around
+        let (first, second) = self.outgoing.slices(data.start);
+        let split = data.len().min(first.len());
+        let payload = [&first[..split], &second[..data.len() - split]];
+        let mut options = Vec::new();
+        if packet.tcp_header.syn {
+            options.push(TcpOption::MaximumSegmentSize(self.advertised_mss.min(u16::MAX as usize) as u16));
+        }
+        if let Some(timeout) = self.advertises_user_timeout(packet.tcp_header.syn, data.len()) {
+            options.push(TcpOption::UserTimeout(timeout));
+        }
+        if let Some(value) = stamp {
+            options.push(TcpOption::Timestamps { value, echo: self.ts_recent.unwrap_or(0) });
+        }
+        #[cfg(feature = "mptcp")]
+        if let Some(subflow) = self.subflow.as_ref().filter(|_| !packet.tcp_header.rst) {
+            let (syn, fin) = (packet.tcp_header.syn, packet.tcp_header.fin);
+            if let Some(option) = subflow.option(syn, fin, seq, data.len()) {
+                options.push(option.to_option());
+            }
+        }
+        if !options.is_empty() {
+            packet.set_options(&options)?;
+        }
+        #[cfg(feature = "tcp-ao")]
+        if let Some(auth) = &mut self.auth {
+            auth.sign(&self.quad, &mut packet, &payload, self.send_seq.iss, self.recv_seq.irs)?;
+        }
+        let mss = self.full_segment();
+        send_packet(iface, &mut packet, &payload, mss)?;
+        let seq_len = data.len() as u32 + packet.tcp_header.syn as u32 + packet.tcp_header.fin as u32;
+        if seq_len > 0 {
+            self.time_segment(seq, seq_len);
+            if self.rto_deadline.is_none() {
+                self.rto_deadline = Some(self.clock.now() + self.rtt.rto());
+            }
+        }
+        trace!(parent: &self.span, seq, ack = packet.tcp_header.acknowledgment_number,
+               syn = packet.tcp_header.syn, ack_flag = packet.tcp_header.ack,
+               fin = packet.tcp_header.fin, rst = packet.tcp_header.rst,
+               wnd = packet.tcp_header.window_size, len = data.len(), "segment out");
+        // segments on the wire, several when the device segments a large one
+        self.stats.segments_sent += data.len().div_ceil(mss).max(1) as u64;
+        self.stats.bytes_sent += data.len() as u64;
+        if !data.is_empty() {
+            let now = self.clock.now();
+            self.scheduler.charge(&self.config, data.len(), now);
+            self.last_sent = Some(now);
+        }
+        Ok(())
+    }
+}
+
+//          send SYN c_seq=x
+// Client ------------------------------------> Server
+//          send SYN,ACK,s_seq=y,ack=x+1
+// Client <----------------------------------- Server
+//          send ACK,ack=y+1,c_seq=x+1
+// Client -----------------------------------> Server
+fn handshake<L: DataLayer + ?Sized>(conn: &mut TcpConnection, iface: &mut L) -> result::Result<()> {
+    let iss = conn.send_seq.iss;
+    if conn.state == TcpState::SynSent {
+        conn.emit(iface, iss, &[TcpControl::SYN], 0..0)
+    } else {
+        // we have to set SYN and ACK flags
+        conn.emit(iface, iss, &[TcpControl::SYN, TcpControl::ACK], 0..0)
+    }
+}
+
+/// the value and the echo of the timestamps option of `tcp`
+fn timestamps(tcp: &TcpHeaderSlice) -> Option<(u32, u32)> {
+    options::parse(tcp.options()).filter_map(|option| option.ok()).find_map(|option| match option {
+        TcpOption::Timestamps { value, echo } => Some((value, echo)),
+        _ => None,
+    })
+}
+
+/// the headers are built in one buffer, the payload slices are handed to the device as they are,
+/// a payload larger than `mss` is segmented by the device
+fn send_packet<L: DataLayer + ?Sized>(iface: &mut L, packet: &mut TcpIpHeader, payload: &[&[u8]], mss: usize) -> result::Result<()> {
+    let len = payload.iter().map(|part| part.len()).sum();
+    if iface.capabilities().contains(Capabilities::TX_CHECKSUM) {
+        packet.finalize_offloaded(len)?;
+    } else {
+        packet.finalize_vectored(payload)?;
+    }
+    #[cfg(feature = "std")]
+    let header = {
+        let mut writer = RawWriter::new(0);
+        writer.write_link_header(iface.header_len())?;
+        writer.write_header(packet)?;
+        writer.finalize()?
+    };
+    // without `std` there is no buffer pool, the headers get a vector of their own
+    #[cfg(not(feature = "std"))]
+    let header = [&vec![0; iface.header_len()][..], &packet.ip_header_bytes(), &packet.tcp_header.to_bytes()].concat();
+    let mut bufs = vec![IoSlice::new(&header)];
+    bufs.extend(payload.iter().filter(|part| !part.is_empty()).map(|part| IoSlice::new(part)));
+    if len > mss {
+        iface.send_segmented(&bufs, mss)?;
+    } else {
+        iface.send_vectored(&bufs)?;
+    }
+    Ok(())
+}
+
+/// the flags of a data segment, the last one before the FIN may carry it
+fn data_controls(fin: bool) -> &'static [TcpControl] {
+    if fin {
+        &[TcpControl::ACK, TcpControl::PSH, TcpControl::FIN]
+    } else {
+        &[TcpControl::ACK, TcpControl::PSH]
+    }
+}
+
+/// answer a segment which belongs to no connection, RFC 793 page 65
+pub fn send_reset<L: DataLayer + ?Sized>(
+    iface: &mut L,
+    ip: &Ipv4HeaderSlice,
+    tcp: &TcpHeaderSlice,
+    data: &[u8],
+) -> result::Result<()> {
+    if tcp.rst() {
+        return Ok(());
+    }
+    let quad = Quad::from_tcpip_header(ip, tcp).reverse();
+    let mut packet = if tcp.ack() {
+        TcpIpHeader::from_quad(&quad, tcp.acknowledgment_number(), 0, DEFAULT_TIME_TO_LIVE)?
+    } else {
+        let seg_len = data.len() as u32 + tcp.syn() as u32 + tcp.fin() as u32;
+        let mut packet = TcpIpHeader::from_quad(&quad, 0, 0, DEFAULT_TIME_TO_LIVE)?;
+        packet.set_ack_number(tcp.sequence_number().wrapping_add(seg_len));
+        packet
+    };
+    packet.set_control(TcpControl::RST);
+    debug!(local = %quad.src(), remote = %quad.dest(),
+           seq = packet.tcp_header.sequence_number, "reset segment of no connection");
+    send_packet(iface, &mut packet, &[], DEFAULT_MSS)
 }
@@ -1,4 +1,17 @@
 pub mod vars;
 pub mod connection;
 pub mod packet;
-
+pub mod options;
+pub mod congestion;
+pub mod bbr;
+pub mod stats;
+pub mod ring;
+pub mod rtt;
+pub mod reassembly;
+pub mod scheduler;
+pub mod shaper;
+pub mod autotune;
+#[cfg(feature = "serde")]
+pub mod checkpoint;
+#[cfg(feature = "tcp-ao")]
+pub mod ao;
@@ -0,0 +1,229 @@
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::time::Duration;
+
+use crate::result::{Error, Result};
+
+/// room for options in a tcp header, a data offset of 15 words
+pub const MAX_OPTIONS_LEN: usize = 40;
+/// a SACK option carries at most 4 blocks (RFC 2018)
+pub const MAX_SACK_BLOCKS: usize = 4;
+
+pub const KIND_END_OF_LIST: u8 = 0;
+pub const KIND_NOP: u8 = 1;
+pub const KIND_MSS: u8 = 2;
+pub const KIND_WINDOW_SCALE: u8 = 3;
+pub const KIND_SACK_PERMITTED: u8 = 4;
+pub const KIND_SACK: u8 = 5;
+pub const KIND_TIMESTAMPS: u8 = 8;
+pub const KIND_USER_TIMEOUT: u8 = 28;
+pub const KIND_AUTHENTICATION: u8 = 29;
+pub const KIND_MULTIPATH: u8 = 30;
+
+/// One option of a tcp header, the end of option list is implied by the padding
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TcpOption {
+    /// no-operation, aligns the next option
+    Nop,
+    /// largest segment the sender accepts (RFC 793)
+    MaximumSegmentSize(u16),
+    /// shift count of the window of the sender (RFC 7323)
+    WindowScale(u8),
+    /// the sender understands SACK blocks (RFC 2018)
+    SackPermitted,
+    /// blocks received above the cumulative ACK
+    Sack(Vec<SackBlock>),
+    /// RFC 7323 timestamps, the clock of the sender and the last one it received
+    Timestamps { value: u32, echo: u32 },
+    /// how long the sender waits for its data to be acknowledged before it aborts (RFC 5482),
+    /// in seconds up to 32767 and in whole minutes beyond
+    UserTimeout(Duration),
+    /// TCP-AO (RFC 5925), the key the MAC was computed with and the key the sender
+    /// wants to receive with next
+    Authentication { key_id: u8, rnext_key_id: u8, mac: Vec<u8> },
+    /// MPTCP (RFC 8684), the subtype is in the upper bits of the first byte
+    Multipath(Vec<u8>),
+    /// an option this stack doesn't know, kept as it is
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+/// A contiguous block of sequence space, `right` is the first sequence number after it
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct SackBlock {
+    pub left: u32,
+    pub right: u32,
+}
+
+impl TcpOption {
+    pub fn kind(&self) -> u8 {
+        match self {
+            TcpOption::Nop => KIND_NOP,
+            TcpOption::MaximumSegmentSize(_) => KIND_MSS,
+            TcpOption::WindowScale(_) => KIND_WINDOW_SCALE,
+            TcpOption::SackPermitted => KIND_SACK_PERMITTED,
+            TcpOption::Sack(_) => KIND_SACK,
+            TcpOption::Timestamps { .. } => KIND_TIMESTAMPS,
+            TcpOption::UserTimeout(_) => KIND_USER_TIMEOUT,
+            TcpOption::Authentication { .. } => KIND_AUTHENTICATION,
+            TcpOption::Multipath(_) => KIND_MULTIPATH,
+            TcpOption::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// bytes of the option, kind and length included
+    pub fn len(&self) -> usize {
+        match self {
+            TcpOption::Nop => 1,
+            TcpOption::MaximumSegmentSize(_) => 4,
+            TcpOption::WindowScale(_) => 3,
+            TcpOption::SackPermitted => 2,
+            TcpOption::Sack(blocks) => 2 + 8 * blocks.len(),
+            TcpOption::Timestamps { .. } => 10,
+            TcpOption::UserTimeout(_) => 4,
+            TcpOption::Authentication { mac, .. } => 4 + mac.len(),
+            TcpOption::Multipath(data) => 2 + data.len(),
+            TcpOption::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    /// an option is never empty, there is always the kind
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) -> Result<()> {
+        let invalid = || Error::InvalidOption(self.kind());
+        match self {
+            TcpOption::Nop => {
+                buf.push(KIND_NOP);
+                return Ok(());
+            }
+            TcpOption::Sack(blocks) if blocks.is_empty() || blocks.len() > MAX_SACK_BLOCKS => return Err(invalid()),
+            TcpOption::Unknown { kind, .. } if *kind == KIND_END_OF_LIST || *kind == KIND_NOP => return Err(invalid()),
+            _ => {}
+        }
+        buf.push(self.kind());
+        buf.push(u8::try_from(self.len()).map_err(|_| invalid())?);
+        match self {
+            TcpOption::MaximumSegmentSize(mss) => buf.extend_from_slice(&mss.to_be_bytes()),
+            TcpOption::WindowScale(shift) => buf.push(*shift),
+            TcpOption::Sack(blocks) => {
+                for block in blocks {
+                    buf.extend_from_slice(&block.left.to_be_bytes());
+                    buf.extend_from_slice(&block.right.to_be_bytes());
+                }
+            }
+            TcpOption::Timestamps { value, echo } => {
+                buf.extend_from_slice(&value.to_be_bytes());
+                buf.extend_from_slice(&echo.to_be_bytes());
+            }
+            TcpOption::UserTimeout(timeout) => buf.extend_from_slice(&user_timeout_field(*timeout).to_be_bytes()),
+            TcpOption::Authentication { key_id, rnext_key_id, mac } => {
+                buf.push(*key_id);
+                buf.push(*rnext_key_id);
+                buf.extend_from_slice(mac);
+            }
+            TcpOption::Multipath(data) | TcpOption::Unknown { data, .. } => buf.extend_from_slice(data),
+            TcpOption::Nop | TcpOption::SackPermitted => {}
+        }
+        Ok(())
+    }
+}
+
+/// the granularity bit of the user timeout option, the value counts minutes
+const USER_TIMEOUT_MINUTES: u16 = 0x8000;
+
+/// the user timeout option field of `timeout`, the largest one it can carry if it's longer
+fn user_timeout_field(timeout: Duration) -> u16 {
+    let max = u64::from(!USER_TIMEOUT_MINUTES);
+    let secs = timeout.as_secs();
+    if secs <= max {
+        secs as u16
+    } else {
+        (secs / 60).min(max) as u16 | USER_TIMEOUT_MINUTES
+    }
+}
+
+/// the options of a header as written on the wire, padded with end of option list
+/// bytes to a multiple of 4
+pub fn serialize(options: &[TcpOption]) -> Result<Vec<u8>> {
+    let len: usize = options.iter().map(TcpOption::len).sum();
+    let padded = (len + 3) & !3;
+    if padded > MAX_OPTIONS_LEN {
+        return Err(Error::OptionsTooLong(len));
+    }
+    let mut buf = Vec::with_capacity(padded);
+    for option in options {
+        option.write(&mut buf)?;
+    }
+    buf.resize(padded, KIND_END_OF_LIST);
+    Ok(buf)
+}
+
+/// iterate the options in `raw`, e.g. `TcpHeaderSlice::options()`
+pub fn parse(raw: &[u8]) -> Options<'_> {
+    Options { raw }
+}
+
+/// Iterator over the options of a header, it stops at the end of option list
+/// and after the first malformed option
+#[derive(Debug, Clone)]
+pub struct Options<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Options<'a> {
+    fn next_option(&mut self) -> Result<Option<TcpOption>> {
+        let kind = match self.raw.first() {
+            None | Some(&KIND_END_OF_LIST) => return Ok(None),
+            Some(&kind) => kind,
+        };
+        if kind == KIND_NOP {
+            self.raw = &self.raw[1..];
+            return Ok(Some(TcpOption::Nop));
+        }
+        let len = match self.raw.get(1) {
+            Some(&len) if len >= 2 && len as usize <= self.raw.len() => len as usize,
+            _ => return Err(Error::InvalidOption(kind)),
+        };
+        let data = &self.raw[2..len];
+        self.raw = &self.raw[len..];
+        let be32 = |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        let option = match (kind, data.len()) {
+            (KIND_MSS, 2) => TcpOption::MaximumSegmentSize(u16::from_be_bytes([data[0], data[1]])),
+            (KIND_WINDOW_SCALE, 1) => TcpOption::WindowScale(data[0]),
+            (KIND_SACK_PERMITTED, 0) => TcpOption::SackPermitted,
+            (KIND_SACK, n) if n > 0 && n % 8 == 0 && n / 8 <= MAX_SACK_BLOCKS => {
+                TcpOption::Sack((0..n).step_by(8).map(|at| SackBlock { left: be32(at), right: be32(at + 4) }).collect())
+            }
+            (KIND_TIMESTAMPS, 8) => TcpOption::Timestamps { value: be32(0), echo: be32(4) },
+            (KIND_USER_TIMEOUT, 2) => {
+                let field = u16::from_be_bytes([data[0], data[1]]);
+                let unit = if field & USER_TIMEOUT_MINUTES != 0 { 60 } else { 1 };
+                TcpOption::UserTimeout(Duration::from_secs(u64::from(field & !USER_TIMEOUT_MINUTES) * unit))
+            }
+            (KIND_AUTHENTICATION, n) if n >= 2 => {
+                TcpOption::Authentication { key_id: data[0], rnext_key_id: data[1], mac: data[2..].to_vec() }
+            }
+            (KIND_MULTIPATH, n) if n > 0 => TcpOption::Multipath(data.to_vec()),
+            (KIND_MSS, _) | (KIND_WINDOW_SCALE, _) | (KIND_SACK_PERMITTED, _) | (KIND_SACK, _) | (KIND_TIMESTAMPS, _)
+            | (KIND_USER_TIMEOUT, _) | (KIND_AUTHENTICATION, _) | (KIND_MULTIPATH, _) => {
+                return Err(Error::InvalidOption(kind));
+            }
+            (kind, _) => TcpOption::Unknown { kind, data: data.to_vec() },
+        };
+        Ok(Some(option))
+    }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = Result<TcpOption>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let option = self.next_option();
+        if option.is_err() {
+            self.raw = &[];
+        }
+        option.transpose()
+    }
+}
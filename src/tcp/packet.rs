@@ -1,8 +1,15 @@
-use etherparse::{Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice};
+use core::convert::TryFrom;
+use core::net::SocketAddrV4;
+use core::ops::Deref;
 
+use etherparse::{IpDscp, IpEcn, IpNumber, Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice};
+
+use crate::checksum::Checksum;
 use crate::result;
+use crate::socket_addr::Quad;
+use crate::tcp::options::{self, Options, TcpOption};
 use crate::tcp::connection::{DEFAULT_ISS, DEFAULT_TIME_TO_LIVE, DEFAULT_WINDOWS_SIZE};
-use crate::tcp::vars::{ReceiveSequenceSpace, SendSequenceSpace};
+use crate::tcp::vars::{ReceiveSequenceSpace, SendSequenceSpace, TcpControl};
 
 pub struct TcpIpHeader {
     pub ip_header: etherparse::Ipv4Header,
@@ -18,12 +25,12 @@ impl TcpIpHeader {
             DEFAULT_WINDOWS_SIZE,
         );
         let ip = Ipv4Header::new(
-            tcp.header_len(),
+            tcp.header_len_u16(),
             DEFAULT_TIME_TO_LIVE,
-            etherparse::IpTrafficClass::IPv4,
-            rcv_ip_pkg.destination_addr().octets(),
-            rcv_ip_pkg.source_addr().octets(),
-        );
+            IpNumber::TCP,
+            rcv_ip_pkg.destination(),
+            rcv_ip_pkg.source(),
+        ).expect("a tcp header fits into an ip packet");
 
         Self::from_tcpip_header(
             ip,
@@ -31,6 +38,26 @@ impl TcpIpHeader {
         )
     }
 
+    /// header of a segment sent from `quad.src()` to `quad.dest()`, both ends must be ipv4
+    pub fn from_quad(quad: &Quad, seq_number: u32, window: u16, ttl: u8) -> result::Result<Self> {
+        let src = SocketAddrV4::try_from(quad.src())?;
+        let dest = SocketAddrV4::try_from(quad.dest())?;
+        let tcp = TcpHeader::new(
+            quad.src().port(),
+            quad.dest().port(),
+            seq_number,
+            window,
+        );
+        let ip = Ipv4Header::new(
+            tcp.header_len_u16(),
+            ttl,
+            IpNumber::TCP,
+            src.ip().octets(),
+            dest.ip().octets(),
+        ).expect("a tcp header fits into an ip packet");
+        Ok(Self::from_tcpip_header(ip, tcp))
+    }
+
     pub fn from_tcpip_header(ip_header: Ipv4Header, tcp_header: TcpHeader) -> Self {
         Self {
             ip_header,
@@ -54,10 +81,17 @@ impl TcpIpHeader {
 
     /// already add tcp header len
     pub fn set_payload_len(&mut self, len: usize) -> result::Result<()> {
-        self.ip_header.set_payload_len(self.tcp_header.header_len() as usize + len)?;
+        self.ip_header.set_payload_len(self.tcp_header.header_len() + len)?;
         Ok(())
     }
 
+    /// the ip header as sent, etherparse leaves its checksum to the writer
+    pub fn ip_header_bytes(&self) -> impl Deref<Target = [u8]> {
+        let mut ip_header = self.ip_header.clone();
+        ip_header.header_checksum = ip_header.calc_header_checksum();
+        ip_header.to_bytes()
+    }
+
     pub fn snd_syn(&mut self) {
         self.tcp_header.syn = true;
     }
@@ -66,6 +100,77 @@ impl TcpIpHeader {
         self.tcp_header.fin = true;
     }
 
+    pub fn set_control(&mut self, control: TcpControl) {
+        match control {
+            TcpControl::URG => self.tcp_header.urg = true,
+            TcpControl::ACK => self.tcp_header.ack = true,
+            TcpControl::PSH => self.tcp_header.psh = true,
+            TcpControl::RST => self.tcp_header.rst = true,
+            TcpControl::SYN => self.tcp_header.syn = true,
+            TcpControl::FIN => self.tcp_header.fin = true,
+        }
+    }
+
+    /// replace the options of the tcp header, they are padded to a multiple of 4 bytes
+    pub fn set_options(&mut self, options: &[TcpOption]) -> result::Result<()> {
+        let raw = options::serialize(options)?;
+        self.tcp_header.set_options_raw(&raw).map_err(|_| result::Error::OptionsTooLong(raw.len()))?;
+        Ok(())
+    }
+
+    pub fn options(&self) -> Options<'_> {
+        options::parse(self.tcp_header.options.as_slice())
+    }
+
+    /// the former type of service byte, DSCP in the upper six bits and ECN in the lower two
+    pub fn set_tos(&mut self, tos: u8) {
+        self.ip_header.dscp = IpDscp::try_new(tos >> 2).unwrap_or_default();
+        self.ip_header.ecn = IpEcn::try_new(tos & 0b11).unwrap_or_default();
+    }
+
+    pub fn set_ack_number(&mut self, ack_number: u32) {
+        self.tcp_header.ack = true;
+        self.tcp_header.acknowledgment_number = ack_number;
+    }
+
+    /// set the payload length and the tcp checksum, etherparse doesn't calculate it on write
+    pub fn finalize(&mut self, payload: &[u8]) -> result::Result<()> {
+        self.set_payload_len(payload.len())?;
+        self.tcp_header.checksum = self.check_sum(payload)?;
+        Ok(())
+    }
+
+    /// `finalize` for a payload split over several slices
+    pub fn finalize_vectored(&mut self, payload: &[&[u8]]) -> result::Result<()> {
+        let len = payload.iter().map(|part| part.len()).sum();
+        self.set_payload_len(len)?;
+        self.tcp_header.checksum = 0;
+        let mut checksum = self.pseudo_header(len);
+        checksum.add(&self.tcp_header.to_bytes());
+        for part in payload {
+            checksum.add(part);
+        }
+        self.tcp_header.checksum = checksum.finish();
+        Ok(())
+    }
+
+    /// `finalize` for a device with checksum offload, only the pseudo header is summed
+    /// and the device completes the checksum over header and payload
+    pub fn finalize_offloaded(&mut self, payload_len: usize) -> result::Result<()> {
+        self.set_payload_len(payload_len)?;
+        self.tcp_header.checksum = self.pseudo_header(payload_len).partial();
+        Ok(())
+    }
+
+    fn pseudo_header(&self, payload_len: usize) -> Checksum {
+        Checksum::ipv4_pseudo_header(
+            self.ip_header.source,
+            self.ip_header.destination,
+            IpNumber::TCP.0,
+            self.tcp_header.header_len() + payload_len,
+        )
+    }
+
     pub fn check_sum(&mut self, payload: &[u8]) -> result::Result<u16> {
         let checksum = self.tcp_header.calc_checksum_ipv4(
             &self.ip_header,
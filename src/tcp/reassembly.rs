@@ -0,0 +1,246 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::vars::{seq_gt, seq_le, seq_lt};
+
+/// bytes of bookkeeping charged for every queued segment besides its payload,
+/// so a spray of one byte segments exhausts the budget as fast as large ones
+pub const SEGMENT_OVERHEAD: usize = 64;
+/// out of order memory of one connection, a full default window of small segments fits
+pub const DEFAULT_REASSEMBLY_LIMIT: usize = 128 * 1024;
+
+/// Memory for out of order segments shared by the connections of a stack,
+/// clones share the budget
+#[derive(Debug, Clone)]
+pub struct ReassemblyBudget {
+    used: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+impl ReassemblyBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            used: Arc::new(AtomicUsize::new(0)),
+            limit,
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn available(&self) -> usize {
+        self.limit.saturating_sub(self.used())
+    }
+
+    /// more than three quarters of the budget are used
+    pub fn under_pressure(&self) -> bool {
+        self.used() > self.limit / 4 * 3
+    }
+
+    /// take `n` bytes of the budget, false if they aren't available
+    fn reserve(&self, n: usize) -> bool {
+        self.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            used.checked_add(n).filter(|total| *total <= self.limit)
+        }).is_ok()
+    }
+
+    fn release(&self, n: usize) {
+        self.used.fetch_sub(n, Ordering::Relaxed);
+    }
+}
+
+impl Default for ReassemblyBudget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+/// Segments received ahead of rcv.nxt, kept until the gap before them is filled
+///
+/// the segments are sorted and don't overlap, what a new segment shares with queued
+/// ones is dropped. each byte is charged to the limit of the queue and to the budget
+/// of the stack, a segment which doesn't fit is dropped and the peer retransmits it
+#[derive(Debug)]
+pub struct ReassemblyQueue {
+    segments: VecDeque<(u32, Vec<u8>)>,
+    /// charged bytes, payload and overhead
+    charged: usize,
+    limit: usize,
+    budget: ReassemblyBudget,
+    /// sequence number of a FIN received out of order
+    fin: Option<u32>,
+}
+
+impl Default for ReassemblyQueue {
+    fn default() -> Self {
+        Self::new(DEFAULT_REASSEMBLY_LIMIT, ReassemblyBudget::unlimited())
+    }
+}
+
+impl Clone for ReassemblyQueue {
+    /// the copy is charged to the same budget, it's empty if the budget can't hold it
+    fn clone(&self) -> Self {
+        let mut queue = Self::new(self.limit, self.budget.clone());
+        if self.budget.reserve(self.charged) {
+            queue.segments = self.segments.clone();
+            queue.charged = self.charged;
+            queue.fin = self.fin;
+        }
+        queue
+    }
+}
+
+impl ReassemblyQueue {
+    pub fn new(limit: usize, budget: ReassemblyBudget) -> Self {
+        Self {
+            segments: VecDeque::new(),
+            charged: 0,
+            limit,
+            budget,
+            fin: None,
+        }
+    }
+
+    /// charge the queued segments to `budget` from now on
+    pub fn set_budget(&mut self, budget: ReassemblyBudget) {
+        if budget.reserve(self.charged) {
+            self.budget.release(self.charged);
+            self.budget = budget;
+        } else {
+            self.clear();
+            self.budget = budget;
+        }
+    }
+
+    pub fn budget(&self) -> &ReassemblyBudget {
+        &self.budget
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// payload bytes queued
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|(_, data)| data.len()).sum()
+    }
+
+    /// queued segments
+    pub fn segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// the queued segments and where they start, in sequence order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.segments.iter().map(|(seq, data)| (*seq, data.as_slice()))
+    }
+
+    /// queue the parts of the segment at `seq` no queued segment covers,
+    /// return false if some of it was dropped for lack of memory
+    pub fn insert(&mut self, seq: u32, data: &[u8]) -> bool {
+        let mut complete = true;
+        let mut start = seq;
+        let end = seq.wrapping_add(data.len() as u32);
+        let mut i = 0;
+        while seq_lt(start, end) {
+            // the gap before the next queued segment, or everything left
+            let gap_end = match self.segments.get(i) {
+                Some((queued, bytes)) => {
+                    let queued_end = queued.wrapping_add(bytes.len() as u32);
+                    if seq_le(queued_end, start) {
+                        i += 1;
+                        continue;
+                    }
+                    if seq_le(*queued, start) {
+                        start = queued_end;
+                        i += 1;
+                        continue;
+                    }
+                    if seq_lt(*queued, end) { *queued } else { end }
+                }
+                None => end,
+            };
+            let piece = &data[start.wrapping_sub(seq) as usize..gap_end.wrapping_sub(seq) as usize];
+            let charge = piece.len() + SEGMENT_OVERHEAD;
+            if self.charged + charge > self.limit || !self.budget.reserve(charge) {
+                complete = false;
+                break;
+            }
+            self.charged += charge;
+            self.segments.insert(i, (start, piece.to_vec()));
+            i += 1;
+            start = gap_end;
+        }
+        complete
+    }
+
+    /// the queued bytes starting at `nxt`, what lies before it is dropped
+    pub fn pop(&mut self, nxt: u32) -> Option<Vec<u8>> {
+        loop {
+            let (seq, _) = self.segments.front()?;
+            if seq_gt(*seq, nxt) {
+                return None;
+            }
+            let (seq, mut data) = self.segments.pop_front()?;
+            self.uncharge(data.len());
+            let skip = nxt.wrapping_sub(seq) as usize;
+            if skip < data.len() {
+                data.drain(..skip);
+                return Some(data);
+            }
+        }
+    }
+
+    /// a FIN arrived at `seq` before the bytes preceding it
+    pub fn set_fin(&mut self, seq: u32) {
+        self.fin = Some(seq);
+    }
+
+    /// where the FIN which arrived early is, if one did
+    pub fn fin(&self) -> Option<u32> {
+        self.fin
+    }
+
+    /// the FIN queued at `nxt`, if any
+    pub fn take_fin(&mut self, nxt: u32) -> bool {
+        if self.fin == Some(nxt) {
+            self.fin = None;
+            return true;
+        }
+        false
+    }
+
+    pub fn clear(&mut self) {
+        self.budget.release(self.charged);
+        self.charged = 0;
+        self.segments.clear();
+        self.fin = None;
+    }
+
+    fn uncharge(&mut self, len: usize) {
+        let charge = len + SEGMENT_OVERHEAD;
+        self.charged -= charge;
+        self.budget.release(charge);
+    }
+}
+
+impl Drop for ReassemblyQueue {
+    fn drop(&mut self) {
+        self.budget.release(self.charged);
+    }
+}
@@ -0,0 +1,195 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Crossing of a watermark of a `RingBuffer`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Watermark {
+    /// the queued bytes rose to the high watermark
+    High,
+    /// the queued bytes fell to the low watermark after reaching the high one
+    Low,
+}
+
+/// Levels of a `RingBuffer` reported to its listener, `High` fires once the
+/// length reaches `high` and `Low` once it falls back to `low`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Watermarks {
+    pub low: usize,
+    pub high: usize,
+}
+
+impl Watermarks {
+    pub fn new(low: usize, high: usize) -> Self {
+        Self { low: low.min(high), high }
+    }
+}
+
+/// nothing is ever reported
+impl Default for Watermarks {
+    fn default() -> Self {
+        Self { low: 0, high: usize::MAX }
+    }
+}
+
+/// called with each crossing while the stack is locked, so it must not use the stack
+pub type WatermarkListener = Arc<dyn Fn(Watermark) + Send + Sync>;
+
+/// Circular byte queue, bytes are copied in and out by slices and the storage
+/// only grows when the queue is full
+#[derive(Clone, Default)]
+pub struct RingBuffer {
+    buf: Vec<u8>,
+    /// index of the first byte
+    head: usize,
+    len: usize,
+    watermarks: Watermarks,
+    /// the high watermark was reached and the low one not yet
+    above_high: bool,
+    listener: Option<WatermarkListener>,
+}
+
+impl RingBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: vec![0; capacity],
+            ..Self::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// bytes which fit before the storage grows
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn watermarks(&self) -> Watermarks {
+        self.watermarks
+    }
+
+    /// the level is checked again by the next change
+    pub fn set_watermarks(&mut self, watermarks: Watermarks) {
+        self.watermarks = watermarks;
+        self.above_high = self.len >= watermarks.high;
+    }
+
+    pub fn set_listener(&mut self, listener: Option<WatermarkListener>) {
+        self.listener = listener;
+    }
+
+    /// the high watermark was reached and the queue didn't drain to the low one yet
+    pub fn is_above_high(&self) -> bool {
+        self.above_high
+    }
+
+    /// append every byte of `data`
+    pub fn push(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.reserve(data.len());
+        let tail = (self.head + self.len) % self.buf.len();
+        let first = data.len().min(self.buf.len() - tail);
+        self.buf[tail..tail + first].copy_from_slice(&data[..first]);
+        self.buf[..data.len() - first].copy_from_slice(&data[first..]);
+        self.len += data.len();
+        if !self.above_high && self.len >= self.watermarks.high {
+            self.above_high = true;
+            self.notify(Watermark::High);
+        }
+    }
+
+    /// copy the bytes starting `offset` bytes after the head into `buf` without removing them
+    pub fn peek(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let (first, second) = self.slices(offset);
+        let n = buf.len().min(first.len() + second.len());
+        let split = n.min(first.len());
+        buf[..split].copy_from_slice(&first[..split]);
+        buf[split..n].copy_from_slice(&second[..n - split]);
+        n
+    }
+
+    /// move the first bytes into `buf`
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = self.peek(0, buf);
+        self.consume(n);
+        n
+    }
+
+    /// drop the first `n` bytes
+    pub fn consume(&mut self, n: usize) {
+        let n = n.min(self.len);
+        if n == 0 {
+            return;
+        }
+        self.head = (self.head + n) % self.buf.len();
+        self.len -= n;
+        if self.len == 0 {
+            self.head = 0;
+        }
+        if self.above_high && self.len <= self.watermarks.low {
+            self.above_high = false;
+            self.notify(Watermark::Low);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.consume(self.len);
+    }
+
+    /// the bytes after `offset` as two contiguous slices, the second one is empty
+    /// unless they wrap around the end of the storage
+    pub fn slices(&self, offset: usize) -> (&[u8], &[u8]) {
+        if offset >= self.len {
+            return (&[], &[]);
+        }
+        let start = (self.head + offset) % self.buf.len();
+        let len = self.len - offset;
+        if start + len <= self.buf.len() {
+            (&self.buf[start..start + len], &[])
+        } else {
+            (&self.buf[start..], &self.buf[..start + len - self.buf.len()])
+        }
+    }
+
+    /// grow so `additional` more bytes fit, the queued bytes move to the start
+    fn reserve(&mut self, additional: usize) {
+        let needed = self.len + additional;
+        if needed <= self.buf.len() {
+            return;
+        }
+        let mut buf = vec![0; needed.max(self.buf.len() * 2)];
+        self.peek(0, &mut buf);
+        self.buf = buf;
+        self.head = 0;
+    }
+
+    fn notify(&self, watermark: Watermark) {
+        if let Some(listener) = &self.listener {
+            listener(watermark);
+        }
+    }
+}
+
+impl fmt::Debug for RingBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RingBuffer")
+            .field("len", &self.len)
+            .field("capacity", &self.buf.len())
+            .field("watermarks", &self.watermarks)
+            .field("above_high", &self.above_high)
+            .finish()
+    }
+}
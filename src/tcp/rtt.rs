@@ -0,0 +1,116 @@
+use core::time::Duration;
+
+/// retransmission timeout before the first sample (RFC 6298 2.1)
+pub const INITIAL_RTO: Duration = Duration::from_secs(1);
+/// timeout of the first data when the handshake was retransmitted (RFC 6298 5.7)
+pub const HANDSHAKE_RETRANSMITTED_RTO: Duration = Duration::from_secs(3);
+/// lower bound of the timeout, RFC 6298 asks for 1s, Linux uses 200ms
+pub const MIN_RTO: Duration = Duration::from_millis(200);
+/// upper bound of the timeout, backoff included
+pub const MAX_RTO: Duration = Duration::from_secs(60);
+/// tick of the clock the samples are measured with
+pub const DEFAULT_CLOCK_GRANULARITY: Duration = Duration::from_millis(1);
+
+/// Smoothed round-trip time and retransmission timeout of RFC 6298
+///
+/// the caller applies Karn's rule: segments which were retransmitted are not sampled,
+/// their ACK can't tell which transmission it answers
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    /// timeout computed from the samples, before backoff
+    rto: Duration,
+    /// times the timeout was doubled since the last sample
+    backoff: u32,
+    granularity: Duration,
+    latest: Option<Duration>,
+    min: Option<Duration>,
+}
+
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self::new(DEFAULT_CLOCK_GRANULARITY)
+    }
+}
+
+impl RttEstimator {
+    /// `granularity` is G of RFC 6298, the variance term of the timeout is at least one tick
+    pub fn new(granularity: Duration) -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::from_secs(0),
+            rto: INITIAL_RTO,
+            backoff: 0,
+            granularity,
+            latest: None,
+            min: None,
+        }
+    }
+
+    /// `None` until the first sample
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    /// the last sample
+    pub fn latest(&self) -> Option<Duration> {
+        self.latest
+    }
+
+    /// the smallest sample seen
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn granularity(&self) -> Duration {
+        self.granularity
+    }
+
+    /// retransmission timeout, doubled for every `backoff` since the last sample
+    pub fn rto(&self) -> Duration {
+        let rto = self.rto.checked_mul(1 << self.backoff.min(16)).unwrap_or(MAX_RTO);
+        rto.min(MAX_RTO)
+    }
+
+    /// a round-trip time measured on a segment sent only once (RFC 6298 2.2, 2.3)
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        let srtt = self.srtt.unwrap_or(rtt);
+        self.rto = (srtt + self.granularity.max(self.rttvar * 4)).clamp(MIN_RTO, MAX_RTO);
+        self.backoff = 0;
+        self.latest = Some(rtt);
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+    }
+
+    /// back to the initial timeout if nothing was sampled yet, the handshake
+    /// was retransmitted and its backoff shouldn't delay the first data (RFC 6298 5.7)
+    pub fn restart(&mut self) {
+        if self.srtt.is_none() {
+            self.rto = HANDSHAKE_RETRANSMITTED_RTO;
+        }
+        self.backoff = 0;
+    }
+
+    /// the timer expired, double the timeout until the next sample (RFC 6298 5.5)
+    pub fn backoff(&mut self) {
+        if self.rto() < MAX_RTO {
+            self.backoff += 1;
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use alloc::collections::BTreeSet;
+use core::time::Duration;
+
+use crate::meta::DEFAULT_TIMER_RESOLUTION;
+use crate::tcp::connection::ConnectionConfig;
+#[cfg(feature = "std")]
+use crate::tcp::shaper::Shaper;
+use crate::tcp::shaper::TokenBucket;
+use crate::time::Instant;
+
+/// how far a paced sender may lag behind its schedule and catch up with a burst,
+/// the timers only fire on the ticks of the wheel
+pub const PACING_SLACK: Duration = DEFAULT_TIMER_RESOLUTION;
+
+/// What the scheduler lets leave next
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Release {
+    /// a segment of this many bytes
+    Now(usize),
+    /// nothing before this point, the pacing rate or a rate limit was reached
+    At(Instant),
+    /// nothing until an ACK arrives or the application writes or closes
+    Blocked,
+}
+
+/// What leaves before new data, in this order
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Transmission {
+    /// the first unacknowledged segment, after a timeout or duplicate ACKs
+    Retransmit,
+    /// a probe of the closed window of the peer (RFC 1122 4.2.2.17)
+    WindowProbe,
+}
+
+/// What a connection could send, in bytes
+#[derive(Debug, Copy, Clone)]
+pub struct SendState {
+    /// queued and never sent
+    pub unsent: usize,
+    pub in_flight: usize,
+    /// window of the receiver
+    pub rwnd: usize,
+    /// window of the congestion control
+    pub cwnd: usize,
+    /// largest segment the device takes, above the mss with segmentation offload
+    pub max_segment: usize,
+    /// the application closed the connection, no more data will join the queue
+    pub closing: bool,
+}
+
+/// Decides when the segments of a connection leave
+///
+/// new data goes within the receive and congestion windows, a small last segment waits for
+/// the ACK of the data in flight with Nagle's algorithm and, with pacing, segments are spread
+/// over the round trip at the pacing rate instead of leaving in a burst. the rate limit of the
+/// connection and the one of the stack hold segments back until their token buckets refill.
+/// retransmissions and window probes are queued, the connection sends them in the order of
+/// `Transmission` before any new data. the windows, Nagle and pacing don't hold them back,
+/// but they are charged to the rate limits
+#[derive(Debug, Clone, Default)]
+pub struct SendScheduler {
+    /// transmissions waiting for the next transmit of the connection
+    queued: BTreeSet<Transmission>,
+    /// the next segment may not leave before
+    next_release: Option<Instant>,
+    /// tokens of `ConnectionConfig::rate_limit`, created by the first charge
+    bucket: Option<TokenBucket>,
+    /// tokens shared by the connections of the stack
+    #[cfg(feature = "std")]
+    shaper: Option<Shaper>,
+}
+
+impl SendScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// when the next paced segment may leave, `None` if it may leave now
+    pub fn next_release(&self) -> Option<Instant> {
+        self.next_release
+    }
+
+    /// share the tokens of `shaper` with the other connections of the stack
+    #[cfg(feature = "std")]
+    pub fn set_shaper(&mut self, shaper: Option<Shaper>) {
+        self.shaper = shaper;
+    }
+
+    /// send `transmission` with the next transmit, once however often it is queued
+    pub fn queue(&mut self, transmission: Transmission) {
+        self.queued.insert(transmission);
+    }
+
+    /// `transmission` turned out to be unnecessary
+    pub fn cancel(&mut self, transmission: Transmission) {
+        self.queued.remove(&transmission);
+    }
+
+    pub fn is_queued(&self, transmission: Transmission) -> bool {
+        self.queued.contains(&transmission)
+    }
+
+    /// the queued transmission which leaves first, new data follows once there is none
+    pub fn pop(&mut self) -> Option<Transmission> {
+        self.queued.pop_first()
+    }
+
+    pub fn next(&self, config: &ConnectionConfig, state: &SendState, now: Instant) -> Release {
+        let window = state.rwnd.min(state.cwnd).saturating_sub(state.in_flight);
+        let len = state.unsent.min(window).min(state.max_segment);
+        if len == 0 {
+            return Release::Blocked;
+        }
+        // Nagle's algorithm (RFC 896), a small segment waits for more data while some is unacknowledged
+        if config.nagle() && len < config.mss() && len == state.unsent && state.in_flight > 0 && !state.closing {
+            return Release::Blocked;
+        }
+        match self.next_release {
+            Some(at) if config.pacing() && at > now => return Release::At(at),
+            _ => {}
+        }
+        // a segment larger than a burst would never get its tokens
+        #[cfg(feature = "std")]
+        let shared = self.shaper.as_ref().map(Shaper::limit);
+        #[cfg(not(feature = "std"))]
+        let shared = None;
+        let len = [config.rate_limit(), shared].iter()
+            .flatten()
+            .fold(len, |len, limit| len.min(limit.burst()));
+        match self.shaped_until(config, len, now) {
+            Some(at) => Release::At(at),
+            None => Release::Now(len),
+        }
+    }
+
+    /// when the rate limits let `len` bytes leave, `None` if they may leave now
+    fn shaped_until(&self, config: &ConnectionConfig, len: usize, now: Instant) -> Option<Instant> {
+        // a bucket of a previous limit is replaced by a full one on the next charge
+        let own = self.bucket.as_ref()
+            .filter(|bucket| Some(bucket.limit()) == config.rate_limit())
+            .and_then(|bucket| bucket.ready_at(len, now));
+        #[cfg(feature = "std")]
+        let shared = self.shaper.as_ref().and_then(|shaper| shaper.ready_at(len, now));
+        #[cfg(not(feature = "std"))]
+        let shared = None;
+        own.max(shared)
+    }
+
+    /// `len` bytes of data left at `now`, new or retransmitted
+    pub fn charge(&mut self, config: &ConnectionConfig, len: usize, now: Instant) {
+        match (&self.bucket, config.rate_limit()) {
+            (Some(bucket), Some(limit)) if bucket.limit() == limit => {}
+            (_, limit) => self.bucket = limit.map(TokenBucket::new),
+        }
+        if let Some(bucket) = &mut self.bucket {
+            bucket.charge(len, now);
+        }
+        #[cfg(feature = "std")]
+        if let Some(shaper) = &self.shaper {
+            shaper.charge(len, now);
+        }
+    }
+
+    /// `len` bytes left, at `rate` bytes per second the next segment waits until they drained
+    pub fn on_sent(&mut self, config: &ConnectionConfig, len: usize, rate: Option<u64>, now: Instant) {
+        let rate = match rate {
+            Some(rate) if config.pacing() && rate > 0 => rate,
+            _ => {
+                self.next_release = None;
+                return;
+            }
+        };
+        // time lost while idle or between timer ticks is not made up for beyond the slack
+        let earliest = now.checked_sub(PACING_SLACK).unwrap_or(now);
+        let base = self.next_release.map_or(now, |at| at.max(earliest));
+        self.next_release = Some(base + Duration::from_secs_f64(len as f64 / rate as f64));
+    }
+}
+
+/// rate derived from the window like Linux: twice the window per round trip
+/// in slow start to let it grow, 1.2 times in congestion avoidance
+pub fn pacing_rate(cwnd: usize, ssthresh: usize, srtt: Duration) -> u64 {
+    let srtt = srtt.as_secs_f64().max(1e-6);
+    let gain = if cwnd < ssthresh { 2.0 } else { 1.2 };
+    (cwnd as f64 * gain / srtt) as u64
+}
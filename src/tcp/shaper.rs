@@ -0,0 +1,107 @@
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::time::Instant;
+
+/// A cap on the bytes sent per second, with bursts of up to `burst` bytes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateLimit {
+    rate: u64,
+    burst: usize,
+}
+
+impl RateLimit {
+    /// `rate` bytes per second, `burst` should hold at least a segment
+    pub const fn new(rate: u64, burst: usize) -> Self {
+        Self {
+            rate: if rate > 1 { rate } else { 1 },
+            burst: if burst > 1 { burst } else { 1 },
+        }
+    }
+
+    /// bytes per second
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// bytes sent back to back after an idle period
+    pub fn burst(&self) -> usize {
+        self.burst
+    }
+}
+
+/// Token bucket enforcing a `RateLimit`, it starts full
+///
+/// a byte sent takes a token and the tokens come back at the rate, up to the burst.
+/// retransmissions are charged too, they may take the bucket below zero
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            updated: None,
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// tokens at `now`, negative while paying off a debt
+    pub fn tokens(&self, now: Instant) -> f64 {
+        let elapsed = self.updated.map_or(0.0, |updated| now.saturating_duration_since(updated).as_secs_f64());
+        (self.tokens + elapsed * self.limit.rate as f64).min(self.limit.burst as f64)
+    }
+
+    /// when `len` bytes may leave, `None` if they may leave now
+    pub fn ready_at(&self, len: usize, now: Instant) -> Option<Instant> {
+        let missing = len.min(self.limit.burst) as f64 - self.tokens(now);
+        (missing > 0.0).then(|| now + Duration::from_secs_f64(missing / self.limit.rate as f64))
+    }
+
+    /// `len` bytes were sent at `now`
+    pub fn charge(&mut self, len: usize, now: Instant) {
+        self.tokens = self.tokens(now) - len as f64;
+        self.updated = Some(now);
+    }
+}
+
+/// Token bucket shared by the connections of a stack, clones share the tokens
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct Shaper {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+#[cfg(feature = "std")]
+impl Shaper {
+    pub fn new(limit: RateLimit) -> Self {
+        Self { bucket: Arc::new(Mutex::new(TokenBucket::new(limit))) }
+    }
+
+    fn bucket(&self) -> MutexGuard<'_, TokenBucket> {
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.bucket().limit()
+    }
+
+    /// see `TokenBucket::ready_at`
+    pub fn ready_at(&self, len: usize, now: Instant) -> Option<Instant> {
+        self.bucket().ready_at(len, now)
+    }
+
+    pub fn charge(&self, len: usize, now: Instant) {
+        self.bucket().charge(len, now);
+    }
+}
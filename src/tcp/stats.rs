@@ -0,0 +1,124 @@
+use core::time::Duration;
+
+#[cfg(feature = "metrics")]
+use crate::socket_addr::Quad;
+
+/// Counters of one connection, see `TcpConnection::stats`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionStats {
+    pub segments_sent: u64,
+    pub segments_received: u64,
+    /// payload bytes sent, retransmissions included
+    pub bytes_sent: u64,
+    /// payload bytes received in order
+    pub bytes_received: u64,
+    /// payload bytes acknowledged by the peer
+    pub bytes_acked: u64,
+    pub retransmits: u64,
+    /// expirations of the retransmission timer
+    pub timeouts: u64,
+    /// timeouts F-RTO found spurious, the congestion window was restored
+    pub spurious_timeouts: u64,
+    /// ACKs which acknowledged nothing new while data was outstanding (RFC 5681)
+    pub dup_acks: u64,
+    /// segments sent again after duplicate ACKs
+    pub fast_retransmits: u64,
+    /// new segments beyond the congestion window sent for the first two duplicate ACKs (RFC 3042)
+    pub limited_transmits: u64,
+    /// fast retransmits of segments which were only reordered, the congestion window was restored
+    pub spurious_retransmits: u64,
+    /// holes the ACKs showed which were filled by a late segment
+    pub reordering_events: u64,
+    /// the farthest a segment was reordered, in duplicate ACKs
+    pub reordering: u32,
+    /// segments received ahead of rcv.nxt
+    pub out_of_order: u64,
+    /// segments with nothing new in them, retransmitted by the peer because an ACK got lost
+    pub dup_segments: u64,
+    /// payload bytes received again, those of segments partly received before included
+    pub dup_bytes: u64,
+    /// out of order segments dropped, wholly or partly, for lack of reassembly memory
+    pub reassembly_drops: u64,
+    /// ACKs answering a suspicious RST, SYN or ACK (RFC 5961)
+    pub challenge_acks: u64,
+    /// challenge ACKs not sent because of the rate limit
+    pub challenge_acks_suppressed: u64,
+    /// segments taking the header prediction fast path
+    pub predicted: u64,
+    /// ACKs which were owed when data was sent and rode along with it
+    pub piggybacked_acks: u64,
+    /// segments dropped for a MAC which didn't verify or a key the connection doesn't have (TCP-AO)
+    pub auth_failures: u64,
+    /// segments dropped for lacking TCP-AO on a connection with keys
+    pub auth_missing: u64,
+    /// segments dropped for arriving below the minimum ttl, see `ConnectionConfig::set_min_ttl`
+    pub min_ttl_drops: u64,
+    /// keep-alive probes sent, answered or not
+    pub keep_alive_probes: u64,
+    /// how long the peer took to answer the last keep-alive probe it answered
+    pub keep_alive_rtt: Option<Duration>,
+    /// probes of a closed window with data waiting
+    pub window_probes: u64,
+    pub cwnd: usize,
+    pub ssthresh: usize,
+    /// `None` until the first RTT sample
+    pub srtt: Option<Duration>,
+    /// receive buffer size, grown by autotuning
+    pub recv_buffer: usize,
+    /// send buffer size, grown by autotuning
+    pub send_buffer: usize,
+    /// bytes per second, `None` without pacing or before the first RTT sample
+    pub pacing_rate: Option<u64>,
+}
+
+/// counters as `tcp_stack_connection_*` and the windows, buffers and srtt as gauges,
+/// labelled with the `local` and `remote` address of the connection
+#[cfg(feature = "metrics")]
+impl ConnectionStats {
+    /// hand the values to the recorder installed for the `metrics` facade
+    pub fn publish(&self, quad: &Quad) {
+        let labels = [("local", quad.src().to_string()), ("remote", quad.dest().to_string())];
+        let counters = [
+            ("tcp_stack_connection_segments_sent", self.segments_sent),
+            ("tcp_stack_connection_segments_received", self.segments_received),
+            ("tcp_stack_connection_bytes_sent", self.bytes_sent),
+            ("tcp_stack_connection_bytes_received", self.bytes_received),
+            ("tcp_stack_connection_bytes_acked", self.bytes_acked),
+            ("tcp_stack_connection_retransmits", self.retransmits),
+            ("tcp_stack_connection_timeouts", self.timeouts),
+            ("tcp_stack_connection_spurious_timeouts", self.spurious_timeouts),
+            ("tcp_stack_connection_dup_acks", self.dup_acks),
+            ("tcp_stack_connection_fast_retransmits", self.fast_retransmits),
+            ("tcp_stack_connection_limited_transmits", self.limited_transmits),
+            ("tcp_stack_connection_spurious_retransmits", self.spurious_retransmits),
+            ("tcp_stack_connection_reordering_events", self.reordering_events),
+            ("tcp_stack_connection_out_of_order", self.out_of_order),
+            ("tcp_stack_connection_dup_segments", self.dup_segments),
+            ("tcp_stack_connection_dup_bytes", self.dup_bytes),
+            ("tcp_stack_connection_reassembly_drops", self.reassembly_drops),
+            ("tcp_stack_connection_challenge_acks", self.challenge_acks),
+            ("tcp_stack_connection_auth_failures", self.auth_failures),
+            ("tcp_stack_connection_auth_missing", self.auth_missing),
+            ("tcp_stack_connection_min_ttl_drops", self.min_ttl_drops),
+            ("tcp_stack_connection_keep_alive_probes", self.keep_alive_probes),
+            ("tcp_stack_connection_window_probes", self.window_probes),
+        ];
+        for (name, value) in counters {
+            ::metrics::counter!(name, &labels).absolute(value);
+        }
+        let gauges = [
+            ("tcp_stack_connection_cwnd", self.cwnd as f64),
+            ("tcp_stack_connection_ssthresh", self.ssthresh as f64),
+            ("tcp_stack_connection_recv_buffer", self.recv_buffer as f64),
+            ("tcp_stack_connection_send_buffer", self.send_buffer as f64),
+            ("tcp_stack_connection_reordering", self.reordering as f64),
+        ];
+        for (name, value) in gauges {
+            ::metrics::gauge!(name, &labels).set(value);
+        }
+        if let Some(srtt) = self.srtt {
+            ::metrics::gauge!("tcp_stack_connection_srtt_seconds", &labels).set(srtt.as_secs_f64());
+        }
+    }
+}
@@ -3,6 +3,7 @@ use core::fmt;
 /// Send Sequence Variables of TCB block
 /// See RFC 793 Section3 for more information
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SendSequenceSpace {
     /// send unacknowledged
     pub una: u32,
@@ -13,9 +14,9 @@ pub struct SendSequenceSpace {
     /// send urgent pointer
     pub up: bool,
     /// segment sequence number used for last window update
-    pub wl1: usize,
+    pub wl1: u32,
     /// segment acknowledgment number used for last window update
-    pub wl2: usize,
+    pub wl2: u32,
     /// initial send sequence number
     pub iss: u32,
 }
@@ -25,7 +26,7 @@ impl SendSequenceSpace {
     pub fn from_seq_number(iss: u32, wnd: u16) -> Self {
         Self {
             una: iss,
-            nxt: iss + 1,
+            nxt: iss.wrapping_add(1),
             wnd,
             up: false,
             wl1: 0,
@@ -35,21 +36,27 @@ impl SendSequenceSpace {
     }
 
     pub fn acceptable(&self, ack_number: u32) -> bool {
-        self.una < ack_number && ack_number <= self.nxt
+        seq_lt(self.una, ack_number) && seq_le(ack_number, self.nxt)
     }
 
     pub fn init_seq_number(&mut self, iss: u32) {
         self.iss = iss;
         self.una = self.iss;
-        self.nxt = self.una + 1;
+        self.nxt = self.una.wrapping_add(1);
         self.wnd = 10;
     }
+
+    /// number of sequence numbers sent but not acknowledged yet
+    pub fn in_flight(&self) -> u32 {
+        self.nxt.wrapping_sub(self.una)
+    }
 }
 
 
 /// Receive Sequence Variables of TCB block
 /// See RFC 793 Section3 for more information
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReceiveSequenceSpace {
     /// receive next
     pub nxt: u32,
@@ -64,7 +71,7 @@ pub struct ReceiveSequenceSpace {
 impl ReceiveSequenceSpace {
     pub fn from_seq_number(seq_number: u32, wnd: u16) -> Self {
         Self {
-            nxt: seq_number + 1,
+            nxt: seq_number.wrapping_add(1),
             wnd,
             up: false,
             irs: seq_number,
@@ -72,19 +79,30 @@ impl ReceiveSequenceSpace {
     }
     /// check if the beginning of segment falls in the window
     pub fn beginning_fall_in_wnd(&self, seq_number: u32) -> bool {
-        self.nxt <= seq_number && seq_number < self.nxt + self.wnd as u32
+        seq_le(self.nxt, seq_number) && seq_lt(seq_number, self.nxt.wrapping_add(self.wnd as u32))
     }
 
     /// check if the end of the segment falls in the window
     pub fn end_of_fall_in_wnd(&self, seq_number: u32, seq_len: u32) -> bool {
-        let seq = seq_number + seq_len - 1;
-        self.nxt <= seq && seq < self.nxt + self.wnd as u32
+        let seq = seq_number.wrapping_add(seq_len).wrapping_sub(1);
+        seq_le(self.nxt, seq) && seq_lt(seq, self.nxt.wrapping_add(self.wnd as u32))
+    }
+
+    /// segment acceptance test of RFC 793 Section 3.3
+    pub fn acceptable(&self, seq_number: u32, seq_len: u32) -> bool {
+        match (seq_len, self.wnd) {
+            (0, 0) => seq_number == self.nxt,
+            (0, _) => self.beginning_fall_in_wnd(seq_number),
+            (_, 0) => false,
+            (_, _) => self.beginning_fall_in_wnd(seq_number) || self.end_of_fall_in_wnd(seq_number, seq_len),
+        }
     }
 }
 
 /// State of a tcp
 /// See RFC 793 for more information
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TcpState {
     Closed,
     Listen,
@@ -102,17 +120,17 @@ pub enum TcpState {
 impl fmt::Display for TcpState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &TcpState::Closed => write!(f, "CLOSED"),
-            &TcpState::Listen => write!(f, "LISTEN"),
-            &TcpState::SynSent => write!(f, "SYN-SENT"),
-            &TcpState::SynReceived => write!(f, "SYN-RECEIVED"),
-            &TcpState::Established => write!(f, "ESTABLISHED"),
-            &TcpState::FinWait1 => write!(f, "FIN-WAIT-1"),
-            &TcpState::FinWait2 => write!(f, "FIN-WAIT-2"),
-            &TcpState::CloseWait => write!(f, "CLOSE-WAIT"),
-            &TcpState::Closing => write!(f, "CLOSING"),
-            &TcpState::LastAck => write!(f, "LAST-ACK"),
-            &TcpState::TimeWait => write!(f, "TIME-WAIT")
+            TcpState::Closed => write!(f, "CLOSED"),
+            TcpState::Listen => write!(f, "LISTEN"),
+            TcpState::SynSent => write!(f, "SYN-SENT"),
+            TcpState::SynReceived => write!(f, "SYN-RECEIVED"),
+            TcpState::Established => write!(f, "ESTABLISHED"),
+            TcpState::FinWait1 => write!(f, "FIN-WAIT-1"),
+            TcpState::FinWait2 => write!(f, "FIN-WAIT-2"),
+            TcpState::CloseWait => write!(f, "CLOSE-WAIT"),
+            TcpState::Closing => write!(f, "CLOSING"),
+            TcpState::LastAck => write!(f, "LAST-ACK"),
+            TcpState::TimeWait => write!(f, "TIME-WAIT")
         }
     }
 }
@@ -142,28 +160,31 @@ impl TcpControl {
             _ => 0
         }
     }
+
+    /// Return true if the control flag does not occupy sequence space
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 pub fn ensure_in_safe_range(data: u32) -> u32 {
-    data % u32::max_value()
+    data % u32::MAX
 }
 
+/// sequence numbers wrap around, `lhs` is before `rhs` when it's at most 2^31 behind
+pub fn seq_lt(lhs: u32, rhs: u32) -> bool {
+    (lhs.wrapping_sub(rhs) as i32) < 0
+}
 
-#[derive(Debug)]
-pub struct TcpOption {
-    /// maximum_segment_size
-    mss: Option<MaximumSegmentSize>,
-    /// SACK Permitted
-    sack: Option<SackPermitted>,
-    /// Timestamp
-    timestamp: Option<TimeStamp>,
+pub fn seq_le(lhs: u32, rhs: u32) -> bool {
+    lhs == rhs || seq_lt(lhs, rhs)
 }
 
-#[derive(Debug)]
-pub struct MaximumSegmentSize(usize);
+pub fn seq_gt(lhs: u32, rhs: u32) -> bool {
+    seq_lt(rhs, lhs)
+}
 
-#[derive(Debug)]
-pub struct SackPermitted(usize);
+pub fn seq_ge(lhs: u32, rhs: u32) -> bool {
+    seq_le(rhs, lhs)
+}
 
-#[derive(Debug)]
-pub struct TimeStamp(usize);
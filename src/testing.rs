@@ -0,0 +1,276 @@
+//! Helpers to express protocol scenarios in tests
+//!
+//! `seg()` builds the segments of a peer, `Recorder` is a device keeping what a
+//! connection sent, e.g.
+//!
+//! ```ignore
+//! let mut device = Recorder::new();
+//! let mut conn = seg().syn().seq(100).accept(&mut device)?.unwrap();
+//! seg().ack(device.last().unwrap().seq_end()?).seq(101).payload(b"hello").deliver(&mut conn, &mut device)?;
+//! ```
+
+use std::convert::TryFrom;
+use std::io::Result;
+use std::net::Ipv4Addr;
+
+use etherparse::{Ipv4Options, TcpHeaderSlice};
+
+use crate::data_link::DataLayer;
+use crate::reader_writer::{RawWriter, Segment};
+use crate::result;
+use crate::socket_addr::{Addr, Quad};
+use crate::tcp::connection::{ConnectionConfig, TcpConnection};
+use crate::tcp::options::TcpOption;
+use crate::tcp::packet::TcpIpHeader;
+use crate::tcp::vars::TcpControl;
+
+/// address of the peer segments are sent from by default
+pub const PEER: Addr = Addr::v4(Ipv4Addr::new(10, 0, 0, 2), 4000);
+/// address of the connection segments are sent to by default
+pub const LOCAL: Addr = Addr::v4(Ipv4Addr::new(10, 0, 0, 1), 80);
+
+/// a segment from `PEER` to `LOCAL` without flags, sequence number 0 and a 64KB window
+pub fn seg() -> SegmentBuilder {
+    SegmentBuilder {
+        src: PEER,
+        dest: LOCAL,
+        seq: 0,
+        ack: None,
+        window: u16::MAX,
+        ttl: 64,
+        tos: 0,
+        controls: Vec::new(),
+        options: Vec::new(),
+        ip_options: Vec::new(),
+        payload: Vec::new(),
+    }
+}
+
+/// Fluent builder of an ipv4 tcp segment with valid checksums
+#[derive(Debug, Clone)]
+pub struct SegmentBuilder {
+    src: Addr,
+    dest: Addr,
+    seq: u32,
+    ack: Option<u32>,
+    window: u16,
+    ttl: u8,
+    tos: u8,
+    controls: Vec<TcpControl>,
+    options: Vec<TcpOption>,
+    ip_options: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl SegmentBuilder {
+    pub fn from(mut self, src: Addr) -> Self {
+        self.src = src;
+        self
+    }
+
+    pub fn to(mut self, dest: Addr) -> Self {
+        self.dest = dest;
+        self
+    }
+
+    pub fn seq(mut self, seq: u32) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    /// set the ACK flag and the acknowledgment number
+    pub fn ack(mut self, ack: u32) -> Self {
+        self.ack = Some(ack);
+        self
+    }
+
+    pub fn window(mut self, window: u16) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// type of service byte, DSCP << 2 | ECN
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.tos = tos;
+        self
+    }
+
+    pub fn syn(self) -> Self {
+        self.control(TcpControl::SYN)
+    }
+
+    pub fn fin(self) -> Self {
+        self.control(TcpControl::FIN)
+    }
+
+    pub fn rst(self) -> Self {
+        self.control(TcpControl::RST)
+    }
+
+    pub fn psh(self) -> Self {
+        self.control(TcpControl::PSH)
+    }
+
+    pub fn urg(self) -> Self {
+        self.control(TcpControl::URG)
+    }
+
+    pub fn control(mut self, control: TcpControl) -> Self {
+        self.controls.push(control);
+        self
+    }
+
+    pub fn option(mut self, option: TcpOption) -> Self {
+        self.options.push(option);
+        self
+    }
+
+    /// raw options of the ip header, padded to a multiple of 4 bytes
+    pub fn ip_options(mut self, raw: &[u8]) -> Self {
+        self.ip_options = raw.to_vec();
+        self.ip_options.resize(raw.len().next_multiple_of(4), 0);
+        self
+    }
+
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// the ip packet
+    pub fn build(&self) -> result::Result<Vec<u8>> {
+        self.frame(0)
+    }
+
+    /// the ip packet after `link_header_len` zeroed bytes of link header
+    pub fn frame(&self, link_header_len: usize) -> result::Result<Vec<u8>> {
+        let mut packet = TcpIpHeader::from_quad(&Quad::new(self.src, self.dest), self.seq, self.window, self.ttl)?;
+        packet.set_tos(self.tos);
+        for control in &self.controls {
+            packet.set_control(*control);
+        }
+        if let Some(ack) = self.ack {
+            packet.set_ack_number(ack);
+        }
+        if !self.options.is_empty() {
+            packet.set_options(&self.options)?;
+        }
+        packet.ip_header.options = Ipv4Options::try_from(self.ip_options.as_slice())
+            .map_err(|_| result::Error::OptionsTooLong(self.ip_options.len()))?;
+        packet.finalize(&self.payload)?;
+        let mut writer = RawWriter::new(0);
+        writer.write_link_header(link_header_len)?;
+        writer.write_header(&packet)?;
+        writer.write_payload(&self.payload)?;
+        Ok(writer.finalize()?.to_vec())
+    }
+
+    /// passive open of a connection by this SYN, `None` if it isn't one
+    pub fn accept<L: DataLayer + ?Sized>(&self, device: &mut L) -> result::Result<Option<TcpConnection>> {
+        self.accept_with_config(device, ConnectionConfig::default())
+    }
+
+    pub fn accept_with_config<L: DataLayer + ?Sized>(
+        &self,
+        device: &mut L,
+        config: ConnectionConfig,
+    ) -> result::Result<Option<TcpConnection>> {
+        let frame = self.build()?;
+        let segment = Segment::parse(&frame, 0)?;
+        TcpConnection::accept_with_config(device, segment.ip(), segment.tcp(), segment.payload(), config)
+    }
+
+    /// hand the segment to `conn`, which answers on `device`
+    pub fn deliver<L: DataLayer + ?Sized>(&self, conn: &mut TcpConnection, device: &mut L) -> result::Result<()> {
+        let frame = self.build()?;
+        let segment = Segment::parse(&frame, 0)?;
+        if !conn.on_ip_header(segment.ip()) {
+            return Ok(());
+        }
+        conn.on_segment(device, segment.tcp(), segment.payload())
+    }
+}
+
+/// A segment sent by a connection, as recorded by `Recorder`
+#[derive(Debug, Clone)]
+pub struct Sent {
+    frame: Vec<u8>,
+    header_len: usize,
+}
+
+impl Sent {
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    pub fn segment(&self) -> result::Result<Segment<'_>> {
+        Segment::parse(&self.frame, self.header_len)
+    }
+
+    pub fn tcp(&self) -> result::Result<TcpHeaderSlice<'_>> {
+        Ok(self.segment()?.tcp().clone())
+    }
+
+    pub fn payload(&self) -> result::Result<&[u8]> {
+        Ok(self.segment()?.payload())
+    }
+
+    /// the sequence number after the segment, SYN and FIN count as one
+    pub fn seq_end(&self) -> result::Result<u32> {
+        let segment = self.segment()?;
+        let tcp = segment.tcp();
+        let len = segment.payload().len() as u32 + tcp.syn() as u32 + tcp.fin() as u32;
+        Ok(tcp.sequence_number().wrapping_add(len))
+    }
+}
+
+/// Device keeping every frame sent on it, receives nothing
+#[derive(Debug, Default)]
+pub struct Recorder {
+    sent: Vec<Sent>,
+    header_len: usize,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// frames start with `header_len` bytes of link header
+    pub fn with_header_len(header_len: usize) -> Self {
+        Self { sent: Vec::new(), header_len }
+    }
+
+    pub fn sent(&self) -> &[Sent] {
+        &self.sent
+    }
+
+    pub fn last(&self) -> Option<&Sent> {
+        self.sent.last()
+    }
+
+    /// the frames sent since the last call
+    pub fn take(&mut self) -> Vec<Sent> {
+        std::mem::take(&mut self.sent)
+    }
+}
+
+impl DataLayer for Recorder {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.sent.push(Sent { frame: data.to_vec(), header_len: self.header_len });
+        Ok(data.len())
+    }
+
+    fn recv(&mut self, _data: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn header_len(&self) -> usize {
+        self.header_len
+    }
+}
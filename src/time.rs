@@ -0,0 +1,91 @@
+//! The point in time of the connections and their timers
+//!
+//! with the `std` feature it is `std::time::Instant`. without it there is no system clock and
+//! an `Instant` is the time since an epoch the `Clock` of the caller picks, e.g. the start of
+//! a hardware timer of the device
+#[cfg(feature = "std")]
+pub use std::time::Instant;
+
+#[cfg(not(feature = "std"))]
+pub use self::epoch::Instant;
+
+#[cfg(not(feature = "std"))]
+mod epoch {
+    use core::ops::{Add, AddAssign, Sub, SubAssign};
+    use core::time::Duration;
+
+    /// A point in time, `since_epoch` after the epoch of the clock
+    #[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+    pub struct Instant(Duration);
+
+    impl Instant {
+        /// the epoch of the clock
+        pub const EPOCH: Instant = Instant(Duration::ZERO);
+
+        pub const fn from_epoch(since_epoch: Duration) -> Self {
+            Instant(since_epoch)
+        }
+
+        pub const fn since_epoch(&self) -> Duration {
+            self.0
+        }
+
+        /// zero if `earlier` is later, like `std::time::Instant`
+        pub fn duration_since(&self, earlier: Instant) -> Duration {
+            self.saturating_duration_since(earlier)
+        }
+
+        pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+            self.0.checked_sub(earlier.0)
+        }
+
+        pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+            self.0.saturating_sub(earlier.0)
+        }
+
+        pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_add(duration).map(Instant)
+        }
+
+        /// `None` before the epoch
+        pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_sub(duration).map(Instant)
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Instant;
+
+        fn add(self, duration: Duration) -> Instant {
+            self.checked_add(duration).expect("overflow when adding duration to instant")
+        }
+    }
+
+    impl AddAssign<Duration> for Instant {
+        fn add_assign(&mut self, duration: Duration) {
+            *self = *self + duration;
+        }
+    }
+
+    impl Sub<Duration> for Instant {
+        type Output = Instant;
+
+        fn sub(self, duration: Duration) -> Instant {
+            self.checked_sub(duration).expect("overflow when subtracting duration from instant")
+        }
+    }
+
+    impl SubAssign<Duration> for Instant {
+        fn sub_assign(&mut self, duration: Duration) {
+            *self = *self - duration;
+        }
+    }
+
+    impl Sub<Instant> for Instant {
+        type Output = Duration;
+
+        fn sub(self, earlier: Instant) -> Duration {
+            self.duration_since(earlier)
+        }
+    }
+}
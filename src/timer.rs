@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{system_clock, Clock};
+
+pub use crate::meta::DEFAULT_TIMER_RESOLUTION;
+pub const DEFAULT_WHEEL_SLOTS: usize = 512;
+
+/// unique across wheels, cancelling a timer on the wrong wheel does nothing
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct TimerId(u64);
+
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+struct Entry<T> {
+    id: TimerId,
+    /// absolute tick at which the timer fires
+    deadline: u64,
+    value: T,
+}
+
+/// Hashed timer wheel
+/// a timer scheduled `n` ticks ahead lives in slot `(current + n) % slots`
+/// and is skipped by the sweep until its own tick is reached,
+/// so schedule and cancel are O(1) and a tick only visits a single slot
+pub struct TimerWheel<T> {
+    slots: Vec<Vec<Entry<T>>>,
+    resolution: Duration,
+    start: Instant,
+    /// last tick swept by `expire`
+    current: u64,
+    /// timer id -> slot index, to find a timer when it's cancelled
+    index: HashMap<TimerId, usize>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T> TimerWheel<T> {
+    pub fn new(start: Instant) -> Self {
+        Self::with_resolution(start, DEFAULT_TIMER_RESOLUTION, DEFAULT_WHEEL_SLOTS)
+    }
+
+    pub fn with_resolution(start: Instant, resolution: Duration, slots: usize) -> Self {
+        Self::with_clock_at(system_clock(), start, resolution, slots)
+    }
+
+    /// the wheel starts at the current time of `clock`, see `now`
+    pub fn with_clock(clock: Arc<dyn Clock>, resolution: Duration, slots: usize) -> Self {
+        let start = clock.now();
+        Self::with_clock_at(clock, start, resolution, slots)
+    }
+
+    fn with_clock_at(clock: Arc<dyn Clock>, start: Instant, resolution: Duration, slots: usize) -> Self {
+        assert!(slots > 0, "timer wheel needs at least one slot");
+        assert!(resolution > Duration::from_nanos(0), "timer resolution must not be zero");
+        Self {
+            slots: (0..slots).map(|_| Vec::new()).collect(),
+            resolution,
+            start,
+            current: 0,
+            index: HashMap::new(),
+            clock,
+        }
+    }
+
+    /// current time of the clock of the wheel, what the callers pass as `now`
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    pub fn resolution(&self) -> Duration {
+        self.resolution
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// fire `value` once `after` elapsed from `now`, rounded up to the resolution
+    pub fn schedule(&mut self, now: Instant, after: Duration, value: T) -> TimerId {
+        let at = self.tick_of(now + after + self.resolution - Duration::from_nanos(1));
+        let deadline = at.max(self.current + 1);
+        let slot = (deadline % self.slots.len() as u64) as usize;
+        let id = TimerId(NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed));
+        self.slots[slot].push(Entry { id, deadline, value });
+        self.index.insert(id, slot);
+        id
+    }
+
+    /// return the value of the cancelled timer, `None` if it already fired
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        let slot = self.index.remove(&id)?;
+        let entries = &mut self.slots[slot];
+        let pos = entries.iter().position(|entry| entry.id == id)?;
+        Some(entries.swap_remove(pos).value)
+    }
+
+    /// time left from `now` until the earliest timer fires, `None` if no timer is pending
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        let deadline = self.slots.iter()
+            .flat_map(|entries| entries.iter().map(|entry| entry.deadline))
+            .min()?;
+        Some(self.instant_of(deadline).saturating_duration_since(now))
+    }
+
+    /// advance the wheel to `now` and return every expired timer in firing order
+    pub fn expire(&mut self, now: Instant) -> Vec<(TimerId, T)> {
+        let target = self.tick_of(now);
+        if target <= self.current {
+            return Vec::new();
+        }
+        let mut expired = Vec::new();
+        // a full rotation visits every slot, no need to sweep the same slot twice
+        let first = target.saturating_sub(self.slots.len() as u64 - 1).max(self.current + 1);
+        for tick in first..=target {
+            let slot = (tick % self.slots.len() as u64) as usize;
+            let entries = &mut self.slots[slot];
+            let mut i = 0;
+            while i < entries.len() {
+                if entries[i].deadline <= target {
+                    let entry = entries.swap_remove(i);
+                    self.index.remove(&entry.id);
+                    expired.push(entry);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.current = target;
+        expired.sort_by_key(|entry| (entry.deadline, entry.id));
+        expired.into_iter().map(|entry| (entry.id, entry.value)).collect()
+    }
+
+    fn instant_of(&self, tick: u64) -> Instant {
+        self.start + Duration::from_nanos((self.resolution.as_nanos() as u64).saturating_mul(tick))
+    }
+
+    fn tick_of(&self, at: Instant) -> u64 {
+        let elapsed = at.saturating_duration_since(self.start);
+        (elapsed.as_nanos() / self.resolution.as_nanos()) as u64
+    }
+}
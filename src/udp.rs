@@ -0,0 +1,190 @@
+//! UDP (RFC 768) datagrams and the ports of a stack bound by `socket::UdpSocket`
+//!
+//! there is no fragmentation, a datagram has to fit the mtu of the stack
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ops::RangeInclusive;
+use std::task::Waker;
+use std::time::Duration;
+
+use crate::checksum::Checksum;
+
+pub const PROTOCOL: u8 = 17;
+pub const HEADER_LEN: usize = 8;
+/// datagrams waiting for `recv_from` on a socket, the ones arriving beyond are dropped
+pub const RECV_QUEUE_LEN: usize = 64;
+
+const IP_HEADER_LEN: usize = 20;
+
+/// ip packet carrying `payload` from `src` to `dst`
+pub fn datagram(src: SocketAddrV4, dst: SocketAddrV4, ttl: u8, payload: &[u8]) -> Vec<u8> {
+    let udp_len = HEADER_LEN + payload.len();
+    let total_len = IP_HEADER_LEN + udp_len;
+    let mut packet = Vec::with_capacity(total_len);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&(total_len as u16).to_be_bytes());
+    // don't fragment, the datagram fits the mtu or isn't sent
+    packet.extend_from_slice(&[0, 0, 0x40, 0, ttl, PROTOCOL, 0, 0]);
+    packet.extend_from_slice(&src.ip().octets());
+    packet.extend_from_slice(&dst.ip().octets());
+    let mut checksum = Checksum::new();
+    checksum.add(&packet);
+    packet[10..12].copy_from_slice(&checksum.finish().to_be_bytes());
+
+    packet.extend_from_slice(&src.port().to_be_bytes());
+    packet.extend_from_slice(&dst.port().to_be_bytes());
+    packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(payload);
+    let mut checksum = Checksum::ipv4_pseudo_header(src.ip().octets(), dst.ip().octets(), PROTOCOL, udp_len);
+    checksum.add(&packet[IP_HEADER_LEN..]);
+    // zero means no checksum, its ones' complement is sent instead
+    let sum = match checksum.finish() {
+        0 => 0xffff,
+        sum => sum,
+    };
+    packet[IP_HEADER_LEN + 6..IP_HEADER_LEN + 8].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+/// source, destination and payload of the udp datagram in the ip `packet`. `None` if it
+/// is cut short, a fragment or its checksum is wrong
+pub fn parse(packet: &[u8]) -> Option<(SocketAddrV4, SocketAddrV4, &[u8])> {
+    if packet.len() < IP_HEADER_LEN || packet[0] >> 4 != 4 || packet[9] != PROTOCOL {
+        return None;
+    }
+    let ihl = (packet[0] & 0x0f) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    // more fragments or a fragment offset
+    if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+        return None;
+    }
+    let packet = packet.get(..total_len)?;
+    let udp = packet.get(ihl..)?;
+    if udp.len() < HEADER_LEN {
+        return None;
+    }
+    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    let udp = udp.get(..udp_len).filter(|_| udp_len >= HEADER_LEN)?;
+    let src_ip = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+    let dst_ip = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+    if udp[6..8] != [0, 0] {
+        let mut checksum = Checksum::ipv4_pseudo_header(src_ip.octets(), dst_ip.octets(), PROTOCOL, udp_len);
+        checksum.add(udp);
+        if checksum.finish() != 0 {
+            return None;
+        }
+    }
+    let src = SocketAddrV4::new(src_ip, u16::from_be_bytes([udp[0], udp[1]]));
+    let dst = SocketAddrV4::new(dst_ip, u16::from_be_bytes([udp[2], udp[3]]));
+    Some((src, dst, &udp[HEADER_LEN..]))
+}
+
+/// A bound port and the datagrams received on it
+#[derive(Default)]
+pub(crate) struct Binding {
+    pub(crate) queue: VecDeque<(SocketAddrV4, Vec<u8>)>,
+    pub(crate) waker: Option<Waker>,
+    /// limit of the blocking `recv_from` of `UdpSocket`
+    pub(crate) read_timeout: Option<Duration>,
+}
+
+/// The udp ports of a stack, shared by its shards
+#[derive(Default)]
+pub(crate) struct UdpTable {
+    bindings: HashMap<SocketAddrV4, Binding>,
+    /// ip packets the sockets sent, the next flush of any shard puts them on the device
+    outbox: VecDeque<Vec<u8>>,
+    next_port: u16,
+}
+
+impl UdpTable {
+    /// bind `local`, port 0 picks a free one of `ephemeral`. a specific address and the
+    /// wildcard don't share a port
+    pub(crate) fn bind(&mut self, local: SocketAddrV4, ephemeral: RangeInclusive<u16>) -> io::Result<SocketAddrV4> {
+        let local = match local.port() {
+            0 => {
+                let (first, last) = (*ephemeral.start(), *ephemeral.end());
+                let mut port = self.next_port.clamp(first, last);
+                let mut free = None;
+                for _ in first..=last {
+                    let next = if port >= last { first } else { port + 1 };
+                    if !self.port_in_use(port) {
+                        free = Some(port);
+                        self.next_port = next;
+                        break;
+                    }
+                    port = next;
+                }
+                let port = free.ok_or_else(|| io::Error::new(io::ErrorKind::AddrNotAvailable, "no free ephemeral port"))?;
+                SocketAddrV4::new(*local.ip(), port)
+            }
+            port if self.port_in_use(port) => return Err(io::ErrorKind::AddrInUse.into()),
+            _ => local,
+        };
+        match self.bindings.entry(local) {
+            Entry::Occupied(_) => Err(io::ErrorKind::AddrInUse.into()),
+            Entry::Vacant(entry) => {
+                entry.insert(Binding::default());
+                Ok(local)
+            }
+        }
+    }
+
+    pub(crate) fn unbind(&mut self, local: &SocketAddrV4) {
+        self.bindings.remove(local);
+    }
+
+    pub(crate) fn binding_mut(&mut self, local: &SocketAddrV4) -> Option<&mut Binding> {
+        self.bindings.get_mut(local)
+    }
+
+    fn port_in_use(&self, port: u16) -> bool {
+        self.bindings.keys().any(|local| local.port() == port)
+    }
+
+    /// queue the datagram in the ip `packet` for the socket bound to its destination or to
+    /// the wildcard on its port. false if no socket is, broken datagrams are dropped
+    pub(crate) fn deliver(&mut self, packet: &[u8]) -> bool {
+        let (src, dst, payload) = match parse(packet) {
+            Some(datagram) => datagram,
+            None => return true,
+        };
+        let wildcard = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, dst.port());
+        let binding = match self.bindings.get_mut(&dst) {
+            Some(binding) => binding,
+            None => match self.bindings.get_mut(&wildcard) {
+                Some(binding) => binding,
+                None => return false,
+            },
+        };
+        if binding.queue.len() < RECV_QUEUE_LEN {
+            binding.queue.push_back((src, payload.to_vec()));
+        }
+        if let Some(waker) = binding.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+
+    /// queue an ip packet for the next flush
+    pub(crate) fn send(&mut self, packet: Vec<u8>) {
+        self.outbox.push_back(packet);
+    }
+
+    pub(crate) fn take_outbox(&mut self) -> VecDeque<Vec<u8>> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// the device went away, wake the readers to tell them
+    pub(crate) fn wake_all(&mut self) {
+        for binding in self.bindings.values_mut() {
+            if let Some(waker) = binding.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
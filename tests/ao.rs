@@ -0,0 +1,98 @@
+//! TCP-AO between two stacks over a loopback pair, run with `cargo test --features tcp-ao`
+#![cfg(feature = "tcp-ao")]
+
+mod common;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::thread;
+use std::time::Duration;
+
+use tcp_stack::socket::{TcpListener, TcpStream};
+use tcp_stack::tcp::ao::{MacAlgorithm, Mkt};
+use tcp_stack::tcp::connection::ConnectionConfig;
+use tcp_stack::tcp::vars::TcpState;
+
+use common::{stacks, CLIENT, SERVER};
+
+fn key(peer: Ipv4Addr, send_id: u8, recv_id: u8, algorithm: MacAlgorithm, secret: &[u8]) -> Mkt {
+    Mkt::new(IpAddr::V4(peer), send_id, recv_id, algorithm, secret)
+}
+
+fn exchange(algorithm: MacAlgorithm) {
+    // the sequence numbers of both directions wrap around
+    let mut config = ConnectionConfig::default();
+    config.set_init_send_seq_number(u32::MAX - 3000);
+    let (client, server) = stacks(config);
+    client.add_auth_key(key(SERVER, 1, 1, algorithm, b"first secret")).unwrap();
+    server.add_auth_key(key(CLIENT, 1, 1, algorithm, b"first secret").prefix(24)).unwrap();
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let echo = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut buf = vec![0; 100_000];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+        stream
+    });
+    let mut stream = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    stream.write_all(&data).unwrap();
+    let mut buf = vec![0; data.len()];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data);
+    let mut peer = echo.join().unwrap();
+    let status = stream.auth_status().unwrap().unwrap();
+    assert_eq!((status.send_sne, status.recv_sne), (1, 1));
+    assert_eq!(stream.stats().unwrap().auth_failures, 0);
+
+    // rotate to the keys 2 and 3, the client asks the server to sign with its key 3
+    client.add_auth_key(key(SERVER, 2, 3, algorithm, b"next secret")).unwrap();
+    server.add_auth_key(key(CLIENT, 3, 2, algorithm, b"next secret").prefix(24)).unwrap();
+    assert_eq!(client.remove_auth_key(IpAddr::V4(SERVER), 32, 1).unwrap_err().kind(), ErrorKind::ResourceBusy);
+    stream.set_auth_rnext(3).unwrap();
+    stream.write_all(b"x").unwrap();
+    let mut byte = [0; 1];
+    peer.read_exact(&mut byte).unwrap();
+    assert_eq!(peer.auth_status().unwrap().unwrap().current_key, 3);
+    peer.set_auth_rnext(2).unwrap();
+    peer.write_all(b"y").unwrap();
+    stream.read_exact(&mut byte).unwrap();
+    assert_eq!(stream.auth_status().unwrap().unwrap().current_key, 2);
+    assert!(client.remove_auth_key(IpAddr::V4(SERVER), 32, 1).unwrap());
+    assert!(server.remove_auth_key(IpAddr::V4(CLIENT), 24, 1).unwrap());
+    stream.write_all(b"z").unwrap();
+    peer.read_exact(&mut byte).unwrap();
+    assert_eq!(&byte, b"z");
+}
+
+#[test]
+fn hmac_sha1() {
+    exchange(MacAlgorithm::HmacSha1);
+}
+
+#[test]
+fn aes_cmac() {
+    exchange(MacAlgorithm::AesCmac128);
+}
+
+#[test]
+fn hmac_sha256() {
+    exchange(MacAlgorithm::HmacSha256);
+}
+
+#[test]
+fn unauthentic_syn_is_dropped() {
+    let mut config = ConnectionConfig::default();
+    config.set_syn_r2(Duration::from_millis(500));
+    for secret in [Some(&b"wrong secret"[..]), None] {
+        let (client, server) = stacks(config);
+        if let Some(secret) = secret {
+            client.add_auth_key(key(SERVER, 1, 1, MacAlgorithm::HmacSha1, secret)).unwrap();
+        }
+        server.add_auth_key(key(CLIENT, 1, 1, MacAlgorithm::HmacSha1, b"right secret")).unwrap();
+        let _listener = TcpListener::bind(&server, 80).unwrap();
+        let err = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(server.connections().iter().all(|info| info.state == TcpState::Listen));
+    }
+}
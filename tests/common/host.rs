@@ -0,0 +1,84 @@
+//! A stack on a TUN interface talking to the kernel's TCP, the tests using it need
+//! CAP_NET_ADMIN and run with `cargo test --features host-tests`
+
+use std::io;
+use std::net::Ipv4Addr;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcp_stack::config::StackConfig;
+use tcp_stack::data_link::faulty::{FaultConfig, FaultyLink};
+use tcp_stack::data_link::iface;
+use tcp_stack::data_link::tun::TunQueue;
+use tcp_stack::data_link::DataLayer;
+use tcp_stack::socket::{TcpListener, TcpStream};
+use tcp_stack::stack::NetStack;
+
+/// how long a test waits for the peer before it fails
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A stack on its own TUN interface, the kernel side of the interface has `host`
+/// and the stack `addr` in the same /24
+pub struct HostLink {
+    pub name: String,
+    pub host: Ipv4Addr,
+    pub addr: Ipv4Addr,
+    pub stack: NetStack,
+}
+
+impl HostLink {
+    /// interface `name` with the subnet 10.71.`net`.0/24, every test uses its own
+    pub fn open(name: &str, net: u8) -> Self {
+        Self::open_with(name, net, |queue| queue)
+    }
+
+    /// the frames the kernel sends to the stack are impaired in userspace,
+    /// for hosts without the netem qdisc
+    pub fn open_faulty(name: &str, net: u8, inbound: FaultConfig) -> Self {
+        Self::open_with(name, net, |queue| FaultyLink::with_config(queue, FaultConfig::default(), inbound))
+    }
+
+    fn open_with<L, F>(name: &str, net: u8, wrap: F) -> Self
+    where
+        L: DataLayer + Send + 'static,
+        F: FnOnce(TunQueue) -> L,
+    {
+        let host = Ipv4Addr::new(10, 71, net, 1);
+        let addr = Ipv4Addr::new(10, 71, net, 2);
+        let mut queues = TunQueue::open(name, 1).expect("open tun, CAP_NET_ADMIN needed");
+        iface::set_addr(name, host, 24).and_then(|_| iface::set_up(name, true)).expect("configure interface");
+        let config = StackConfig::builder().addr(addr).build().unwrap();
+        let stack = NetStack::with_device(wrap(queues.remove(0)), config).unwrap();
+        Self {
+            name: name.to_string(),
+            host,
+            addr,
+            stack,
+        }
+    }
+
+    /// impair the packets the kernel sends to the stack with tc-netem,
+    /// false if `tc` or the netem qdisc isn't available
+    pub fn netem(&self, args: &[&str]) -> bool {
+        let status = Command::new("tc")
+            .args(["qdisc", "add", "dev", &self.name, "root", "netem"])
+            .args(args)
+            .status();
+        matches!(status, Ok(status) if status.success())
+    }
+}
+
+/// `accept` with a deadline, the blocking one would hang a failing test
+pub fn accept(listener: &TcpListener) -> TcpStream {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match listener.try_accept() {
+            Ok(stream) => return stream,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err(e) => panic!("accept: {}", e),
+        }
+    }
+}
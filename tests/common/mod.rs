@@ -0,0 +1,37 @@
+//! Helpers of the integration tests: the stacks on the two ends of a loopback link and,
+//! in `host`, a stack on a TUN interface connected to the kernel
+// every test crate uses a part of them
+#![allow(dead_code)]
+
+#[cfg(feature = "host-tests")]
+pub mod host;
+
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use tcp_stack::config::StackConfig;
+use tcp_stack::data_link::loopback::Loopback;
+use tcp_stack::stack::NetStack;
+use tcp_stack::tcp::connection::ConnectionConfig;
+
+pub const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+pub const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+/// how long a test waits for a handshake or a read before it fails
+pub const TIMEOUT: Option<Duration> = Some(Duration::from_secs(5));
+
+/// a stack at `CLIENT` and one at `SERVER` on a loopback link, both with `config`
+pub fn stacks(config: ConnectionConfig) -> (NetStack, NetStack) {
+    let open = |addr| StackConfig::builder().addr(addr).connection(config).build().unwrap();
+    pair(open(CLIENT), open(SERVER))
+}
+
+/// stacks with their own configuration on the two ends of a loopback link
+pub fn pair(client: StackConfig, server: StackConfig) -> (NetStack, NetStack) {
+    let (a, b) = Loopback::pair();
+    (NetStack::with_device(a, client).unwrap(), NetStack::with_device(b, server).unwrap())
+}
+
+/// deterministic payload, so corrupted or misplaced bytes are noticed
+pub fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
@@ -0,0 +1,222 @@
+//! Conformance against the Linux TCP stack over a TUN interface,
+//! run as root with `cargo test --features host-tests`
+#![cfg(all(target_os = "linux", feature = "host-tests"))]
+
+mod common;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, SocketAddrV4};
+use std::thread;
+use std::time::Duration;
+
+use tcp_stack::data_link::faulty::FaultConfig;
+use tcp_stack::socket::{TcpListener, TcpStream};
+use tcp_stack::tcp::vars::TcpState;
+
+use common::host::{accept, HostLink, TIMEOUT};
+use common::pattern;
+
+fn kernel_connect(link: &HostLink, port: u16) -> std::io::Result<std::net::TcpStream> {
+    let stream = std::net::TcpStream::connect_timeout(&SocketAddr::V4(SocketAddrV4::new(link.addr, port)), TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+fn kernel_listener(link: &HostLink) -> std::net::TcpListener {
+    std::net::TcpListener::bind(SocketAddrV4::new(link.host, 0)).unwrap()
+}
+
+fn stack_connect(link: &HostLink, port: u16) -> TcpStream {
+    let stream = TcpStream::connect(&link.stack, SocketAddrV4::new(link.host, port)).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    stream.set_write_timeout(Some(TIMEOUT)).unwrap();
+    stream
+}
+
+#[test]
+fn handshake_from_kernel() {
+    let link = HostLink::open("cf0", 0);
+    let listener = TcpListener::bind(&link.stack, 80).unwrap();
+    let client = kernel_connect(&link, 80).unwrap();
+    let server = accept(&listener);
+    assert_eq!(server.state(), TcpState::Established);
+    assert_eq!(SocketAddr::V4(server.peer_addr()), client.local_addr().unwrap());
+    assert_eq!(*server.local_addr().ip(), link.addr);
+}
+
+#[test]
+fn handshake_to_kernel() {
+    let link = HostLink::open("cf1", 1);
+    let listener = kernel_listener(&link);
+    let port = listener.local_addr().unwrap().port();
+    let client = stack_connect(&link, port);
+    client.wait_established(Some(TIMEOUT)).unwrap();
+    let (_server, peer) = listener.accept().unwrap();
+    assert_eq!(peer, SocketAddr::V4(client.local_addr()));
+}
+
+#[test]
+fn bulk_transfer_both_directions() {
+    let link = HostLink::open("cf2", 2);
+    let listener = TcpListener::bind(&link.stack, 80).unwrap();
+    let mut client = kernel_connect(&link, 80).unwrap();
+    let mut server = accept(&listener);
+    let upload = pattern(4_000_000);
+    let download = pattern(3_000_000);
+
+    let expected = download.clone();
+    let reader = thread::spawn(move || {
+        client.write_all(&upload).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        assert!(received == expected, "download corrupted");
+    });
+    let mut received = Vec::new();
+    server.read_to_end(&mut received).unwrap();
+    assert!(received == pattern(4_000_000), "upload corrupted");
+    server.write_all(&download).unwrap();
+    server.shutdown().unwrap();
+    reader.join().unwrap();
+}
+
+#[test]
+fn graceful_close_by_kernel() {
+    let link = HostLink::open("cf3", 3);
+    let listener = TcpListener::bind(&link.stack, 80).unwrap();
+    let mut client = kernel_connect(&link, 80).unwrap();
+    let mut server = accept(&listener);
+    server.set_read_timeout(Some(TIMEOUT)).unwrap();
+    client.write_all(b"last words").unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+
+    let mut received = Vec::new();
+    server.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"last words");
+    assert_eq!(server.state(), TcpState::CloseWait);
+    // the peer can still receive after its FIN
+    server.write_all(b"reply").unwrap();
+    server.shutdown().unwrap();
+    let mut reply = Vec::new();
+    client.read_to_end(&mut reply).unwrap();
+    assert_eq!(reply, b"reply");
+    server.wait_sent(TIMEOUT).unwrap();
+    assert_eq!(server.state(), TcpState::Closed);
+}
+
+#[test]
+fn graceful_close_by_stack() {
+    let link = HostLink::open("cf4", 4);
+    let listener = kernel_listener(&link);
+    let port = listener.local_addr().unwrap().port();
+    let mut client = stack_connect(&link, port);
+    client.wait_established(Some(TIMEOUT)).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+    server.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+    client.write_all(b"goodbye").unwrap();
+    client.shutdown().unwrap();
+    let mut received = Vec::new();
+    server.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"goodbye");
+    drop(server);
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    client.wait_sent(TIMEOUT).unwrap();
+    assert_eq!(client.state(), TcpState::TimeWait);
+}
+
+#[test]
+fn reset_on_closed_ports() {
+    let link = HostLink::open("cf5", 5);
+    // nothing listens on the stack
+    let err = kernel_connect(&link, 81).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+
+    // nothing listens on the kernel
+    let unused = kernel_listener(&link);
+    let port = unused.local_addr().unwrap().port();
+    drop(unused);
+    let err = TcpStream::connect_timeout(&link.stack, SocketAddrV4::new(link.host, port), TIMEOUT).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn reset_by_kernel_abort() {
+    let link = HostLink::open("cf6", 6);
+    let listener = TcpListener::bind(&link.stack, 80).unwrap();
+    let client = kernel_connect(&link, 80).unwrap();
+    let mut server = accept(&listener);
+    server.set_read_timeout(Some(TIMEOUT)).unwrap();
+    // SO_LINGER with a zero timeout makes close send a RST
+    let linger = libc::linger { l_onoff: 1, l_linger: 0 };
+    let res = unsafe {
+        libc::setsockopt(
+            std::os::unix::io::AsRawFd::as_raw_fd(&client),
+            libc::SOL_SOCKET,
+            libc::SO_LINGER,
+            &linger as *const libc::linger as *const libc::c_void,
+            std::mem::size_of::<libc::linger>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(res, 0);
+    drop(client);
+    let err = server.read(&mut [0; 16]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+}
+
+#[test]
+fn abort_by_stack() {
+    let link = HostLink::open("cf7", 7);
+    let listener = TcpListener::bind(&link.stack, 80).unwrap();
+    let mut client = kernel_connect(&link, 80).unwrap();
+    let server = accept(&listener);
+    server.set_linger(Some(Duration::from_secs(0))).unwrap();
+    drop(server);
+    let err = client.read(&mut [0; 16]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+}
+
+const UPLOAD_WITH_LOSS: usize = 200_000;
+
+/// the kernel retransmits what got lost on the way to the stack. the stack drops
+/// out of order segments, so every loss costs the kernel a retransmission timeout
+/// and the loss rate and transfer stay small
+fn upload_with_loss(link: HostLink) {
+    let listener = TcpListener::bind(&link.stack, 80).unwrap();
+    let mut client = kernel_connect(&link, 80).unwrap();
+    let mut server = accept(&listener);
+    server.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let writer = thread::spawn(move || {
+        client.write_all(&pattern(UPLOAD_WITH_LOSS)).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        client
+    });
+    let mut received = Vec::new();
+    server.read_to_end(&mut received).unwrap();
+    assert!(received == pattern(UPLOAD_WITH_LOSS), "upload corrupted");
+    drop(writer.join().unwrap());
+}
+
+#[test]
+fn kernel_retransmits_under_netem_loss() {
+    let link = HostLink::open("cf8", 8);
+    if !link.netem(&["loss", "1%", "delay", "2ms"]) {
+        eprintln!("skipped: tc-netem is not available");
+        return;
+    }
+    upload_with_loss(link);
+}
+
+#[test]
+fn kernel_retransmits_under_userspace_loss() {
+    let faults = FaultConfig {
+        loss: 0.01,
+        duplicate: 0.01,
+        delay: Duration::from_millis(2),
+        ..FaultConfig::default()
+    };
+    upload_with_loss(HostLink::open_faulty("cf9", 9, faults));
+}
@@ -0,0 +1,109 @@
+//! Event loops and stacks on devices which return nothing or go away
+#![cfg(all(unix, feature = "std"))]
+
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::thread;
+use std::time::Duration;
+
+use tcp_stack::config::StackConfig;
+use tcp_stack::data_link::loopback::Loopback;
+use tcp_stack::data_link::DataLayer;
+use tcp_stack::event_loop::{Context, EventLoop, Handler};
+use tcp_stack::result;
+use tcp_stack::socket::{TcpListener, TcpStream};
+use tcp_stack::stack::NetStack;
+use tcp_stack::timer::TimerId;
+
+const ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+/// a device whose reads always succeed without a frame
+struct Empty {
+    reads: usize,
+}
+
+impl DataLayer for Empty {
+    fn send(&mut self, data: &[u8]) -> io::Result<usize> {
+        Ok(data.len())
+    }
+
+    fn recv(&mut self, _data: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        Ok(0)
+    }
+
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// the frames received, stops after `until` of them
+#[derive(Default)]
+struct Frames {
+    frames: Vec<Vec<u8>>,
+    until: usize,
+}
+
+impl<L: DataLayer> Handler<L, ()> for Frames {
+    fn on_frame(&mut self, _cx: &mut Context<L, ()>, frame: &[u8]) -> result::Result<()> {
+        self.frames.push(frame.to_vec());
+        Ok(())
+    }
+
+    fn on_timer(&mut self, _cx: &mut Context<L, ()>, _id: TimerId, _timer: ()) -> result::Result<()> {
+        Ok(())
+    }
+
+    fn should_stop(&self) -> bool {
+        self.frames.len() >= self.until
+    }
+}
+
+#[test]
+fn zero_reads_end_the_drain() {
+    let mut event_loop = EventLoop::new(Empty { reads: 0 }).unwrap();
+    let mut handler = Frames::default();
+    for _ in 0..3 {
+        event_loop.run_once(&mut handler, Some(Duration::from_millis(1))).unwrap();
+    }
+    // one read per round and no frame out of it
+    assert_eq!(event_loop.device_mut().reads, 3);
+    assert!(handler.frames.is_empty());
+}
+
+#[test]
+fn gone_devices_are_reopened() {
+    let (device, peer) = Loopback::pair();
+    let mut event_loop = EventLoop::new(device).unwrap();
+    let mut handler = Frames { until: 1, ..Frames::default() };
+    drop(peer);
+    let err = event_loop.run_once(&mut handler, Some(Duration::from_millis(1))).unwrap_err();
+    assert!(matches!(err, result::Error::DeviceGone));
+    assert_eq!(io::Error::from(err).kind(), ErrorKind::NetworkDown);
+
+    let (device, mut peer) = Loopback::pair();
+    let mut reopened = vec![Err(io::Error::from(ErrorKind::NotFound)), Ok(device)];
+    event_loop.set_reopen(Box::new(move || reopened.remove(0)), Duration::from_millis(1));
+    peer.send(b"frame").unwrap();
+    event_loop.run(&mut handler).unwrap();
+    assert_eq!(handler.frames, [b"frame".to_vec()]);
+}
+
+#[test]
+fn gone_devices_fail_the_sockets() {
+    assert!(StackConfig::builder().addr(ADDR).queues(2).reopen(Some(Duration::from_secs(1))).build().is_err());
+    let (device, peer) = Loopback::pair();
+    let stack = NetStack::with_device(device, StackConfig::builder().addr(ADDR).build().unwrap()).unwrap();
+    let listener = TcpListener::bind(&stack, 80).unwrap();
+    let connecting = TcpStream::start_connect(&stack, SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 80), stack.default_options()).unwrap();
+    let accept = thread::spawn(move || listener.accept().map(|_| ()));
+    let connect = thread::spawn(move || connecting.wait(None).map(|_| ()));
+    drop(peer);
+
+    // the waiting tasks wake up with the error, the new sockets get it right away
+    assert_eq!(accept.join().unwrap().unwrap_err().kind(), ErrorKind::NetworkDown);
+    assert_eq!(connect.join().unwrap().unwrap_err().kind(), ErrorKind::NetworkDown);
+    let err = TcpStream::connect(&stack, SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 80)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NetworkDown);
+    assert!(TcpListener::bind(&stack, 81).is_err());
+}
@@ -0,0 +1,110 @@
+//! Udp sockets and the stub resolver between two stacks on a loopback link
+#![cfg(all(unix, feature = "std"))]
+
+mod common;
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::thread;
+use std::time::Duration;
+
+use tcp_stack::dns::{self, RecordType, Resolver};
+use tcp_stack::socket::{TcpListener, UdpSocket};
+use tcp_stack::tcp::connection::ConnectionConfig;
+
+use common::{stacks, CLIENT, SERVER, TIMEOUT};
+
+/// the answer of a name server knowing `example.test` and dropping the first query of
+/// `slow.test`: the header and question of `query` and the records
+fn answer(query: &[u8], slow_seen: &mut bool) -> Option<Vec<u8>> {
+    let end = 12 + query[12..].iter().position(|len| *len == 0).unwrap() + 5;
+    let question = &query[12..end];
+    let kind = u16::from_be_bytes([question[question.len() - 4], question[question.len() - 3]]);
+    let name: Vec<&[u8]> = question.split(|b| *b < 32).filter(|label| !label.is_empty()).collect();
+    let (rcode, records): (u16, Vec<Vec<u8>>) = match (&name[..2], kind) {
+        ([b"slow", _], _) if !*slow_seen => {
+            *slow_seen = true;
+            return None;
+        }
+        ([b"example", _] | [b"slow", _], 1) => (0, vec![SERVER.octets().to_vec(), vec![10, 0, 0, 9]]),
+        ([b"example", _] | [b"slow", _], _) => (0, Vec::new()),
+        _ => (3, Vec::new()),
+    };
+    let mut message = query[..2].to_vec();
+    message.extend_from_slice(&(0x8180 | rcode).to_be_bytes());
+    message.extend_from_slice(&[0, 1, 0, records.len() as u8 + 1, 0, 0, 0, 0]);
+    message.extend_from_slice(question);
+    // a CNAME first, like the answers for names behind a CDN
+    message.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 12]);
+    for rdata in records {
+        message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, rdata.len() as u8]);
+        message.extend_from_slice(&rdata);
+    }
+    Some(message)
+}
+
+#[test]
+fn datagrams_go_both_ways() {
+    let (client, server) = stacks(ConnectionConfig::default());
+    let echo = UdpSocket::bind(&server, 7).unwrap();
+    assert_eq!(UdpSocket::bind(&server, 7).err().unwrap().kind(), ErrorKind::AddrInUse);
+    let unknown = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 9), 7);
+    assert_eq!(UdpSocket::bind_addr(&server, unknown).err().unwrap().kind(), ErrorKind::AddrNotAvailable);
+    let socket = UdpSocket::bind(&client, 0).unwrap();
+    assert_ne!(socket.local_addr().port(), 0);
+    assert_eq!(socket.try_recv_from(&mut [0; 16]).unwrap_err().kind(), ErrorKind::WouldBlock);
+    assert_eq!(socket.send_to(&vec![0; 1500], SocketAddrV4::new(SERVER, 7)).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    socket.send_to(b"hello", SocketAddrV4::new(SERVER, 7)).unwrap();
+    echo.set_read_timeout(TIMEOUT).unwrap();
+    let mut buf = [0; 16];
+    let (n, from) = echo.recv_from(&mut buf).unwrap();
+    assert_eq!((&buf[..n], from), (&b"hello"[..], SocketAddrV4::new(CLIENT, socket.local_addr().port())));
+    echo.send_to(&buf[..n], from).unwrap();
+    socket.set_read_timeout(TIMEOUT).unwrap();
+    // the rest of a datagram larger than the buffer is gone
+    let mut short = [0; 3];
+    assert_eq!(socket.recv_from(&mut short).unwrap(), (3, SocketAddrV4::new(SERVER, 7)));
+    assert_eq!(&short, b"hel");
+    socket.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+    assert_eq!(socket.recv_from(&mut buf).unwrap_err().kind(), ErrorKind::TimedOut);
+
+    // the port is free again once the socket is dropped
+    drop(echo);
+    UdpSocket::bind(&server, 7).unwrap();
+}
+
+#[test]
+fn names_resolve_inside_the_stack() {
+    let (client, server) = stacks(ConnectionConfig::default());
+    let name_server = UdpSocket::bind(&server, dns::PORT).unwrap();
+    thread::spawn(move || {
+        let mut slow_seen = false;
+        let mut buf = [0; 512];
+        while let Ok((n, from)) = name_server.recv_from(&mut buf) {
+            if let Some(message) = answer(&buf[..n], &mut slow_seen) {
+                name_server.send_to(&message, from).unwrap();
+            }
+        }
+    });
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let resolver = Resolver::new(&client, SocketAddrV4::new(SERVER, dns::PORT)).timeout(Duration::from_millis(200));
+
+    let expected = [IpAddr::V4(SERVER), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9))];
+    assert_eq!(resolver.lookup("example.test").unwrap(), expected);
+    assert_eq!(resolver.lookup_ipv4("example.test.").unwrap(), [SERVER, Ipv4Addr::new(10, 0, 0, 9)]);
+    assert!(resolver.query("example.test", RecordType::Aaaa).unwrap().is_empty());
+    assert_eq!(resolver.lookup("10.0.0.7").unwrap(), [IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7))]);
+    assert_eq!(resolver.lookup("missing.test").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(resolver.lookup("bad..name").unwrap_err().kind(), ErrorKind::InvalidInput);
+    // the first query goes unanswered, the second one isn't
+    assert_eq!(resolver.lookup_ipv4("slow.test").unwrap()[0], SERVER);
+
+    let stream = resolver.connect("example.test", 80).unwrap();
+    assert_eq!(stream.peer_addr(), SocketAddrV4::new(SERVER, 80));
+    listener.accept().unwrap();
+
+    // nobody answers
+    let silent = Resolver::new(&client, SocketAddrV4::new(SERVER, 5353)).timeout(Duration::from_millis(50)).attempts(2);
+    assert_eq!(silent.lookup("example.test").unwrap_err().kind(), ErrorKind::TimedOut);
+}
@@ -0,0 +1,196 @@
+//! Stacks on an ethernet link: framing, ARP resolution and the ARP cache API
+#![cfg(all(unix, feature = "std"))]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::thread;
+use std::time::Duration;
+
+use tcp_stack::config::StackConfig;
+use tcp_stack::data_link::arp::{EntryKind, MacAddr};
+use tcp_stack::data_link::loopback::Loopback;
+use tcp_stack::data_link::DataLayer;
+use tcp_stack::socket::{TcpListener, TcpStream};
+use tcp_stack::stack::NetStack;
+
+use common::{CLIENT, SERVER, TIMEOUT};
+
+const CLIENT_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 1]);
+const PEER_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 2]);
+const ARP: [u8; 2] = [0x08, 0x06];
+const IPV4: [u8; 2] = [0x08, 0x00];
+const VLAN: [u8; 2] = [0x81, 0x00];
+
+fn stack(device: Loopback, addr: Ipv4Addr, mac: MacAddr) -> NetStack {
+    NetStack::with_ethernet(device, StackConfig::builder().addr(addr).mac(mac).build().unwrap()).unwrap()
+}
+
+/// the client stack and the other end of its link
+fn client() -> (NetStack, Loopback) {
+    let (device, mut peer) = Loopback::pair();
+    peer.set_read_timeout(Some(Duration::from_millis(500)));
+    (stack(device, CLIENT, CLIENT_MAC), peer)
+}
+
+fn next_frame(peer: &mut Loopback) -> Vec<u8> {
+    let mut buf = [0; 1514];
+    let n = peer.recv(&mut buf).unwrap();
+    buf[..n].to_vec()
+}
+
+/// the ARP packet of `frame`: operation, sender and target
+fn arp(frame: &[u8]) -> (u16, MacAddr, Ipv4Addr, Ipv4Addr) {
+    assert_eq!(frame[12..14], ARP);
+    let packet = &frame[14..];
+    let mut sender = [0; 6];
+    sender.copy_from_slice(&packet[8..14]);
+    let ip = |at: usize| Ipv4Addr::new(packet[at], packet[at + 1], packet[at + 2], packet[at + 3]);
+    (u16::from_be_bytes([packet[6], packet[7]]), MacAddr(sender), ip(14), ip(24))
+}
+
+fn request(sender: Ipv4Addr, target: Ipv4Addr) -> Vec<u8> {
+    let mut frame = Vec::new();
+    frame.extend_from_slice(&MacAddr::BROADCAST.0);
+    frame.extend_from_slice(&PEER_MAC.0);
+    frame.extend_from_slice(&ARP);
+    frame.extend_from_slice(&[0, 1, 0x08, 0x00, 6, 4, 0, 1]);
+    frame.extend_from_slice(&PEER_MAC.0);
+    frame.extend_from_slice(&sender.octets());
+    frame.extend_from_slice(&[0; 6]);
+    frame.extend_from_slice(&target.octets());
+    frame
+}
+
+#[test]
+fn neighbours_are_resolved() {
+    let (a, b) = Loopback::pair();
+    let server_mac = MacAddr::random().unwrap();
+    assert!(!server_mac.is_multicast());
+    assert_eq!(server_mac.to_string().parse::<MacAddr>().unwrap(), server_mac);
+    let (client, server) = (stack(a, CLIENT, CLIENT_MAC), stack(b, SERVER, server_mac));
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let echo = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+    });
+    let mut stream = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    stream.wait_established(TIMEOUT).unwrap();
+    stream.write_all(b"hello").unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    echo.join().unwrap();
+
+    let entries = client.arp().unwrap().entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!((entries[0].ip, entries[0].mac, entries[0].kind), (SERVER, Some(server_mac), EntryKind::Dynamic));
+    assert_eq!(server.arp().unwrap().get(CLIENT), Some(CLIENT_MAC));
+    // the learnt mappings go, the static ones stay
+    let static_ip = Ipv4Addr::new(10, 0, 0, 9);
+    client.arp().unwrap().insert_static(static_ip, PEER_MAC);
+    client.arp().unwrap().clear();
+    let entries = client.arp().unwrap().entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!((entries[0].ip, entries[0].kind), (static_ip, EntryKind::Static));
+    assert!(client.arp().unwrap().remove(static_ip));
+    assert!(client.arp().unwrap().entries().is_empty());
+}
+
+#[test]
+fn jumbo_frames() {
+    let (mut a, mut b) = Loopback::pair();
+    a.set_mtu(9000);
+    b.set_mtu(9000);
+    // the client keeps to the mtu it was given, which the server learns from its MSS option
+    let config = StackConfig::builder().addr(CLIENT).mac(CLIENT_MAC).mtu(4000).build().unwrap();
+    let client = NetStack::with_ethernet(a, config).unwrap();
+    let server = stack(b, SERVER, PEER_MAC);
+    assert_eq!((client.mtu(), server.mtu()), (4000, 9000));
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let sent = data.clone();
+    let echo = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        stream.write_all(&sent).unwrap();
+        stream.stats().unwrap()
+    });
+    let mut stream = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    let mut buf = vec![0; data.len()];
+    stream.read_exact(&mut buf).unwrap();
+    assert!(buf == data);
+    let stats = echo.join().unwrap();
+    let largest = stats.bytes_sent / stats.segments_sent;
+    assert!(largest > 1460 && largest <= 3960, "{:?}", stats);
+}
+
+#[test]
+fn addresses_are_announced() {
+    let (client, mut peer) = client();
+    assert_eq!(arp(&next_frame(&mut peer)), (1, CLIENT_MAC, CLIENT, CLIENT));
+
+    let second = Ipv4Addr::new(10, 0, 0, 3);
+    client.add_addr(second).unwrap();
+    assert_eq!(arp(&next_frame(&mut peer)), (1, CLIENT_MAC, second, second));
+    client.arp().unwrap().announce(CLIENT).unwrap();
+    assert_eq!(arp(&next_frame(&mut peer)), (1, CLIENT_MAC, CLIENT, CLIENT));
+    assert!(client.arp().unwrap().announce(SERVER).is_err());
+
+    // requests for an address of the stack are answered and teach it the sender
+    peer.send(&request(SERVER, second)).unwrap();
+    let reply = next_frame(&mut peer);
+    assert_eq!(reply[..6], PEER_MAC.0);
+    assert_eq!(arp(&reply), (2, CLIENT_MAC, second, SERVER));
+    assert_eq!(client.arp().unwrap().get(SERVER), Some(PEER_MAC));
+    // the ones for other hosts aren't
+    peer.send(&request(SERVER, Ipv4Addr::new(10, 0, 0, 9))).unwrap();
+    assert!(peer.recv(&mut [0; 1514]).is_err());
+}
+
+#[test]
+fn static_entries_skip_resolution() {
+    let (client, mut peer) = client();
+    next_frame(&mut peer);
+    client.arp().unwrap().insert_static(SERVER, PEER_MAC);
+    let _stream = TcpStream::start_connect(&client, SocketAddrV4::new(SERVER, 80), client.default_options()).unwrap();
+    let syn = next_frame(&mut peer);
+    assert_eq!((&syn[..6], &syn[6..12], &syn[12..14]), (&PEER_MAC.0[..], &CLIENT_MAC.0[..], &IPV4[..]));
+
+    // the packet to an unknown neighbour waits for its mapping
+    let other = Ipv4Addr::new(10, 0, 0, 4);
+    let _stream = TcpStream::start_connect(&client, SocketAddrV4::new(other, 80), client.default_options()).unwrap();
+    assert_eq!(arp(&next_frame(&mut peer)), (1, CLIENT_MAC, CLIENT, other));
+    let pending = client.arp().unwrap().entries().into_iter().find(|entry| entry.ip == other).unwrap();
+    assert_eq!((pending.mac, pending.kind, pending.pending), (None, EntryKind::Incomplete, 1));
+    client.arp().unwrap().insert_static(other, PEER_MAC);
+    let syn = next_frame(&mut peer);
+    assert_eq!((&syn[..6], &syn[12..14]), (&PEER_MAC.0[..], &IPV4[..]));
+    assert_eq!(syn[14 + 16..14 + 20], other.octets());
+}
+
+#[test]
+fn vlan_frames_are_tagged() {
+    assert!(StackConfig::builder().addr(CLIENT).vlan(4095, 0).build().is_err());
+    let (device, mut peer) = Loopback::pair();
+    peer.set_read_timeout(Some(Duration::from_millis(500)));
+    let config = StackConfig::builder().addr(CLIENT).mac(CLIENT_MAC).vlan(100, 5).build().unwrap();
+    let _client = NetStack::with_ethernet(device, config).unwrap();
+    // the tag sits between the source address and the ethertype: priority 5, id 100
+    let tagged = |frame: &[u8]| [&frame[..12], &frame[16..]].concat();
+    let announcement = next_frame(&mut peer);
+    assert_eq!(announcement[12..16], [0x81, 0x00, 0xa0, 100]);
+    assert_eq!(arp(&tagged(&announcement)), (1, CLIENT_MAC, CLIENT, CLIENT));
+
+    let tag = |frame: Vec<u8>, id: u8| [&frame[..12], &VLAN[..], &[0, id], &frame[12..]].concat();
+    // untagged and other VLANs don't reach the stack
+    peer.send(&request(SERVER, CLIENT)).unwrap();
+    peer.send(&tag(request(SERVER, CLIENT), 101)).unwrap();
+    assert!(peer.recv(&mut [0; 1514]).is_err());
+    peer.send(&tag(request(SERVER, CLIENT), 100)).unwrap();
+    let reply = next_frame(&mut peer);
+    assert_eq!(reply[12..16], [0x81, 0x00, 0xa0, 100]);
+    assert_eq!(arp(&tagged(&reply)), (2, CLIENT_MAC, CLIENT, SERVER));
+}
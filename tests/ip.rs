@@ -0,0 +1,124 @@
+//! What a stack does with the ip layer of the frames it receives, built with the segment
+//! builder and sent over a loopback link, run with `cargo test --features testing`
+#![cfg(feature = "testing")]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tcp_stack::checksum::Checksum;
+use tcp_stack::config::{SourceRoute, StackConfig, StackConfigBuilder};
+use tcp_stack::data_link::loopback::Loopback;
+use tcp_stack::data_link::DataLayer;
+use tcp_stack::firewall::{Rule, Verdict};
+use tcp_stack::icmp;
+use tcp_stack::ip_options::{self, IpOption, IpOptionSet};
+use tcp_stack::reader_writer::Segment;
+use tcp_stack::socket::{TcpListener, UdpSocket};
+use tcp_stack::stack::NetStack;
+use tcp_stack::testing::{seg, SegmentBuilder, LOCAL, PEER};
+
+/// loose source route through 10.0.0.9, pointer at the first address
+const SOURCE_ROUTE: [u8; 7] = [ip_options::KIND_LOOSE_SOURCE_ROUTE, 7, 4, 10, 0, 0, 9];
+const ROUTER_ALERT: [u8; 4] = [ip_options::KIND_ROUTER_ALERT, 4, 0, 0];
+const SYN_ACK: u8 = 0x12;
+const RST: u8 = 0x04;
+
+/// a stack listening on port 80 of `LOCAL` and the other end of its link
+fn listening(config: StackConfigBuilder) -> (NetStack, TcpListener, Loopback) {
+    let (device, mut peer) = Loopback::pair();
+    peer.set_read_timeout(Some(Duration::from_millis(300)));
+    let ip = LOCAL.ipv4().unwrap();
+    let stack = NetStack::with_device(device, config.addr(ip).build().unwrap()).unwrap();
+    let listener = TcpListener::bind(&stack, LOCAL.port()).unwrap();
+    (stack, listener, peer)
+}
+
+/// the flags byte of what the stack answers to `segment`, `None` if it doesn't
+fn answer(peer: &mut Loopback, segment: SegmentBuilder) -> Option<u8> {
+    peer.send(&segment.build().unwrap()).unwrap();
+    let mut buf = [0; 1500];
+    let n = peer.recv(&mut buf).ok()?;
+    let segment = Segment::parse(&buf[..n], 0).unwrap();
+    Some(segment.tcp().slice()[13])
+}
+
+/// an ipv4 packet of `protocol` from `PEER` to `LOCAL`
+fn packet(protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, protocol, 0, 0];
+    packet[2..4].copy_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+    packet.extend_from_slice(&PEER.ipv4().unwrap().octets());
+    packet.extend_from_slice(&LOCAL.ipv4().unwrap().octets());
+    let mut checksum = Checksum::new();
+    checksum.add(&packet);
+    packet[10..12].copy_from_slice(&checksum.finish().to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+#[test]
+fn options_are_parsed() {
+    let raw = [1, 148, 4, 0, 0, 131, 7, 4, 10, 0, 0, 9, 0];
+    let options: Vec<_> = ip_options::parse(&raw).collect::<Result<_, _>>().unwrap();
+    assert_eq!(options, [IpOption::Nop, IpOption::RouterAlert(0), IpOption::LooseSourceRoute(&raw[7..12])]);
+    let set = IpOptionSet::of(&raw).unwrap();
+    assert!(set.is_source_routed() && set.contains(ip_options::KIND_ROUTER_ALERT));
+    assert!(!set.contains(ip_options::KIND_TIMESTAMP));
+    // the length runs past the header
+    assert!(IpOptionSet::of(&[137, 12, 4, 10, 0, 0, 9, 0]).is_err());
+}
+
+#[test]
+fn source_routed_syn_is_dropped() {
+    let (_stack, _listener, mut peer) = listening(StackConfig::builder());
+    assert_eq!(answer(&mut peer, seg().syn().seq(1000).ip_options(&SOURCE_ROUTE)), None);
+    // a malformed option drops the packet as well
+    assert_eq!(answer(&mut peer, seg().syn().seq(1000).ip_options(&[ip_options::KIND_ROUTER_ALERT, 9])), None);
+    // the router alert asks nothing of a host
+    assert_eq!(answer(&mut peer, seg().syn().seq(1000).ip_options(&ROUTER_ALERT)), Some(SYN_ACK));
+}
+
+#[test]
+fn firewall_sees_ip_options() {
+    let (stack, _listener, mut peer) = listening(StackConfig::builder().source_route(SourceRoute::Flag));
+    let alerted = Arc::new(AtomicBool::new(false));
+    let seen = alerted.clone();
+    stack.add_rule(Rule::from_fn(move |segment| {
+        seen.store(segment.ip_options.contains(ip_options::KIND_ROUTER_ALERT), Ordering::Relaxed);
+        None
+    }));
+    stack.add_rule(Rule::new("srcroute".parse().unwrap(), Verdict::Reject));
+
+    assert_eq!(answer(&mut peer, seg().syn().seq(1000).ip_options(&SOURCE_ROUTE)).map(|flags| flags & RST), Some(RST));
+    assert!(!alerted.load(Ordering::Relaxed));
+    assert_eq!(answer(&mut peer, seg().syn().seq(1000).ip_options(&ROUTER_ALERT)), Some(SYN_ACK));
+    assert!(alerted.load(Ordering::Relaxed));
+}
+
+#[test]
+fn firewall_drops_icmp_and_udp() {
+    let (stack, _listener, mut peer) = listening(StackConfig::builder());
+    let socket = UdpSocket::bind(&stack, 53).unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    let mut echo = vec![icmp::ECHO_REQUEST, 0, 0, 0, 0, 1, 0, 1, b'p', b'i', b'n', b'g'];
+    let mut checksum = Checksum::new();
+    checksum.add(&echo);
+    echo[2..4].copy_from_slice(&checksum.finish().to_be_bytes());
+    let echo = packet(icmp::PROTOCOL, &echo);
+    // no udp checksum
+    let datagram = packet(17, &[0x0f, 0xa0, 0, 53, 0, 12, 0, 0, b'p', b'i', b'n', b'g']);
+
+    let mut buf = [0; 1500];
+    peer.send(&echo).unwrap();
+    peer.recv(&mut buf).unwrap();
+    assert_eq!(buf[20], icmp::ECHO_REPLY);
+    peer.send(&datagram).unwrap();
+    assert_eq!(socket.recv_from(&mut buf).unwrap().0, 4);
+
+    stack.add_rule(Rule::new("not tcp".parse().unwrap(), Verdict::Drop));
+    peer.send(&echo).unwrap();
+    assert!(peer.recv(&mut buf).is_err());
+    peer.send(&datagram).unwrap();
+    assert!(socket.recv_from(&mut buf).is_err());
+    assert_eq!(answer(&mut peer, seg().syn().seq(1000)), Some(SYN_ACK));
+}
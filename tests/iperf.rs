@@ -0,0 +1,106 @@
+//! iperf3 tests between two stacks on a loopback link, the server on one, the client on the other
+#![cfg(all(unix, feature = "std"))]
+
+mod common;
+
+use std::net::SocketAddrV4;
+use std::thread;
+use std::time::Duration;
+
+use tcp_stack::iperf::{self, Outcome, Params};
+use tcp_stack::socket::TcpListener;
+use tcp_stack::tcp::connection::ConnectionConfig;
+
+use common::{stacks, SERVER};
+
+/// what the client and the server saw of one test
+fn test(params: Params) -> (Outcome, Outcome) {
+    let (client, server) = stacks(ConnectionConfig::default());
+    let listener = TcpListener::bind(&server, iperf::DEFAULT_PORT).unwrap();
+    let serving = thread::spawn(move || iperf::serve(&listener).unwrap());
+    let addr = SocketAddrV4::new(SERVER, iperf::DEFAULT_PORT);
+    let at_client = iperf::run(&client, addr, params).unwrap();
+    (at_client, serving.join().unwrap())
+}
+
+#[test]
+fn client_sends_on_every_stream() {
+    let params = Params { parallel: 3, time: Duration::from_millis(300), len: 16 * 1024, reverse: false };
+    let (client, server) = test(params);
+    assert!(client.sent.bytes > 0);
+    assert!(server.received.bytes > 0 && server.received.bytes <= client.sent.bytes);
+    // both ends report what the other one counted
+    assert_eq!(client.received.bytes, server.received.bytes);
+    assert_eq!((server.sent.bytes, server.sent.retransmits), (client.sent.bytes, client.sent.retransmits));
+    assert!(client.sent.duration >= params.time);
+    assert!(client.received.bits_per_second() > 0.0);
+}
+
+#[test]
+fn reverse_tests_send_from_the_server() {
+    let params = Params { parallel: 1, time: Duration::from_millis(300), len: 16 * 1024, reverse: true };
+    let (client, server) = test(params);
+    assert!(server.sent.bytes > 0);
+    assert!(client.received.bytes > 0 && client.received.bytes <= server.sent.bytes);
+    assert_eq!(client.sent.bytes, server.sent.bytes);
+    assert_eq!(server.received.bytes, client.received.bytes);
+}
+
+/// against iperf3 on the host over a TUN interface, run as root with iperf3 on the path and
+/// `cargo test --features host-tests --test iperf -- --ignored`
+#[cfg(all(target_os = "linux", feature = "host-tests"))]
+mod host {
+    use std::io::ErrorKind;
+    use std::net::SocketAddrV4;
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use tcp_stack::iperf::{self, Params};
+    use tcp_stack::socket::TcpListener;
+
+    use super::common::host::{HostLink, TIMEOUT};
+
+    #[test]
+    #[ignore]
+    fn run_against_iperf3_server() {
+        let link = HostLink::open("ip0", 40);
+        let mut server = Command::new("iperf3")
+            .args(["-s", "-1", "-B", &link.host.to_string()])
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("iperf3 on the path");
+        let addr = SocketAddrV4::new(link.host, iperf::DEFAULT_PORT);
+        let params = Params { parallel: 2, time: Duration::from_secs(1), ..Params::default() };
+        let deadline = Instant::now() + TIMEOUT;
+        // until the server listens
+        let outcome = loop {
+            match iperf::run(&link.stack, addr, params) {
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                outcome => break outcome.unwrap(),
+            }
+        };
+        assert!(server.wait().unwrap().success());
+        assert!(outcome.sent.bytes > 0);
+        assert!(outcome.received.bytes > 0 && outcome.received.bytes <= outcome.sent.bytes);
+        assert!(outcome.received.duration >= Duration::from_millis(900));
+    }
+
+    #[test]
+    #[ignore]
+    fn serve_iperf3_client() {
+        let link = HostLink::open("ip1", 41);
+        let listener = TcpListener::bind(&link.stack, iperf::DEFAULT_PORT).unwrap();
+        let serving = thread::spawn(move || iperf::serve(&listener).unwrap());
+        let client = Command::new("iperf3")
+            .args(["-c", &link.addr.to_string(), "-t", "1", "-P", "2", "--json"])
+            .output()
+            .expect("iperf3 on the path");
+        assert!(client.status.success(), "{}", String::from_utf8_lossy(&client.stdout));
+        let outcome = serving.join().unwrap();
+        assert!(outcome.received.bytes > 0);
+        assert!(outcome.sent.bytes >= outcome.received.bytes);
+    }
+}
@@ -0,0 +1,135 @@
+//! Multipath TCP between two stacks over a loopback pair, run with `cargo test --features mptcp`
+#![cfg(feature = "mptcp")]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcp_stack::socket::{MptcpListener, MptcpStream, TcpListener, TcpStream};
+use tcp_stack::stack::NetStack;
+use tcp_stack::tcp::connection::ConnectionConfig;
+
+use common::{pattern, SERVER, TIMEOUT};
+
+const SECOND: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 3);
+
+/// the shared loopback stacks, the client with a second address
+fn stacks() -> (NetStack, NetStack) {
+    let (client, server) = common::stacks(ConnectionConfig::default());
+    client.add_addr(SECOND).unwrap();
+    (client, server)
+}
+
+/// wait for the connection of `stream` to have `count` open subflows
+fn wait_subflows(stream: &MptcpStream, count: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while stream.subflows().iter().filter(|info| !info.closed).count() != count {
+        assert!(Instant::now() < deadline, "{:?}", stream.subflows());
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn echo(listener: MptcpListener, len: usize) -> thread::JoinHandle<MptcpStream> {
+    thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+        stream
+    })
+}
+
+#[test]
+fn two_subflows() {
+    let (client, server) = stacks();
+    let listener = MptcpListener::bind(&server, 80).unwrap();
+    let data = pattern(300_000);
+    let peer = echo(listener, data.len());
+    let mut stream = MptcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    stream.wait_established(TIMEOUT).unwrap();
+    assert!(!stream.is_fallback());
+    stream.add_subflow(SECOND).unwrap();
+    wait_subflows(&stream, 2);
+    assert!(stream.add_subflow(Ipv4Addr::new(10, 0, 0, 9)).is_err());
+
+    stream.write_all(&data).unwrap();
+    let mut buf = vec![0; data.len()];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data);
+    let peer = peer.join().unwrap();
+    assert!(!peer.is_fallback());
+    assert_eq!(peer.subflows().len(), 2);
+
+    stream.shutdown().unwrap();
+    let mut rest = Vec::new();
+    (&peer).read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn fallback_to_tcp() {
+    let (client, server) = stacks();
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let peer = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+        stream
+    });
+    let mut stream = MptcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    stream.wait_established(TIMEOUT).unwrap();
+    assert!(stream.is_fallback());
+    assert!(stream.add_subflow(SECOND).is_err());
+    stream.write_all(b"hello").unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    drop(peer.join().unwrap());
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    // and a plain client gets plain tcp from an MPTCP listener
+    let listener = MptcpListener::bind(&server, 81).unwrap();
+    let peer = echo(listener, 5);
+    let mut stream = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 81)).unwrap();
+    stream.write_all(b"world").unwrap();
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+    assert!(peer.join().unwrap().is_fallback());
+}
+
+#[test]
+fn subflow_lost_mid_transfer() {
+    let (client, server) = stacks();
+    let listener = MptcpListener::bind(&server, 80).unwrap();
+    let data = pattern(1_000_000);
+    let peer = echo(listener, data.len());
+    let stream = MptcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    stream.wait_established(TIMEOUT).unwrap();
+    stream.add_subflow(SECOND).unwrap();
+    wait_subflows(&stream, 2);
+
+    let received = thread::scope(|scope| {
+        let writer = scope.spawn(|| (&stream).write_all(&data));
+        let mut buf = vec![0; data.len()];
+        let mut read = 0;
+        while read < buf.len() {
+            // the subflow of the second address is reset with what it had in flight
+            if read >= 100_000 && client.addrs().contains(&SECOND) {
+                client.remove_addr(SECOND).unwrap();
+            }
+            read += (&stream).read(&mut buf[read..]).unwrap();
+        }
+        writer.join().unwrap().unwrap();
+        buf
+    });
+    assert_eq!(received, data);
+    assert!(!client.addrs().contains(&SECOND));
+    wait_subflows(&stream, 1);
+    drop(peer.join().unwrap());
+}
@@ -0,0 +1,104 @@
+//! Connections of the `no_std` build driven like firmware would: the frames go through
+//! a queue of the test, the time comes from a clock it sets. run with
+//! `cargo test --no-default-features --test no_std`
+#![cfg(not(feature = "std"))]
+
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use etherparse::{Ipv4HeaderSlice, TcpHeaderSlice};
+
+use tcp_stack::clock::Clock;
+use tcp_stack::data_link::DataLayer;
+use tcp_stack::io::{self, ErrorKind};
+use tcp_stack::socket_addr::Addr;
+use tcp_stack::tcp::connection::{ConnectionConfig, TcpConnection};
+use tcp_stack::tcp::vars::TcpState;
+use tcp_stack::time::Instant;
+
+/// A timer of the device, in milliseconds since it started
+#[derive(Debug, Default)]
+struct Ticks(AtomicU64);
+
+impl Ticks {
+    fn advance(&self, by: Duration) {
+        self.0.fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for Ticks {
+    fn now(&self) -> Instant {
+        Instant::from_epoch(Duration::from_millis(self.0.load(Ordering::Relaxed)))
+    }
+}
+
+/// The frames a connection sent, waiting for the test to deliver them
+#[derive(Debug, Default)]
+struct Queue(VecDeque<Vec<u8>>);
+
+impl DataLayer for Queue {
+    fn send(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.push_back(data.to_vec());
+        Ok(data.len())
+    }
+
+    fn recv(&mut self, _data: &mut [u8]) -> io::Result<usize> {
+        Err(ErrorKind::WouldBlock.into())
+    }
+}
+
+fn headers(frame: &[u8]) -> (Ipv4HeaderSlice<'_>, TcpHeaderSlice<'_>, &[u8]) {
+    let ip = Ipv4HeaderSlice::from_slice(frame).unwrap();
+    let tcp = TcpHeaderSlice::from_slice(&frame[ip.slice().len()..]).unwrap();
+    let payload = &frame[ip.slice().len() + tcp.slice().len()..];
+    (ip, tcp, payload)
+}
+
+/// hand every frame of `from` to `to`
+fn deliver(from: &mut Queue, to: &mut TcpConnection, device: &mut Queue) {
+    while let Some(frame) = from.0.pop_front() {
+        let (ip, tcp, payload) = headers(&frame);
+        if to.on_ip_header(&ip) {
+            to.on_segment(device, &tcp, payload).unwrap();
+        }
+    }
+}
+
+#[test]
+fn connections_run_on_the_clock_and_device_of_the_caller() {
+    let ticks = Arc::new(Ticks::default());
+    let client_addr = Addr::new(Ipv4Addr::new(10, 0, 0, 2), 4000);
+    let server_addr = Addr::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+    let (mut to_server, mut to_client) = (Queue::default(), Queue::default());
+
+    let mut client = TcpConnection::connect(&mut to_server, client_addr, server_addr, ConnectionConfig::default()).unwrap();
+    client.set_clock(ticks.clone());
+    assert_eq!(client.state(), TcpState::SynSent);
+
+    let syn = to_server.0.pop_front().unwrap();
+    let (ip, tcp, payload) = headers(&syn);
+    let mut server = TcpConnection::accept(&mut to_client, &ip, &tcp, payload).unwrap().unwrap();
+    server.set_clock(ticks.clone());
+    deliver(&mut to_client, &mut client, &mut to_server);
+    deliver(&mut to_server, &mut server, &mut to_client);
+    assert_eq!((client.state(), server.state()), (TcpState::Established, TcpState::Established));
+
+    assert_eq!(client.write(b"hello"), 5);
+    client.transmit(&mut to_server).unwrap();
+    // the segment is lost, nothing is resent before the device's clock reaches the timeout
+    assert_eq!(to_server.0.len(), 1);
+    to_server.0.clear();
+    let timeout = client.next_timeout().unwrap();
+    client.on_timeout(&mut to_server).unwrap();
+    assert!(to_server.0.is_empty());
+
+    ticks.advance(timeout.duration_since(ticks.now()));
+    client.on_timeout(&mut to_server).unwrap();
+    deliver(&mut to_server, &mut server, &mut to_client);
+    let mut buf = [0; 16];
+    assert_eq!(server.read(&mut buf), 5);
+    assert_eq!(&buf[..5], b"hello");
+}
@@ -0,0 +1,846 @@
+//! Scenarios written with the segment builder and invariants of a connection
+//! driven by random peers, run with `cargo test --features testing`
+#![cfg(feature = "testing")]
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use proptest::prelude::*;
+
+use tcp_stack::clock::Clock;
+use tcp_stack::config::StackConfig;
+use tcp_stack::data_link::gro::Coalescer;
+use tcp_stack::net_types::Dscp;
+use tcp_stack::reader_writer::Segment;
+use tcp_stack::tcp::connection::{ConnectionConfig, TcpConnection};
+use tcp_stack::tcp::options::{self, TcpOption};
+use tcp_stack::tcp::scheduler::{SendScheduler, Transmission};
+use tcp_stack::tcp::vars::{seq_le, TcpState};
+use tcp_stack::testing::{seg, Recorder, LOCAL, PEER};
+
+const PEER_ISS: u32 = 1000;
+
+/// a connection accepted from `PEER` which completed the handshake
+fn established(device: &mut Recorder, config: ConnectionConfig) -> TcpConnection {
+    let mut conn = seg().syn().seq(PEER_ISS).accept_with_config(device, config).unwrap().unwrap();
+    let syn_ack = device.last().unwrap().seq_end().unwrap();
+    seg().seq(PEER_ISS + 1).ack(syn_ack).deliver(&mut conn, device).unwrap();
+    assert_eq!(conn.state(), TcpState::Established);
+    conn
+}
+
+#[test]
+fn syn_is_answered_with_syn_ack() {
+    let mut device = Recorder::new();
+    let conn = seg().syn().seq(PEER_ISS).accept(&mut device).unwrap().unwrap();
+    assert_eq!(conn.state(), TcpState::SynReceived);
+    let tcp = device.last().unwrap().tcp().unwrap();
+    assert!(tcp.syn() && tcp.ack());
+    assert_eq!(tcp.acknowledgment_number(), PEER_ISS + 1);
+}
+
+#[test]
+fn data_on_syn_is_delivered_once_established() {
+    let mut device = Recorder::new();
+    let mut conn = seg().syn().seq(PEER_ISS).payload(b"early").accept(&mut device).unwrap().unwrap();
+    // the SYN,ACK acknowledges only the SYN
+    assert_eq!(device.last().unwrap().tcp().unwrap().acknowledgment_number(), PEER_ISS + 1);
+    let mut buf = [0; 16];
+    assert_eq!(conn.read(&mut buf), 0);
+    let syn_ack = device.last().unwrap().seq_end().unwrap();
+    seg().seq(PEER_ISS + 1).ack(syn_ack).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(conn.read(&mut buf), 5);
+    assert_eq!(&buf[..5], b"early");
+    assert_eq!(device.last().unwrap().tcp().unwrap().acknowledgment_number(), PEER_ISS + 6);
+}
+
+#[test]
+fn data_is_acknowledged_and_readable() {
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, ConnectionConfig::default());
+    let nxt = conn.send_sequence().nxt;
+    seg().seq(PEER_ISS + 1).ack(nxt).psh().payload(b"hello").deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(device.last().unwrap().tcp().unwrap().acknowledgment_number(), PEER_ISS + 6);
+    let mut buf = [0; 16];
+    assert_eq!(conn.read(&mut buf), 5);
+    assert_eq!(&buf[..5], b"hello");
+}
+
+#[test]
+fn acks_advance_una_and_the_window() {
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, ConnectionConfig::default());
+    conn.write(&[0x5a; 300]);
+    conn.transmit(&mut device).unwrap();
+    let una = conn.send_sequence().una;
+    let state = |conn: &TcpConnection| {
+        let send = conn.send_sequence();
+        (send.una.wrapping_sub(una), send.wnd, conn.send_queue_len())
+    };
+    assert_eq!(state(&conn), (0, 65535, 300));
+
+    // what is acknowledged leaves the retransmission queue
+    seg().seq(PEER_ISS + 1).ack(una + 100).window(1000).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(state(&conn), (100, 1000, 200));
+    // an older ACK changes nothing, not even the window
+    seg().seq(PEER_ISS + 1).ack(una + 50).window(5000).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(state(&conn), (100, 1000, 200));
+    // the same one with another window is a window update
+    seg().seq(PEER_ISS + 1).ack(una + 100).window(2000).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(state(&conn), (100, 2000, 200));
+    // one of data never sent is answered with an ACK and not taken
+    let sent = device.sent().len();
+    seg().seq(PEER_ISS + 1).ack(una + 1000).window(5000).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(state(&conn), (100, 2000, 200));
+    assert_eq!(device.sent().len(), sent + 1);
+    let ack = device.last().unwrap().tcp().unwrap();
+    assert!(ack.ack() && ack.acknowledgment_number() == PEER_ISS + 1);
+    assert_eq!(ack.sequence_number(), conn.send_sequence().nxt);
+}
+
+#[test]
+fn window_follows_the_unread_bytes() {
+    let mut config = ConnectionConfig::default();
+    config.set_recv_buffer_size(4000);
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, config);
+    let nxt = conn.send_sequence().nxt;
+    let window = |device: &Recorder| device.last().unwrap().tcp().unwrap().window_size();
+    assert_eq!(conn.recv_sequence().wnd, 4000);
+
+    // the application reads nothing, the window shrinks with every segment
+    seg().seq(PEER_ISS + 1).ack(nxt).payload(&[1; 1000]).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(window(&device), 3000);
+    seg().seq(PEER_ISS + 1001).ack(nxt).payload(&[2; 2500]).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(window(&device), 500);
+    // what doesn't fit is left to the peer
+    seg().seq(PEER_ISS + 3501).ack(nxt).payload(&[3; 1000]).deliver(&mut conn, &mut device).unwrap();
+    let ack = device.last().unwrap().tcp().unwrap();
+    assert_eq!((ack.acknowledgment_number(), ack.window_size()), (PEER_ISS + 4001, 0));
+
+    // a small read keeps the window closed, reading a segment's worth opens it again
+    let mut buf = [0; 4000];
+    assert_eq!(conn.read(&mut buf[..100]), 100);
+    let sent = device.sent().len();
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(device.sent().len(), sent);
+    assert_eq!(conn.read(&mut buf), 3900);
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(device.sent().len(), sent + 1);
+    assert_eq!(window(&device), 4000);
+}
+
+#[test]
+fn fin_of_peer_moves_to_close_wait() {
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, ConnectionConfig::default());
+    let nxt = conn.send_sequence().nxt;
+    seg().seq(PEER_ISS + 1).ack(nxt).fin().deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(conn.state(), TcpState::CloseWait);
+    assert!(conn.is_eof());
+    assert_eq!(device.last().unwrap().tcp().unwrap().acknowledgment_number(), PEER_ISS + 2);
+}
+
+#[test]
+fn duplicate_segment_is_acknowledged_again() {
+    let mut config = ConnectionConfig::default();
+    config.set_delayed_ack(Some(Duration::from_millis(40)));
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, config);
+    let nxt = conn.send_sequence().nxt;
+    seg().seq(PEER_ISS + 1).ack(nxt).payload(b"hello").deliver(&mut conn, &mut device).unwrap();
+    // as if our delayed ACK got lost, the peer sends the segment again
+    let sent = device.sent().len();
+    seg().seq(PEER_ISS + 1).ack(nxt).payload(b"hello").deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(device.sent().len(), sent + 1);
+    let ack = device.last().unwrap();
+    assert_eq!(ack.tcp().unwrap().acknowledgment_number(), PEER_ISS + 6);
+    assert!(ack.payload().unwrap().is_empty());
+    // one overlapping what arrived is acknowledged right away too
+    seg().seq(PEER_ISS + 3).ack(nxt).payload(b"llo world").deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(device.sent().len(), sent + 2);
+    assert_eq!(device.last().unwrap().tcp().unwrap().acknowledgment_number(), PEER_ISS + 12);
+    let stats = conn.stats();
+    assert_eq!((stats.dup_segments, stats.dup_bytes), (1, 8));
+    let mut buf = [0; 16];
+    assert_eq!(conn.read(&mut buf), 11);
+    assert_eq!(&buf[..11], b"hello world");
+}
+
+#[test]
+fn reset_in_window_closes() {
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, ConnectionConfig::default());
+    seg().seq(PEER_ISS + 1).rst().deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(conn.state(), TcpState::Closed);
+    assert!(conn.is_reset());
+}
+
+#[test]
+fn dscp_is_marked_and_received() {
+    let mut config = ConnectionConfig::default();
+    config.set_tos(0b01);
+    config.set_dscp(Dscp::EF);
+    assert_eq!(config.tos(), 46 << 2 | 0b01);
+    let mut device = Recorder::new();
+    let mut conn = seg().syn().seq(PEER_ISS).tos(Dscp::AF41.with_ecn_of(0)).accept_with_config(&mut device, config).unwrap().unwrap();
+    assert_eq!(device.last().unwrap().segment().unwrap().ip().dcp().value(), Dscp::EF.value());
+    assert_eq!(conn.received_dscp(), Some(Dscp::AF41));
+    let syn_ack = device.last().unwrap().seq_end().unwrap();
+    seg().seq(PEER_ISS + 1).ack(syn_ack).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(conn.received_dscp(), Some(Dscp::CS0));
+    conn.config_mut().set_dscp(Dscp::LE);
+    conn.write(b"bulk");
+    conn.transmit(&mut device).unwrap();
+    let ip = *device.last().unwrap().segment().unwrap().ip();
+    assert_eq!((ip.dcp().value(), ip.ecn().value()), (Dscp::LE.value(), 0b01));
+}
+
+#[test]
+fn gtsm_takes_only_packets_of_a_neighbour() {
+    let mut config = ConnectionConfig::default();
+    config.set_gtsm(true);
+    let mut device = Recorder::new();
+    assert!(seg().syn().seq(PEER_ISS).ttl(254).accept_with_config(&mut device, config).unwrap().is_none());
+    assert!(device.sent().is_empty());
+    let mut conn = seg().syn().seq(PEER_ISS).ttl(255).accept_with_config(&mut device, config).unwrap().unwrap();
+    assert_eq!(device.last().unwrap().segment().unwrap().ip().ttl(), 255);
+    let syn_ack = device.last().unwrap().seq_end().unwrap();
+    seg().seq(PEER_ISS + 1).ack(syn_ack).ttl(255).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(conn.state(), TcpState::Established);
+    // one hop further away
+    seg().seq(PEER_ISS + 1).ack(syn_ack).ttl(254).payload(b"spoofed").deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(conn.stats().min_ttl_drops, 1);
+    assert_eq!(conn.read(&mut [0; 16]), 0);
+}
+
+/// a clock which only moves when told to
+#[derive(Debug)]
+struct ManualClock(Mutex<Instant>);
+
+impl ManualClock {
+    fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+fn user_timeout_of(tcp: &etherparse::TcpHeaderSlice) -> Option<Duration> {
+    tcp_stack::tcp::options::parse(tcp.options()).find_map(|option| match option {
+        Ok(TcpOption::UserTimeout(timeout)) => Some(timeout),
+        _ => None,
+    })
+}
+
+#[test]
+fn user_timeout_is_advertised_until_data_is_acknowledged() {
+    let mut config = ConnectionConfig::default();
+    config.set_user_timeout(Some(Duration::from_secs(300)));
+    let mut device = Recorder::new();
+    let mut conn = seg().syn().seq(PEER_ISS).accept_with_config(&mut device, config).unwrap().unwrap();
+    assert_eq!(user_timeout_of(&device.last().unwrap().tcp().unwrap()), Some(Duration::from_secs(300)));
+    let syn_ack = device.last().unwrap().seq_end().unwrap();
+    seg().seq(PEER_ISS + 1).ack(syn_ack).deliver(&mut conn, &mut device).unwrap();
+    conn.write(b"first");
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(user_timeout_of(&device.last().unwrap().tcp().unwrap()), Some(Duration::from_secs(300)));
+    seg().seq(PEER_ISS + 1).ack(syn_ack + 5).deliver(&mut conn, &mut device).unwrap();
+    conn.write(b"second");
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(user_timeout_of(&device.last().unwrap().tcp().unwrap()), None);
+    assert_eq!(conn.user_timeout(), Duration::from_secs(300));
+}
+
+#[test]
+fn user_timeout_of_peer_is_taken_within_limits() {
+    let accept = |accept: bool, advertised: u64| {
+        let mut config = ConnectionConfig::default();
+        config.set_accept_user_timeout(accept);
+        let syn = seg().syn().seq(PEER_ISS).option(TcpOption::UserTimeout(Duration::from_secs(advertised)));
+        syn.accept_with_config(&mut Recorder::new(), config).unwrap().unwrap().user_timeout()
+    };
+    assert_eq!(accept(false, 600), ConnectionConfig::default().r2());
+    assert_eq!(accept(true, 600), Duration::from_secs(600));
+    // below the lower limit of 100 seconds, and in minutes beyond 32767 seconds
+    assert_eq!(accept(true, 20), Duration::from_secs(100));
+    assert_eq!(accept(true, 40_000), Duration::from_secs(666 * 60));
+}
+
+#[test]
+fn unanswered_keep_alive_times_out() {
+    let mut config = ConnectionConfig::default();
+    config.set_keep_alive(Some(Duration::from_secs(10)));
+    config.set_keep_alive_interval(Duration::from_secs(1));
+    config.set_keep_alive_probes(2);
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, config);
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    conn.set_clock(clock.clone());
+    let start = clock.now();
+    assert_eq!(conn.next_timeout(), Some(start + Duration::from_secs(10)));
+
+    // a probe is an ACK of the byte before snd.nxt, the answer starts the idle time over
+    clock.advance(Duration::from_secs(10));
+    conn.on_timeout(&mut device).unwrap();
+    let nxt = conn.send_sequence().nxt;
+    let probe = device.last().unwrap().tcp().unwrap();
+    assert_eq!((probe.sequence_number(), probe.ack()), (nxt - 1, true));
+    assert!(device.last().unwrap().payload().unwrap().is_empty());
+    seg().seq(PEER_ISS + 1).ack(nxt).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(conn.next_timeout(), Some(clock.now() + Duration::from_secs(10)));
+
+    clock.advance(Duration::from_secs(10));
+    conn.on_timeout(&mut device).unwrap();
+    clock.advance(Duration::from_secs(1));
+    conn.on_timeout(&mut device).unwrap();
+    assert_eq!(conn.state(), TcpState::Established);
+    clock.advance(Duration::from_secs(1));
+    conn.on_timeout(&mut device).unwrap();
+    assert_eq!(conn.state(), TcpState::Closed);
+    assert!(conn.is_timed_out());
+}
+
+/// data of 100 byte segments in flight on an established connection with an RTT sample
+/// of 100ms, on a clock which moves when told to
+fn sending(device: &mut Recorder) -> (TcpConnection, Arc<ManualClock>) {
+    let mut config = ConnectionConfig::default();
+    config.set_mss(100);
+    let mut conn = established(device, config);
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    conn.set_clock(clock.clone());
+    conn.write(&[0x5a; 4000]);
+    conn.transmit(device).unwrap();
+    clock.advance(Duration::from_millis(100));
+    let nxt = conn.send_sequence().nxt;
+    seg().seq(PEER_ISS + 1).ack(nxt).deliver(&mut conn, device).unwrap();
+    (conn, clock)
+}
+
+fn dup_acks(conn: &mut TcpConnection, device: &mut Recorder, count: usize) {
+    let una = conn.send_sequence().una;
+    for _ in 0..count {
+        seg().seq(PEER_ISS + 1).ack(una).deliver(conn, device).unwrap();
+    }
+}
+
+#[test]
+fn late_segment_counts_as_reordering() {
+    let mut device = Recorder::new();
+    let (mut conn, _) = sending(&mut device);
+    dup_acks(&mut conn, &mut device, 2);
+    let nxt = conn.send_sequence().nxt;
+    seg().seq(PEER_ISS + 1).ack(nxt).deliver(&mut conn, &mut device).unwrap();
+    let stats = conn.stats();
+    assert_eq!((stats.reordering_events, stats.reordering, stats.fast_retransmits), (1, 2, 0));
+    assert_eq!(conn.dupthresh(), 3);
+}
+
+#[test]
+fn first_two_dup_acks_send_new_segments() {
+    let mut device = Recorder::new();
+    let (mut conn, _) = sending(&mut device);
+    let una = conn.send_sequence().una;
+    let cwnd = conn.stats().cwnd;
+    assert_eq!(conn.send_sequence().nxt - una, cwnd as u32);
+    device.take();
+
+    for limited in 1..=2 {
+        let nxt = conn.send_sequence().nxt;
+        dup_acks(&mut conn, &mut device, 1);
+        let sent = device.take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].tcp().unwrap().sequence_number(), nxt);
+        assert_eq!(sent[0].payload().unwrap().len(), 100);
+        assert_eq!(conn.stats().limited_transmits, limited);
+        assert_eq!(conn.stats().cwnd, cwnd);
+    }
+
+    // the third one retransmits, no new segment goes along
+    let nxt = conn.send_sequence().nxt;
+    dup_acks(&mut conn, &mut device, 1);
+    assert_eq!(conn.stats().fast_retransmits, 1);
+    let sent = device.take();
+    assert_eq!(sent[0].tcp().unwrap().sequence_number(), una);
+    assert!(sent.iter().all(|sent| sent.tcp().unwrap().sequence_number() != nxt));
+    assert_eq!(conn.stats().limited_transmits, 2);
+}
+
+#[test]
+fn limited_transmit_sends_nothing_without_new_data() {
+    let mut config = ConnectionConfig::default();
+    config.set_mss(100);
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, config);
+    conn.write(&[0x5a; 300]);
+    conn.transmit(&mut device).unwrap();
+    device.take();
+    dup_acks(&mut conn, &mut device, 2);
+    assert!(device.take().iter().all(|sent| sent.payload().unwrap().is_empty()));
+    assert_eq!(conn.stats().limited_transmits, 0);
+}
+
+#[test]
+fn spurious_fast_retransmit_raises_dupthresh() {
+    let mut device = Recorder::new();
+    let (mut conn, clock) = sending(&mut device);
+    let una = conn.send_sequence().una;
+    let cwnd = conn.stats().cwnd;
+    dup_acks(&mut conn, &mut device, 3);
+    assert_eq!(conn.stats().fast_retransmits, 1);
+    let retransmission = device.last().unwrap().tcp().unwrap();
+    assert_eq!(retransmission.sequence_number(), una);
+    assert!(conn.stats().cwnd < cwnd);
+
+    // everything is acknowledged long before the retransmission could have made it
+    clock.advance(Duration::from_millis(1));
+    let nxt = conn.send_sequence().nxt;
+    seg().seq(PEER_ISS + 1).ack(nxt).deliver(&mut conn, &mut device).unwrap();
+    let stats = conn.stats();
+    assert_eq!((stats.spurious_retransmits, stats.reordering), (1, 3));
+    assert!(stats.cwnd >= cwnd);
+    assert_eq!(conn.dupthresh(), 4);
+
+    dup_acks(&mut conn, &mut device, 3);
+    assert_eq!(conn.stats().fast_retransmits, 1);
+    dup_acks(&mut conn, &mut device, 1);
+    assert_eq!(conn.stats().fast_retransmits, 2);
+}
+
+#[test]
+fn fin_rides_on_the_last_data_segment() {
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, ConnectionConfig::default());
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    conn.set_clock(clock.clone());
+    device.take();
+    let una = conn.send_sequence().una;
+    conn.write(&[0x5a; 300]);
+    conn.close();
+    conn.transmit(&mut device).unwrap();
+    let sent = device.take();
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].tcp().unwrap().fin());
+    assert_eq!(sent[0].payload().unwrap().len(), 300);
+    assert_eq!(conn.send_sequence().nxt, una + 301);
+    assert_eq!(conn.state(), TcpState::FinWait1);
+
+    // the retransmission carries it again
+    clock.advance(Duration::from_secs(60));
+    conn.on_timeout(&mut device).unwrap();
+    let resent = device.last().unwrap();
+    assert!(resent.tcp().unwrap().fin());
+    assert_eq!(resent.payload().unwrap().len(), 300);
+
+    seg().seq(PEER_ISS + 1).ack(una + 301).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(conn.state(), TcpState::FinWait2);
+}
+
+/// the value and echo of the timestamps option of a segment sent
+fn sent_timestamps(sent: &tcp_stack::testing::Sent) -> Option<(u32, u32)> {
+    let tcp = sent.tcp().unwrap();
+    options::parse(tcp.options()).find_map(|option| match option.unwrap() {
+        TcpOption::Timestamps { value, echo } => Some((value, echo)),
+        _ => None,
+    })
+}
+
+#[test]
+fn timestamps_are_echoed_once_both_ends_send_them() {
+    let ts = |value, echo| TcpOption::Timestamps { value, echo };
+    let mut config = ConnectionConfig::default();
+    config.set_timestamps(true);
+    let mut device = Recorder::new();
+    seg().syn().seq(PEER_ISS).accept_with_config(&mut device, config).unwrap().unwrap();
+    assert_eq!(sent_timestamps(device.last().unwrap()), None);
+
+    let mut conn = seg().syn().seq(PEER_ISS).option(ts(7, 0)).accept_with_config(&mut device, config).unwrap().unwrap();
+    let syn_ack = device.last().unwrap().clone();
+    assert_eq!(sent_timestamps(&syn_ack).map(|(_, echo)| echo), Some(7));
+    let syn_ack = syn_ack.seq_end().unwrap();
+    seg().seq(PEER_ISS + 1).ack(syn_ack).option(ts(8, 0)).payload(b"hello").deliver(&mut conn, &mut device).unwrap();
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(sent_timestamps(device.last().unwrap()).map(|(_, echo)| echo), Some(8));
+    // an older one is not taken
+    seg().seq(PEER_ISS + 6).ack(syn_ack).option(ts(5, 0)).payload(b"world").deliver(&mut conn, &mut device).unwrap();
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(sent_timestamps(device.last().unwrap()).map(|(_, echo)| echo), Some(8));
+}
+
+/// the cwnd and ssthresh after the ACK of the first segment arrives after its retransmission
+/// timeout, echoing the timestamp of the original or of the retransmission
+fn after_late_ack(original: bool) -> ((usize, usize), (usize, usize), u64) {
+    let ts = |value, echo| TcpOption::Timestamps { value, echo };
+    let mut config = ConnectionConfig::default();
+    config.set_mss(100);
+    config.set_timestamps(true);
+    let mut device = Recorder::new();
+    let mut conn = seg().syn().seq(PEER_ISS).option(ts(1, 0)).accept_with_config(&mut device, config).unwrap().unwrap();
+    let syn_ack = device.last().unwrap().seq_end().unwrap();
+    seg().seq(PEER_ISS + 1).ack(syn_ack).option(ts(2, 0)).deliver(&mut conn, &mut device).unwrap();
+    device.take();
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    conn.set_clock(clock.clone());
+    conn.write(&[0x5a; 2000]);
+    conn.transmit(&mut device).unwrap();
+    let sent = device.take();
+    // the option leaves 88 bytes of the mss
+    assert_eq!(sent[0].payload().unwrap().len(), 88);
+    let (first, _) = sent_timestamps(&sent[0]).unwrap();
+    let una = conn.send_sequence().una;
+    let before = (conn.stats().cwnd, conn.stats().ssthresh);
+
+    clock.advance(conn.next_timeout().unwrap() - clock.now());
+    conn.on_timeout(&mut device).unwrap();
+    let retransmission = device.take();
+    assert_eq!(retransmission[0].tcp().unwrap().sequence_number(), una);
+    let (retransmitted, _) = sent_timestamps(&retransmission[0]).unwrap();
+    assert!(retransmitted > first);
+    assert!(conn.stats().cwnd < before.0);
+
+    let echo = if original { first } else { retransmitted };
+    seg().seq(PEER_ISS + 1).ack(una + 88).option(ts(3, echo)).deliver(&mut conn, &mut device).unwrap();
+    let stats = conn.stats();
+    (before, (stats.cwnd, stats.ssthresh), stats.spurious_timeouts)
+}
+
+#[test]
+fn ack_of_the_original_segment_undoes_the_timeout() {
+    let (before, after, spurious) = after_late_ack(true);
+    assert_eq!(after, before);
+    assert_eq!(spurious, 1);
+    // the ACK of the retransmission leaves the reduction
+    let (before, after, spurious) = after_late_ack(false);
+    assert!(after.0 < before.0 && after.1 < before.1);
+    assert_eq!(spurious, 0);
+}
+
+#[test]
+fn retransmissions_leave_before_window_probes() {
+    let mut scheduler = SendScheduler::new();
+    scheduler.queue(Transmission::WindowProbe);
+    scheduler.queue(Transmission::Retransmit);
+    scheduler.queue(Transmission::Retransmit);
+    assert_eq!(scheduler.pop(), Some(Transmission::Retransmit));
+    assert_eq!(scheduler.pop(), Some(Transmission::WindowProbe));
+    assert_eq!(scheduler.pop(), None);
+
+    scheduler.queue(Transmission::Retransmit);
+    scheduler.cancel(Transmission::Retransmit);
+    assert!(!scheduler.is_queued(Transmission::Retransmit));
+}
+
+#[test]
+fn retransmission_timeout_resends_the_oldest_segment_before_new_data() {
+    let mut device = Recorder::new();
+    let (mut conn, clock) = sending(&mut device);
+    let (una, snd_max) = (conn.send_sequence().una, conn.send_sequence().nxt);
+    let unsent = conn.send_queue_len() - (snd_max - una) as usize;
+    assert!(una != snd_max && unsent > 0);
+    device.take();
+
+    clock.advance(conn.next_timeout().unwrap() - clock.now());
+    conn.on_timeout(&mut device).unwrap();
+    // the window shrank to one segment, which is the oldest one and not the queued data
+    let sent = device.take();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].tcp().unwrap().sequence_number(), una);
+    assert_eq!(sent[0].payload().unwrap().len(), 100);
+
+    // its ACK lets new data go after it
+    seg().seq(PEER_ISS + 1).ack(una + 100).deliver(&mut conn, &mut device).unwrap();
+    let sent = device.take();
+    assert_eq!(sent[0].tcp().unwrap().sequence_number(), snd_max);
+    assert!(sent.iter().all(|sent| sent.payload().unwrap().len() == 100));
+}
+
+#[test]
+fn closed_window_is_probed_until_an_update_gets_through() {
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, ConnectionConfig::default());
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    conn.set_clock(clock.clone());
+    let una = conn.send_sequence().una;
+    seg().seq(PEER_ISS + 1).ack(una).window(0).deliver(&mut conn, &mut device).unwrap();
+    conn.write(&[0x5a; 500]);
+    conn.transmit(&mut device).unwrap();
+    device.take();
+    let start = clock.now();
+    let first = conn.persist_deadline().unwrap();
+
+    // the update opening the window got lost, the probe is answered with a closed one
+    clock.advance(first - start);
+    conn.on_timeout(&mut device).unwrap();
+    let probe = device.take();
+    assert_eq!(probe.len(), 1);
+    assert_eq!(probe[0].tcp().unwrap().sequence_number(), una - 1);
+    assert!(probe[0].payload().unwrap().is_empty());
+    seg().seq(PEER_ISS + 1).ack(una).window(0).deliver(&mut conn, &mut device).unwrap();
+    let second = conn.persist_deadline().unwrap();
+    assert_eq!(second - clock.now(), (first - start) * 2);
+
+    // the answer to the next probe opens it, the data leaves
+    clock.advance(second - clock.now());
+    conn.on_timeout(&mut device).unwrap();
+    assert_eq!(conn.stats().window_probes, 2);
+    device.take();
+    seg().seq(PEER_ISS + 1).ack(una).window(1000).deliver(&mut conn, &mut device).unwrap();
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(conn.persist_deadline(), None);
+    let sent: usize = device.take().iter().map(|sent| sent.payload().unwrap().len()).sum();
+    assert_eq!(sent, 500);
+}
+
+/// bytes the first flight of a connection with `config` carries
+fn first_flight(config: ConnectionConfig) -> usize {
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, config);
+    device.take();
+    conn.write(&[0x5a; 5000]);
+    conn.transmit(&mut device).unwrap();
+    device.take().iter().map(|sent| sent.payload().unwrap().len()).sum()
+}
+
+#[test]
+fn initial_window_is_ten_segments_unless_configured() {
+    let mut config = ConnectionConfig::default();
+    config.set_mss(100);
+    assert_eq!(first_flight(config), 1000);
+    config.set_initial_window(1);
+    assert_eq!(first_flight(config), 100);
+    config.set_initial_window(0);
+    assert_eq!(config.initial_window(), 1);
+
+    let stack = StackConfig::builder().addr([10, 0, 0, 1].into()).initial_window(4).build().unwrap();
+    assert_eq!(stack.connection().initial_window(), 4);
+}
+
+/// send everything queued, every segment acknowledged on its own
+fn drain(conn: &mut TcpConnection, device: &mut Recorder) {
+    conn.transmit(device).unwrap();
+    while conn.send_sequence().una != conn.send_sequence().nxt {
+        for sent in device.take().iter().filter(|sent| !sent.payload().unwrap().is_empty()) {
+            seg().seq(PEER_ISS + 1).ack(sent.seq_end().unwrap()).deliver(conn, device).unwrap();
+        }
+    }
+}
+
+#[test]
+fn idle_connection_restarts_from_a_decayed_window() {
+    let mut config = ConnectionConfig::default();
+    config.set_mss(100);
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, config);
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    conn.set_clock(clock.clone());
+    conn.write(&[0x5a; 4000]);
+    drain(&mut conn, &mut device);
+    let cwnd = conn.stats().cwnd;
+    assert_eq!(cwnd, 5000);
+
+    // idle for less than a timeout the window stays
+    let rto = conn.rtt().rto();
+    clock.advance(rto / 2);
+    conn.write(&[0x5a; 1]);
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(conn.stats().cwnd, cwnd);
+    drain(&mut conn, &mut device);
+
+    let cwnd = conn.stats().cwnd;
+    // halved for each of the two timeouts
+    clock.advance(rto * 2 + Duration::from_millis(1));
+    conn.write(&[0x5a; 1]);
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(conn.stats().cwnd, cwnd >> 2);
+    assert!(conn.stats().ssthresh >= cwnd / 4 * 3);
+    drain(&mut conn, &mut device);
+
+    // never below the restart window
+    let cwnd = conn.stats().cwnd;
+    clock.advance(rto * 20);
+    conn.write(&[0x5a; 1]);
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(conn.stats().cwnd, 1000.min(cwnd));
+}
+
+#[test]
+fn window_is_kept_when_idle_restart_is_off() {
+    let mut config = ConnectionConfig::default();
+    config.set_mss(100);
+    config.set_slow_start_after_idle(false);
+    let mut device = Recorder::new();
+    let mut conn = established(&mut device, config);
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    conn.set_clock(clock.clone());
+    conn.write(&[0x5a; 4000]);
+    drain(&mut conn, &mut device);
+    let cwnd = conn.stats().cwnd;
+    clock.advance(Duration::from_secs(600));
+    conn.write(&[0x5a; 1]);
+    conn.transmit(&mut device).unwrap();
+    assert_eq!(conn.stats().cwnd, cwnd);
+}
+
+/// the frames a connection sends for 950 bytes and a shutdown, with or without GSO
+fn closing_flight(gso: bool) -> (Vec<tcp_stack::testing::Sent>, TcpConnection) {
+    let mut config = ConnectionConfig::default();
+    config.set_mss(100);
+    config.set_gso(gso);
+    let mut device = Recorder::with_header_len(4);
+    let mut conn = established(&mut device, config);
+    device.take();
+    conn.write(&[0x5a; 950]);
+    conn.close();
+    conn.transmit(&mut device).unwrap();
+    (device.take(), conn)
+}
+
+#[test]
+fn large_segments_are_cut_like_the_ones_sent_one_by_one() {
+    let (segmented, conn) = closing_flight(true);
+    let (single, single_conn) = closing_flight(false);
+    assert_eq!(segmented.len(), 10);
+    assert_eq!(conn.stats().segments_sent, single_conn.stats().segments_sent);
+    for (cut, sent) in segmented.iter().zip(&single) {
+        assert!(cut.segment().unwrap().checksum_valid().unwrap());
+        assert_eq!(cut.tcp().unwrap().slice(), sent.tcp().unwrap().slice());
+        assert_eq!(cut.payload().unwrap(), sent.payload().unwrap());
+    }
+    let ids: Vec<u16> = segmented.iter().map(|cut| cut.segment().unwrap().ip().identification()).collect();
+    assert!(ids.windows(2).all(|pair| pair[1] == pair[0].wrapping_add(1)));
+    assert!(segmented.last().unwrap().tcp().unwrap().fin());
+    assert!(segmented[..9].iter().all(|cut| !cut.tcp().unwrap().fin()));
+}
+
+#[test]
+fn consecutive_segments_are_coalesced_until_one_does_not_continue() {
+    let data = |seq: u32, len: usize| seg().seq(seq).ack(1).payload(&vec![seq as u8; len]);
+    let mut gro = Coalescer::new(4, false);
+    for seq in [1, 101, 201] {
+        assert!(gro.push(&data(seq, 100).frame(4).unwrap()));
+    }
+    assert!(gro.push(&data(301, 50).psh().frame(4).unwrap()));
+    assert_eq!(gro.segments(), 4);
+    // nothing continues a PSH
+    assert!(!gro.push(&data(351, 100).frame(4).unwrap()));
+
+    let merged = gro.take().unwrap();
+    let segment = Segment::parse(&merged, 4).unwrap();
+    assert!(segment.checksum_valid().unwrap());
+    assert_eq!(segment.ip().payload_len().unwrap() as usize, segment.tcp().slice().len() + 350);
+    assert_eq!(segment.tcp().sequence_number(), 1);
+    assert!(segment.tcp().psh());
+    let expected: Vec<u8> = [1u8, 101, 201].iter().flat_map(|&b| vec![b; 100]).chain(vec![45; 50]).collect();
+    assert_eq!(segment.payload(), &expected[..]);
+    assert!(gro.take().is_none());
+
+    assert!(gro.push(&data(1, 100).frame(4).unwrap()));
+    // a gap, a FIN, another ack or a corrupted checksum start over
+    assert!(!gro.push(&data(201, 100).frame(4).unwrap()));
+    assert!(!gro.push(&data(101, 100).fin().frame(4).unwrap()));
+    assert!(!gro.push(&seg().seq(101).ack(2).payload(&[1; 100]).frame(4).unwrap()));
+    let mut corrupted = data(101, 100).frame(4).unwrap();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(!gro.push(&corrupted));
+    let single = gro.take().unwrap();
+    assert_eq!(single, data(1, 100).frame(4).unwrap());
+}
+
+/// what a random peer or the application does next
+#[derive(Debug, Clone)]
+enum Op {
+    /// a segment at an offset from rcv.nxt acknowledging up to an offset from snd.una
+    Segment { seq: i16, ack: u16, window: u16, len: usize, fin: bool },
+    Write(usize),
+    Read(usize),
+    Close,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (-64i16..64, 0u16..3000, any::<u16>(), 0usize..1500, prop::bool::weighted(0.05))
+            .prop_map(|(seq, ack, window, len, fin)| Op::Segment { seq, ack, window, len, fin }),
+        2 => (0usize..20_000).prop_map(Op::Write),
+        2 => (0usize..20_000).prop_map(Op::Read),
+        1 => Just(Op::Close),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn sequence_invariants_hold(ops in prop::collection::vec(op(), 1..64), recv_buffer in 1usize..32_768) {
+        let mut device = Recorder::new();
+        let mut config = ConnectionConfig::default();
+        config.set_recv_buffer_size(recv_buffer);
+        let mut conn = established(&mut device, config);
+        device.take();
+        let mut buf = vec![0; 20_000];
+        for op in ops {
+            match op {
+                Op::Segment { seq, ack, window, len, fin } => {
+                    let snd = conn.send_sequence();
+                    let seq = conn.recv_sequence().nxt.wrapping_add(seq as i32 as u32);
+                    let ack = snd.una.wrapping_add((ack as u32).min(snd.in_flight()));
+                    let mut segment = seg().seq(seq).ack(ack).window(window).payload(&vec![0xa5; len]);
+                    if fin {
+                        segment = segment.fin();
+                    }
+                    segment.deliver(&mut conn, &mut device).unwrap();
+                }
+                Op::Write(len) => {
+                    conn.write(&buf[..len]);
+                    conn.transmit(&mut device).unwrap();
+                }
+                Op::Read(len) => {
+                    conn.read(&mut buf[..len]);
+                    conn.transmit(&mut device).unwrap();
+                }
+                Op::Close => {
+                    conn.close();
+                    conn.transmit(&mut device).unwrap();
+                }
+            }
+
+            let snd = conn.send_sequence();
+            prop_assert!(seq_le(snd.una, snd.nxt), "snd.una {} after snd.nxt {}", snd.una, snd.nxt);
+            prop_assert!(snd.in_flight() as usize <= conn.send_queue_len() + 2);
+            prop_assert!(conn.bytes_available() <= recv_buffer, "{} bytes queued in a {} byte buffer",
+                         conn.bytes_available(), recv_buffer);
+            for sent in device.take() {
+                let tcp = sent.tcp().unwrap();
+                let len = sent.payload().unwrap().len();
+                // the advertised window covers what is free in the receive buffer
+                prop_assert!(tcp.window_size() as usize + conn.bytes_available() <= recv_buffer);
+                if len > 0 {
+                    let end = sent.seq_end().unwrap();
+                    prop_assert!(seq_le(end, snd.una.wrapping_add(snd.wnd as u32)),
+                                 "sent up to {} beyond the window of {} at {}", end, snd.wnd, snd.una);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn syn_ack_text_is_trimmed_to_the_window() {
+    let mut device = Recorder::new();
+    let mut config = ConnectionConfig::default();
+    config.set_recv_buffer_size(1000);
+    let mut conn = TcpConnection::connect(&mut device, LOCAL, PEER, config).unwrap();
+    let syn = device.last().unwrap().seq_end().unwrap();
+    seg().syn().seq(PEER_ISS).ack(syn).payload(&[0x5a; 1500]).fin().deliver(&mut conn, &mut device).unwrap();
+    // the FIN after the text beyond the window isn't taken either
+    assert_eq!(conn.state(), TcpState::Established);
+    assert_eq!(device.last().unwrap().tcp().unwrap().acknowledgment_number(), PEER_ISS + 1001);
+    let mut buf = [0; 2000];
+    assert_eq!(conn.read(&mut buf), 1000);
+    // the rest comes again without it, with a window update keeping it off the fast path
+    let nxt = conn.send_sequence().nxt;
+    seg().seq(PEER_ISS + 1001).ack(nxt).window(1000).payload(&[0x5a; 500]).deliver(&mut conn, &mut device).unwrap();
+    assert_eq!(conn.read(&mut buf), 500);
+    assert_eq!(conn.state(), TcpState::Established);
+}
@@ -0,0 +1,86 @@
+//! Parsing received frames which are cut short or carry garbage
+#![cfg(feature = "std")]
+
+use etherparse::PacketBuilder;
+use proptest::prelude::*;
+
+use tcp_stack::reader_writer::{RawReader, Segment};
+use tcp_stack::result::Error;
+
+const LINK: usize = 4;
+
+/// a tcp segment with `payload` behind a link header of `LINK` bytes
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; LINK];
+    PacketBuilder::ipv4([10, 0, 0, 2], [10, 0, 0, 1], 64)
+        .tcp(4000, 80, 1000, 65535)
+        .write(&mut frame, payload)
+        .unwrap();
+    frame
+}
+
+/// every accessor of the reader, in the order the stack calls them
+fn read_all(buf: &[u8], nread: usize, offset: usize) {
+    let mut raw = RawReader::from_slice(buf, nread, offset);
+    let _ = raw.offset_data();
+    let _ = raw.packet();
+    let _ = raw.is_ipv6_packet();
+    let _ = raw.ipv6_header();
+    if let Ok(ip) = raw.ipv4_header() {
+        let _ = ip.to_header().calc_header_checksum();
+    }
+    let _ = raw.is_ipv4_packet();
+    let _ = raw.data_offset();
+    let _ = raw.tcp_header();
+    if let Ok(segment) = raw.segment() {
+        let _ = segment.checksum_valid();
+        let _ = segment.options().count();
+    }
+}
+
+#[test]
+fn truncated_frames_are_errors() {
+    let frame = frame(b"hello");
+    for len in 0..frame.len() {
+        let err = RawReader::from_slice(&frame, len, LINK).segment().unwrap_err();
+        assert!(matches!(err, Error::Truncated { .. }), "{} bytes: {:?}", len, err);
+        assert!(matches!(Segment::parse(&frame[..len], LINK), Err(Error::Truncated { .. })));
+    }
+    // the padding after the ip packet isn't payload
+    let padded = [&frame[..], &[0; 10]].concat();
+    assert_eq!(Segment::parse(&padded, LINK).unwrap().payload(), b"hello");
+}
+
+#[test]
+fn short_reads_tell_what_is_missing() {
+    let frame = frame(b"hello");
+    let one_byte = RawReader::from_slice(&frame[LINK..], 1, 0);
+    assert!(one_byte.is_ipv4_packet().unwrap());
+    assert!(matches!(one_byte.ipv4_header(), Err(Error::Truncated { needed: 20, available: 1 })));
+    let empty = RawReader::from_slice(&frame, LINK, LINK);
+    assert!(matches!(empty.is_ipv4_packet(), Err(Error::Truncated { needed: 1, available: 0 })));
+    let no_link_header = RawReader::from_slice(&frame, 2, LINK);
+    assert!(matches!(no_link_header.offset_data(), Err(Error::Truncated { needed: 4, available: 2 })));
+    // the ip header claims more than the frame has
+    let mut raw = RawReader::from_slice(&frame, frame.len() - 1, LINK);
+    let missing = frame.len() - LINK;
+    assert!(matches!(raw.segment(), Err(Error::Truncated { needed, available }) if needed == missing && available == missing - 1));
+    // a count beyond the buffer is cut to it
+    assert_eq!(RawReader::from_slice(&frame, 10_000, LINK).segment().unwrap().payload(), b"hello");
+}
+
+proptest! {
+    #[test]
+    fn garbage_never_panics(buf in prop::collection::vec(any::<u8>(), 0..128), nread in 0usize..160, offset in 0usize..32) {
+        read_all(&buf, nread, offset);
+    }
+
+    #[test]
+    fn corrupted_frames_never_panic(at in 0usize..64, value in any::<u8>(), len in 0usize..64) {
+        let mut frame = frame(b"hello, world");
+        let at = at % frame.len();
+        frame[at] = value;
+        read_all(&frame, len.min(frame.len()), LINK);
+        let _ = Segment::parse(&frame, LINK);
+    }
+}
@@ -0,0 +1,298 @@
+//! Reading from the streams of two stacks on a loopback link
+#![cfg(all(unix, feature = "std"))]
+
+mod common;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcp_stack::config::StackConfig;
+use tcp_stack::firewall::{Rule, Verdict};
+use tcp_stack::observer::{ConnectionObserver, KeepAliveVerdict};
+use tcp_stack::socket::{TcpListener, TcpStream, CONNECTION_ATTEMPT_DELAY};
+use tcp_stack::socket_addr::Quad;
+use tcp_stack::stack::NetStack;
+use tcp_stack::tcp::congestion::CongestionAlgorithm;
+use tcp_stack::tcp::connection::ConnectionConfig;
+
+use common::{pair, pattern, stacks, CLIENT, SERVER, TIMEOUT};
+
+/// a connection between two stacks, the client end and the server end
+fn connected() -> (TcpStream, TcpStream, [NetStack; 2]) {
+    let (client, server) = stacks(ConnectionConfig::default());
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let stream = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    stream.wait_established(TIMEOUT).unwrap();
+    let accepted = listener.accept().unwrap();
+    stream.set_read_timeout(TIMEOUT).unwrap();
+    (stream, accepted, [client, server])
+}
+
+#[test]
+fn frames_are_peeked_before_they_are_read() {
+    let (mut stream, mut peer, _stacks) = connected();
+    assert_eq!(stream.bytes_available().unwrap(), 0);
+    assert_eq!(stream.try_peek(&mut [0; 4]).unwrap_err().kind(), ErrorKind::WouldBlock);
+
+    // a length prefixed frame arriving in two parts
+    let writer = thread::spawn(move || {
+        peer.write_all(&[0, 5, b'h']).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        peer.write_all(b"ello").unwrap();
+        peer
+    });
+    let mut len = [0; 2];
+    stream.peek_exact(&mut len).unwrap();
+    let len = u16::from_be_bytes(len) as usize;
+    let mut frame = vec![0; 2 + len];
+    stream.peek_exact(&mut frame).unwrap();
+    assert_eq!(stream.bytes_available().unwrap(), frame.len());
+    let mut peeked = [0; 16];
+    assert_eq!(stream.peek(&mut peeked).unwrap(), frame.len());
+    stream.read_exact(&mut frame).unwrap();
+    assert_eq!(&frame[2..], b"hello");
+    assert_eq!(&peeked[..frame.len()], &frame[..]);
+    assert_eq!(stream.bytes_available().unwrap(), 0);
+
+    // nothing this large ever fits, a closed peer ends the wait
+    let too_large = stream.peek_exact(&mut vec![0; 64 << 20]).unwrap_err();
+    assert_eq!(too_large.kind(), ErrorKind::InvalidInput);
+    let mut peer = writer.join().unwrap();
+    peer.write_all(b"ab").unwrap();
+    drop(peer);
+    assert_eq!(stream.peek_exact(&mut [0; 4]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"ab");
+}
+
+#[test]
+fn listeners_on_an_address_have_their_own_options() {
+    let (client, server) = stacks(ConnectionConfig::default());
+    let second = Ipv4Addr::new(10, 0, 0, 3);
+    server.add_addr(second).unwrap();
+    let unknown = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 9), 80);
+    assert_eq!(TcpListener::bind_addr(&server, unknown).err().unwrap().kind(), ErrorKind::AddrNotAvailable);
+
+    let wildcard = TcpListener::bind(&server, 80).unwrap();
+    let mut config = ConnectionConfig::default();
+    config.set_ttl(7);
+    config.set_recv_buffer_size(2000);
+    config.set_keep_alive(Some(Duration::from_secs(30)));
+    config.set_congestion(CongestionAlgorithm::Bbr);
+    config.set_mss(9000);
+    let options = server.default_options().connection(config).reuse_addr(true);
+    assert_eq!(options.connection_config().ttl(), 7);
+    let specific = TcpListener::bind_with(&server, SocketAddrV4::new(second, 80), options).unwrap();
+    // an overlapping listener without reuse_addr is refused
+    assert!(TcpListener::bind_addr(&server, SocketAddrV4::new(second, 80)).is_err());
+
+    let to_second = TcpStream::connect(&client, SocketAddrV4::new(second, 80)).unwrap();
+    let to_first = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    to_second.wait_established(TIMEOUT).unwrap();
+    to_first.wait_established(TIMEOUT).unwrap();
+    let accepted = specific.accept().unwrap();
+    assert_eq!(accepted.local_addr(), SocketAddrV4::new(second, 80));
+    assert_eq!(accepted.ttl().unwrap(), 7);
+    assert_eq!(accepted.recv_buffer_size().unwrap(), 2000);
+    assert_eq!(accepted.keep_alive().unwrap(), Some(Duration::from_secs(30)));
+    let other = wildcard.accept().unwrap();
+    assert_eq!(other.local_addr(), SocketAddrV4::new(SERVER, 80));
+    assert_eq!(other.ttl().unwrap(), server.default_options().connection_config().ttl());
+    assert_eq!(other.keep_alive().unwrap(), None);
+
+    // the segments of the listener's connections still fit the link
+    let data = vec![7; 20_000];
+    let mut accepted = accepted;
+    accepted.write_all(&data).unwrap();
+    let mut received = vec![0; data.len()];
+    let mut to_second = to_second;
+    to_second.set_read_timeout(TIMEOUT).unwrap();
+    to_second.read_exact(&mut received).unwrap();
+    assert!(received == data);
+    let stats = accepted.stats().unwrap();
+    assert!(stats.bytes_sent / stats.segments_sent <= 1460, "{:?}", stats);
+}
+
+#[test]
+fn connects_time_out_and_are_cancelled() {
+    let (client, server) = stacks(ConnectionConfig::default());
+    let _listener = TcpListener::bind(&server, 80).unwrap();
+    // nobody answers for an address the server doesn't have
+    let nobody = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 9), 80);
+
+    let zero = TcpStream::connect_timeout(&client, nobody, Duration::from_secs(0)).err().unwrap();
+    assert_eq!(zero.kind(), ErrorKind::InvalidInput);
+    let timed_out = TcpStream::connect_timeout(&client, nobody, Duration::from_millis(100)).err().unwrap();
+    assert_eq!(timed_out.kind(), ErrorKind::TimedOut);
+    assert!(client.connections().is_empty());
+    let refused = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 81)).err().unwrap();
+    assert_eq!(refused.kind(), ErrorKind::ConnectionRefused);
+    assert!(client.connections().is_empty());
+
+    // the embryonic connection holds its port until it's cancelled or dropped
+    let connecting = TcpStream::start_connect(&client, nobody, client.default_options()).unwrap();
+    assert_eq!(connecting.try_established().unwrap_err().kind(), ErrorKind::WouldBlock);
+    let port = connecting.local_addr().port();
+    assert!(client.connections().iter().any(|info| info.local.port() == port));
+    connecting.cancel();
+    assert!(client.connections().is_empty());
+    drop(TcpStream::start_connect(&client, nobody, client.default_options()).unwrap());
+    assert!(client.connections().is_empty());
+
+    let connecting = TcpStream::start_connect(&client, SocketAddrV4::new(SERVER, 80), client.default_options()).unwrap();
+    let stream = connecting.wait(TIMEOUT).unwrap();
+    assert!(stream.try_established().is_ok());
+    assert_eq!(client.connections().len(), 1);
+}
+
+#[test]
+fn the_first_address_to_answer_wins() {
+    let (client, server) = stacks(ConnectionConfig::default());
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let nobody = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9));
+    let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+    assert_eq!(TcpStream::connect_host(&client, &[], 80).err().unwrap().kind(), ErrorKind::InvalidInput);
+    assert_eq!(TcpStream::connect_host(&client, &[v6], 80).err().unwrap().kind(), ErrorKind::Unsupported);
+    let refused = TcpStream::connect_host(&client, &[v6, IpAddr::V4(SERVER)], 81).err().unwrap();
+    assert_eq!(refused.kind(), ErrorKind::ConnectionRefused);
+
+    // the ipv6 attempt fails right away, the server gets its turn once the black hole had its delay
+    let start = Instant::now();
+    let stream = TcpStream::connect_host(&client, &[nobody, IpAddr::V4(SERVER), v6], 80).unwrap();
+    assert!(start.elapsed() >= CONNECTION_ATTEMPT_DELAY);
+    assert_eq!(stream.peer_addr(), SocketAddrV4::new(SERVER, 80));
+    listener.accept().unwrap();
+    // the other attempt was cancelled
+    let connections = client.connections();
+    assert_eq!(connections.len(), 1, "{:?}", connections);
+}
+
+/// keeps probing a silent peer past the limit, gives up after `patience` probes
+#[derive(Default)]
+struct Liveness {
+    patience: u32,
+    answered: Mutex<Vec<Duration>>,
+    unanswered: Mutex<Vec<u32>>,
+}
+
+impl ConnectionObserver for Liveness {
+    fn on_keep_alive_answered(&self, _quad: &Quad, rtt: Duration) {
+        self.answered.lock().unwrap().push(rtt);
+    }
+
+    fn on_keep_alive_unanswered(&self, _quad: &Quad, unanswered: u32, last_rtt: Option<Duration>) -> KeepAliveVerdict {
+        assert!(last_rtt.is_some());
+        self.unanswered.lock().unwrap().push(unanswered);
+        if unanswered < self.patience { KeepAliveVerdict::Probe } else { KeepAliveVerdict::Abort }
+    }
+}
+
+#[test]
+fn observers_decide_about_silent_peers() {
+    let (client, server) = stacks(ConnectionConfig::default());
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let liveness = Arc::new(Liveness { patience: 4, ..Liveness::default() });
+    client.add_observer(liveness.clone());
+    let mut config = ConnectionConfig::default();
+    config.set_keep_alive(Some(Duration::from_millis(40)));
+    config.set_keep_alive_interval(Duration::from_millis(20));
+    config.set_keep_alive_probes(2);
+    let mut stream = TcpStream::connect_with(&client, SocketAddrV4::new(SERVER, 80), client.default_options().connection(config)).unwrap();
+    let _accepted = listener.accept().unwrap();
+
+    // the peer answers every probe
+    thread::sleep(Duration::from_millis(200));
+    let answered = liveness.answered.lock().unwrap().len();
+    assert!(answered >= 2, "{}", answered);
+    assert!(liveness.unanswered.lock().unwrap().is_empty());
+    let stats = stream.stats().unwrap();
+    assert!(stats.keep_alive_probes >= answered as u64 && stats.keep_alive_rtt.is_some(), "{:?}", stats);
+
+    // then goes silent, the probes go on past the limit of the config until the observer gives up
+    server.add_rule(Rule::from_fn(|_| Some(Verdict::Drop)));
+    stream.set_read_timeout(TIMEOUT).unwrap();
+    assert_eq!(stream.read(&mut [0; 16]).unwrap_err().kind(), ErrorKind::TimedOut);
+    assert_eq!(*liveness.unanswered.lock().unwrap(), [1, 2, 3, 4]);
+}
+
+#[test]
+fn coalesced_segments_arrive_intact() {
+    let config = StackConfig::builder().addr(SERVER).gro(true).build().unwrap();
+    assert!(config.gro());
+    let (client, server) = pair(StackConfig::builder().addr(CLIENT).build().unwrap(), config);
+    let listener = TcpListener::bind(&server, 80).unwrap();
+    let mut stream = TcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap();
+    stream.wait_established(TIMEOUT).unwrap();
+    let mut accepted = listener.accept().unwrap();
+    accepted.set_read_timeout(TIMEOUT).unwrap();
+
+    let data = pattern(1 << 20);
+    let sent = data.clone();
+    let writer = thread::spawn(move || {
+        stream.write_all(&sent).unwrap();
+        stream
+    });
+    let mut received = vec![0; data.len()];
+    accepted.read_exact(&mut received).unwrap();
+    assert!(received == data);
+    drop(writer.join().unwrap());
+}
+
+#[test]
+fn connections_spread_over_shards_are_driven_by_one_device() {
+    assert!(StackConfig::builder().addr(SERVER).shards(0).build().is_err());
+    let config = StackConfig::builder().addr(SERVER).shards(8).build().unwrap();
+    assert_eq!(config.shards(), 8);
+    let (client, server) = pair(StackConfig::builder().addr(CLIENT).build().unwrap(), config);
+    let listener = TcpListener::bind(&server, 80).unwrap();
+
+    let streams: Vec<TcpStream> = (0..32).map(|_| TcpStream::connect(&client, SocketAddrV4::new(SERVER, 80)).unwrap()).collect();
+    let writers: Vec<_> = streams.into_iter()
+        .enumerate()
+        .map(|(i, mut stream)| thread::spawn(move || {
+            stream.wait_established(TIMEOUT).unwrap();
+            stream.write_all(&[i as u8; 4096]).unwrap();
+            stream
+        }))
+        .collect();
+    let mut seen = Vec::new();
+    for _ in 0..writers.len() {
+        let mut accepted = listener.accept().unwrap();
+        accepted.set_read_timeout(TIMEOUT).unwrap();
+        let mut data = [0; 4096];
+        accepted.read_exact(&mut data).unwrap();
+        assert!(data.iter().all(|&b| b == data[0]));
+        seen.push(data[0]);
+    }
+    seen.sort_unstable();
+    assert_eq!(seen, (0..32).collect::<Vec<u8>>());
+    for writer in writers {
+        drop(writer.join().unwrap());
+    }
+}
+
+#[test]
+fn streams_and_listeners_of_a_closed_stack_fail() {
+    let (_client, mut accepted, [_client_stack, server]) = connected();
+    let listener = TcpListener::bind(&server, 81).unwrap();
+    let reading = thread::spawn(move || {
+        let res = accepted.read(&mut [0; 16]).map_err(|e| e.kind());
+        (res, accepted)
+    });
+    let accepting = thread::spawn(move || (listener.accept().map(drop).map_err(|e| e.kind()), listener));
+    thread::sleep(Duration::from_millis(50));
+    // what `tcp_stack_close` does
+    server.shutdown(Duration::ZERO);
+    drop(server);
+    let (read, mut accepted) = reading.join().unwrap();
+    assert_eq!(read, Ok(0));
+    assert_eq!(accepted.write(b"x").map_err(|e| e.kind()), Err(ErrorKind::BrokenPipe));
+    let (accept, listener) = accepting.join().unwrap();
+    assert_eq!(accept, Err(ErrorKind::NotConnected));
+    assert!(listener.try_accept().is_err());
+}